@@ -64,6 +64,9 @@ fn bench_rebase_plan(c: &mut Criterion) {
                             dump_rebase_constraints: false,
                             dump_rebase_plan: false,
                             detect_duplicate_commits_via_patch_id: true,
+                            drop_merges: false,
+                            keep_empty: false,
+                            autosquash: false,
                         },
                     )
                     .unwrap()
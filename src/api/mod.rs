@@ -0,0 +1,154 @@
+//! A programmatic interface to `git-branchless`, for tools which would
+//! otherwise have to invoke the `git-branchless` executable and parse its
+//! output.
+//!
+//! Each entry point operates on the repository at
+//! [`GitRunInfo::working_directory`], rather than the current directory of the
+//! process. Human-readable output is written to the provided [`Effects`]; use
+//! [`Effects::new_from_writers`] to capture it instead of writing it to stdout
+//! and stderr.
+//!
+//! Errors are reported as [`Error`], which implements [`std::error::Error`].
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::path::PathBuf;
+//!
+//! use branchless::core::effects::Effects;
+//! use branchless::core::formatting::Glyphs;
+//! use branchless::git::{GitRunInfo, Repo};
+//!
+//! fn main() -> Result<(), branchless::Error> {
+//!     let effects = Effects::new_from_writers(Glyphs::text(), Vec::new(), Vec::new());
+//!     let git_run_info = GitRunInfo {
+//!         path_to_git: PathBuf::from("git"),
+//!         working_directory: PathBuf::from("/path/to/repo"),
+//!         env: std::env::vars_os().collect(),
+//!     };
+//!
+//!     branchless::init(&effects, &git_run_info, "master")?;
+//!     branchless::amend(&effects, &git_run_info)?;
+//!     branchless::restack(&effects, &git_run_info)?;
+//!
+//!     let repo = Repo::from_dir(&git_run_info.working_directory)?;
+//!     let graph = branchless::smartlog::render_graph(&effects, &repo)?;
+//!     for node in graph.nodes {
+//!         println!("{} {:?}", node.oid, node.summary);
+//!     }
+//!
+//!     let plan = branchless::undo::plan(&effects, &git_run_info, 1)?;
+//!     branchless::undo::apply(&effects, &git_run_info, &plan)?;
+//!     Ok(())
+//! }
+//! ```
+
+pub mod smartlog;
+pub mod undo;
+
+use std::fmt::Display;
+
+use crate::commands;
+use crate::core::effects::Effects;
+use crate::git::GitRunInfo;
+use crate::opts::{CommitOptions, HookPosition, MoveOptions};
+
+/// An error produced by the library API.
+#[derive(Debug)]
+pub enum Error {
+    /// The operation didn't complete successfully, such as due to a merge
+    /// conflict. A description of the problem was written to the provided
+    /// `Effects`.
+    Failed {
+        /// The exit code which the corresponding `git-branchless` command
+        /// would have exited with.
+        exit_code: isize,
+    },
+
+    /// An unexpected error occurred.
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Failed { exit_code } => {
+                write!(f, "Operation failed with exit code {}", exit_code)
+            }
+            Error::Internal(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Failed { .. } => None,
+            Error::Internal(err) => err.source(),
+        }
+    }
+}
+
+impl From<eyre::Report> for Error {
+    fn from(err: eyre::Report) -> Self {
+        Error::Internal(err.into())
+    }
+}
+
+/// The result type of the library API.
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn check_exit_code(exit_code: isize) -> Result<()> {
+    match exit_code {
+        0 => Ok(()),
+        exit_code => Err(Error::Failed { exit_code }),
+    }
+}
+
+/// Install `git-branchless` in the repository, using `main_branch_name` as the
+/// main branch. Equivalent to `git branchless init --main-branch`.
+pub fn init(effects: &Effects, git_run_info: &GitRunInfo, main_branch_name: &str) -> Result<()> {
+    let exit_code = commands::init::init(
+        effects,
+        git_run_info,
+        &[main_branch_name.to_string()],
+        None,
+        None,
+        HookPosition::Append,
+        false,
+    )?;
+    check_exit_code(exit_code)
+}
+
+/// Amend the `HEAD` commit with the changes to tracked files in the working
+/// copy. Descendant commits are left in place; call [`restack`] to move them
+/// onto the amended commit.
+pub fn amend(effects: &Effects, git_run_info: &GitRunInfo) -> Result<()> {
+    let exit_code = commands::amend::amend(
+        effects,
+        git_run_info,
+        Vec::new(),
+        false,
+        false,
+        false,
+        Vec::new(),
+        true,
+        &CommitOptions::default(),
+        &MoveOptions::default(),
+    )?;
+    check_exit_code(exit_code)
+}
+
+/// Move abandoned commits and branches onto the latest versions of their
+/// parent commits. Equivalent to `git restack`.
+pub fn restack(effects: &Effects, git_run_info: &GitRunInfo) -> Result<()> {
+    let exit_code = commands::restack::restack(
+        effects,
+        git_run_info,
+        Vec::new(),
+        false,
+        false,
+        &MoveOptions::default(),
+    )?;
+    check_exit_code(exit_code)
+}
@@ -0,0 +1,141 @@
+//! Query the smartlog commit graph.
+
+use std::time::SystemTime;
+
+use os_str_bytes::OsStrBytes;
+
+use crate::commands::smartlog::{make_smartlog_graph, render_graph as render_smartlog_lines};
+use crate::core::dag::Dag;
+use crate::core::effects::Effects;
+use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::core::formatting::printable_styled_string;
+use crate::core::node_descriptors::{
+    BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor,
+    DifferentialRevisionDescriptor, NodeObject, ObsolescenceExplanationDescriptor,
+    RelativeTimeDescriptor,
+};
+use crate::git::{NonZeroOid, Repo};
+
+use super::Result;
+
+/// A commit in the smartlog graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphNode {
+    /// The OID of the commit.
+    pub oid: NonZeroOid,
+
+    /// The OID of the parent node in the graph, if any. This may differ from
+    /// the commit's actual parents, since the smartlog omits most commits.
+    pub parent: Option<NonZeroOid>,
+
+    /// The OIDs of the children nodes in the graph.
+    pub children: Vec<NonZeroOid>,
+
+    /// Whether the commit is part of the main branch.
+    pub is_main: bool,
+
+    /// Whether the commit has been rewritten or hidden.
+    pub is_obsolete: bool,
+
+    /// The full names of the references which point to this commit, such as
+    /// `refs/heads/master`.
+    pub branches: Vec<String>,
+
+    /// The first line of the commit message, or `None` if the commit has been
+    /// garbage-collected.
+    pub summary: Option<String>,
+}
+
+/// The smartlog commit graph, as displayed by `git smartlog`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Graph {
+    /// The OID of the `HEAD` commit, or `None` if `HEAD` is unborn.
+    pub head_oid: Option<NonZeroOid>,
+
+    /// The nodes of the graph, ordered by commit time, from oldest to newest.
+    /// Ties are broken by OID.
+    pub nodes: Vec<GraphNode>,
+
+    /// The rendered lines of the smartlog, using the glyphs of the provided
+    /// `Effects`.
+    pub lines: Vec<String>,
+}
+
+/// Compute the smartlog commit graph for the repository, along with its
+/// rendered form.
+pub fn render_graph(effects: &Effects, repo: &Repo) -> Result<Graph> {
+    let references_snapshot = repo.get_references_snapshot()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let dag = Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+    let graph = make_smartlog_graph(effects, repo, &dag, &event_replayer, event_cursor, true)?;
+
+    let mut nodes = Vec::new();
+    for (oid, node) in graph.iter() {
+        let (time, summary) = match node.get_object()? {
+            NodeObject::Commit { commit } => (
+                Some(commit.get_time().seconds()),
+                Some(commit.get_summary()?.to_string_lossy().into_owned()),
+            ),
+            NodeObject::GarbageCollected { oid: _ } => (None, None),
+        };
+        let mut branches: Vec<String> = references_snapshot
+            .branch_oid_to_names
+            .get(oid)
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| String::from_utf8_lossy(&name.to_raw_bytes()).into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        branches.sort_unstable();
+        nodes.push((
+            time,
+            GraphNode {
+                oid: *oid,
+                parent: node.parent,
+                children: node.children.clone(),
+                is_main: node.is_main,
+                is_obsolete: node.is_obsolete,
+                branches,
+                summary,
+            },
+        ));
+    }
+    nodes.sort_by_key(|(time, node)| (*time, node.oid.to_string()));
+    let nodes = nodes.into_iter().map(|(_time, node)| node).collect();
+
+    let lines = render_smartlog_lines(
+        effects,
+        repo,
+        &dag,
+        &graph,
+        references_snapshot.head_oid,
+        &mut [
+            &mut CommitOidDescriptor::new(true)?,
+            &mut RelativeTimeDescriptor::new(repo, SystemTime::now())?,
+            &mut ObsolescenceExplanationDescriptor::new(&event_replayer, event_cursor)?,
+            &mut BranchesDescriptor::new(repo, &references_snapshot)?,
+            &mut DifferentialRevisionDescriptor::new(repo)?,
+            &mut CommitMessageDescriptor::new()?,
+        ],
+    )?
+    .into_iter()
+    .map(|line| printable_styled_string(effects.get_glyphs(), line))
+    .collect::<eyre::Result<Vec<String>>>()?;
+
+    Ok(Graph {
+        head_oid: references_snapshot.head_oid,
+        nodes,
+        lines,
+    })
+}
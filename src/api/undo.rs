@@ -0,0 +1,27 @@
+//! Undo operations on the repository.
+
+use crate::commands::undo::{apply_undo_plan, plan_undo};
+use crate::core::effects::Effects;
+use crate::git::GitRunInfo;
+
+use super::{check_exit_code, Result};
+
+pub use crate::commands::undo::UndoPlan;
+
+/// Determine how to undo the most recent `num_transactions` transactions,
+/// without modifying the repository. Equivalent to the confirmation prompt of
+/// `git undo -n`.
+pub fn plan(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    num_transactions: usize,
+) -> Result<UndoPlan> {
+    let plan = plan_undo(effects, git_run_info, num_transactions)?;
+    Ok(plan)
+}
+
+/// Restore the repository to the state described by `plan`.
+pub fn apply(effects: &Effects, git_run_info: &GitRunInfo, plan: &UndoPlan) -> Result<()> {
+    let exit_code = apply_undo_plan(effects, git_run_info, plan)?;
+    check_exit_code(exit_code)
+}
@@ -0,0 +1,481 @@
+//! Absorb uncommitted changes into the commits in the current stack which they
+//! belong to.
+//!
+//! Each hunk of the uncommitted changes is attributed to the commit which last
+//! changed the lines around it, as determined by blame. If that commit is in
+//! the current stack, then the hunk is amended into it in-memory. Hunks which
+//! can't be attributed to exactly one commit in the stack are left in the
+//! working copy. Once all of the hunks are absorbed, the descendants of the
+//! amended commits are restacked onto them.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use eden_dag::DagAlgorithm;
+use eyre::Context;
+use tracing::instrument;
+
+use crate::commands::gc::mark_commit_reachable;
+use crate::commands::restack;
+use crate::core::config::get_restack_preserve_timestamps;
+use crate::core::dag::{sort_commit_set, CommitSet, CommitVertex, Dag};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::core::rewrite::move_head_to_rewritten_commit;
+use crate::core::snapshot::{create_snapshot, discard_uncommitted_changes};
+use crate::git::{
+    blame_file, get_uncommitted_hunks, hydrate_tree, BlamedLine, GitRunInfo, GitRunOpts, Hunk,
+    NonZeroOid, Repo,
+};
+use crate::opts::MoveOptions;
+
+/// A hunk which will be absorbed into a commit.
+#[derive(Debug)]
+struct AbsorbedHunk {
+    hunk: Hunk,
+
+    /// The index (0-based) of the line in the target commit's version of the
+    /// file which corresponds to `hunk.old_start`.
+    orig_start: usize,
+}
+
+/// Split file contents into lines, keeping their line endings.
+fn split_lines(contents: &[u8]) -> Vec<Vec<u8>> {
+    contents
+        .split_inclusive(|byte| *byte == b'\n')
+        .map(|line| line.to_vec())
+        .collect()
+}
+
+/// Caches the lines of files as of the commits which hunks may be absorbed
+/// into.
+#[derive(Debug)]
+struct FileLinesCache<'repo> {
+    repo: &'repo Repo,
+    lines: HashMap<(NonZeroOid, PathBuf), Option<Vec<Vec<u8>>>>,
+}
+
+impl<'repo> FileLinesCache<'repo> {
+    fn new(repo: &'repo Repo) -> Self {
+        Self {
+            repo,
+            lines: HashMap::new(),
+        }
+    }
+
+    /// Get the lines of the file at `path` in the given commit, or `None` if
+    /// it doesn't exist there.
+    fn get(&mut self, commit_oid: NonZeroOid, path: &Path) -> eyre::Result<Option<&[Vec<u8>]>> {
+        let key = (commit_oid, path.to_owned());
+        if !self.lines.contains_key(&key) {
+            let commit = self.repo.find_commit_or_fail(commit_oid)?;
+            let lines = match commit.get_tree()?.get_path(path)? {
+                Some(entry) => Some(split_lines(&self.repo.read_blob(entry.get_oid())?)),
+                None => None,
+            };
+            self.lines.insert(key.clone(), lines);
+        }
+        Ok(self.lines[&key].as_deref())
+    }
+}
+
+/// Determine which commit the given hunk should be absorbed into, and where in
+/// that commit's version of the file it applies. Returns `None` if the hunk
+/// can't be attributed to exactly one commit in the stack.
+fn attribute_hunk(
+    hunk: &Hunk,
+    blamed_lines: &[BlamedLine],
+    stack_commits: &CommitSet,
+    file_lines_cache: &mut FileLinesCache,
+) -> eyre::Result<Option<(NonZeroOid, usize)>> {
+    let (commit_oid, orig_start) = if hunk.old_lines.is_empty() {
+        // The hunk only inserts lines, so attribute it to the commit which
+        // changed the lines on either side of it. Those lines must also have
+        // been adjacent in that commit, or else it's not clear where to insert
+        // the new lines.
+        let before = hunk
+            .old_start
+            .checked_sub(1)
+            .and_then(|i| blamed_lines.get(i));
+        let after = blamed_lines.get(hunk.old_start);
+        match (before, after) {
+            (Some(before), Some(after)) => {
+                if before.commit_oid != after.commit_oid
+                    || before.orig_line_index + 1 != after.orig_line_index
+                {
+                    return Ok(None);
+                }
+                (after.commit_oid, after.orig_line_index)
+            }
+
+            (Some(before), None) => {
+                let num_lines = match file_lines_cache.get(before.commit_oid, &hunk.path)? {
+                    Some(lines) => lines.len(),
+                    None => return Ok(None),
+                };
+                if before.orig_line_index + 1 != num_lines {
+                    return Ok(None);
+                }
+                (before.commit_oid, num_lines)
+            }
+
+            (None, Some(after)) => {
+                if after.orig_line_index != 0 {
+                    return Ok(None);
+                }
+                (after.commit_oid, 0)
+            }
+
+            (None, None) => return Ok(None),
+        }
+    } else {
+        let changed_lines =
+            match blamed_lines.get(hunk.old_start..hunk.old_start + hunk.old_lines.len()) {
+                Some(changed_lines) => changed_lines,
+                None => return Ok(None),
+            };
+        let first = changed_lines[0];
+        let is_from_one_commit = changed_lines.iter().enumerate().all(|(i, line)| {
+            line.commit_oid == first.commit_oid && line.orig_line_index == first.orig_line_index + i
+        });
+        if !is_from_one_commit {
+            return Ok(None);
+        }
+        (first.commit_oid, first.orig_line_index)
+    };
+
+    if !stack_commits.contains(&CommitVertex::from(commit_oid))? {
+        return Ok(None);
+    }
+
+    // Sanity check: the removed lines should be the same in the commit that
+    // last changed them.
+    let lines = match file_lines_cache.get(commit_oid, &hunk.path)? {
+        Some(lines) => lines,
+        None => return Ok(None),
+    };
+    match lines.get(orig_start..orig_start + hunk.old_lines.len()) {
+        Some(orig_lines) if orig_lines == hunk.old_lines.as_slice() => {
+            Ok(Some((commit_oid, orig_start)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn describe_hunk(hunk: &Hunk) -> String {
+    format!(
+        "{}:{} (+{} -{})",
+        hunk.path.to_string_lossy(),
+        hunk.old_start + 1,
+        hunk.new_lines.len(),
+        hunk.old_lines.len()
+    )
+}
+
+/// Create a version of the given commit with the hunks applied to it, and
+/// return its OID.
+fn amend_commit_with_hunks(
+    repo: &Repo,
+    now: SystemTime,
+    file_lines_cache: &mut FileLinesCache,
+    commit_oid: NonZeroOid,
+    absorbed_hunks: &[AbsorbedHunk],
+) -> eyre::Result<NonZeroOid> {
+    let commit = repo.find_commit_or_fail(commit_oid)?;
+    let tree = commit.get_tree()?;
+
+    let mut hunks_by_path: HashMap<&Path, Vec<&AbsorbedHunk>> = HashMap::new();
+    for absorbed_hunk in absorbed_hunks {
+        hunks_by_path
+            .entry(&absorbed_hunk.hunk.path)
+            .or_default()
+            .push(absorbed_hunk);
+    }
+
+    let mut entries = HashMap::new();
+    for (path, mut hunks) in hunks_by_path {
+        let file_mode = match tree.get_path(path)? {
+            Some(entry) => entry.get_filemode(),
+            None => eyre::bail!("File {:?} not found in commit {:?}", path, commit_oid),
+        };
+        let mut lines = match file_lines_cache.get(commit_oid, path)? {
+            Some(lines) => lines.to_vec(),
+            None => eyre::bail!("File {:?} not found in commit {:?}", path, commit_oid),
+        };
+        // Apply the hunks from the bottom of the file up, so that applying a
+        // hunk doesn't shift the lines which the remaining hunks apply to.
+        hunks.sort_by_key(|absorbed_hunk| std::cmp::Reverse(absorbed_hunk.orig_start));
+        for AbsorbedHunk { hunk, orig_start } in hunks {
+            lines.splice(
+                *orig_start..*orig_start + hunk.old_lines.len(),
+                hunk.new_lines.iter().cloned(),
+            );
+        }
+        let blob_oid = repo.create_blob(&lines.concat())?;
+        entries.insert(path.to_owned(), Some((blob_oid, file_mode)));
+    }
+    let tree_oid = hydrate_tree(repo, Some(&tree), entries)?;
+    let tree = repo.find_tree_or_fail(tree_oid)?;
+
+    let committer = if get_restack_preserve_timestamps(repo)? {
+        commit.get_committer()
+    } else {
+        commit.get_committer().update_timestamp(now)?
+    };
+    let amended_commit_oid = repo.create_commit_with_message_from(
+        &commit.get_author(),
+        &committer,
+        &commit,
+        &tree,
+        commit.get_parents().iter().collect(),
+    )?;
+    mark_commit_reachable(repo, amended_commit_oid)
+        .wrap_err("Marking commit as reachable for GC purposes.")?;
+    Ok(amended_commit_oid)
+}
+
+/// Absorb the uncommitted changes (or only the staged changes, if `staged` is
+/// set) into the commits in the current stack which they belong to.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
+pub fn absorb(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    staged: bool,
+    dry_run: bool,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let glyphs = effects.get_glyphs();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+
+    let head_info = repo.get_head_info()?;
+    let head_oid = match head_info.oid {
+        Some(head_oid) => head_oid,
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "No commit is currently checked out. Check out a commit and then try again."
+            )?;
+            return Ok(1);
+        }
+    };
+    if repo.get_index()?.has_conflicts() {
+        writeln!(
+            effects.get_output_stream(),
+            "Cannot absorb, because there are unresolved merge conflicts. Resolve the merge conflicts and try again."
+        )?;
+        return Ok(1);
+    }
+
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+    let draft_commits = dag
+        .observed_commits
+        .difference(&dag.query_public_commits()?);
+    let stack_commits = dag
+        .query()
+        .ancestors(CommitSet::from(head_oid))?
+        .intersection(&draft_commits);
+
+    let head_commit = repo.find_commit_or_fail(head_oid)?;
+    let uncommitted_hunks = get_uncommitted_hunks(&repo, &head_commit.get_tree()?, staged)?;
+    if uncommitted_hunks.hunks.is_empty() && uncommitted_hunks.other_paths.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "There are no {}. Nothing to absorb.",
+            if staged {
+                "staged changes"
+            } else {
+                "uncommitted changes"
+            }
+        )?;
+        return Ok(0);
+    }
+
+    let mut file_lines_cache = FileLinesCache::new(&repo);
+    let mut blamed_lines_by_path: HashMap<PathBuf, Vec<BlamedLine>> = HashMap::new();
+    let mut absorbed_hunks: HashMap<NonZeroOid, Vec<AbsorbedHunk>> = HashMap::new();
+    let mut unabsorbed_hunks = Vec::new();
+    for hunk in uncommitted_hunks.hunks {
+        if !blamed_lines_by_path.contains_key(&hunk.path) {
+            let blamed_lines = blame_file(&repo, head_oid, &hunk.path)?.unwrap_or_default();
+            blamed_lines_by_path.insert(hunk.path.clone(), blamed_lines);
+        }
+        let blamed_lines = &blamed_lines_by_path[&hunk.path];
+        match attribute_hunk(&hunk, blamed_lines, &stack_commits, &mut file_lines_cache)? {
+            Some((commit_oid, orig_start)) => absorbed_hunks
+                .entry(commit_oid)
+                .or_default()
+                .push(AbsorbedHunk { hunk, orig_start }),
+            None => unabsorbed_hunks.push(hunk),
+        }
+    }
+
+    let target_commits = sort_commit_set(
+        &repo,
+        &dag,
+        &absorbed_hunks.keys().copied().collect::<CommitSet>(),
+    )?;
+    for commit in target_commits.iter() {
+        writeln!(
+            effects.get_output_stream(),
+            "{} {}:",
+            if dry_run {
+                "Would absorb into"
+            } else {
+                "Absorbing into"
+            },
+            printable_styled_string(glyphs, commit.friendly_describe()?)?
+        )?;
+        for AbsorbedHunk { hunk, .. } in &absorbed_hunks[&commit.get_oid()] {
+            writeln!(effects.get_output_stream(), "- {}", describe_hunk(hunk))?;
+        }
+    }
+    if !unabsorbed_hunks.is_empty() || !uncommitted_hunks.other_paths.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "{} in the working copy, because {} could not be attributed to a single commit in the current stack:",
+            if dry_run { "Would leave these changes" } else { "Leaving these changes" },
+            if unabsorbed_hunks.len() + uncommitted_hunks.other_paths.len() == 1 {
+                "it"
+            } else {
+                "they"
+            }
+        )?;
+        for hunk in &unabsorbed_hunks {
+            writeln!(effects.get_output_stream(), "- {}", describe_hunk(hunk))?;
+        }
+        for path in &uncommitted_hunks.other_paths {
+            writeln!(effects.get_output_stream(), "- {}", path.to_string_lossy())?;
+        }
+    }
+    if target_commits.is_empty() {
+        writeln!(effects.get_output_stream(), "Nothing to absorb.")?;
+        return Ok(0);
+    }
+    if dry_run {
+        return Ok(0);
+    }
+
+    let event_tx_id = event_log_db.make_transaction_id(now, "absorb")?;
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let mut rewritten_oids = HashMap::new();
+    let mut events = Vec::new();
+    for commit in target_commits.iter() {
+        let commit_oid = commit.get_oid();
+        let amended_commit_oid = amend_commit_with_hunks(
+            &repo,
+            now,
+            &mut file_lines_cache,
+            commit_oid,
+            &absorbed_hunks[&commit_oid],
+        )?;
+        rewritten_oids.insert(commit_oid, amended_commit_oid);
+        events.push(Event::RewriteEvent {
+            timestamp,
+            event_tx_id,
+            old_commit_oid: commit_oid.into(),
+            new_commit_oid: amended_commit_oid.into(),
+        });
+    }
+
+    // The working copy is cleaned while restacking, so that checking out the
+    // restacked `HEAD` commit doesn't conflict with the uncommitted changes.
+    // The uncommitted changes are then restored from the snapshot, at which
+    // point only the unabsorbed changes remain uncommitted.
+    let snapshot_oid = match create_snapshot(&repo, git_run_info, &mut event_log_db, event_tx_id)? {
+        Some(snapshot_oid) => snapshot_oid,
+        None => eyre::bail!("Could not take a snapshot of the uncommitted changes"),
+    };
+    discard_uncommitted_changes(&repo, git_run_info, event_tx_id)?;
+    let head_moved = rewritten_oids.contains_key(&head_oid);
+    events.extend(move_head_to_rewritten_commit(
+        &repo,
+        now,
+        event_tx_id,
+        &rewritten_oids,
+    )?);
+    event_log_db.add_events(events)?;
+    if head_moved {
+        discard_uncommitted_changes(&repo, git_run_info, event_tx_id)?;
+    }
+
+    writeln!(
+        effects.get_output_stream(),
+        "Absorbed {} into {}.",
+        Pluralize {
+            amount: absorbed_hunks
+                .values()
+                .map(|hunks| hunks.len())
+                .sum::<usize>()
+                .try_into()?,
+            singular: "hunk",
+            plural: "hunks",
+        }
+        .to_string(),
+        Pluralize {
+            amount: target_commits.len().try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string()
+    )?;
+
+    // Restacking on-disk would leave the repository in the middle of a
+    // rebase, at which point the uncommitted changes couldn't be restored.
+    let move_options = MoveOptions {
+        force_in_memory: true,
+        ..Default::default()
+    };
+    let restack_exit_code = restack::restack_in_transaction(
+        effects,
+        git_run_info,
+        event_tx_id,
+        rewritten_oids
+            .keys()
+            .map(|commit_oid| commit_oid.to_string())
+            .collect(),
+        false,
+        &move_options,
+    )?;
+
+    git_run_info
+        .run_silent(
+            &repo,
+            Some(event_tx_id),
+            &["stash", "apply", "--quiet", &snapshot_oid.to_string()],
+            GitRunOpts::default(),
+        )
+        .wrap_err("Restoring uncommitted changes")?;
+    if staged {
+        // Unabsorbed staged changes should stay staged.
+        let snapshot_commit = repo.find_commit_or_fail(snapshot_oid)?;
+        let index_oid = match snapshot_commit.get_parent_oids().as_slice() {
+            [_head_oid, index_oid] => *index_oid,
+            _ => eyre::bail!("Unexpected parents for snapshot commit: {:?}", snapshot_oid),
+        };
+        git_run_info.run_silent(
+            &repo,
+            Some(event_tx_id),
+            &["read-tree", &index_oid.to_string()],
+            GitRunOpts::default(),
+        )?;
+    }
+
+    Ok(restack_exit_code)
+}
@@ -2,36 +2,55 @@
 //!
 //! This command amends the HEAD commit with changes to files
 //! that are already tracked in the repo. Following the amend,
-//! the command performs a restack.
+//! the command performs a restack, unless disabled with `--no-restack` or the
+//! `branchless.amend.autoRestack` config option.
 
 use std::convert::TryInto;
+use std::ffi::OsString;
 use std::fmt::Write;
 use std::time::SystemTime;
 
+use eden_dag::DagAlgorithm;
 use eyre::Context;
 use itertools::Itertools;
 
 use crate::commands::gc::mark_commit_reachable;
 use crate::commands::restack;
-use crate::core::config::get_restack_preserve_timestamps;
+use crate::core::commit_hooks::prepare_commit_message;
+use crate::core::config::{get_amend_auto_restack, get_restack_preserve_timestamps};
+use crate::core::dag::{sort_commit_set, CommitSet, Dag};
 use crate::core::effects::Effects;
-use crate::core::eventlog::{Event, EventLogDb};
-use crate::core::formatting::Pluralize;
-use crate::git::{AmendFastOptions, FileStatus, GitRunInfo, Repo};
-use crate::opts::MoveOptions;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::core::rewrite::check_rewritten_commits_are_draft;
+use crate::core::snapshot::create_snapshot;
+use crate::git::{AmendFastOptions, FileStatus, GitRunInfo, NonZeroOid, Pathspecs, Repo};
+use crate::opts::{CommitOptions, MoveOptions};
+use crate::tui::{clean_up_message, edit_message, MessageCleanupMode};
 
 /// Amends the existing HEAD commit.
+///
+/// If the message is amended, then the `commit-msg` hook is run on the new
+/// message, as with `git commit --amend`.
 pub fn amend(
     effects: &Effects,
     git_run_info: &GitRunInfo,
+    messages: Vec<String>,
+    edit: bool,
+    staged: bool,
+    include_untracked: bool,
+    pathspecs: Vec<String>,
+    no_restack: bool,
+    commit_options: &CommitOptions,
     move_options: &MoveOptions,
 ) -> eyre::Result<isize> {
     let now = SystemTime::now();
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
     let conn = repo.get_db_conn()?;
     let mut event_log_db = EventLogDb::new(&conn)?;
 
-    let head_oid = match repo.get_head_info()?.oid {
+    let head_info = repo.get_head_info()?;
+    let head_oid = match head_info.oid {
         Some(oid) => oid,
         None => {
             writeln!(
@@ -52,86 +71,238 @@ pub fn amend(
         return Ok(1);
     }
 
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+    if !check_rewritten_commits_are_draft(
+        effects,
+        &repo,
+        &dag,
+        std::iter::once(head_oid),
+        move_options.force_rewrite_public,
+    )? {
+        return Ok(1);
+    }
+
     let event_tx_id = event_log_db.make_transaction_id(now, "amend")?;
-    let staged_index_paths = repo.get_staged_paths()?;
-    let (opts, dirty_working_tree) = if !staged_index_paths.is_empty() {
-        let dirty_working_tree = repo.has_changed_files(effects, git_run_info)?;
-        let opts = AmendFastOptions::FromIndex {
-            paths: staged_index_paths.into_iter().collect_vec(),
+    let pathspecs = Pathspecs::new(&repo, &git_run_info.working_directory, &pathspecs)?;
+    let staged_index_paths = repo
+        .get_staged_paths()?
+        .into_iter()
+        .filter(|path| pathspecs.matches(path))
+        .collect_vec();
+    let (opts, dirty_working_tree) =
+        if staged || !staged_index_paths.is_empty() {
+            let dirty_working_tree = repo.has_changed_files(effects, git_run_info)?;
+            let opts = AmendFastOptions::FromIndex {
+                paths: staged_index_paths,
+            };
+            (opts, dirty_working_tree)
+        } else {
+            let status = repo.get_status(git_run_info, Some(event_tx_id))?;
+            let mut entries_to_amend = status
+                .into_iter()
+                .filter(|entry| match entry.working_copy_status {
+                    FileStatus::Added
+                    | FileStatus::Copied
+                    | FileStatus::Deleted
+                    | FileStatus::Modified
+                    | FileStatus::Renamed => true,
+                    FileStatus::Ignored
+                    | FileStatus::Unmerged
+                    | FileStatus::Unmodified
+                    | FileStatus::Untracked => false,
+                })
+                .filter(|entry| entry.paths().iter().any(|path| pathspecs.matches(path)))
+                .collect_vec();
+            // Untracked files are only amended if they were asked for, either
+            // explicitly by name or with `--include-untracked`.
+            entries_to_amend.extend(repo.get_untracked_status_entries()?.into_iter().filter(
+                |entry| {
+                    pathspecs.names_path(&entry.path)
+                        || (include_untracked && pathspecs.matches(&entry.path))
+                },
+            ));
+            let opts = AmendFastOptions::FromWorkingCopy {
+                status_entries: entries_to_amend,
+            };
+            (opts, true)
         };
-        (opts, dirty_working_tree)
-    } else {
-        let status = repo.get_status(git_run_info, Some(event_tx_id))?;
-        let entries_to_amend = status
-            .into_iter()
-            .filter(|entry| match entry.working_copy_status {
-                FileStatus::Added
-                | FileStatus::Copied
-                | FileStatus::Deleted
-                | FileStatus::Modified
-                | FileStatus::Renamed => true,
-                FileStatus::Ignored
-                | FileStatus::Unmerged
-                | FileStatus::Unmodified
-                | FileStatus::Untracked => false,
-            })
-            .collect_vec();
-        let opts = AmendFastOptions::FromWorkingCopy {
-            status_entries: entries_to_amend,
+    let should_amend_message = edit || !messages.is_empty() || commit_options.signoff;
+    if opts.is_empty() && !should_amend_message {
+        let changes = if staged {
+            "staged changes"
+        } else {
+            "uncommitted or staged changes"
         };
-        (opts, true)
-    };
-    if opts.is_empty() {
-        writeln!(
-            effects.get_output_stream(),
-            "There are no uncommitted or staged changes. Nothing to amend."
-        )?;
+        if pathspecs.is_empty() {
+            writeln!(
+                effects.get_output_stream(),
+                "There are no {}. Nothing to amend.",
+                changes
+            )?;
+        } else {
+            writeln!(
+                effects.get_output_stream(),
+                "There are no {} in the given paths. Nothing to amend.",
+                changes
+            )?;
+        }
         return Ok(0);
     }
 
-    let amended_tree = repo.amend_fast(&head_commit, &opts)?;
-    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let amended_message = if should_amend_message {
+        let message = if messages.is_empty() {
+            head_commit
+                .get_message_raw()?
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            messages.join("\n\n")
+        };
+        let message = if edit {
+            edit_message(git_run_info, &repo, &message)?
+        } else {
+            clean_up_message(
+                &repo,
+                &message,
+                MessageCleanupMode::from_config(&repo, false)?,
+            )?
+        };
+        if message.trim().is_empty() {
+            writeln!(
+                effects.get_output_stream(),
+                "Aborting amend due to empty commit message."
+            )?;
+            return Ok(1);
+        }
+        match prepare_commit_message(
+            effects,
+            git_run_info,
+            &repo,
+            event_tx_id,
+            &message,
+            commit_options,
+        )? {
+            Some(message) => Some(message),
+            None => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Aborting amend because the commit-msg hook failed."
+                )?;
+                return Ok(1);
+            }
+        }
+    } else {
+        None
+    };
 
-    let (author, committer) = (head_commit.get_author(), head_commit.get_committer());
-    let (author, committer) = if get_restack_preserve_timestamps(&repo)? {
-        (author, committer)
+    let amended_tree = if opts.is_empty() {
+        None
     } else {
-        (
-            author.update_timestamp(now)?,
-            committer.update_timestamp(now)?,
-        )
+        // Save the uncommitted changes first, so that undoing the amend puts
+        // them back into the working copy, rather than leaving them only in
+        // the (then-obsolete) amended commit.
+        create_snapshot(&repo, git_run_info, &mut event_log_db, event_tx_id)?;
+        Some(repo.amend_fast(&head_commit, &opts)?)
     };
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+
+    let (author, committer) = (head_commit.get_author(), head_commit.get_committer());
+    let (author, committer) =
+        if move_options.preserve_timestamps || get_restack_preserve_timestamps(&repo)? {
+            (author, committer)
+        } else {
+            (
+                author.update_timestamp(now)?,
+                committer.update_timestamp(now)?,
+            )
+        };
 
-    let amended_commit_oid = head_commit.amend_commit(
+    let amended_commit_oid = repo.amend_commit(
+        &head_commit,
         Some("HEAD"),
         Some(&author),
         Some(&committer),
-        None,
-        Some(&amended_tree),
+        amended_message.as_deref(),
+        amended_tree.as_ref(),
     )?;
     mark_commit_reachable(&repo, amended_commit_oid)
         .wrap_err("Marking commit as reachable for GC purposes.")?;
 
-    event_log_db.add_events(vec![Event::RewriteEvent {
+    // The references were updated without going through Git, so no hooks
+    // were invoked to record the updates. Record them here, so that the amend
+    // can be undone.
+    let mut events = vec![Event::RewriteEvent {
         timestamp,
         event_tx_id,
         old_commit_oid: head_oid.into(),
         new_commit_oid: amended_commit_oid.into(),
-    }])?;
+    }];
+    let updated_ref_names = std::iter::once(OsString::from("HEAD")).chain(
+        head_info
+            .reference_name
+            .map(|ref_name| ref_name.into_owned()),
+    );
+    for ref_name in updated_ref_names {
+        events.push(Event::RefUpdateEvent {
+            timestamp,
+            event_tx_id,
+            ref_name,
+            old_oid: head_oid.into(),
+            new_oid: amended_commit_oid.into(),
+            message: None,
+        });
+    }
+    event_log_db.add_events(events)?;
 
-    if let AmendFastOptions::FromWorkingCopy { .. } = opts {
-        // TODO(#201): Figure out a way to perform "fast amend" on the working copy without needing a reset.
-        git_run_info.run(effects, Some(event_tx_id), &["reset"])?;
+    if let AmendFastOptions::FromWorkingCopy { status_entries } = &opts {
+        if !status_entries.is_empty() {
+            // TODO(#201): Figure out a way to perform "fast amend" on the working copy without needing a reset.
+            if pathspecs.is_empty() {
+                git_run_info.run(effects, Some(event_tx_id), &["reset"])?;
+            } else {
+                // Only reset the amended paths, so that any changes staged
+                // outside of the given paths are left alone.
+                let mut args: Vec<OsString> = vec!["reset".into(), "--".into()];
+                args.extend(
+                    status_entries
+                        .iter()
+                        .flat_map(|entry| entry.paths())
+                        .map(OsString::from),
+                );
+                git_run_info.run(effects, Some(event_tx_id), &args)?;
+            }
+        }
     }
 
-    let restack_exit_code = restack::restack(
-        effects,
-        git_run_info,
-        vec![head_oid.to_string()],
-        move_options,
-    )?;
-    if restack_exit_code != 0 {
-        return Ok(restack_exit_code);
+    let should_restack = !no_restack && get_amend_auto_restack(&repo)?;
+    if should_restack {
+        let restack_exit_code = restack::restack(
+            effects,
+            git_run_info,
+            vec![head_oid.to_string()],
+            false,
+            false,
+            move_options,
+        )?;
+        if restack_exit_code != 0 {
+            describe_abandoned_descendants(
+                effects,
+                &repo,
+                &event_log_db,
+                head_oid,
+                "restacking failed",
+            )?;
+            return Ok(restack_exit_code);
+        }
     }
 
     match opts {
@@ -148,7 +319,7 @@ pub fn amend(
             }
             writeln!(effects.get_output_stream(), "{}", message)?;
         }
-        AmendFastOptions::FromWorkingCopy { status_entries } => {
+        AmendFastOptions::FromWorkingCopy { status_entries } if !status_entries.is_empty() => {
             let uncommitted_changes = Pluralize {
                 amount: status_entries.len().try_into()?,
                 plural: "uncommitted changes",
@@ -160,6 +331,77 @@ pub fn amend(
                 uncommitted_changes.to_string(),
             )?;
         }
+        AmendFastOptions::FromWorkingCopy { .. } => {}
+    }
+    if amended_message.is_some() {
+        writeln!(effects.get_output_stream(), "Amended commit message.")?;
+    }
+    if !should_restack {
+        describe_abandoned_descendants(
+            effects,
+            &repo,
+            &event_log_db,
+            head_oid,
+            "restacking is disabled",
+        )?;
     }
     Ok(0)
 }
+
+/// List the visible descendants of the commit that was amended, which were
+/// not moved onto the amended commit and still need to be restacked.
+fn describe_abandoned_descendants(
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    old_commit_oid: NonZeroOid,
+    reason: &str,
+) -> eyre::Result<()> {
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let dag = Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let descendants = dag
+        .query()
+        .descendants(CommitSet::from(old_commit_oid))?
+        .difference(&CommitSet::from(old_commit_oid));
+    let abandoned_descendants = dag
+        .observed_commits
+        .intersection(&descendants)
+        .difference(&dag.obsolete_commits);
+    let abandoned_descendants = sort_commit_set(repo, &dag, &abandoned_descendants)?;
+    if abandoned_descendants.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(
+        effects.get_output_stream(),
+        "Left {} based on the old version of the amended commit, because {}:",
+        Pluralize {
+            amount: abandoned_descendants.len().try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string(),
+        reason,
+    )?;
+    for commit in abandoned_descendants {
+        writeln!(
+            effects.get_output_stream(),
+            "- {}",
+            printable_styled_string(effects.get_glyphs(), commit.friendly_describe()?)?
+        )?;
+    }
+    writeln!(
+        effects.get_output_stream(),
+        "Run `git restack` to move abandoned commits onto the amended commit."
+    )?;
+    Ok(())
+}
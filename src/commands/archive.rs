@@ -0,0 +1,261 @@
+//! Manage the named groups of commits created by `git hide --archive`.
+
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use tracing::instrument;
+
+use crate::commands::smartlog::{make_smartlog_graph_for_commits, render_graph};
+use crate::core::archive::{ArchiveDb, ArchiveGroup};
+use crate::core::dag::{CommitSet, Dag};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{CommitActivityStatus, Event, EventCursor, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::core::node_descriptors::{
+    BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor,
+    ObsolescenceExplanationDescriptor,
+};
+use crate::git::{GitRunInfo, NonZeroOid, Repo};
+
+/// Get the commits in the group which are currently hidden.
+fn get_hidden_commits(
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    group: &ArchiveGroup,
+) -> Vec<NonZeroOid> {
+    group
+        .commit_oids
+        .iter()
+        .copied()
+        .filter(|commit_oid| {
+            matches!(
+                event_replayer.get_cursor_commit_activity_status(event_cursor, *commit_oid),
+                CommitActivityStatus::Obsolete
+            )
+        })
+        .collect()
+}
+
+/// Look up the group with the given name, printing an error if it doesn't
+/// exist.
+fn find_group(
+    effects: &Effects,
+    archive_db: &ArchiveDb,
+    name: &str,
+) -> eyre::Result<Option<ArchiveGroup>> {
+    let group = archive_db.get_group(name)?;
+    if group.is_none() {
+        writeln!(
+            effects.get_error_stream(),
+            "There is no archive named: {}",
+            name
+        )?;
+    }
+    Ok(group)
+}
+
+/// List the archive groups, along with how many commits they contain and when
+/// they were created.
+#[instrument]
+pub fn list(effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let archive_db = ArchiveDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+
+    let groups = archive_db.get_groups()?;
+    if groups.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "There are no archived commits. To archive commits, run: git hide --archive <name> <commits>"
+        )?;
+        return Ok(0);
+    }
+
+    for group in groups {
+        let num_hidden_commits = get_hidden_commits(&event_replayer, event_cursor, &group).len();
+        writeln!(
+            effects.get_output_stream(),
+            "{}: {} ({} hidden), archived {}",
+            group.name,
+            Pluralize {
+                amount: group.commit_oids.len().try_into()?,
+                singular: "commit",
+                plural: "commits",
+            }
+            .to_string(),
+            num_hidden_commits,
+            DateTime::<Local>::from(group.timestamp).format("%Y-%m-%d %H:%M"),
+        )?;
+    }
+    Ok(0)
+}
+
+/// Render a smartlog of the commits in the given group, whether or not they're
+/// hidden.
+#[instrument]
+pub fn show(effects: &Effects, git_run_info: &GitRunInfo, name: &str) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let archive_db = ArchiveDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let group = match find_group(effects, &archive_db, name)? {
+        Some(group) => group,
+        None => return Ok(1),
+    };
+    let mut commit_oids = Vec::new();
+    for commit_oid in group.commit_oids.iter() {
+        if repo.find_commit(*commit_oid)?.is_some() {
+            commit_oids.push(*commit_oid);
+        }
+    }
+    let num_missing_commits = group.commit_oids.len() - commit_oids.len();
+
+    let graph = make_smartlog_graph_for_commits(
+        effects,
+        &repo,
+        &dag,
+        &event_replayer,
+        event_cursor,
+        &commit_oids.into_iter().collect::<CommitSet>(),
+    )?;
+    let lines = render_graph(
+        effects,
+        &repo,
+        &dag,
+        &graph,
+        references_snapshot.head_oid,
+        &mut [
+            &mut CommitOidDescriptor::new(true)?,
+            &mut ObsolescenceExplanationDescriptor::new(&event_replayer, event_cursor)?,
+            &mut BranchesDescriptor::new(&repo, &references_snapshot)?,
+            &mut CommitMessageDescriptor::new()?,
+        ],
+    )?;
+    for line in lines {
+        writeln!(
+            effects.get_output_stream(),
+            "{}",
+            printable_styled_string(effects.get_glyphs(), line)?
+        )?;
+    }
+    if num_missing_commits > 0 {
+        writeln!(
+            effects.get_output_stream(),
+            "({} in this archive could not be found.)",
+            Pluralize {
+                amount: num_missing_commits.try_into()?,
+                singular: "commit",
+                plural: "commits",
+            }
+            .to_string(),
+        )?;
+    }
+    Ok(0)
+}
+
+/// Unhide all of the hidden commits in the given group, in a single
+/// transaction. The group itself is kept, so that the commits can be found
+/// again if the restore is undone.
+#[instrument]
+pub fn restore(effects: &Effects, git_run_info: &GitRunInfo, name: &str) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let archive_db = ArchiveDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+
+    let group = match find_group(effects, &archive_db, name)? {
+        Some(group) => group,
+        None => return Ok(1),
+    };
+    let hidden_commit_oids = get_hidden_commits(&event_replayer, event_cursor, &group);
+    if hidden_commit_oids.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "None of the commits in archive {} are hidden, so there is nothing to restore.",
+            name
+        )?;
+        return Ok(0);
+    }
+
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let event_tx_id = event_log_db.make_transaction_id(now, "archive restore")?;
+    event_log_db.add_events(
+        hidden_commit_oids
+            .iter()
+            .map(|commit_oid| Event::UnobsoleteEvent {
+                timestamp,
+                event_tx_id,
+                commit_oid: *commit_oid,
+            })
+            .collect(),
+    )?;
+
+    for commit_oid in hidden_commit_oids.iter() {
+        if let Some(commit) = repo.find_commit(*commit_oid)? {
+            writeln!(
+                effects.get_output_stream(),
+                "Unhid commit: {}",
+                printable_styled_string(effects.get_glyphs(), commit.friendly_describe()?)?,
+            )?;
+        }
+    }
+    writeln!(
+        effects.get_result_stream(),
+        "Restored {} from archive: {}",
+        Pluralize {
+            amount: hidden_commit_oids.len().try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string(),
+        name
+    )?;
+    Ok(0)
+}
+
+/// Delete the given group. Its commits are no longer kept reachable on its
+/// account, so any which are still hidden can be garbage-collected once they've
+/// been hidden for longer than `branchless.gc.retentionDays`.
+#[instrument]
+pub fn delete(effects: &Effects, git_run_info: &GitRunInfo, name: &str) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let archive_db = ArchiveDb::new(&conn)?;
+
+    let group = match find_group(effects, &archive_db, name)? {
+        Some(group) => group,
+        None => return Ok(1),
+    };
+    archive_db.delete_group(name)?;
+    writeln!(
+        effects.get_output_stream(),
+        "Deleted archive {} ({}). Any of its commits which are still hidden will be released for garbage collection.",
+        name,
+        Pluralize {
+            amount: group.commit_oids.len().try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string(),
+    )?;
+    Ok(0)
+}
@@ -0,0 +1,410 @@
+//! Collect diagnostic information to attach to a bug report.
+//!
+//! The report is meant to be pasted into an issue as-is, so it must never
+//! include the contents of files in the repository or diffs. Commit messages
+//! are redacted unless the user explicitly opts in.
+
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::instrument;
+
+use crate::commands::init::{check_installation, read_installed_hooks};
+use crate::commands::smartlog::{make_smartlog_graph, render_graph};
+use crate::core::dag::Dag;
+use crate::core::effects::Effects;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer, EventTransactionId};
+use crate::core::formatting::printable_styled_string;
+use crate::core::node_descriptors::{
+    BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor, NodeDescriptor,
+    ObsolescenceExplanationDescriptor,
+};
+use crate::git::{ConfigRead, GitRunInfo, Repo};
+
+/// Placeholder printed in place of commit messages, unless the user asked to
+/// include them.
+const REDACTED: &str = "<redacted>";
+
+/// The number of lines to include from the end of the tracing log file.
+const NUM_TRACING_LOG_LINES: usize = 20;
+
+fn describe_git_version(git_run_info: &GitRunInfo, repo: &Repo) -> eyre::Result<String> {
+    let result = git_run_info.run_silent(repo, None, &["version"], Default::default())?;
+    let version = String::from_utf8_lossy(&result.stdout).trim().to_string();
+    if result.exit_code != 0 || version.is_empty() {
+        Ok(format!("<unknown> (exit code {})", result.exit_code))
+    } else {
+        Ok(version)
+    }
+}
+
+fn write_versions_section(
+    out: &mut String,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+) -> eyre::Result<()> {
+    writeln!(out, "## Versions")?;
+    writeln!(out, "git-branchless: {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out, "git: {}", describe_git_version(git_run_info, repo)?)?;
+    writeln!(
+        out,
+        "event log schema: {}",
+        event_log_db.get_schema_version()?
+    )?;
+    writeln!(
+        out,
+        "OS: {} ({})",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )?;
+    Ok(())
+}
+
+fn write_config_section(out: &mut String, repo: &Repo) -> eyre::Result<()> {
+    writeln!(out, "## Config")?;
+    let config = repo.get_readonly_config()?;
+    let entries = config.list(r"^branchless\.")?;
+    if entries.is_empty() {
+        writeln!(out, "(no branchless.* config values set)")?;
+    }
+    for (name, value) in entries {
+        writeln!(out, "{} = {}", name, value)?;
+    }
+    Ok(())
+}
+
+fn write_installation_section(out: &mut String, repo: &Repo) -> eyre::Result<()> {
+    writeln!(out, "## Installation")?;
+    for check in check_installation(repo)? {
+        writeln!(out, "{}", check.describe())?;
+    }
+    Ok(())
+}
+
+fn write_hooks_section(out: &mut String, repo: &Repo) -> eyre::Result<()> {
+    writeln!(out, "## Hooks")?;
+    for hook in read_installed_hooks(repo)? {
+        let status = match (&hook.contents, hook.markers_intact) {
+            (None, _) => "not installed",
+            (Some(_), None) => "installed (multihook)",
+            (Some(_), Some(true)) => "installed, markers intact",
+            (Some(_), Some(false)) => "installed, markers NOT intact",
+        };
+        writeln!(
+            out,
+            "### {} ({}): {}",
+            hook.hook_type,
+            hook.path.display(),
+            status
+        )?;
+        if let Some(contents) = &hook.contents {
+            for line in contents.lines() {
+                writeln!(out, "    {}", line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn describe_message(message: &OsStr, include_messages: bool) -> String {
+    if include_messages {
+        format!("{:?}", message.to_string_lossy())
+    } else {
+        REDACTED.to_string()
+    }
+}
+
+fn describe_event(repo: &Repo, event: &Event, include_messages: bool) -> eyre::Result<String> {
+    let description = match event {
+        Event::RewriteEvent {
+            timestamp: _,
+            event_tx_id: _,
+            old_commit_oid,
+            new_commit_oid,
+        } => format!("rewrite {} -> {}", old_commit_oid, new_commit_oid),
+
+        Event::RefUpdateEvent {
+            timestamp: _,
+            event_tx_id: _,
+            ref_name,
+            old_oid,
+            new_oid,
+            message,
+        } => {
+            let message = match message {
+                Some(message) => {
+                    format!(
+                        " (message: {})",
+                        describe_message(message, include_messages)
+                    )
+                }
+                None => String::new(),
+            };
+            format!(
+                "ref-update {}: {} -> {}{}",
+                ref_name.to_string_lossy(),
+                old_oid,
+                new_oid,
+                message
+            )
+        }
+
+        Event::CommitEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+        } => {
+            let message = match repo.find_commit(*commit_oid)? {
+                Some(commit) => format!(
+                    " (message: {})",
+                    describe_message(&commit.get_summary()?, include_messages)
+                ),
+                None => String::new(),
+            };
+            format!("commit {}{}", commit_oid, message)
+        }
+
+        Event::ObsoleteEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+        } => format!("obsolete {}", commit_oid),
+
+        Event::UnobsoleteEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+        } => format!("unobsolete {}", commit_oid),
+
+        Event::WorkingCopySnapshot {
+            timestamp: _,
+            event_tx_id: _,
+            head_oid,
+            commit_oid,
+            ref_name,
+        } => format!(
+            "working-copy-snapshot {} (HEAD {}{})",
+            commit_oid,
+            head_oid,
+            match ref_name {
+                Some(ref_name) => format!(" at {}", ref_name.to_string_lossy()),
+                None => String::new(),
+            }
+        ),
+    };
+    Ok(description)
+}
+
+fn write_event_log_section(
+    out: &mut String,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    num_transactions: usize,
+    include_messages: bool,
+) -> eyre::Result<()> {
+    writeln!(out, "## Event log (last {} transactions)", num_transactions)?;
+
+    let mut transactions: Vec<(EventTransactionId, Vec<Event>)> = Vec::new();
+    for event in event_log_db.get_events()? {
+        let event_tx_id = event.get_event_tx_id();
+        match transactions.last_mut() {
+            Some((last_event_tx_id, events)) if *last_event_tx_id == event_tx_id => {
+                events.push(event)
+            }
+            Some(_) | None => transactions.push((event_tx_id, vec![event])),
+        }
+    }
+    if transactions.is_empty() {
+        writeln!(out, "(no events)")?;
+    }
+
+    let start = transactions.len().saturating_sub(num_transactions);
+    for (event_tx_id, events) in &transactions[start..] {
+        let transaction_message = event_log_db
+            .get_transaction_message(*event_tx_id)?
+            .unwrap_or_else(|| "<no message>".to_string());
+        let timestamp = match events.first() {
+            Some(event) => event
+                .get_timestamp()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            None => 0,
+        };
+        writeln!(
+            out,
+            "### Transaction {} at {}: {}",
+            event_tx_id.to_string(),
+            timestamp,
+            transaction_message
+        )?;
+        for event in events {
+            writeln!(out, "{}", describe_event(repo, event, include_messages)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_smartlog_section(
+    out: &mut String,
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    include_messages: bool,
+) -> eyre::Result<()> {
+    writeln!(out, "## Smartlog")?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let dag = Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+    let graph = make_smartlog_graph(effects, repo, &dag, &event_replayer, event_cursor, true)?;
+
+    let mut commit_oid_descriptor = CommitOidDescriptor::new(false)?;
+    let mut obsolescence_explanation_descriptor =
+        ObsolescenceExplanationDescriptor::new(&event_replayer, event_cursor)?;
+    let mut branches_descriptor = BranchesDescriptor::new(repo, &references_snapshot)?;
+    let mut commit_message_descriptor = CommitMessageDescriptor::new()?;
+    let mut commit_descriptors: Vec<&mut dyn NodeDescriptor> = vec![
+        &mut commit_oid_descriptor,
+        &mut obsolescence_explanation_descriptor,
+        &mut branches_descriptor,
+    ];
+    if include_messages {
+        commit_descriptors.push(&mut commit_message_descriptor);
+    }
+
+    let lines = render_graph(
+        effects,
+        repo,
+        &dag,
+        &graph,
+        references_snapshot.head_oid,
+        &mut commit_descriptors,
+    )?;
+    if lines.is_empty() {
+        writeln!(out, "(no commits)")?;
+    }
+    for line in lines {
+        writeln!(
+            out,
+            "{}",
+            printable_styled_string(effects.get_glyphs(), line)?
+        )?;
+    }
+    Ok(())
+}
+
+/// Find the most recently-modified tracing log file in the given directory, as
+/// written when `git-branchless` is run with `RUST_PROFILE` set.
+fn find_latest_tracing_log(dir: &Path) -> eyre::Result<Option<PathBuf>> {
+    let custom_prefix = match std::env::var("RUST_PROFILE") {
+        Ok(value) if !value.is_empty() && value != "1" && value != "true" => {
+            Some(format!("{}-", value))
+        }
+        Ok(_) | Err(_) => None,
+    };
+    let is_tracing_log = |file_name: &str| -> bool {
+        (file_name.starts_with("trace-") && file_name.contains(".json-"))
+            || matches!(&custom_prefix, Some(prefix) if file_name.starts_with(prefix.as_str()))
+    };
+
+    let mut latest: Option<(SystemTime, PathBuf)> = None;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if !is_tracing_log(&file_name.to_string_lossy()) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || metadata.len() == 0 {
+            continue;
+        }
+        let modified = metadata.modified()?;
+        match &latest {
+            Some((latest_modified, _)) if *latest_modified >= modified => {}
+            Some(_) | None => latest = Some((modified, entry.path())),
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
+
+fn write_tracing_log_section(out: &mut String, git_run_info: &GitRunInfo) -> eyre::Result<()> {
+    writeln!(out, "## Tracing log")?;
+    match find_latest_tracing_log(&git_run_info.working_directory)? {
+        None => {
+            writeln!(
+                out,
+                "(no tracing log found; re-run the failing command with RUST_PROFILE=1 to produce one)"
+            )?;
+        }
+        Some(path) => {
+            writeln!(
+                out,
+                "Last {} lines of {}:",
+                NUM_TRACING_LOG_LINES,
+                path.display()
+            )?;
+            let contents = std::fs::read_to_string(&path)?;
+            let lines: Vec<&str> = contents.lines().collect();
+            let start = lines.len().saturating_sub(NUM_TRACING_LOG_LINES);
+            for line in &lines[start..] {
+                writeln!(out, "{}", line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print diagnostic information about the repository and the `git-branchless`
+/// installation as a fenced Markdown block, suitable for pasting into a bug
+/// report.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
+pub fn bug_report(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    num_transactions: usize,
+    include_messages: bool,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+
+    let mut out = String::new();
+    writeln!(out, "# git-branchless bug report")?;
+    writeln!(out)?;
+    write_versions_section(&mut out, git_run_info, &repo, &event_log_db)?;
+    writeln!(out)?;
+    write_config_section(&mut out, &repo)?;
+    writeln!(out)?;
+    write_installation_section(&mut out, &repo)?;
+    writeln!(out)?;
+    write_hooks_section(&mut out, &repo)?;
+    writeln!(out)?;
+    write_event_log_section(
+        &mut out,
+        &repo,
+        &event_log_db,
+        num_transactions,
+        include_messages,
+    )?;
+    writeln!(out)?;
+    write_smartlog_section(&mut out, effects, &repo, &event_log_db, include_messages)?;
+    writeln!(out)?;
+    write_tracing_log_section(&mut out, git_run_info)?;
+
+    writeln!(effects.get_output_stream(), "```")?;
+    write!(effects.get_output_stream(), "{}", out)?;
+    writeln!(effects.get_output_stream(), "```")?;
+    Ok(0)
+}
@@ -0,0 +1,171 @@
+//! Show the combined changes of a stack or a set of commits.
+//!
+//! The endpoints of the diff are computed from the commit graph, but the diff
+//! itself is rendered by `git diff` or `git show`, so that the user's pager and
+//! diff configuration apply as usual.
+
+use std::convert::TryInto;
+use std::fmt::Write;
+
+use eden_dag::DagAlgorithm;
+use tracing::instrument;
+
+use crate::core::dag::{commit_set_to_vec, CommitSet, Dag};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::core::formatting::Pluralize;
+use crate::core::revset::resolve_revsets;
+use crate::git::{GitRunInfo, GitRunStreamingOpts, NonZeroOid, Repo};
+
+/// Show the diff of the commits in `revset`. If it's a single commit, its patch
+/// is shown, as with `git show`. Otherwise, the combined diff of the commits
+/// against the commit they're based on is shown. If `revset` isn't provided,
+/// the combined diff of the current stack up to `HEAD` is shown.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
+pub fn diff(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    revset: Option<String>,
+    stat: bool,
+    name_only: bool,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let (description, commits) = match revset {
+        Some(revset) => {
+            let commits =
+                match resolve_revsets(effects, &repo, &mut dag, std::slice::from_ref(&revset))? {
+                    Ok(mut commit_sets) => commit_sets.remove(0),
+                    Err(err) => {
+                        err.describe(effects)?;
+                        return Ok(1);
+                    }
+                };
+            (revset, commits)
+        }
+        None => {
+            let head_oid = match references_snapshot.head_oid {
+                Some(head_oid) => head_oid,
+                None => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "No commits have been made in this repository yet."
+                    )?;
+                    return Ok(1);
+                }
+            };
+            let commits = dag
+                .query()
+                .ancestors(CommitSet::from(head_oid))?
+                .intersection(&dag.query_draft_commits()?);
+            ("the current stack".to_string(), commits)
+        }
+    };
+
+    let mut args = match commit_set_to_vec(&commits)?.as_slice() {
+        [] => {
+            writeln!(
+                effects.get_output_stream(),
+                "There are no commits in {}.",
+                description
+            )?;
+            return Ok(0);
+        }
+
+        [commit_oid] => vec!["show".to_string(), commit_oid.to_string()],
+
+        _ => {
+            let (base_oid, head_oid) = match find_endpoints(&dag, &commits)? {
+                Ok(endpoints) => endpoints,
+                Err(reason) => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Cannot show the combined diff of {}, because {}.",
+                        description,
+                        reason
+                    )?;
+                    return Ok(1);
+                }
+            };
+            vec![
+                "diff".to_string(),
+                base_oid.to_string(),
+                head_oid.to_string(),
+            ]
+        }
+    };
+    if stat {
+        args.push("--stat".to_string());
+    }
+    if name_only {
+        args.push("--name-only".to_string());
+    }
+
+    let result = git_run_info.run_streaming(
+        effects,
+        &repo,
+        None,
+        &args,
+        GitRunStreamingOpts {
+            treat_git_failure_as_error: false,
+            show_stdout: true,
+            use_terminal: true,
+        },
+    )?;
+    Ok(result.exit_code.try_into()?)
+}
+
+/// Find the commit which `commits` are based on and the single head of
+/// `commits`, between which the combined diff is computed. If there are no
+/// such commits, or if the diff between them would include commits which
+/// aren't in `commits`, returns the reason why.
+fn find_endpoints(
+    dag: &Dag,
+    commits: &CommitSet,
+) -> eyre::Result<Result<(NonZeroOid, NonZeroOid), String>> {
+    let base_oid = match dag.find_base_commit(commits)? {
+        Some(base_oid) => base_oid,
+        None => return Ok(Err("they are not based on a single commit".to_string())),
+    };
+
+    let heads = dag.query().heads(commits.clone())?;
+    let head_oid = match commit_set_to_vec(&heads)?.as_slice() {
+        [head_oid] => *head_oid,
+        head_oids => {
+            return Ok(Err(format!(
+                "they have {}",
+                Pluralize {
+                    amount: head_oids.len().try_into()?,
+                    singular: "head",
+                    plural: "heads",
+                }
+                .to_string()
+            )))
+        }
+    };
+
+    let range = dag
+        .query()
+        .range(dag.query().roots(commits.clone())?, heads)?;
+    if range.count()? != commits.count()? {
+        return Ok(Err(
+            "other commits between them would be included".to_string()
+        ));
+    }
+
+    Ok(Ok((base_oid, head_oid)))
+}
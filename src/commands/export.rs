@@ -0,0 +1,419 @@
+//! Export commits as a series of patches, in the style of `git format-patch`.
+//!
+//! Each commit becomes one email-formatted patch, with a `[PATCH m/n]` subject
+//! prefix. The patches are written to numbered files, a single mbox file, or
+//! standard output. Optionally, a cover letter stub is generated as well, which
+//! includes a smartlog of the exported commits.
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{FixedOffset, TimeZone};
+use eyre::Context;
+use tracing::instrument;
+
+use crate::commands::smartlog::{make_smartlog_graph_for_commits, render_graph};
+use crate::core::dag::{sort_commit_set, CommitSet, Dag};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Glyphs};
+use crate::core::node_descriptors::{
+    BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor,
+};
+use crate::core::revset::resolve_revsets;
+use crate::git::{
+    get_diff_stat, get_patch_text, Commit, GitRunInfo, MaybeZeroOid, Repo, Signature,
+    MAX_DIFF_STAT_FILES,
+};
+
+/// The maximum length of the part of a patch file name which is derived from
+/// the commit summary.
+const MAX_FILE_NAME_SUBJECT_LEN: usize = 52;
+
+/// Options for `export`.
+#[derive(Debug, Default)]
+pub struct ExportOptions {
+    /// The directory to write the patch files to, if not the current
+    /// directory.
+    pub output_directory: Option<PathBuf>,
+
+    /// If set, write all of the patches to this file in mbox format.
+    pub mbox: Option<PathBuf>,
+
+    /// If set, write all of the patches to standard output in mbox format.
+    pub stdout: bool,
+
+    /// The version of the patch series, if not the first.
+    pub reroll_count: Option<usize>,
+
+    /// Whether to also generate a cover letter.
+    pub cover_letter: bool,
+}
+
+/// A generated patch, or the cover letter.
+struct Patch {
+    file_name: String,
+    contents: Vec<u8>,
+}
+
+/// Make the subject prefix for the given patch, such as `[PATCH v2 1/3]`.
+/// `patch_num` is 0 for the cover letter.
+fn make_subject_prefix(
+    reroll_count: Option<usize>,
+    patch_num: usize,
+    num_patches: usize,
+    is_numbered: bool,
+) -> String {
+    let mut prefix = "PATCH".to_string();
+    if let Some(reroll_count) = reroll_count {
+        prefix.push_str(&format!(" v{}", reroll_count));
+    }
+    if is_numbered {
+        let width = num_patches.to_string().len();
+        prefix.push_str(&format!(
+            " {:0width$}/{}",
+            patch_num,
+            num_patches,
+            width = width
+        ));
+    }
+    format!("[{}]", prefix)
+}
+
+/// Make the name of the file for the given patch, such as
+/// `v2-0001-Fix-the-frobnicator.patch`, in the same way as `git format-patch`.
+fn make_file_name(reroll_count: Option<usize>, patch_num: usize, subject: &str) -> String {
+    let mut sanitized_subject = String::new();
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            sanitized_subject.push(c);
+        } else if !sanitized_subject.is_empty() && !sanitized_subject.ends_with('-') {
+            sanitized_subject.push('-');
+        }
+        if sanitized_subject.len() >= MAX_FILE_NAME_SUBJECT_LEN {
+            break;
+        }
+    }
+    let sanitized_subject = sanitized_subject.trim_end_matches(&['-', '.'][..]);
+
+    let version_prefix = match reroll_count {
+        Some(reroll_count) => format!("v{}-", reroll_count),
+        None => String::new(),
+    };
+    format!(
+        "{}{:04}-{}.patch",
+        version_prefix, patch_num, sanitized_subject
+    )
+}
+
+/// Write the email headers for a patch.
+fn write_headers(
+    contents: &mut String,
+    commit_oid: &str,
+    signature: &Signature,
+    subject: &str,
+) -> eyre::Result<()> {
+    let time = signature.get_time();
+    let offset = FixedOffset::east(time.offset_minutes() * 60);
+    let date = offset.timestamp(time.seconds(), 0).to_rfc2822();
+    writeln!(contents, "From {} Mon Sep 17 00:00:00 2001", commit_oid)?;
+    writeln!(
+        contents,
+        "From: {} <{}>",
+        signature.get_name().unwrap_or_default(),
+        signature.get_email().unwrap_or_default()
+    )?;
+    writeln!(contents, "Date: {}", date)?;
+    writeln!(contents, "Subject: {}", subject)?;
+    writeln!(contents)?;
+    Ok(())
+}
+
+/// Generate the patch for a commit, which must have exactly one parent.
+fn make_patch(
+    repo: &Repo,
+    commit: &Commit,
+    subject_prefix: &str,
+    file_name: String,
+) -> eyre::Result<Patch> {
+    let parent_oid = match commit.get_parent_oids().as_slice() {
+        [parent_oid] => *parent_oid,
+        _ => eyre::bail!("Commit {:?} does not have exactly one parent", commit),
+    };
+    let parent = repo.find_commit_or_fail(parent_oid)?;
+    let commit_oid = commit.get_oid();
+
+    let message = commit.get_message_raw()?.to_string_lossy().into_owned();
+    let summary = commit.get_summary()?.to_string_lossy().into_owned();
+    // The summary is the first paragraph of the message, and the rest of the
+    // message is the body.
+    let body = match message.trim().split_once("\n\n") {
+        Some((_summary, body)) => body.trim().to_string(),
+        None => String::new(),
+    };
+
+    let mut headers = String::new();
+    write_headers(
+        &mut headers,
+        &commit_oid.to_string(),
+        &commit.get_author(),
+        &format!("{} {}", subject_prefix, summary),
+    )?;
+    if !body.is_empty() {
+        writeln!(headers, "{}", body)?;
+        writeln!(headers)?;
+    }
+    writeln!(headers, "---")?;
+    for line in get_diff_stat(repo, parent_oid, commit_oid)?.render(MAX_DIFF_STAT_FILES) {
+        writeln!(headers, "{}", line)?;
+    }
+    writeln!(headers)?;
+
+    let mut contents = headers.into_bytes();
+    contents.extend(get_patch_text(
+        repo,
+        Some(&parent.get_tree()?),
+        &commit.get_tree()?,
+    )?);
+    contents.push(b'\n');
+    Ok(Patch {
+        file_name,
+        contents,
+    })
+}
+
+/// Generate the cover letter for the given commits, which must be in
+/// topological order.
+#[allow(clippy::too_many_arguments)]
+fn make_cover_letter(
+    repo: &Repo,
+    dag: &Dag,
+    event_replayer: &EventReplayer,
+    commit_set: &CommitSet,
+    commits: &[Commit],
+    subject_prefix: &str,
+    file_name: String,
+) -> eyre::Result<Patch> {
+    let last_commit = match commits.last() {
+        Some(last_commit) => last_commit,
+        None => eyre::bail!("No commits for cover letter"),
+    };
+
+    let mut contents = String::new();
+    write_headers(
+        &mut contents,
+        &MaybeZeroOid::Zero.to_string(),
+        &last_commit.get_committer(),
+        &format!("{} *** SUBJECT HERE ***", subject_prefix),
+    )?;
+    writeln!(contents, "*** BLURB HERE ***")?;
+    writeln!(contents)?;
+
+    // The cover letter is plain text, so render the smartlog without any
+    // special characters or colors.
+    let effects = Effects::new_without_progress(Glyphs::text());
+    let event_cursor = event_replayer.make_default_cursor();
+    let graph = make_smartlog_graph_for_commits(
+        &effects,
+        repo,
+        dag,
+        event_replayer,
+        event_cursor,
+        commit_set,
+    )?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let lines = render_graph(
+        &effects,
+        repo,
+        dag,
+        &graph,
+        None,
+        &mut [
+            &mut CommitOidDescriptor::new(false)?,
+            &mut BranchesDescriptor::new(repo, &references_snapshot)?,
+            &mut CommitMessageDescriptor::new()?,
+        ],
+    )?;
+    for line in lines {
+        writeln!(
+            contents,
+            "{}",
+            printable_styled_string(effects.get_glyphs(), line)?
+        )?;
+    }
+    writeln!(contents)?;
+
+    let mut authors: Vec<(String, Vec<String>)> = Vec::new();
+    for commit in commits {
+        let author = commit
+            .get_author()
+            .get_name()
+            .unwrap_or_default()
+            .to_string();
+        let summary = commit.get_summary()?.to_string_lossy().into_owned();
+        match authors.iter_mut().find(|(name, _)| *name == author) {
+            Some((_, summaries)) => summaries.push(summary),
+            None => authors.push((author, vec![summary])),
+        }
+    }
+    for (author, summaries) in authors {
+        writeln!(contents, "{} ({}):", author, summaries.len())?;
+        for summary in summaries {
+            writeln!(contents, "  {}", summary)?;
+        }
+        writeln!(contents)?;
+    }
+
+    // There's no meaningful combined diffstat if the commits aren't based on a
+    // single commit, such as if they're from different stacks.
+    if let Some(base_oid) = dag.find_base_commit(commit_set)? {
+        for line in
+            get_diff_stat(repo, base_oid, last_commit.get_oid())?.render(MAX_DIFF_STAT_FILES)
+        {
+            writeln!(contents, "{}", line)?;
+        }
+        writeln!(contents)?;
+    }
+
+    Ok(Patch {
+        file_name,
+        contents: contents.into_bytes(),
+    })
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> eyre::Result<()> {
+    std::fs::write(path, contents).wrap_err_with(|| format!("Writing file: {:?}", path))
+}
+
+/// Export the given commits (or the current stack, if none are given) as a
+/// series of patches.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
+pub fn export(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    revsets: Vec<String>,
+    options: &ExportOptions,
+) -> eyre::Result<isize> {
+    let ExportOptions {
+        output_directory,
+        mbox,
+        stdout,
+        reroll_count,
+        cover_letter,
+    } = options;
+    let glyphs = effects.get_glyphs();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let revsets = if revsets.is_empty() {
+        vec!["stack()".to_string()]
+    } else {
+        revsets
+    };
+    let commit_sets = match resolve_revsets(effects, &repo, &mut dag, &revsets)? {
+        Ok(commit_sets) => commit_sets,
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(1);
+        }
+    };
+    let commit_set = commit_sets
+        .iter()
+        .fold(CommitSet::empty(), |acc, commit_set| acc.union(commit_set));
+    let commits = sort_commit_set(&repo, &dag, &commit_set)?;
+    if commits.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "There are no commits to export."
+        )?;
+        return Ok(0);
+    }
+
+    for commit in commits.iter() {
+        let reason = match commit.get_parent_count() {
+            1 => continue,
+            0 => "it has no parent",
+            _ => "it is a merge commit",
+        };
+        writeln!(
+            effects.get_output_stream(),
+            "Cannot export {}, because {}.",
+            printable_styled_string(glyphs, commit.friendly_describe()?)?,
+            reason
+        )?;
+        return Ok(1);
+    }
+
+    let num_patches = commits.len();
+    let is_numbered = num_patches > 1 || *cover_letter;
+    let mut patches = Vec::new();
+    if *cover_letter {
+        patches.push(make_cover_letter(
+            &repo,
+            &dag,
+            &event_replayer,
+            &commit_set,
+            &commits,
+            &make_subject_prefix(*reroll_count, 0, num_patches, is_numbered),
+            make_file_name(*reroll_count, 0, "cover-letter"),
+        )?);
+    }
+    for (patch_num, commit) in (1..).zip(commits.iter()) {
+        let summary = commit.get_summary()?.to_string_lossy().into_owned();
+        patches.push(make_patch(
+            &repo,
+            commit,
+            &make_subject_prefix(*reroll_count, patch_num, num_patches, is_numbered),
+            make_file_name(*reroll_count, patch_num, &summary),
+        )?);
+    }
+
+    if *stdout {
+        for Patch { contents, .. } in patches {
+            write!(
+                effects.get_output_stream(),
+                "{}",
+                String::from_utf8_lossy(&contents)
+            )?;
+        }
+    } else if let Some(mbox) = mbox {
+        let contents: Vec<u8> = patches
+            .into_iter()
+            .flat_map(|Patch { contents, .. }| contents)
+            .collect();
+        write_file(&git_run_info.working_directory.join(mbox), &contents)?;
+        writeln!(effects.get_output_stream(), "{}", mbox.to_string_lossy())?;
+    } else {
+        if let Some(output_directory) = output_directory {
+            std::fs::create_dir_all(output_directory)
+                .wrap_err_with(|| format!("Creating output directory: {:?}", output_directory))?;
+        }
+        for Patch {
+            file_name,
+            contents,
+        } in patches
+        {
+            let path = match output_directory {
+                Some(output_directory) => output_directory.join(file_name),
+                None => PathBuf::from(file_name),
+            };
+            write_file(&git_run_info.working_directory.join(&path), &contents)?;
+            writeln!(effects.get_output_stream(), "{}", path.to_string_lossy())?;
+        }
+    }
+
+    Ok(0)
+}
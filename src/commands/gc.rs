@@ -6,26 +6,72 @@
 //!
 //! This module is responsible for adding extra references to Git, so that Git's
 //! garbage collection doesn't collect commits which branchless thinks are still
-//! active.
+//! active. Hidden commits and dropped stash entries stay anchored for
+//! `branchless.gc.retentionDays` days, so that `git undo` can still restore
+//! them, and archived commits (see `git hide --archive`) stay anchored for as
+//! long as their archive exists.
+//!
+//! It can also prune old events from the event log, as long as they're no
+//! longer needed to determine which commits are visible.
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 use std::ffi::OsStr;
 use std::fmt::Write;
+use std::time::{Duration, SystemTime};
 
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
 use eyre::Context;
 use tracing::instrument;
 
+use crate::core::archive::ArchiveDb;
+use crate::core::config::{
+    get_eventlog_auto_prune_days, get_eventlog_prune_keep_transactions,
+    get_eventlog_ref_ignore_patterns, get_gc_retention_days, get_snapshot_max_age_days,
+};
+use crate::core::dag::{CommitSet, CommitVertex, Dag};
 use crate::core::effects::Effects;
 use crate::core::eventlog::{
-    is_gc_ref, CommitActivityStatus, EventCursor, EventLogDb, EventReplayer,
+    is_gc_ref, matches_ref_ignore_patterns, should_ignore_ref_updates, CommitActivityStatus, Event,
+    EventCursor, EventLogDb, EventReplayer, EventTransactionId,
 };
 use crate::core::formatting::Pluralize;
-use crate::git::{NonZeroOid, Reference, Repo};
+use crate::core::snapshot::{find_expired_snapshot_references, is_snapshot_ref};
+use crate::core::stash::find_recent_stash_commits;
+use crate::git::{GitRunInfo, GitRunOpts, MaybeZeroOid, NonZeroOid, Reference, Repo};
 
-fn find_dangling_references<'repo>(
-    repo: &'repo Repo,
+/// The number of days of events to keep when pruning the event log, if neither
+/// `--before` nor `branchless.eventlog.autoPruneDays` is set.
+const DEFAULT_PRUNE_DAYS: u64 = 90;
+
+/// Find the commits which should be kept reachable: those which are visible,
+/// and those which were hidden no earlier than `cutoff`, so that they can still
+/// be restored with `git undo`.
+fn find_anchored_commits(
     event_replayer: &EventReplayer,
     event_cursor: EventCursor,
+    cutoff: SystemTime,
+) -> HashSet<NonZeroOid> {
+    event_replayer
+        .get_cursor_oids(event_cursor)
+        .into_iter()
+        .filter(|commit_oid| {
+            match event_replayer.get_cursor_commit_activity_status(event_cursor, *commit_oid) {
+                CommitActivityStatus::Active => true,
+                CommitActivityStatus::Inactive | CommitActivityStatus::Obsolete => {
+                    match event_replayer.get_cursor_commit_latest_event(event_cursor, *commit_oid) {
+                        Some(event) => event.get_timestamp() >= cutoff,
+                        None => false,
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+fn find_dangling_references<'repo>(
+    repo: &'repo Repo,
+    anchored_commits: &HashSet<NonZeroOid>,
 ) -> eyre::Result<Vec<Reference<'repo>>> {
     let mut result = Vec::new();
     for reference in repo.get_all_references()? {
@@ -34,6 +80,12 @@ fn find_dangling_references<'repo>(
             continue;
         }
 
+        // Snapshot commits are never active, so their references are
+        // collected based on their age instead.
+        if is_snapshot_ref(&reference_name) {
+            continue;
+        }
+
         // The graph only contains commits, so we don't need to handle the
         // case of the reference not peeling to a valid commit. (It might be
         // a reference to a different kind of object.)
@@ -42,18 +94,17 @@ fn find_dangling_references<'repo>(
             None => continue,
         };
 
-        match event_replayer.get_cursor_commit_activity_status(event_cursor, commit.get_oid()) {
-            CommitActivityStatus::Active => {
-                // Do nothing.
-            }
-            CommitActivityStatus::Inactive | CommitActivityStatus::Obsolete => {
-                result.push(reference)
-            }
+        if !anchored_commits.contains(&commit.get_oid()) {
+            result.push(reference);
         }
     }
     Ok(result)
 }
 
+fn get_gc_ref_name(commit_oid: NonZeroOid) -> String {
+    format!("refs/branchless/{}", commit_oid.to_string())
+}
+
 /// Mark a commit as reachable.
 ///
 /// Once marked as reachable, the commit won't be collected by Git's garbage
@@ -73,7 +124,7 @@ pub fn mark_commit_reachable(repo: &Repo, commit_oid: NonZeroOid) -> eyre::Resul
         return Ok(());
     }
 
-    let ref_name = format!("refs/branchless/{}", commit_oid.to_string());
+    let ref_name = get_gc_ref_name(commit_oid);
     eyre::ensure!(
         Reference::is_valid_name(&ref_name),
         format!("Invalid ref name to mark commit as reachable: {}", ref_name)
@@ -88,36 +139,755 @@ pub fn mark_commit_reachable(repo: &Repo, commit_oid: NonZeroOid) -> eyre::Resul
     Ok(())
 }
 
-/// Run branchless's garbage collection.
+fn days_before(now: SystemTime, days: u64) -> SystemTime {
+    now.checked_sub(Duration::from_secs(days * 24 * 60 * 60))
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Parse the argument to `--before`, which is either a duration before `now`
+/// (such as `12h`, `30d`, or `8w`) or a date (such as `2021-06-01`).
+fn parse_prune_cutoff(now: SystemTime, before: &str) -> eyre::Result<SystemTime> {
+    if let Some(unit) = before.chars().last() {
+        let amount = &before[..before.len() - unit.len_utf8()];
+        if let Ok(amount) = amount.parse::<u64>() {
+            let seconds_per_unit = match unit {
+                's' => Some(1),
+                'm' => Some(60),
+                'h' => Some(60 * 60),
+                'd' => Some(24 * 60 * 60),
+                'w' => Some(7 * 24 * 60 * 60),
+                _ => None,
+            };
+            if let Some(seconds_per_unit) = seconds_per_unit {
+                return Ok(now
+                    .checked_sub(Duration::from_secs(amount * seconds_per_unit))
+                    .unwrap_or(SystemTime::UNIX_EPOCH));
+            }
+        }
+    }
+
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(before) {
+        return Ok(date_time.into());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(before, "%Y-%m-%d") {
+        if let Some(date_time) = Local.from_local_datetime(&date.and_hms(0, 0, 0)).earliest() {
+            return Ok(date_time.into());
+        }
+    }
+
+    eyre::bail!(
+        "Could not parse {:?} as a duration (such as `30d`) or a date (such as `2021-06-01`)",
+        before
+    )
+}
+
+fn get_event_commit_oids(event: &Event) -> Vec<NonZeroOid> {
+    match event {
+        Event::CommitEvent { commit_oid, .. }
+        | Event::ObsoleteEvent { commit_oid, .. }
+        | Event::UnobsoleteEvent { commit_oid, .. } => vec![*commit_oid],
+        Event::RewriteEvent {
+            old_commit_oid,
+            new_commit_oid,
+            ..
+        } => [old_commit_oid, new_commit_oid]
+            .iter()
+            .filter_map(|oid| match oid {
+                MaybeZeroOid::NonZero(oid) => Some(*oid),
+                MaybeZeroOid::Zero => None,
+            })
+            .collect(),
+        Event::RefUpdateEvent { .. } | Event::WorkingCopySnapshot { .. } => Vec::new(),
+    }
+}
+
+/// Determine whether the events for the given commit must be kept to preserve
+/// the current commit graph. This is the case for commits which will still be
+/// anchored after garbage collection, and for visible commits, unless they're
+/// public and not obsolete (in which case they're shown anyways).
+fn is_commit_history_needed(
+    dag: &Dag,
+    anchored_commits: &HashSet<NonZeroOid>,
+    visible_commits: &CommitSet,
+    public_commits: &CommitSet,
+    commit_oid: NonZeroOid,
+) -> eyre::Result<bool> {
+    if anchored_commits.contains(&commit_oid) {
+        return Ok(true);
+    }
+    let vertex = CommitVertex::from(commit_oid);
+    if !visible_commits.contains(&vertex)? {
+        return Ok(false);
+    }
+    Ok(!public_commits.contains(&vertex)? || dag.obsolete_commits.contains(&vertex)?)
+}
+
+/// Find the events which can be deleted from the event log.
 ///
-/// Frees any references to commits which are no longer visible in the smartlog.
-#[instrument]
-pub fn gc(effects: &Effects) -> eyre::Result<()> {
-    let repo = Repo::from_current_dir()?;
-    let conn = repo.get_db_conn()?;
-    let event_log_db = EventLogDb::new(&conn)?;
-    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+/// Only events from transactions which are older than `cutoff`, and which are
+/// not among the `num_keep_transactions` most recent transactions, are
+/// considered. Of those, the latest event for each commit whose history is
+/// still needed and the latest update for each reference are kept, so that
+/// the state of the repository after the pruned transactions is unchanged.
+///
+/// Args:
+/// * `anchored_commits`: The commits which will be anchored once garbage
+///   collection has finished.
+fn find_prunable_events(
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    anchored_commits: &HashSet<NonZeroOid>,
+    cutoff: SystemTime,
+    num_keep_transactions: usize,
+) -> eyre::Result<PrunableEvents> {
+    const NOTHING_TO_PRUNE: PrunableEvents = PrunableEvents {
+        rowids: Vec::new(),
+        num_transactions: 0,
+        pruned_before_event_tx_id: None,
+    };
+
+    let events = event_log_db.get_events_with_rowids()?;
+
+    let mut event_tx_ids = Vec::new();
+    let mut latest_tx_timestamps: HashMap<EventTransactionId, SystemTime> = HashMap::new();
+    for (_rowid, event) in events.iter() {
+        let event_tx_id = event.get_event_tx_id();
+        let timestamp = event.get_timestamp();
+        match latest_tx_timestamps.get_mut(&event_tx_id) {
+            Some(latest_timestamp) => {
+                if timestamp > *latest_timestamp {
+                    *latest_timestamp = timestamp;
+                }
+            }
+            None => {
+                event_tx_ids.push(event_tx_id);
+                latest_tx_timestamps.insert(event_tx_id, timestamp);
+            }
+        }
+    }
+    let kept_event_tx_ids: HashSet<EventTransactionId> = event_tx_ids
+        .iter()
+        .rev()
+        .take(num_keep_transactions.max(1))
+        .copied()
+        .collect();
+
+    let num_pruned_events = events
+        .iter()
+        .position(|(_rowid, event)| {
+            let event_tx_id = event.get_event_tx_id();
+            kept_event_tx_ids.contains(&event_tx_id) || latest_tx_timestamps[&event_tx_id] >= cutoff
+        })
+        .unwrap_or(events.len());
+    let (pruned_events, remaining_events) = events.split_at(num_pruned_events);
+    let pruned_before_event_tx_id = match remaining_events.first() {
+        Some((_rowid, event)) if !pruned_events.is_empty() => event.get_event_tx_id(),
+        Some(_) | None => return Ok(NOTHING_TO_PRUNE),
+    };
+
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    let dag = Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+    let visible_commits = dag.query_visible_commits()?;
+    let public_commits = dag.query_public_commits()?;
+
+    // Find the latest event in the pruned range for each commit, and for each
+    // reference.
+    let ref_ignore_patterns = get_eventlog_ref_ignore_patterns(repo)?;
+    let mut latest_commit_events: HashMap<NonZeroOid, usize> = HashMap::new();
+    let mut kept_events: HashSet<usize> = HashSet::new();
+    let mut seen_ref_names = HashSet::new();
+    for (i, (_rowid, event)) in pruned_events.iter().enumerate().rev() {
+        for commit_oid in get_event_commit_oids(event) {
+            latest_commit_events.entry(commit_oid).or_insert(i);
+        }
+        if let Event::RefUpdateEvent {
+            ref_name, new_oid, ..
+        } = event
+        {
+            if should_ignore_ref_updates(ref_name)
+                || matches_ref_ignore_patterns(ref_name, &ref_ignore_patterns)
+            {
+                continue;
+            }
+            if seen_ref_names.insert(ref_name) && *new_oid != MaybeZeroOid::Zero {
+                kept_events.insert(i);
+            }
+        }
+    }
+
+    // Keeping an event for one commit (such as a `RewriteEvent`) also affects
+    // the other commits it mentions, so their latest events must be kept too.
+    let mut needed_commits = HashSet::new();
+    for commit_oid in latest_commit_events.keys() {
+        if is_commit_history_needed(
+            &dag,
+            anchored_commits,
+            &visible_commits,
+            &public_commits,
+            *commit_oid,
+        )? {
+            needed_commits.insert(*commit_oid);
+        }
+    }
+    let mut unprocessed_commits: Vec<NonZeroOid> = needed_commits.iter().copied().collect();
+    while let Some(commit_oid) = unprocessed_commits.pop() {
+        let i = latest_commit_events[&commit_oid];
+        if kept_events.insert(i) {
+            let (_rowid, event) = &pruned_events[i];
+            for other_commit_oid in get_event_commit_oids(event) {
+                if needed_commits.insert(other_commit_oid) {
+                    unprocessed_commits.push(other_commit_oid);
+                }
+            }
+        }
+    }
+
+    let mut rowids = Vec::new();
+    let mut pruned_event_tx_ids = HashSet::new();
+    for (i, (rowid, event)) in pruned_events.iter().enumerate() {
+        if !kept_events.contains(&i) {
+            rowids.push(*rowid);
+            pruned_event_tx_ids.insert(event.get_event_tx_id());
+        }
+    }
+    if rowids.is_empty() {
+        return Ok(NOTHING_TO_PRUNE);
+    }
+    Ok(PrunableEvents {
+        rowids,
+        num_transactions: pruned_event_tx_ids.len(),
+        pruned_before_event_tx_id: Some(pruned_before_event_tx_id),
+    })
+}
+
+/// How long ago a released commit was hidden, used to summarize a `GcPlan`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum HiddenAge {
+    LessThanOneWeek,
+    OneToFourWeeks,
+    OneToThreeMonths,
+    MoreThanThreeMonths,
+    Unknown,
+}
+
+impl HiddenAge {
+    fn new(now: SystemTime, hidden_at: Option<SystemTime>) -> Self {
+        let hidden_at = match hidden_at {
+            Some(hidden_at) => hidden_at,
+            None => return HiddenAge::Unknown,
+        };
+        let days = now.duration_since(hidden_at).unwrap_or_default().as_secs() / (24 * 60 * 60);
+        if days < 7 {
+            HiddenAge::LessThanOneWeek
+        } else if days < 28 {
+            HiddenAge::OneToFourWeeks
+        } else if days < 90 {
+            HiddenAge::OneToThreeMonths
+        } else {
+            HiddenAge::MoreThanThreeMonths
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            HiddenAge::LessThanOneWeek => "less than 1 week ago",
+            HiddenAge::OneToFourWeeks => "1-4 weeks ago",
+            HiddenAge::OneToThreeMonths => "1-3 months ago",
+            HiddenAge::MoreThanThreeMonths => "more than 3 months ago",
+            HiddenAge::Unknown => "at an unknown time",
+        }
+    }
+}
+
+/// The events which can be deleted from the event log.
+#[derive(Debug)]
+struct PrunableEvents {
+    /// The row IDs of the events to delete.
+    rowids: Vec<i64>,
+
+    /// The number of transactions which have at least one event deleted.
+    num_transactions: usize,
+
+    /// The ID of the earliest transaction which is unaffected, or `None` if
+    /// there is nothing to delete.
+    pruned_before_event_tx_id: Option<EventTransactionId>,
+}
+
+/// The changes to make when collecting garbage. The plan is computed before
+/// anything is changed, so that `--dry-run` can report exactly what a real run
+/// would do.
+#[derive(Debug)]
+struct GcPlan<'repo> {
+    /// Commits which should be kept reachable, but which aren't anchored yet.
+    commits_to_anchor: Vec<NonZeroOid>,
+
+    /// The anchor references to delete. For references to hidden commits, also
+    /// records how long ago the commit was hidden; references to expired
+    /// working copy snapshots have `None` instead.
+    references_to_delete: Vec<(Reference<'repo>, Option<HiddenAge>)>,
+
+    /// The events to delete from the event log, if pruning was requested.
+    prunable_events: Option<PrunableEvents>,
+}
+
+/// The number of changes made by garbage collection, or which would be made
+/// for a dry run.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct GcSummary {
+    num_anchored_commits: usize,
+    released_commit_ages: BTreeMap<HiddenAge, usize>,
+    num_deleted_references: usize,
+    num_pruned_events: Option<(usize, usize)>,
+}
+
+impl GcSummary {
+    fn record_deleted_reference(&mut self, hidden_age: Option<HiddenAge>) {
+        self.num_deleted_references += 1;
+        if let Some(hidden_age) = hidden_age {
+            *self.released_commit_ages.entry(hidden_age).or_insert(0) += 1;
+        }
+    }
+}
+
+impl GcPlan<'_> {
+    fn get_summary(&self) -> GcSummary {
+        let mut summary = GcSummary {
+            num_anchored_commits: self.commits_to_anchor.len(),
+            num_pruned_events: self.prunable_events.as_ref().map(|prunable_events| {
+                (
+                    prunable_events.rowids.len(),
+                    prunable_events.num_transactions,
+                )
+            }),
+            ..Default::default()
+        };
+        for (_reference, hidden_age) in self.references_to_delete.iter() {
+            summary.record_deleted_reference(*hidden_age);
+        }
+        summary
+    }
+}
+
+/// Determine what garbage collection should do, without changing anything.
+///
+/// Commits which should be kept reachable but aren't yet anchored (such as
+/// those created by external tools) will be anchored. References to commits
+/// which have been hidden for longer than `branchless.gc.retentionDays`, as
+/// well as references to working copy snapshots which have expired, will be
+/// deleted. If `prune_cutoff` is provided, events from before that time will
+/// also be pruned from the event log (see `find_prunable_events`).
+fn make_gc_plan<'repo>(
+    effects: &Effects,
+    repo: &'repo Repo,
+    event_log_db: &EventLogDb,
+    now: SystemTime,
+    prune_cutoff: Option<SystemTime>,
+) -> eyre::Result<GcPlan<'repo>> {
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
     let event_cursor = event_replayer.make_default_cursor();
 
+    let retention_days: u64 = get_gc_retention_days(repo)?.max(0).try_into()?;
+    let cutoff = days_before(now, retention_days);
+    let mut anchored_commits = find_anchored_commits(&event_replayer, event_cursor, cutoff);
+    anchored_commits.extend(find_recent_stash_commits(
+        &event_log_db.get_events()?,
+        cutoff,
+    ));
+    {
+        let conn = repo.get_db_conn()?;
+        anchored_commits.extend(ArchiveDb::new(&conn)?.get_archived_commits()?);
+    }
+
+    let mut commits_to_anchor = Vec::new();
+    let mut existing_anchored_commits = HashSet::new();
+    for commit_oid in anchored_commits.iter().copied() {
+        if repo
+            .find_reference(OsStr::new(&get_gc_ref_name(commit_oid)))?
+            .is_some()
+        {
+            existing_anchored_commits.insert(commit_oid);
+            continue;
+        }
+        // The commit may have already been collected by Git before we had a
+        // chance to anchor it.
+        if repo.find_commit(commit_oid)?.is_some() {
+            existing_anchored_commits.insert(commit_oid);
+            commits_to_anchor.push(commit_oid);
+        }
+    }
+    commits_to_anchor.sort_unstable();
+
+    let mut references_to_delete = Vec::new();
+    for reference in find_dangling_references(repo, &anchored_commits)? {
+        let hidden_at = match reference.peel_to_commit()? {
+            Some(commit) => event_replayer
+                .get_cursor_commit_latest_event(event_cursor, commit.get_oid())
+                .map(Event::get_timestamp),
+            None => None,
+        };
+        references_to_delete.push((reference, Some(HiddenAge::new(now, hidden_at))));
+    }
+    for reference in find_expired_snapshot_references(repo, now, get_snapshot_max_age_days(repo)?)?
+    {
+        references_to_delete.push((reference, None));
+    }
+
+    let prunable_events = match prune_cutoff {
+        Some(prune_cutoff) => {
+            let num_keep_transactions: usize = get_eventlog_prune_keep_transactions(repo)?
+                .max(0)
+                .try_into()?;
+            Some(find_prunable_events(
+                effects,
+                repo,
+                event_log_db,
+                &existing_anchored_commits,
+                prune_cutoff,
+                num_keep_transactions,
+            )?)
+        }
+        None => None,
+    };
+
+    Ok(GcPlan {
+        commits_to_anchor,
+        references_to_delete,
+        prunable_events,
+    })
+}
+
+/// Carry out the given plan. Each step is attempted even if an earlier one
+/// failed, and failures are reported as they happen.
+///
+/// Returns: A summary of the changes which were actually made, and whether
+/// every step succeeded.
+fn execute_gc_plan(
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    plan: GcPlan,
+) -> eyre::Result<(GcSummary, bool)> {
+    let GcPlan {
+        commits_to_anchor,
+        references_to_delete,
+        prunable_events,
+    } = plan;
+    let mut summary = GcSummary::default();
+    let mut succeeded = true;
+
+    for commit_oid in commits_to_anchor {
+        match mark_commit_reachable(repo, commit_oid) {
+            Ok(()) => summary.num_anchored_commits += 1,
+            Err(err) => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "branchless: failed to anchor commit {}: {}",
+                    commit_oid,
+                    err
+                )?;
+                succeeded = false;
+            }
+        }
+    }
+
+    for (mut reference, hidden_age) in references_to_delete {
+        match reference.delete() {
+            Ok(()) => summary.record_deleted_reference(hidden_age),
+            Err(err) => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "branchless: failed to delete reference {}: {}",
+                    reference.get_name()?.to_string_lossy(),
+                    err
+                )?;
+                succeeded = false;
+            }
+        }
+    }
+
+    if let Some(prunable_events) = prunable_events {
+        let PrunableEvents {
+            rowids,
+            num_transactions,
+            pruned_before_event_tx_id,
+        } = prunable_events;
+        let result = match pruned_before_event_tx_id {
+            Some(pruned_before_event_tx_id) => event_log_db
+                .prune_events(SystemTime::now(), &rowids, pruned_before_event_tx_id)
+                .and_then(|()| event_log_db.vacuum()),
+            None => Ok(()),
+        };
+        match result {
+            Ok(()) => summary.num_pruned_events = Some((rowids.len(), num_transactions)),
+            Err(err) => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "branchless: failed to prune event log: {}",
+                    err
+                )?;
+                summary.num_pruned_events = Some((0, 0));
+                succeeded = false;
+            }
+        }
+    }
+
+    Ok((summary, succeeded))
+}
+
+/// Estimate how much space `git gc` could reclaim, in KiB. Only loose objects
+/// are counted, as reported by `git count-objects`, since those are what
+/// releasing recently-hidden commits usually frees up.
+fn estimate_reclaimable_kib(git_run_info: &GitRunInfo, repo: &Repo) -> eyre::Result<Option<u64>> {
+    let result = git_run_info.run_silent(
+        repo,
+        None,
+        &["count-objects", "-v"],
+        GitRunOpts {
+            treat_git_failure_as_error: false,
+        },
+    )?;
+    if result.exit_code != 0 {
+        return Ok(None);
+    }
+
+    let mut reclaimable_kib = 0;
+    for line in String::from_utf8_lossy(&result.stdout).lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            if key == "size" || key == "size-garbage" {
+                reclaimable_kib += value.trim().parse::<u64>()?;
+            }
+        }
+    }
+    Ok(Some(reclaimable_kib))
+}
+
+fn write_gc_summary(
+    effects: &Effects,
+    summary: &GcSummary,
+    reclaimable_kib: Option<u64>,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    let (anchor, release, delete, prune) = if dry_run {
+        (
+            "would anchor",
+            "would release",
+            "would delete",
+            "would prune",
+        )
+    } else {
+        ("anchored", "released", "deleted", "pruned")
+    };
+    let GcSummary {
+        num_anchored_commits,
+        released_commit_ages,
+        num_deleted_references,
+        num_pruned_events,
+    } = summary;
+
+    if *num_anchored_commits > 0 {
+        writeln!(
+            effects.get_output_stream(),
+            "branchless: {} {}",
+            anchor,
+            Pluralize {
+                amount: (*num_anchored_commits).try_into()?,
+                singular: "commit",
+                plural: "commits",
+            }
+            .to_string()
+        )?;
+    }
+
     writeln!(
         effects.get_output_stream(),
-        "branchless: collecting garbage"
+        "branchless: {} {}",
+        release,
+        Pluralize {
+            amount: released_commit_ages.values().sum::<usize>().try_into()?,
+            singular: "hidden commit",
+            plural: "hidden commits",
+        }
+        .to_string()
     )?;
-    let dangling_references = find_dangling_references(&repo, &event_replayer, event_cursor)?;
-    let num_dangling_references = Pluralize {
-        amount: dangling_references.len().try_into()?,
-        singular: "dangling reference",
-        plural: "dangling references",
-    }
-    .to_string();
-    for mut reference in dangling_references.into_iter() {
-        reference.delete()?;
+    for (hidden_age, num_commits) in released_commit_ages {
+        writeln!(
+            effects.get_output_stream(),
+            "branchless:   {} hidden {}",
+            num_commits,
+            hidden_age.describe()
+        )?;
     }
 
     writeln!(
         effects.get_output_stream(),
-        "branchless: {} deleted",
-        num_dangling_references,
+        "branchless: {} {}",
+        delete,
+        Pluralize {
+            amount: (*num_deleted_references).try_into()?,
+            singular: "anchor ref",
+            plural: "anchor refs",
+        }
+        .to_string()
     )?;
+
+    if let Some((num_events, num_transactions)) = num_pruned_events {
+        writeln!(
+            effects.get_output_stream(),
+            "branchless: {} {} from {}",
+            prune,
+            Pluralize {
+                amount: (*num_events).try_into()?,
+                singular: "event",
+                plural: "events",
+            }
+            .to_string(),
+            Pluralize {
+                amount: (*num_transactions).try_into()?,
+                singular: "transaction",
+                plural: "transactions",
+            }
+            .to_string()
+        )?;
+    }
+
+    if let Some(reclaimable_kib) = reclaimable_kib {
+        writeln!(
+            effects.get_output_stream(),
+            "branchless: up to {} KiB of loose objects can be reclaimed by `git gc`",
+            reclaimable_kib
+        )?;
+    }
     Ok(())
 }
+
+fn run_gc(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    prune_cutoff: Option<SystemTime>,
+    dry_run: bool,
+) -> eyre::Result<isize> {
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+
+    writeln!(
+        effects.get_output_stream(),
+        "branchless: collecting garbage"
+    )?;
+    let plan = make_gc_plan(
+        effects,
+        repo,
+        &event_log_db,
+        SystemTime::now(),
+        prune_cutoff,
+    )?;
+    let reclaimable_kib = estimate_reclaimable_kib(git_run_info, repo)?;
+
+    if dry_run {
+        write_gc_summary(effects, &plan.get_summary(), reclaimable_kib, true)?;
+        return Ok(0);
+    }
+
+    let (summary, succeeded) = execute_gc_plan(effects, repo, &event_log_db, plan)?;
+    write_gc_summary(effects, &summary, reclaimable_kib, false)?;
+    Ok(if succeeded { 0 } else { 1 })
+}
+
+/// Run branchless's garbage collection.
+///
+/// Adds references to any commits which should be kept reachable but aren't
+/// yet anchored, such as those created by external tools. Then frees any
+/// references to commits which have been hidden for longer than
+/// `branchless.gc.retentionDays`, as well as references to working copy
+/// snapshots which have expired. A summary of the changes is printed
+/// afterwards.
+///
+/// Args:
+/// * `dry_run`: If `true`, print the summary of what would change, but don't
+///   add or delete any references (or events).
+/// * `prune_events`: If `true`, also prune old events from the event log (see
+///   `find_prunable_events`).
+/// * `before`: The time before which to prune events, as a duration (such as
+///   `30d`) or a date (such as `2021-06-01`). Defaults to
+///   `branchless.eventlog.autoPruneDays` days ago.
+///
+/// Returns: A non-zero exit code if any step failed.
+#[instrument]
+pub fn gc(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    dry_run: bool,
+    prune_events: bool,
+    before: Option<&str>,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_env()?;
+    let prune_cutoff = if prune_events {
+        let now = SystemTime::now();
+        let cutoff = match before {
+            Some(before) => parse_prune_cutoff(now, before)?,
+            None => {
+                let days = match get_eventlog_auto_prune_days(&repo)? {
+                    Some(days) => days.max(0).try_into()?,
+                    None => DEFAULT_PRUNE_DAYS,
+                };
+                days_before(now, days)
+            }
+        };
+        Some(cutoff)
+    } else {
+        None
+    };
+    run_gc(effects, git_run_info, &repo, prune_cutoff, dry_run)
+}
+
+/// Run branchless's garbage collection as part of Git's automatic garbage
+/// collection. Old events are also pruned from the event log if
+/// `branchless.eventlog.autoPruneDays` is set.
+#[instrument]
+pub fn auto_gc(effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<isize> {
+    let repo = Repo::from_env()?;
+    let prune_cutoff = match get_eventlog_auto_prune_days(&repo)? {
+        Some(days) => Some(days_before(SystemTime::now(), days.max(0).try_into()?)),
+        None => None,
+    };
+    run_gc(effects, git_run_info, &repo, prune_cutoff, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prune_cutoff() -> eyre::Result<()> {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100 * 24 * 60 * 60);
+        assert_eq!(
+            parse_prune_cutoff(now, "30d")?,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(70 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            parse_prune_cutoff(now, "2w")?,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(86 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            parse_prune_cutoff(now, "12h")?,
+            now - Duration::from_secs(12 * 60 * 60)
+        );
+        assert_eq!(
+            parse_prune_cutoff(now, "1970-01-02T00:00:00Z")?,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(24 * 60 * 60)
+        );
+        assert!(parse_prune_cutoff(now, "2021-06-01").is_ok());
+        assert!(parse_prune_cutoff(now, "30").is_err());
+        assert!(parse_prune_cutoff(now, "yesterday").is_err());
+        Ok(())
+    }
+}
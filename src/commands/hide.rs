@@ -1,23 +1,74 @@
 //! Handle obsoleting commits when explicitly requested by the user (as opposed to
 //! automatically as the result of a rewrite operation).
 
+use std::ffi::{OsStr, OsString};
 use std::fmt::Write;
+use std::io::{stdin, IsTerminal};
 use std::time::SystemTime;
 
 use eden_dag::DagAlgorithm;
+use itertools::Itertools;
 use tracing::instrument;
 
-use crate::core::dag::{resolve_commits, sort_commit_set, CommitSet, Dag, ResolveCommitsResult};
+use crate::core::archive::ArchiveDb;
+use crate::core::dag::{commit_set_to_vec, sort_commit_set, CommitSet, Dag};
 use crate::core::effects::Effects;
-use crate::core::eventlog::{CommitActivityStatus, Event};
+use crate::core::eventlog::{CommitActivityStatus, Event, EventTransactionId};
 use crate::core::eventlog::{EventLogDb, EventReplayer};
 use crate::core::formatting::{printable_styled_string, Glyphs};
 use crate::core::node_descriptors::{render_node_descriptors, CommitOidDescriptor, NodeObject};
-use crate::git::Repo;
+use crate::core::revset::{self, resolve_revsets};
+use crate::core::rewrite::check_rewritten_commits_are_draft;
+use crate::git::{CategorizedReferenceName, Commit, MaybeZeroOid, Repo};
+
+/// Resolve the commit arguments provided on the command-line, which may be
+/// ranges or other revset expressions. If any of them can't be resolved, or
+/// don't match any commits, describes the problem and returns `None`.
+///
+/// Returns: All of the resolved commits, and the subset of them which were
+/// resolved from expressions containing ranges.
+fn resolve_commits(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &mut Dag,
+    hashes: &[String],
+) -> eyre::Result<Option<(CommitSet, CommitSet)>> {
+    let commit_sets = match resolve_revsets(effects, repo, dag, hashes)? {
+        Ok(commit_sets) => commit_sets,
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(None);
+        }
+    };
+
+    let mut all_commits = CommitSet::empty();
+    let mut range_commits = CommitSet::empty();
+    for (hash, commits) in hashes.iter().zip(commit_sets) {
+        if commits.is_empty()? {
+            writeln!(
+                effects.get_output_stream(),
+                "Expression '{}' did not match any commits.",
+                hash
+            )?;
+            return Ok(None);
+        }
+        if matches!(revset::parse(hash), Ok(expr) if expr.contains_range()) {
+            range_commits = range_commits.union(&commits);
+        }
+        all_commits = all_commits.union(&commits);
+    }
+    Ok(Some((all_commits, range_commits)))
+}
 
 /// Hide the hashes provided on the command-line.
 #[instrument]
-pub fn hide(effects: &Effects, hashes: Vec<String>, recursive: bool) -> eyre::Result<isize> {
+pub fn hide(
+    effects: &Effects,
+    hashes: Vec<String>,
+    recursive: bool,
+    force_rewrite_public: bool,
+    archive: Option<String>,
+) -> eyre::Result<isize> {
     let now = SystemTime::now();
     let glyphs = Glyphs::detect();
     let repo = Repo::from_current_dir()?;
@@ -34,20 +85,11 @@ pub fn hide(effects: &Effects, hashes: Vec<String>, recursive: bool) -> eyre::Re
         &references_snapshot,
     )?;
 
-    let commits = resolve_commits(effects, &repo, &mut dag, hashes)?;
-    let commits = match commits {
-        ResolveCommitsResult::Ok { commits } => commits,
-        ResolveCommitsResult::CommitNotFound { commit: hash } => {
-            writeln!(effects.get_output_stream(), "Commit not found: {}", hash)?;
-            return Ok(1);
-        }
+    let (commits, range_commits) = match resolve_commits(effects, &repo, &mut dag, &hashes)? {
+        Some(commits) => commits,
+        None => return Ok(1),
     };
 
-    let commits: CommitSet = commits
-        .into_iter()
-        .map(|commit| commit.get_oid())
-        .rev()
-        .collect();
     let commits = if recursive {
         dag.query()
             .descendants(commits)?
@@ -58,6 +100,18 @@ pub fn hide(effects: &Effects, hashes: Vec<String>, recursive: bool) -> eyre::Re
     let commits = dag.query().sort(&commits)?;
     let commits = sort_commit_set(&repo, &dag, &commits)?;
 
+    // A range can easily reach further back than intended, such as into the
+    // main branch, so guard against hiding public commits that way.
+    if !check_rewritten_commits_are_draft(
+        effects,
+        &repo,
+        &dag,
+        commit_set_to_vec(&range_commits)?,
+        force_rewrite_public,
+    )? {
+        return Ok(1);
+    }
+
     let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
     let event_tx_id = event_log_db.make_transaction_id(now, "hide")?;
     let events = commits
@@ -69,6 +123,10 @@ pub fn hide(effects: &Effects, hashes: Vec<String>, recursive: bool) -> eyre::Re
         })
         .collect();
     event_log_db.add_events(events)?;
+    if let Some(archive) = &archive {
+        let commit_oids = commits.iter().map(|commit| commit.get_oid()).collect_vec();
+        ArchiveDb::new(&conn)?.add_commits(archive, &commit_oids, now)?;
+    }
 
     let cursor = event_replayer.make_default_cursor();
     for commit in commits {
@@ -96,13 +154,30 @@ pub fn hide(effects: &Effects, hashes: Vec<String>, recursive: bool) -> eyre::Re
             printable_styled_string(&glyphs, commit_target_oid)?
         )?;
     }
+    if let Some(archive) = &archive {
+        writeln!(effects.get_output_stream(), "Archived as: {}", archive)?;
+        writeln!(
+            effects.get_output_stream(),
+            "To restore these commits, run: git branchless archive restore {}",
+            archive
+        )?;
+    }
 
     Ok(0)
 }
 
 /// Unhide the hashes provided on the command-line.
+///
+/// Branches which pointed to an unhidden commit when it was hidden, but which
+/// no longer exist, are recreated if `restore_branches` is set, or if the user
+/// confirms it at a prompt.
 #[instrument]
-pub fn unhide(effects: &Effects, hashes: Vec<String>, recursive: bool) -> eyre::Result<isize> {
+pub fn unhide(
+    effects: &Effects,
+    hashes: Vec<String>,
+    recursive: bool,
+    restore_branches: bool,
+) -> eyre::Result<isize> {
     let now = SystemTime::now();
     let glyphs = Glyphs::detect();
     let repo = Repo::from_current_dir()?;
@@ -119,16 +194,11 @@ pub fn unhide(effects: &Effects, hashes: Vec<String>, recursive: bool) -> eyre::
         &references_snapshot,
     )?;
 
-    let commits = resolve_commits(effects, &repo, &mut dag, hashes)?;
-    let commits = match commits {
-        ResolveCommitsResult::Ok { commits } => commits,
-        ResolveCommitsResult::CommitNotFound { commit: hash } => {
-            writeln!(effects.get_output_stream(), "Commit not found: {}", hash)?;
-            return Ok(1);
-        }
+    let (commits, _range_commits) = match resolve_commits(effects, &repo, &mut dag, &hashes)? {
+        Some(commits) => commits,
+        None => return Ok(1),
     };
 
-    let commits: CommitSet = commits.into_iter().map(|commit| commit.get_oid()).collect();
     let commits = if recursive {
         dag.query()
             .descendants(commits)?
@@ -167,16 +237,117 @@ pub fn unhide(effects: &Effects, hashes: Vec<String>, recursive: bool) -> eyre::
             )?;
         }
 
+        let mut branch_names: Vec<OsString> = event_replayer
+            .get_cursor_commit_hidden_branch_names(cursor, commit.get_oid())
+            .into_iter()
+            .collect();
+        branch_names.sort_unstable();
+        let mut unrestored_branch_names = Vec::new();
+        for branch_name in branch_names {
+            if !restore_branch(
+                effects,
+                &glyphs,
+                &repo,
+                &mut event_log_db,
+                timestamp,
+                event_tx_id,
+                &commit,
+                &branch_name,
+                restore_branches,
+            )? {
+                unrestored_branch_names.push(branch_name);
+            }
+        }
+
         let commit_target_oid = render_node_descriptors(
             &NodeObject::Commit { commit },
             &mut [&mut CommitOidDescriptor::new(false)?],
         )?;
+        let commit_target_oid = printable_styled_string(&glyphs, commit_target_oid)?;
         writeln!(
             effects.get_output_stream(),
             "To hide this commit, run: git hide {}",
-            printable_styled_string(&glyphs, commit_target_oid)?
+            commit_target_oid
         )?;
+        for branch_name in unrestored_branch_names {
+            writeln!(
+                effects.get_output_stream(),
+                "To restore {}, run: git unhide --restore-branches {}",
+                CategorizedReferenceName::new(&branch_name).friendly_describe(),
+                commit_target_oid
+            )?;
+        }
     }
 
     Ok(0)
 }
+
+/// Recreate a branch which pointed to `commit` when it was hidden, if it no
+/// longer exists. The reference is updated without going through Git, so the
+/// update is recorded as part of the unhide transaction directly; this way,
+/// undoing the unhide also deletes the branch again.
+///
+/// Returns: `false` if the branch could have been restored, but the user
+/// declined to do so, and `true` otherwise.
+fn restore_branch(
+    effects: &Effects,
+    glyphs: &Glyphs,
+    repo: &Repo,
+    event_log_db: &mut EventLogDb,
+    timestamp: f64,
+    event_tx_id: EventTransactionId,
+    commit: &Commit,
+    branch_name: &OsStr,
+    restore_branches: bool,
+) -> eyre::Result<bool> {
+    let friendly_name = CategorizedReferenceName::new(branch_name).friendly_describe();
+    if let Some(reference) = repo.find_reference(branch_name)? {
+        match reference.peel_to_commit()? {
+            Some(target) if target.get_oid() == commit.get_oid() => {}
+            Some(target) => writeln!(
+                effects.get_output_stream(),
+                "Not restoring {}, since it now points to: {}",
+                friendly_name,
+                printable_styled_string(glyphs, target.friendly_describe()?)?
+            )?,
+            None => writeln!(
+                effects.get_output_stream(),
+                "Not restoring {}, since it now points to a non-commit object.",
+                friendly_name,
+            )?,
+        }
+        return Ok(true);
+    }
+
+    let confirmed = restore_branches
+        || (stdin().is_terminal() && {
+            write!(
+                effects.get_output_stream(),
+                "Restore {}, which pointed to this commit when it was hidden? [yN] ",
+                friendly_name
+            )?;
+            let mut user_input = String::new();
+            match stdin().read_line(&mut user_input) {
+                Ok(_size) => {
+                    let user_input = user_input.trim();
+                    user_input == "y" || user_input == "Y"
+                }
+                Err(_) => false,
+            }
+        });
+    if !confirmed {
+        return Ok(false);
+    }
+
+    repo.create_reference(branch_name, commit.get_oid(), false, "branchless: unhide")?;
+    event_log_db.add_events(vec![Event::RefUpdateEvent {
+        timestamp,
+        event_tx_id,
+        ref_name: branch_name.to_owned(),
+        old_oid: MaybeZeroOid::Zero,
+        new_oid: commit.get_oid().into(),
+        message: None,
+    }])?;
+    writeln!(effects.get_output_stream(), "Restored {}.", friendly_name)?;
+    Ok(true)
+}
@@ -8,9 +8,9 @@
 //! contains the implementations for the hooks.
 
 use std::convert::TryInto;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Write;
-use std::io::{stdin, BufRead};
+use std::io::{stdin, Read};
 use std::time::SystemTime;
 
 use eyre::Context;
@@ -18,16 +18,41 @@ use itertools::Itertools;
 use tracing::{error, instrument, warn};
 
 use crate::commands::gc::mark_commit_reachable;
-use crate::core::eventlog::{should_ignore_ref_updates, Event, EventLogDb};
+use crate::core::config::{get_eventlog_ref_ignore_patterns, get_hook_enabled};
+use crate::core::eventlog::{
+    matches_ref_ignore_patterns, should_ignore_ref_updates, Event, EventLogDb, ResetInfo,
+};
 use crate::core::formatting::{printable_styled_string, Glyphs, Pluralize};
-use crate::git::{CategorizedReferenceName, MaybeZeroOid, Repo};
+use crate::core::stash::{is_stash_ref, normalize_stash_update, sync_stash_events};
+use crate::git::{CategorizedReferenceName, GitRunInfo, MaybeZeroOid, Repo};
 
 use crate::core::effects::Effects;
 pub use crate::core::rewrite::rewrite_hooks::{
-    hook_drop_commit_if_empty, hook_post_rewrite, hook_register_extra_post_rewrite_hook,
-    hook_skip_upstream_applied_commit,
+    hook_drop_commit_if_empty, hook_drop_merge_commit, hook_post_rewrite,
+    hook_register_extra_post_rewrite_hook, hook_skip_upstream_applied_commit,
 };
 
+/// Print a note if the `reference-transaction` hook has been disabled, since
+/// branch updates aren't recorded in the event log in that case, so they
+/// don't appear in the smartlog history and can't be undone.
+#[instrument]
+pub fn warn_if_reference_transaction_hook_disabled(
+    effects: &Effects,
+    repo: &Repo,
+) -> eyre::Result<()> {
+    if !get_hook_enabled(repo, "reference-transaction")? {
+        writeln!(
+            effects.get_error_stream(),
+            "branchless: the reference-transaction hook is disabled (see branchless.hooks.reference-transaction.enabled)"
+        )?;
+        writeln!(
+            effects.get_error_stream(),
+            "branchless: branch updates since it was disabled aren't recorded and can't be undone"
+        )?;
+    }
+    Ok(())
+}
+
 /// Handle Git's `post-checkout` hook.
 ///
 /// See the man-page for `githooks(5)`.
@@ -39,6 +64,17 @@ pub fn hook_post_checkout(
     is_branch_checkout: isize,
 ) -> eyre::Result<()> {
     if is_branch_checkout == 0 {
+        // A file checkout, such as `git checkout -- <path>`, which doesn't move
+        // `HEAD`, so there's nothing to record.
+        return Ok(());
+    }
+
+    let old_oid: MaybeZeroOid = previous_head_oid.parse()?;
+    let new_oid: MaybeZeroOid = current_head_oid.parse()?;
+    if old_oid == new_oid {
+        // Switching branches without moving `HEAD`, such as with `git checkout
+        // -b`, or while `HEAD` is unborn in a freshly-initialized repository.
+        // `HEAD` didn't move, so there's nothing to record.
         return Ok(());
     }
 
@@ -49,20 +85,22 @@ pub fn hook_post_checkout(
         "branchless: processing checkout"
     )?;
 
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_env()?;
+    let ref_ignore_patterns = get_eventlog_ref_ignore_patterns(&repo)?;
+    if matches_ref_ignore_patterns(OsStr::new("HEAD"), &ref_ignore_patterns) {
+        return Ok(());
+    }
+
     let conn = repo.get_db_conn()?;
     let mut event_log_db = EventLogDb::new(&conn)?;
-    let event_tx_id = event_log_db.make_transaction_id(now, "hook-post-checkout")?;
+    let event_tx_id = event_log_db.make_hook_transaction_id(&repo, now, "hook-post-checkout")?;
     event_log_db.add_events(vec![Event::RefUpdateEvent {
         timestamp: timestamp.as_secs_f64(),
         event_tx_id,
-        old_oid: previous_head_oid.parse()?,
-        new_oid: {
-            let oid: MaybeZeroOid = current_head_oid.parse()?;
-            oid
-        },
+        old_oid,
+        new_oid,
         ref_name: OsString::from("HEAD"),
-        message: None,
+        message: repo.get_worktree_name().map(OsStr::to_owned),
     }])?;
     Ok(())
 }
@@ -70,7 +108,7 @@ pub fn hook_post_checkout(
 fn hook_post_commit_common(effects: &Effects, hook_name: &str) -> eyre::Result<()> {
     let now = SystemTime::now();
     let glyphs = Glyphs::detect();
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_env()?;
     let conn = repo.get_db_conn()?;
     let mut event_log_db = EventLogDb::new(&conn)?;
 
@@ -93,7 +131,7 @@ fn hook_post_commit_common(effects: &Effects, hook_name: &str) -> eyre::Result<(
         .wrap_err("Marking commit as reachable for GC purposes")?;
 
     let timestamp = commit.get_time().seconds() as f64;
-    let event_tx_id = event_log_db.make_transaction_id(now, hook_name)?;
+    let event_tx_id = event_log_db.make_hook_transaction_id(&repo, now, hook_name)?;
     event_log_db.add_events(vec![Event::CommitEvent {
         timestamp,
         event_tx_id,
@@ -126,6 +164,17 @@ pub fn hook_post_merge(effects: &Effects, _is_squash_merge: isize) -> eyre::Resu
     hook_post_commit_common(effects, "post-merge")
 }
 
+/// Handle Git's `post-applypatch` hook. Git doesn't invoke the `post-commit`
+/// hook for the commits made by `git am`, so we need to handle this case
+/// explicitly with another hook. The commits share the transaction of the `git
+/// am` operation, so that the whole patch series can be undone in one step.
+///
+/// See the man-page for `githooks(5)`.
+#[instrument]
+pub fn hook_post_applypatch(effects: &Effects) -> eyre::Result<()> {
+    hook_post_commit_common(effects, "post-applypatch")
+}
+
 mod reference_transaction {
     use std::collections::HashMap;
     use std::convert::TryInto;
@@ -382,11 +431,60 @@ mod reference_transaction {
     }
 }
 
+/// Determine whether the reference transaction reset the branch checked out in
+/// the current worktree to one of its ancestors, along with the working copy,
+/// such as with `git reset --hard HEAD~2`. Returns the branch and its tip
+/// before the reset if so.
+///
+/// The working copy has already been updated by the time that the transaction
+/// is committed, so it was reset if it has no changes relative to the new tip.
+/// Otherwise, such as for `git reset --soft`, the commits are still
+/// effectively present in the working copy, so it's not treated as a reset.
+#[instrument]
+fn detect_reset(
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    parsed_lines: &[reference_transaction::ParsedReferenceTransactionLine],
+) -> eyre::Result<Option<ResetInfo>> {
+    if repo.get_working_copy_path().is_none() {
+        return Ok(None);
+    }
+    let branch_name = match repo.get_head_info()?.reference_name {
+        Some(branch_name) => branch_name.into_owned(),
+        None => return Ok(None),
+    };
+    let (old_oid, new_oid) = match parsed_lines
+        .iter()
+        .find(|parsed_line| parsed_line.ref_name == branch_name)
+    {
+        Some(reference_transaction::ParsedReferenceTransactionLine {
+            ref_name: _,
+            old_oid: MaybeZeroOid::NonZero(old_oid),
+            new_oid: MaybeZeroOid::NonZero(new_oid),
+        }) if old_oid != new_oid => (*old_oid, *new_oid),
+        _ => return Ok(None),
+    };
+    if repo.find_merge_base(old_oid, new_oid)? != Some(new_oid) {
+        return Ok(None);
+    }
+    if !repo.get_status(git_run_info, None)?.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ResetInfo {
+        ref_name: branch_name,
+        old_oid,
+    }))
+}
+
 /// Handle Git's `reference-transaction` hook.
 ///
 /// See the man-page for `githooks(5)`.
 #[instrument]
-pub fn hook_reference_transaction(effects: &Effects, transaction_state: &str) -> eyre::Result<()> {
+pub fn hook_reference_transaction(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    transaction_state: &str,
+) -> eyre::Result<()> {
     use reference_transaction::{
         fix_packed_reference_oid, parse_reference_transaction_line, read_packed_refs_file,
         ParsedReferenceTransactionLine,
@@ -397,27 +495,21 @@ pub fn hook_reference_transaction(effects: &Effects, transaction_state: &str) ->
     }
     let now = SystemTime::now();
 
-    let repo = Repo::from_current_dir()?;
-    let conn = repo.get_db_conn()?;
-    let mut event_log_db = EventLogDb::new(&conn)?;
-    let event_tx_id = event_log_db.make_transaction_id(now, "reference-transaction")?;
-
-    let packed_references = read_packed_refs_file(&repo)?;
-
-    let parsed_lines: Vec<ParsedReferenceTransactionLine> = stdin()
-        .lock()
-        .split(b'\n')
-        .filter_map(|line| {
-            let line = match line {
-                Ok(line) => line,
-                Err(_) => return None,
-            };
-            match parse_reference_transaction_line(line.as_slice()) {
-                Ok(line) => Some(line),
-                Err(err) => {
-                    error!(?err, "Could not parse reference-transaction-line");
-                    None
-                }
+    // Read the whole payload before doing any other work, so that Git isn't
+    // blocked on writing to our stdin while we open the database.
+    let mut input = Vec::new();
+    stdin().lock().read_to_end(&mut input)?;
+
+    let repo = Repo::from_env()?;
+    let ref_ignore_patterns = get_eventlog_ref_ignore_patterns(&repo)?;
+    let parsed_lines: Vec<ParsedReferenceTransactionLine> = input
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| match parse_reference_transaction_line(line) {
+            Ok(line) => Some(line),
+            Err(err) => {
+                error!(?err, "Could not parse reference-transaction-line");
+                None
             }
         })
         .filter(
@@ -425,14 +517,36 @@ pub fn hook_reference_transaction(effects: &Effects, transaction_state: &str) ->
                  ref_name,
                  old_oid: _,
                  new_oid: _,
-             }| !should_ignore_ref_updates(ref_name),
+             }| {
+                !should_ignore_ref_updates(ref_name)
+                    && !matches_ref_ignore_patterns(ref_name, &ref_ignore_patterns)
+            },
         )
-        .map(|parsed_line| fix_packed_reference_oid(&repo, &packed_references, parsed_line))
         .collect();
+    // Most reference transactions, such as those from fetching, only touch
+    // references which we ignore, so avoid opening the database for them.
     if parsed_lines.is_empty() {
         return Ok(());
     }
 
+    let packed_references = read_packed_refs_file(&repo)?;
+    let parsed_lines: Vec<ParsedReferenceTransactionLine> = parsed_lines
+        .into_iter()
+        .map(|parsed_line| fix_packed_reference_oid(&repo, &packed_references, parsed_line))
+        .collect();
+
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = match detect_reset(git_run_info, &repo, &parsed_lines)? {
+        Some(reset_info) => event_log_db.make_reset_transaction_id(
+            &repo,
+            now,
+            "reference-transaction",
+            &reset_info,
+        )?,
+        None => event_log_db.make_hook_transaction_id(&repo, now, "reference-transaction")?,
+    };
+
     let num_reference_updates = Pluralize {
         amount: parsed_lines.len().try_into()?,
         singular: "update",
@@ -461,6 +575,16 @@ pub fn hook_reference_transaction(effects: &Effects, transaction_state: &str) ->
         .duration_since(SystemTime::UNIX_EPOCH)
         .wrap_err("Calculating timestamp")?
         .as_secs_f64();
+    // Record any stash entries which were dropped since the stash was last
+    // updated first, so that the event log tracks the stash in order.
+    if parsed_lines
+        .iter()
+        .any(|parsed_line| is_stash_ref(&parsed_line.ref_name))
+    {
+        sync_stash_events(&repo, &mut event_log_db, now)?;
+    }
+
+    let worktree_name = repo.get_worktree_name();
     let events = parsed_lines
         .into_iter()
         .map(
@@ -469,13 +593,25 @@ pub fn hook_reference_transaction(effects: &Effects, transaction_state: &str) ->
                  old_oid,
                  new_oid,
              }| {
+                // `HEAD` is specific to each worktree, so note which one it
+                // belongs to.
+                let message = if ref_name == "HEAD" {
+                    worktree_name.map(OsStr::to_owned)
+                } else {
+                    None
+                };
+                let (old_oid, new_oid) = if is_stash_ref(&ref_name) {
+                    normalize_stash_update(old_oid, new_oid)
+                } else {
+                    (old_oid, new_oid)
+                };
                 Event::RefUpdateEvent {
                     timestamp,
                     event_tx_id,
                     ref_name,
                     old_oid,
                     new_oid,
-                    message: None,
+                    message,
                 }
             },
         )
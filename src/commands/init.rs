@@ -1,20 +1,45 @@
 //! Install any hooks, aliases, etc. to set up `git-branchless` in this repo.
 
+use std::convert::TryInto;
+use std::ffi::OsString;
 use std::fmt::Write;
-use std::io::{stdin, stdout, BufRead, BufReader, Write as WriteIo};
+use std::io::{stdin, BufRead, BufReader, IsTerminal};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
+use clap::{ErrorKind, IntoApp};
+use color_eyre::Help;
 use console::style;
+use eden_dag::DagAlgorithm;
 use eyre::Context;
+use itertools::Itertools;
 use path_slash::PathExt;
+use regex::Regex;
 use tracing::{instrument, warn};
 
-use crate::core::config::{get_core_hooks_path, get_default_branch_name};
-use crate::core::effects::Effects;
-use crate::git::{Config, ConfigRead, ConfigWrite, GitRunInfo, GitVersion, Repo};
-use crate::opts::write_man_pages;
+use crate::core::config::{
+    get_core_hooks_path, get_default_branch_name, get_hook_enabled, get_main_branch_name,
+};
+use crate::core::dag::{commit_set_to_vec, Dag};
+use crate::core::effects::{Effects, OperationType};
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer, EventTransactionId};
+use crate::core::formatting::Pluralize;
+use crate::git::{
+    get_global_config_path, Config, ConfigRead, ConfigWrite, GitRunInfo, GitVersion, MaybeZeroOid,
+    NonZeroOid, ReflogEntry, Repo, ResolvedBranchName,
+};
+use crate::opts::{
+    write_man_pages, HookPosition, HooksDirSetting, Opts, MAN_PAGES_VERSION_STAMP_FILE_NAME,
+};
+use crate::tui::prompt_choose_or_enter;
 
 const ALL_HOOKS: &[(&str, &str)] = &[
+    (
+        "post-applypatch",
+        r#"
+git branchless hook-post-applypatch "$@"
+"#,
+    ),
     (
         "post-commit",
         r#"
@@ -51,9 +76,11 @@ git branchless hook-pre-auto-gc "$@"
 # Avoid canceling the reference transaction in the case that `branchless` fails
 # for whatever reason.
 git branchless hook-reference-transaction "$@" || (
+if [ "$BRANCHLESS_QUIET" != 1 ] && [ "$(git config --type bool branchless.hooks.quiet)" != true ]; then
 echo 'branchless: Failed to process reference transaction!'
 echo 'branchless: Some events (e.g. branch updates) may have been lost.'
 echo 'branchless: This is a bug. Please report it.'
+fi
 )
 "#,
     ),
@@ -66,24 +93,39 @@ const ALL_ALIASES: &[(&str, &str)] = &[
     ("move", "move"),
     ("next", "next"),
     ("prev", "prev"),
+    ("record", "record"),
+    ("redo", "redo"),
     ("restack", "restack"),
+    ("reword", "reword"),
     ("sl", "smartlog"),
     ("smartlog", "smartlog"),
+    ("split", "split"),
     ("undo", "undo"),
     ("unhide", "unhide"),
 ];
 
 #[derive(Debug)]
 enum Hook {
-    /// Regular Git hook.
-    RegularHook { path: PathBuf },
+    /// Regular Git hook. If `is_shared` is set, then the hook is in a hooks
+    /// directory outside of the repository, which may be used by other
+    /// repositories as well.
+    RegularHook { path: PathBuf, is_shared: bool },
 
     /// For Twitter multihooks.
     MultiHook { path: PathBuf },
 }
 
+/// Determine whether `hooks_dir` is outside of the repository's `.git`
+/// directory, in which case it may be shared with other repositories (such as
+/// when `core.hooksPath` is set globally).
+fn is_hooks_dir_shared(repo: &Repo, hooks_dir: &Path) -> bool {
+    let canonicalize =
+        |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    !canonicalize(hooks_dir).starts_with(canonicalize(&repo.get_common_path()))
+}
+
 #[instrument]
-fn determine_hook_path(repo: &Repo, hook_type: &str) -> eyre::Result<Hook> {
+fn determine_hook_path(repo: &Repo, hooks_dir: &Path, hook_type: &str) -> eyre::Result<Hook> {
     let multi_hooks_path = repo.get_path().join("hooks_multi");
     let hook = if multi_hooks_path.exists() {
         let path = multi_hooks_path
@@ -91,17 +133,93 @@ fn determine_hook_path(repo: &Repo, hook_type: &str) -> eyre::Result<Hook> {
             .join("00_local_branchless");
         Hook::MultiHook { path }
     } else {
-        let hooks_dir = get_core_hooks_path(repo)?;
         let path = hooks_dir.join(hook_type);
-        Hook::RegularHook { path }
+        let is_shared = is_hooks_dir_shared(repo, hooks_dir);
+        Hook::RegularHook { path, is_shared }
     };
     Ok(hook)
 }
 
+/// The number of reflog entries to import when initializing, matching the
+/// default of `git branchless init --seed-depth`.
+pub const DEFAULT_SEED_DEPTH: usize = 1000;
+
 const SHEBANG: &str = "#!/bin/sh";
 const UPDATE_MARKER_START: &str = "## START BRANCHLESS CONFIG";
 const UPDATE_MARKER_END: &str = "## END BRANCHLESS CONFIG";
 
+/// The state of one of the hooks installed by `git-branchless`, as found on
+/// disk.
+#[derive(Debug)]
+pub struct InstalledHook {
+    /// The type of the hook, such as `post-commit`.
+    pub hook_type: &'static str,
+
+    /// The path where the hook is expected to be installed.
+    pub path: PathBuf,
+
+    /// The contents of the hook file, or `None` if it doesn't exist.
+    pub contents: Option<String>,
+
+    /// Whether the `git-branchless` section of the hook is delimited by intact
+    /// start and end markers. This is `None` if the hook doesn't exist or if
+    /// it's a multihook, which doesn't use markers.
+    pub markers_intact: Option<bool>,
+}
+
+fn are_markers_intact(lines: &str) -> bool {
+    let mut num_sections = 0;
+    let mut is_in_section = false;
+    for line in lines.lines() {
+        if line == UPDATE_MARKER_START {
+            if is_in_section {
+                return false;
+            }
+            is_in_section = true;
+        } else if line == UPDATE_MARKER_END {
+            if !is_in_section {
+                return false;
+            }
+            is_in_section = false;
+            num_sections += 1;
+        }
+    }
+    !is_in_section && num_sections == 1
+}
+
+/// Read the hooks which `git-branchless` installs from the repository,
+/// without modifying them.
+#[instrument]
+pub fn read_installed_hooks(repo: &Repo) -> eyre::Result<Vec<InstalledHook>> {
+    let hooks_dir = get_core_hooks_path(repo)?;
+    let mut result = Vec::new();
+    for (hook_type, _hook_script) in ALL_HOOKS {
+        let hook = determine_hook_path(repo, &hooks_dir, hook_type)?;
+        let (path, is_multi_hook) = match hook {
+            Hook::RegularHook { path, .. } => (path, false),
+            Hook::MultiHook { path } => (path, true),
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => Some(contents),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(eyre::eyre!(err)).wrap_err_with(|| format!("Reading hook {:?}", path))
+            }
+        };
+        let markers_intact = match &contents {
+            Some(contents) if !is_multi_hook => Some(are_markers_intact(contents)),
+            Some(_) | None => None,
+        };
+        result.push(InstalledHook {
+            hook_type,
+            path,
+            contents,
+            markers_intact,
+        });
+    }
+    Ok(result)
+}
+
 fn append_hook(new_lines: &mut String, hook_contents: &str) {
     new_lines.push_str(UPDATE_MARKER_START);
     new_lines.push('\n');
@@ -110,15 +228,28 @@ fn append_hook(new_lines: &mut String, hook_contents: &str) {
     new_lines.push('\n');
 }
 
-fn update_between_lines(lines: &str, updated_lines: &str) -> String {
+fn update_between_lines(lines: &str, updated_lines: &str, hook_position: HookPosition) -> String {
     let mut new_lines = String::new();
+    let mut lines = lines.lines().peekable();
     let mut found_marker = false;
+    if hook_position == HookPosition::Prepend {
+        // Keep the shebang line first, since it determines the interpreter.
+        if let Some(shebang) = lines.next_if(|line| line.starts_with("#!")) {
+            new_lines.push_str(shebang);
+            new_lines.push('\n');
+        }
+        found_marker = true;
+        append_hook(&mut new_lines, updated_lines);
+    }
+
     let mut is_ignoring_lines = false;
-    for line in lines.lines() {
+    for line in lines {
         if line == UPDATE_MARKER_START {
-            found_marker = true;
             is_ignoring_lines = true;
-            append_hook(&mut new_lines, updated_lines);
+            if !found_marker {
+                found_marker = true;
+                append_hook(&mut new_lines, updated_lines);
+            }
         } else if line == UPDATE_MARKER_END {
             is_ignoring_lines = false;
         } else if !is_ignoring_lines {
@@ -161,11 +292,15 @@ fn write_script(path: &Path, contents: &str) -> eyre::Result<()> {
 }
 
 #[instrument]
-fn update_hook_contents(hook: &Hook, hook_contents: &str) -> eyre::Result<()> {
+fn update_hook_contents(
+    hook: &Hook,
+    hook_contents: &str,
+    hook_position: HookPosition,
+) -> eyre::Result<()> {
     let (hook_path, hook_contents) = match hook {
-        Hook::RegularHook { path } => match std::fs::read_to_string(path) {
+        Hook::RegularHook { path, .. } => match std::fs::read_to_string(path) {
             Ok(lines) => {
-                let lines = update_between_lines(&lines, hook_contents);
+                let lines = update_between_lines(&lines, hook_contents, hook_position);
                 (path, lines)
             }
             Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
@@ -187,101 +322,527 @@ fn update_hook_contents(hook: &Hook, hook_contents: &str) -> eyre::Result<()> {
     Ok(())
 }
 
-#[instrument]
-fn install_hook(repo: &Repo, hook_type: &str, hook_script: &str) -> eyre::Result<()> {
-    let hook = determine_hook_path(repo, hook_type)?;
-    update_hook_contents(&hook, hook_script)?;
-    Ok(())
+/// Wrap `hook_script` so that it only invokes `git-branchless` in repositories
+/// where it's been initialized, for use in a shared hooks directory. The check
+/// is for the isolated config file (see `create_isolated_config`), since that's
+/// removed by `git branchless init --uninstall`, unlike the `.git/branchless`
+/// directory itself.
+fn make_shared_hook_script(hook_script: &str) -> String {
+    format!(
+        r#"
+# This hooks directory may be shared with other repositories, so only run in
+# repositories where git-branchless has been initialized.
+if [ -f "$(git rev-parse --git-common-dir)/branchless/config" ]; then
+{}fi
+"#,
+        hook_script.trim_start_matches('\n')
+    )
+}
+
+/// Get the contents of the `git-branchless` section which `install_hooks`
+/// writes to the given hook. Hooks which have been disabled with
+/// `branchless.hooks.<hook-type>.enabled` get a stub which does nothing.
+fn get_hook_section(
+    repo: &Repo,
+    hook: &Hook,
+    hook_type: &str,
+    hook_script: &str,
+) -> eyre::Result<String> {
+    if !get_hook_enabled(repo, hook_type)? {
+        return Ok(format!(
+            r#"
+# This hook has been disabled by `branchless.hooks.{}.enabled`.
+# Run `git branchless init --update` after re-enabling it.
+"#,
+            hook_type
+        ));
+    }
+
+    let section = match hook {
+        Hook::RegularHook {
+            is_shared: true, ..
+        } => make_shared_hook_script(hook_script),
+        Hook::RegularHook { .. } | Hook::MultiHook { .. } => hook_script.to_string(),
+    };
+    Ok(section)
 }
 
+/// Install all of the hooks used by `git-branchless` into `hooks_dir`,
+/// replacing any existing `git-branchless` section in each hook. With
+/// `HookPosition::Prepend`, the section is moved to the start of each hook.
 #[instrument]
-fn install_hooks(effects: &Effects, repo: &Repo) -> eyre::Result<()> {
+pub fn install_hooks(
+    effects: &Effects,
+    repo: &Repo,
+    hooks_dir: &Path,
+    hook_position: HookPosition,
+) -> eyre::Result<()> {
     for (hook_type, hook_script) in ALL_HOOKS {
-        writeln!(
-            effects.get_output_stream(),
-            "Installing hook: {}",
-            hook_type
+        let hook = determine_hook_path(repo, hooks_dir, hook_type)?;
+        if get_hook_enabled(repo, hook_type)? {
+            writeln!(
+                effects.get_output_stream(),
+                "Installing hook: {}",
+                hook_type
+            )?;
+        } else {
+            writeln!(effects.get_output_stream(), "Disabling hook: {}", hook_type)?;
+        }
+        update_hook_contents(
+            &hook,
+            &get_hook_section(repo, &hook, hook_type, hook_script)?,
+            hook_position,
         )?;
-        install_hook(repo, hook_type, hook_script)?;
     }
     Ok(())
 }
 
+/// Replace the `git-branchless` section of each hook in `hooks_dir`. Hooks in a
+/// shared hooks directory are left alone, since other repositories may still
+/// be using them. They won't do anything in this repository once the isolated
+/// config file has been deleted.
 #[instrument]
-fn uninstall_hooks(effects: &Effects, repo: &Repo) -> eyre::Result<()> {
+fn uninstall_hooks(effects: &Effects, repo: &Repo, hooks_dir: &Path) -> eyre::Result<()> {
+    if is_hooks_dir_shared(repo, hooks_dir) {
+        writeln!(
+            effects.get_output_stream(),
+            "Not uninstalling hooks from shared hooks directory: {}",
+            hooks_dir.to_string_lossy()
+        )?;
+        writeln!(
+            effects.get_output_stream(),
+            "(Other repositories may be using them. They won't run git-branchless in this repository.)"
+        )?;
+        return Ok(());
+    }
+
     for (hook_type, _hook_script) in ALL_HOOKS {
         writeln!(
             effects.get_output_stream(),
             "Uninstalling hook: {}",
             hook_type
         )?;
-        install_hook(
-            repo,
-            hook_type,
+        let hook = determine_hook_path(repo, hooks_dir, hook_type)?;
+        update_hook_contents(
+            &hook,
             r#"
 # This hook has been uninstalled.
 # Run `git branchless init` to reinstall.
 "#,
+            HookPosition::Append,
         )?;
     }
     Ok(())
 }
 
-/// Determine if we should make an alias of the form `branchless smartlog` or
-/// `branchless-smartlog`.
+/// Determine whether the `git-branchless-smartlog`-style executables for each
+/// subcommand are installed, along with their man-pages.
+fn has_wrapped_command_executables() -> bool {
+    cfg!(feature = "man-pages")
+}
+
+/// Get the forms that the alias for the given subcommand can take.
 ///
-/// The form of the alias is important because it determines what command Git
-/// tries to look up with `man` when you run e.g. `git smartlog --help`:
+/// The form of the alias is important because it determines what man-page Git
+/// tries to look up when you run e.g. `git smartlog --help`:
 ///
-/// - `branchless smartlog`: invokes `man git-branchless`, which means that the
-/// subcommand is not included in the `man` invocation, so it can only show
-/// generic help.
-/// - `branchless-smartlog`: invokes `man git-branchless-smartlog, so the
-/// subcommand is included in the `man` invocation, so it can show more specific
-/// help.
-fn should_use_wrapped_command_alias() -> bool {
-    cfg!(feature = "man-pages")
+/// - `branchless smartlog`: looks up `git-branchless`, which means that the
+/// subcommand is not included in the lookup, so it can only show generic help.
+/// - `branchless-smartlog`: looks up `git-branchless-smartlog`, so the
+/// subcommand is included in the lookup, so it can show more specific help.
+///
+/// The latter form is always installed. If there's no `git-branchless-smartlog`
+/// executable for it to run, then `branchless-smartlog` is additionally
+/// installed as an alias of `branchless smartlog`, and the help is rendered by
+/// `git branchless help-viewer` rather than `man` (see `install_man_pages`).
+fn get_alias_values(to: &str) -> [String; 2] {
+    [format!("branchless-{}", to), format!("branchless {}", to)]
 }
 
 #[instrument]
 fn install_alias(repo: &Repo, config: &mut Config, from: &str, to: &str) -> eyre::Result<()> {
-    let alias = if should_use_wrapped_command_alias() {
-        format!("branchless-{}", to)
-    } else {
-        format!("branchless {}", to)
-    };
-    config.set(format!("alias.{}", from), alias)?;
+    let [wrapped_alias, unwrapped_alias] = get_alias_values(to);
+    config.set(format!("alias.{}", from), wrapped_alias.clone())?;
+    if !has_wrapped_command_executables() {
+        config.set(format!("alias.{}", wrapped_alias), unwrapped_alias)?;
+    }
     Ok(())
 }
 
+/// Remove the aliases installed by `install_alias` from the repository's own
+/// configuration file, where they may have been written by older versions or
+/// copied by hand. Aliases which have been changed to something else are left
+/// alone. (The aliases in the isolated configuration file are removed along
+/// with it.)
+#[instrument]
+fn uninstall_aliases(repo: &Repo) -> eyre::Result<()> {
+    let mut config = Config::open(&repo.get_path().join("config"))?;
+    for (from, to) in ALL_ALIASES {
+        let alias_values = get_alias_values(to);
+        let [wrapped_alias, unwrapped_alias] = &alias_values;
+        for (key, expected_values) in [
+            (format!("alias.{}", from), &alias_values[..]),
+            (
+                format!("alias.{}", wrapped_alias),
+                std::slice::from_ref(unwrapped_alias),
+            ),
+        ] {
+            let value: Option<String> = config.get(&key)?;
+            if let Some(value) = value {
+                if expected_values.contains(&value) {
+                    config.remove(&key)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get the values which the alias for the given subcommand was set to by older
+/// versions of `git-branchless`, including the original Python prototype,
+/// which should be replaced by the current form (see `get_alias_values`).
+fn get_legacy_alias_values(to: &str) -> [String; 4] {
+    [
+        format!("branchless {}", to),
+        format!("!git branchless {}", to),
+        format!("!git-branchless {}", to),
+        format!("!branchless {}", to),
+    ]
+}
+
+/// Config keys which have been superseded, along with the keys which replace
+/// them. Values of keys marked as multivar-split were whitespace-separated
+/// lists, and become one entry of the new multivar key per item.
+const SUPERSEDED_CONFIG_KEYS: &[(&str, &str, bool)] = &[
+    ("branchless.mainBranch", "branchless.core.mainBranch", false),
+    (
+        "branchless.eventlog.refIgnorePatterns",
+        "branchless.eventlog.ignoreRefs",
+        true,
+    ),
+];
+
+/// Get the config files which may contain settings written by older versions
+/// of `git-branchless`, along with a description of each for reporting
+/// changes. Files which don't exist are skipped.
+fn get_migratable_config_files(repo: &Repo) -> Vec<(&'static str, PathBuf)> {
+    // The repository config includes the branchless config, so the branchless
+    // config is migrated first. Otherwise, its entries would also appear to be
+    // in the repository config.
+    let mut result = vec![
+        ("branchless config", repo.get_config_path()),
+        ("repository config", repo.get_path().join("config")),
+    ];
+    if let Ok(global_config_path) = get_global_config_path() {
+        result.push(("global config", global_config_path));
+    }
+    result.retain(|(_description, path)| path.exists());
+    result
+}
+
+/// Rewrite aliases which invoke `git-branchless` subcommands in a form used by
+/// older versions into the current form. Aliases with any other value are left
+/// alone. Returns the number of aliases which were rewritten.
+fn migrate_legacy_aliases(
+    effects: &Effects,
+    config: &mut Config,
+    config_description: &str,
+) -> eyre::Result<usize> {
+    let mut num_migrated = 0;
+    for (key, value) in config.list(r"^alias\.")? {
+        let to = ALL_ALIASES.iter().map(|(_from, to)| to).find(|to| {
+            get_legacy_alias_values(to)
+                .iter()
+                .any(|legacy_value| legacy_value == value.trim())
+        });
+        let to = match to {
+            Some(to) => to,
+            None => continue,
+        };
+        let [wrapped_alias, unwrapped_alias] = get_alias_values(to);
+        let new_value = if key == format!("alias.{}", wrapped_alias) {
+            unwrapped_alias
+        } else {
+            wrapped_alias
+        };
+        if value == new_value {
+            continue;
+        }
+        // Only replace this entry, in case the key is also set in an included
+        // config file.
+        config.set_multivar(&key, format!("^{}$", regex::escape(&value)), &new_value)?;
+        writeln!(
+            effects.get_output_stream(),
+            "Updated {} in the {}: {} -> {}",
+            key,
+            config_description,
+            value,
+            new_value
+        )?;
+        num_migrated += 1;
+    }
+    Ok(num_migrated)
+}
+
+/// Rename config keys which have been superseded (see
+/// `SUPERSEDED_CONFIG_KEYS`). If the new key is already set, then the old key
+/// was being ignored, so it's removed. Returns the number of keys which were
+/// renamed or removed.
+fn migrate_superseded_config_keys(
+    effects: &Effects,
+    config: &mut Config,
+    config_description: &str,
+) -> eyre::Result<usize> {
+    let mut num_migrated = 0;
+    for (old_key, new_key, is_multivar_split) in SUPERSEDED_CONFIG_KEYS {
+        let old_value: Option<String> = config.get(old_key)?;
+        let old_value = match old_value {
+            Some(old_value) => old_value,
+            None => continue,
+        };
+        if config.get_all(new_key)?.is_empty() {
+            if *is_multivar_split {
+                for item in old_value.split_whitespace() {
+                    config.set_multivar(new_key, format!("^{}$", regex::escape(item)), item)?;
+                }
+            } else {
+                config.set(new_key, old_value.as_str())?;
+            }
+            writeln!(
+                effects.get_output_stream(),
+                "Renamed {} to {} in the {}",
+                old_key,
+                new_key,
+                config_description
+            )?;
+        } else {
+            writeln!(
+                effects.get_output_stream(),
+                "Removed {} from the {}, since it was superseded by {}",
+                old_key,
+                config_description,
+                new_key
+            )?;
+        }
+        config.remove(old_key)?;
+        num_migrated += 1;
+    }
+    Ok(num_migrated)
+}
+
+/// Determine whether the given hook consists only of an invocation of
+/// `git-branchless` as installed by older versions, which didn't delimit
+/// their section of the hook with markers.
+fn is_legacy_hook(contents: &str, hook_type: &str) -> bool {
+    let legacy_lines = [
+        format!(r#"git branchless hook-{} "$@""#, hook_type),
+        format!(r#"git-branchless hook-{} "$@""#, hook_type),
+        format!(r#"branchless hook-{} "$@""#, hook_type),
+    ];
+    let mut lines = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .peekable();
+    lines.next_if(|line| line.starts_with("#!"));
+    let lines = lines.collect_vec();
+    !lines.is_empty()
+        && lines
+            .iter()
+            .all(|line| legacy_lines.iter().any(|legacy_line| legacy_line == line))
+}
+
+/// Replace hooks installed by older versions of `git-branchless` (see
+/// `is_legacy_hook`) with the current hook. Hooks with any other contents are
+/// left alone. Returns the number of hooks which were replaced.
+fn migrate_legacy_hooks(effects: &Effects, repo: &Repo) -> eyre::Result<usize> {
+    let hooks_dir = get_core_hooks_path(repo)?;
+    let mut num_migrated = 0;
+    for (hook_type, hook_script) in ALL_HOOKS {
+        let hook = determine_hook_path(repo, &hooks_dir, hook_type)?;
+        let path = match &hook {
+            Hook::RegularHook { path, .. } => path,
+            // Multihooks are always rewritten in full by `install_hooks`.
+            Hook::MultiHook { .. } => continue,
+        };
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(eyre::eyre!(err)).wrap_err_with(|| format!("Reading hook {:?}", path))
+            }
+        };
+        if !is_legacy_hook(&contents, hook_type) {
+            continue;
+        }
+
+        std::fs::remove_file(path).wrap_err_with(|| format!("Removing hook {:?}", path))?;
+        update_hook_contents(
+            &hook,
+            &get_hook_section(repo, &hook, hook_type, hook_script)?,
+            HookPosition::Append,
+        )?;
+        writeln!(
+            effects.get_output_stream(),
+            "Replaced legacy hook: {}",
+            hook_type
+        )?;
+        num_migrated += 1;
+    }
+    Ok(num_migrated)
+}
+
+/// Rewrite the aliases, hooks, and config keys left behind by older versions
+/// of `git-branchless` into their current forms. Returns the number of changes
+/// which were made.
+#[instrument]
+fn migrate_legacy_installation(effects: &Effects, repo: &Repo) -> eyre::Result<usize> {
+    let mut num_migrated = 0;
+    for (config_description, path) in get_migratable_config_files(repo) {
+        let mut config = Config::open(&path)?;
+        num_migrated += migrate_legacy_aliases(effects, &mut config, config_description)?;
+        num_migrated += migrate_superseded_config_keys(effects, &mut config, config_description)?;
+    }
+    num_migrated += migrate_legacy_hooks(effects, repo)?;
+    Ok(num_migrated)
+}
+
+/// Detect the main branch. Local branches are preferred. If there are none
+/// with a likely name, such as in a fork-based workflow where the local main
+/// branch was deleted, then a remote-tracking branch like `origin/main` is
+/// used instead.
+///
+/// Branch names are matched exactly, so that a branch whose name differs only
+/// in case (such as `Master`) isn't mistaken for the main branch.
 #[instrument]
 fn detect_main_branch_name(repo: &Repo) -> eyre::Result<Option<String>> {
+    let mut candidate_names = Vec::new();
     if let Some(default_branch_name) = get_default_branch_name(repo)? {
+        candidate_names.push(default_branch_name);
+    }
+    candidate_names.extend(
+        [
+            "master",
+            "main",
+            "mainline",
+            "devel",
+            "develop",
+            "development",
+            "trunk",
+        ]
+        .iter()
+        .map(|branch_name| branch_name.to_string()),
+    );
+
+    for branch_name in candidate_names.iter() {
+        if matches!(
+            repo.resolve_branch_name(&format!("refs/heads/{}", branch_name))?,
+            ResolvedBranchName::Found(_)
+        ) {
+            return Ok(Some(branch_name.clone()));
+        }
+    }
+
+    // Check `origin` first, since it's the remote created by `git clone`.
+    let mut remote_names = repo.get_remote_names()?;
+    remote_names.sort_by_key(|remote_name| remote_name != "origin");
+    for remote_name in remote_names {
+        for branch_name in candidate_names.iter() {
+            let remote_branch_name = format!("{}/{}", remote_name, branch_name);
+            if matches!(
+                repo.resolve_branch_name(&format!("refs/remotes/{}", remote_branch_name))?,
+                ResolvedBranchName::Found(_)
+            ) {
+                return Ok(Some(remote_branch_name));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Get the names of the branches which could be the main branch, to offer when
+/// it couldn't be auto-detected. The currently checked-out branch is listed
+/// first, since it's the most likely candidate, followed by the other local
+/// branches and then the remote-tracking branches.
+fn get_main_branch_candidates(repo: &Repo) -> eyre::Result<Vec<String>> {
+    let head_branch_name = repo
+        .get_head_info()?
+        .get_branch_name()?
+        .map(|branch_name| branch_name.to_string_lossy().into_owned());
+
+    let mut local_branch_names = Vec::new();
+    for branch in repo.get_all_local_branches()? {
+        if let Some(branch_name) = branch.get_name()? {
+            local_branch_names.push(branch_name.to_string());
+        }
+    }
+    local_branch_names.sort_by_key(|branch_name| Some(branch_name) != head_branch_name.as_ref());
+
+    let mut remote_branch_names = Vec::new();
+    for branch in repo.get_all_remote_branches()? {
+        if let Some(branch_name) = branch.get_name()? {
+            if !branch_name.ends_with("/HEAD") {
+                remote_branch_names.push(branch_name.to_string());
+            }
+        }
+    }
+
+    Ok(local_branch_names
+        .into_iter()
+        .chain(remote_branch_names)
+        .collect())
+}
+
+/// Convert a main branch name provided by the user into the form stored in
+/// `branchless.core.mainBranch`, checking that the branch exists.
+///
+/// Local branches are stored by their short name (`main`), and remote-tracking
+/// branches by their remote-qualified name (`origin/main`), which are the forms
+/// understood by `Repo::find_main_branch_reference_by_name`. If `HEAD` is
+/// unborn, a local branch which doesn't exist yet is accepted, since it will be
+/// created by the first commit.
+fn normalize_main_branch_name(repo: &Repo, main_branch_name: &str) -> eyre::Result<String> {
+    if let Some(remote_branch_name) = main_branch_name.strip_prefix("refs/remotes/") {
         if repo
-            .find_branch(&default_branch_name, git2::BranchType::Local)?
+            .find_branch(remote_branch_name, git2::BranchType::Remote)?
             .is_some()
         {
-            return Ok(Some(default_branch_name));
+            return Ok(remote_branch_name.to_string());
         }
-    }
-
-    for branch_name in [
-        "master",
-        "main",
-        "mainline",
-        "devel",
-        "develop",
-        "development",
-        "trunk",
-    ] {
+    } else {
+        let is_fully_qualified = main_branch_name.starts_with("refs/heads/");
+        let local_branch_name = main_branch_name
+            .strip_prefix("refs/heads/")
+            .unwrap_or(main_branch_name);
         if repo
-            .find_branch(branch_name, git2::BranchType::Local)?
+            .find_branch(local_branch_name, git2::BranchType::Local)?
             .is_some()
         {
-            return Ok(Some(branch_name.to_string()));
+            return Ok(local_branch_name.to_string());
+        }
+        if !is_fully_qualified
+            && repo
+                .find_branch(local_branch_name, git2::BranchType::Remote)?
+                .is_some()
+        {
+            return Ok(local_branch_name.to_string());
+        }
+        if !local_branch_name.is_empty() && repo.get_head_info()?.is_unborn() {
+            return Ok(local_branch_name.to_string());
         }
     }
-    Ok(None)
+
+    let suggestion = r"
+The main branch can be provided in any of these forms:
+  - a local branch name, such as: main
+  - a full local branch reference name, such as: refs/heads/main
+  - a remote-tracking branch name, such as: origin/main
+  - a full remote-tracking branch reference name, such as: refs/remotes/origin/main
+";
+    Err(
+        eyre::eyre!("The main branch {:?} could not be found", main_branch_name)
+            .with_suggestion(|| suggestion),
+    )
 }
 
 #[instrument]
@@ -295,16 +856,20 @@ fn install_aliases(
         install_alias(repo, config, from, to)?;
     }
 
-    let version_str = git_run_info
-        .run_silent(repo, None, &["version"], Default::default())
-        .wrap_err("Determining Git version")?
-        .stdout;
-    let version_str =
-        String::from_utf8(version_str).wrap_err("Decoding stdout from Git subprocess")?;
-    let version_str = version_str.trim();
-    let version: GitVersion = version_str
-        .parse()
-        .wrap_err_with(|| format!("Parsing Git version string: {}", version_str))?;
+    let version_str = git_run_info.get_git_version_output(repo)?;
+    let version: GitVersion = match version_str.parse() {
+        Ok(version) => version,
+        Err(err) => {
+            warn!(?err, ?version_str, "Could not parse Git version");
+            writeln!(
+                effects.get_output_stream(),
+                "{warning_str}: could not determine the Git version from: {version_str}",
+                warning_str = style("Warning").yellow().bold(),
+                version_str = version_str,
+            )?;
+            return Ok(());
+        }
+    };
     if version < GitVersion(2, 29, 0) {
         write!(
             effects.get_output_stream(),
@@ -331,8 +896,18 @@ the branchless workflow will work properly.
 
 #[instrument]
 fn install_man_pages(effects: &Effects, repo: &Repo, config: &mut Config) -> eyre::Result<()> {
-    let should_install = cfg!(feature = "man-pages");
-    if !should_install {
+    if is_user_man_viewer_installed()? {
+        // The man-pages were installed for the current user, and the global
+        // configuration already points `git help` at them.
+        return Ok(());
+    }
+
+    if !has_wrapped_command_executables() {
+        // There are no man-pages to show, so render the `--help` output for
+        // the subcommand instead. Pages for other commands are passed through
+        // to `man`.
+        config.set("man.branchless.cmd", "git branchless help-viewer")?;
+        config.set("man.viewer", "branchless")?;
         return Ok(());
     }
 
@@ -376,10 +951,14 @@ fn set_configs(
     effects: &Effects,
     repo: &Repo,
     config: &mut Config,
-    main_branch_name: Option<&str>,
+    main_branch_names: &[String],
 ) -> eyre::Result<()> {
-    let main_branch_name = match main_branch_name {
-        Some(main_branch_name) => main_branch_name.to_string(),
+    let main_branch_names: Vec<String> = main_branch_names
+        .iter()
+        .map(|main_branch_name| normalize_main_branch_name(repo, main_branch_name))
+        .collect::<eyre::Result<_>>()?;
+    let main_branch_name = match main_branch_names.first() {
+        Some(main_branch_name) => main_branch_name.clone(),
 
         None => match detect_main_branch_name(repo)? {
             Some(main_branch_name) => {
@@ -411,28 +990,111 @@ fn set_configs(
                     effects.get_output_stream(),
                     "See https://github.com/arxanas/git-branchless/wiki/Concepts#main-branch"
                 )?;
-                write!(
-                    effects.get_output_stream(),
-                    "Enter the name of your main branch: "
-                )?;
-                stdout().flush()?;
-                let mut input = String::new();
-                r#in.read_line(&mut input)?;
-                match input.trim() {
-                    "" => eyre::bail!("No main branch name provided"),
-                    main_branch_name => main_branch_name.to_string(),
+                let candidates = get_main_branch_candidates(repo)?;
+                match prompt_choose_or_enter(
+                    effects,
+                    r#in,
+                    "Branches in this repository:",
+                    "Enter the name of your main branch",
+                    &candidates,
+                    0,
+                )? {
+                    None => eyre::bail!("No main branch name provided"),
+                    Some(main_branch_name) => normalize_main_branch_name(repo, &main_branch_name)?,
                 }
             }
         },
     };
 
     config.set("branchless.core.mainBranch", main_branch_name)?;
+    if main_branch_names.len() > 1 {
+        for main_branch_name in &main_branch_names {
+            config.set_multivar(
+                "branchless.core.mainBranches",
+                format!("^{}$", regex::escape(main_branch_name)),
+                main_branch_name,
+            )?;
+        }
+    }
     config.set("advice.detachedHead", false)?;
     config.set("log.excludeDecoration", "refs/branchless/*")?;
 
     Ok(())
 }
 
+/// If the hooks directory is outside of the repository, then warn that it may
+/// be shared with other repositories, or override `core.hooksPath` so that the
+/// hooks are installed into the repository instead, depending on
+/// `hooks_dir_setting`. Returns the directory to install the hooks into.
+#[instrument]
+fn set_hooks_dir(
+    effects: &Effects,
+    repo: &Repo,
+    config: &mut Config,
+    hooks_dir_setting: Option<HooksDirSetting>,
+) -> eyre::Result<PathBuf> {
+    let hooks_dir = get_core_hooks_path(repo)?;
+    if repo.get_path().join("hooks_multi").exists() || !is_hooks_dir_shared(repo, &hooks_dir) {
+        return Ok(hooks_dir);
+    }
+
+    match hooks_dir_setting {
+        Some(HooksDirSetting::Repo) => {
+            let repo_hooks_dir = repo.get_path().join("hooks");
+            // As in `create_isolated_config`, use forward-slashes for Git.
+            let repo_hooks_dir_str = repo_hooks_dir.to_slash().ok_or_else(|| {
+                eyre::eyre!(
+                    "Could not convert hooks path to UTF-8 string: {:?}",
+                    &repo_hooks_dir
+                )
+            })?;
+            config.set("core.hooksPath", repo_hooks_dir_str.as_str())?;
+            writeln!(
+                effects.get_output_stream(),
+                "Installing hooks into {} instead of the shared hooks directory {}",
+                repo_hooks_dir_str,
+                hooks_dir.to_string_lossy()
+            )?;
+            return Ok(repo_hooks_dir);
+        }
+
+        Some(HooksDirSetting::Shared) => {
+            writeln!(
+                effects.get_output_stream(),
+                "Installing hooks into shared hooks directory: {}",
+                hooks_dir.to_string_lossy()
+            )?;
+        }
+
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "{}",
+                console::style(format!(
+                    "Warning: your hooks directory is outside of this repository: {}",
+                    hooks_dir.to_string_lossy()
+                ))
+                .yellow()
+                .bold()
+            )?;
+            writeln!(
+                effects.get_output_stream(),
+                "It may be shared with other repositories, such as if core.hooksPath is set globally."
+            )?;
+            writeln!(
+                effects.get_output_stream(),
+                "The hooks installed there will only run git-branchless in repositories where it's been initialized."
+            )?;
+            writeln!(
+                effects.get_output_stream(),
+                "To install the hooks into this repository instead, run: {}",
+                console::style("git branchless init --hooks-dir repo").bold()
+            )?;
+        }
+    }
+    Ok(hooks_dir)
+}
+
 const INCLUDE_PATH_REGEX: &str = r"^branchless/";
 
 /// Create an isolated configuration file under `.git/branchless`, which is then
@@ -440,7 +1102,7 @@ const INCLUDE_PATH_REGEX: &str = r"^branchless/";
 /// to uninstall our settings (or for the user to override our settings) without
 /// needing to modify the user's configuration file.
 #[instrument]
-fn create_isolated_config(
+pub fn create_isolated_config(
     effects: &Effects,
     repo: &Repo,
     mut parent_config: Config,
@@ -478,51 +1140,938 @@ fn create_isolated_config(
     Ok(config)
 }
 
-/// Delete the configuration file created by `create_isolated_config` and remove
-/// its `include` directive from the repository's configuration file.
+/// Determine whether the repository's configuration includes the isolated
+/// configuration file created by `create_isolated_config`.
 #[instrument]
-fn delete_isolated_config(
-    effects: &Effects,
-    repo: &Repo,
-    mut parent_config: Config,
-) -> eyre::Result<()> {
-    writeln!(
-        effects.get_output_stream(),
-        "Removing config file: {}",
-        repo.get_config_path().to_string_lossy()
-    )?;
-    parent_config.remove_multivar("include.path", INCLUDE_PATH_REGEX)?;
-    let result = match std::fs::remove_file(repo.get_config_path()) {
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-            writeln!(
-                effects.get_output_stream(),
-                "(The config file was not present, ignoring)"
-            )?;
-            Ok(())
+pub fn is_isolated_config_included(repo: &Repo) -> eyre::Result<bool> {
+    let include_path_regex = Regex::new(INCLUDE_PATH_REGEX)?;
+    let config = repo.get_readonly_config()?;
+    let is_included = config
+        .list(r"^include\.path$")?
+        .into_iter()
+        .any(|(_name, value)| include_path_regex.is_match(&value));
+    Ok(is_included)
+}
+
+/// The key in the isolated config storing the fingerprint of the machine and
+/// location at which the repository was initialized. See
+/// `get_repo_fingerprint`.
+const FINGERPRINT_CONFIG_KEY: &str = "branchless.internal.fingerprint";
+
+/// The key in the isolated config storing the fingerprint for which
+/// `warn_if_repo_copied` last printed its hint, so that it's only printed once.
+const COPIED_HINT_CONFIG_KEY: &str = "branchless.internal.copiedHintShown";
+
+fn get_hostname() -> String {
+    if let Ok(hostname) = std::fs::read_to_string("/etc/hostname") {
+        return hostname.trim().to_string();
+    }
+    for var in &["COMPUTERNAME", "HOSTNAME"] {
+        if let Ok(hostname) = std::env::var(var) {
+            return hostname;
         }
-        result => result,
-    };
-    let result = result.wrap_err("Deleting isolated config")?;
-    Ok(result)
+    }
+    String::new()
 }
 
-/// Initialize `git-branchless` in the current repo.
-#[instrument]
-pub fn init(
-    effects: &Effects,
-    git_run_info: &GitRunInfo,
-    main_branch_name: Option<&str>,
-) -> eyre::Result<()> {
-    let mut in_ = BufReader::new(stdin());
-    let mut repo = Repo::from_current_dir()?;
-    let readonly_config = repo.get_readonly_config()?;
-    let mut config = create_isolated_config(effects, &repo, readonly_config.into_config())?;
+/// Get a string identifying the machine and the location of the repository on
+/// it. If the stored fingerprint doesn't match, then the repository was copied
+/// (such as with `rsync`) after it was initialized.
+fn get_repo_fingerprint(repo: &Repo) -> String {
+    format!("{}:{}", get_hostname(), repo.get_path().to_string_lossy())
+}
 
-    set_configs(&mut in_, effects, &repo, &mut config, main_branch_name)?;
-    install_hooks(effects, &repo)?;
-    install_aliases(effects, &mut repo, &mut config, git_run_info)?;
-    install_man_pages(effects, &repo, &mut config)?;
-    writeln!(
+/// Record the fingerprint of the current machine and repository location in
+/// the isolated config.
+fn write_repo_fingerprint(repo: &Repo, config: &mut Config) -> eyre::Result<()> {
+    config.set(FINGERPRINT_CONFIG_KEY, get_repo_fingerprint(repo))?;
+    config.remove_multivar(COPIED_HINT_CONFIG_KEY, ".*")?;
+    Ok(())
+}
+
+/// If the repository appears to have been copied from another machine or
+/// location since it was initialized, print a hint to run `git branchless init
+/// --update`. The hint is only printed once per copy.
+#[instrument]
+pub fn warn_if_repo_copied(effects: &Effects, repo: &Repo) -> eyre::Result<()> {
+    let config_path = repo.get_config_path();
+    if !config_path.exists() {
+        return Ok(());
+    }
+    let mut config = Config::open(&config_path)?;
+    let fingerprint = get_repo_fingerprint(repo);
+    let stored_fingerprint: Option<String> = config.get(FINGERPRINT_CONFIG_KEY)?;
+    match stored_fingerprint {
+        Some(stored_fingerprint) if stored_fingerprint != fingerprint => {}
+        Some(_) | None => return Ok(()),
+    }
+    let hint_shown_fingerprint: Option<String> = config.get(COPIED_HINT_CONFIG_KEY)?;
+    if hint_shown_fingerprint.as_ref() == Some(&fingerprint) {
+        return Ok(());
+    }
+
+    writeln!(
+        effects.get_output_stream(),
+        "{}: this repository appears to have been copied; run `git branchless init --update`",
+        style("hint").bold()
+    )?;
+    config.set(COPIED_HINT_CONFIG_KEY, fingerprint)?;
+    Ok(())
+}
+
+/// Determine whether `hooks_path` refers to the hooks directory of another
+/// copy of this repository, such as if `core.hooksPath` was set to the
+/// repository's hooks directory by `set_hooks_dir` and then the repository was
+/// copied to a different location.
+fn is_stale_hooks_path(repo: &Repo, hooks_path: &Path) -> bool {
+    let repo_hooks_path = repo.get_path().join("hooks");
+    hooks_path.is_absolute()
+        && hooks_path != repo_hooks_path
+        && hooks_path.file_name() == repo_hooks_path.file_name()
+        && hooks_path.parent().and_then(|parent| parent.file_name()) == repo.get_path().file_name()
+}
+
+/// Regenerate the settings in the isolated config which refer to absolute
+/// paths specific to another copy of this repository.
+#[instrument]
+pub fn fix_stale_config_paths(effects: &Effects, repo: &Repo) -> eyre::Result<()> {
+    let config_path = repo.get_config_path();
+    if !config_path.exists() {
+        return Ok(());
+    }
+    let mut config = Config::open(&config_path)?;
+    for (name, value) in find_stale_config_paths(repo)? {
+        // `set_hooks_dir` is the only place which writes an absolute path.
+        let repo_hooks_dir = repo.get_path().join("hooks");
+        let repo_hooks_dir_str = repo_hooks_dir.to_slash().ok_or_else(|| {
+            eyre::eyre!(
+                "Could not convert hooks path to UTF-8 string: {:?}",
+                &repo_hooks_dir
+            )
+        })?;
+        config.set(&name, repo_hooks_dir_str.as_str())?;
+        writeln!(
+            effects.get_output_stream(),
+            "Updated {} from {} to {}",
+            name,
+            value,
+            repo_hooks_dir_str
+        )?;
+    }
+    Ok(())
+}
+
+/// Find the settings in the isolated config which refer to absolute paths
+/// specific to another copy of this repository. Returns the names of the
+/// settings and their values.
+pub fn find_stale_config_paths(repo: &Repo) -> eyre::Result<Vec<(String, String)>> {
+    let config_path = repo.get_config_path();
+    if !config_path.exists() {
+        return Ok(Vec::new());
+    }
+    let config = Config::open(&config_path)?;
+    let hooks_path: Option<PathBuf> = config.get("core.hooksPath")?;
+    let result = match hooks_path {
+        Some(hooks_path) if is_stale_hooks_path(repo, &hooks_path) => vec![(
+            "core.hooksPath".to_string(),
+            hooks_path.to_string_lossy().into_owned(),
+        )],
+        Some(_) | None => Vec::new(),
+    };
+    Ok(result)
+}
+
+/// Delete the configuration file created by `create_isolated_config` and remove
+/// its `include` directive from the repository's configuration file.
+#[instrument]
+fn delete_isolated_config(
+    effects: &Effects,
+    repo: &Repo,
+    mut parent_config: Config,
+) -> eyre::Result<()> {
+    writeln!(
+        effects.get_output_stream(),
+        "Removing config file: {}",
+        repo.get_config_path().to_string_lossy()
+    )?;
+    parent_config.remove_multivar("include.path", INCLUDE_PATH_REGEX)?;
+    let result = match std::fs::remove_file(repo.get_config_path()) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            writeln!(
+                effects.get_output_stream(),
+                "(The config file was not present, ignoring)"
+            )?;
+            Ok(())
+        }
+        result => result,
+    };
+    let result = result.wrap_err("Deleting isolated config")?;
+    Ok(result)
+}
+
+/// An event imported from existing history, before it's been assigned a
+/// transaction.
+enum SeedEvent {
+    RefUpdate {
+        ref_name: OsString,
+        old_oid: MaybeZeroOid,
+        new_oid: MaybeZeroOid,
+    },
+    Commit {
+        commit_oid: NonZeroOid,
+    },
+}
+
+impl SeedEvent {
+    fn into_event(
+        self,
+        timestamp: SystemTime,
+        event_tx_id: EventTransactionId,
+    ) -> eyre::Result<Event> {
+        let timestamp = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .wrap_err("Calculating event timestamp")?
+            .as_secs_f64();
+        let event = match self {
+            SeedEvent::RefUpdate {
+                ref_name,
+                old_oid,
+                new_oid,
+            } => Event::RefUpdateEvent {
+                timestamp,
+                event_tx_id,
+                ref_name,
+                old_oid,
+                new_oid,
+                message: None,
+            },
+            SeedEvent::Commit { commit_oid } => Event::CommitEvent {
+                timestamp,
+                event_tx_id,
+                commit_oid,
+            },
+        };
+        Ok(event)
+    }
+}
+
+/// Import the existing history of `HEAD` and the local branches from their
+/// reflogs into the event log, so that `git undo` can reach states from before
+/// initialization. Also mark the commits in existing stacks (those reachable
+/// from a local branch, but not from the main branch) as visible.
+///
+/// At most `seed_depth` reflog entries are imported for each reference, and at
+/// most `seed_depth` commits are marked as visible. Nothing is imported if the
+/// event log already has events, such as when re-initializing.
+#[instrument]
+fn seed_event_log(effects: &Effects, repo: &Repo, seed_depth: usize) -> eyre::Result<()> {
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    if !event_log_db.get_events()?.is_empty() {
+        return Ok(());
+    }
+    let (effects, progress) = effects.start_operation(OperationType::ImportReflog);
+
+    let commit_exists = |oid: MaybeZeroOid| -> eyre::Result<bool> {
+        match oid {
+            MaybeZeroOid::NonZero(oid) => Ok(repo.find_commit(oid)?.is_some()),
+            MaybeZeroOid::Zero => Ok(true),
+        }
+    };
+    let mut ref_names = vec![OsString::from("HEAD")];
+    for branch in repo.get_all_local_branches()? {
+        ref_names.push(branch.into_reference().get_name()?);
+    }
+    let mut timed_events: Vec<(SystemTime, SeedEvent)> = Vec::new();
+    for ref_name in ref_names {
+        for entry in repo.get_reflog_entries(&ref_name, seed_depth)? {
+            let ReflogEntry {
+                old_oid,
+                new_oid,
+                timestamp,
+                message: _,
+            } = entry;
+            // Entries may refer to commits which have since been collected.
+            if !commit_exists(old_oid)? || !commit_exists(new_oid)? {
+                continue;
+            }
+            timed_events.push((
+                timestamp,
+                SeedEvent::RefUpdate {
+                    ref_name: ref_name.clone(),
+                    old_oid,
+                    new_oid,
+                },
+            ));
+        }
+    }
+
+    // Without the main branch, we can't tell which commits are public, so
+    // don't mark any commits as visible.
+    if repo.find_main_branch_reference()?.is_some() {
+        let event_replayer = EventReplayer::from_events(&effects, repo, &[])?;
+        let event_cursor = event_replayer.make_default_cursor();
+        let references_snapshot = repo.get_references_snapshot()?;
+        let dag = Dag::open_and_sync(
+            &effects,
+            repo,
+            &event_replayer,
+            event_cursor,
+            &references_snapshot,
+        )?;
+        let stack_commits = dag
+            .query()
+            .only(dag.branch_commits.clone(), dag.main_branch_commit.clone())?;
+        for commit_oid in commit_set_to_vec(&stack_commits)?
+            .into_iter()
+            .take(seed_depth)
+        {
+            let commit = repo.find_commit_or_fail(commit_oid)?;
+            let timestamp = SystemTime::UNIX_EPOCH
+                + Duration::from_secs(commit.get_time().seconds().max(0).try_into()?);
+            timed_events.push((timestamp, SeedEvent::Commit { commit_oid }));
+        }
+    }
+
+    // Events which happened at the same time (such as a commit moving both
+    // `HEAD` and the current branch) are grouped into the same transaction.
+    timed_events.sort_by_key(|(timestamp, _event)| *timestamp);
+    let num_events = timed_events.len();
+    progress.notify_progress(0, num_events);
+    for (timestamp, group) in &timed_events
+        .into_iter()
+        .group_by(|(timestamp, _event)| *timestamp)
+    {
+        let event_tx_id = event_log_db.make_transaction_id(timestamp, "import reflog")?;
+        let events: Vec<Event> = group
+            .map(|(timestamp, event)| event.into_event(timestamp, event_tx_id))
+            .try_collect()?;
+        progress.notify_progress_inc(events.len());
+        event_log_db.add_events(events)?;
+    }
+
+    if num_events > 0 {
+        writeln!(
+            effects.get_output_stream(),
+            "Imported {} from existing history.",
+            Pluralize {
+                amount: num_events.try_into()?,
+                singular: "event",
+                plural: "events",
+            }
+            .to_string()
+        )?;
+    }
+    Ok(())
+}
+
+/// The result of one of the checks made by `git branchless init --check`.
+#[derive(Debug)]
+pub struct InstallationCheck {
+    /// What was checked, such as `hook post-commit`.
+    pub item: String,
+
+    /// Why the check failed, or `None` if it passed.
+    pub problem: Option<String>,
+}
+
+impl InstallationCheck {
+    /// Describe the result of the check on a single line.
+    pub fn describe(&self) -> String {
+        match &self.problem {
+            None => format!("[ok] {}", self.item),
+            Some(problem) => format!("[FAILED] {}: {}", self.item, problem),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> eyre::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata =
+        std::fs::metadata(path).wrap_err_with(|| format!("Reading permissions of {:?}", path))?;
+    Ok(metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> eyre::Result<bool> {
+    Ok(true)
+}
+
+/// Check that the given hook is installed with the contents that
+/// `install_hooks` would write. Returns a description of the problem, if any.
+fn check_hook(
+    repo: &Repo,
+    hooks_dir: &Path,
+    hook_type: &str,
+    hook_script: &str,
+) -> eyre::Result<Option<String>> {
+    let hook = determine_hook_path(repo, hooks_dir, hook_type)?;
+    let expected_section = get_hook_section(repo, &hook, hook_type, hook_script)?;
+    let path = match &hook {
+        Hook::RegularHook { path, .. } | Hook::MultiHook { path } => path,
+    };
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Some("not installed".to_string()))
+        }
+        Err(err) => return Err(err).wrap_err_with(|| format!("Reading hook {:?}", path)),
+    };
+    if !is_executable(path)? {
+        return Ok(Some("not executable".to_string()));
+    }
+
+    let is_up_to_date = match hook {
+        Hook::RegularHook { .. } => {
+            if !are_markers_intact(&contents) {
+                return Ok(Some(
+                    "git-branchless section markers not intact".to_string(),
+                ));
+            }
+            // The first installation of a hook surrounds the section with
+            // blank lines, so they're ignored.
+            let section = contents
+                .lines()
+                .skip_while(|line| *line != UPDATE_MARKER_START)
+                .skip(1)
+                .take_while(|line| *line != UPDATE_MARKER_END)
+                .join("\n");
+            section.trim() == expected_section.trim()
+        }
+        Hook::MultiHook { .. } => contents == format!("{}\n{}", SHEBANG, expected_section),
+    };
+    if is_up_to_date {
+        Ok(None)
+    } else {
+        Ok(Some("git-branchless section is out of date".to_string()))
+    }
+}
+
+/// Check that the alias `from` runs the `git-branchless` subcommand `to`.
+/// Returns a description of the problem, if any.
+fn check_alias(config: &impl ConfigRead, from: &str, to: &str) -> eyre::Result<Option<String>> {
+    let [wrapped_alias, unwrapped_alias] = get_alias_values(to);
+    let value: Option<String> = config.get(format!("alias.{}", from))?;
+    let problem = match value {
+        None => Some("not set".to_string()),
+        Some(value) if value == unwrapped_alias => None,
+        Some(value) if value == wrapped_alias => {
+            let wrapped_value: Option<String> = config.get(format!("alias.{}", wrapped_alias))?;
+            if has_wrapped_command_executables()
+                || wrapped_value.as_deref() == Some(unwrapped_alias.as_str())
+            {
+                None
+            } else {
+                Some(format!(
+                    "alias {} is not set to `{}`",
+                    wrapped_alias, unwrapped_alias
+                ))
+            }
+        }
+        Some(value) => Some(format!("set to `{}` instead of `{}`", value, wrapped_alias)),
+    };
+    Ok(problem)
+}
+
+/// Check that the hooks, aliases, and configuration installed by `init` are
+/// still in place, since other tools may have overwritten them.
+#[instrument]
+pub fn check_installation(repo: &Repo) -> eyre::Result<Vec<InstallationCheck>> {
+    let mut checks = Vec::new();
+
+    let hooks_dir = get_core_hooks_path(repo)?;
+    for (hook_type, hook_script) in ALL_HOOKS {
+        checks.push(InstallationCheck {
+            item: format!("hook {}", hook_type),
+            problem: check_hook(repo, &hooks_dir, hook_type, hook_script)?,
+        });
+    }
+
+    let config = repo.get_readonly_config()?;
+    for (from, to) in ALL_ALIASES {
+        checks.push(InstallationCheck {
+            item: format!("alias {}", from),
+            problem: check_alias(&config, from, to)?,
+        });
+    }
+
+    checks.push(InstallationCheck {
+        item: "isolated config".to_string(),
+        problem: if !repo.get_config_path().exists() {
+            Some(format!(
+                "{} does not exist",
+                repo.get_config_path().to_string_lossy()
+            ))
+        } else if !is_isolated_config_included(repo)? {
+            Some("not included by the repository's config file".to_string())
+        } else {
+            None
+        },
+    });
+
+    let main_branch_name = get_main_branch_name(repo)?;
+    checks.push(InstallationCheck {
+        item: format!("main branch {}", main_branch_name),
+        problem: if repo.find_main_branch_reference()?.is_some()
+            || repo.get_head_info()?.is_unborn()
+        {
+            None
+        } else {
+            Some(
+                "does not exist (set it with: git config branchless.core.mainBranch <branch>)"
+                    .to_string(),
+            )
+        },
+    });
+
+    Ok(checks)
+}
+
+/// Reinstall whichever hooks, aliases, and configuration failed the checks
+/// made by `check_installation`. Aliases which have been overridden in the
+/// repository's config file are removed from it.
+#[instrument]
+fn fix_installation(
+    effects: &Effects,
+    repo: &Repo,
+    checks: &[InstallationCheck],
+) -> eyre::Result<()> {
+    let has_failed = |item: &str| {
+        checks
+            .iter()
+            .any(|check| check.item == item && check.problem.is_some())
+    };
+
+    let mut config = if has_failed("isolated config") {
+        let readonly_config = repo.get_readonly_config()?;
+        create_isolated_config(effects, repo, readonly_config.into_config())?
+    } else {
+        Config::open(&repo.get_config_path())?
+    };
+
+    let mut repo_config = Config::open(&repo.get_path().join("config"))?;
+    for (from, to) in ALL_ALIASES {
+        if !has_failed(&format!("alias {}", from)) {
+            continue;
+        }
+        writeln!(effects.get_output_stream(), "Reinstalling alias: {}", from)?;
+        let key = format!("alias.{}", from);
+        let value: Option<String> = repo_config.get(&key)?;
+        if let Some(value) = value {
+            if !get_alias_values(to).contains(&value) {
+                // The configuration file includes the isolated configuration
+                // file, so only remove the overriding value.
+                repo_config.remove_multivar(&key, format!("^{}$", regex::escape(&value)))?;
+            }
+        }
+        install_alias(repo, &mut config, from, to)?;
+    }
+
+    if ALL_HOOKS
+        .iter()
+        .any(|(hook_type, _hook_script)| has_failed(&format!("hook {}", hook_type)))
+    {
+        install_hooks(
+            effects,
+            repo,
+            &get_core_hooks_path(repo)?,
+            HookPosition::Append,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Check that `git-branchless` is still correctly installed in the current
+/// repo, printing the result of each check. If `fix` is set, then reinstall
+/// anything which failed the checks first.
+///
+/// Returns an exit code (0 denotes successful exit). The exit code is nonzero
+/// if any check failed, unless it was fixed.
+#[instrument]
+pub fn check(effects: &Effects, git_run_info: &GitRunInfo, fix: bool) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let mut checks = check_installation(&repo)?;
+    if fix && checks.iter().any(|check| check.problem.is_some()) {
+        fix_installation(effects, &repo, &checks)?;
+        checks = check_installation(&repo)?;
+    }
+
+    for check in &checks {
+        writeln!(effects.get_output_stream(), "{}", check.describe())?;
+    }
+    let num_failed = checks
+        .iter()
+        .filter(|check| check.problem.is_some())
+        .count();
+    if num_failed == 0 {
+        return Ok(0);
+    }
+
+    writeln!(
+        effects.get_output_stream(),
+        "{}",
+        console::style(format!(
+            "{} failed.",
+            Pluralize {
+                amount: num_failed.try_into()?,
+                singular: "check",
+                plural: "checks",
+            }
+            .to_string()
+        ))
+        .yellow()
+        .bold()
+    )?;
+    if !fix {
+        writeln!(
+            effects.get_output_stream(),
+            "To reinstall the hooks, run: git branchless init --update"
+        )?;
+        writeln!(
+            effects.get_output_stream(),
+            "To fix these problems automatically, run: git branchless init --check --fix"
+        )?;
+    }
+    Ok(1)
+}
+
+/// Determine the directory which `set_hooks_dir` will install the hooks into,
+/// without modifying the configuration.
+fn get_planned_hooks_dir(
+    repo: &Repo,
+    hooks_dir_setting: Option<HooksDirSetting>,
+) -> eyre::Result<PathBuf> {
+    let hooks_dir = get_core_hooks_path(repo)?;
+    let hooks_dir = if is_stale_hooks_path(repo, &hooks_dir) {
+        // `install` regenerates it before setting the hooks directory.
+        repo.get_path().join("hooks")
+    } else if hooks_dir_setting == Some(HooksDirSetting::Repo)
+        && !repo.get_path().join("hooks_multi").exists()
+        && is_hooks_dir_shared(repo, &hooks_dir)
+    {
+        repo.get_path().join("hooks")
+    } else {
+        hooks_dir
+    };
+    Ok(hooks_dir)
+}
+
+/// Get the paths of the hook files which `install_hooks` writes.
+fn get_hook_paths(repo: &Repo, hooks_dir: &Path) -> eyre::Result<Vec<PathBuf>> {
+    ALL_HOOKS
+        .iter()
+        .map(
+            |(hook_type, _hook_script)| match determine_hook_path(repo, hooks_dir, hook_type)? {
+                Hook::RegularHook { path, .. } | Hook::MultiHook { path } => Ok(path),
+            },
+        )
+        .collect()
+}
+
+/// Determine whether files can be created in `dir`, or in the closest of its
+/// ancestors which exists, if it doesn't exist yet.
+fn is_dir_writable(dir: &Path) -> bool {
+    match dir.ancestors().find(|ancestor| ancestor.exists()) {
+        Some(ancestor) => ancestor.is_dir() && tempfile::tempfile_in(ancestor).is_ok(),
+        None => false,
+    }
+}
+
+/// Check that `init` will be able to write everything it needs to, so that it
+/// can fail before modifying anything. Returns a description of the problem,
+/// if any.
+#[instrument]
+fn find_init_problem(repo: &Repo, hooks_dir: &Path) -> eyre::Result<Option<String>> {
+    if !is_dir_writable(repo.get_path()) {
+        return Ok(Some(format!(
+            "Repository directory is not writable: {}",
+            repo.get_path().to_string_lossy()
+        )));
+    }
+
+    for hook_path in get_hook_paths(repo, hooks_dir)? {
+        let hook_dir = hook_path
+            .parent()
+            .ok_or_else(|| eyre::eyre!("No parent for hook path {:?}", hook_path))?;
+        if !is_dir_writable(hook_dir) {
+            return Ok(Some(format!(
+                "Hooks directory is not writable: {}",
+                hook_dir.to_string_lossy()
+            )));
+        }
+        if hook_path.exists() && !hook_path.is_file() {
+            return Ok(Some(format!(
+                "Hook is not a regular file: {}",
+                hook_path.to_string_lossy()
+            )));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Signatures of hooks installed by other tools, which may conflict with the
+/// `git-branchless` section: the name of the tool, the text identifying its
+/// hooks, and what to do about it.
+const KNOWN_HOOK_TOOLS: &[(&str, &str, &str)] = &[
+    (
+        "git-lfs",
+        "command -v git-lfs",
+        "running `git lfs install` or `git lfs update --force` may remove the git-branchless section; run `git branchless init --check` afterwards",
+    ),
+    (
+        "husky",
+        "husky.sh",
+        "husky regenerates its hooks on install, which removes the git-branchless section; run `git branchless init --check` afterwards",
+    ),
+    (
+        "pre-commit",
+        "File generated by pre-commit",
+        "running `pre-commit install` may remove the git-branchless section; run `git branchless init --check` afterwards",
+    ),
+];
+
+/// Find the first line of an existing hook which would exit the script before
+/// the `git-branchless` section is reached, if placed at `hook_position`.
+fn find_early_exit(contents: &str, hook_position: HookPosition) -> Option<&str> {
+    match hook_position {
+        HookPosition::Prepend => None,
+        HookPosition::Append => contents
+            .lines()
+            .take_while(|line| *line != UPDATE_MARKER_START)
+            .map(|line| line.trim())
+            .find(|line| line == &"exit" || line.starts_with("exit ") || line.starts_with("exec ")),
+    }
+}
+
+/// Scan the existing hooks in `hooks_dir` for anything which would prevent the
+/// `git-branchless` section from running once installed, such as hooks managed
+/// by other tools or early `exit` statements. Returns a warning describing each
+/// problem and how to fix it.
+#[instrument]
+fn find_hook_conflicts(
+    repo: &Repo,
+    hooks_dir: &Path,
+    hook_position: HookPosition,
+) -> eyre::Result<Vec<String>> {
+    let mut conflicts = Vec::new();
+    for (hook_type, _hook_script) in ALL_HOOKS {
+        let path = match determine_hook_path(repo, hooks_dir, hook_type)? {
+            Hook::RegularHook { path, .. } => path,
+            // Multihooks are each run separately, so they can't interfere.
+            Hook::MultiHook { .. } => continue,
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).wrap_err_with(|| format!("Reading hook {:?}", path)),
+        };
+
+        for (tool_name, signature, remedy) in KNOWN_HOOK_TOOLS {
+            if contents.contains(signature) {
+                conflicts.push(format!(
+                    "your {} hook is managed by {}; {}",
+                    hook_type, tool_name, remedy
+                ));
+            }
+        }
+        if let Some(line) = find_early_exit(&contents, hook_position) {
+            conflicts.push(format!(
+                "your {} hook exits before the branchless section (at `{}`); move the branchless section above the exit or re-run init with --hook-position=prepend",
+                hook_type, line
+            ));
+        }
+    }
+    Ok(conflicts)
+}
+
+/// The files which `init` may modify, as they were before it modified any of
+/// them, so that a failed initialization can be rolled back instead of leaving
+/// `git-branchless` partially installed.
+#[derive(Debug)]
+struct InitSnapshot {
+    /// The original contents of each file, or `None` if it didn't exist.
+    files: Vec<(PathBuf, Option<Vec<u8>>)>,
+
+    /// The directories which didn't exist, and so are deleted entirely.
+    new_dirs: Vec<PathBuf>,
+}
+
+impl InitSnapshot {
+    #[instrument]
+    fn take(repo: &Repo, hooks_dir: &Path) -> eyre::Result<Self> {
+        let mut paths = vec![repo.get_path().join("config"), repo.get_config_path()];
+        paths.extend(get_hook_paths(repo, hooks_dir)?);
+
+        let mut files = Vec::new();
+        let mut new_dirs: Vec<PathBuf> = Vec::new();
+        let mut add_new_dir = |dir: &Path| {
+            let outermost_new_dir = dir.ancestors().take_while(|dir| !dir.exists()).last();
+            if let Some(new_dir) = outermost_new_dir {
+                if !new_dirs.iter().any(|dir| new_dir.starts_with(dir)) {
+                    new_dirs.push(new_dir.to_owned());
+                }
+            }
+        };
+        add_new_dir(&repo.get_man_dir());
+        for path in paths {
+            let contents = match std::fs::read(&path) {
+                Ok(contents) => Some(contents),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err).wrap_err_with(|| format!("Reading {:?}", path)),
+            };
+            if let Some(parent) = path.parent() {
+                add_new_dir(parent);
+            }
+            files.push((path, contents));
+        }
+        Ok(InitSnapshot { files, new_dirs })
+    }
+
+    #[instrument]
+    fn restore(&self) -> eyre::Result<()> {
+        for (path, contents) in &self.files {
+            let result = match contents {
+                Some(contents) => std::fs::write(path, contents),
+                None => match std::fs::remove_file(path) {
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                    result => result,
+                },
+            };
+            result.wrap_err_with(|| format!("Restoring {:?}", path))?;
+        }
+        for dir in &self.new_dirs {
+            match std::fs::remove_dir_all(dir) {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                result => result.wrap_err_with(|| format!("Deleting {:?}", dir))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the configuration, hooks, aliases, and man-pages for `init`, and
+/// import the existing history.
+#[instrument(skip(in_))]
+fn install(
+    in_: &mut impl BufRead,
+    effects: &Effects,
+    repo: &mut Repo,
+    git_run_info: &GitRunInfo,
+    main_branch_names: &[String],
+    seed_depth: Option<usize>,
+    hooks_dir: Option<HooksDirSetting>,
+    hook_position: HookPosition,
+) -> eyre::Result<()> {
+    fix_stale_config_paths(effects, repo)?;
+    migrate_legacy_installation(effects, repo)?;
+    let readonly_config = repo.get_readonly_config()?;
+    let mut config = create_isolated_config(effects, repo, readonly_config.into_config())?;
+
+    set_configs(in_, effects, repo, &mut config, main_branch_names)?;
+    let hooks_dir = set_hooks_dir(effects, repo, &mut config, hooks_dir)?;
+    install_hooks(effects, repo, &hooks_dir, hook_position)?;
+    install_aliases(effects, repo, &mut config, git_run_info)?;
+    install_man_pages(effects, repo, &mut config)?;
+    write_repo_fingerprint(repo, &mut config)?;
+    if let Some(seed_depth) = seed_depth {
+        seed_event_log(effects, repo, seed_depth)?;
+    }
+    Ok(())
+}
+
+/// Initialize `git-branchless` in the current repo.
+///
+/// If `seed_depth` is set, also import up to that many entries from the
+/// reflogs of `HEAD` and the local branches into the event log.
+///
+/// `hooks_dir` determines where the hooks are installed if `core.hooksPath`
+/// refers to a directory outside of the repository, and `hook_position`
+/// determines where the `git-branchless` section goes in existing hooks.
+///
+/// If initialization fails partway through, then the changes made so far are
+/// rolled back, unless `keep_partial` is set.
+#[instrument]
+pub fn init(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    main_branch_names: &[String],
+    seed_depth: Option<usize>,
+    hooks_dir: Option<HooksDirSetting>,
+    hook_position: HookPosition,
+    keep_partial: bool,
+) -> eyre::Result<isize> {
+    let mut in_ = BufReader::new(stdin());
+    let mut repo = Repo::from_dir(&git_run_info.working_directory)?;
+
+    // Check this before writing anything, so that the repository isn't left
+    // partially initialized, and so that non-interactive callers such as CI
+    // fail immediately rather than blocking on the prompt.
+    if main_branch_names.is_empty()
+        && !stdin().is_terminal()
+        && detect_main_branch_name(&repo)?.is_none()
+    {
+        writeln!(
+            effects.get_error_stream(),
+            "could not auto-detect main branch; pass --main-branch <name>"
+        )?;
+        return Ok(1);
+    }
+
+    let planned_hooks_dir = get_planned_hooks_dir(&repo, hooks_dir)?;
+    if let Some(problem) = find_init_problem(&repo, &planned_hooks_dir)? {
+        writeln!(effects.get_error_stream(), "{}", problem)?;
+        writeln!(
+            effects.get_error_stream(),
+            "git-branchless was not installed, and no changes were made."
+        )?;
+        return Ok(1);
+    }
+    for conflict in find_hook_conflicts(&repo, &planned_hooks_dir, hook_position)? {
+        writeln!(
+            effects.get_output_stream(),
+            "{}: {}",
+            style("Warning").yellow().bold(),
+            conflict
+        )?;
+    }
+
+    let snapshot = InitSnapshot::take(&repo, &planned_hooks_dir)?;
+    let result = install(
+        &mut in_,
+        effects,
+        &mut repo,
+        git_run_info,
+        main_branch_names,
+        seed_depth,
+        hooks_dir,
+        hook_position,
+    );
+    if let Err(err) = result {
+        if keep_partial {
+            writeln!(
+                effects.get_error_stream(),
+                "Initialization failed. Keeping the partial installation, since --keep-partial was passed."
+            )?;
+            writeln!(
+                effects.get_error_stream(),
+                "To remove it, run: git branchless init --uninstall"
+            )?;
+        } else {
+            writeln!(
+                effects.get_error_stream(),
+                "Initialization failed. Rolling back the changes made so far."
+            )?;
+            snapshot
+                .restore()
+                .wrap_err("Rolling back partial initialization")?;
+        }
+        return Err(err);
+    }
+
+    writeln!(
         effects.get_output_stream(),
         "{}",
         console::style("Successfully installed git-branchless.")
@@ -534,6 +2083,294 @@ pub fn init(
         "To uninstall, run: {}",
         console::style("git branchless init --uninstall").bold()
     )?;
+    Ok(0)
+}
+
+/// Reinstall the hooks used by `git-branchless` in the current repo, so that
+/// changes to which hooks are enabled take effect. Settings which refer to
+/// another copy of the repository are also regenerated, but nothing else is
+/// changed.
+#[instrument]
+pub fn update_hooks(effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    if !repo.is_branchless_initialized() {
+        writeln!(
+            effects.get_error_stream(),
+            "git-branchless has not been initialized in this repository; run `git branchless init`"
+        )?;
+        return Ok(1);
+    }
+
+    fix_stale_config_paths(effects, &repo)?;
+    install_hooks(
+        effects,
+        &repo,
+        &get_core_hooks_path(&repo)?,
+        HookPosition::Append,
+    )?;
+    if repo.get_config_path().exists() {
+        let mut config = Config::open(&repo.get_config_path())?;
+        write_repo_fingerprint(&repo, &mut config)?;
+    }
+    writeln!(
+        effects.get_output_stream(),
+        "{}",
+        console::style("Successfully updated git-branchless hooks.")
+            .green()
+            .bold()
+    )?;
+    Ok(0)
+}
+
+/// Rewrite the aliases, hooks, and config keys left behind by older versions
+/// of `git-branchless` into their current forms, and make sure that the
+/// aliases they rely on are installed.
+#[instrument]
+pub fn repair_aliases(effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    if !repo.is_branchless_initialized() {
+        writeln!(
+            effects.get_error_stream(),
+            "git-branchless has not been initialized in this repository; run `git branchless init`"
+        )?;
+        return Ok(1);
+    }
+
+    let num_migrated = migrate_legacy_installation(effects, &repo)?;
+    if repo.get_config_path().exists() {
+        let mut config = Config::open(&repo.get_config_path())?;
+        for (from, to) in ALL_ALIASES {
+            install_alias(&repo, &mut config, from, to)?;
+        }
+    }
+    if num_migrated == 0 {
+        writeln!(
+            effects.get_output_stream(),
+            "No aliases, hooks, or settings from older versions of git-branchless were found."
+        )?;
+    }
+    Ok(0)
+}
+
+/// Get the name of the `git-branchless` subcommand documented by the given man
+/// page, such as `smartlog` for `git-branchless-smartlog`, or the empty string
+/// for `git-branchless` itself. Returns `None` if the page isn't for
+/// `git-branchless`.
+///
+/// Git names the page `gitbranchless-smartlog` instead if there's no
+/// `git-branchless-smartlog` executable, so that form is accepted as well.
+fn get_man_page_subcommand_name(page: &str) -> Option<&str> {
+    let name = page
+        .strip_prefix("git-")
+        .or_else(|| page.strip_prefix("git"))?
+        .strip_prefix("branchless")?;
+    if name.is_empty() {
+        return Some(name);
+    }
+    let subcommand_name = name.strip_prefix('-')?;
+    Opts::into_app().find_subcommand(subcommand_name)?;
+    Some(subcommand_name)
+}
+
+/// Render the `--help` output for the given subcommand, or for `git-branchless`
+/// itself if the name is empty.
+fn render_help(subcommand_name: &str) -> Option<String> {
+    let mut args = vec!["git-branchless"];
+    if !subcommand_name.is_empty() {
+        args.push(subcommand_name);
+    }
+    args.push("--help");
+
+    match Opts::into_app().try_get_matches_from(args) {
+        Err(err) if err.kind == ErrorKind::DisplayHelp => Some(err.to_string()),
+        _ => None,
+    }
+}
+
+/// Show the given man page with `man`, looking in `man_dir` before the usual
+/// locations if provided.
+fn run_man(page: &str, man_dir: Option<&Path>) -> eyre::Result<isize> {
+    let mut command = std::process::Command::new("man");
+    if let Some(man_dir) = man_dir {
+        // NB: the trailing `:` indicates to `man` that it should try its
+        // normal lookup paths if the page can't be found in `man_dir`.
+        let mut manpath = man_dir.as_os_str().to_owned();
+        manpath.push(":");
+        command.env("MANPATH", manpath);
+    }
+    let exit_status = command.arg(page).status().wrap_err("Running man")?;
+    Ok(exit_status.code().unwrap_or(1).try_into()?)
+}
+
+/// Show the help for the given man page, as the man viewer installed by
+/// `install_man_pages` or `install_user_man_pages`. The man-page in `man_dir`
+/// is shown if it exists, and otherwise the `--help` output for the subcommand
+/// is printed. Pages which aren't for `git-branchless` are shown with `man`.
+#[instrument]
+pub fn show_help_for_man_page(
+    effects: &Effects,
+    man_dir: Option<&Path>,
+    page: &str,
+) -> eyre::Result<isize> {
+    let subcommand_name = match get_man_page_subcommand_name(page) {
+        Some(subcommand_name) => subcommand_name,
+        None => return run_man(page, None),
+    };
+
+    if let Some(man_dir) = man_dir {
+        let page_name = if subcommand_name.is_empty() {
+            "git-branchless".to_string()
+        } else {
+            format!("git-branchless-{}", subcommand_name)
+        };
+        let page_path = man_dir.join("man1").join(format!("{}.1", page_name));
+        if page_path.exists() {
+            return run_man(&page_name, Some(man_dir));
+        }
+    }
+
+    match render_help(subcommand_name) {
+        Some(help) => {
+            write!(effects.get_output_stream(), "{}", help)?;
+            Ok(0)
+        }
+        None => run_man(page, None),
+    }
+}
+
+/// Get the directory to install the man-pages into for the current user if
+/// none was provided: `$XDG_DATA_HOME/git-branchless/man`, where
+/// `$XDG_DATA_HOME` defaults to `~/.local/share`.
+fn get_default_user_man_dir() -> eyre::Result<PathBuf> {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(data_dir) if !data_dir.is_empty() => PathBuf::from(data_dir),
+        _ => match std::env::var_os("HOME") {
+            Some(home_dir) => PathBuf::from(home_dir).join(".local").join("share"),
+            None => eyre::bail!(
+                "Could not determine where to install the man-pages: neither XDG_DATA_HOME nor HOME is set (pass --man-dir instead)"
+            ),
+        },
+    };
+    Ok(data_dir.join("git-branchless").join("man"))
+}
+
+/// Quote the given string so that a POSIX shell treats it as a single word.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Determine whether the man viewer for the man-pages installed by
+/// `install_user_man_pages` is configured in the global configuration.
+fn is_user_man_viewer_installed() -> eyre::Result<bool> {
+    let global_config_path = match get_global_config_path() {
+        Ok(global_config_path) => global_config_path,
+        Err(_) => return Ok(false),
+    };
+    if !global_config_path.exists() {
+        return Ok(false);
+    }
+    let global_config = Config::open(&global_config_path)?;
+    let man_viewer: Option<String> = global_config.get("man.viewer")?;
+    Ok(man_viewer.as_deref() == Some("branchless"))
+}
+
+/// Install the man-pages into a directory for the current user (see
+/// `get_default_user_man_dir`), and configure `git help` to use them in the
+/// global configuration, so that they don't have to be installed into each
+/// repository. The man-pages aren't regenerated if they were already installed
+/// by this version of `git-branchless`.
+#[instrument]
+pub fn install_user_man_pages(effects: &Effects, man_dir: Option<PathBuf>) -> eyre::Result<()> {
+    let man_dir = match man_dir {
+        Some(man_dir) => man_dir,
+        None => get_default_user_man_dir()?,
+    };
+    let man_dir_str = man_dir
+        .to_str()
+        .ok_or_else(|| eyre::eyre!("Could not convert man dir to UTF-8 string: {:?}", &man_dir))?;
+
+    let did_write = write_man_pages(&man_dir)
+        .wrap_err_with(|| format!("Writing man-pages to: {:?}", &man_dir))?;
+    if did_write {
+        writeln!(
+            effects.get_output_stream(),
+            "Installed man-pages to: {}",
+            man_dir_str
+        )?;
+    } else {
+        writeln!(
+            effects.get_output_stream(),
+            "Man-pages are already up to date in: {}",
+            man_dir_str
+        )?;
+    }
+
+    let mut global_config = Config::open_global()?;
+    global_config.set(
+        "man.branchless.cmd",
+        format!(
+            "git branchless help-viewer --man-dir {}",
+            shell_quote(man_dir_str)
+        ),
+    )?;
+    global_config.set("man.viewer", "branchless")?;
+    writeln!(
+        effects.get_output_stream(),
+        "Configured the man-page viewer in: {}",
+        get_global_config_path()?.to_string_lossy()
+    )?;
+    Ok(())
+}
+
+/// Remove the man-pages installed by `install_user_man_pages`, along with the
+/// man viewer configuration. Only the files which it wrote are deleted.
+#[instrument]
+pub fn uninstall_user_man_pages(effects: &Effects, man_dir: Option<PathBuf>) -> eyre::Result<()> {
+    let man_dir = match man_dir {
+        Some(man_dir) => man_dir,
+        None => get_default_user_man_dir()?,
+    };
+
+    if is_user_man_viewer_installed()? {
+        let mut global_config = Config::open_global()?;
+        global_config.remove("man.branchless.cmd")?;
+        global_config.remove("man.viewer")?;
+        writeln!(
+            effects.get_output_stream(),
+            "Removed the man-page viewer from: {}",
+            get_global_config_path()?.to_string_lossy()
+        )?;
+    }
+
+    let man1_dir = man_dir.join("man1");
+    let entries = match std::fs::read_dir(&man1_dir) {
+        Ok(entries) => entries.collect::<Result<Vec<_>, _>>()?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err).wrap_err_with(|| format!("Reading {:?}", &man1_dir)),
+    };
+    for entry in entries {
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with("git-branchless") && file_name.ends_with(".1") {
+            std::fs::remove_file(entry.path())
+                .wrap_err_with(|| format!("Removing man-page: {:?}", entry.path()))?;
+        }
+    }
+    match std::fs::remove_file(man_dir.join(MAN_PAGES_VERSION_STAMP_FILE_NAME)) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            return Err(err).wrap_err("Removing man-pages version stamp")
+        }
+        _ => {}
+    }
+    // Leave the directories alone if they contain anything else.
+    let _ = std::fs::remove_dir(&man1_dir);
+    let _ = std::fs::remove_dir(&man_dir);
+
+    writeln!(
+        effects.get_output_stream(),
+        "Removed man-pages from: {}",
+        man_dir.to_string_lossy()
+    )?;
     Ok(())
 }
 
@@ -542,14 +2379,29 @@ pub fn init(
 pub fn uninstall(effects: &Effects) -> eyre::Result<()> {
     let repo = Repo::from_current_dir()?;
     let readonly_config = repo.get_readonly_config().wrap_err("Getting repo config")?;
+    // Determine the hooks directory before deleting the isolated config, since
+    // it may override `core.hooksPath` (see `set_hooks_dir`).
+    let hooks_dir = get_core_hooks_path(&repo)?;
     delete_isolated_config(effects, &repo, readonly_config.into_config())?;
-    uninstall_hooks(effects, &repo)?;
+    uninstall_aliases(&repo)?;
+    uninstall_hooks(effects, &repo, &hooks_dir)?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{update_between_lines, ALL_ALIASES, UPDATE_MARKER_END, UPDATE_MARKER_START};
+    use std::sync::{Arc, Mutex};
+
+    use crate::core::effects::Effects;
+    use crate::core::formatting::Glyphs;
+    use crate::git::{Config, ConfigRead};
+    use crate::opts::HookPosition;
+    use crate::testing::{make_git, GitInitOptions};
+
+    use super::{
+        are_markers_intact, get_hook_paths, set_configs, update_between_lines, InitSnapshot,
+        ALL_ALIASES, UPDATE_MARKER_END, UPDATE_MARKER_START,
+    };
 
     #[test]
     fn test_update_between_lines() {
@@ -581,10 +2433,51 @@ goodbye, world
                 "\
 contents 2
 contents 3
-"
+",
+                HookPosition::Append,
             ),
             expected
-        )
+        );
+
+        let expected = format!(
+            "\
+{}
+contents 2
+{}
+hello, world
+goodbye, world
+",
+            UPDATE_MARKER_START, UPDATE_MARKER_END
+        );
+        assert_eq!(
+            update_between_lines(&input, "contents 2\n", HookPosition::Prepend),
+            expected
+        );
+        assert_eq!(
+            update_between_lines(
+                &format!("#!/bin/sh\n{}", input),
+                "contents 2\n",
+                HookPosition::Prepend
+            ),
+            format!("#!/bin/sh\n{}", expected)
+        );
+    }
+
+    #[test]
+    fn test_are_markers_intact() {
+        let intact = format!(
+            "#!/bin/sh\n{}\ncontents\n{}\n",
+            UPDATE_MARKER_START, UPDATE_MARKER_END
+        );
+        assert!(are_markers_intact(&intact));
+
+        let missing_end = format!("#!/bin/sh\n{}\ncontents\n", UPDATE_MARKER_START);
+        assert!(!are_markers_intact(&missing_end));
+
+        let duplicated = format!("{}{}", intact, intact);
+        assert!(!are_markers_intact(&duplicated));
+
+        assert!(!are_markers_intact("#!/bin/sh\ncontents\n"));
     }
 
     #[test]
@@ -610,4 +2503,209 @@ contents 3
                 .success();
         }
     }
+
+    /// Run `set_configs` with the given `--main-branch` arguments and prompt
+    /// input, returning the main branch setting which was written.
+    fn set_main_branch_config(
+        git: &crate::testing::Git,
+        main_branch_names: &[&str],
+        input: &str,
+    ) -> eyre::Result<Option<String>> {
+        let effects = Effects::new_suppress_for_test(Glyphs::text());
+        let repo = git.get_repo()?;
+        let mut config = Config::open(&git.repo_path.join("test-config"))?;
+        let main_branch_names: Vec<String> = main_branch_names
+            .iter()
+            .map(|main_branch_name| main_branch_name.to_string())
+            .collect();
+        set_configs(
+            &mut input.as_bytes(),
+            &effects,
+            &repo,
+            &mut config,
+            &main_branch_names,
+        )?;
+        config.get("branchless.core.mainBranch")
+    }
+
+    #[test]
+    fn test_main_branch_name_forms() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo_with_options(&GitInitOptions {
+            run_branchless_init: false,
+            ..Default::default()
+        })?;
+        git.run(&["update-ref", "refs/remotes/origin/main", "HEAD"])?;
+
+        assert_eq!(
+            set_main_branch_config(&git, &["master"], "")?,
+            Some("master".to_string())
+        );
+        assert_eq!(
+            set_main_branch_config(&git, &["refs/heads/master"], "")?,
+            Some("master".to_string())
+        );
+        assert_eq!(
+            set_main_branch_config(&git, &["origin/main"], "")?,
+            Some("origin/main".to_string())
+        );
+        assert_eq!(
+            set_main_branch_config(&git, &["refs/remotes/origin/main"], "")?,
+            Some("origin/main".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_main_branch_name_prompt() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo_with_options(&GitInitOptions {
+            run_branchless_init: false,
+            ..Default::default()
+        })?;
+        git.run(&["branch", "-m", "master", "bespoke"])?;
+        git.run(&["update-ref", "refs/remotes/upstream/trunk", "HEAD"])?;
+
+        assert_eq!(
+            set_main_branch_config(&git, &[], "refs/heads/bespoke\n")?,
+            Some("bespoke".to_string())
+        );
+        assert_eq!(
+            set_main_branch_config(&git, &[], "refs/remotes/upstream/trunk\n")?,
+            Some("upstream/trunk".to_string())
+        );
+
+        let err = set_main_branch_config(&git, &[], "nonexistent\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"The main branch "nonexistent" could not be found"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_main_branch_name_prompt_selection() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo_with_options(&GitInitOptions {
+            run_branchless_init: false,
+            ..Default::default()
+        })?;
+        git.run(&["branch", "-m", "master", "bespoke"])?;
+        git.run(&["branch", "another"])?;
+        git.run(&["update-ref", "refs/remotes/upstream/trunk", "HEAD"])?;
+
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+        let effects = Effects::new_from_buffer_for_test(Glyphs::text(), &stdout, &stderr);
+        let repo = git.get_repo()?;
+        let mut config = Config::open(&git.repo_path.join("test-config"))?;
+        set_configs(&mut "\n".as_bytes(), &effects, &repo, &mut config, &[])?;
+        assert_eq!(
+            config.get::<String, _>("branchless.core.mainBranch")?,
+            Some("bespoke".to_string())
+        );
+        let stdout = String::from_utf8(stdout.lock().unwrap().clone())?;
+        insta::assert_snapshot!(stdout, @r###"
+        Your main branch name could not be auto-detected!
+        Examples of a main branch: master, main, trunk, etc.
+        See https://github.com/arxanas/git-branchless/wiki/Concepts#main-branch
+        Branches in this repository:
+        > 1) bespoke
+          2) another
+          3) upstream/trunk
+        Enter the name of your main branch [1-3, or a name; default 1]: 
+        "###);
+
+        assert_eq!(
+            set_main_branch_config(&git, &[], "2\n")?,
+            Some("another".to_string())
+        );
+        assert_eq!(
+            set_main_branch_config(&git, &[], "3\n")?,
+            Some("upstream/trunk".to_string())
+        );
+        assert_eq!(
+            set_main_branch_config(&git, &[], "another\n")?,
+            Some("another".to_string())
+        );
+
+        let err = set_main_branch_config(&git, &[], "").unwrap_err();
+        assert_eq!(err.to_string(), "No main branch name provided");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_main_branch_name_nonexistent() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo_with_options(&GitInitOptions {
+            run_branchless_init: false,
+            ..Default::default()
+        })?;
+
+        for main_branch_name in [
+            "nonexistent",
+            "refs/heads/nonexistent",
+            "origin/master",
+            "refs/remotes/origin/master",
+        ] {
+            let err = set_main_branch_config(&git, &[main_branch_name], "").unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("The main branch {:?} could not be found", main_branch_name)
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_main_branch_name_unborn_head() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo_with_options(&GitInitOptions {
+            make_initial_commit: false,
+            run_branchless_init: false,
+        })?;
+
+        assert_eq!(
+            set_main_branch_config(&git, &["refs/heads/main"], "")?,
+            Some("main".to_string())
+        );
+        assert!(set_main_branch_config(&git, &["refs/remotes/origin/main"], "").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_snapshot_restore() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo_with_options(&GitInitOptions {
+            run_branchless_init: false,
+            ..Default::default()
+        })?;
+        let repo = git.get_repo()?;
+        let hooks_dir = repo.get_path().join("hooks");
+        let config_path = repo.get_path().join("config");
+        let post_commit_path = hooks_dir.join("post-commit");
+        std::fs::write(&post_commit_path, "existing hook")?;
+        let original_config = std::fs::read_to_string(&config_path)?;
+
+        let snapshot = InitSnapshot::take(&repo, &hooks_dir)?;
+        std::fs::write(&config_path, "modified config")?;
+        for hook_path in get_hook_paths(&repo, &hooks_dir)? {
+            std::fs::write(hook_path, "new hook")?;
+        }
+        std::fs::create_dir_all(repo.get_man_dir())?;
+        std::fs::write(repo.get_config_path(), "isolated config")?;
+        snapshot.restore()?;
+
+        assert_eq!(std::fs::read_to_string(&config_path)?, original_config);
+        assert_eq!(std::fs::read_to_string(&post_commit_path)?, "existing hook");
+        assert!(!hooks_dir.join("post-checkout").exists());
+        assert!(!repo.get_path().join("branchless").exists());
+
+        Ok(())
+    }
 }
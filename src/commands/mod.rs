@@ -1,42 +1,129 @@
 //! Sub-commands of `git-branchless`.
 
+pub mod absorb;
 pub mod amend;
+pub mod archive;
+pub mod bug_report;
+pub mod diff;
+pub mod export;
 pub mod gc;
 pub mod hide;
 pub mod hooks;
 pub mod init;
 pub mod r#move;
 pub mod navigation;
+pub mod obslog;
+pub mod query;
+pub mod record;
+pub mod repair;
 pub mod restack;
+pub mod reword;
 pub mod smartlog;
+pub mod split;
+pub mod squash;
+pub mod submit;
+pub mod sync;
+pub mod test;
 pub mod undo;
 pub mod wrap;
 
 use std::any::Any;
 use std::convert::TryInto;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+use std::io::{stdin, IsTerminal};
 use std::path::PathBuf;
 use std::time::SystemTime;
 
-use clap::Parser;
+use clap::{ArgEnum, Parser};
 use eyre::Context;
 use itertools::Itertools;
+use serde_json::json;
+use tracing::warn;
 use tracing_chrome::ChromeLayerBuilder;
 use tracing_error::ErrorLayer;
 use tracing_subscriber::fmt as tracing_fmt;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
+use crate::core::config::{
+    get_hook_enabled, get_hooks_quiet, get_initialize_on_demand, get_output_color,
+    get_output_format_from_env, get_output_glyphs, get_progress_log_enabled,
+    BRANCHLESS_AUTO_SMARTLOG_ENV_VAR, BRANCHLESS_OUTPUT_ENV_VAR, BRANCHLESS_QUIET_ENV_VAR,
+};
 use crate::core::effects::Effects;
+use crate::core::eventlog::is_database_busy_error;
 use crate::core::formatting::Glyphs;
+use crate::core::lock::acquire_operation_lock;
 use crate::git::GitRunInfo;
 use crate::git::NonZeroOid;
+use crate::git::Repo;
+use crate::opts::ArchiveCommand;
 use crate::opts::ColorSetting;
 use crate::opts::Command;
+use crate::opts::GlyphsSetting;
+use crate::opts::HookPosition;
 use crate::opts::Opts;
+use crate::opts::OutputFormat;
 use crate::opts::WrappedCommand;
 
-use self::smartlog::SmartlogOptions;
+use self::export::ExportOptions;
+use self::smartlog::{should_show_stack_smartlog, SmartlogOptions};
+
+/// Parse an output setting from the config, ignoring invalid values.
+fn parse_output_setting<T: ArgEnum>(key: &str, value: Option<String>) -> Option<T> {
+    let value = value?;
+    match T::from_str(&value, true) {
+        Ok(setting) => Some(setting),
+        Err(err) => {
+            warn!(?key, ?value, ?err, "Invalid output setting");
+            None
+        }
+    }
+}
+
+/// Determine the `Glyphs` to use for output. Settings passed on the command
+/// line take precedence over the `branchless.output.color` and
+/// `branchless.output.glyphs` config settings.
+fn make_glyphs(color: Option<ColorSetting>, glyphs: Option<GlyphsSetting>) -> eyre::Result<Glyphs> {
+    let (color, glyphs) = match (color, glyphs) {
+        (Some(color), Some(glyphs)) => (color, glyphs),
+        (color, glyphs) => {
+            // The config can only be read from inside a repository.
+            let (config_color, config_glyphs) = match Repo::from_current_dir() {
+                Ok(repo) => (
+                    parse_output_setting("branchless.output.color", get_output_color(&repo)?),
+                    parse_output_setting("branchless.output.glyphs", get_output_glyphs(&repo)?),
+                ),
+                Err(_) => (None, None),
+            };
+            (
+                color.or(config_color).unwrap_or(ColorSetting::Auto),
+                glyphs.or(config_glyphs).unwrap_or(GlyphsSetting::Auto),
+            )
+        }
+    };
+
+    let should_write_ansi_escape_codes = match color {
+        ColorSetting::Always => true,
+        ColorSetting::Never => false,
+        ColorSetting::Auto => Glyphs::detect_color_support(),
+    };
+    // Some output is styled with `console` directly, rather than through
+    // `Glyphs`. It does its own detection in `auto` mode.
+    if color != ColorSetting::Auto {
+        console::set_colors_enabled(should_write_ansi_escape_codes);
+        console::set_colors_enabled_stderr(should_write_ansi_escape_codes);
+    }
+
+    let glyphs = match glyphs {
+        GlyphsSetting::Unicode => Glyphs::pretty(),
+        GlyphsSetting::Ascii => Glyphs::text(),
+        GlyphsSetting::Auto if should_write_ansi_escape_codes => Glyphs::pretty(),
+        GlyphsSetting::Auto => Glyphs::text(),
+    };
+    Ok(glyphs.with_ansi_escape_codes(should_write_ansi_escape_codes))
+}
 
 fn rewrite_args(args: Vec<OsString>) -> Vec<OsString> {
     let first_arg = match args.first() {
@@ -74,15 +161,26 @@ fn rewrite_args(args: Vec<OsString>) -> Vec<OsString> {
 /// Wrapper function for `main` to ensure that `Drop` is called for local
 /// variables, since `std::process::exit` will skip them.
 fn do_main_and_drop_locals() -> eyre::Result<i32> {
-    let _tracing_guard = install_tracing();
-
     let args = rewrite_args(std::env::args_os().collect_vec());
     let Opts {
         working_directory,
         command,
         color,
+        glyphs,
+        output,
+        smartlog,
+        no_smartlog,
+        quiet,
+        verbose,
+        yes,
+        force_unlock,
     } = Opts::parse_from(args);
-    if let Some(working_directory) = working_directory {
+    let _tracing_guard = install_tracing(verbose);
+
+    if !working_directory.is_empty() {
+        let working_directory = working_directory
+            .iter()
+            .fold(PathBuf::new(), |acc, path| acc.join(path));
         std::env::set_current_dir(&working_directory).wrap_err_with(|| {
             format!(
                 "Could not set working directory to: {:?}",
@@ -91,38 +189,446 @@ fn do_main_and_drop_locals() -> eyre::Result<i32> {
         })?;
     }
 
+    if smartlog || no_smartlog {
+        std::env::set_var(
+            BRANCHLESS_AUTO_SMARTLOG_ENV_VAR,
+            if smartlog { "1" } else { "0" },
+        );
+    }
+
     let path_to_git = std::env::var_os("PATH_TO_GIT").unwrap_or_else(|| OsString::from("git"));
     let path_to_git = PathBuf::from(&path_to_git);
-    let git_run_info = GitRunInfo {
+    let mut git_run_info = GitRunInfo {
         path_to_git,
         working_directory: std::env::current_dir()?,
         env: std::env::vars_os().collect(),
     };
+    if quiet {
+        // Also silence the hooks invoked by Git subprocesses.
+        git_run_info
+            .env
+            .insert(BRANCHLESS_QUIET_ENV_VAR.into(), "1".into());
+    }
+
+    let is_hook = is_hook_command(&command);
+    if is_hook && !working_directory.is_empty() {
+        // Hooks normally open the repository specified by the environment
+        // variables which Git sets for them, but an explicitly-passed `-C`
+        // should take precedence.
+        for var in ["GIT_DIR", "GIT_WORK_TREE", "GIT_COMMON_DIR"] {
+            std::env::remove_var(var);
+            git_run_info.env.remove(OsStr::new(var));
+        }
+    }
 
-    let color = match color {
-        Some(ColorSetting::Always) => Glyphs::pretty(),
-        Some(ColorSetting::Never) => Glyphs::text(),
-        Some(ColorSetting::Auto) | None => Glyphs::detect(),
+    let output = output
+        .or_else(|| parse_output_setting(BRANCHLESS_OUTPUT_ENV_VAR, get_output_format_from_env()))
+        .unwrap_or(OutputFormat::Text);
+    let effects = match output {
+        OutputFormat::Json => {
+            // Subprocesses (such as hooks invoked by `git rebase`) write
+            // human-readable output, which is then wrapped into message
+            // records, rather than nesting JSON records inside messages.
+            git_run_info
+                .env
+                .remove(OsStr::new(BRANCHLESS_OUTPUT_ENV_VAR));
+            Effects::new_json(Glyphs::text())
+        }
+        OutputFormat::Text => {
+            let glyphs = make_glyphs(color, glyphs)?;
+            if quiet {
+                Effects::new_without_progress(glyphs).suppress_output()
+            } else if is_hook || !get_progress_log_enabled() {
+                // Progress lines logged by hooks would be interleaved with the
+                // output of the Git command which invoked them.
+                Effects::new_without_progress_log(glyphs)
+            } else {
+                Effects::new(glyphs)
+            }
+        }
+    };
+    let effects = if is_hook {
+        let repo = Repo::from_env()?;
+        // The hooks may still be installed after an incomplete uninstall, or
+        // be shared across repositories via `core.hooksPath`. Don't do
+        // anything (and particularly don't create the database) in that case.
+        if !repo.is_branchless_initialized() && !get_initialize_on_demand() {
+            return Ok(0);
+        }
+        if let Some(hook_type) = get_git_hook_type(&command) {
+            if !get_hook_enabled(&repo, hook_type)? {
+                return Ok(0);
+            }
+        }
+        if get_hooks_quiet(&repo)? {
+            effects.suppress_output()
+        } else {
+            effects
+        }
+    } else {
+        effects
     };
-    let effects = Effects::new(color);
 
-    let exit_code = match command {
-        Command::Amend { move_options } => amend::amend(&effects, &git_run_info, &move_options)?,
+    if command_requires_initialization(&command)
+        && !ensure_initialized(&effects, &git_run_info, yes)?
+    {
+        return Ok(1);
+    }
 
-        Command::Checkout { initial_query } => {
-            navigation::checkout(&effects, &git_run_info, &initial_query)?
-        }
+    let _operation_lock = match get_operation_lock_command_name(&command) {
+        Some(command_name) => match Repo::from_dir(&git_run_info.working_directory) {
+            // Don't create the `.git/branchless` directory for commands which
+            // can run without git-branchless being initialized.
+            Ok(repo) if repo.is_branchless_initialized() => {
+                match acquire_operation_lock(&effects, &repo, command_name, force_unlock)? {
+                    Some(operation_lock) => Some(operation_lock),
+                    None => return Ok(1),
+                }
+            }
+            Ok(_) | Err(_) => None,
+        },
+        None => None,
+    };
 
-        Command::Gc | Command::HookPreAutoGc => {
-            gc::gc(&effects)?;
+    let exit_code = match run_command(&effects, git_run_info, command, quiet) {
+        Err(err) if is_hook && is_database_busy_error(&err) => {
+            // Another process is holding the event log database (e.g. a
+            // concurrent Git operation in another terminal). Don't fail the
+            // Git operation which invoked the hook because of it.
+            writeln!(
+                effects.get_error_stream(),
+                "branchless: the event log database is busy (another Git command may be running); some events may not have been recorded"
+            )?;
             0
         }
+        Err(err) if effects.is_json_output() => {
+            effects.write_error_record(&err);
+            return Ok(1);
+        }
+        result => result?,
+    };
+
+    let exit_code: i32 = exit_code.try_into()?;
+    if effects.is_json_output() {
+        if let Ok(repo) = Repo::from_current_dir() {
+            let head_oid = repo.get_head_info()?.oid;
+            effects.set_result_field("head_oid", json!(head_oid.map(|oid| oid.to_string())));
+        }
+        effects.write_result_record(exit_code);
+    }
+    Ok(exit_code)
+}
+
+fn is_hook_command(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::HookDetectEmptyCommit { .. }
+            | Command::HookDropMergeCommit { .. }
+            | Command::HookPostCheckout { .. }
+            | Command::HookPostApplypatch
+            | Command::HookPostCommit
+            | Command::HookPostMerge { .. }
+            | Command::HookPostRewrite { .. }
+            | Command::HookPreAutoGc
+            | Command::HookReferenceTransaction { .. }
+            | Command::HookRegisterExtraPostRewriteHook
+            | Command::HookSkipUpstreamAppliedCommit { .. }
+    )
+}
+
+/// Get the type of the Git hook which invokes the given command, such as
+/// `post-commit`, if any. The other hook commands are invoked by
+/// git-branchless itself, such as from the rebase plans it generates.
+fn get_git_hook_type(command: &Command) -> Option<&'static str> {
+    match command {
+        Command::HookPostCheckout { .. } => Some("post-checkout"),
+        Command::HookPostApplypatch => Some("post-applypatch"),
+        Command::HookPostCommit => Some("post-commit"),
+        Command::HookPostMerge { .. } => Some("post-merge"),
+        Command::HookPostRewrite { .. } => Some("post-rewrite"),
+        Command::HookPreAutoGc => Some("pre-auto-gc"),
+        Command::HookReferenceTransaction { .. } => Some("reference-transaction"),
+        _ => None,
+    }
+}
+
+/// Whether the given command needs git-branchless to have been initialized in
+/// the repository before it can run. The exempt commands either set up the
+/// repository themselves, only print information (`bug-report` and
+/// `help-viewer`), support being run standalone (`move`), or (for hooks) decide
+/// on their own to do nothing.
+fn command_requires_initialization(command: &Command) -> bool {
+    let is_exempt = matches!(
+        command,
+        Command::Init { .. }
+            | Command::BugReport { .. }
+            | Command::HelpViewer { .. }
+            | Command::Move { .. }
+    ) || is_hook_command(command);
+    !is_exempt
+}
+
+/// The name of the given command for the operation lock (see the `lock`
+/// module), if it changes commits, references, or the event log and so must
+/// not run concurrently with another such command. Read-only commands and
+/// hooks don't take the lock. Neither does `git branchless init`, which may
+/// remove the directory containing the lock file, nor `git branchless wrap`,
+/// since the Git command it wraps may itself be a locking command.
+fn get_operation_lock_command_name(command: &Command) -> Option<&'static str> {
+    match command {
+        Command::Absorb { .. } => Some("git absorb"),
+        Command::Amend { .. } => Some("git amend"),
+        Command::Archive {
+            command: ArchiveCommand::Restore { .. },
+        } => Some("git branchless archive restore"),
+        Command::Archive {
+            command: ArchiveCommand::Delete { .. },
+        } => Some("git branchless archive delete"),
+        Command::Checkout { .. } => Some("git checkout"),
+        Command::Gc { .. } => Some("git branchless gc"),
+        Command::Hide { .. } => Some("git hide"),
+        Command::Move { .. } => Some("git move"),
+        Command::Next { .. } => Some("git next"),
+        Command::Prev { .. } => Some("git prev"),
+        Command::Record { .. } => Some("git record"),
+        Command::Redo { .. } => Some("git redo"),
+        Command::Repair { .. } => Some("git branchless repair"),
+        Command::Restack { .. } => Some("git restack"),
+        Command::Reword { .. } => Some("git reword"),
+        Command::Split { .. } => Some("git split"),
+        Command::Squash { .. } => Some("git squash"),
+        Command::Submit { .. } => Some("git submit"),
+        Command::Sync { .. } => Some("git sync"),
+        Command::Test { .. } => Some("git test"),
+        Command::Undo { .. } => Some("git undo"),
+        Command::Unhide { .. } => Some("git unhide"),
+
+        Command::Archive {
+            command: ArchiveCommand::List | ArchiveCommand::Show { .. },
+        }
+        | Command::BugReport { .. }
+        | Command::Diff { .. }
+        | Command::Export { .. }
+        | Command::HelpViewer { .. }
+        | Command::Init { .. }
+        | Command::Obslog { .. }
+        | Command::Query { .. }
+        | Command::Smartlog { .. }
+        | Command::Wrap { .. } => None,
+
+        Command::HookDetectEmptyCommit { .. }
+        | Command::HookDropMergeCommit { .. }
+        | Command::HookPreAutoGc
+        | Command::HookPostApplypatch
+        | Command::HookPostCheckout { .. }
+        | Command::HookPostCommit
+        | Command::HookPostMerge { .. }
+        | Command::HookPostRewrite { .. }
+        | Command::HookReferenceTransaction { .. }
+        | Command::HookRegisterExtraPostRewriteHook
+        | Command::HookSkipUpstreamAppliedCommit { .. } => None,
+    }
+}
+
+/// Check that git-branchless has been initialized in the current repository,
+/// offering to initialize it if not. Returns `false` if the command should not
+/// proceed.
+fn ensure_initialized(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    skip_confirmation: bool,
+) -> eyre::Result<bool> {
+    let repo = match Repo::from_dir(&git_run_info.working_directory) {
+        Ok(repo) => repo,
+        // Not in a repository at all. Let the command itself report that.
+        Err(_) => return Ok(true),
+    };
+    if repo.is_branchless_initialized() {
+        return Ok(true);
+    }
+
+    writeln!(
+        effects.get_error_stream(),
+        "git-branchless has not been initialized in this repository; run `git branchless init`"
+    )?;
+    let confirmed = skip_confirmation
+        || (stdin().is_terminal() && {
+            write!(
+                effects.get_error_stream(),
+                "Initialize git-branchless now? [yN] "
+            )?;
+            let mut user_input = String::new();
+            match stdin().read_line(&mut user_input) {
+                Ok(_size) => {
+                    let user_input = user_input.trim();
+                    user_input == "y" || user_input == "Y"
+                }
+                Err(_) => false,
+            }
+        });
+    if !confirmed {
+        return Ok(false);
+    }
+
+    let exit_code = init::init(
+        effects,
+        git_run_info,
+        &[],
+        Some(init::DEFAULT_SEED_DEPTH),
+        None,
+        HookPosition::Append,
+        false,
+    )?;
+    Ok(exit_code == 0)
+}
+
+fn run_command(
+    effects: &Effects,
+    git_run_info: GitRunInfo,
+    command: Command,
+    quiet: bool,
+) -> eyre::Result<isize> {
+    // Navigation and rewrite commands finish by displaying a compact smartlog
+    // of the current stack, if enabled.
+    let stack_smartlog_git_run_info = match command {
+        Command::Absorb { .. }
+        | Command::Amend { .. }
+        | Command::Checkout { .. }
+        | Command::Move { .. }
+        | Command::Next { .. }
+        | Command::Prev { .. }
+        | Command::Restack { .. }
+        | Command::Reword { .. }
+        | Command::Split { .. }
+        | Command::Squash { .. } => Some(git_run_info.clone()),
+        _ => None,
+    };
+
+    let exit_code = match command {
+        Command::Absorb { staged, dry_run } => {
+            absorb::absorb(effects, &git_run_info, staged, dry_run)?
+        }
+
+        Command::Amend {
+            messages,
+            edit,
+            staged,
+            include_untracked,
+            pathspecs,
+            no_restack,
+            commit_options,
+            move_options,
+        } => amend::amend(
+            effects,
+            &git_run_info,
+            messages,
+            edit,
+            staged,
+            include_untracked,
+            pathspecs,
+            no_restack,
+            &commit_options,
+            &move_options,
+        )?,
 
-        Command::Hide { commits, recursive } => hide::hide(&effects, commits, recursive)?,
+        Command::Archive { command } => match command {
+            ArchiveCommand::List => archive::list(effects, &git_run_info)?,
+            ArchiveCommand::Show { name } => archive::show(effects, &git_run_info, &name)?,
+            ArchiveCommand::Restore { name } => archive::restore(effects, &git_run_info, &name)?,
+            ArchiveCommand::Delete { name } => archive::delete(effects, &git_run_info, &name)?,
+        },
+
+        Command::BugReport {
+            num_transactions,
+            include_messages,
+        } => bug_report::bug_report(effects, &git_run_info, num_transactions, include_messages)?,
+
+        Command::Checkout {
+            initial_query,
+            show_hidden_commits,
+            merge,
+            branch_name,
+            force_branch_name,
+        } => match (branch_name, force_branch_name) {
+            (Some(branch_name), _) => navigation::checkout_new_branch(
+                effects,
+                &git_run_info,
+                &initial_query,
+                &branch_name,
+                false,
+                merge,
+            )?,
+            (None, Some(branch_name)) => navigation::checkout_new_branch(
+                effects,
+                &git_run_info,
+                &initial_query,
+                &branch_name,
+                true,
+                merge,
+            )?,
+            (None, None) => navigation::checkout(
+                effects,
+                &git_run_info,
+                &initial_query,
+                show_hidden_commits,
+                merge,
+            )?,
+        },
+
+        Command::Diff {
+            revset,
+            stat,
+            name_only,
+        } => diff::diff(effects, &git_run_info, revset, stat, name_only)?,
+
+        Command::Export {
+            revsets,
+            output_directory,
+            mbox,
+            stdout,
+            reroll_count,
+            cover_letter,
+        } => export::export(
+            effects,
+            &git_run_info,
+            revsets,
+            &ExportOptions {
+                output_directory,
+                mbox,
+                stdout,
+                reroll_count,
+                cover_letter,
+            },
+        )?,
+
+        Command::Gc {
+            dry_run,
+            prune_events,
+            before,
+        } => gc::gc(
+            effects,
+            &git_run_info,
+            dry_run,
+            prune_events,
+            before.as_deref(),
+        )?,
+
+        Command::HookPreAutoGc => gc::auto_gc(effects, &git_run_info)?,
+
+        Command::Hide {
+            commits,
+            recursive,
+            force_rewrite_public,
+            archive,
+        } => hide::hide(effects, commits, recursive, force_rewrite_public, archive)?,
+
+        Command::HelpViewer { man_dir, page } => {
+            init::show_help_for_man_page(effects, man_dir.as_deref(), &page)?
+        }
 
         Command::HookDetectEmptyCommit { old_commit_oid } => {
             let old_commit_oid: NonZeroOid = old_commit_oid.parse()?;
-            hooks::hook_drop_commit_if_empty(&effects, old_commit_oid)?;
+            hooks::hook_drop_commit_if_empty(effects, old_commit_oid)?;
             0
         }
 
@@ -132,7 +638,7 @@ fn do_main_and_drop_locals() -> eyre::Result<i32> {
             is_branch_checkout,
         } => {
             hooks::hook_post_checkout(
-                &effects,
+                effects,
                 &previous_commit,
                 &current_commit,
                 is_branch_checkout,
@@ -140,23 +646,28 @@ fn do_main_and_drop_locals() -> eyre::Result<i32> {
             0
         }
 
+        Command::HookPostApplypatch => {
+            hooks::hook_post_applypatch(effects)?;
+            0
+        }
+
         Command::HookPostCommit => {
-            hooks::hook_post_commit(&effects)?;
+            hooks::hook_post_commit(effects)?;
             0
         }
 
         Command::HookPostMerge { is_squash_merge } => {
-            hooks::hook_post_merge(&effects, is_squash_merge)?;
+            hooks::hook_post_merge(effects, is_squash_merge)?;
             0
         }
 
         Command::HookPostRewrite { rewrite_type } => {
-            hooks::hook_post_rewrite(&effects, &git_run_info, &rewrite_type)?;
+            hooks::hook_post_rewrite(effects, &git_run_info, &rewrite_type)?;
             0
         }
 
         Command::HookReferenceTransaction { transaction_state } => {
-            hooks::hook_reference_transaction(&effects, &transaction_state)?;
+            hooks::hook_reference_transaction(effects, &git_run_info, &transaction_state)?;
             0
         }
 
@@ -165,25 +676,98 @@ fn do_main_and_drop_locals() -> eyre::Result<i32> {
             0
         }
 
+        Command::HookDropMergeCommit { commit_oid } => {
+            let commit_oid: NonZeroOid = commit_oid.parse()?;
+            hooks::hook_drop_merge_commit(effects, commit_oid)?;
+            0
+        }
+
         Command::HookSkipUpstreamAppliedCommit { commit_oid } => {
             let commit_oid: NonZeroOid = commit_oid.parse()?;
-            hooks::hook_skip_upstream_applied_commit(&effects, commit_oid)?;
+            hooks::hook_skip_upstream_applied_commit(effects, commit_oid)?;
             0
         }
 
+        Command::Init {
+            check: true, fix, ..
+        } => init::check(effects, &git_run_info, fix)?,
+
+        Command::Init { update: true, .. } => init::update_hooks(effects, &git_run_info)?,
+
+        Command::Init {
+            repair_aliases: true,
+            ..
+        } => init::repair_aliases(effects, &git_run_info)?,
+
         Command::Init {
             uninstall: false,
-            main_branch_name,
+            global: true,
+            man_dir,
+            ..
         } => {
-            init::init(&effects, &git_run_info, main_branch_name.as_deref())?;
+            init::install_user_man_pages(effects, man_dir)?;
             0
         }
 
         Command::Init {
             uninstall: true,
-            main_branch_name: _,
+            global: true,
+            man_dir,
+            ..
         } => {
-            init::uninstall(&effects)?;
+            init::uninstall_user_man_pages(effects, man_dir)?;
+            0
+        }
+
+        Command::Init {
+            uninstall: false,
+            main_branch_names,
+            no_seed,
+            seed_depth,
+            hooks_dir,
+            hook_position,
+            global: false,
+            man_dir,
+            update: false,
+            repair_aliases: false,
+            keep_partial,
+            check: false,
+            fix: _,
+        } => {
+            if let Some(man_dir) = man_dir {
+                init::install_user_man_pages(effects, Some(man_dir))?;
+            }
+            let seed_depth = if no_seed { None } else { Some(seed_depth) };
+            init::init(
+                effects,
+                &git_run_info,
+                &main_branch_names,
+                seed_depth,
+                hooks_dir,
+                hook_position,
+                keep_partial,
+            )?
+        }
+
+        Command::Init {
+            uninstall: true,
+            main_branch_names: _,
+            no_seed: _,
+            seed_depth: _,
+            hooks_dir: _,
+            hook_position: _,
+            global: false,
+            man_dir,
+            update: false,
+            repair_aliases: false,
+            keep_partial: _,
+            check: false,
+            fix: _,
+        } => {
+            init::uninstall(effects)?;
+            if let Some(man_dir) = man_dir {
+                init::uninstall_user_man_pages(effects, Some(man_dir))?;
+            }
             0
         }
 
@@ -191,48 +775,219 @@ fn do_main_and_drop_locals() -> eyre::Result<i32> {
             source,
             dest,
             base,
+            exact,
+            insert,
+            continue_move,
+            abort_move,
+            dry_run,
             move_options,
-        } => r#move::r#move(&effects, &git_run_info, source, dest, base, &move_options)?,
+        } => {
+            if continue_move || abort_move {
+                r#move::resume_move(effects, &git_run_info, abort_move)?
+            } else {
+                r#move::r#move(
+                    effects,
+                    &git_run_info,
+                    source,
+                    dest,
+                    base,
+                    exact,
+                    insert,
+                    dry_run,
+                    &move_options,
+                )?
+            }
+        }
 
         Command::Next {
             traverse_commits_options,
         } => navigation::traverse_commits(
-            &effects,
+            effects,
             &git_run_info,
             navigation::Command::Next,
             &traverse_commits_options,
         )?,
 
+        Command::Obslog {
+            commit,
+            stat,
+            patch,
+        } => obslog::obslog(effects, &git_run_info, commit, stat, patch)?,
+
         Command::Prev {
             traverse_commits_options,
         } => navigation::traverse_commits(
-            &effects,
+            effects,
             &git_run_info,
             navigation::Command::Prev,
             &traverse_commits_options,
         )?,
 
+        Command::Query { revset, format } => query::query(effects, &git_run_info, revset, &format)?,
+
+        Command::Record {
+            messages,
+            staged,
+            interactive,
+            pathspecs,
+            commit_options,
+        } => record::record(
+            effects,
+            &git_run_info,
+            messages,
+            staged,
+            interactive,
+            pathspecs,
+            &commit_options,
+        )?,
+
+        Command::Redo {
+            num_undos,
+            yes,
+            force,
+        } => undo::redo(effects, &git_run_info, num_undos, yes, force)?,
+
+        Command::Repair { apply } => repair::repair(effects, &git_run_info, apply)?,
+
         Command::Restack {
             commits,
+            dry_run,
             move_options,
-        } => restack::restack(&effects, &git_run_info, commits, &move_options)?,
+        } => restack::restack(
+            effects,
+            &git_run_info,
+            commits,
+            dry_run,
+            !quiet,
+            &move_options,
+        )?,
+
+        Command::Reword {
+            commits,
+            messages,
+            force_rewrite_public,
+            commit_options,
+        } => reword::reword(
+            effects,
+            &git_run_info,
+            commits,
+            messages,
+            force_rewrite_public,
+            &commit_options,
+        )?,
 
         Command::Smartlog {
             show_hidden_commits,
+            revset,
+            since,
+            touches,
+            debug_timing,
         } => {
+            hooks::warn_if_reference_transaction_hook_disabled(
+                effects,
+                &Repo::from_dir(&git_run_info.working_directory)?,
+            )?;
             smartlog::smartlog(
-                &effects,
+                effects,
                 &git_run_info,
                 &SmartlogOptions {
                     show_hidden_commits,
+                    revset,
+                    since,
+                    touches,
+                    debug_timing,
                 },
-            )?;
-            0
+            )?
         }
 
-        Command::Undo => undo::undo(&effects, &git_run_info)?,
+        Command::Split {
+            commit,
+            pathspecs,
+            messages,
+            force_rewrite_public,
+            commit_options,
+        } => split::split(
+            effects,
+            &git_run_info,
+            commit,
+            pathspecs,
+            messages,
+            force_rewrite_public,
+            &commit_options,
+        )?,
 
-        Command::Unhide { commits, recursive } => hide::unhide(&effects, commits, recursive)?,
+        Command::Squash {
+            commits,
+            messages,
+            use_child_message,
+            use_parent_message,
+            force_rewrite_public,
+            commit_options,
+        } => squash::squash(
+            effects,
+            &git_run_info,
+            commits,
+            messages,
+            use_child_message,
+            use_parent_message,
+            force_rewrite_public,
+            &commit_options,
+        )?,
+
+        Command::Submit {
+            create,
+            force,
+            dry_run,
+            message_file,
+            format,
+            revsets,
+        } => submit::submit(
+            effects,
+            &git_run_info,
+            revsets,
+            create,
+            force,
+            dry_run,
+            message_file,
+            format,
+        )?,
+
+        Command::Sync { pull, autosquash } => sync::sync(effects, &git_run_info, pull, autosquash)?,
+
+        Command::Test {
+            command,
+            jobs,
+            no_cache,
+            revsets,
+        } => test::test(effects, &git_run_info, revsets, command, jobs, no_cache)?,
+
+        Command::Undo {
+            num_transactions,
+            event_tx_id,
+            yes,
+            force,
+            diff,
+        } => {
+            hooks::warn_if_reference_transaction_hook_disabled(
+                effects,
+                &Repo::from_dir(&git_run_info.working_directory)?,
+            )?;
+            undo::undo(
+                effects,
+                &git_run_info,
+                num_transactions,
+                event_tx_id.as_deref(),
+                yes,
+                force,
+                diff,
+            )?
+        }
+
+        Command::Unhide {
+            commits,
+            recursive,
+            restore_branches,
+        } => hide::unhide(effects, commits, recursive, restore_branches)?,
 
         Command::Wrap {
             git_executable: explicit_git_executable,
@@ -250,7 +1005,13 @@ fn do_main_and_drop_locals() -> eyre::Result<i32> {
         }
     };
 
-    let exit_code: i32 = exit_code.try_into()?;
+    if let Some(git_run_info) = stack_smartlog_git_run_info {
+        let repo = Repo::from_dir(&git_run_info.working_directory)?;
+        if exit_code == 0 && should_show_stack_smartlog(effects, &repo)? {
+            smartlog::stack_smartlog(effects, &git_run_info)?;
+        }
+    }
+
     Ok(exit_code)
 }
 
@@ -263,16 +1024,28 @@ pub fn main() {
     std::process::exit(exit_code)
 }
 
+/// Install the tracing subscriber. Logging output is written to stderr if
+/// `RUST_LOG` is set, or if `verbosity` (the number of times `--verbose` was
+/// passed) is nonzero, in which case info-level spans are shown, or also
+/// debug-level events for a verbosity of two or more.
 #[must_use = "This function returns a guard object to flush traces. Dropping it immediately is probably incorrect. Make sure that the returned value lives until tracing has finished."]
-fn install_tracing() -> eyre::Result<impl Drop> {
-    let (filter_layer, fmt_layer) = match EnvFilter::try_from_default_env() {
-        Ok(filter_layer) => {
+fn install_tracing(verbosity: usize) -> eyre::Result<impl Drop> {
+    let filter_layer = match (EnvFilter::try_from_default_env(), verbosity) {
+        (Ok(filter_layer), _) => Some(filter_layer),
+        (Err(_), 0) => None,
+        (Err(_), 1) => Some(EnvFilter::new("info")),
+        (Err(_), _) => Some(EnvFilter::new("debug")),
+    };
+    let (filter_layer, fmt_layer) = match filter_layer {
+        Some(filter_layer) => {
             let fmt_layer = tracing_fmt::layer()
                 .with_span_events(tracing_fmt::format::FmtSpan::CLOSE)
-                .with_target(false);
+                .with_target(false)
+                .with_ansi(console::colors_enabled_stderr())
+                .with_writer(std::io::stderr);
             (Some(filter_layer), Some(fmt_layer))
         }
-        Err(_) => {
+        None => {
             // We would like the filter layer to apply *only* to the formatting
             // layer. That way, the logging output is suppressed, but we still
             // get spantraces for use with `color-eyre`. However, it's currently
@@ -280,8 +1053,8 @@ fn install_tracing() -> eyre::Result<impl Drop> {
             // subscriber. See https://github.com/tokio-rs/tracing/pull/1523
             //
             // The workaround is to only display logging messages if `RUST_LOG`
-            // is set (which is unfortunate, because we'll miss out on
-            // `WARN`-level messages by default).
+            // is set or `--verbose` is passed (which is unfortunate, because
+            // we'll miss out on `WARN`-level messages by default).
             (None, None)
         }
     };
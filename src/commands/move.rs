@@ -3,22 +3,29 @@
 //! Under the hood, this makes use of Git's advanced rebase functionality, which
 //! is also used to preserve merge commits using the `--rebase-merges` option.
 
-use std::convert::TryFrom;
+use std::convert::{TryFrom, TryInto};
 use std::fmt::Write;
 use std::time::SystemTime;
 
 use eden_dag::DagAlgorithm;
+use itertools::Itertools;
 use tracing::instrument;
 
-use crate::core::config::get_restack_preserve_timestamps;
-use crate::core::dag::{resolve_commits, CommitSet, Dag, ResolveCommitsResult};
+use crate::core::config::{
+    get_main_branch_name, get_rebase_autosquash, get_restack_preserve_timestamps,
+};
+use crate::core::dag::{
+    commit_set_to_vec, resolve_commits, CommitSet, CommitVertex, Dag, ResolveCommitsResult,
+    StackRoot,
+};
 use crate::core::effects::Effects;
-use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Pluralize};
 use crate::core::rewrite::{
-    execute_rebase_plan, BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
-    RebasePlanBuilder,
+    check_rewritten_commits_are_draft, execute_rebase_plan, get_rebase_event_tx_id,
+    BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult, RebasePlanBuilder,
 };
-use crate::git::{GitRunInfo, NonZeroOid, Repo};
+use crate::git::{CategorizedReferenceName, GitRunInfo, GitRunStreamingOpts, NonZeroOid, Repo};
 use crate::opts::MoveOptions;
 
 #[instrument]
@@ -49,48 +56,173 @@ fn resolve_base_commit(
     }
 }
 
-/// Move a subtree from one place to another.
+/// Check the root of the stack containing `HEAD`, which is moved when no
+/// source commits are provided. Prints the inferred source, or an explanation
+/// if it can't be inferred, in which case `None` is returned.
+#[instrument]
+fn check_inferred_stack_root(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &Dag,
+    stack_root: StackRoot,
+) -> eyre::Result<Option<NonZeroOid>> {
+    match stack_root {
+        StackRoot::Root(root_oid) => {
+            let num_commits = dag
+                .query_stack_commits(CommitSet::from(root_oid))?
+                .count()?;
+            writeln!(
+                effects.get_output_stream(),
+                "Moving stack rooted at {} ({}).",
+                printable_styled_string(
+                    effects.get_glyphs(),
+                    repo.friendly_describe_commit_from_oid(root_oid)?
+                )?,
+                Pluralize {
+                    amount: num_commits.try_into()?,
+                    singular: "commit",
+                    plural: "commits",
+                }
+                .to_string(),
+            )?;
+            Ok(Some(root_oid))
+        }
+
+        StackRoot::Public => {
+            writeln!(
+                effects.get_output_stream(),
+                "HEAD is on the main branch, so there is no current stack to move."
+            )?;
+            writeln!(
+                effects.get_output_stream(),
+                "To choose the commits to move, pass --source, --base, or --exact."
+            )?;
+            Ok(None)
+        }
+
+        StackRoot::Ambiguous { merge_oids } => {
+            writeln!(
+                effects.get_output_stream(),
+                "The root of the current stack can't be determined, because the stack contains {}:",
+                Pluralize {
+                    amount: merge_oids.len().try_into()?,
+                    singular: "merge commit",
+                    plural: "merge commits",
+                }
+                .to_string(),
+            )?;
+            for merge_oid in merge_oids {
+                writeln!(
+                    effects.get_output_stream(),
+                    "{}",
+                    printable_styled_string(
+                        effects.get_glyphs(),
+                        repo.friendly_describe_commit_from_oid(merge_oid)?
+                    )?
+                )?;
+            }
+            writeln!(
+                effects.get_output_stream(),
+                "To choose the commits to move, pass --source, --base, or --exact."
+            )?;
+            Ok(None)
+        }
+    }
+}
+
+/// Move subtrees from one place to another.
+///
+/// Each of the subtrees rooted at `sources` is moved onto `dest`. All of the
+/// moves are carried out as part of a single rebase, so they're recorded in the
+/// same event transaction.
+///
+/// If `exact` is non-empty, then only the provided commits are moved, rather
+/// than the subtree rooted at `source`. They must form a contiguous range of
+/// commits. Any other children of the moved commits are moved onto the parent
+/// of the range, closing the gap left behind.
+///
+/// If `insert` is set, the children of the destination commit are then moved
+/// on top of the moved commits, so that they're spliced in between the
+/// destination commit and its children.
+///
+/// If `dry_run` is set, the rebase plan is described but not executed.
 #[instrument]
 pub fn r#move(
     effects: &Effects,
     git_run_info: &GitRunInfo,
-    source: Option<String>,
+    sources: Vec<String>,
     dest: Option<String>,
     base: Option<String>,
+    exact: Vec<String>,
+    insert: bool,
+    dry_run: bool,
     move_options: &MoveOptions,
 ) -> eyre::Result<isize> {
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
     let head_oid = repo.get_head_info()?.oid;
-    let (source, should_resolve_base_commit) = match (source, base) {
-        (Some(_), Some(_)) => {
-            writeln!(
-                effects.get_output_stream(),
-                "The --source and --base options cannot both be provided."
-            )?;
-            return Ok(1);
-        }
-        (Some(source), None) => (source, false),
-        (None, Some(base)) => (base, true),
-        (None, None) => {
-            let source_oid = match head_oid {
-                Some(oid) => oid,
-                None => {
-                    writeln!(effects.get_output_stream(), "No --source or --base argument was provided, and no OID for HEAD is available as a default")?;
-                    return Ok(1);
-                }
-            };
-            (source_oid.to_string(), true)
-        }
+    if !sources.is_empty() && base.is_some() {
+        writeln!(
+            effects.get_output_stream(),
+            "The --source and --base options cannot both be provided."
+        )?;
+        return Ok(1);
+    }
+    if !exact.is_empty() && (!sources.is_empty() || base.is_some()) {
+        writeln!(
+            effects.get_output_stream(),
+            "The --exact option cannot be provided with the --source or --base options."
+        )?;
+        return Ok(1);
+    }
+    let is_exact = !exact.is_empty();
+    let (sources, should_resolve_base_commit) = match base {
+        Some(base) => (vec![base], true),
+        None => (sources, false),
     };
-    let dest = match dest {
-        Some(dest) => dest,
-        None => match head_oid {
+    let should_infer_source = sources.is_empty() && !is_exact;
+    if should_infer_source && head_oid.is_none() {
+        writeln!(effects.get_output_stream(), "No --source or --base argument was provided, and no OID for HEAD is available as a default")?;
+        return Ok(1);
+    }
+    let dest = match dest.as_deref() {
+        Some(".") => match head_oid {
             Some(oid) => oid.to_string(),
             None => {
-                writeln!(effects.get_output_stream(), "No --dest argument was provided, and no OID for HEAD is available as a default")?;
+                writeln!(
+                    effects.get_output_stream(),
+                    "The destination . refers to HEAD, but no OID for HEAD is available"
+                )?;
                 return Ok(1);
             }
         },
+        Some(dest) => dest.to_owned(),
+        None => {
+            let main_branch_reference = match repo.find_main_branch_reference()? {
+                Some(main_branch_reference) => main_branch_reference,
+                None => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "No --dest argument was provided, and the main branch {:?} could not be found to use as a default. To set the main branch, run: git config branchless.core.mainBranch <branch>",
+                        get_main_branch_name(&repo)?
+                    )?;
+                    return Ok(1);
+                }
+            };
+            let main_branch_oid = match main_branch_reference.peel_to_commit()? {
+                Some(commit) => commit.get_oid(),
+                None => eyre::bail!(
+                    "Could not find commit pointed to by main branch: {:?}",
+                    main_branch_reference.get_name()?
+                ),
+            };
+            writeln!(
+                effects.get_output_stream(),
+                "destination: {} ({})",
+                CategorizedReferenceName::new(&main_branch_reference.get_name()?).render_suffix(),
+                &main_branch_oid.to_string()[..8]
+            )?;
+            main_branch_oid.to_string()
+        }
     };
 
     let references_snapshot = repo.get_references_snapshot()?;
@@ -106,29 +238,199 @@ pub fn r#move(
         &references_snapshot,
     )?;
 
-    let (source_oid, dest_oid) =
-        match resolve_commits(effects, &repo, &mut dag, vec![source, dest])? {
-            ResolveCommitsResult::Ok { commits } => match &commits.as_slice() {
-                [source_commit, dest_commit] => (source_commit.get_oid(), dest_commit.get_oid()),
+    let (sources, should_resolve_base_commit) = match head_oid {
+        Some(head_oid) if should_infer_source => match dag.find_stack_root(head_oid)? {
+            // Public commits aren't part of a stack, but they can still be
+            // moved with `--force-rewrite-public`, in which case `HEAD` is
+            // treated as the base commit.
+            StackRoot::Public if move_options.force_rewrite_public => {
+                (vec![head_oid.to_string()], true)
+            }
+            stack_root => match check_inferred_stack_root(effects, &repo, &dag, stack_root)? {
+                Some(root_oid) => (vec![root_oid.to_string()], false),
+                None => return Ok(1),
+            },
+        },
+        _ => (sources, should_resolve_base_commit),
+    };
+
+    let commits_to_resolve = {
+        let mut commits_to_resolve = vec![dest];
+        commits_to_resolve.extend(sources);
+        commits_to_resolve.extend(exact);
+        commits_to_resolve
+    };
+    let (dest_oid, source_oids) =
+        match resolve_commits(effects, &repo, &mut dag, commits_to_resolve)? {
+            ResolveCommitsResult::Ok { commits } => match commits.as_slice() {
+                [dest_commit, source_commits @ ..] if !source_commits.is_empty() => (
+                    dest_commit.get_oid(),
+                    source_commits
+                        .iter()
+                        .map(|commit| commit.get_oid())
+                        .collect_vec(),
+                ),
                 _ => eyre::bail!("Unexpected number of returns values from resolve_commits"),
             },
             ResolveCommitsResult::CommitNotFound { commit } => {
                 writeln!(effects.get_output_stream(), "Commit not found: {}", commit)?;
                 return Ok(1);
             }
+            ResolveCommitsResult::AmbiguousName(ambiguous_name) => {
+                writeln!(effects.get_output_stream(), "{}", ambiguous_name)?;
+                return Ok(1);
+            }
         };
 
-    let source_oid = if should_resolve_base_commit {
-        let merge_base_oid = dag.get_one_merge_base_oid(effects, &repo, source_oid, dest_oid)?;
-        resolve_base_commit(&dag, merge_base_oid, source_oid)?
+    let visible_commits = dag.query_visible_commits()?;
+    let (source_oids, source_commits, gap_parent_oid) = if is_exact {
+        let exact_commits =
+            CommitSet::from_iter(source_oids.into_iter().map(CommitVertex::from).map(Ok));
+        let exact_root_oids = commit_set_to_vec(&dag.query().roots(exact_commits.clone())?)?;
+        let exact_head_oids = commit_set_to_vec(&dag.query().heads(exact_commits.clone())?)?;
+        let root_oid = match (exact_root_oids.as_slice(), exact_head_oids.as_slice()) {
+            ([root_oid], [head_oid])
+                if dag
+                    .query()
+                    .range(CommitSet::from(*root_oid), CommitSet::from(*head_oid))?
+                    .count()?
+                    == exact_commits.count()? =>
+            {
+                *root_oid
+            }
+            _ => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "The commits passed to --exact must form a contiguous range of commits. Move each range separately instead."
+                )?;
+                return Ok(1);
+            }
+        };
+        let root_commit = repo.find_commit_or_fail(root_oid)?;
+        let gap_parent_oid = match root_commit.get_parent_oids().as_slice() {
+            [parent_oid] => *parent_oid,
+            _ => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "The --exact option cannot be used to move a commit which does not have exactly one parent: {}",
+                    printable_styled_string(effects.get_glyphs(), root_commit.friendly_describe()?)?
+                )?;
+                return Ok(1);
+            }
+        };
+        (vec![root_oid], exact_commits, Some(gap_parent_oid))
     } else {
-        source_oid
+        let source_oids: Vec<NonZeroOid> = if should_resolve_base_commit {
+            source_oids
+                .into_iter()
+                .map(|source_oid| {
+                    let merge_base_oid =
+                        dag.get_one_merge_base_oid(effects, &repo, source_oid, dest_oid)?;
+                    resolve_base_commit(&dag, merge_base_oid, source_oid)
+                })
+                .try_collect()?
+        } else {
+            source_oids
+        };
+
+        // If one source is a descendant of another, then it will already be
+        // moved along with the other source's subtree, so only the outermost
+        // sources are moved explicitly.
+        let source_oids =
+            CommitSet::from_iter(source_oids.into_iter().map(CommitVertex::from).map(Ok));
+        let source_oids = source_oids.difference(
+            &dag.query()
+                .descendants(dag.query().children(source_oids.clone())?)?,
+        );
+        let source_commits = dag
+            .query()
+            .descendants(source_oids.clone())?
+            .intersection(&visible_commits)
+            .union(&source_oids);
+        let source_oids = commit_set_to_vec(&source_oids)?;
+
+        for source_oid in source_oids.iter().copied() {
+            let source_descendants = dag.query().descendants(CommitSet::from(source_oid))?;
+            if source_descendants.contains(&CommitVertex::from(dest_oid))? {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Cannot move {} onto {}, because the destination is {} the source.",
+                    printable_styled_string(
+                        effects.get_glyphs(),
+                        repo.friendly_describe_commit_from_oid(source_oid)?
+                    )?,
+                    printable_styled_string(
+                        effects.get_glyphs(),
+                        repo.friendly_describe_commit_from_oid(dest_oid)?
+                    )?,
+                    if source_oid == dest_oid {
+                        "the same as"
+                    } else {
+                        "a descendant of"
+                    },
+                )?;
+                if source_oid != dest_oid {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "To move only the source commit, and not its descendants, use the --exact option."
+                    )?;
+                }
+                return Ok(1);
+            }
+        }
+
+        // A source which is already a child of the destination would be moved
+        // to where it already is. (With `--insert`, the destination's other
+        // children still need to be moved on top of it.)
+        let source_oids = if insert {
+            source_oids
+        } else {
+            let mut moved_source_oids = Vec::new();
+            for source_oid in source_oids.iter().copied() {
+                let source_commit = repo.find_commit_or_fail(source_oid)?;
+                if !source_commit.get_parent_oids().contains(&dest_oid) {
+                    moved_source_oids.push(source_oid);
+                }
+            }
+            if moved_source_oids.is_empty() {
+                let dest_description = printable_styled_string(
+                    effects.get_glyphs(),
+                    repo.friendly_describe_commit_from_oid(dest_oid)?,
+                )?;
+                match source_oids.as_slice() {
+                    [source_oid] => writeln!(
+                        effects.get_output_stream(),
+                        "{} is already a child of {}, so there is nothing to move.",
+                        printable_styled_string(
+                            effects.get_glyphs(),
+                            repo.friendly_describe_commit_from_oid(*source_oid)?
+                        )?,
+                        dest_description,
+                    )?,
+                    _ => writeln!(
+                        effects.get_output_stream(),
+                        "The source commits are already children of {}, so there is nothing to move.",
+                        dest_description,
+                    )?,
+                }
+                return Ok(0);
+            }
+            moved_source_oids
+        };
+
+        (source_oids, source_commits, None)
     };
 
     let MoveOptions {
         force_in_memory,
         force_on_disk,
         resolve_merge_conflicts,
+        preserve_timestamps,
+        drop_merges,
+        keep_empty,
+        autosquash,
+        force_rewrite_public,
+        update_worktrees,
         dump_rebase_constraints,
         dump_rebase_plan,
     } = *move_options;
@@ -136,13 +438,60 @@ pub fn r#move(
     let event_tx_id = event_log_db.make_transaction_id(now, "move")?;
     let rebase_plan = {
         let mut builder = RebasePlanBuilder::new(&repo, &dag);
-        builder.move_subtree(source_oid, dest_oid)?;
+        for source_oid in source_oids {
+            builder.move_subtree(source_oid, dest_oid)?;
+        }
+
+        if let Some(gap_parent_oid) = gap_parent_oid {
+            // Any commits which descended from the exact commits, but which
+            // weren't themselves requested to be moved, stay behind and are
+            // moved onto the nearest ancestor which isn't being moved.
+            let gap_child_oids = dag
+                .query()
+                .children(source_commits.clone())?
+                .intersection(&visible_commits)
+                .difference(&source_commits);
+            for gap_child_oid in commit_set_to_vec(&gap_child_oids)? {
+                builder.move_subtree(gap_child_oid, gap_parent_oid)?;
+            }
+        }
+
+        if insert {
+            let source_head_oids = commit_set_to_vec(&dag.query().heads(source_commits.clone())?)?;
+            let source_head_oid = match source_head_oids.as_slice() {
+                [source_head_oid] => *source_head_oid,
+                _ => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "The --insert option cannot be used when the commits being moved have more than one head."
+                    )?;
+                    return Ok(1);
+                }
+            };
+
+            // The children of the destination are moved on top of the
+            // inserted commits. If the source commits were already descendants
+            // of the destination, then they're excluded here, and the net
+            // effect is to reorder them before the destination's other
+            // descendants.
+            let dest_child_oids = dag
+                .query()
+                .children(CommitSet::from(dest_oid))?
+                .intersection(&visible_commits)
+                .difference(&source_commits);
+            for dest_child_oid in commit_set_to_vec(&dest_child_oids)? {
+                builder.move_subtree(dest_child_oid, source_head_oid)?;
+            }
+        }
         builder.build(
             effects,
             &BuildRebasePlanOptions {
                 dump_rebase_constraints,
                 dump_rebase_plan,
-                detect_duplicate_commits_via_patch_id: true,
+                detect_duplicate_commits_via_patch_id: !keep_empty,
+                drop_merges,
+                keep_empty,
+                autosquash: autosquash || get_rebase_autosquash(&repo)?,
             },
         )?
     };
@@ -151,14 +500,29 @@ pub fn r#move(
             writeln!(effects.get_output_stream(), "Nothing to do.")?;
             return Ok(0);
         }
+        Ok(Some(rebase_plan)) if dry_run => {
+            let is_ok = rebase_plan.describe_dry_run(effects, &repo, &dag, force_in_memory)?;
+            return Ok(if is_ok { 0 } else { 1 });
+        }
         Ok(Some(rebase_plan)) => {
+            if !check_rewritten_commits_are_draft(
+                effects,
+                &repo,
+                &dag,
+                rebase_plan.get_rewritten_commit_oids(),
+                force_rewrite_public,
+            )? {
+                return Ok(1);
+            }
+
             let options = ExecuteRebasePlanOptions {
                 now,
                 event_tx_id,
-                preserve_timestamps: get_restack_preserve_timestamps(&repo)?,
+                preserve_timestamps: preserve_timestamps || get_restack_preserve_timestamps(&repo)?,
                 force_in_memory,
                 force_on_disk,
                 resolve_merge_conflicts,
+                update_worktrees,
             };
             execute_rebase_plan(effects, git_run_info, &repo, &rebase_plan, &options)?
         }
@@ -176,6 +540,100 @@ pub fn r#move(
             Ok(1)
         }
 
-        ExecuteRebasePlanResult::Failed { exit_code } => Ok(exit_code),
+        ExecuteRebasePlanResult::Failed { exit_code } => {
+            if get_rebase_event_tx_id(&repo)?.is_some() {
+                writeln!(
+                    effects.get_output_stream(),
+                    "After resolving the merge conflicts, run `git move --continue` to finish moving commits, or run `git move --abort` to cancel."
+                )?;
+            }
+            Ok(exit_code)
+        }
+    }
+}
+
+/// Continue or abort a move which stopped partway through an on-disk rebase
+/// because of merge conflicts. The rest of the rebase is recorded in the same
+/// event transaction as the original move, so that it can be undone all at
+/// once.
+#[instrument]
+pub fn resume_move(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    abort: bool,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    if repo.get_current_operation_type() != Some("rebase") {
+        writeln!(effects.get_output_stream(), "There is no move in progress.")?;
+        return Ok(1);
+    }
+
+    let event_tx_id = match get_rebase_event_tx_id(&repo)? {
+        Some(event_tx_id) => event_tx_id,
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "The rebase in progress was not started by git-branchless. Run `git rebase {}` instead.",
+                if abort { "--abort" } else { "--continue" }
+            )?;
+            return Ok(1);
+        }
+    };
+
+    if !abort {
+        // Git may open an editor to confirm the message of the commit whose
+        // conflicts were just resolved.
+        let result = git_run_info.run_streaming(
+            effects,
+            &repo,
+            Some(event_tx_id),
+            &["-c", "submodule.recurse=false", "rebase", "--continue"],
+            GitRunStreamingOpts {
+                treat_git_failure_as_error: false,
+                use_terminal: true,
+                ..Default::default()
+            },
+        )?;
+        return Ok(result.exit_code.try_into()?);
+    }
+
+    let result = git_run_info.run_streaming(
+        effects,
+        &repo,
+        Some(event_tx_id),
+        &["-c", "submodule.recurse=false", "rebase", "--abort"],
+        GitRunStreamingOpts {
+            treat_git_failure_as_error: false,
+            ..Default::default()
+        },
+    )?;
+    if result.exit_code != 0 {
+        return Ok(result.exit_code.try_into()?);
     }
+
+    // Any commits which were created before the rebase stopped are no longer
+    // reachable from the original commits, so hide them.
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs_f64();
+    let events = event_log_db
+        .get_events()?
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::CommitEvent {
+                event_tx_id: commit_event_tx_id,
+                commit_oid,
+                ..
+            } if commit_event_tx_id == event_tx_id => Some(Event::ObsoleteEvent {
+                timestamp,
+                event_tx_id,
+                commit_oid,
+            }),
+            _ => None,
+        })
+        .collect();
+    event_log_db.add_events(events)?;
+    Ok(0)
 }
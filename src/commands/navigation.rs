@@ -1,6 +1,5 @@
 //! Convenience commands to help the user move through a stack of commits.
 
-use std::collections::HashSet;
 use std::convert::TryInto;
 use std::ffi::{OsStr, OsString};
 use std::fmt::Write;
@@ -12,18 +11,24 @@ use eden_dag::DagAlgorithm;
 use tracing::{instrument, warn};
 
 use crate::commands::smartlog::make_smartlog_graph;
-use crate::core::config::get_next_interactive;
-use crate::core::dag::{sort_commit_set, CommitSet, Dag};
+use crate::core::config::{
+    get_navigation_auto_merge, get_navigation_auto_switch_to_rewritten, get_next_interactive,
+};
+use crate::core::dag::{sort_commit_set, CommitSet, CommitVertex, Dag};
 use crate::core::effects::Effects;
-use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::core::eventlog::{Event, EventCursor, EventLogDb, EventReplayer, EventTransactionId};
 use crate::core::formatting::{printable_styled_string, Pluralize};
 use crate::core::node_descriptors::{
-    BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor,
-    DifferentialRevisionDescriptor, NodeDescriptor, RelativeTimeDescriptor,
+    render_node_descriptors, BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor,
+    DifferentialRevisionDescriptor, NodeDescriptor, NodeObject, RelativeTimeDescriptor,
+};
+use crate::core::rewrite::{find_abandoned_children, find_rewrite_target};
+use crate::git::{
+    check_out_commit, GitRunInfo, MaybeZeroOid, NonZeroOid, Repo, RepoReferencesSnapshot,
+    ResolvedBranchName,
 };
-use crate::git::{check_out_commit, GitRunInfo, NonZeroOid, Repo};
 use crate::opts::TraverseCommitsOptions;
-use crate::tui::prompt_select_commit;
+use crate::tui::{can_prompt_choose_commit, prompt_choose_commit, prompt_select_commit};
 
 /// The command being invoked, indicating which direction to traverse commits.
 #[derive(Clone, Copy, Debug)]
@@ -68,6 +73,10 @@ pub enum Towards {
     /// When encountering multiple children, interactively prompt for
     /// which one to advance to.
     Interactive,
+
+    /// When encountering multiple children, print a numbered list of them and
+    /// prompt the user to pick one with a single keypress.
+    Choose,
 }
 
 #[instrument(skip(commit_descriptors))]
@@ -80,22 +89,32 @@ fn advance(
     command: Command,
     distance: Distance,
     towards: Option<Towards>,
+    parent_number: Option<usize>,
 ) -> eyre::Result<Option<NonZeroOid>> {
     let towards = match towards {
-        Some(towards) => Some(towards),
+        Some(towards) => towards,
         None => {
             if get_next_interactive(repo)? {
-                Some(Towards::Interactive)
+                Towards::Interactive
             } else {
-                None
+                Towards::Choose
             }
         }
     };
 
-    let public_commits = dag.query().ancestors(dag.main_branch_commit.clone())?;
+    let public_commits = dag.query_public_commits()?;
+
+    let move_by_branches = match distance {
+        Distance::NumCommits {
+            amount: _,
+            move_by_branches,
+        }
+        | Distance::AllTheWay { move_by_branches } => move_by_branches,
+    };
 
     let glyphs = effects.get_glyphs();
     let mut current_oid = current_oid;
+    let mut chose_merge_parent = false;
     let mut i = 0;
     loop {
         let candidate_commits = match command {
@@ -143,7 +162,12 @@ fn advance(
                     Ok(result)
                 };
                 let ancestor_branches = || -> eyre::Result<CommitSet> {
-                    let ancestor_commits = dag.query().ancestors(parent_commits()?)?;
+                    // Don't go past the main branch: it's the last branch
+                    // that can be reached when moving by branches.
+                    let ancestor_commits = dag
+                        .query()
+                        .ancestors(parent_commits()?)?
+                        .difference(&public_commits.difference(&dag.main_branch_commit));
                     let ancestor_branches = dag.branch_commits.intersection(&ancestor_commits);
                     let nearest_ancestor_branches =
                         dag.query().heads_ancestors(ancestor_branches)?;
@@ -159,8 +183,15 @@ fn advance(
                         // we assume that the user wanted to get to the root commit for
                         // their current *commit stack*. We filter out commits which
                         // aren't part of the commit stack so that we stop early here.
-                        let parents = parent_commits()?;
-                        parents.difference(&public_commits)
+                        // Merge commits are traversed via their first parent, so
+                        // that there's only one root to go to.
+                        let first_parent: CommitSet = repo
+                            .find_commit_or_fail(current_oid)?
+                            .get_parent_oids()
+                            .into_iter()
+                            .take(1)
+                            .collect();
+                        first_parent.difference(&public_commits)
                     }
 
                     Distance::AllTheWay {
@@ -205,22 +236,92 @@ fn advance(
             }
         }
 
-        let pluralize = match command {
-            Command::Next => Pluralize {
-                amount: i.try_into()?,
-                plural: "children",
-                singular: "child",
-            },
-
-            Command::Prev => Pluralize {
-                amount: i.try_into()?,
-                plural: "parents",
-                singular: "parent",
-            },
+        // When going to the parent of a merge commit, the parents are
+        // identified by their position in the commit, as in `<commit>^<n>`.
+        let parent_oids = match (command, move_by_branches) {
+            (Command::Prev, false) => repo.find_commit_or_fail(current_oid)?.get_parent_oids(),
+            (Command::Prev, true) | (Command::Next, _) => Vec::new(),
+        };
+        let candidate_commits = match parent_number {
+            Some(parent_number) if i == 0 => {
+                let parent_oid = match parent_oids.get(parent_number - 1) {
+                    Some(parent_oid) => *parent_oid,
+                    None => {
+                        writeln!(
+                            effects.get_output_stream(),
+                            "Cannot go to parent {} of {}, as it has {}.",
+                            parent_number,
+                            printable_styled_string(
+                                glyphs,
+                                repo.find_commit_or_fail(current_oid)?.friendly_describe()?
+                            )?,
+                            Pluralize {
+                                amount: parent_oids.len().try_into()?,
+                                plural: "parents",
+                                singular: "parent",
+                            }
+                            .to_string(),
+                        )?;
+                        return Ok(None);
+                    }
+                };
+                vec![repo.find_commit_or_fail(parent_oid)?]
+            }
+            Some(_) if candidate_commits.len() > 1 => {
+                match candidate_commits
+                    .iter()
+                    .find(|commit| parent_oids.first() == Some(&commit.get_oid()))
+                {
+                    Some(first_parent) => vec![first_parent.clone()],
+                    None => candidate_commits,
+                }
+            }
+            Some(_) | None => candidate_commits,
+        };
+        if parent_oids.len() > 1 && !candidate_commits.is_empty() {
+            chose_merge_parent = true;
+        }
+
+        let (pluralize, candidates_noun) = match (command, move_by_branches) {
+            (Command::Next, false) => (
+                Pluralize {
+                    amount: i.try_into()?,
+                    plural: "children",
+                    singular: "child",
+                },
+                "child commits",
+            ),
+
+            (Command::Next, true) => (
+                Pluralize {
+                    amount: i.try_into()?,
+                    plural: "branches",
+                    singular: "branch",
+                },
+                "descendant branches",
+            ),
+
+            (Command::Prev, false) => (
+                Pluralize {
+                    amount: i.try_into()?,
+                    plural: "parents",
+                    singular: "parent",
+                },
+                "parent commits",
+            ),
+
+            (Command::Prev, true) => (
+                Pluralize {
+                    amount: i.try_into()?,
+                    plural: "branches",
+                    singular: "branch",
+                },
+                "ancestor branches",
+            ),
         };
         let header = format!(
-            "Found multiple possible {} commits to go to after traversing {}:",
-            pluralize.singular,
+            "Found multiple possible {} to go to after traversing {}:",
+            candidates_noun,
             pluralize.to_string(),
         );
 
@@ -233,8 +334,8 @@ fn advance(
                         glyphs,
                         StyledString::styled(
                             format!(
-                                "No more {} commits to go to after traversing {}.",
-                                pluralize.singular,
+                                "No more {} to go to after traversing {}.",
+                                candidates_noun,
                                 pluralize.to_string(),
                             ),
                             BaseColor::Yellow.light()
@@ -246,6 +347,20 @@ fn advance(
                     // If we didn't succeed in traversing any commits, then
                     // treat the operation as a failure. Otherwise, assume that
                     // the user just meant to go as many commits as possible.
+                    let current_commit = repo.find_commit_or_fail(current_oid)?;
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Staying at {}",
+                        printable_styled_string(
+                            glyphs,
+                            render_node_descriptors(
+                                &NodeObject::Commit {
+                                    commit: current_commit
+                                },
+                                commit_descriptors
+                            )?
+                        )?
+                    )?;
                     return Ok(None);
                 } else {
                     break;
@@ -253,9 +368,7 @@ fn advance(
             }
 
             (_, [only_child]) => only_child.get_oid(),
-            (Some(Towards::Newest), [.., newest_child]) => newest_child.get_oid(),
-            (Some(Towards::Oldest), [oldest_child, ..]) => oldest_child.get_oid(),
-            (Some(Towards::Interactive), [_, _, ..]) => {
+            (Towards::Interactive, [_, _, ..]) => {
                 match prompt_select_commit(
                     Some(&header),
                     "",
@@ -268,7 +381,60 @@ fn advance(
                     }
                 }
             }
-            (None, [_, _, ..]) => {
+            (Towards::Choose, [_, _, ..]) if can_prompt_choose_commit(&candidate_commits) => {
+                // List the parents of a merge commit in order, so that the
+                // numbers in the prompt correspond to `--parent`.
+                let mut candidate_commits = candidate_commits;
+                candidate_commits.sort_by_key(|commit| {
+                    parent_oids
+                        .iter()
+                        .position(|parent_oid| *parent_oid == commit.get_oid())
+                });
+                match prompt_choose_commit(
+                    effects,
+                    &header,
+                    &candidate_commits,
+                    commit_descriptors,
+                )? {
+                    Some(oid) => oid,
+                    None => {
+                        return Ok(None);
+                    }
+                }
+            }
+            (towards, [oldest_child, .., newest_child]) => {
+                let first_parent = candidate_commits
+                    .iter()
+                    .find(|commit| parent_oids.first() == Some(&commit.get_oid()));
+                let (selected_child, selection, alternatives) = match (towards, first_parent) {
+                    (Towards::Oldest, _) => (
+                        oldest_child,
+                        "the oldest commit by commit date, since --oldest was passed",
+                        "--newest (-n)",
+                    ),
+                    (Towards::Newest, _) => (
+                        newest_child,
+                        "the newest commit by commit date, since --newest was passed",
+                        "--oldest (-o)",
+                    ),
+                    (Towards::Choose, Some(first_parent)) => (
+                        first_parent,
+                        "the first parent",
+                        "--parent <n>, --oldest (-o), --newest (-n),",
+                    ),
+                    (Towards::Choose, None) if console::user_attended() => (
+                        newest_child,
+                        "the newest commit by commit date, since there are too many commits to prompt for",
+                        "--oldest (-o)",
+                    ),
+                    (Towards::Choose, None) => (
+                        newest_child,
+                        "the newest commit by commit date, since there is no terminal to prompt in",
+                        "--oldest (-o)",
+                    ),
+                    (Towards::Interactive, _) => unreachable!("Handled above"),
+                };
+
                 writeln!(effects.get_output_stream(), "{}", header)?;
                 for (j, child) in (0..).zip(candidate_commits.iter()) {
                     let descriptor = if j == 0 {
@@ -287,16 +453,256 @@ fn advance(
                         descriptor
                     )?;
                 }
-                writeln!(effects.get_output_stream(), "(Pass --oldest (-o), --newest (-n), or --interactive (-i) to select between ambiguous commits)")?;
-                return Ok(None);
+                writeln!(
+                    effects.get_output_stream(),
+                    "Going to {}: {}",
+                    selection,
+                    printable_styled_string(glyphs, selected_child.friendly_describe()?)?,
+                )?;
+                writeln!(
+                    effects.get_output_stream(),
+                    "(Pass {} or --interactive (-i) to select a different commit)",
+                    alternatives
+                )?;
+                selected_child.get_oid()
             }
         };
 
         i += 1;
     }
+
+    if chose_merge_parent {
+        let current_vertex = CommitVertex::from(current_oid);
+        if public_commits.contains(&current_vertex)? {
+            writeln!(
+                effects.get_output_stream(),
+                "Note: you are now on a public commit."
+            )?;
+        } else if dag.obsolete_commits.contains(&current_vertex)? {
+            writeln!(
+                effects.get_output_stream(),
+                "Note: you are now on a hidden commit."
+            )?;
+        }
+    }
+
+    if let Distance::AllTheWay {
+        move_by_branches: _,
+    } = distance
+    {
+        let current_commit = repo.find_commit_or_fail(current_oid)?;
+        let description = printable_styled_string(
+            glyphs,
+            render_node_descriptors(
+                &NodeObject::Commit {
+                    commit: current_commit,
+                },
+                commit_descriptors,
+            )?,
+        )?;
+        if i == 0
+            && matches!(command, Command::Prev)
+            && public_commits.contains(&CommitVertex::from(current_oid))?
+        {
+            writeln!(
+                effects.get_output_stream(),
+                "Not in a stack of draft commits, since this commit is public: {}",
+                description
+            )?;
+        } else if i == 0 {
+            let stack_end = match (command, move_by_branches) {
+                (Command::Next, false) => "head",
+                (Command::Next, true) => "last branch",
+                (Command::Prev, false) => "root",
+                (Command::Prev, true) => "first branch",
+            };
+            writeln!(
+                effects.get_output_stream(),
+                "Already at the {} of the current stack: {}",
+                stack_end,
+                description
+            )?;
+        } else {
+            let pluralize = if move_by_branches {
+                Pluralize {
+                    amount: i.try_into()?,
+                    plural: "branches",
+                    singular: "branch",
+                }
+            } else {
+                Pluralize {
+                    amount: i.try_into()?,
+                    plural: "commits",
+                    singular: "commit",
+                }
+            };
+            let verb = match command {
+                Command::Next => "Advanced",
+                Command::Prev => "Went back",
+            };
+            writeln!(
+                effects.get_output_stream(),
+                "{} {} to {}",
+                verb,
+                pluralize.to_string(),
+                description
+            )?;
+        }
+    }
+
     Ok(Some(current_oid))
 }
 
+/// How to check out the destination commit of a navigation command.
+#[derive(Clone, Debug)]
+struct CheckoutOptions {
+    /// Whether to merge uncommitted changes into the destination commit, as
+    /// with `git checkout --merge`.
+    merge: bool,
+}
+
+impl CheckoutOptions {
+    /// Constructor. Uncommitted changes are merged if `merge` is set or if
+    /// the repository is configured to always do so.
+    fn new(repo: &Repo, merge: bool) -> eyre::Result<Self> {
+        let merge = merge || get_navigation_auto_merge(repo)?;
+        Ok(CheckoutOptions { merge })
+    }
+
+    fn additional_args(&self) -> Vec<&'static OsStr> {
+        let CheckoutOptions { merge } = self;
+        let mut args = Vec::new();
+        if *merge {
+            args.push(OsStr::new("--merge"));
+        }
+        args
+    }
+
+    /// Check out `target` with these options. If the checkout fails while
+    /// there are uncommitted changes, suggest merging them.
+    fn check_out(
+        &self,
+        effects: &Effects,
+        git_run_info: &GitRunInfo,
+        repo: &Repo,
+        event_tx_id: Option<EventTransactionId>,
+        target: impl AsRef<OsStr> + std::fmt::Debug,
+    ) -> eyre::Result<isize> {
+        let exit_code = check_out_commit(
+            effects,
+            git_run_info,
+            event_tx_id,
+            target,
+            self.additional_args(),
+        )?;
+        if exit_code != 0 && !self.merge && !repo.get_status(git_run_info, None)?.is_empty() {
+            writeln!(
+                effects.get_output_stream(),
+                "(Pass --merge (-m) to merge your uncommitted changes into the destination commit, or set `branchless.navigation.autoMerge` to `true` to do so by default)"
+            )?;
+        }
+        Ok(exit_code)
+    }
+}
+
+/// If the destination commit has been rewritten and the repository is
+/// configured to do so, return the newest version of the commit instead.
+fn resolve_rewritten_destination(
+    effects: &Effects,
+    repo: &Repo,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    oid: NonZeroOid,
+) -> eyre::Result<NonZeroOid> {
+    if !get_navigation_auto_switch_to_rewritten(repo)? {
+        return Ok(oid);
+    }
+
+    match find_rewrite_target(event_replayer, event_cursor, oid) {
+        Some(MaybeZeroOid::NonZero(rewritten_oid)) => {
+            let glyphs = effects.get_glyphs();
+            writeln!(
+                effects.get_output_stream(),
+                "Going to {} instead of {}, since it has been rewritten.",
+                printable_styled_string(
+                    glyphs,
+                    repo.find_commit_or_fail(rewritten_oid)?
+                        .friendly_describe()?
+                )?,
+                printable_styled_string(
+                    glyphs,
+                    repo.find_commit_or_fail(oid)?.friendly_describe()?
+                )?,
+            )?;
+            Ok(rewritten_oid)
+        }
+        Some(MaybeZeroOid::Zero) | None => Ok(oid),
+    }
+}
+
+/// Print a note if the commit that was just checked out has been rewritten,
+/// since any new work on it would be based on an outdated commit.
+fn warn_if_rewritten(
+    effects: &Effects,
+    dag: &Dag,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    oid: NonZeroOid,
+) -> eyre::Result<()> {
+    let (rewritten_oid, abandoned_child_oids) =
+        match find_abandoned_children(dag, event_replayer, event_cursor, oid)? {
+            Some((rewritten_oid, abandoned_child_oids)) if rewritten_oid != oid => {
+                (rewritten_oid, abandoned_child_oids)
+            }
+            Some(_) | None => return Ok(()),
+        };
+
+    let short_oid = &rewritten_oid.to_string()[..8];
+    writeln!(
+        effects.get_output_stream(),
+        "{}",
+        printable_styled_string(
+            effects.get_glyphs(),
+            StyledString::styled(
+                format!(
+                    "note: this commit has been rewritten as {}; run `git restack` or `git checkout {}`",
+                    short_oid, short_oid
+                ),
+                BaseColor::Yellow.light()
+            )
+        )?
+    )?;
+    if !abandoned_child_oids.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "note: {} of this commit will be moved onto {} by `git restack`",
+            Pluralize {
+                amount: abandoned_child_oids.len().try_into()?,
+                singular: "abandoned child",
+                plural: "abandoned children",
+            }
+            .to_string(),
+            short_oid
+        )?;
+    }
+    Ok(())
+}
+
+/// Get the argument to pass to `git checkout` to go to the given commit: the
+/// name of the branch pointing to it, if there is exactly one, or else its OID.
+fn get_checkout_target(references_snapshot: &RepoReferencesSnapshot, oid: NonZeroOid) -> OsString {
+    match references_snapshot.branch_oid_to_names.get(&oid) {
+        Some(branches) if branches.len() == 1 => {
+            let branch = branches.iter().next().unwrap();
+            branch.clone()
+        }
+
+        // If there are multiple branches, it's ambiguous which branch the user
+        // wants; just check out the commit directly.
+        Some(_) | None => oid.to_string().into(),
+    }
+}
+
 /// Go forward or backward a certain number of commits.
 #[instrument]
 pub fn traverse_commits(
@@ -312,9 +718,20 @@ pub fn traverse_commits(
         oldest,
         newest,
         interactive,
+        parent,
         merge,
     } = *options;
 
+    match (command, parent) {
+        (_, None) | (Command::Prev, Some(1..=usize::MAX)) => {}
+        (Command::Prev, Some(_)) => {
+            eyre::bail!("--parent must be at least 1")
+        }
+        (Command::Next, Some(_)) => {
+            eyre::bail!("--parent can only be used with `git prev`")
+        }
+    }
+
     let distance = match (all_the_way, num_commits) {
         (false, None) => Distance::NumCommits {
             amount: 1,
@@ -343,7 +760,7 @@ pub fn traverse_commits(
         }
     };
 
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
     let references_snapshot = repo.get_references_snapshot()?;
     let conn = repo.get_db_conn()?;
     let event_log_db = EventLogDb::new(&conn)?;
@@ -360,7 +777,12 @@ pub fn traverse_commits(
     let head_oid = match references_snapshot.head_oid {
         Some(head_oid) => head_oid,
         None => {
-            eyre::bail!("No HEAD present; cannot calculate next commit");
+            // `HEAD` is unborn, such as in a freshly-initialized repository.
+            writeln!(
+                effects.get_output_stream(),
+                "No commits have been made in this repository yet, so there is no commit to move from."
+            )?;
+            return Ok(1);
         }
     };
 
@@ -379,55 +801,51 @@ pub fn traverse_commits(
         command,
         distance,
         towards,
+        parent,
     )?;
     let current_oid = match current_oid {
         None => return Ok(1),
         Some(current_oid) => current_oid,
     };
 
-    let current_oid: OsString = match distance {
-        Distance::AllTheWay {
-            move_by_branches: false,
-        }
-        | Distance::NumCommits {
-            amount: _,
-            move_by_branches: false,
-        } => current_oid.to_string().into(),
+    if current_oid == head_oid {
+        // Only possible with `--all` when already at the end of the stack,
+        // which has been reported above.
+        return Ok(0);
+    }
 
+    // When moving by branches or all the way to the end of the stack, attach
+    // `HEAD` to the destination's branch, if it has exactly one.
+    let should_attach_branch = match distance {
         Distance::AllTheWay {
-            move_by_branches: true,
-        }
-        | Distance::NumCommits {
+            move_by_branches: _,
+        } => true,
+        Distance::NumCommits {
             amount: _,
-            move_by_branches: true,
-        } => {
-            let empty = HashSet::new();
-            let branches = references_snapshot
+            move_by_branches,
+        } => move_by_branches,
+    };
+    let current_oid =
+        resolve_rewritten_destination(effects, &repo, &event_replayer, event_cursor, current_oid)?;
+    let target: OsString = if should_attach_branch {
+        if move_by_branches
+            && !references_snapshot
                 .branch_oid_to_names
-                .get(&current_oid)
-                .unwrap_or(&empty);
-
-            if branches.is_empty() {
-                warn!(?current_oid, "No branches attached to commit with OID");
-                current_oid.to_string().into()
-            } else if branches.len() == 1 {
-                let branch = branches.iter().next().unwrap();
-                branch.clone()
-            } else {
-                // It's ambiguous which branch the user wants; just check out the commit directly.
-                current_oid.to_string().into()
-            }
+                .contains_key(&current_oid)
+        {
+            warn!(?current_oid, "No branches attached to commit with OID");
         }
+        get_checkout_target(&references_snapshot, current_oid)
+    } else {
+        current_oid.to_string().into()
     };
 
-    let additional_args = if merge { vec!["--merge"] } else { vec![] };
-    check_out_commit(
-        effects,
-        git_run_info,
-        None,
-        &current_oid,
-        additional_args.as_slice(),
-    )
+    let checkout_options = CheckoutOptions::new(&repo, merge)?;
+    let exit_code = checkout_options.check_out(effects, git_run_info, &repo, None, &target)?;
+    if exit_code == 0 {
+        warn_if_rewritten(effects, &dag, &event_replayer, event_cursor, current_oid)?;
+    }
+    Ok(exit_code)
 }
 
 /// Interactively checkout a commit from the smartlog.
@@ -435,8 +853,32 @@ pub fn checkout(
     effects: &Effects,
     git_run_info: &GitRunInfo,
     initial_query: &str,
+    show_hidden_commits: bool,
+    merge: bool,
 ) -> eyre::Result<isize> {
-    let repo = Repo::from_current_dir()?;
+    if let Some(num_checkouts_back) = parse_previous_checkout_query(initial_query) {
+        return checkout_previous(
+            effects,
+            git_run_info,
+            initial_query,
+            num_checkouts_back,
+            merge,
+        );
+    }
+
+    if !console::user_attended() {
+        writeln!(
+            effects.get_error_stream(),
+            "Cannot prompt for a commit to check out, since there is no terminal to prompt in."
+        )?;
+        writeln!(
+            effects.get_error_stream(),
+            "(To check out a commit non-interactively, run `git checkout <commit>`)"
+        )?;
+        return Ok(1);
+    }
+
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
     let references_snapshot = repo.get_references_snapshot()?;
     let conn = repo.get_db_conn()?;
     let event_log_db = EventLogDb::new(&conn)?;
@@ -450,12 +892,20 @@ pub fn checkout(
         &references_snapshot,
     )?;
 
-    let graph = make_smartlog_graph(effects, &repo, &dag, &event_replayer, event_cursor, true)?;
+    let graph = make_smartlog_graph(
+        effects,
+        &repo,
+        &dag,
+        &event_replayer,
+        event_cursor,
+        !show_hidden_commits,
+    )?;
+    let commits = graph.get_commits()?;
 
     match prompt_select_commit(
         None,
         initial_query,
-        graph.get_commits(),
+        commits,
         &mut [
             &mut CommitOidDescriptor::new(true)?,
             &mut RelativeTimeDescriptor::new(&repo, SystemTime::now())?,
@@ -464,13 +914,182 @@ pub fn checkout(
             &mut CommitMessageDescriptor::new()?,
         ],
     )? {
-        Some(oid) => check_out_commit(
-            effects,
-            git_run_info,
-            None,
-            &oid.to_string(),
-            &[] as &[&OsStr],
-        ),
+        Some(oid) => {
+            let oid =
+                resolve_rewritten_destination(effects, &repo, &event_replayer, event_cursor, oid)?;
+            let checkout_options = CheckoutOptions::new(&repo, merge)?;
+            let exit_code = checkout_options.check_out(
+                effects,
+                git_run_info,
+                &repo,
+                None,
+                get_checkout_target(&references_snapshot, oid),
+            )?;
+            if exit_code == 0 {
+                warn_if_rewritten(effects, &dag, &event_replayer, event_cursor, oid)?;
+            }
+            Ok(exit_code)
+        }
         None => Ok(1),
     }
 }
+
+/// Create a branch named `branch_name` at `start_point` (or `HEAD`, if empty)
+/// and check it out, as with `git checkout -b`. If `force` is set, an existing
+/// branch with the same name is reset to the start point, as with `git checkout
+/// -B`. The branch creation and the checkout are recorded in the same event
+/// transaction, so that they can be undone together.
+pub fn checkout_new_branch(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    start_point: &str,
+    branch_name: &str,
+    force: bool,
+    merge: bool,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let start_point = if start_point.is_empty() {
+        "HEAD"
+    } else {
+        start_point
+    };
+    let start_commit = match repo.resolve_branch_name(start_point)? {
+        ResolvedBranchName::Found(reference) => reference.peel_to_commit()?,
+        ResolvedBranchName::Ambiguous(ambiguous_name) => {
+            writeln!(effects.get_error_stream(), "{}", ambiguous_name)?;
+            return Ok(1);
+        }
+        ResolvedBranchName::NotFound => repo.revparse_single_commit(start_point)?,
+    };
+    let start_commit = match start_commit {
+        Some(start_commit) => start_commit,
+        None => {
+            writeln!(
+                effects.get_error_stream(),
+                "Could not resolve start point: {}",
+                start_point
+            )?;
+            return Ok(1);
+        }
+    };
+
+    let reference_name = format!("refs/heads/{}", branch_name);
+    let old_oid: MaybeZeroOid = match repo.resolve_branch_name(&reference_name)? {
+        ResolvedBranchName::Found(_) if !force => {
+            writeln!(
+                effects.get_error_stream(),
+                "A branch named {} already exists.",
+                branch_name
+            )?;
+            writeln!(
+                effects.get_error_stream(),
+                "(Pass -B instead of -b to reset it to the start point)"
+            )?;
+            return Ok(1);
+        }
+        ResolvedBranchName::Found(reference) => repo.resolve_reference(&reference)?.oid.into(),
+        ResolvedBranchName::Ambiguous(ambiguous_name) => {
+            // Creating the branch would clobber the loose reference file for
+            // the existing branch on a case-insensitive filesystem.
+            writeln!(effects.get_error_stream(), "{}", ambiguous_name)?;
+            return Ok(1);
+        }
+        ResolvedBranchName::NotFound => MaybeZeroOid::Zero,
+    };
+
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let now = SystemTime::now();
+    let event_tx_id = event_log_db.make_transaction_id(now, "checkout")?;
+    repo.create_branch(OsStr::new(branch_name), &start_commit, force)?;
+    event_log_db.add_events(vec![Event::RefUpdateEvent {
+        timestamp: now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64(),
+        event_tx_id,
+        ref_name: OsString::from(reference_name),
+        old_oid,
+        new_oid: start_commit.get_oid().into(),
+        message: None,
+    }])?;
+
+    let checkout_options = CheckoutOptions::new(&repo, merge)?;
+    checkout_options.check_out(effects, git_run_info, &repo, Some(event_tx_id), branch_name)
+}
+
+/// Parse `-` or `@{-<n>}` into the number of checkouts to go back.
+fn parse_previous_checkout_query(query: &str) -> Option<usize> {
+    if query == "-" {
+        return Some(1);
+    }
+    let num_checkouts_back: usize = query.strip_prefix("@{-")?.strip_suffix('}')?.parse().ok()?;
+    if num_checkouts_back == 0 {
+        None
+    } else {
+        Some(num_checkouts_back)
+    }
+}
+
+/// Check out the commit that `HEAD` was at `num_checkouts_back` checkouts ago,
+/// according to the event log.
+fn checkout_previous(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    query: &str,
+    num_checkouts_back: usize,
+    merge: bool,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+
+    let mut head_oid_history = event_replayer.get_cursor_head_oid_history(event_cursor);
+    if let Some(head_oid) = references_snapshot.head_oid {
+        // `HEAD` may have moved without the event log being notified, such as
+        // if the hooks aren't installed.
+        if head_oid_history.first() != Some(&head_oid) {
+            head_oid_history.insert(0, head_oid);
+        }
+    }
+
+    let target_oid = match head_oid_history.get(num_checkouts_back) {
+        Some(target_oid) => *target_oid,
+        None => {
+            writeln!(
+                effects.get_error_stream(),
+                "No previous checkout found for {}.",
+                query
+            )?;
+            return Ok(1);
+        }
+    };
+
+    let target_commit = repo.find_commit_or_fail(target_oid)?;
+    writeln!(
+        effects.get_output_stream(),
+        "Returning to previous checkout: {}",
+        printable_styled_string(effects.get_glyphs(), target_commit.friendly_describe()?)?
+    )?;
+    let target_oid =
+        resolve_rewritten_destination(effects, &repo, &event_replayer, event_cursor, target_oid)?;
+    let checkout_options = CheckoutOptions::new(&repo, merge)?;
+    let exit_code = checkout_options.check_out(
+        effects,
+        git_run_info,
+        &repo,
+        None,
+        get_checkout_target(&references_snapshot, target_oid),
+    )?;
+    if exit_code == 0 {
+        let dag = Dag::open_and_sync(
+            effects,
+            &repo,
+            &event_replayer,
+            event_cursor,
+            &references_snapshot,
+        )?;
+        warn_if_rewritten(effects, &dag, &event_replayer, event_cursor, target_oid)?;
+    }
+    Ok(exit_code)
+}
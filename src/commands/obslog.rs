@@ -0,0 +1,272 @@
+//! Show the rewrite history of a commit, in the style of `hg obslog`.
+//!
+//! The history is reconstructed from the rewrite events in the event log.
+//! Starting from the given commit, the newest versions are found by following
+//! the rewrites forwards. Then each version is printed along with the versions
+//! which it was rewritten from, recursively, and the commands which rewrote
+//! them.
+
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use tracing::instrument;
+
+use crate::core::effects::Effects;
+use crate::core::eventlog::{Event, EventLogDb, EventTransactionId};
+use crate::core::formatting::printable_styled_string;
+use crate::core::node_descriptors::RelativeTimeDescriptor;
+use crate::git::{get_diff_stat, get_patch_text, GitRunInfo, MaybeZeroOid, NonZeroOid, Repo};
+
+/// A rewrite of one version of a commit into another.
+#[derive(Clone, Copy, Debug)]
+struct Rewrite {
+    old_commit_oid: NonZeroOid,
+    new_commit_oid: NonZeroOid,
+    event_tx_id: EventTransactionId,
+    timestamp: SystemTime,
+}
+
+/// The rewrites recorded in the event log, indexed in both directions.
+#[derive(Debug, Default)]
+struct RewriteGraph {
+    successors: HashMap<NonZeroOid, Vec<Rewrite>>,
+    predecessors: HashMap<NonZeroOid, Vec<Rewrite>>,
+}
+
+impl RewriteGraph {
+    fn from_events(events: &[Event]) -> Self {
+        let mut graph = Self::default();
+        for event in events {
+            if let Event::RewriteEvent {
+                timestamp: _,
+                event_tx_id,
+                old_commit_oid: MaybeZeroOid::NonZero(old_commit_oid),
+                new_commit_oid: MaybeZeroOid::NonZero(new_commit_oid),
+            } = event
+            {
+                if old_commit_oid == new_commit_oid {
+                    continue;
+                }
+                let rewrite = Rewrite {
+                    old_commit_oid: *old_commit_oid,
+                    new_commit_oid: *new_commit_oid,
+                    event_tx_id: *event_tx_id,
+                    timestamp: event.get_timestamp(),
+                };
+                graph
+                    .successors
+                    .entry(*old_commit_oid)
+                    .or_default()
+                    .push(rewrite);
+                graph
+                    .predecessors
+                    .entry(*new_commit_oid)
+                    .or_default()
+                    .push(rewrite);
+            }
+        }
+        graph
+    }
+
+    /// Find the newest versions of the given commit, i.e. those which
+    /// haven't been rewritten, by following the rewrites forwards.
+    fn find_newest_versions(&self, commit_oid: NonZeroOid) -> Vec<NonZeroOid> {
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![commit_oid];
+        while let Some(commit_oid) = stack.pop() {
+            if !seen.insert(commit_oid) {
+                continue;
+            }
+            match self.successors.get(&commit_oid) {
+                Some(rewrites) => {
+                    stack.extend(rewrites.iter().rev().map(|rewrite| rewrite.new_commit_oid))
+                }
+                None => result.push(commit_oid),
+            }
+        }
+        if result.is_empty() {
+            // Every version was rewritten into another one, such as after an
+            // undo, so just start from the given commit.
+            result.push(commit_oid);
+        }
+        result
+    }
+}
+
+/// Prints the versions of a commit and the rewrites between them.
+struct ObslogPrinter<'a> {
+    effects: &'a Effects,
+    repo: &'a Repo,
+    event_log_db: &'a EventLogDb<'a>,
+    graph: &'a RewriteGraph,
+    head_oid: Option<NonZeroOid>,
+    now: SystemTime,
+    show_time: bool,
+    stat: bool,
+    patch: bool,
+    found_missing_version: Cell<bool>,
+}
+
+impl ObslogPrinter<'_> {
+    fn print_version(
+        &self,
+        commit_oid: NonZeroOid,
+        seen: &mut HashSet<NonZeroOid>,
+    ) -> eyre::Result<()> {
+        let glyphs = self.effects.get_glyphs();
+        let is_head = self.head_oid == Some(commit_oid);
+        let is_obsolete = self.graph.successors.contains_key(&commit_oid);
+        let cursor = match (is_obsolete, is_head) {
+            (false, false) => glyphs.commit_visible,
+            (false, true) => glyphs.commit_visible_head,
+            (true, false) => glyphs.commit_obsolete,
+            (true, true) => glyphs.commit_obsolete_head,
+        };
+        let description = match self.repo.find_commit(commit_oid)? {
+            Some(commit) => printable_styled_string(glyphs, commit.friendly_describe()?)?,
+            None => {
+                self.found_missing_version.set(true);
+                format!("{} (no longer exists)", commit_oid)
+            }
+        };
+        writeln!(
+            self.effects.get_output_stream(),
+            "{} {}",
+            cursor,
+            description
+        )?;
+        if !seen.insert(commit_oid) {
+            return Ok(());
+        }
+
+        // Print the older versions, oldest rewrite last, so that the history
+        // reads from newest to oldest.
+        let rewrites = match self.graph.predecessors.get(&commit_oid) {
+            Some(rewrites) => rewrites.iter().rev().copied().collect::<Vec<_>>(),
+            None => return Ok(()),
+        };
+        for rewrite in rewrites {
+            self.print_rewrite(&rewrite)?;
+            self.print_version(rewrite.old_commit_oid, seen)?;
+        }
+        Ok(())
+    }
+
+    fn print_rewrite(&self, rewrite: &Rewrite) -> eyre::Result<()> {
+        let glyphs = self.effects.get_glyphs();
+        let command = match (
+            self.event_log_db
+                .get_transaction_command(rewrite.event_tx_id)?,
+            self.event_log_db
+                .get_transaction_message(rewrite.event_tx_id)?,
+        ) {
+            (Some(command), _) => format!("`{}`", command),
+            // Transactions recorded by older versions only have a message.
+            (None, Some(message)) => format!("`{}`", message),
+            (None, None) => "an unknown command".to_string(),
+        };
+        let time = if self.show_time {
+            format!(
+                " {} ago",
+                RelativeTimeDescriptor::describe_time_delta(self.now, rewrite.timestamp)?
+            )
+        } else {
+            String::new()
+        };
+        writeln!(
+            self.effects.get_output_stream(),
+            "{} Rewritten by {}{}",
+            glyphs.line,
+            command,
+            time
+        )?;
+
+        if !self.stat && !self.patch {
+            return Ok(());
+        }
+        let (old_commit, new_commit) = match (
+            self.repo.find_commit(rewrite.old_commit_oid)?,
+            self.repo.find_commit(rewrite.new_commit_oid)?,
+        ) {
+            (Some(old_commit), Some(new_commit)) => (old_commit, new_commit),
+            _ => return Ok(()),
+        };
+        let mut lines = Vec::new();
+        if self.stat {
+            lines.extend(
+                get_diff_stat(self.repo, old_commit.get_oid(), new_commit.get_oid())?
+                    .render(usize::MAX),
+            );
+        }
+        if self.patch {
+            let patch = get_patch_text(
+                self.repo,
+                Some(&old_commit.get_tree()?),
+                &new_commit.get_tree()?,
+            )?;
+            lines.extend(
+                String::from_utf8_lossy(&patch)
+                    .lines()
+                    .map(|line| line.to_string()),
+            );
+        }
+        for line in lines {
+            writeln!(self.effects.get_output_stream(), "{} {}", glyphs.line, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Show the rewrite history of the given commit.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
+pub fn obslog(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    commit: String,
+    stat: bool,
+    patch: bool,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+
+    let commit_oid = match repo.revparse_single_commit(&commit)? {
+        Some(commit) => commit.get_oid(),
+        None => {
+            writeln!(effects.get_output_stream(), "Commit not found: {}", commit)?;
+            return Ok(1);
+        }
+    };
+
+    let graph = RewriteGraph::from_events(&event_log_db.get_events()?);
+    let printer = ObslogPrinter {
+        effects,
+        repo: &repo,
+        event_log_db: &event_log_db,
+        graph: &graph,
+        head_oid: repo.get_head_info()?.oid,
+        now,
+        show_time: RelativeTimeDescriptor::new(&repo, now)?.is_enabled(),
+        stat,
+        patch,
+        found_missing_version: Cell::new(false),
+    };
+    let mut seen = HashSet::new();
+    for newest_commit_oid in graph.find_newest_versions(commit_oid) {
+        printer.print_version(newest_commit_oid, &mut seen)?;
+    }
+    if printer.found_missing_version.get() && repo.is_shallow() {
+        writeln!(
+            effects.get_output_stream(),
+            "Some versions may be beyond the boundary of this shallow clone. Run `git fetch --unshallow` to fetch the full history."
+        )?;
+    }
+
+    Ok(0)
+}
@@ -0,0 +1,105 @@
+//! Print the commits matching a revset expression.
+//!
+//! See the `revset` module for the syntax of revset expressions.
+
+use std::fmt::Write;
+
+use tracing::instrument;
+
+use crate::core::dag::{sort_commit_set, CommitSet, Dag};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::core::formatting::printable_styled_string;
+use crate::core::revset::resolve_revsets;
+use crate::git::{GitRunInfo, Repo};
+use crate::opts::QueryFormat;
+
+/// Escape a string for inclusion in JSON output.
+fn json_escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Evaluate the given revset expression and print the matching commits,
+/// ancestors first.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
+pub fn query(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    revset: String,
+    format: &QueryFormat,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let commits: CommitSet = match resolve_revsets(effects, &repo, &mut dag, &[revset])? {
+        Ok(commit_sets) => commit_sets
+            .into_iter()
+            .fold(CommitSet::empty(), |acc, commits| acc.union(&commits)),
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(1);
+        }
+    };
+    let commits = sort_commit_set(&repo, &dag, &commits)?;
+
+    match format {
+        QueryFormat::Oid => {
+            for commit in commits {
+                writeln!(effects.get_output_stream(), "{}", commit.get_oid())?;
+            }
+        }
+
+        QueryFormat::Summary => {
+            for commit in commits {
+                writeln!(
+                    effects.get_output_stream(),
+                    "{}",
+                    printable_styled_string(effects.get_glyphs(), commit.friendly_describe()?)?
+                )?;
+            }
+        }
+
+        QueryFormat::Json => {
+            let mut entries = Vec::new();
+            for commit in commits {
+                entries.push(format!(
+                    r#"  {{"oid": "{}", "summary": "{}"}}"#,
+                    commit.get_oid(),
+                    json_escape(&commit.get_summary()?.to_string_lossy())
+                ));
+            }
+            if entries.is_empty() {
+                writeln!(effects.get_output_stream(), "[]")?;
+            } else {
+                writeln!(effects.get_output_stream(), "[\n{}\n]", entries.join(",\n"))?;
+            }
+        }
+    }
+
+    Ok(0)
+}
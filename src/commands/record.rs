@@ -0,0 +1,193 @@
+//! Commit changes in the working copy.
+//!
+//! Unlike a plain `git commit`, the working copy is snapshotted before
+//! committing, so that undoing the commit restores the committed changes to the
+//! working copy.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::io::{stdin, BufReader};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use itertools::Itertools;
+
+use crate::commands::smartlog::smartlog;
+use crate::core::config::get_commit_signoff;
+use crate::core::effects::Effects;
+use crate::core::eventlog::EventLogDb;
+use crate::core::snapshot::create_snapshot;
+use crate::git::{FileStatus, GitRunInfo, Pathspecs, Repo};
+use crate::opts::CommitOptions;
+use crate::tui::prompt_choose_paths;
+
+/// Commit the changes in the working copy.
+///
+/// By default, all changes to tracked files are committed. If `staged` is set,
+/// only the changes staged in the index are committed (in which case
+/// `pathspecs` and `interactive` aren't supported). If `pathspecs` are
+/// given, only the changes to matching paths are committed; untracked files
+/// are only committed if they're named explicitly. If `interactive` is set,
+/// the user is prompted to choose which of the changed files to commit.
+///
+/// The commit is made with `git commit`, so the commit hooks and
+/// `commit.template` apply as usual.
+///
+/// Returns an exit code (0 denotes successful exit).
+pub fn record(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    messages: Vec<String>,
+    staged: bool,
+    interactive: bool,
+    pathspecs: Vec<String>,
+    commit_options: &CommitOptions,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = event_log_db.make_transaction_id(now, "record")?;
+
+    if repo.get_head_info()?.is_unborn() {
+        writeln!(
+            effects.get_output_stream(),
+            "No commit is currently checked out. Use `git commit` to create the first commit."
+        )?;
+        return Ok(1);
+    }
+    let index = repo.get_index()?;
+    if index.has_conflicts() {
+        writeln!(
+            effects.get_output_stream(),
+            "Cannot record, because there are unresolved merge conflicts. Resolve the merge conflicts and try again."
+        )?;
+        return Ok(1);
+    }
+
+    let pathspecs = Pathspecs::new(&repo, &git_run_info.working_directory, &pathspecs)?;
+    let (paths, untracked_paths): (Vec<PathBuf>, HashSet<PathBuf>) = if staged {
+        let paths = repo
+            .get_staged_paths()?
+            .into_iter()
+            .filter(|path| pathspecs.matches(path))
+            .sorted()
+            .collect();
+        (paths, HashSet::new())
+    } else {
+        let tracked_paths = repo
+            .get_status(git_run_info, Some(event_tx_id))?
+            .into_iter()
+            .filter(|entry| {
+                let is_changed = |status: &FileStatus| match status {
+                    FileStatus::Added
+                    | FileStatus::Copied
+                    | FileStatus::Deleted
+                    | FileStatus::Modified
+                    | FileStatus::Renamed => true,
+                    FileStatus::Ignored
+                    | FileStatus::Unmerged
+                    | FileStatus::Unmodified
+                    | FileStatus::Untracked => false,
+                };
+                is_changed(&entry.index_status) || is_changed(&entry.working_copy_status)
+            })
+            .flat_map(|entry| entry.paths())
+            .filter(|path| pathspecs.matches(path));
+        // Untracked files are only recorded if they're named explicitly.
+        let untracked_paths: HashSet<PathBuf> = repo
+            .get_untracked_status_entries()?
+            .into_iter()
+            .map(|entry| entry.path)
+            .filter(|path| pathspecs.names_path(path))
+            .collect();
+        let paths = tracked_paths
+            .chain(untracked_paths.iter().cloned())
+            .sorted()
+            .dedup()
+            .collect();
+        (paths, untracked_paths)
+    };
+    if paths.is_empty() {
+        let changes = if staged {
+            "staged changes"
+        } else {
+            "uncommitted changes"
+        };
+        if pathspecs.is_empty() {
+            writeln!(
+                effects.get_output_stream(),
+                "There are no {}. Nothing to record.",
+                changes
+            )?;
+        } else {
+            writeln!(
+                effects.get_output_stream(),
+                "There are no {} in the given paths. Nothing to record.",
+                changes
+            )?;
+        }
+        return Ok(0);
+    }
+
+    let paths = if interactive {
+        match prompt_choose_paths(
+            effects,
+            &mut BufReader::new(stdin()),
+            "Changed files:",
+            &paths,
+        )? {
+            Some(paths) => paths,
+            None => {
+                writeln!(effects.get_output_stream(), "No files selected. Aborting.")?;
+                return Ok(1);
+            }
+        }
+    } else {
+        paths
+    };
+
+    // Save the uncommitted changes first, so that undoing the commit puts them
+    // back into the working copy.
+    create_snapshot(&repo, git_run_info, &mut event_log_db, event_tx_id)?;
+
+    let untracked_paths = paths
+        .iter()
+        .filter(|path| untracked_paths.contains(*path))
+        .collect_vec();
+    if !untracked_paths.is_empty() {
+        let mut args: Vec<OsString> = vec!["add".into(), "--".into()];
+        args.extend(untracked_paths.into_iter().map(OsString::from));
+        let exit_code = git_run_info.run(effects, Some(event_tx_id), &args)?;
+        if exit_code != 0 {
+            return Ok(exit_code);
+        }
+    }
+
+    let mut args: Vec<OsString> = vec!["commit".into()];
+    for message in messages {
+        args.push("-m".into());
+        args.push(message.into());
+    }
+    let CommitOptions { no_verify, signoff } = commit_options;
+    if *no_verify {
+        args.push("--no-verify".into());
+    }
+    if *signoff || get_commit_signoff(&repo)? {
+        args.push("--signoff".into());
+    }
+    // Only the selected paths are committed (with their contents in the
+    // working copy), and anything else staged is left staged.
+    if !staged {
+        args.push("--".into());
+        args.extend(paths.into_iter().map(OsString::from));
+    }
+    let exit_code = git_run_info.run(effects, Some(event_tx_id), &args)?;
+    if exit_code != 0 {
+        return Ok(exit_code);
+    }
+
+    smartlog(effects, git_run_info, &Default::default())?;
+    Ok(0)
+}
@@ -0,0 +1,372 @@
+//! Detect and fix problems with the `git-branchless` state in a repository,
+//! such as an event log which refers to commits that no longer exist.
+//!
+//! By default, problems are only reported. They're fixed only if `--apply` is
+//! passed.
+
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use eyre::Context;
+use tracing::instrument;
+
+use crate::commands::init::{
+    create_isolated_config, find_stale_config_paths, fix_stale_config_paths, install_hooks,
+    is_isolated_config_included, read_installed_hooks,
+};
+use crate::core::config::get_core_hooks_path;
+use crate::core::dag::Dag;
+use crate::core::effects::Effects;
+use crate::core::eventlog::{
+    CommitActivityStatus, Event, EventLogDb, EventReplayer, EventTransactionId, InvalidEvent,
+};
+use crate::git::{ConfigRead, GitRunInfo, NonZeroOid, Repo};
+use crate::opts::HookPosition;
+
+/// The message recorded for event transactions created by `repair`.
+const REPAIR_TRANSACTION_MESSAGE: &str = "repair";
+
+/// A problem found by `repair`.
+#[derive(Debug)]
+enum Problem {
+    /// A row in the event log couldn't be loaded.
+    InvalidEvent(InvalidEvent),
+
+    /// Events were recorded for a transaction which doesn't exist.
+    OrphanedTransaction(EventTransactionId),
+
+    /// A commit which is visible according to the event log doesn't exist in
+    /// the repository, such as if it was garbage-collected.
+    MissingCommit(NonZeroOid),
+
+    /// A hook isn't installed, or its `git-branchless` section is damaged.
+    BrokenHook { hook_type: &'static str },
+
+    /// The isolated config file isn't included from the repository's config.
+    MissingConfigInclude,
+
+    /// The isolated config file doesn't exist.
+    MissingConfigFile,
+
+    /// A setting in the isolated config refers to an absolute path for
+    /// another copy of the repository, such as if it was copied from another
+    /// machine.
+    StaleConfigPath { name: String, value: String },
+
+    /// The commit graph cache couldn't be loaded.
+    BrokenDag { message: String },
+}
+
+impl Problem {
+    fn describe(&self) -> String {
+        match self {
+            Problem::InvalidEvent(InvalidEvent {
+                event_tx_id,
+                reason,
+                ..
+            }) => format!(
+                "An event in transaction {} could not be loaded: {}",
+                event_tx_id.to_string(),
+                reason
+            ),
+            Problem::OrphanedTransaction(event_tx_id) => format!(
+                "Transaction {} has events, but the transaction itself was never recorded",
+                event_tx_id.to_string()
+            ),
+            Problem::MissingCommit(commit_oid) => format!(
+                "Commit {} is visible according to the event log, but it no longer exists",
+                commit_oid
+            ),
+            Problem::BrokenHook { hook_type } => format!(
+                "Hook {} is not installed, or its branchless section is damaged",
+                hook_type
+            ),
+            Problem::MissingConfigInclude => {
+                "The branchless config file is not included from the repository config".to_string()
+            }
+            Problem::MissingConfigFile => "The branchless config file does not exist".to_string(),
+            Problem::StaleConfigPath { name, value } => format!(
+                "Setting {} in the branchless config file refers to another copy of the repository: {}",
+                name, value
+            ),
+            Problem::BrokenDag { message } => {
+                format!("The commit graph cache could not be loaded: {}", message)
+            }
+        }
+    }
+}
+
+fn find_broken_hooks(repo: &Repo) -> eyre::Result<Vec<Problem>> {
+    let problems = read_installed_hooks(repo)?
+        .into_iter()
+        .filter_map(|hook| match (hook.contents, hook.markers_intact) {
+            (None, _) | (Some(_), Some(false)) => Some(Problem::BrokenHook {
+                hook_type: hook.hook_type,
+            }),
+            (Some(_), Some(true)) | (Some(_), None) => None,
+        })
+        .collect();
+    Ok(problems)
+}
+
+fn find_missing_commits(
+    repo: &Repo,
+    event_replayer: &EventReplayer,
+) -> eyre::Result<Vec<NonZeroOid>> {
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut missing_commit_oids = Vec::new();
+    for commit_oid in event_replayer.get_cursor_oids(event_cursor) {
+        match event_replayer.get_cursor_commit_activity_status(event_cursor, commit_oid) {
+            CommitActivityStatus::Active => {}
+            CommitActivityStatus::Inactive | CommitActivityStatus::Obsolete => continue,
+        }
+        if repo.find_commit(commit_oid)?.is_none() {
+            missing_commit_oids.push(commit_oid);
+        }
+    }
+    missing_commit_oids.sort_by_key(|commit_oid| commit_oid.to_string());
+    Ok(missing_commit_oids)
+}
+
+fn find_problems(
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+) -> eyre::Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    let (events, invalid_events) = event_log_db.get_events_and_invalid_events()?;
+    problems.extend(invalid_events.into_iter().map(Problem::InvalidEvent));
+    // Transactions whose events are all invalid will be empty once the
+    // invalid events are deleted, so they don't need to be restored.
+    problems.extend(
+        event_log_db
+            .get_orphaned_transaction_ids()?
+            .into_iter()
+            .filter(|event_tx_id| {
+                events
+                    .iter()
+                    .any(|event| event.get_event_tx_id() == *event_tx_id)
+            })
+            .map(Problem::OrphanedTransaction),
+    );
+
+    let event_replayer = EventReplayer::from_events(effects, repo, &events)?;
+    problems.extend(
+        find_missing_commits(repo, &event_replayer)?
+            .into_iter()
+            .map(Problem::MissingCommit),
+    );
+
+    problems.extend(find_broken_hooks(repo)?);
+    if !is_isolated_config_included(repo)? {
+        problems.push(Problem::MissingConfigInclude);
+    }
+    if !repo.get_config_path().exists() {
+        problems.push(Problem::MissingConfigFile);
+    }
+    problems.extend(
+        find_stale_config_paths(repo)?
+            .into_iter()
+            .map(|(name, value)| Problem::StaleConfigPath { name, value }),
+    );
+
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_cursor = event_replayer.make_default_cursor();
+    if let Err(err) = Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    ) {
+        problems.push(Problem::BrokenDag {
+            message: err.to_string(),
+        });
+    }
+
+    Ok(problems)
+}
+
+#[instrument]
+fn apply_repairs(
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &mut EventLogDb,
+    problems: Vec<Problem>,
+) -> eyre::Result<()> {
+    let now = SystemTime::now();
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+
+    let mut invalid_events = Vec::new();
+    let mut orphaned_event_tx_ids = Vec::new();
+    let mut missing_commit_oids = Vec::new();
+    let mut should_install_hooks = false;
+    let mut should_create_config = false;
+    let mut should_fix_config_paths = false;
+    for problem in problems {
+        match problem {
+            Problem::InvalidEvent(invalid_event) => invalid_events.push(invalid_event),
+            Problem::OrphanedTransaction(event_tx_id) => orphaned_event_tx_ids.push(event_tx_id),
+            Problem::MissingCommit(commit_oid) => missing_commit_oids.push(commit_oid),
+            Problem::BrokenHook { .. } => should_install_hooks = true,
+            Problem::MissingConfigInclude => should_create_config = true,
+            Problem::MissingConfigFile => {
+                should_create_config = true;
+                writeln!(
+                    effects.get_output_stream(),
+                    "The settings in the branchless config file were lost. To restore them, run: git branchless init"
+                )?;
+            }
+            Problem::StaleConfigPath { .. } => should_fix_config_paths = true,
+            Problem::BrokenDag { .. } => {
+                // The commit graph cache is always rebuilt below.
+            }
+        }
+    }
+
+    if !invalid_events.is_empty() {
+        event_log_db.delete_invalid_events(&invalid_events)?;
+        writeln!(
+            effects.get_output_stream(),
+            "Deleted {} invalid {}",
+            invalid_events.len(),
+            if invalid_events.len() == 1 {
+                "event"
+            } else {
+                "events"
+            }
+        )?;
+    }
+
+    for event_tx_id in orphaned_event_tx_ids {
+        event_log_db.restore_orphaned_transaction(event_tx_id, REPAIR_TRANSACTION_MESSAGE)?;
+        writeln!(
+            effects.get_output_stream(),
+            "Recorded missing transaction {}",
+            event_tx_id.to_string()
+        )?;
+    }
+
+    if !missing_commit_oids.is_empty() {
+        let event_tx_id = event_log_db.make_transaction_id(now, REPAIR_TRANSACTION_MESSAGE)?;
+        let events = missing_commit_oids
+            .iter()
+            .map(|commit_oid| Event::ObsoleteEvent {
+                timestamp,
+                event_tx_id,
+                commit_oid: *commit_oid,
+            })
+            .collect();
+        event_log_db.add_events(events)?;
+        for commit_oid in missing_commit_oids {
+            writeln!(
+                effects.get_output_stream(),
+                "Hid missing commit: {}",
+                commit_oid
+            )?;
+        }
+    }
+
+    // Fix the hooks directory first, so that the hooks are installed into the
+    // right place.
+    if should_fix_config_paths {
+        fix_stale_config_paths(effects, repo)?;
+        should_install_hooks = true;
+    }
+
+    if should_install_hooks {
+        install_hooks(
+            effects,
+            repo,
+            &get_core_hooks_path(repo)?,
+            HookPosition::Append,
+        )?;
+    }
+
+    if should_create_config {
+        let readonly_config = repo.get_readonly_config()?;
+        create_isolated_config(effects, repo, readonly_config.into_config())?;
+    }
+
+    // Like the commit graph cache, the smartlog cache may have been built
+    // from events which have since been deleted.
+    event_log_db.invalidate_smartlog_cache()?;
+    let dag_dir = repo.get_dag_dir();
+    match std::fs::remove_dir_all(&dag_dir) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err)
+                .wrap_err_with(|| format!("Deleting commit graph cache at: {:?}", &dag_dir))
+        }
+    }
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+    writeln!(effects.get_output_stream(), "Rebuilt commit graph cache")?;
+
+    Ok(())
+}
+
+/// Check the event log, hooks, and configuration of the repository for
+/// problems, and optionally fix them.
+///
+/// Returns an exit code (0 denotes successful exit). The exit code is nonzero
+/// if any problems were found, unless they were all fixed.
+#[instrument]
+pub fn repair(effects: &Effects, git_run_info: &GitRunInfo, apply: bool) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    writeln!(
+        effects.get_output_stream(),
+        "Event log schema version: {}",
+        event_log_db.get_schema_version()?
+    )?;
+
+    let problems = find_problems(effects, &repo, &event_log_db)?;
+    if problems.is_empty() {
+        writeln!(effects.get_output_stream(), "No problems found.")?;
+        return Ok(0);
+    }
+    for problem in &problems {
+        writeln!(
+            effects.get_output_stream(),
+            "Problem: {}",
+            problem.describe()
+        )?;
+    }
+
+    if !apply {
+        writeln!(
+            effects.get_output_stream(),
+            "To fix these problems, run: git branchless repair --apply"
+        )?;
+        return Ok(1);
+    }
+
+    apply_repairs(effects, &repo, &mut event_log_db, problems)?;
+
+    let remaining_problems = find_problems(effects, &repo, &event_log_db)?;
+    if remaining_problems.is_empty() {
+        writeln!(effects.get_output_stream(), "All problems were fixed.")?;
+        Ok(0)
+    } else {
+        for problem in &remaining_problems {
+            writeln!(
+                effects.get_output_stream(),
+                "Could not fix problem: {}",
+                problem.describe()
+            )?;
+        }
+        Ok(1)
+    }
+}
@@ -56,45 +56,219 @@
 //! ```
 
 use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::ffi::OsString;
 use std::fmt::Write;
 use std::time::SystemTime;
 
+use eden_dag::DagAlgorithm;
+use itertools::Itertools;
 use tracing::{instrument, warn};
 
-use crate::commands::smartlog::smartlog;
-use crate::core::config::get_restack_preserve_timestamps;
-use crate::core::dag::{resolve_commits, sort_commit_set, CommitSet, Dag, ResolveCommitsResult};
+use crate::commands::smartlog::{should_show_stack_smartlog, smartlog};
+use crate::core::config::{
+    get_rebase_autosquash, get_restack_preserve_timestamps, get_rewrite_sign,
+};
+use crate::core::dag::{commit_set_to_vec, sort_commit_set, CommitSet, CommitVertex, Dag};
 use crate::core::effects::Effects;
-use crate::core::eventlog::{EventCursor, EventLogDb, EventReplayer};
+use crate::core::eventlog::{EventCursor, EventLogDb, EventReplayer, EventTransactionId};
+use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::core::revset::resolve_revsets;
 use crate::core::rewrite::{
-    execute_rebase_plan, find_abandoned_children, find_rewrite_target, move_branches,
-    BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult, RebasePlanBuilder,
+    check_out_updated_head, check_rewritten_commits_are_draft, execute_rebase_plan,
+    find_abandoned_children, find_rewrite_target, move_branches, predict_merge_conflict,
+    BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult, MergeConflictInfo,
+    RebasePlan, RebasePlanBuilder,
 };
-use crate::git::{GitRunInfo, NonZeroOid, Repo};
+use crate::git::{CategorizedReferenceName, GitRunInfo, MaybeZeroOid, NonZeroOid, Repo};
 use crate::opts::MoveOptions;
 
+/// Find the obsolete commits which should be restacked for the given commits.
+///
+/// An obsolete commit stands for itself. A visible commit stands for its
+/// stack, i.e. all draft commits which share a draft ancestor with it. The
+/// obsolete commits in the stack are included, as well as any obsolete
+/// commits which were rewritten into a commit in the stack.
+#[instrument]
+fn find_obsolete_commits_in_stacks(
+    dag: &Dag,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    commit_oids: &HashSet<NonZeroOid>,
+) -> eyre::Result<HashSet<NonZeroOid>> {
+    let mut result = HashSet::new();
+    let mut visible_commits = CommitSet::empty();
+    for commit_oid in commit_oids {
+        if dag
+            .obsolete_commits
+            .contains(&CommitVertex::from(*commit_oid))?
+        {
+            result.insert(*commit_oid);
+        } else {
+            visible_commits = visible_commits.union(&CommitSet::from(*commit_oid));
+        }
+    }
+
+    let draft_commits = dag
+        .observed_commits
+        .difference(&dag.query_public_commits()?);
+    let stack_roots = dag.query().roots(
+        dag.query()
+            .ancestors(visible_commits)?
+            .intersection(&draft_commits),
+    )?;
+    let stack_commits = dag
+        .query()
+        .descendants(stack_roots)?
+        .intersection(&draft_commits);
+
+    for obsolete_oid in commit_set_to_vec(&dag.obsolete_commits)? {
+        let is_in_stack = stack_commits.contains(&CommitVertex::from(obsolete_oid))?;
+        let is_rewritten_into_stack =
+            match find_rewrite_target(event_replayer, event_cursor, obsolete_oid) {
+                Some(MaybeZeroOid::NonZero(rewritten_oid)) => {
+                    stack_commits.contains(&CommitVertex::from(rewritten_oid))?
+                }
+                Some(MaybeZeroOid::Zero) | None => false,
+            };
+        if is_in_stack || is_rewritten_into_stack {
+            result.insert(obsolete_oid);
+        }
+    }
+    Ok(result)
+}
+
+/// The abandoned children of an obsolete commit, which should be moved onto
+/// the commit that it was rewritten into.
+#[derive(Clone, Debug)]
+struct RebaseInfo {
+    dest_oid: NonZeroOid,
+    abandoned_child_oids: Vec<NonZeroOid>,
+}
+
+/// Group the rebases so that rebases which affect the same stack end up in the
+/// same group. Rebases in different groups can be carried out independently.
+fn group_rebases_by_stack(
+    dag: &Dag,
+    rebases: Vec<RebaseInfo>,
+) -> eyre::Result<Vec<Vec<RebaseInfo>>> {
+    let draft_commits = dag
+        .observed_commits
+        .difference(&dag.query_public_commits()?);
+
+    let mut groups: Vec<(HashSet<NonZeroOid>, Vec<RebaseInfo>)> = Vec::new();
+    for rebase in rebases {
+        let commits: CommitSet = rebase
+            .abandoned_child_oids
+            .iter()
+            .copied()
+            .chain(std::iter::once(rebase.dest_oid))
+            .collect();
+        let stack_roots: HashSet<NonZeroOid> = commit_set_to_vec(
+            &dag.query()
+                .roots(dag.query().ancestors(commits)?.intersection(&draft_commits))?,
+        )?
+        .into_iter()
+        .collect();
+
+        let (mut overlapping_groups, other_groups): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .partition(|(roots, _rebases)| !roots.is_disjoint(&stack_roots));
+        groups = other_groups;
+        let mut merged_roots = stack_roots;
+        let mut merged_rebases = Vec::new();
+        for (roots, rebases) in overlapping_groups.drain(..) {
+            merged_roots.extend(roots);
+            merged_rebases.extend(rebases);
+        }
+        merged_rebases.push(rebase);
+        groups.push((merged_roots, merged_rebases));
+    }
+    Ok(groups
+        .into_iter()
+        .map(|(_roots, rebases)| rebases)
+        .collect())
+}
+
+/// The rebases needed to restack a single stack, and the resulting rebase
+/// plan.
+#[derive(Debug)]
+struct StackPlan {
+    rebases: Vec<RebaseInfo>,
+    rebase_plan: RebasePlan,
+}
+
+fn build_rebase_plan(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &Dag,
+    rebases: &[RebaseInfo],
+    build_options: &BuildRebasePlanOptions,
+) -> eyre::Result<Result<Option<RebasePlan>, isize>> {
+    let mut builder = RebasePlanBuilder::new(repo, dag);
+    for RebaseInfo {
+        dest_oid,
+        abandoned_child_oids,
+    } in rebases
+    {
+        for child_oid in abandoned_child_oids {
+            builder.move_subtree(*child_oid, *dest_oid)?;
+        }
+    }
+    match builder.build(effects, build_options)? {
+        Ok(rebase_plan) => Ok(Ok(rebase_plan)),
+        Err(err) => {
+            err.describe(effects, repo)?;
+            Ok(Err(1))
+        }
+    }
+}
+
+/// Find the abandoned commits which should be restacked, and build a rebase
+/// plan for each stack containing them. If `commits` is provided, only the
+/// given obsolete commits are considered.
 #[instrument(skip(commits))]
-fn restack_commits(
+fn plan_restack_commits(
     effects: &Effects,
     repo: &Repo,
     dag: &Dag,
     event_replayer: &EventReplayer,
     event_cursor: EventCursor,
-    git_run_info: &GitRunInfo,
     commits: Option<impl IntoIterator<Item = NonZeroOid>>,
     build_options: &BuildRebasePlanOptions,
-    execute_options: &ExecuteRebasePlanOptions,
-) -> eyre::Result<isize> {
+) -> eyre::Result<Result<Vec<StackPlan>, isize>> {
     let commit_set: CommitSet = match commits {
-        Some(commits) => commits.into_iter().collect(),
+        Some(commits) => {
+            let commit_set: CommitSet = commits.into_iter().collect();
+
+            let skipped_commits = dag.obsolete_commits.difference(&commit_set);
+            for skipped_commit in sort_commit_set(repo, dag, &skipped_commits)? {
+                let abandoned_children = find_abandoned_children(
+                    dag,
+                    event_replayer,
+                    event_cursor,
+                    skipped_commit.get_oid(),
+                )?;
+                if let Some((_rewritten_oid, abandoned_child_oids)) = abandoned_children {
+                    for abandoned_child_oid in abandoned_child_oids {
+                        writeln!(
+                            effects.get_output_stream(),
+                            "Skipped abandoned commit outside of the given stacks: {}",
+                            printable_styled_string(
+                                effects.get_glyphs(),
+                                repo.friendly_describe_commit_from_oid(abandoned_child_oid)?
+                            )?
+                        )?;
+                    }
+                }
+            }
+
+            commit_set
+        }
         None => dag.obsolete_commits.clone(),
     };
     let commits = sort_commit_set(repo, dag, &commit_set)?;
 
-    struct RebaseInfo {
-        dest_oid: NonZeroOid,
-        abandoned_child_oids: Vec<NonZeroOid>,
-    }
     let rebases: Vec<RebaseInfo> = {
         let mut result = Vec::new();
         for original_commit in commits {
@@ -114,36 +288,343 @@ fn restack_commits(
         result
     };
 
-    let rebase_plan = {
-        let mut builder = RebasePlanBuilder::new(repo, dag);
-        for RebaseInfo {
-            dest_oid,
-            abandoned_child_oids,
-        } in rebases
-        {
-            for child_oid in abandoned_child_oids {
-                builder.move_subtree(child_oid, dest_oid)?;
+    let mut stack_plans = Vec::new();
+    for rebases in group_rebases_by_stack(dag, rebases)? {
+        match build_rebase_plan(effects, repo, dag, &rebases, build_options)? {
+            Ok(Some(rebase_plan)) => stack_plans.push(StackPlan {
+                rebases,
+                rebase_plan,
+            }),
+            Ok(None) => {}
+            Err(exit_code) => return Ok(Err(exit_code)),
+        }
+    }
+    Ok(Ok(stack_plans))
+}
+
+/// Find the branches which point to rewritten commits, and the commits which
+/// they should be moved to. If `commits` is provided, branches pointing to
+/// other commits are returned separately, since they won't be moved.
+#[instrument]
+fn find_abandoned_branches(
+    repo: &Repo,
+    event_replayer: &EventReplayer,
+    commits: Option<&HashSet<NonZeroOid>>,
+) -> eyre::Result<(HashMap<NonZeroOid, MaybeZeroOid>, Vec<OsString>)> {
+    let mut rewritten_oids = HashMap::new();
+    let mut skipped_branch_names = Vec::new();
+    for branch in repo.get_all_local_branches()? {
+        let branch_target = match branch.get_oid()? {
+            Some(branch_target) => branch_target,
+            None => {
+                warn!(
+                    branch_name = ?branch.into_reference().get_name(),
+                    "Branch was not a direct reference, could not resolve target"
+                );
+                continue;
+            }
+        };
+
+        if let Some(new_oid) = find_rewrite_target(
+            event_replayer,
+            event_replayer.make_default_cursor(),
+            branch_target,
+        ) {
+            match commits {
+                Some(commits) if !commits.contains(&branch_target) => {
+                    skipped_branch_names.push(branch.into_reference().get_name()?);
+                }
+                Some(_) | None => {
+                    rewritten_oids.insert(branch_target, new_oid);
+                }
             }
+        };
+    }
+    Ok((rewritten_oids, skipped_branch_names))
+}
+
+/// Describe the restack plan: for each stack, the commits which will be
+/// rewritten and the commits they will be moved onto, the branches which
+/// will move along with them, and whether a merge conflict is predicted.
+/// Then describe the abandoned branches which will be moved.
+#[instrument(skip(stack_plans))]
+fn describe_restack_plan(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    stack_plans: &[StackPlan],
+    abandoned_branches: &HashMap<NonZeroOid, MaybeZeroOid>,
+    execute_options: &ExecuteRebasePlanOptions,
+) -> eyre::Result<()> {
+    if stack_plans.is_empty() && abandoned_branches.is_empty() {
+        return Ok(());
+    }
+
+    let glyphs = effects.get_glyphs();
+    let mut output = effects.get_output_stream();
+    let describe_commit = |oid: NonZeroOid| -> eyre::Result<String> {
+        printable_styled_string(glyphs, repo.friendly_describe_commit_from_oid(oid)?)
+    };
+    let describe_branch_names = |names: &HashSet<OsString>| -> String {
+        names
+            .iter()
+            .map(|name| CategorizedReferenceName::new(name).friendly_describe())
+            .sorted()
+            .join(", ")
+    };
+    let branch_oid_to_names = repo.get_branch_oid_to_names()?;
+    // Signing a commit may prompt for a passphrase, so don't create commits
+    // just to predict merge conflicts.
+    let should_predict_merge_conflicts = !get_rewrite_sign(repo)?;
+
+    writeln!(output, "Restack plan:")?;
+    let num_stacks = stack_plans.len();
+    for (
+        i,
+        StackPlan {
+            rebases,
+            rebase_plan,
+        },
+    ) in stack_plans.iter().enumerate()
+    {
+        let num_abandoned_commits: usize = rebases
+            .iter()
+            .map(|rebase| rebase.abandoned_child_oids.len())
+            .sum();
+        let rewritten_oids = rebase_plan.get_rewritten_commit_oids();
+        writeln!(
+            output,
+            "Stack {}/{}: {} ({} in total):",
+            i + 1,
+            num_stacks,
+            Pluralize {
+                amount: num_abandoned_commits.try_into()?,
+                singular: "abandoned commit",
+                plural: "abandoned commits",
+            }
+            .to_string(),
+            Pluralize {
+                amount: rewritten_oids.len().try_into()?,
+                singular: "commit to rewrite",
+                plural: "commits to rewrite",
+            }
+            .to_string(),
+        )?;
+        for line in rebase_plan.describe_rewritten_commits(effects, repo)? {
+            writeln!(output, "{}", line)?;
         }
-        let rebase_plan = match builder.build(effects, build_options)? {
-            Ok(Some(rebase_plan)) => rebase_plan,
-            Ok(None) => {
+
+        for oid in rewritten_oids.iter() {
+            if let Some(names) = branch_oid_to_names.get(oid) {
                 writeln!(
-                    effects.get_output_stream(),
-                    "No abandoned commits to restack."
+                    output,
+                    "Will move {} along with {}",
+                    describe_branch_names(names),
+                    describe_commit(*oid)?,
                 )?;
-                return Ok(0);
             }
-            Err(err) => {
-                err.describe(effects, repo)?;
-                return Ok(1);
+        }
+
+        if !should_predict_merge_conflicts {
+            writeln!(
+                output,
+                "Merge conflicts were not predicted, since branchless.rewrite.sign is set."
+            )?;
+        } else {
+            match predict_merge_conflict(effects, git_run_info, repo, rebase_plan, execute_options)?
+            {
+                Some(MergeConflictInfo {
+                    commit_oid,
+                    conflicting_paths,
+                }) => writeln!(
+                    output,
+                    "Predicted merge conflict ({}): {}",
+                    Pluralize {
+                        amount: conflicting_paths.len().try_into()?,
+                        singular: "conflicting file",
+                        plural: "conflicting files",
+                    }
+                    .to_string(),
+                    describe_commit(commit_oid)?,
+                )?,
+                None => writeln!(output, "No merge conflicts predicted.")?,
             }
+        }
+    }
+
+    if !abandoned_branches.is_empty() {
+        writeln!(output, "Abandoned branches:")?;
+        for (old_oid, new_oid) in abandoned_branches
+            .iter()
+            .sorted_by_key(|(old_oid, _new_oid)| {
+                branch_oid_to_names.get(old_oid).map(describe_branch_names)
+            })
+        {
+            let names = match branch_oid_to_names.get(old_oid) {
+                Some(names) => names,
+                None => continue,
+            };
+            match new_oid {
+                MaybeZeroOid::NonZero(new_oid) => writeln!(
+                    output,
+                    "{} {} will move from {} to {}",
+                    glyphs.bullet_point,
+                    describe_branch_names(names),
+                    describe_commit(*old_oid)?,
+                    describe_commit(*new_oid)?,
+                )?,
+                MaybeZeroOid::Zero => writeln!(
+                    output,
+                    "{} {} will be deleted, since {} was deleted",
+                    glyphs.bullet_point,
+                    describe_branch_names(names),
+                    describe_commit(*old_oid)?,
+                )?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[instrument(skip(stack_plans))]
+fn restack_commits(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &Dag,
+    git_run_info: &GitRunInfo,
+    stack_plans: Vec<StackPlan>,
+    dry_run: bool,
+    force_rewrite_public: bool,
+    build_options: &BuildRebasePlanOptions,
+    execute_options: &ExecuteRebasePlanOptions,
+) -> eyre::Result<isize> {
+    if stack_plans.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "No abandoned commits to restack."
+        )?;
+        return Ok(0);
+    }
+
+    if !check_rewritten_commits_are_draft(
+        effects,
+        repo,
+        dag,
+        stack_plans
+            .iter()
+            .flat_map(|stack_plan| stack_plan.rebase_plan.get_rewritten_commit_oids()),
+        force_rewrite_public,
+    )? {
+        return Ok(1);
+    }
+
+    if dry_run {
+        writeln!(
+            effects.get_output_stream(),
+            "This was a dry run, so no changes were made."
+        )?;
+        return Ok(0);
+    }
+
+    // Restack each stack separately, so that a merge conflict in one stack
+    // doesn't prevent the other stacks from being restacked in-memory. An
+    // on-disk rebase can only stop for one merge conflict at a time anyways,
+    // so in that case, just restack everything at once.
+    let rebase_plans: Vec<RebasePlan> = if execute_options.force_on_disk && stack_plans.len() > 1 {
+        let rebases = stack_plans
+            .into_iter()
+            .flat_map(|stack_plan| stack_plan.rebases)
+            .collect_vec();
+        match build_rebase_plan(effects, repo, dag, &rebases, build_options)? {
+            Ok(rebase_plan) => rebase_plan.into_iter().collect(),
+            Err(exit_code) => return Ok(exit_code),
+        }
+    } else {
+        stack_plans
+            .into_iter()
+            .map(|stack_plan| stack_plan.rebase_plan)
+            .collect()
+    };
+
+    // Try to restack each stack in-memory first. Stacks which would cause a
+    // merge conflict are left until all of the other stacks are done.
+    let num_stacks = rebase_plans.len();
+    let remaining_rebase_plans = if num_stacks == 1 {
+        rebase_plans
+    } else {
+        let in_memory_execute_options = ExecuteRebasePlanOptions {
+            resolve_merge_conflicts: false,
+            force_in_memory: false,
+            ..execute_options.clone()
         };
-        rebase_plan
+        let mut conflicting_stacks = Vec::new();
+        for rebase_plan in rebase_plans {
+            match execute_rebase_plan(
+                effects,
+                git_run_info,
+                repo,
+                &rebase_plan,
+                &in_memory_execute_options,
+            )? {
+                ExecuteRebasePlanResult::Succeeded => {}
+                ExecuteRebasePlanResult::DeclinedToMerge { merge_conflict } => {
+                    conflicting_stacks.push((rebase_plan, merge_conflict));
+                }
+                ExecuteRebasePlanResult::Failed { exit_code } => {
+                    return report_restack_failure(effects, exit_code);
+                }
+            }
+        }
+        if conflicting_stacks.is_empty() {
+            writeln!(effects.get_output_stream(), "Finished restacking commits.")?;
+            return Ok(0);
+        }
+
+        if !execute_options.resolve_merge_conflicts {
+            writeln!(
+                effects.get_output_stream(),
+                "Restacked {} of {} stacks. The remaining stacks were left for manual resolution:",
+                num_stacks - conflicting_stacks.len(),
+                num_stacks,
+            )?;
+            for (_rebase_plan, merge_conflict) in conflicting_stacks.iter() {
+                merge_conflict.describe(effects, repo)?;
+            }
+            return Ok(1);
+        }
+        conflicting_stacks
+            .into_iter()
+            .map(|(rebase_plan, _merge_conflict)| rebase_plan)
+            .collect()
     };
 
+    // Only one stack can be rebased on-disk at a time, since the rebase will
+    // stop to let the user resolve the merge conflict.
+    let mut remaining_rebase_plans = remaining_rebase_plans.into_iter();
+    let rebase_plan = match remaining_rebase_plans.next() {
+        Some(rebase_plan) => rebase_plan,
+        None => return Ok(0),
+    };
     let execute_rebase_plan_result =
         execute_rebase_plan(effects, git_run_info, repo, &rebase_plan, execute_options)?;
+    let num_remaining_stacks = remaining_rebase_plans.count();
+    if num_remaining_stacks > 0 {
+        writeln!(
+            effects.get_output_stream(),
+            "Left {} for manual resolution. Run `git restack` again afterwards to restack {}.",
+            Pluralize {
+                amount: num_remaining_stacks.try_into()?,
+                singular: "other stack",
+                plural: "other stacks",
+            }
+            .to_string(),
+            if num_remaining_stacks == 1 {
+                "it"
+            } else {
+                "them"
+            },
+        )?;
+    }
     match execute_rebase_plan_result {
         ExecuteRebasePlanResult::Succeeded => {
             writeln!(effects.get_output_stream(), "Finished restacking commits.")?;
@@ -155,21 +636,23 @@ fn restack_commits(
             Ok(1)
         }
 
-        ExecuteRebasePlanResult::Failed { exit_code } => {
-            writeln!(
-                effects.get_output_stream(),
-                "Error: Could not restack commits (exit code {}).",
-                exit_code
-            )?;
-            writeln!(
-                effects.get_output_stream(),
-                "You can resolve the error and try running `git restack` again."
-            )?;
-            Ok(exit_code)
-        }
+        ExecuteRebasePlanResult::Failed { exit_code } => report_restack_failure(effects, exit_code),
     }
 }
 
+fn report_restack_failure(effects: &Effects, exit_code: isize) -> eyre::Result<isize> {
+    writeln!(
+        effects.get_output_stream(),
+        "Error: Could not restack commits (exit code {}).",
+        exit_code
+    )?;
+    writeln!(
+        effects.get_output_stream(),
+        "You can resolve the error and try running `git restack` again."
+    )?;
+    Ok(exit_code)
+}
+
 #[instrument]
 fn restack_branches(
     effects: &Effects,
@@ -177,30 +660,18 @@ fn restack_branches(
     conn: &rusqlite::Connection,
     git_run_info: &GitRunInfo,
     event_log_db: &EventLogDb,
+    commits: Option<&HashSet<NonZeroOid>>,
     options: &ExecuteRebasePlanOptions,
 ) -> eyre::Result<isize> {
     let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
-
-    let mut rewritten_oids = HashMap::new();
-    for branch in repo.get_all_local_branches()? {
-        let branch_target = match branch.get_oid()? {
-            Some(branch_target) => branch_target,
-            None => {
-                warn!(
-                    branch_name = ?branch.into_reference().get_name(),
-                    "Branch was not a direct reference, could not resolve target"
-                );
-                continue;
-            }
-        };
-
-        if let Some(new_oid) = find_rewrite_target(
-            &event_replayer,
-            event_replayer.make_default_cursor(),
-            branch_target,
-        ) {
-            rewritten_oids.insert(branch_target, new_oid);
-        };
+    let (rewritten_oids, skipped_branch_names) =
+        find_abandoned_branches(repo, &event_replayer, commits)?;
+    for branch_name in skipped_branch_names {
+        writeln!(
+            effects.get_output_stream(),
+            "Skipped abandoned branch outside of the given stacks: {}",
+            CategorizedReferenceName::new(&branch_name).friendly_describe()
+        )?;
     }
 
     if rewritten_oids.is_empty() {
@@ -209,19 +680,41 @@ fn restack_branches(
             "No abandoned branches to restack."
         )?;
     } else {
-        move_branches(
+        // If the checked-out branch is moved, then check it out again
+        // afterwards, or else the working copy will appear to have changes.
+        let head_info = repo.get_head_info()?;
+        if head_info.oid.is_some() {
+            repo.detach_head(&head_info)?;
+        }
+        let exit_code = move_branches(
             effects,
             git_run_info,
             repo,
             options.event_tx_id,
             &rewritten_oids,
         )?;
+        if exit_code != 0 {
+            return Ok(exit_code);
+        }
         writeln!(effects.get_output_stream(), "Finished restacking branches.")?;
+        let exit_code = check_out_updated_head(
+            effects,
+            git_run_info,
+            repo,
+            options.event_tx_id,
+            &rewritten_oids,
+            &head_info,
+            None,
+        )?;
+        if exit_code != 0 {
+            return Ok(exit_code);
+        }
     }
     Ok(0)
 }
 
-/// Restack all abandoned commits.
+/// Restack abandoned commits. If `commits` is non-empty, only the abandoned
+/// commits in the stacks containing those commits are restacked.
 ///
 /// Returns an exit code (0 denotes successful exit).
 #[instrument]
@@ -229,14 +722,63 @@ pub fn restack(
     effects: &Effects,
     git_run_info: &GitRunInfo,
     commits: Vec<String>,
+    dry_run: bool,
+    show_plan: bool,
     move_options: &MoveOptions,
 ) -> eyre::Result<isize> {
     let now = SystemTime::now();
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
     let conn = repo.get_db_conn()?;
     let event_log_db = EventLogDb::new(&conn)?;
     let event_tx_id = event_log_db.make_transaction_id(now, "restack")?;
-    let head_oid = repo.get_head_info()?.oid;
+    run_restack(
+        effects,
+        git_run_info,
+        event_tx_id,
+        commits,
+        dry_run,
+        show_plan,
+        move_options,
+    )
+}
+
+/// Same as `restack`, but record the rewrites as part of the given event
+/// transaction, so that they can be undone together with the operation which
+/// abandoned the commits.
+#[instrument]
+pub fn restack_in_transaction(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    event_tx_id: EventTransactionId,
+    commits: Vec<String>,
+    dry_run: bool,
+    move_options: &MoveOptions,
+) -> eyre::Result<isize> {
+    run_restack(
+        effects,
+        git_run_info,
+        event_tx_id,
+        commits,
+        dry_run,
+        false,
+        move_options,
+    )
+}
+
+#[instrument]
+fn run_restack(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    event_tx_id: EventTransactionId,
+    commits: Vec<String>,
+    dry_run: bool,
+    show_plan: bool,
+    move_options: &MoveOptions,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
 
     let references_snapshot = repo.get_references_snapshot()?;
     let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
@@ -249,52 +791,98 @@ pub fn restack(
         &references_snapshot,
     )?;
 
-    let commits = match resolve_commits(effects, &repo, &mut dag, commits)? {
-        ResolveCommitsResult::Ok { commits } => commits,
-        ResolveCommitsResult::CommitNotFound { commit } => {
-            writeln!(effects.get_output_stream(), "Commit not found: {}", commit)?;
-            return Ok(1);
-        }
-    };
     let commits: Option<HashSet<NonZeroOid>> = if commits.is_empty() {
         None
     } else {
-        Some(commits.into_iter().map(|commit| commit.get_oid()).collect())
+        let commit_oids = match resolve_revsets(effects, &repo, &mut dag, &commits)? {
+            Ok(commit_sets) => {
+                let mut commit_oids = HashSet::new();
+                for commit_set in commit_sets {
+                    commit_oids.extend(commit_set_to_vec(&commit_set)?);
+                }
+                commit_oids
+            }
+            Err(err) => {
+                err.describe(effects)?;
+                return Ok(1);
+            }
+        };
+        Some(find_obsolete_commits_in_stacks(
+            &dag,
+            &event_replayer,
+            event_cursor,
+            &commit_oids,
+        )?)
     };
 
     let MoveOptions {
         force_in_memory,
         force_on_disk,
         resolve_merge_conflicts,
+        preserve_timestamps,
+        drop_merges,
+        keep_empty,
+        autosquash,
+        force_rewrite_public,
+        update_worktrees,
         dump_rebase_constraints,
         dump_rebase_plan,
     } = *move_options;
     let build_options = BuildRebasePlanOptions {
         dump_rebase_constraints,
         dump_rebase_plan,
-        detect_duplicate_commits_via_patch_id: true,
+        detect_duplicate_commits_via_patch_id: !keep_empty,
+        drop_merges,
+        keep_empty,
+        autosquash: autosquash || get_rebase_autosquash(&repo)?,
     };
     let execute_options = ExecuteRebasePlanOptions {
         now,
         event_tx_id,
-        preserve_timestamps: get_restack_preserve_timestamps(&repo)?,
+        preserve_timestamps: preserve_timestamps || get_restack_preserve_timestamps(&repo)?,
         force_in_memory,
         force_on_disk,
         resolve_merge_conflicts,
+        update_worktrees,
     };
 
-    let result = restack_commits(
+    let stack_plans = match plan_restack_commits(
         effects,
         &repo,
         &dag,
         &event_replayer,
         event_cursor,
+        commits.clone(),
+        &build_options,
+    )? {
+        Ok(stack_plans) => stack_plans,
+        Err(exit_code) => return Ok(exit_code),
+    };
+    if show_plan || dry_run {
+        let (abandoned_branches, _skipped_branch_names) =
+            find_abandoned_branches(&repo, &event_replayer, commits.as_ref())?;
+        describe_restack_plan(
+            effects,
+            git_run_info,
+            &repo,
+            &stack_plans,
+            &abandoned_branches,
+            &execute_options,
+        )?;
+    }
+
+    let result = restack_commits(
+        effects,
+        &repo,
+        &dag,
         git_run_info,
-        commits,
+        stack_plans,
+        dry_run,
+        force_rewrite_public,
         &build_options,
         &execute_options,
     )?;
-    if result != 0 {
+    if result != 0 || dry_run {
         return Ok(result);
     }
 
@@ -304,21 +892,15 @@ pub fn restack(
         &conn,
         git_run_info,
         &event_log_db,
+        commits.as_ref(),
         &execute_options,
     )?;
     if result != 0 {
         return Ok(result);
     }
 
-    let result = match head_oid {
-        Some(head_oid) => git_run_info.run(
-            effects,
-            Some(event_tx_id),
-            &["checkout", &head_oid.to_string()],
-        )?,
-        None => result,
-    };
-
-    smartlog(effects, git_run_info, &Default::default())?;
+    if !should_show_stack_smartlog(effects, &repo)? {
+        smartlog(effects, git_run_info, &Default::default())?;
+    }
     Ok(result)
 }
@@ -0,0 +1,221 @@
+//! Change the messages of commits, without changing their contents.
+//!
+//! The reworded commits are created in-memory with the same trees and the new
+//! messages. Afterwards, their descendants are restacked onto them, as if the
+//! reworded commits had been amended.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use eyre::Context;
+use itertools::Itertools;
+
+use crate::commands::gc::mark_commit_reachable;
+use crate::commands::restack;
+use crate::core::commit_hooks::{prepare_commit_message, run_pre_commit_hook};
+use crate::core::config::get_restack_preserve_timestamps;
+use crate::core::dag::{resolve_commits, sort_commit_set, CommitSet, Dag, ResolveCommitsResult};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::Pluralize;
+use crate::core::rewrite::{check_rewritten_commits_are_draft, move_head_to_rewritten_commit};
+use crate::git::{GitRunInfo, NonZeroOid, Repo};
+use crate::opts::{CommitOptions, MoveOptions};
+use crate::tui::{clean_up_message, edit_message, MessageCleanupMode};
+
+/// Reword the given commits (or `HEAD`, if none are given).
+///
+/// If `messages` is empty, an editor is opened for each commit in turn, from
+/// the ancestor-most commit onwards. Otherwise, the messages are used for all
+/// of the commits.
+///
+/// As with `git commit --amend`, the `pre-commit` hook is run first, and the
+/// `commit-msg` hook is run on each new message.
+///
+/// Returns an exit code (0 denotes successful exit).
+pub fn reword(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    commits: Vec<String>,
+    messages: Vec<String>,
+    force_rewrite_public: bool,
+    commit_options: &CommitOptions,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let commits = if commits.is_empty() {
+        vec!["HEAD".to_string()]
+    } else {
+        commits
+    };
+    let commits = match resolve_commits(effects, &repo, &mut dag, commits)? {
+        ResolveCommitsResult::Ok { commits } => commits,
+        ResolveCommitsResult::CommitNotFound { commit } => {
+            writeln!(effects.get_output_stream(), "Commit not found: {}", commit)?;
+            return Ok(1);
+        }
+        ResolveCommitsResult::AmbiguousName(ambiguous_name) => {
+            writeln!(effects.get_output_stream(), "{}", ambiguous_name)?;
+            return Ok(1);
+        }
+    };
+    // Reword ancestors before their descendants, so that the reworded
+    // descendants can be created directly on top of the reworded ancestors.
+    let commit_set: CommitSet = commits.iter().map(|commit| commit.get_oid()).collect();
+    let commits = sort_commit_set(&repo, &dag, &commit_set)?;
+
+    if !check_rewritten_commits_are_draft(
+        effects,
+        &repo,
+        &dag,
+        commits.iter().map(|commit| commit.get_oid()),
+        force_rewrite_public,
+    )? {
+        return Ok(1);
+    }
+
+    let event_tx_id = event_log_db.make_transaction_id(now, "reword")?;
+    if !run_pre_commit_hook(effects, git_run_info, &repo, event_tx_id, commit_options)? {
+        writeln!(
+            effects.get_output_stream(),
+            "Aborting reword because the pre-commit hook failed."
+        )?;
+        return Ok(1);
+    }
+
+    // Get all of the new messages before rewriting anything, so that aborting
+    // one of the edits leaves all of the commits untouched.
+    let mut new_messages = Vec::new();
+    for commit in commits.iter() {
+        let message = if messages.is_empty() {
+            let message = commit.get_message_raw()?.to_string_lossy().into_owned();
+            edit_message(git_run_info, &repo, &message)?
+        } else {
+            clean_up_message(
+                &repo,
+                &messages.join("\n\n"),
+                MessageCleanupMode::from_config(&repo, false)?,
+            )?
+        };
+        if message.trim().is_empty() {
+            writeln!(
+                effects.get_output_stream(),
+                "Aborting reword due to empty commit message."
+            )?;
+            return Ok(1);
+        }
+        let message = match prepare_commit_message(
+            effects,
+            git_run_info,
+            &repo,
+            event_tx_id,
+            &message,
+            commit_options,
+        )? {
+            Some(message) => message,
+            None => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Aborting reword because the commit-msg hook failed."
+                )?;
+                return Ok(1);
+            }
+        };
+        new_messages.push(message);
+    }
+
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let preserve_timestamps = get_restack_preserve_timestamps(&repo)?;
+    let mut rewritten_oids: HashMap<NonZeroOid, NonZeroOid> = HashMap::new();
+    let mut events = Vec::new();
+    for (commit, message) in commits.iter().zip(new_messages) {
+        let parents = commit
+            .get_parent_oids()
+            .into_iter()
+            .map(|parent_oid| {
+                let parent_oid = rewritten_oids
+                    .get(&parent_oid)
+                    .copied()
+                    .unwrap_or(parent_oid);
+                repo.find_commit_or_fail(parent_oid)
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        let committer = if preserve_timestamps {
+            commit.get_committer()
+        } else {
+            commit.get_committer().update_timestamp(now)?
+        };
+        let reworded_commit_oid = repo.create_commit(
+            None,
+            &commit.get_author(),
+            &committer,
+            &message,
+            &commit.get_tree()?,
+            parents.iter().collect(),
+        )?;
+        mark_commit_reachable(&repo, reworded_commit_oid)
+            .wrap_err("Marking commit as reachable for GC purposes.")?;
+
+        rewritten_oids.insert(commit.get_oid(), reworded_commit_oid);
+        events.push(Event::RewriteEvent {
+            timestamp,
+            event_tx_id,
+            old_commit_oid: commit.get_oid().into(),
+            new_commit_oid: reworded_commit_oid.into(),
+        });
+    }
+
+    // The trees of the reworded commits are unchanged, so `HEAD` can be moved
+    // onto its reworded commit without touching the working copy. Other
+    // branches are moved as part of the restack below.
+    events.extend(move_head_to_rewritten_commit(
+        &repo,
+        now,
+        event_tx_id,
+        &rewritten_oids,
+    )?);
+    event_log_db.add_events(events)?;
+
+    writeln!(
+        effects.get_output_stream(),
+        "Reworded {}.",
+        Pluralize {
+            amount: commits.len().try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string()
+    )?;
+
+    let move_options = MoveOptions {
+        force_rewrite_public,
+        ..Default::default()
+    };
+    restack::restack_in_transaction(
+        effects,
+        git_run_info,
+        event_tx_id,
+        commits
+            .iter()
+            .map(|commit| commit.get_oid().to_string())
+            .collect_vec(),
+        false,
+        &move_options,
+    )
+}
@@ -3,25 +3,40 @@
 //! The set of commits that are still being worked on is inferred from the event
 //! log; see the `eventlog` module.
 
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
 use std::fmt::Write;
-use std::time::SystemTime;
+use std::io::{stdout, IsTerminal};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use itertools::Itertools;
 use tracing::instrument;
 
-use crate::core::dag::Dag;
+use crate::commands::init::warn_if_repo_copied;
+use crate::core::config::{
+    get_auto_smartlog, get_auto_smartlog_from_env, get_smartlog_mark_related_commits,
+    get_smartlog_show_stashes,
+};
+use crate::core::dag::{CommitSet, Dag};
 use crate::core::effects::Effects;
-use crate::core::eventlog::{EventLogDb, EventReplayer};
-use crate::core::formatting::printable_styled_string;
+use crate::core::eventlog::{EventCursor, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Pluralize};
 use crate::core::node_descriptors::{
-    BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor,
-    DifferentialRevisionDescriptor, ObsolescenceExplanationDescriptor, RelativeTimeDescriptor,
+    AnnotationDescriptor, BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor,
+    DifferentialRevisionDescriptor, NodeObject, ObsolescenceExplanationDescriptor,
+    RelatedCommitDescriptor, RelativeTimeDescriptor,
 };
-use crate::git::{GitRunInfo, Repo};
+use crate::core::revset::resolve_revsets;
+use crate::core::stash::get_stash_entries;
+use crate::git::{GitRunInfo, NonZeroOid, Repo, RepoReferencesSnapshot};
 
-pub use graph::{make_smartlog_graph, SmartlogGraph};
+pub use graph::{make_smartlog_graph, make_smartlog_graph_for_commits, SmartlogGraph};
 pub use render::{render_graph, SmartlogOptions};
 
 mod graph {
+    use std::cell::OnceCell;
     use std::collections::HashMap;
     use std::convert::TryFrom;
     use std::ops::Deref;
@@ -33,14 +48,20 @@ mod graph {
     use crate::core::effects::{Effects, OperationType};
     use crate::core::eventlog::{EventCursor, EventReplayer};
     use crate::core::node_descriptors::NodeObject;
+    use crate::core::stash::StashEntry;
     use crate::git::Commit;
     use crate::git::{NonZeroOid, Repo};
 
     /// Node contained in the smartlog commit graph.
     #[derive(Debug)]
     pub struct Node<'repo> {
-        /// The underlying commit object.
-        pub object: NodeObject<'repo>,
+        repo: &'repo Repo,
+
+        /// The OID of the underlying commit.
+        pub oid: NonZeroOid,
+
+        /// The underlying commit object, which is only loaded once it's needed.
+        object: OnceCell<NodeObject<'repo>>,
 
         /// The OID of the parent node in the smartlog commit graph.
         ///
@@ -72,28 +93,90 @@ mod graph {
         /// where you commit directly to the main branch and then later rewrite the
         /// commit.
         pub is_obsolete: bool,
+
+        /// If this node is an entry in the stash (see
+        /// `branchless.smartlog.showStashes`), its index in the stash.
+        pub stash_index: Option<usize>,
+    }
+
+    impl<'repo> Node<'repo> {
+        /// Get the underlying commit object, loading it from the repository if
+        /// it hasn't been loaded yet.
+        pub fn get_object(&self) -> eyre::Result<&NodeObject<'repo>> {
+            if let Some(object) = self.object.get() {
+                return Ok(object);
+            }
+            let object = match self.repo.find_commit(self.oid)? {
+                Some(commit) => NodeObject::Commit { commit },
+                None => {
+                    // Assume that this commit was garbage collected.
+                    NodeObject::GarbageCollected { oid: self.oid }
+                }
+            };
+            Ok(self.object.get_or_init(|| object))
+        }
     }
 
     /// Graph of commits that the user is working on.
     pub struct SmartlogGraph<'repo> {
+        repo: &'repo Repo,
         nodes: HashMap<NonZeroOid, Node<'repo>>,
     }
 
     impl<'repo> SmartlogGraph<'repo> {
         /// Get a list of commits stored in the graph.
         /// Returns commits in descending commit time order.
-        pub fn get_commits(&self) -> Vec<Commit<'repo>> {
-            let mut commits = self
-                .nodes
-                .values()
-                .filter_map(|node| match &node.object {
-                    NodeObject::Commit { commit } => Some(commit.clone()),
-                    NodeObject::GarbageCollected { oid: _ } => None,
-                })
-                .collect::<Vec<Commit<'repo>>>();
+        pub fn get_commits(&self) -> eyre::Result<Vec<Commit<'repo>>> {
+            let mut commits = Vec::new();
+            for node in self.nodes.values() {
+                match node.get_object()? {
+                    NodeObject::Commit { commit } => commits.push(commit.clone()),
+                    NodeObject::GarbageCollected { oid: _ } => {}
+                }
+            }
             commits.sort_by_key(|commit| (commit.get_committer().get_time(), commit.get_oid()));
             commits.reverse();
-            commits
+            Ok(commits)
+        }
+
+        /// Add the given stash entries to the graph, each as a child of the
+        /// commit it was created on top of. Entries whose base commit isn't in
+        /// the graph are skipped.
+        pub fn add_stash_entries(&mut self, stash_entries: &[StashEntry]) -> eyre::Result<()> {
+            // Add the oldest entries first, so that the most recent entry is
+            // rendered last among its siblings.
+            for entry in stash_entries.iter().rev() {
+                if self.nodes.contains_key(&entry.commit_oid) {
+                    continue;
+                }
+                let commit = match self.repo.find_commit(entry.commit_oid)? {
+                    Some(commit) => commit,
+                    None => continue,
+                };
+                let base_oid = match commit.get_parent_oids().first() {
+                    Some(base_oid) => *base_oid,
+                    None => continue,
+                };
+                let base_node = match self.nodes.get_mut(&base_oid) {
+                    Some(base_node) => base_node,
+                    None => continue,
+                };
+                base_node.children.push(entry.commit_oid);
+                self.nodes.insert(
+                    entry.commit_oid,
+                    Node {
+                        repo: self.repo,
+                        oid: entry.commit_oid,
+                        object: OnceCell::from(NodeObject::Commit { commit }),
+                        parent: Some(base_oid),
+                        children: Vec::new(),
+                        is_main: false,
+                        is_obsolete: false,
+                        stash_index: Some(entry.index),
+                    },
+                );
+            }
+            Ok(())
         }
     }
 
@@ -117,6 +200,9 @@ mod graph {
     /// between it and the main branch, those intermediate commits should be shown
     /// (or else you won't get a good idea of the line of development that happened
     /// for this commit since the main branch).
+    ///
+    /// No commit objects are loaded here, since the graph structure can be
+    /// determined from the DAG alone.
     #[instrument]
     fn walk_from_active_heads<'repo>(
         effects: &Effects,
@@ -141,23 +227,23 @@ mod graph {
                 for vertex in path_to_main_branch.iter_rev()? {
                     let vertex = vertex?;
                     let oid = NonZeroOid::try_from(vertex.clone())?;
-
-                    let object = match repo.find_commit(oid)? {
-                        Some(commit) => NodeObject::Commit { commit },
-                        None => {
-                            // Assume that this commit was garbage collected.
-                            NodeObject::GarbageCollected { oid }
-                        }
-                    };
+                    if result.contains_key(&oid) {
+                        // Already visited as part of the path for another
+                        // head.
+                        continue;
+                    }
 
                     result.insert(
                         oid,
                         Node {
-                            object,
+                            repo,
+                            oid,
+                            object: OnceCell::new(),
                             parent: None,         // populated below
                             children: Vec::new(), // populated below
                             is_main: public_commits.contains(&vertex)?,
                             is_obsolete: dag.obsolete_commits.contains(&vertex)?,
+                            stash_index: None,
                         },
                     );
                 }
@@ -190,28 +276,35 @@ mod graph {
             graph.get_mut(parent_oid).unwrap().children.push(*child_oid);
         }
 
-        Ok(SmartlogGraph { nodes: graph })
+        Ok(SmartlogGraph { repo, nodes: graph })
     }
 
     /// Sort children nodes of the commit graph in a standard order, for determinism
     /// in output.
-    fn sort_children(graph: &mut SmartlogGraph) {
-        let commit_times: HashMap<NonZeroOid, Option<git2::Time>> = graph
-            .iter()
-            .map(|(oid, node)| {
-                (
-                    *oid,
-                    match &node.object {
-                        NodeObject::Commit { commit } => Some(commit.get_time()),
-                        NodeObject::GarbageCollected { oid: _ } => None,
-                    },
-                )
-            })
-            .collect();
+    ///
+    /// Only the commits which have siblings need to be loaded to do so.
+    fn sort_children(graph: &mut SmartlogGraph) -> eyre::Result<()> {
+        let mut commit_times: HashMap<NonZeroOid, Option<git2::Time>> = HashMap::new();
+        for node in graph.nodes.values() {
+            if node.children.len() < 2 {
+                continue;
+            }
+            for child_oid in node.children.iter() {
+                let time = match graph.nodes[child_oid].get_object()? {
+                    NodeObject::Commit { commit } => Some(commit.get_time()),
+                    NodeObject::GarbageCollected { oid: _ } => None,
+                };
+                commit_times.insert(*child_oid, time);
+            }
+        }
         for node in graph.nodes.values_mut() {
+            if node.children.len() < 2 {
+                continue;
+            }
             node.children
                 .sort_by_key(|child_oid| (commit_times[child_oid], child_oid.to_string()));
         }
+        Ok(())
     }
 
     /// Construct the smartlog graph for the repo.
@@ -249,13 +342,45 @@ mod graph {
                 &active_heads,
             )?
         };
-        sort_children(&mut graph);
+        sort_children(&mut graph)?;
+        Ok(graph)
+    }
+
+    /// Construct the smartlog graph for the given commits, along with their
+    /// paths to the main branch.
+    #[instrument]
+    pub fn make_smartlog_graph_for_commits<'repo>(
+        effects: &Effects,
+        repo: &'repo Repo,
+        dag: &Dag,
+        event_replayer: &EventReplayer,
+        event_cursor: EventCursor,
+        commits: &CommitSet,
+    ) -> eyre::Result<SmartlogGraph<'repo>> {
+        let (effects, _progress) = effects.start_operation(OperationType::MakeGraph);
+
+        let mut graph = {
+            let (effects, _progress) = effects.start_operation(OperationType::WalkCommits);
+
+            let public_commits = dag.query_public_commits()?;
+            walk_from_active_heads(
+                &effects,
+                repo,
+                dag,
+                event_replayer,
+                event_cursor,
+                &public_commits,
+                commits,
+            )?
+        };
+        sort_children(&mut graph)?;
         Ok(graph)
     }
 }
 
 mod render {
     use std::cmp::Ordering;
+    use std::path::PathBuf;
 
     use cursive::theme::Effect;
     use cursive::utils::markup::StyledString;
@@ -266,7 +391,8 @@ mod render {
     use crate::core::effects::Effects;
     use crate::core::formatting::set_effect;
     use crate::core::formatting::{Glyphs, StyledStringBuilder};
-    use crate::core::node_descriptors::{render_node_descriptors, NodeDescriptor};
+    use crate::core::node_descriptors::{render_node_descriptors, NodeDescriptor, NodeObject};
+    use crate::core::pool::{get_num_jobs, make_repo_thread_pool};
     use crate::git::{NonZeroOid, Repo};
 
     use super::graph::SmartlogGraph;
@@ -292,11 +418,14 @@ mod render {
             .collect();
 
         let compare = |lhs_oid: &NonZeroOid, rhs_oid: &NonZeroOid| -> Ordering {
-            let lhs_commit = repo.find_commit(*lhs_oid);
-            let rhs_commit = repo.find_commit(*rhs_oid);
+            let lhs_object = graph[lhs_oid].get_object();
+            let rhs_object = graph[rhs_oid].get_object();
 
-            let (lhs_commit, rhs_commit) = match (lhs_commit, rhs_commit) {
-                (Ok(Some(lhs_commit)), Ok(Some(rhs_commit))) => (lhs_commit, rhs_commit),
+            let (lhs_commit, rhs_commit) = match (lhs_object, rhs_object) {
+                (
+                    Ok(NodeObject::Commit { commit: lhs_commit }),
+                    Ok(NodeObject::Commit { commit: rhs_commit }),
+                ) => (lhs_commit, rhs_commit),
                 _ => return lhs_oid.cmp(rhs_oid),
             };
 
@@ -338,8 +467,16 @@ mod render {
         let current_node = &graph[&current_oid];
         let is_head = Some(current_oid) == head_oid;
 
-        let text = render_node_descriptors(&current_node.object, commit_descriptors)?;
+        let text = render_node_descriptors(current_node.get_object()?, commit_descriptors)?;
+        let text = match current_node.stash_index {
+            Some(stash_index) => StyledStringBuilder::new()
+                .append_plain(format!("stash@{{{}}} ", stash_index))
+                .append(text)
+                .build(),
+            None => text,
+        };
         let cursor = match (current_node.is_main, current_node.is_obsolete, is_head) {
+            _ if current_node.stash_index.is_some() => glyphs.commit_stash,
             (false, false, false) => glyphs.commit_visible,
             (false, false, true) => glyphs.commit_visible_head,
             (false, true, false) => glyphs.commit_obsolete,
@@ -501,6 +638,14 @@ mod render {
         head_oid: Option<NonZeroOid>,
         commit_descriptors: &mut [&mut dyn NodeDescriptor],
     ) -> eyre::Result<Vec<StyledString>> {
+        if get_num_jobs(repo)? > 1 && graph.len() > 1 {
+            let pool = make_repo_thread_pool(repo)?;
+            let oids: Vec<NonZeroOid> = graph.keys().copied().collect();
+            for commit_descriptor in commit_descriptors.iter_mut() {
+                commit_descriptor.prefetch(repo, &pool, &oids)?;
+            }
+        }
+
         let root_oids = split_commit_graph_by_roots(effects, repo, dag, graph);
         let lines = get_output(
             effects.get_glyphs(),
@@ -519,43 +664,389 @@ mod render {
         /// Whether to also show commits in the smartlog which would normally not be
         /// visible.
         pub show_hidden_commits: bool,
+
+        /// If set, only show the commits matching this revset expression (and
+        /// their paths to the main branch), rather than the active commits.
+        pub revset: Option<String>,
+
+        /// If set, only show the draft commits which were committed or last
+        /// had activity recorded in the event log after this date (see
+        /// `parse_since_date`), along with their paths to the main branch.
+        pub since: Option<String>,
+
+        /// If non-empty, mark the draft commits which touch any of these paths
+        /// (relative to the working directory), rather than the paths modified
+        /// in the working copy (see `branchless.smartlog.markRelatedCommits`).
+        pub touches: Vec<PathBuf>,
+
+        /// Whether to print a breakdown of how long each stage of rendering
+        /// the smartlog took, and how many commits were loaded during it.
+        pub debug_timing: bool,
     }
 }
 
+/// Records the duration of each stage of rendering the smartlog, along with
+/// the number of commits loaded (and events replayed, if applicable) during
+/// it, for `--debug-timing`.
+struct StageTimings<'repo> {
+    repo: &'repo Repo,
+    stage_start_time: Instant,
+    stage_start_commit_load_count: usize,
+    stages: Vec<(&'static str, Duration, usize, Option<usize>)>,
+}
+
+impl<'repo> StageTimings<'repo> {
+    fn new(repo: &'repo Repo) -> Self {
+        Self {
+            repo,
+            stage_start_time: Instant::now(),
+            stage_start_commit_load_count: repo.get_commit_load_count(),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Record the end of the stage with the given name, and start the next
+    /// one.
+    fn finish_stage(&mut self, name: &'static str) {
+        self.finish_stage_inner(name, None)
+    }
+
+    /// Like `finish_stage`, but also record the number of events replayed
+    /// during the stage.
+    fn finish_stage_with_events(&mut self, name: &'static str, num_events: usize) {
+        self.finish_stage_inner(name, Some(num_events))
+    }
+
+    fn finish_stage_inner(&mut self, name: &'static str, num_events: Option<usize>) {
+        let now = Instant::now();
+        let commit_load_count = self.repo.get_commit_load_count();
+        self.stages.push((
+            name,
+            now.saturating_duration_since(self.stage_start_time),
+            commit_load_count - self.stage_start_commit_load_count,
+            num_events,
+        ));
+        self.stage_start_time = now;
+        self.stage_start_commit_load_count = commit_load_count;
+    }
+
+    fn write(&self, effects: &Effects) -> eyre::Result<()> {
+        let mut stderr = effects.get_error_stream();
+        for (name, duration, commit_load_count, num_events) in self.stages.iter() {
+            let num_events = match num_events {
+                Some(num_events) => format!(
+                    ", {} replayed",
+                    Pluralize {
+                        amount: (*num_events).try_into()?,
+                        singular: "event",
+                        plural: "events",
+                    }
+                    .to_string()
+                ),
+                None => String::new(),
+            };
+            writeln!(
+                stderr,
+                "branchless: timing: {}: {} loaded{} in {:.1}ms",
+                name,
+                Pluralize {
+                    amount: (*commit_load_count).try_into()?,
+                    singular: "commit",
+                    plural: "commits",
+                }
+                .to_string(),
+                num_events,
+                duration.as_secs_f64() * 1000.0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the value of `smartlog --since`. This accepts a subset of the dates
+/// understood by Git: absolute dates such as `2021-06-01` or `2021-06-01
+/// 12:00:00 +0100` (in the local timezone if none is given), and relative
+/// dates such as `2 weeks ago` or `2.weeks.ago`.
+fn parse_since_date(value: &str, now: SystemTime) -> Option<SystemTime> {
+    let value = value.trim();
+
+    let relative_value = value.replace('.', " ");
+    if let Some(amount_and_unit) = relative_value.strip_suffix("ago") {
+        let (amount, unit) = match amount_and_unit.split_whitespace().collect_vec().as_slice() {
+            [amount, unit] => (amount.parse::<u64>().ok()?, *unit),
+            [unit] => (1, *unit),
+            _ => return None,
+        };
+        let unit_seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return None,
+        };
+        return now.checked_sub(Duration::from_secs(amount.checked_mul(unit_seconds)?));
+    }
+
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Some(date_time.into());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S %z", "%Y-%m-%d %H:%M %z"] {
+        if let Ok(date_time) = DateTime::parse_from_str(value, format) {
+            return Some(date_time.into());
+        }
+    }
+    let naive_date_time = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_hms(0, 0, 0))
+        })?;
+    Local
+        .from_local_datetime(&naive_date_time)
+        .earliest()
+        .map(SystemTime::from)
+}
+
+/// Remove the draft commits from the graph which were neither committed nor
+/// had any activity recorded in the event log after `cutoff`, except for the
+/// ancestors of the remaining commits, which are needed to connect them to
+/// the main branch. `HEAD` and the main branch are always kept.
+///
+/// Returns the filtered graph and the number of draft commits which were
+/// removed from it.
+#[instrument]
+fn filter_graph_since<'repo>(
+    effects: &Effects,
+    repo: &'repo Repo,
+    dag: &Dag,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    references_snapshot: &RepoReferencesSnapshot,
+    graph: SmartlogGraph<'repo>,
+    cutoff: SystemTime,
+) -> eyre::Result<(SmartlogGraph<'repo>, usize)> {
+    let mut kept_oids: Vec<NonZeroOid> = references_snapshot
+        .head_oid
+        .iter()
+        .chain(references_snapshot.main_branch_oid.iter())
+        .chain(references_snapshot.additional_main_branch_oids.iter())
+        .copied()
+        .filter(|oid| graph.contains_key(oid))
+        .collect();
+    for (oid, node) in graph.iter() {
+        if node.is_main {
+            continue;
+        }
+
+        let last_event_time = event_replayer
+            .get_cursor_commit_latest_event(event_cursor, *oid)
+            .map(|event| event.get_timestamp());
+        let commit_time = match node.get_object()? {
+            NodeObject::Commit { commit } => {
+                let seconds = commit.get_committer().get_time().seconds();
+                u64::try_from(seconds)
+                    .ok()
+                    .map(|seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            }
+            NodeObject::GarbageCollected { oid: _ } => None,
+        };
+        let is_recent = last_event_time
+            .into_iter()
+            .chain(commit_time)
+            .any(|time| time > cutoff);
+        if is_recent {
+            kept_oids.push(*oid);
+        }
+    }
+
+    let filtered_graph = make_smartlog_graph_for_commits(
+        effects,
+        repo,
+        dag,
+        event_replayer,
+        event_cursor,
+        &kept_oids.into_iter().collect(),
+    )?;
+    let num_removed_commits = graph
+        .iter()
+        .filter(|(oid, node)| !node.is_main && !filtered_graph.contains_key(oid))
+        .count();
+    Ok((filtered_graph, num_removed_commits))
+}
+
+/// Get the paths for `smartlog --touches`, relative to the root of the
+/// repository.
+fn resolve_touched_paths(
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    touches: &[PathBuf],
+) -> HashSet<PathBuf> {
+    let working_copy_path = repo.get_working_copy_path();
+    let relative_working_directory = working_copy_path
+        .as_ref()
+        .and_then(|working_copy_path| {
+            git_run_info
+                .working_directory
+                .strip_prefix(working_copy_path)
+                .ok()
+        })
+        .unwrap_or_else(|| Path::new(""));
+    touches
+        .iter()
+        .map(|path| {
+            let path = match &working_copy_path {
+                Some(working_copy_path) if path.is_absolute() => path
+                    .strip_prefix(working_copy_path)
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|_| path.clone()),
+                Some(_) | None => relative_working_directory.join(path),
+            };
+            path.components()
+                .filter(|component| !matches!(component, Component::CurDir))
+                .collect()
+        })
+        .collect()
+}
+
+/// Get the paths with uncommitted changes in the working copy, whether staged
+/// or not, relative to the root of the repository.
+fn get_working_copy_changed_paths(
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+) -> eyre::Result<HashSet<PathBuf>> {
+    if repo.get_working_copy_path().is_none() {
+        return Ok(HashSet::new());
+    }
+    let status = repo.get_status(git_run_info, None)?;
+    Ok(status
+        .iter()
+        .flat_map(|status_entry| status_entry.paths())
+        .collect())
+}
+
 /// Display a nice graph of commits you've recently worked on.
+///
+/// Returns an exit code (0 denotes successful exit).
 #[instrument]
 pub fn smartlog(
     effects: &Effects,
     git_run_info: &GitRunInfo,
     options: &SmartlogOptions,
-) -> eyre::Result<()> {
+) -> eyre::Result<isize> {
     let SmartlogOptions {
         show_hidden_commits,
+        revset,
+        since,
+        touches,
+        debug_timing,
     } = options;
 
     let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let mut stage_timings = StageTimings::new(&repo);
+    warn_if_repo_copied(effects, &repo)?;
     let references_snapshot = repo.get_references_snapshot()?;
     let conn = repo.get_db_conn()?;
     let event_log_db = EventLogDb::new(&conn)?;
-    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_replayer =
+        EventReplayer::from_event_log_db_incremental(effects, &repo, &event_log_db)?;
+    stage_timings.finish_stage_with_events("replay events", event_replayer.get_num_loaded_events());
     let event_cursor = event_replayer.make_default_cursor();
-    let dag = Dag::open_and_sync(
+    let mut dag = Dag::open_and_sync(
         effects,
         &repo,
         &event_replayer,
         event_cursor,
         &references_snapshot,
     )?;
+    stage_timings.finish_stage("sync commit graph");
 
-    let graph = make_smartlog_graph(
-        effects,
-        &repo,
-        &dag,
-        &event_replayer,
-        event_cursor,
-        !show_hidden_commits,
-    )?;
+    let graph = match revset {
+        None => make_smartlog_graph(
+            effects,
+            &repo,
+            &dag,
+            &event_replayer,
+            event_cursor,
+            !show_hidden_commits,
+        )?,
+        Some(revset) => {
+            let commits =
+                match resolve_revsets(effects, &repo, &mut dag, std::slice::from_ref(revset))? {
+                    Ok(commit_sets) => commit_sets
+                        .into_iter()
+                        .fold(CommitSet::empty(), |acc, commits| acc.union(&commits)),
+                    Err(err) => {
+                        err.describe(effects)?;
+                        return Ok(1);
+                    }
+                };
+            make_smartlog_graph_for_commits(
+                effects,
+                &repo,
+                &dag,
+                &event_replayer,
+                event_cursor,
+                &commits,
+            )?
+        }
+    };
+    let (graph, num_older_commits) = match since {
+        None => (graph, 0),
+        Some(since) => {
+            let cutoff = match parse_since_date(since, SystemTime::now()) {
+                Some(cutoff) => cutoff,
+                None => {
+                    writeln!(
+                        effects.get_error_stream(),
+                        "Could not parse date for --since: {:?} (expected e.g. '2021-06-01', '2021-06-01 12:00:00 +0100' or '2 weeks ago')",
+                        since
+                    )?;
+                    return Ok(1);
+                }
+            };
+            filter_graph_since(
+                effects,
+                &repo,
+                &dag,
+                &event_replayer,
+                event_cursor,
+                &references_snapshot,
+                graph,
+                cutoff,
+            )?
+        }
+    };
+    let graph = if get_smartlog_show_stashes(&repo)? {
+        let mut graph = graph;
+        graph.add_stash_entries(&get_stash_entries(&repo)?)?;
+        graph
+    } else {
+        graph
+    };
+    stage_timings.finish_stage("make graph");
 
+    let displayed_oids = graph
+        .get_commits()?
+        .iter()
+        .map(|commit| commit.get_oid())
+        .collect::<Vec<_>>();
+    let related_paths = if !touches.is_empty() {
+        resolve_touched_paths(git_run_info, &repo, touches)
+    } else if get_smartlog_mark_related_commits(&repo)? {
+        get_working_copy_changed_paths(git_run_info, &repo)?
+    } else {
+        HashSet::new()
+    };
+    let draft_oids = graph
+        .iter()
+        .filter(|(_oid, node)| !node.is_main && node.stash_index.is_none())
+        .map(|(oid, _node)| *oid)
+        .collect_vec();
     let lines = render_graph(
         effects,
         &repo,
@@ -572,8 +1063,18 @@ pub fn smartlog(
             &mut BranchesDescriptor::new(&repo, &references_snapshot)?,
             &mut DifferentialRevisionDescriptor::new(&repo)?,
             &mut CommitMessageDescriptor::new()?,
+            &mut AnnotationDescriptor::new(effects, git_run_info, &repo, &displayed_oids)?,
+            &mut RelatedCommitDescriptor::new(effects, &repo, &conn, &draft_oids, &related_paths)?,
         ],
     )?;
+    if lines.is_empty() && references_snapshot.head_oid.is_none() {
+        // `HEAD` is unborn, such as in a freshly-initialized repository, so
+        // there's nothing to render.
+        writeln!(
+            effects.get_output_stream(),
+            "No commits have been made in this repository yet."
+        )?;
+    }
     for line in lines {
         writeln!(
             effects.get_output_stream(),
@@ -581,6 +1082,79 @@ pub fn smartlog(
             printable_styled_string(effects.get_glyphs(), line)?
         )?;
     }
+    if num_older_commits > 0 {
+        writeln!(
+            effects.get_output_stream(),
+            "({} hidden; use --since to adjust)",
+            Pluralize {
+                amount: num_older_commits.try_into()?,
+                singular: "older commit",
+                plural: "older commits",
+            }
+            .to_string()
+        )?;
+    }
+    stage_timings.finish_stage("render graph");
 
-    Ok(())
+    if *debug_timing {
+        stage_timings.write(effects)?;
+        writeln!(
+            effects.get_error_stream(),
+            "branchless: timing: merge-bases: {} computed, {} cached",
+            dag.get_merge_base_compute_count(),
+            dag.get_merge_base_cache_hit_count(),
+        )?;
+    }
+    Ok(0)
+}
+
+/// Whether navigation and rewrite commands should finish by displaying a
+/// compact smartlog of the current stack, rather than the full smartlog (see
+/// `branchless.autoSmartlog`).
+#[instrument]
+pub fn should_show_stack_smartlog(effects: &Effects, repo: &Repo) -> eyre::Result<bool> {
+    if effects.is_json_output() {
+        return Ok(false);
+    }
+    match get_auto_smartlog_from_env() {
+        Some(value) => Ok(value),
+        None => Ok(get_auto_smartlog(repo)? && stdout().is_terminal()),
+    }
+}
+
+/// Display a compact smartlog containing only the current stack and `HEAD`.
+///
+/// The event replay state cached by the preceding command is reused, so only
+/// the events which that command added are replayed.
+#[instrument]
+pub fn stack_smartlog(effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<isize> {
+    smartlog(
+        effects,
+        git_run_info,
+        &SmartlogOptions {
+            revset: Some("union(stack(), HEAD)".to_string()),
+            ..Default::default()
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_since_date() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(parse_since_date("2 weeks ago", now), Some(now - day * 14));
+        assert_eq!(parse_since_date("1.day.ago", now), Some(now - day));
+        assert_eq!(parse_since_date("hour ago", now), Some(now - day / 24));
+        assert_eq!(parse_since_date("2001-09-09T01:46:40Z", now), Some(now));
+        assert_eq!(
+            parse_since_date("2001-09-09 03:46:40 +0200", now),
+            Some(now)
+        );
+        assert_eq!(parse_since_date("3 fortnights ago", now), None);
+        assert_eq!(parse_since_date("last tuesday", now), None);
+    }
 }
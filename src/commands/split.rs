@@ -0,0 +1,279 @@
+//! Split a commit into two commits.
+//!
+//! The first commit contains the changes to the selected paths, and the second
+//! commit contains the rest of the changes. Both commits are created in-memory.
+//! Since the second commit has the same tree as the original commit, the
+//! descendants of the original commit can then be restacked onto it, as if the
+//! original commit had been amended.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use eyre::Context;
+
+use crate::commands::gc::mark_commit_reachable;
+use crate::commands::restack;
+use crate::core::commit_hooks::{get_commit_template, prepare_commit_message, run_pre_commit_hook};
+use crate::core::config::get_restack_preserve_timestamps;
+use crate::core::dag::{resolve_commits, Dag, ResolveCommitsResult};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::printable_styled_string;
+use crate::core::rewrite::{check_rewritten_commits_are_draft, move_head_to_rewritten_commit};
+use crate::git::{GitRunInfo, Pathspecs, Repo};
+use crate::opts::{CommitOptions, MoveOptions};
+use crate::tui::{clean_up_message, edit_message, MessageCleanupMode};
+
+/// Split the changes to the paths matching `pathspecs` out of the given commit
+/// into a new commit, which is inserted before the commit with the remaining
+/// changes.
+///
+/// The first commit keeps the original commit message. The message of the
+/// second commit is taken from `messages` if provided, and otherwise an editor
+/// is opened to write it, starting from `commit.template` if it's set and from
+/// the original commit message otherwise. Since the second commit is a new
+/// commit, the `pre-commit` and `commit-msg` hooks are run for it, as with
+/// `git commit`.
+///
+/// Returns an exit code (0 denotes successful exit).
+pub fn split(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    commit: String,
+    pathspecs: Vec<String>,
+    messages: Vec<String>,
+    force_rewrite_public: bool,
+    commit_options: &CommitOptions,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let commit = match resolve_commits(effects, &repo, &mut dag, vec![commit])? {
+        ResolveCommitsResult::Ok { commits } => match commits.as_slice() {
+            [commit] => commit.clone(),
+            _ => eyre::bail!("Expected exactly one commit, got: {:?}", commits),
+        },
+        ResolveCommitsResult::CommitNotFound { commit } => {
+            writeln!(effects.get_output_stream(), "Commit not found: {}", commit)?;
+            return Ok(1);
+        }
+        ResolveCommitsResult::AmbiguousName(ambiguous_name) => {
+            writeln!(effects.get_output_stream(), "{}", ambiguous_name)?;
+            return Ok(1);
+        }
+    };
+    let commit_description =
+        printable_styled_string(effects.get_glyphs(), commit.friendly_describe()?)?;
+
+    let touched_paths = match repo.get_paths_touched_by_commit(&commit)? {
+        Some(touched_paths) => touched_paths,
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "Cannot split merge commit: {}",
+                commit_description
+            )?;
+            return Ok(1);
+        }
+    };
+    let pathspecs = Pathspecs::new(&repo, &git_run_info.working_directory, &pathspecs)?;
+    let selected_paths: HashSet<PathBuf> = touched_paths
+        .iter()
+        .filter(|path| pathspecs.matches(path))
+        .cloned()
+        .collect();
+    if selected_paths.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "The given paths don't match any of the changes in commit: {}",
+            commit_description
+        )?;
+        return Ok(1);
+    }
+    if selected_paths.len() == touched_paths.len() {
+        writeln!(
+            effects.get_output_stream(),
+            "The given paths match all of the changes in commit: {}",
+            commit_description
+        )?;
+        writeln!(
+            effects.get_output_stream(),
+            "Leave some of the changes unmatched to split them into a separate commit."
+        )?;
+        return Ok(1);
+    }
+
+    if !check_rewritten_commits_are_draft(
+        effects,
+        &repo,
+        &dag,
+        std::iter::once(commit.get_oid()),
+        force_rewrite_public,
+    )? {
+        return Ok(1);
+    }
+
+    let event_tx_id = event_log_db.make_transaction_id(now, "split")?;
+    if !run_pre_commit_hook(effects, git_run_info, &repo, event_tx_id, commit_options)? {
+        writeln!(
+            effects.get_output_stream(),
+            "Aborting split because the pre-commit hook failed."
+        )?;
+        return Ok(1);
+    }
+
+    let second_message = if messages.is_empty() {
+        match get_commit_template(git_run_info, &repo)? {
+            Some(template) => {
+                let message = edit_message(git_run_info, &repo, &template)?;
+                // Like `git commit`, refuse to use the template as-is.
+                let template = clean_up_message(
+                    &repo,
+                    &template,
+                    MessageCleanupMode::from_config(&repo, true)?,
+                )?;
+                if message == template {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Aborting split because the commit message template was not edited."
+                    )?;
+                    return Ok(1);
+                }
+                message
+            }
+            None => edit_message(
+                git_run_info,
+                &repo,
+                &commit.get_message_raw()?.to_string_lossy(),
+            )?,
+        }
+    } else {
+        clean_up_message(
+            &repo,
+            &messages.join("\n\n"),
+            MessageCleanupMode::from_config(&repo, false)?,
+        )?
+    };
+    if second_message.trim().is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "Aborting split due to empty commit message."
+        )?;
+        return Ok(1);
+    }
+    let second_message = match prepare_commit_message(
+        effects,
+        git_run_info,
+        &repo,
+        event_tx_id,
+        &second_message,
+        commit_options,
+    )? {
+        Some(second_message) => second_message,
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "Aborting split because the commit-msg hook failed."
+            )?;
+            return Ok(1);
+        }
+    };
+
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let committer = if get_restack_preserve_timestamps(&repo)? {
+        commit.get_committer()
+    } else {
+        commit.get_committer().update_timestamp(now)?
+    };
+
+    let first_tree = repo.get_partial_commit_tree(&commit, &selected_paths)?;
+    let first_commit_oid = repo.create_commit_with_message_from(
+        &commit.get_author(),
+        &committer,
+        &commit,
+        &first_tree,
+        commit.get_parents().iter().collect(),
+    )?;
+    let first_commit = repo.find_commit_or_fail(first_commit_oid)?;
+    let second_commit_oid = repo.create_commit(
+        None,
+        &commit.get_author(),
+        &committer,
+        &second_message,
+        &commit.get_tree()?,
+        vec![&first_commit],
+    )?;
+    for commit_oid in [first_commit_oid, second_commit_oid] {
+        mark_commit_reachable(&repo, commit_oid)
+            .wrap_err("Marking commit as reachable for GC purposes.")?;
+    }
+
+    // The original commit is considered to have been rewritten into the
+    // second commit, so that its descendants and branches are moved there.
+    let mut events = vec![
+        Event::CommitEvent {
+            timestamp,
+            event_tx_id,
+            commit_oid: first_commit_oid,
+        },
+        Event::RewriteEvent {
+            timestamp,
+            event_tx_id,
+            old_commit_oid: commit.get_oid().into(),
+            new_commit_oid: second_commit_oid.into(),
+        },
+    ];
+    let rewritten_oids: HashMap<_, _> =
+        std::iter::once((commit.get_oid(), second_commit_oid)).collect();
+    events.extend(move_head_to_rewritten_commit(
+        &repo,
+        now,
+        event_tx_id,
+        &rewritten_oids,
+    )?);
+    event_log_db.add_events(events)?;
+
+    writeln!(
+        effects.get_output_stream(),
+        "Split commit {} into:",
+        commit_description
+    )?;
+    for commit_oid in [first_commit_oid, second_commit_oid] {
+        writeln!(
+            effects.get_output_stream(),
+            "- {}",
+            printable_styled_string(
+                effects.get_glyphs(),
+                repo.friendly_describe_commit_from_oid(commit_oid)?
+            )?
+        )?;
+    }
+
+    let move_options = MoveOptions {
+        force_rewrite_public,
+        ..Default::default()
+    };
+    restack::restack_in_transaction(
+        effects,
+        git_run_info,
+        event_tx_id,
+        vec![commit.get_oid().to_string()],
+        false,
+        &move_options,
+    )
+}
@@ -0,0 +1,331 @@
+//! Combine commits into their parent.
+//!
+//! The combined commit is created in-memory, with the tree of the
+//! descendant-most squashed commit and the parents of the commit being squashed
+//! into. All of the squashed commits are recorded as having been rewritten into
+//! the combined commit, so their descendants and branches are then restacked
+//! onto it.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use eyre::Context;
+use itertools::Itertools;
+
+use crate::commands::gc::mark_commit_reachable;
+use crate::commands::restack;
+use crate::core::commit_hooks::prepare_commit_message;
+use crate::core::config::get_restack_preserve_timestamps;
+use crate::core::dag::{sort_commit_set, CommitSet, Dag};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::core::revset::resolve_revsets;
+use crate::core::rewrite::{
+    check_out_updated_head, check_rewritten_commits_are_draft, move_head_to_rewritten_commit,
+};
+use crate::git::{GitRunInfo, MaybeZeroOid, NonZeroOid, Repo};
+use crate::opts::{CommitOptions, MoveOptions};
+use crate::tui::{clean_up_message, MessageCleanupMode};
+
+/// Squash the given commits into their parent.
+///
+/// If a single commit is given, it is squashed into its parent. Otherwise, the
+/// commits must form a contiguous run, which is squashed into the
+/// ancestor-most commit of the run.
+///
+/// The message of the combined commit is taken from `messages` if provided.
+/// Otherwise, it is the message of the descendant-most commit if
+/// `use_child_message` is set, the message of the ancestor-most commit if
+/// `use_parent_message` is set, and the concatenation of all of the messages
+/// if neither is set. The `commit-msg` hook is then run on the message.
+///
+/// Returns an exit code (0 denotes successful exit).
+pub fn squash(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    commits: Vec<String>,
+    messages: Vec<String>,
+    use_child_message: bool,
+    use_parent_message: bool,
+    force_rewrite_public: bool,
+    commit_options: &CommitOptions,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let glyphs = effects.get_glyphs();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let commits = if commits.is_empty() {
+        vec!["HEAD".to_string()]
+    } else {
+        commits
+    };
+    let commit_sets = match resolve_revsets(effects, &repo, &mut dag, &commits)? {
+        Ok(commit_sets) => commit_sets,
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(1);
+        }
+    };
+    let commit_set = commit_sets
+        .iter()
+        .fold(CommitSet::empty(), |acc, commit_set| acc.union(commit_set));
+    let mut commits = sort_commit_set(&repo, &dag, &commit_set)?;
+
+    match commits.as_slice() {
+        [] => {
+            writeln!(
+                effects.get_output_stream(),
+                "There are no commits to squash."
+            )?;
+            return Ok(0);
+        }
+
+        [commit] => match commit.get_parent_oids().as_slice() {
+            [parent_oid] => {
+                let parent = repo.find_commit_or_fail(*parent_oid)?;
+                commits.insert(0, parent);
+            }
+            [] => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Cannot squash {}, because it has no parent.",
+                    printable_styled_string(glyphs, commit.friendly_describe()?)?
+                )?;
+                return Ok(1);
+            }
+            _ => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Cannot squash {}, because it is a merge commit.",
+                    printable_styled_string(glyphs, commit.friendly_describe()?)?
+                )?;
+                return Ok(1);
+            }
+        },
+
+        commits => {
+            let is_contiguous = commits
+                .iter()
+                .tuple_windows()
+                .all(|(parent, child)| child.get_parent_oids() == vec![parent.get_oid()]);
+            if !is_contiguous {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Cannot squash these commits, because they don't form a contiguous run of commits:"
+                )?;
+                for commit in commits {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "- {}",
+                        printable_styled_string(glyphs, commit.friendly_describe()?)?
+                    )?;
+                }
+                return Ok(1);
+            }
+        }
+    }
+
+    if !check_rewritten_commits_are_draft(
+        effects,
+        &repo,
+        &dag,
+        commits.iter().map(|commit| commit.get_oid()),
+        force_rewrite_public,
+    )? {
+        return Ok(1);
+    }
+
+    let (base_commit, top_commit) = match (commits.first(), commits.last()) {
+        (Some(base_commit), Some(top_commit)) => (base_commit, top_commit),
+        _ => eyre::bail!("No commits to squash"),
+    };
+    let message = if !messages.is_empty() {
+        clean_up_message(
+            &repo,
+            &messages.join("\n\n"),
+            MessageCleanupMode::from_config(&repo, false)?,
+        )?
+    } else if use_child_message {
+        top_commit.get_message_raw()?.to_string_lossy().into_owned()
+    } else if use_parent_message {
+        base_commit
+            .get_message_raw()?
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        let messages = commits
+            .iter()
+            .map(|commit| -> eyre::Result<String> {
+                Ok(commit
+                    .get_message_raw()?
+                    .to_string_lossy()
+                    .trim()
+                    .to_string())
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        clean_up_message(
+            &repo,
+            &messages.join("\n\n"),
+            MessageCleanupMode::from_config(&repo, false)?,
+        )?
+    };
+    if message.trim().is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "Aborting squash due to empty commit message."
+        )?;
+        return Ok(1);
+    }
+
+    let event_tx_id = event_log_db.make_transaction_id(now, "squash")?;
+    let message = match prepare_commit_message(
+        effects,
+        git_run_info,
+        &repo,
+        event_tx_id,
+        &message,
+        commit_options,
+    )? {
+        Some(message) => message,
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "Aborting squash because the commit-msg hook failed."
+            )?;
+            return Ok(1);
+        }
+    };
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let committer = if get_restack_preserve_timestamps(&repo)? {
+        base_commit.get_committer()
+    } else {
+        base_commit.get_committer().update_timestamp(now)?
+    };
+    let parents = base_commit
+        .get_parent_oids()
+        .into_iter()
+        .map(|parent_oid| repo.find_commit_or_fail(parent_oid))
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let squashed_commit_oid = repo.create_commit(
+        None,
+        &base_commit.get_author(),
+        &committer,
+        &message,
+        &top_commit.get_tree()?,
+        parents.iter().collect(),
+    )?;
+    mark_commit_reachable(&repo, squashed_commit_oid)
+        .wrap_err("Marking commit as reachable for GC purposes.")?;
+
+    let squashed_commit = repo.find_commit_or_fail(squashed_commit_oid)?;
+    writeln!(
+        effects.get_output_stream(),
+        "Squashed {} into {}.",
+        Pluralize {
+            amount: commits.len().try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string(),
+        printable_styled_string(glyphs, squashed_commit.friendly_describe()?)?
+    )?;
+
+    let rewritten_oids: HashMap<NonZeroOid, NonZeroOid> = commits
+        .iter()
+        .map(|commit| (commit.get_oid(), squashed_commit_oid))
+        .collect();
+    let mut events = commits
+        .iter()
+        .map(|commit| Event::RewriteEvent {
+            timestamp,
+            event_tx_id,
+            old_commit_oid: commit.get_oid().into(),
+            new_commit_oid: squashed_commit_oid.into(),
+        })
+        .collect_vec();
+
+    // The combined commit has the same tree as the descendant-most squashed
+    // commit, so if that commit is checked out, `HEAD` can be moved without
+    // touching the working copy. If another one of the squashed commits is
+    // checked out, then the combined commit has to be checked out instead.
+    let head_info = repo.get_head_info()?;
+    let head_needs_checkout = match head_info.oid {
+        Some(head_oid) if head_oid == top_commit.get_oid() => {
+            events.extend(move_head_to_rewritten_commit(
+                &repo,
+                now,
+                event_tx_id,
+                &rewritten_oids,
+            )?);
+            false
+        }
+        Some(head_oid) => rewritten_oids.contains_key(&head_oid),
+        None => false,
+    };
+    event_log_db.add_events(events)?;
+    if head_needs_checkout {
+        let exit_code = git_run_info.run(
+            effects,
+            Some(event_tx_id),
+            &["checkout", &squashed_commit_oid.to_string()],
+        )?;
+        if exit_code != 0 {
+            return Ok(exit_code);
+        }
+    }
+
+    let move_options = MoveOptions {
+        force_rewrite_public,
+        ..Default::default()
+    };
+    let exit_code = restack::restack_in_transaction(
+        effects,
+        git_run_info,
+        event_tx_id,
+        commits
+            .iter()
+            .map(|commit| commit.get_oid().to_string())
+            .collect_vec(),
+        false,
+        &move_options,
+    )?;
+    if exit_code != 0 {
+        return Ok(exit_code);
+    }
+
+    // The checkout above detached `HEAD`, but its branch has since been moved
+    // to the combined commit, so reattach `HEAD` to it.
+    if head_needs_checkout && head_info.reference_name.is_some() {
+        let rewritten_oids: HashMap<NonZeroOid, MaybeZeroOid> = rewritten_oids
+            .into_iter()
+            .map(|(old_oid, new_oid)| (old_oid, new_oid.into()))
+            .collect();
+        return check_out_updated_head(
+            effects,
+            git_run_info,
+            &repo,
+            event_tx_id,
+            &rewritten_oids,
+            &head_info,
+            None,
+        );
+    }
+
+    Ok(0)
+}
@@ -0,0 +1,801 @@
+//! Push the branches in a stack to their remotes.
+//!
+//! Branches are always pushed with `--force-with-lease`, using the
+//! remote-tracking branch as the expected value, so that changes which were
+//! pushed by someone else since the last fetch are never overwritten.
+//!
+//! Once the branches have been pushed, a description of the stack can be
+//! written to a file with `--message-file`, or passed to the command in
+//! `branchless.submit.messageCmd`, such as to create or update a pull request
+//! for each branch. The JSON form of the description has the following
+//! schema, which should only be changed in backwards-compatible ways:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "commits": [
+//!     {"oid": "<oid>", "summary": "<summary>", "branches": ["<branch>", ...]},
+//!     ...
+//!   ],
+//!   "branches": [
+//!     {
+//!       "name": "<branch>",
+//!       "remote": "<remote>",
+//!       "remote_url": "<url>" | null,
+//!       "commit_oid": "<oid>",
+//!       "old_oid": "<oid>" | null,
+//!       "new_oid": "<oid>" | null,
+//!       "status": "<status>"
+//!     },
+//!     ...
+//!   ]
+//! }
+//! ```
+//!
+//! The commits and branches are ordered from the bottom of the stack to the
+//! top. For each branch, `old_oid` and `new_oid` are the commits which the
+//! remote branch pointed to before and after submitting (`null` if it didn't
+//! exist), and `status` is one of the values of `SubmitStatus::as_str`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+use eden_dag::DagAlgorithm;
+use eyre::{eyre, Context};
+use serde_json::json;
+use tracing::instrument;
+
+use crate::core::config::get_submit_message_command;
+use crate::core::dag::{sort_commit_set, CommitSet, CommitVertex, Dag};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::core::formatting::Pluralize;
+use crate::core::revset::{eval, parse, EvalError, Expr, ResolveError};
+use crate::git::{Commit, ConfigRead, GitRunInfo, GitRunStreamingOpts, NonZeroOid, Repo};
+use crate::opts::SubmitMessageFormat;
+use crate::util::get_sh;
+
+/// What to do with a branch in the stack.
+#[derive(Debug)]
+enum BranchAction {
+    /// The branch doesn't exist on the remote, and will be created.
+    Create,
+
+    /// The branch exists on the remote at the given commit, and will be
+    /// updated.
+    Update { remote_oid: NonZeroOid },
+
+    /// The branch is already up-to-date on the remote.
+    UpToDate,
+
+    /// The branch doesn't exist on the remote, and `--create` wasn't passed.
+    SkipNoRemoteBranch,
+
+    /// The remote branch has commits which aren't in the local history, and
+    /// `--force` wasn't passed.
+    SkipDiverged { remote_oid: NonZeroOid },
+}
+
+#[derive(Debug)]
+struct BranchPlan {
+    branch_name: String,
+    local_oid: NonZeroOid,
+    action: BranchAction,
+}
+
+/// What happened to a branch when submitting it.
+#[derive(Clone, Copy, Debug)]
+enum SubmitStatus {
+    Created,
+    Updated,
+    UpToDate,
+    WouldCreate,
+    WouldUpdate,
+    SkippedNoRemoteBranch,
+    SkippedDiverged,
+    NoRemote,
+    Rejected,
+    Failed,
+}
+
+impl SubmitStatus {
+    /// The name of the status in the stack description.
+    fn as_str(&self) -> &'static str {
+        match self {
+            SubmitStatus::Created => "created",
+            SubmitStatus::Updated => "updated",
+            SubmitStatus::UpToDate => "up-to-date",
+            SubmitStatus::WouldCreate => "would-create",
+            SubmitStatus::WouldUpdate => "would-update",
+            SubmitStatus::SkippedNoRemoteBranch => "skipped-no-remote-branch",
+            SubmitStatus::SkippedDiverged => "skipped-diverged",
+            SubmitStatus::NoRemote => "no-remote",
+            SubmitStatus::Rejected => "rejected",
+            SubmitStatus::Failed => "failed",
+        }
+    }
+}
+
+/// The outcome of submitting a branch, for the stack description.
+#[derive(Debug)]
+struct SubmittedBranch {
+    branch_name: String,
+    remote: String,
+    remote_url: Option<String>,
+    commit_oid: NonZeroOid,
+    old_oid: Option<NonZeroOid>,
+    new_oid: Option<NonZeroOid>,
+    status: SubmitStatus,
+}
+
+impl SubmittedBranch {
+    fn new(
+        plan: &BranchPlan,
+        remote: &str,
+        remote_url: &Option<String>,
+        status: SubmitStatus,
+    ) -> Self {
+        let old_oid = match plan.action {
+            BranchAction::Create | BranchAction::SkipNoRemoteBranch => None,
+            BranchAction::Update { remote_oid } | BranchAction::SkipDiverged { remote_oid } => {
+                Some(remote_oid)
+            }
+            BranchAction::UpToDate => Some(plan.local_oid),
+        };
+        let new_oid = match status {
+            SubmitStatus::Created | SubmitStatus::Updated => Some(plan.local_oid),
+            SubmitStatus::UpToDate
+            | SubmitStatus::WouldCreate
+            | SubmitStatus::WouldUpdate
+            | SubmitStatus::SkippedNoRemoteBranch
+            | SubmitStatus::SkippedDiverged
+            | SubmitStatus::NoRemote
+            | SubmitStatus::Rejected
+            | SubmitStatus::Failed => old_oid,
+        };
+        SubmittedBranch {
+            branch_name: plan.branch_name.clone(),
+            remote: remote.to_string(),
+            remote_url: remote_url.clone(),
+            commit_oid: plan.local_oid,
+            old_oid,
+            new_oid,
+            status,
+        }
+    }
+}
+
+/// Build the JSON description of the submitted stack. See the module
+/// documentation for the schema.
+fn make_stack_description(
+    commits: &[Commit],
+    branches: &[SubmittedBranch],
+) -> eyre::Result<serde_json::Value> {
+    let mut commit_descriptions = Vec::new();
+    for commit in commits {
+        let branch_names: Vec<&str> = branches
+            .iter()
+            .filter(|branch| branch.commit_oid == commit.get_oid())
+            .map(|branch| branch.branch_name.as_str())
+            .collect();
+        commit_descriptions.push(json!({
+            "oid": commit.get_oid().to_string(),
+            "summary": commit.get_summary()?.to_string_lossy(),
+            "branches": branch_names,
+        }));
+    }
+    let branch_descriptions: Vec<serde_json::Value> = branches
+        .iter()
+        .map(|branch| {
+            json!({
+                "name": branch.branch_name,
+                "remote": branch.remote,
+                "remote_url": branch.remote_url,
+                "commit_oid": branch.commit_oid.to_string(),
+                "old_oid": branch.old_oid.map(|oid| oid.to_string()),
+                "new_oid": branch.new_oid.map(|oid| oid.to_string()),
+                "status": branch.status.as_str(),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "version": 1,
+        "commits": commit_descriptions,
+        "branches": branch_descriptions,
+    }))
+}
+
+/// Render the submitted stack as a Markdown table, with one row per branch.
+fn render_stack_description_markdown(
+    repo: &Repo,
+    branches: &[SubmittedBranch],
+) -> eyre::Result<String> {
+    let mut result = String::new();
+    writeln!(result, "| # | Branch | Commit | Status |")?;
+    writeln!(result, "| --- | --- | --- | --- |")?;
+    for (i, branch) in branches.iter().enumerate() {
+        let summary = match repo.find_commit(branch.commit_oid)? {
+            Some(commit) => commit.get_summary()?.to_string_lossy().into_owned(),
+            None => String::new(),
+        };
+        writeln!(
+            result,
+            "| {} | `{}` | {} {} | {} |",
+            i + 1,
+            branch.branch_name,
+            short_oid(branch.commit_oid),
+            summary.replace('|', "\\|"),
+            branch.status.as_str()
+        )?;
+    }
+    Ok(result)
+}
+
+/// Run the `branchless.submit.messageCmd` command with the given stack
+/// description on stdin, forwarding its output. Returns the command's exit
+/// code.
+fn run_message_command(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    command: &str,
+    description: &str,
+) -> eyre::Result<i32> {
+    let mut child = Command::new(get_sh().ok_or_else(|| eyre!("could not get sh"))?)
+        .arg("-c")
+        .arg(command)
+        .current_dir(
+            repo.get_working_copy_path()
+                .unwrap_or_else(|| repo.get_path()),
+        )
+        .env_clear()
+        .envs(git_run_info.env.iter())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("Running submit message command: {}", command))?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let input = description.to_string();
+    let writer = std::thread::spawn(move || {
+        // The command may exit without reading all of its input, so ignore
+        // errors due to the pipe being closed.
+        let _ignored = stdin.write_all(input.as_bytes());
+    });
+    let output = child
+        .wait_with_output()
+        .wrap_err_with(|| format!("Waiting for submit message command: {}", command))?;
+    let _ignored = writer.join();
+
+    write!(
+        effects.get_output_stream(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    )?;
+    write!(
+        effects.get_error_stream(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    )?;
+    // If the command was terminated by a signal, there's no exit code, so
+    // report it as a failure.
+    Ok(output.status.code().unwrap_or(1))
+}
+
+fn short_oid(oid: NonZeroOid) -> String {
+    oid.to_string()[..8].to_string()
+}
+
+/// Determine the remote to push the given branch to, in the same order of
+/// precedence as `git push`.
+fn get_push_remote(repo: &Repo, branch_name: &str) -> eyre::Result<String> {
+    let config = repo.get_readonly_config()?;
+    if let Some(remote) = config.get(format!("branch.{}.pushRemote", branch_name))? {
+        return Ok(remote);
+    }
+    if let Some(remote) = config.get("remote.pushDefault")? {
+        return Ok(remote);
+    }
+    Ok("origin".to_string())
+}
+
+/// Find the commits in the stacks containing the given revsets, or in the
+/// current stack if none are given.
+fn resolve_stack_commits(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &mut Dag,
+    revsets: &[String],
+) -> eyre::Result<Result<CommitSet, ResolveError>> {
+    let stack_exprs = if revsets.is_empty() {
+        vec![(
+            "stack()".to_string(),
+            Expr::FunctionCall("stack".to_string(), Vec::new()),
+        )]
+    } else {
+        let mut stack_exprs = Vec::new();
+        for revset in revsets {
+            match parse(revset) {
+                Ok(expr) => stack_exprs.push((
+                    revset.clone(),
+                    Expr::FunctionCall("stack".to_string(), vec![expr]),
+                )),
+                Err(error) => {
+                    return Ok(Err(ResolveError::ParseError {
+                        expr: revset.clone(),
+                        error,
+                    }))
+                }
+            }
+        }
+        stack_exprs
+    };
+
+    let mut commits = CommitSet::empty();
+    for (revset, expr) in stack_exprs {
+        match eval(effects, repo, dag, &expr) {
+            Ok(stack_commits) => commits = commits.union(&stack_commits),
+            Err(EvalError::Other(err)) => return Err(err),
+            Err(error) => {
+                return Ok(Err(ResolveError::EvalError {
+                    expr: revset,
+                    error,
+                }))
+            }
+        }
+    }
+    Ok(Ok(commits))
+}
+
+fn plan_branch(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &mut Dag,
+    remote: &str,
+    branch_name: String,
+    local_oid: NonZeroOid,
+    create: bool,
+    force: bool,
+) -> eyre::Result<BranchPlan> {
+    let remote_branch_name = format!("refs/remotes/{}/{}", remote, branch_name);
+    let remote_oid = match repo.find_reference(OsStr::new(&remote_branch_name))? {
+        Some(reference) => reference.peel_to_commit()?.map(|commit| commit.get_oid()),
+        None => None,
+    };
+
+    let action = match remote_oid {
+        None if create => BranchAction::Create,
+        None => BranchAction::SkipNoRemoteBranch,
+        Some(remote_oid) if remote_oid == local_oid => BranchAction::UpToDate,
+        Some(remote_oid) => {
+            // The remote branch is safe to overwrite if it points to a commit
+            // which is in the local history, including any commits which were
+            // rewritten locally (such as by amending or restacking).
+            dag.sync_from_oids(
+                effects,
+                repo,
+                CommitSet::empty(),
+                CommitSet::from(remote_oid),
+            )?;
+            let local_history = dag
+                .query()
+                .ancestors(dag.observed_commits.union(&CommitSet::from(local_oid)))?;
+            if force || local_history.contains(&CommitVertex::from(remote_oid))? {
+                BranchAction::Update { remote_oid }
+            } else {
+                BranchAction::SkipDiverged { remote_oid }
+            }
+        }
+    };
+    Ok(BranchPlan {
+        branch_name,
+        local_oid,
+        action,
+    })
+}
+
+/// Parse the output of `git push --porcelain` into a mapping from remote
+/// reference name to the status flag and summary for that reference.
+fn parse_push_porcelain(stdout: &str) -> HashMap<String, (char, String)> {
+    let mut result = HashMap::new();
+    for line in stdout.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if let [flag, refspec, summary] = fields.as_slice() {
+            let flag = match flag.chars().next() {
+                Some(flag) => flag,
+                None => continue,
+            };
+            if let Some((_from, to)) = refspec.split_once(':') {
+                result.insert(to.to_string(), (flag, summary.to_string()));
+            }
+        }
+    }
+    result
+}
+
+/// Push the branches in the current stack (or in the stacks containing the
+/// given revsets) to their remotes, then describe the submitted stack to
+/// `message_file` and `branchless.submit.messageCmd`, if provided.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
+pub fn submit(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    revsets: Vec<String>,
+    create: bool,
+    force: bool,
+    dry_run: bool,
+    message_file: Option<PathBuf>,
+    format: SubmitMessageFormat,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let stack_commits = match resolve_stack_commits(effects, &repo, &mut dag, &revsets)? {
+        Ok(stack_commits) => stack_commits,
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(1);
+        }
+    };
+    let stack_commits = sort_commit_set(&repo, &dag, &stack_commits)?;
+
+    let mut branches = Vec::new();
+    for commit in stack_commits.iter() {
+        let commit_oid = commit.get_oid();
+        let mut branch_names: Vec<String> =
+            match references_snapshot.branch_oid_to_names.get(&commit_oid) {
+                Some(branch_names) => branch_names
+                    .iter()
+                    .filter_map(|branch_name| {
+                        branch_name
+                            .to_str()
+                            .and_then(|branch_name| branch_name.strip_prefix("refs/heads/"))
+                            .map(|branch_name| branch_name.to_string())
+                    })
+                    .collect(),
+                None => Vec::new(),
+            };
+        branch_names.sort_unstable();
+        branches.extend(
+            branch_names
+                .into_iter()
+                .map(|branch_name| (branch_name, commit_oid)),
+        );
+    }
+    if branches.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "There are no branches to submit in the stack."
+        )?;
+        return Ok(0);
+    }
+
+    let branch_positions: HashMap<String, usize> = branches
+        .iter()
+        .enumerate()
+        .map(|(i, (branch_name, _local_oid))| (branch_name.clone(), i))
+        .collect();
+    let mut remote_to_plans: BTreeMap<String, Vec<BranchPlan>> = BTreeMap::new();
+    for (branch_name, local_oid) in branches {
+        let remote = get_push_remote(&repo, &branch_name)?;
+        let plan = plan_branch(
+            effects,
+            &repo,
+            &mut dag,
+            &remote,
+            branch_name,
+            local_oid,
+            create,
+            force,
+        )?;
+        remote_to_plans.entry(remote).or_default().push(plan);
+    }
+
+    let mut exit_code = 0;
+    let mut submitted_branches = Vec::new();
+    for (remote, plans) in remote_to_plans {
+        let config = repo.get_readonly_config()?;
+        let remote_url: Option<String> = config.get(format!("remote.{}.url", remote))?;
+        if remote_url.is_none() {
+            submitted_branches.extend(plans.iter().map(|plan| {
+                SubmittedBranch::new(plan, &remote, &remote_url, SubmitStatus::NoRemote)
+            }));
+            writeln!(
+                effects.get_output_stream(),
+                "No remote named {} is configured, so these branches were not pushed: {}",
+                remote,
+                plans
+                    .iter()
+                    .map(|plan| plan.branch_name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?;
+            exit_code = 1;
+            continue;
+        }
+
+        let mut args = vec![
+            "push".to_string(),
+            "--porcelain".to_string(),
+            remote.clone(),
+        ];
+        let mut refspecs = Vec::new();
+        for plan in &plans {
+            let BranchPlan {
+                branch_name,
+                local_oid,
+                action,
+            } = plan;
+            let expected_oid = match action {
+                BranchAction::Create => String::new(),
+                BranchAction::Update { remote_oid } => remote_oid.to_string(),
+                BranchAction::UpToDate => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Branch {} is already up-to-date on {}",
+                        branch_name,
+                        remote
+                    )?;
+                    submitted_branches.push(SubmittedBranch::new(
+                        plan,
+                        &remote,
+                        &remote_url,
+                        SubmitStatus::UpToDate,
+                    ));
+                    continue;
+                }
+                BranchAction::SkipNoRemoteBranch => {
+                    submitted_branches.push(SubmittedBranch::new(
+                        plan,
+                        &remote,
+                        &remote_url,
+                        SubmitStatus::SkippedNoRemoteBranch,
+                    ));
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Skipped branch {}: it does not exist on {} (use --create to push it)",
+                        branch_name,
+                        remote
+                    )?;
+                    continue;
+                }
+                BranchAction::SkipDiverged { remote_oid } => {
+                    submitted_branches.push(SubmittedBranch::new(
+                        plan,
+                        &remote,
+                        &remote_url,
+                        SubmitStatus::SkippedDiverged,
+                    ));
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Skipped branch {}: {}/{} ({}) has commits which are not in the local history (use --force to push anyway)",
+                        branch_name,
+                        remote,
+                        branch_name,
+                        short_oid(*remote_oid)
+                    )?;
+                    exit_code = 1;
+                    continue;
+                }
+            };
+
+            if dry_run {
+                match action {
+                    BranchAction::Create => {
+                        submitted_branches.push(SubmittedBranch::new(
+                            plan,
+                            &remote,
+                            &remote_url,
+                            SubmitStatus::WouldCreate,
+                        ));
+                        writeln!(
+                            effects.get_output_stream(),
+                            "Would create branch {} on {} at {}",
+                            branch_name,
+                            remote,
+                            short_oid(*local_oid)
+                        )?
+                    }
+                    BranchAction::Update { remote_oid } => {
+                        submitted_branches.push(SubmittedBranch::new(
+                            plan,
+                            &remote,
+                            &remote_url,
+                            SubmitStatus::WouldUpdate,
+                        ));
+                        writeln!(
+                            effects.get_output_stream(),
+                            "Would update branch {} on {}: {}..{}",
+                            branch_name,
+                            remote,
+                            short_oid(*remote_oid),
+                            short_oid(*local_oid)
+                        )?
+                    }
+                    BranchAction::UpToDate
+                    | BranchAction::SkipNoRemoteBranch
+                    | BranchAction::SkipDiverged { .. } => {}
+                }
+                continue;
+            }
+
+            args.push(format!(
+                "--force-with-lease=refs/heads/{}:{}",
+                branch_name, expected_oid
+            ));
+            refspecs.push(format!("{}:refs/heads/{}", local_oid, branch_name));
+        }
+        if refspecs.is_empty() {
+            continue;
+        }
+        args.extend(refspecs);
+
+        let event_tx_id = event_log_db.make_transaction_id(now, "submit")?;
+        // The porcelain output on stdout is parsed below, so only show the
+        // progress and errors which Git writes to stderr.
+        let result = git_run_info.run_streaming(
+            effects,
+            &repo,
+            Some(event_tx_id),
+            &args,
+            GitRunStreamingOpts {
+                treat_git_failure_as_error: false,
+                show_stdout: false,
+                use_terminal: false,
+            },
+        )?;
+        let statuses = parse_push_porcelain(&String::from_utf8_lossy(&result.stdout));
+        if statuses.is_empty() && result.exit_code != 0 {
+            return Ok(result.exit_code.try_into()?);
+        }
+
+        let mut num_pushed: isize = 0;
+        for plan in plans.iter() {
+            let BranchPlan {
+                branch_name,
+                local_oid,
+                action,
+            } = plan;
+            let remote_oid = match action {
+                BranchAction::Create => None,
+                BranchAction::Update { remote_oid } => Some(remote_oid),
+                BranchAction::UpToDate
+                | BranchAction::SkipNoRemoteBranch
+                | BranchAction::SkipDiverged { .. } => continue,
+            };
+            let status = match statuses.get(&format!("refs/heads/{}", branch_name)) {
+                Some(('!', summary)) | Some(('-', summary)) => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Rejected branch {}: {}",
+                        branch_name,
+                        summary
+                    )?;
+                    exit_code = 1;
+                    SubmitStatus::Rejected
+                }
+                Some(('=', _)) => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Branch {} is already up-to-date on {}",
+                        branch_name,
+                        remote
+                    )?;
+                    SubmitStatus::UpToDate
+                }
+                Some(_) => {
+                    num_pushed += 1;
+                    match remote_oid {
+                        None => {
+                            writeln!(
+                                effects.get_output_stream(),
+                                "Created branch {} on {} at {}",
+                                branch_name,
+                                remote,
+                                short_oid(*local_oid)
+                            )?;
+                            let upstream = format!("{}/{}", remote, branch_name);
+                            git_run_info.run_silent(
+                                &repo,
+                                Some(event_tx_id),
+                                &["branch", "--set-upstream-to", &upstream, branch_name],
+                                Default::default(),
+                            )?;
+                            SubmitStatus::Created
+                        }
+                        Some(remote_oid) => {
+                            writeln!(
+                                effects.get_output_stream(),
+                                "Updated branch {} on {}: {}..{}",
+                                branch_name,
+                                remote,
+                                short_oid(*remote_oid),
+                                short_oid(*local_oid)
+                            )?;
+                            SubmitStatus::Updated
+                        }
+                    }
+                }
+                None => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Failed to push branch {}",
+                        branch_name
+                    )?;
+                    exit_code = 1;
+                    SubmitStatus::Failed
+                }
+            };
+            submitted_branches.push(SubmittedBranch::new(plan, &remote, &remote_url, status));
+        }
+        if num_pushed > 0 {
+            writeln!(
+                effects.get_output_stream(),
+                "Pushed {} to {}.",
+                Pluralize {
+                    amount: num_pushed,
+                    singular: "branch",
+                    plural: "branches",
+                }
+                .to_string(),
+                remote
+            )?;
+        }
+    }
+
+    submitted_branches.sort_by_key(|branch| branch_positions.get(&branch.branch_name).copied());
+    let description = make_stack_description(&stack_commits, &submitted_branches)?;
+    let description = serde_json::to_string_pretty(&description)?;
+
+    if let Some(message_file) = message_file {
+        let contents = match format {
+            SubmitMessageFormat::Json => format!("{}\n", description),
+            SubmitMessageFormat::Markdown => {
+                render_stack_description_markdown(&repo, &submitted_branches)?
+            }
+        };
+        std::fs::write(&message_file, contents)
+            .wrap_err_with(|| format!("Writing stack description to: {:?}", &message_file))?;
+    }
+
+    if !dry_run {
+        if let Some(command) = get_submit_message_command(&repo)? {
+            // The branches have already been pushed at this point, so a
+            // failure is reported, but doesn't undo anything.
+            let command_exit_code =
+                run_message_command(effects, git_run_info, &repo, &command, &description)?;
+            if command_exit_code != 0 {
+                writeln!(
+                    effects.get_error_stream(),
+                    "The branchless.submit.messageCmd command failed with exit code {}, but the branches were still submitted: {}",
+                    command_exit_code,
+                    command
+                )?;
+                exit_code = 1;
+            }
+        }
+    }
+
+    Ok(exit_code)
+}
@@ -0,0 +1,216 @@
+//! Move all of the draft stacks onto the latest version of the main branch.
+//!
+//! Each stack is moved with a separate in-memory rebase and recorded in a
+//! separate event transaction, so that a merge conflict in one stack doesn't
+//! prevent the other stacks from being moved, and so that each stack's move can
+//! be undone independently.
+
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use eden_dag::DagAlgorithm;
+use itertools::Itertools;
+
+use crate::commands::smartlog::smartlog;
+use crate::core::config::{get_rebase_autosquash, get_restack_preserve_timestamps};
+use crate::core::dag::{sort_commit_set, CommitVertex, Dag, StackRoot};
+use crate::core::effects::Effects;
+use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::core::rewrite::{
+    execute_rebase_plan, BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
+    RebasePlanBuilder,
+};
+use crate::git::{GitRunInfo, GitRunStreamingOpts, Repo};
+
+/// Move each draft stack whose base is an out-of-date main branch commit onto
+/// the current commit of the main branch it's based on. If `pull` is set, run `git fetch` first.
+///
+/// Returns an exit code (0 denotes successful exit).
+pub fn sync(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    pull: bool,
+    autosquash: bool,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+
+    if pull {
+        let event_tx_id = event_log_db.make_transaction_id(now, "sync fetch")?;
+        let result = git_run_info.run_streaming(
+            effects,
+            &repo,
+            Some(event_tx_id),
+            &["fetch", "--all"],
+            GitRunStreamingOpts {
+                treat_git_failure_as_error: false,
+                ..Default::default()
+            },
+        )?;
+        if result.exit_code != 0 {
+            return Ok(result.exit_code.try_into()?);
+        }
+    }
+
+    let main_branch_oid = match repo.get_main_branch_oid()? {
+        Some(main_branch_oid) => main_branch_oid,
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "The main branch has not been created yet. Nothing to sync."
+            )?;
+            return Ok(0);
+        }
+    };
+    let references_snapshot = repo.get_references_snapshot()?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let public_commits = dag.query_public_commits()?;
+    let draft_commits = dag
+        .observed_commits
+        .difference(&dag.obsolete_commits)
+        .difference(&public_commits);
+    let stack_roots = dag.query().roots(draft_commits)?;
+    let stack_roots = sort_commit_set(&repo, &dag, &stack_roots)?;
+
+    let build_options = BuildRebasePlanOptions {
+        dump_rebase_constraints: false,
+        dump_rebase_plan: false,
+        detect_duplicate_commits_via_patch_id: true,
+        drop_merges: false,
+        keep_empty: false,
+        autosquash: autosquash || get_rebase_autosquash(&repo)?,
+    };
+    let preserve_timestamps = get_restack_preserve_timestamps(&repo)?;
+    let shallow_commit_oids = repo.get_shallow_commit_oids()?;
+    let mut num_synced_stacks: usize = 0;
+    let mut conflicting_roots = Vec::new();
+    for root in stack_roots {
+        // The base of a stack rooted at the shallow clone boundary isn't
+        // available, so we can't tell which main branch commit it's based on.
+        if shallow_commit_oids.contains(&root.get_oid()) {
+            writeln!(
+                effects.get_output_stream(),
+                "Not moving stack at {}, as its base commit is beyond the boundary of this shallow clone.",
+                printable_styled_string(effects.get_glyphs(), root.friendly_describe()?)?
+            )?;
+            writeln!(
+                effects.get_output_stream(),
+                "Run `git fetch --unshallow` to fetch the full history, and then try again."
+            )?;
+            continue;
+        }
+
+        // Stacks based on abandoned commits are left for `git restack`.
+        if dag.find_stack_root(root.get_oid())? != StackRoot::Root(root.get_oid()) {
+            continue;
+        }
+        let parent_oid = match root.get_only_parent_oid() {
+            Some(parent_oid) => parent_oid,
+            None => continue,
+        };
+
+        // If there are several main branches, move the stack onto the one
+        // that it was based on.
+        let mut target_oid = main_branch_oid;
+        if !dag.query().is_ancestor(
+            CommitVertex::from(parent_oid),
+            CommitVertex::from(main_branch_oid),
+        )? {
+            for candidate_oid in references_snapshot.additional_main_branch_oids.iter() {
+                if dag.query().is_ancestor(
+                    CommitVertex::from(parent_oid),
+                    CommitVertex::from(*candidate_oid),
+                )? {
+                    target_oid = *candidate_oid;
+                    break;
+                }
+            }
+        }
+
+        if parent_oid == target_oid {
+            writeln!(
+                effects.get_output_stream(),
+                "Not moving up-to-date stack at {}",
+                printable_styled_string(effects.get_glyphs(), root.friendly_describe()?)?
+            )?;
+            continue;
+        }
+
+        let mut builder = RebasePlanBuilder::new(&repo, &dag);
+        builder.move_subtree(root.get_oid(), target_oid)?;
+        let rebase_plan = match builder.build(effects, &build_options)? {
+            Ok(Some(rebase_plan)) => rebase_plan,
+            Ok(None) => continue,
+            Err(err) => {
+                err.describe(effects, &repo)?;
+                return Ok(1);
+            }
+        };
+
+        let event_tx_id = event_log_db.make_transaction_id(now, "sync")?;
+        let execute_options = ExecuteRebasePlanOptions {
+            now,
+            event_tx_id,
+            preserve_timestamps,
+            force_in_memory: false,
+            force_on_disk: false,
+            resolve_merge_conflicts: false,
+            update_worktrees: false,
+        };
+        match execute_rebase_plan(effects, git_run_info, &repo, &rebase_plan, &execute_options)? {
+            ExecuteRebasePlanResult::Succeeded => {
+                num_synced_stacks += 1;
+            }
+            ExecuteRebasePlanResult::DeclinedToMerge { merge_conflict: _ } => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Skipped stack at {} due to a merge conflict.",
+                    printable_styled_string(effects.get_glyphs(), root.friendly_describe()?)?
+                )?;
+                conflicting_roots.push(root);
+            }
+            ExecuteRebasePlanResult::Failed { exit_code } => return Ok(exit_code),
+        }
+    }
+
+    let synced_stacks = Pluralize {
+        amount: num_synced_stacks.try_into()?,
+        singular: "stack",
+        plural: "stacks",
+    }
+    .to_string();
+    if conflicting_roots.is_empty() {
+        writeln!(effects.get_output_stream(), "{} synced.", synced_stacks)?;
+    } else {
+        writeln!(
+            effects.get_output_stream(),
+            "{} synced, {} skipped due to conflicts: {}",
+            synced_stacks,
+            conflicting_roots.len(),
+            conflicting_roots
+                .iter()
+                .map(|root| root.get_oid().to_string()[..8].to_string())
+                .join(", ")
+        )?;
+        writeln!(
+            effects.get_output_stream(),
+            "To resolve merge conflicts, move the skipped stacks with `git move --merge`."
+        )?;
+    }
+
+    smartlog(effects, git_run_info, &Default::default())?;
+    Ok(if conflicting_roots.is_empty() { 0 } else { 1 })
+}
@@ -0,0 +1,463 @@
+//! Run a command on each commit in a stack, such as to check that every commit
+//! builds, and report which commits failed.
+//!
+//! Commits are checked out into temporary worktrees, so that the main working
+//! copy (and any build artifacts in it) are left untouched. The temporary
+//! worktrees are removed once testing is done, including if the user
+//! interrupts it.
+
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use eyre::{eyre, Context};
+use rayon::{prelude::*, ThreadPoolBuilder};
+use tracing::{instrument, warn};
+
+use crate::core::dag::{sort_commit_set, CommitSet, Dag};
+use crate::core::effects::{Effects, OperationType};
+use crate::core::eventlog::{init_db_schema, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::core::revset::resolve_revsets;
+use crate::git::{GitRunInfo, GitRunOpts, NonZeroOid, Repo};
+use crate::util::get_sh;
+
+/// The outcome of testing a single commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TestStatus {
+    /// The command exited successfully.
+    Passed,
+
+    /// The command previously exited successfully for this commit, so it
+    /// wasn't run again.
+    PassedCached,
+
+    /// The command exited with the given non-zero exit code.
+    Failed { exit_code: i32 },
+
+    /// The commit wasn't tested because testing was interrupted.
+    Skipped,
+}
+
+impl TestStatus {
+    fn describe(&self) -> String {
+        match self {
+            TestStatus::Passed => "passed".to_string(),
+            TestStatus::PassedCached => "passed (cached)".to_string(),
+            TestStatus::Failed { exit_code } => format!("failed (exit code {})", exit_code),
+            TestStatus::Skipped => "skipped".to_string(),
+        }
+    }
+}
+
+/// Stores the results of previous test runs, so that commits which already
+/// passed don't need to be tested again.
+struct TestResultsDb<'conn> {
+    conn: &'conn rusqlite::Connection,
+}
+
+impl std::fmt::Debug for TestResultsDb<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<TestResultsDb>")
+    }
+}
+
+impl<'conn> TestResultsDb<'conn> {
+    #[instrument]
+    fn new(conn: &'conn rusqlite::Connection) -> eyre::Result<Self> {
+        init_db_schema(conn)?;
+        Ok(TestResultsDb { conn })
+    }
+
+    /// Get the exit code of the last time the command was run on the given
+    /// commit, if any.
+    #[instrument]
+    fn get_exit_code(&self, commit_oid: NonZeroOid, command: &str) -> eyre::Result<Option<i32>> {
+        let mut stmt = self.conn.prepare(
+            "
+SELECT exit_code
+FROM test_results
+WHERE commit_oid = :commit_oid AND command = :command
+",
+        )?;
+        let mut rows = stmt.query(rusqlite::named_params! {
+            ":commit_oid": commit_oid.to_string(),
+            ":command": command,
+        })?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    #[instrument]
+    fn set_exit_code(
+        &self,
+        commit_oid: NonZeroOid,
+        command: &str,
+        exit_code: i32,
+        now: SystemTime,
+    ) -> eyre::Result<()> {
+        let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+        self.conn.execute(
+            "
+INSERT OR REPLACE INTO test_results VALUES (
+    :commit_oid,
+    :command,
+    :exit_code,
+    :timestamp
+)
+",
+            rusqlite::named_params! {
+                ":commit_oid": commit_oid.to_string(),
+                ":command": command,
+                ":exit_code": exit_code,
+                ":timestamp": timestamp,
+            },
+        )?;
+        Ok(())
+    }
+}
+
+/// A set of temporary worktrees to test commits in. The worktrees are removed
+/// when this is dropped.
+struct TestWorktrees<'a> {
+    git_run_info: &'a GitRunInfo,
+
+    /// The path of the main repository, which the worktrees belong to.
+    repo_path: PathBuf,
+
+    /// The directory containing all of the worktrees.
+    root_dir: PathBuf,
+
+    /// An empty directory, used as the hooks directory when running Git in
+    /// the worktrees, so that checking out commits there isn't recorded in
+    /// the event log.
+    hooks_dir: PathBuf,
+
+    /// The worktrees which aren't currently being used to test a commit.
+    available: Mutex<Vec<PathBuf>>,
+}
+
+impl std::fmt::Debug for TestWorktrees<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<TestWorktrees root_dir={:?}>", self.root_dir)
+    }
+}
+
+impl<'a> TestWorktrees<'a> {
+    #[instrument]
+    fn new(repo: &Repo, git_run_info: &'a GitRunInfo, num_worktrees: usize) -> eyre::Result<Self> {
+        let tempfile_dir = repo.get_tempfile_dir();
+        std::fs::create_dir_all(&tempfile_dir)
+            .wrap_err_with(|| format!("Creating temporary directory at {:?}", &tempfile_dir))?;
+        let root_dir = tempfile::Builder::new()
+            .prefix("test-")
+            .tempdir_in(&tempfile_dir)
+            .wrap_err("Creating directory for test worktrees")?
+            .into_path();
+        let hooks_dir = root_dir.join("hooks");
+        let mut worktrees = TestWorktrees {
+            git_run_info,
+            repo_path: repo.get_path().to_owned(),
+            root_dir,
+            hooks_dir,
+            available: Default::default(),
+        };
+        std::fs::create_dir(&worktrees.hooks_dir).wrap_err("Creating empty hooks directory")?;
+
+        for i in 0..num_worktrees {
+            let path = worktrees.root_dir.join(format!("worktree-{}", i));
+            let path_str = path
+                .to_str()
+                .ok_or_else(|| eyre!("Worktree path is not valid UTF-8: {:?}", &path))?;
+            worktrees.run_git(
+                repo,
+                &["worktree", "add", "--detach", "--no-checkout", path_str],
+            )?;
+            worktrees.available.get_mut().unwrap().push(path);
+        }
+        Ok(worktrees)
+    }
+
+    fn run_git(&self, repo: &Repo, args: &[&str]) -> eyre::Result<()> {
+        let hooks_path_arg = format!("core.hooksPath={}", self.hooks_dir.to_string_lossy());
+        let mut git_args = vec!["-c", &hooks_path_arg];
+        git_args.extend(args);
+        self.git_run_info
+            .run_silent(repo, None, &git_args, GitRunOpts::default())?;
+        Ok(())
+    }
+
+    /// Check out the given commit in a free worktree and call `f` with the
+    /// worktree's path.
+    fn with_worktree<T>(
+        &self,
+        commit_oid: NonZeroOid,
+        f: impl FnOnce(&Path) -> eyre::Result<T>,
+    ) -> eyre::Result<T> {
+        let path = self
+            .available
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or_else(|| eyre!("No free worktree to test commit {}", commit_oid))?;
+        let result = (|| {
+            let worktree_repo = Repo::from_dir(&path)?;
+            self.run_git(
+                &worktree_repo,
+                &["checkout", "--detach", "--force", &commit_oid.to_string()],
+            )?;
+            f(&path)
+        })();
+        self.available.lock().unwrap().push(path);
+        result
+    }
+}
+
+impl Drop for TestWorktrees<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.root_dir) {
+            warn!(?err, root_dir = ?self.root_dir, "Could not remove test worktrees");
+        }
+        let result = Repo::from_dir(&self.repo_path)
+            .and_then(|repo| self.run_git(&repo, &["worktree", "prune"]));
+        if let Err(err) = result {
+            warn!(?err, "Could not prune test worktrees");
+        }
+    }
+}
+
+/// Run the command in the given directory. Returns the exit code and the
+/// combined output of the command.
+fn run_command(
+    git_run_info: &GitRunInfo,
+    command: &str,
+    working_directory: &Path,
+) -> eyre::Result<(i32, String)> {
+    let output = Command::new(get_sh().ok_or_else(|| eyre!("could not get sh"))?)
+        .arg("-c")
+        .arg(command)
+        .current_dir(working_directory)
+        .env_clear()
+        .envs(git_run_info.env.iter())
+        .stdin(Stdio::null())
+        .output()
+        .wrap_err_with(|| format!("Running command: {}", command))?;
+    // If the command was terminated by a signal, there's no exit code, so
+    // report it as a failure.
+    let exit_code = output.status.code().unwrap_or(1);
+    let mut combined_output = String::from_utf8_lossy(&output.stdout).into_owned();
+    combined_output.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok((exit_code, combined_output))
+}
+
+/// Run `command` on each commit in the given revsets (or in the current stack,
+/// if none are given), and print a summary of which commits passed and
+/// failed.
+///
+/// Returns an exit code (0 denotes successful exit). The exit code is nonzero
+/// if any commit failed.
+#[instrument]
+pub fn test(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    revsets: Vec<String>,
+    command: String,
+    jobs: usize,
+    no_cache: bool,
+) -> eyre::Result<isize> {
+    let now = SystemTime::now();
+    let glyphs = effects.get_glyphs();
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let references_snapshot = repo.get_references_snapshot()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let test_results_db = TestResultsDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let mut dag = Dag::open_and_sync(
+        effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let revsets = if revsets.is_empty() {
+        vec!["stack()".to_string()]
+    } else {
+        revsets
+    };
+    let commit_sets = match resolve_revsets(effects, &repo, &mut dag, &revsets)? {
+        Ok(commit_sets) => commit_sets,
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(1);
+        }
+    };
+    let commits = commit_sets
+        .iter()
+        .fold(CommitSet::empty(), |acc, commit_set| acc.union(commit_set));
+    let commits = sort_commit_set(&repo, &dag, &commits)?;
+    if commits.is_empty() {
+        writeln!(effects.get_output_stream(), "There are no commits to test.")?;
+        return Ok(0);
+    }
+
+    let mut descriptions = Vec::new();
+    let mut statuses = Vec::new();
+    let mut commits_to_test = Vec::new();
+    for (index, commit) in commits.iter().enumerate() {
+        let description = printable_styled_string(glyphs, commit.friendly_describe()?)?;
+        let commit_oid = commit.get_oid();
+        let cached_exit_code = if no_cache {
+            None
+        } else {
+            test_results_db.get_exit_code(commit_oid, &command)?
+        };
+        if cached_exit_code == Some(0) {
+            writeln!(
+                effects.get_output_stream(),
+                "Passed (cached): {}",
+                description
+            )?;
+            statuses.push(TestStatus::PassedCached);
+        } else {
+            commits_to_test.push((index, commit_oid, description.clone()));
+            statuses.push(TestStatus::Skipped);
+        }
+        descriptions.push(description);
+    }
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    if !commits_to_test.is_empty() {
+        {
+            let interrupted = Arc::clone(&interrupted);
+            ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))
+                .wrap_err("Setting interrupt handler")?;
+        }
+
+        let pool = ThreadPoolBuilder::new().num_threads(jobs).build()?;
+        let num_worktrees = pool.current_num_threads().min(commits_to_test.len());
+        let worktrees = TestWorktrees::new(&repo, git_run_info, num_worktrees)?;
+
+        let (effects, progress) = effects.start_operation(OperationType::RunTests);
+        progress.notify_progress(0, commits_to_test.len());
+        let results: Vec<(usize, i32, NonZeroOid)> = pool.install(|| {
+            commits_to_test
+                .par_iter()
+                .map(
+                    |(index, commit_oid, description)| -> eyre::Result<Option<(usize, i32, NonZeroOid)>> {
+                        if interrupted.load(Ordering::SeqCst) {
+                            return Ok(None);
+                        }
+                        let (exit_code, output) = match worktrees.with_worktree(
+                            *commit_oid,
+                            |path| run_command(git_run_info, &command, path),
+                        ) {
+                            Ok(result) => result,
+                            // If checking out the commit failed because it
+                            // was interrupted, don't report it as an error.
+                            Err(_) if interrupted.load(Ordering::SeqCst) => return Ok(None),
+                            Err(err) => return Err(err),
+                        };
+                        if interrupted.load(Ordering::SeqCst) {
+                            return Ok(None);
+                        }
+
+                        let mut message = if exit_code == 0 {
+                            format!("Passed: {}\n", description)
+                        } else {
+                            format!("Failed (exit code {}): {}\n", exit_code, description)
+                        };
+                        if exit_code != 0 {
+                            for line in output.lines() {
+                                writeln!(message, "    {}", line)?;
+                            }
+                        }
+                        write!(effects.get_output_stream(), "{}", message)?;
+                        progress.notify_progress_inc(1);
+                        Ok(Some((*index, exit_code, *commit_oid)))
+                    },
+                )
+                .filter_map(|result| result.transpose())
+                .collect::<eyre::Result<_>>()
+        })?;
+        drop(worktrees);
+
+        for (index, exit_code, commit_oid) in results {
+            test_results_db.set_exit_code(commit_oid, &command, exit_code, now)?;
+            statuses[index] = if exit_code == 0 {
+                TestStatus::Passed
+            } else {
+                TestStatus::Failed { exit_code }
+            };
+        }
+    }
+
+    writeln!(
+        effects.get_output_stream(),
+        "Ran `{}` on {}:",
+        command,
+        Pluralize {
+            amount: commits.len().try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string()
+    )?;
+    let status_width = statuses
+        .iter()
+        .map(|status| status.describe().len())
+        .max()
+        .unwrap_or_default();
+    for (status, description) in statuses.iter().zip(descriptions.iter()) {
+        writeln!(
+            effects.get_output_stream(),
+            "{:width$}  {}",
+            status.describe(),
+            description,
+            width = status_width
+        )?;
+    }
+
+    let num_passed = statuses
+        .iter()
+        .filter(|status| matches!(status, TestStatus::Passed | TestStatus::PassedCached))
+        .count();
+    let num_failed = statuses
+        .iter()
+        .filter(|status| matches!(status, TestStatus::Failed { .. }))
+        .count();
+    let num_skipped = statuses
+        .iter()
+        .filter(|status| matches!(status, TestStatus::Skipped))
+        .count();
+    write!(
+        effects.get_output_stream(),
+        "{} passed, {} failed",
+        num_passed,
+        num_failed
+    )?;
+    if num_skipped > 0 {
+        write!(effects.get_output_stream(), ", {} skipped", num_skipped)?;
+    }
+    writeln!(effects.get_output_stream())?;
+
+    if interrupted.load(Ordering::SeqCst) {
+        writeln!(
+            effects.get_output_stream(),
+            "Testing was interrupted; the temporary worktrees were removed."
+        )?;
+        Ok(130)
+    } else if num_failed > 0 {
+        Ok(1)
+    } else {
+        Ok(0)
+    }
+}
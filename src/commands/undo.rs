@@ -1,12 +1,15 @@
-//! Allows undoing to a previous state of the repo.
+//! Allows undoing to a previous state of the repo, and redoing operations
+//! which were undone.
 //!
 //! This is accomplished by finding the events that have happened since a certain
 //! time and inverting them.
 
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Write;
 use std::io::{stdin, BufRead, BufReader, Read};
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::time::SystemTime;
 
@@ -16,19 +19,30 @@ use cursive::utils::markup::StyledString;
 use cursive::views::{Dialog, EditView, LinearLayout, OnEventView, Panel, ScrollView, TextView};
 use cursive::{Cursive, CursiveRunnable, CursiveRunner};
 use eyre::Context;
+use itertools::Itertools;
 use tracing::instrument;
 
-use crate::commands::smartlog::{make_smartlog_graph, render_graph};
+use crate::commands::init::warn_if_repo_copied;
+use crate::commands::smartlog::{make_smartlog_graph, render_graph, smartlog};
 use crate::core::dag::Dag;
 use crate::core::effects::Effects;
-use crate::core::eventlog::{Event, EventCursor, EventLogDb, EventReplayer, EventTransactionId};
-use crate::core::formatting::{printable_styled_string, Pluralize, StyledStringBuilder};
+use crate::core::eventlog::{
+    Event, EventCursor, EventLogDb, EventReplayer, EventTransactionId, ResetInfo,
+};
+use crate::core::formatting::{printable_styled_string, Glyphs, Pluralize, StyledStringBuilder};
 use crate::core::node_descriptors::{
     BranchesDescriptor, CommitMessageDescriptor, CommitOidDescriptor,
     DifferentialRevisionDescriptor, ObsolescenceExplanationDescriptor, RelativeTimeDescriptor,
 };
+use crate::core::snapshot::{
+    create_snapshot, discard_uncommitted_changes, get_snapshot_changed_paths, restore_snapshot,
+};
+use crate::core::stash::{apply_stash_update, is_stash_ref, sync_stash_events};
 use crate::declare_views;
-use crate::git::{check_out_commit, CategorizedReferenceName, GitRunInfo, MaybeZeroOid, Repo};
+use crate::git::{
+    check_out_commit, get_diff_stat, CategorizedReferenceName, FileStatus, GitRunInfo, GitRunOpts,
+    MaybeZeroOid, Repo, MAX_DIFF_STAT_FILES,
+};
 use crate::tui::{with_siv, SingletonView};
 
 fn render_cursor_smartlog(
@@ -157,6 +171,26 @@ fn describe_event(repo: &Repo, event: &Event) -> eyre::Result<Vec<StyledString>>
             ]
         }
 
+        Event::RefUpdateEvent {
+            timestamp: _,
+            event_tx_id: _,
+            ref_name,
+            old_oid: MaybeZeroOid::NonZero(old_oid),
+            new_oid: MaybeZeroOid::Zero,
+            message: _,
+        } if ref_name == "HEAD" => {
+            // Only recorded when `git undo` returns `HEAD` to being unborn.
+            vec![
+                StyledStringBuilder::new()
+                    .append_plain("Check out from ")
+                    .append(repo.friendly_describe_commit_from_oid(*old_oid)?)
+                    .build(),
+                StyledStringBuilder::new()
+                    .append_plain("            to an unborn HEAD (no commits)")
+                    .build(),
+            ]
+        }
+
         Event::RefUpdateEvent {
             timestamp: _,
             event_tx_id: _,
@@ -257,6 +291,37 @@ fn describe_event(repo: &Repo, event: &Event) -> eyre::Result<Vec<StyledString>>
             ]
         }
 
+        Event::WorkingCopySnapshot {
+            timestamp: _,
+            event_tx_id: _,
+            head_oid: MaybeZeroOid::NonZero(head_oid),
+            commit_oid: _,
+            ref_name: _,
+        } => {
+            vec![
+                StyledStringBuilder::new()
+                    .append_plain("Save uncommitted changes at ")
+                    .append(repo.friendly_describe_commit_from_oid(*head_oid)?)
+                    .build(),
+                StyledString::new(),
+            ]
+        }
+
+        Event::WorkingCopySnapshot {
+            timestamp: _,
+            event_tx_id: _,
+            head_oid: MaybeZeroOid::Zero,
+            commit_oid: _,
+            ref_name: _,
+        } => {
+            vec![
+                StyledStringBuilder::new()
+                    .append_plain("Save uncommitted changes")
+                    .build(),
+                StyledString::new(),
+            ]
+        }
+
         Event::RewriteEvent {
             timestamp: _,
             event_tx_id: _,
@@ -276,14 +341,152 @@ fn describe_event(repo: &Repo, event: &Event) -> eyre::Result<Vec<StyledString>>
     Ok(result)
 }
 
+/// Describe an event which is about to be applied as part of an undo
+/// operation. Since it's the inverse of an event which was previously recorded,
+/// the creation of a reference is really the restoration of a deleted one.
+fn describe_inverse_event(repo: &Repo, event: &Event) -> eyre::Result<Vec<StyledString>> {
+    match event {
+        Event::RefUpdateEvent {
+            timestamp: _,
+            event_tx_id: _,
+            ref_name,
+            old_oid: MaybeZeroOid::Zero,
+            new_oid: MaybeZeroOid::NonZero(new_oid),
+            message: _,
+        } if ref_name != "HEAD" => Ok(vec![
+            StyledStringBuilder::new()
+                .append_plain("Recreate ")
+                .append_plain(CategorizedReferenceName::new(ref_name).friendly_describe())
+                .append_plain(" at ")
+                .append(repo.friendly_describe_commit_from_oid(*new_oid)?)
+                .build(),
+            StyledString::new(),
+        ]),
+
+        Event::WorkingCopySnapshot {
+            timestamp: _,
+            event_tx_id: _,
+            head_oid: _,
+            commit_oid,
+            ref_name: _,
+        } if repo.find_commit(*commit_oid)?.is_none() => Ok(vec![
+            StyledStringBuilder::new()
+                .append_plain("Skip unavailable working copy snapshot ")
+                .append_plain(&commit_oid.to_string()[..8])
+                .build(),
+            StyledString::new(),
+        ]),
+
+        Event::WorkingCopySnapshot {
+            timestamp: _,
+            event_tx_id: _,
+            head_oid: _,
+            commit_oid,
+            ref_name: _,
+        } => {
+            let changed_files = match get_snapshot_changed_paths(repo, *commit_oid)? {
+                Some(changed_paths) => format!(
+                    " to {}",
+                    Pluralize {
+                        amount: changed_paths.len().try_into()?,
+                        singular: "file",
+                        plural: "files",
+                    }
+                    .to_string()
+                ),
+                None => String::new(),
+            };
+            Ok(vec![
+                StyledStringBuilder::new()
+                    .append_plain(format!(
+                        "Restore uncommitted changes{} from working copy snapshot ",
+                        changed_files
+                    ))
+                    .append_plain(&commit_oid.to_string()[..8])
+                    .build(),
+                StyledString::new(),
+            ])
+        }
+
+        event => describe_event(repo, event),
+    }
+}
+
+/// Describe how the contents of the repository would change if it were
+/// restored to its state before `events` happened. For each reference which
+/// would be moved (including `HEAD`), renders the diffstat between its current
+/// location and its restored location.
+fn describe_ref_movement_diffs(repo: &Repo, events: &[Event]) -> eyre::Result<Vec<StyledString>> {
+    // For each reference, find its location before the first event and after
+    // the last event.
+    let mut ref_movements: Vec<(OsString, MaybeZeroOid, MaybeZeroOid)> = Vec::new();
+    for event in events {
+        if let Event::RefUpdateEvent {
+            ref_name,
+            old_oid,
+            new_oid,
+            ..
+        } = event
+        {
+            match ref_movements
+                .iter_mut()
+                .find(|(other_ref_name, _, _)| other_ref_name == ref_name)
+            {
+                Some((_, _, current_oid)) => *current_oid = *new_oid,
+                None => ref_movements.push((ref_name.clone(), *old_oid, *new_oid)),
+            }
+        }
+    }
+    ref_movements.sort_by_key(|(ref_name, _, _)| ref_name != "HEAD");
+
+    let mut lines = Vec::new();
+    for (ref_name, restored_oid, current_oid) in ref_movements {
+        let (restored_oid, current_oid) = match (restored_oid, current_oid) {
+            (MaybeZeroOid::NonZero(restored_oid), MaybeZeroOid::NonZero(current_oid))
+                if restored_oid != current_oid =>
+            {
+                (restored_oid, current_oid)
+            }
+            _ => continue,
+        };
+
+        let ref_description = if ref_name == "HEAD" {
+            "HEAD".to_string()
+        } else {
+            CategorizedReferenceName::new(&ref_name).friendly_describe()
+        };
+        lines.push(StyledString::plain(format!(
+            "Changes to {} from {} to {}:",
+            ref_description,
+            &current_oid.to_string()[..8],
+            &restored_oid.to_string()[..8],
+        )));
+        let diff_stat = get_diff_stat(repo, current_oid, restored_oid)?;
+        lines.extend(
+            diff_stat
+                .render(MAX_DIFF_STAT_FILES)
+                .into_iter()
+                .map(StyledString::plain),
+        );
+    }
+
+    if lines.is_empty() {
+        lines.push(StyledString::plain(
+            "No branches or checked-out commits would change.",
+        ));
+    }
+    Ok(lines)
+}
+
 fn describe_events_numbered(
     repo: &Repo,
     events: &[Event],
+    describe: fn(&Repo, &Event) -> eyre::Result<Vec<StyledString>>,
 ) -> Result<Vec<StyledString>, eyre::Error> {
     let mut lines = Vec::new();
     for (i, event) in (1..).zip(events) {
         let num_header = format!("{}. ", i);
-        for (j, event_line) in (0..).zip(describe_event(repo, event)?) {
+        for (j, event_line) in (0..).zip(describe(repo, event)?) {
             let prefix = if j == 0 {
                 num_header.clone()
             } else {
@@ -300,21 +503,137 @@ fn describe_events_numbered(
     Ok(lines)
 }
 
+/// Determine whether the event should still be shown in the event browser
+/// when low-signal events are hidden. Commits, rewrites, hides, unhides, and
+/// checkouts are kept; bare reference updates (such as from fetches) and
+/// working copy snapshots are not.
+fn is_high_signal_event(event: &Event) -> bool {
+    match event {
+        Event::CommitEvent { .. }
+        | Event::RewriteEvent { .. }
+        | Event::ObsoleteEvent { .. }
+        | Event::UnobsoleteEvent { .. } => true,
+        Event::RefUpdateEvent { ref_name, .. } => ref_name == "HEAD",
+        Event::WorkingCopySnapshot { .. } => false,
+    }
+}
+
+/// Determine whether any of the descriptions of the given events contain the
+/// search query, ignoring case. The descriptions include the summaries of
+/// the affected commits and the names of the affected branches.
+fn events_match_search_query(repo: &Repo, events: &[Event], query: &str) -> eyre::Result<bool> {
+    let query = query.to_lowercase();
+    for event in events {
+        for line in describe_event(repo, event)? {
+            if line.source().to_lowercase().contains(&query) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// The direction in which to search for events in the event browser.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SearchDirection {
+    /// Search for the most recent matching event at or before the cursor.
+    Backward { include_current: bool },
+
+    /// Search for the least recent matching event after the cursor.
+    Forward,
+}
+
+/// Get the positions of the cursor immediately after each transaction that
+/// the event browser can display, from least to most recent. If
+/// `hide_low_signal_events` is set, transactions which consist only of
+/// low-signal events are omitted.
+fn get_visible_transaction_cursors(
+    event_replayer: &EventReplayer,
+    hide_low_signal_events: bool,
+) -> Vec<EventCursor> {
+    let events = event_replayer.get_events_since_cursor(event_replayer.make_cursor(0));
+    let transactions = get_transactions(event_replayer);
+    let transaction_end_event_ids = transactions
+        .iter()
+        .skip(1)
+        .map(|(_event_tx_id, event_id)| *event_id)
+        .chain(std::iter::once(events.len().try_into().unwrap()));
+    transactions
+        .iter()
+        .zip(transaction_end_event_ids)
+        .filter(|((_event_tx_id, start_event_id), end_event_id)| {
+            if !hide_low_signal_events {
+                return true;
+            }
+            let start: usize = (*start_event_id).try_into().unwrap();
+            let end: usize = (*end_event_id).try_into().unwrap();
+            events[start..end].iter().any(is_high_signal_event)
+        })
+        .map(|(_transaction, end_event_id)| event_replayer.make_cursor(end_event_id))
+        .collect()
+}
+
+/// Find the cursor for the transaction closest to `cursor` in the given
+/// direction whose events match the search query.
+fn search_transactions(
+    repo: &Repo,
+    event_replayer: &EventReplayer,
+    transaction_cursors: &[EventCursor],
+    cursor: EventCursor,
+    query: &str,
+    direction: SearchDirection,
+) -> eyre::Result<Option<EventCursor>> {
+    let candidates: Vec<EventCursor> = match direction {
+        SearchDirection::Backward { include_current } => transaction_cursors
+            .iter()
+            .rev()
+            .filter(|transaction_cursor| {
+                if include_current {
+                    transaction_cursor.get_event_id() <= cursor.get_event_id()
+                } else {
+                    transaction_cursor.get_event_id() < cursor.get_event_id()
+                }
+            })
+            .copied()
+            .collect(),
+        SearchDirection::Forward => transaction_cursors
+            .iter()
+            .filter(|transaction_cursor| transaction_cursor.get_event_id() > cursor.get_event_id())
+            .copied()
+            .collect(),
+    };
+    for candidate in candidates {
+        if let Some((_event_id, events)) = event_replayer.get_tx_events_before_cursor(candidate) {
+            if events_match_search_query(repo, events, query)? {
+                return Ok(Some(candidate));
+            }
+        }
+    }
+    Ok(None)
+}
+
 #[instrument(skip(siv))]
 fn select_past_event(
     mut siv: CursiveRunner<CursiveRunnable>,
     effects: &Effects,
     repo: &Repo,
+    event_log_db: &EventLogDb,
     dag: &Dag,
     event_replayer: &mut EventReplayer,
 ) -> eyre::Result<Option<EventCursor>> {
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Debug)]
     enum Message {
         Init,
         Next,
         Previous,
         GoToEvent,
+        ShowDiff,
         SetEventReplayerCursor { event_id: isize },
+        PromptSearch,
+        Search { query: String },
+        RepeatSearch,
+        RepeatSearchReverse,
+        ToggleFilter,
         Help,
         Quit,
         SelectEventIdAndQuit,
@@ -322,8 +641,8 @@ fn select_past_event(
     let (main_tx, main_rx): (Sender<Message>, Receiver<Message>) = channel();
 
     [
-        ('n'.into(), Message::Next),
-        ('N'.into(), Message::Next),
+        ('n'.into(), Message::RepeatSearch),
+        ('N'.into(), Message::RepeatSearchReverse),
         (Key::Right.into(), Message::Next),
         ('p'.into(), Message::Previous),
         ('P'.into(), Message::Previous),
@@ -333,6 +652,11 @@ fn select_past_event(
         ('?'.into(), Message::Help),
         ('g'.into(), Message::GoToEvent),
         ('G'.into(), Message::GoToEvent),
+        ('d'.into(), Message::ShowDiff),
+        ('D'.into(), Message::ShowDiff),
+        ('/'.into(), Message::PromptSearch),
+        ('f'.into(), Message::ToggleFilter),
+        ('F'.into(), Message::ToggleFilter),
         ('q'.into(), Message::Quit),
         ('Q'.into(), Message::Quit),
         (
@@ -345,11 +669,15 @@ fn select_past_event(
     .for_each(|(event, message): (cursive::event::Event, Message)| {
         siv.add_global_callback(event, {
             let main_tx = main_tx.clone();
-            move |_siv| main_tx.send(message).unwrap()
+            move |_siv| main_tx.send(message.clone()).unwrap()
         });
     });
 
     let mut cursor = event_replayer.make_default_cursor();
+    let mut hide_low_signal_events = false;
+    let mut transaction_cursors =
+        get_visible_transaction_cursors(event_replayer, hide_low_signal_events);
+    let mut search_query: Option<String> = None;
     let now = SystemTime::now();
     main_tx.send(Message::Init)?;
     while siv.is_running() {
@@ -369,7 +697,8 @@ fn select_past_event(
 
         let redraw = |siv: &mut Cursive,
                       event_replayer: &mut EventReplayer,
-                      event_cursor: EventCursor|
+                      event_cursor: EventCursor,
+                      hide_low_signal_events: bool|
          -> eyre::Result<()> {
             let smartlog =
                 render_cursor_smartlog(effects, repo, dag, event_replayer, event_cursor)?;
@@ -379,11 +708,22 @@ fn select_past_event(
 
             let event = event_replayer.get_tx_events_before_cursor(event_cursor);
             let info_view_contents = match event {
-                None => vec![StyledString::plain(
-                    "There are no previous available events.",
-                )],
+                None => vec![StyledString::plain(if event_replayer.has_pruned_events() {
+                    "There are no previous available events. Older history has been pruned."
+                } else {
+                    "There are no previous available events."
+                })],
                 Some((event_id, events)) => {
-                    let event_description_lines = describe_events_numbered(repo, events)?;
+                    let event_description_lines =
+                        describe_events_numbered(repo, events, describe_event)?;
+                    let command = match describe_transaction_command(
+                        event_log_db,
+                        events[0].get_event_tx_id(),
+                        events,
+                    )? {
+                        Some(command) => format!(" by `{}`", command),
+                        None => String::new(),
+                    };
                     let relative_time_provider = RelativeTimeDescriptor::new(repo, now)?;
                     let relative_time = if relative_time_provider.is_enabled() {
                         format!(
@@ -403,9 +743,27 @@ fn select_past_event(
                         .append_plain(" (event ")
                         .append_plain(event_id.to_string())
                         .append_plain(")")
+                        .append_plain(command)
                         .append_plain(relative_time)
                         .append_plain(". Press 'h' for help, 'q' to quit.")
+                        .append_plain(if hide_low_signal_events {
+                            " Low-signal events are hidden."
+                        } else {
+                            ""
+                        })
+                        .append_plain(
+                            if event_replayer.is_event_tx_id_pruned(events[0].get_event_tx_id()) {
+                                " Older history has been pruned."
+                            } else {
+                                ""
+                            },
+                        )
                         .build()];
+                    if let Some(reset_description) =
+                        describe_reset_restoration(repo, event_log_db, events[0].get_event_tx_id())?
+                    {
+                        lines.push(reset_description);
+                    }
                     lines.extend(event_description_lines);
                     lines
                 }
@@ -414,6 +772,7 @@ fn select_past_event(
             Ok(())
         };
 
+        let should_refresh = message.is_ok();
         match message {
             Err(TryRecvError::Disconnected) => break,
 
@@ -436,22 +795,134 @@ fn select_past_event(
                         .child(Panel::new(ScrollView::new(info_view)).title("Events"))
                         .full_width(),
                 );
-                redraw(&mut siv, event_replayer, cursor)?;
+                redraw(&mut siv, event_replayer, cursor, hide_low_signal_events)?;
             }
 
             Ok(Message::Next) => {
-                cursor = event_replayer.advance_cursor_by_transaction(cursor, 1);
-                redraw(&mut siv, event_replayer, cursor)?;
+                cursor = if hide_low_signal_events {
+                    transaction_cursors
+                        .iter()
+                        .find(|transaction_cursor| {
+                            transaction_cursor.get_event_id() > cursor.get_event_id()
+                        })
+                        .copied()
+                        .unwrap_or(cursor)
+                } else {
+                    event_replayer.advance_cursor_by_transaction(cursor, 1)
+                };
+                redraw(&mut siv, event_replayer, cursor, hide_low_signal_events)?;
             }
 
             Ok(Message::Previous) => {
-                cursor = event_replayer.advance_cursor_by_transaction(cursor, -1);
-                redraw(&mut siv, event_replayer, cursor)?;
+                cursor = if hide_low_signal_events {
+                    transaction_cursors
+                        .iter()
+                        .rev()
+                        .find(|transaction_cursor| {
+                            transaction_cursor.get_event_id() < cursor.get_event_id()
+                        })
+                        .copied()
+                        .unwrap_or(cursor)
+                } else {
+                    event_replayer.advance_cursor_by_transaction(cursor, -1)
+                };
+                redraw(&mut siv, event_replayer, cursor, hide_low_signal_events)?;
             }
 
             Ok(Message::SetEventReplayerCursor { event_id }) => {
                 cursor = event_replayer.make_cursor(event_id);
-                redraw(&mut siv, event_replayer, cursor)?;
+                redraw(&mut siv, event_replayer, cursor, hide_low_signal_events)?;
+            }
+
+            Ok(Message::ToggleFilter) => {
+                hide_low_signal_events = !hide_low_signal_events;
+                transaction_cursors =
+                    get_visible_transaction_cursors(event_replayer, hide_low_signal_events);
+                redraw(&mut siv, event_replayer, cursor, hide_low_signal_events)?;
+            }
+
+            Ok(Message::PromptSearch) => {
+                let main_tx = main_tx.clone();
+                siv.add_layer(
+                    OnEventView::new(
+                        Dialog::new()
+                            .title("Search events")
+                            .content(EditView::new().on_submit(move |siv, text| {
+                                main_tx
+                                    .send(Message::Search {
+                                        query: text.to_string(),
+                                    })
+                                    .unwrap();
+                                siv.pop_layer();
+                            }))
+                            .dismiss_button("Cancel"),
+                    )
+                    .on_event(Key::Esc, |siv| {
+                        siv.pop_layer();
+                    }),
+                );
+            }
+
+            Ok(Message::Search { query }) if query.is_empty() => {
+                search_query = None;
+            }
+
+            Ok(Message::Search { query }) => {
+                let direction = SearchDirection::Backward {
+                    include_current: true,
+                };
+                match search_transactions(
+                    repo,
+                    event_replayer,
+                    &transaction_cursors,
+                    cursor,
+                    &query,
+                    direction,
+                )? {
+                    Some(search_cursor) => {
+                        cursor = search_cursor;
+                        redraw(&mut siv, event_replayer, cursor, hide_low_signal_events)?;
+                    }
+                    None => {
+                        siv.add_layer(Dialog::info(format!("No events match: {}", query)));
+                    }
+                }
+                search_query = Some(query);
+            }
+
+            Ok(message @ (Message::RepeatSearch | Message::RepeatSearchReverse)) => {
+                let query = match &search_query {
+                    Some(query) => query,
+                    None => {
+                        // With no search to repeat, fall back to moving to the
+                        // next state, which is what `n` did before searching
+                        // was supported.
+                        main_tx.send(Message::Next)?;
+                        continue;
+                    }
+                };
+                let direction = match message {
+                    Message::RepeatSearchReverse => SearchDirection::Forward,
+                    _ => SearchDirection::Backward {
+                        include_current: false,
+                    },
+                };
+                match search_transactions(
+                    repo,
+                    event_replayer,
+                    &transaction_cursors,
+                    cursor,
+                    query,
+                    direction,
+                )? {
+                    Some(search_cursor) => {
+                        cursor = search_cursor;
+                        redraw(&mut siv, event_replayer, cursor, hide_low_signal_events)?;
+                    }
+                    None => {
+                        siv.add_layer(Dialog::info(format!("No more events match: {}", query)));
+                    }
+                }
             }
 
             Ok(Message::GoToEvent) => {
@@ -484,6 +955,26 @@ fn select_past_event(
                 );
             }
 
+            Ok(Message::ShowDiff) => {
+                let lines = describe_ref_movement_diffs(
+                    repo,
+                    event_replayer.get_events_since_cursor(cursor),
+                )?;
+                siv.add_layer(
+                    OnEventView::new(
+                        Dialog::new()
+                            .title("Changes if reverted to this state")
+                            .content(ScrollView::new(TextView::new(
+                                StyledStringBuilder::from_lines(lines),
+                            )))
+                            .dismiss_button("Close"),
+                    )
+                    .on_event(Key::Esc, |siv| {
+                        siv.pop_layer();
+                    }),
+                );
+            }
+
             Ok(Message::Help) => {
                 siv.add_layer(
                         Dialog::new()
@@ -493,8 +984,12 @@ fn select_past_event(
 
 h/?: Show this help.
 q: Quit.
-p/n or <left>/<right>: View next/previous state.
+p/n or <left>/<right>: View previous/next state.
 g: Go to a provided event ID.
+/: Search for a state by commit message, branch name, etc.
+n/N: Go to the next older/newer search result.
+f: Toggle hiding low-signal events, such as branch updates from fetches.
+d: Show the files which would change if the repository were reverted to the given state.
 <enter>: Revert the repository to the given state (requires confirmation).
 
 You can also copy a commit hash from the past and manually run `git unhide` or `git rebase` on it.
@@ -512,7 +1007,7 @@ You can also copy a commit hash from the past and manually run `git unhide` or `
             }
         };
 
-        if message.is_ok() {
+        if should_refresh {
             siv.refresh();
         }
     }
@@ -579,30 +1074,95 @@ fn inverse_event(
             new_oid: old_ref,
             message: None,
         },
+
+        // The inverse of taking a snapshot is restoring it.
+        Event::WorkingCopySnapshot {
+            timestamp: _,
+            event_tx_id: _,
+            head_oid,
+            commit_oid,
+            ref_name,
+        } => Event::WorkingCopySnapshot {
+            timestamp,
+            event_tx_id,
+            head_oid,
+            commit_oid,
+            ref_name,
+        },
     };
     Ok(inverse_event)
 }
 
+/// Combine the inverse events which move the same reference, so that each
+/// reference is moved only once, directly from its current location to its
+/// oldest restored location. The combined event takes the place of the last of
+/// the events that it replaces. References which would end up back where they
+/// started aren't moved at all.
 fn optimize_inverse_events(events: Vec<Event>) -> Vec<Event> {
     let mut optimized_events = Vec::new();
-    let mut seen_checkout = false;
+    let mut ref_current_oids: HashMap<OsString, MaybeZeroOid> = HashMap::new();
     for event in events.into_iter().rev() {
         match event {
-            Event::RefUpdateEvent { ref ref_name, .. } if ref_name == "HEAD" => {
-                if seen_checkout {
-                    continue;
+            Event::RefUpdateEvent {
+                ref ref_name,
+                old_oid,
+                ..
+            } => {
+                // Iterating in reverse, the first event seen for a reference
+                // determines its restored location, and the last event seen
+                // holds its current location.
+                if let Some(current_oid) = ref_current_oids.get_mut(ref_name) {
+                    *current_oid = old_oid;
                 } else {
-                    seen_checkout = true;
-                    optimized_events.push(event)
+                    ref_current_oids.insert(ref_name.clone(), old_oid);
+                    optimized_events.push(event);
                 }
             }
             event => optimized_events.push(event),
         };
     }
     optimized_events.reverse();
+
     optimized_events
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::RefUpdateEvent {
+                timestamp,
+                event_tx_id,
+                ref_name,
+                old_oid: _,
+                new_oid,
+                message,
+            } => {
+                let old_oid = ref_current_oids[&ref_name];
+                if old_oid == new_oid && ref_name != "HEAD" {
+                    None
+                } else {
+                    Some(Event::RefUpdateEvent {
+                        timestamp,
+                        event_tx_id,
+                        ref_name,
+                        old_oid,
+                        new_oid,
+                        message,
+                    })
+                }
+            }
+            event => Some(event),
+        })
+        .collect()
 }
 
+/// The prefix of the message recorded for event transactions created by `git
+/// undo`. The message is followed by the ID of the event which the repository
+/// was restored to.
+const UNDO_TRANSACTION_MESSAGE_PREFIX: &str = "undo ";
+
+/// The prefix of the message recorded for event transactions created by `git
+/// redo`. The message is followed by the ID of the event which the repository
+/// was restored to.
+const REDO_TRANSACTION_MESSAGE_PREFIX: &str = "redo ";
+
 #[instrument(skip(in_))]
 fn undo_events(
     in_: &mut impl Read,
@@ -612,11 +1172,103 @@ fn undo_events(
     event_log_db: &mut EventLogDb,
     event_replayer: &EventReplayer,
     event_cursor: EventCursor,
+    skip_confirmation: bool,
+    force: bool,
+    show_diff: bool,
 ) -> eyre::Result<isize> {
     let now = SystemTime::now();
-    let event_tx_id = event_log_db.make_transaction_id(now, "undo")?;
-    let inverse_events: Vec<Event> = event_replayer
-        .get_events_since_cursor(event_cursor)
+    let event_tx_id = event_log_db.make_transaction_id(
+        now,
+        format!(
+            "{}{}",
+            UNDO_TRANSACTION_MESSAGE_PREFIX,
+            event_cursor.get_event_id()
+        ),
+    )?;
+    restore_cursor(
+        in_,
+        effects,
+        repo,
+        git_run_info,
+        event_log_db,
+        event_replayer,
+        event_cursor,
+        now,
+        event_tx_id,
+        skip_confirmation,
+        force,
+        show_diff,
+    )
+}
+
+/// Determine whether `HEAD` was unborn at the cursor's point in time, i.e. no
+/// commits had been made in the repository yet.
+///
+/// Git reports ordinary checkouts as updating `HEAD` from the zero OID, so
+/// such an update doesn't mean that `HEAD` was previously unborn. Instead,
+/// either `HEAD` must have been explicitly returned to being unborn by a
+/// previous undo, or else `HEAD` must not have been observed before the
+/// cursor and its first observed position afterwards must be a root commit
+/// which was committed after the cursor.
+fn is_head_unborn_at_cursor(
+    repo: &Repo,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+) -> eyre::Result<bool> {
+    let events = event_replayer.get_events_since_cursor(event_replayer.make_cursor(0));
+    let (events_before_cursor, events_since_cursor) =
+        events.split_at(event_cursor.get_event_id().try_into()?);
+
+    let head_oid_before_cursor = events_before_cursor
+        .iter()
+        .rev()
+        .find_map(|event| match event {
+            Event::RefUpdateEvent {
+                ref_name, new_oid, ..
+            } if ref_name == "HEAD" => Some(*new_oid),
+            Event::CommitEvent { commit_oid, .. } => Some(MaybeZeroOid::NonZero(*commit_oid)),
+            _ => None,
+        });
+    match head_oid_before_cursor {
+        Some(MaybeZeroOid::Zero) => return Ok(true),
+        Some(MaybeZeroOid::NonZero(_)) => return Ok(false),
+        None => {}
+    }
+
+    let first_head_oid = events_since_cursor.iter().find_map(|event| match event {
+        Event::RefUpdateEvent {
+            ref_name,
+            old_oid,
+            new_oid,
+            ..
+        } if ref_name == "HEAD" => Some((*old_oid, *new_oid)),
+        _ => None,
+    });
+    let first_head_oid = match first_head_oid {
+        Some((MaybeZeroOid::Zero, MaybeZeroOid::NonZero(first_head_oid))) => first_head_oid,
+        _ => return Ok(false),
+    };
+    let is_root_commit = match repo.find_commit(first_head_oid)? {
+        Some(commit) => commit.get_parent_oids().is_empty(),
+        None => false,
+    };
+    let was_committed_since_cursor = events_since_cursor.iter().any(|event| {
+        matches!(event, Event::CommitEvent { commit_oid, .. } if *commit_oid == first_head_oid)
+    });
+    Ok(is_root_commit && was_committed_since_cursor)
+}
+
+/// Calculate the events which need to be applied to restore the repository to
+/// the state at `event_cursor`, in the order that they should be applied.
+fn make_inverse_events(
+    repo: &Repo,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    now: SystemTime,
+    event_tx_id: EventTransactionId,
+) -> eyre::Result<Vec<Event>> {
+    let events_since_cursor = event_replayer.get_events_since_cursor(event_cursor);
+    let inverse_events: Vec<Event> = events_since_cursor
         .iter()
         .rev()
         .filter(|event| {
@@ -630,21 +1282,322 @@ fn undo_events(
                     new_oid: _,
                     message: _,
                 } if ref_name == "HEAD"
-            )
+            ) && !matches!(event, Event::WorkingCopySnapshot { .. })
         })
         .map(|event| inverse_event(event.clone(), now, event_tx_id))
         .collect::<eyre::Result<Vec<Event>>>()?;
     let mut inverse_events = optimize_inverse_events(inverse_events);
 
+    // If no commits had been made yet, then return `HEAD` to being unborn,
+    // rather than checking out whichever commit it was first observed at.
+    if is_head_unborn_at_cursor(repo, event_replayer, event_cursor)? {
+        inverse_events.retain(
+            |event| !matches!(event, Event::RefUpdateEvent { ref_name, .. } if ref_name == "HEAD"),
+        );
+        if let Some(head_oid) = repo.get_head_info()?.oid {
+            inverse_events.push(Event::RefUpdateEvent {
+                timestamp: now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64(),
+                event_tx_id,
+                ref_name: "HEAD".into(),
+                old_oid: MaybeZeroOid::NonZero(head_oid),
+                new_oid: MaybeZeroOid::Zero,
+                message: None,
+            });
+        }
+    }
+
+    // If we're going to check out a different commit, then restore the
+    // uncommitted changes which were in the working copy when that commit was
+    // last checked out. The first snapshot taken after the cursor holds those
+    // changes, as long as it was taken at the commit we're checking out.
+    let checkout_target_oid = inverse_events.iter().find_map(|event| match event {
+        Event::RefUpdateEvent {
+            ref_name,
+            new_oid: MaybeZeroOid::NonZero(new_oid),
+            ..
+        } if ref_name == "HEAD" => Some(*new_oid),
+        _ => None,
+    });
+    if let Some(checkout_target_oid) = checkout_target_oid {
+        let snapshot_event = events_since_cursor
+            .iter()
+            .find(|event| matches!(event, Event::WorkingCopySnapshot { .. }));
+        if let Some(
+            snapshot_event @ Event::WorkingCopySnapshot {
+                head_oid: MaybeZeroOid::NonZero(head_oid),
+                ..
+            },
+        ) = snapshot_event
+        {
+            if *head_oid == checkout_target_oid {
+                inverse_events.push(inverse_event(snapshot_event.clone(), now, event_tx_id)?);
+            }
+        }
+    }
+
     // Move any checkout operations to be first. Otherwise, we have the risk
     // that `HEAD` is a symbolic reference pointing to another reference, and we
     // update that reference. This would cause the working copy to become dirty
-    // from Git's perspective.
+    // from Git's perspective. Working copy snapshots can only be restored once
+    // the checkout has happened, so they come immediately afterwards.
     inverse_events.sort_by_key(|event| match event {
         Event::RefUpdateEvent { ref_name, .. } if ref_name == "HEAD" => 0,
-        _ => 1,
+        Event::WorkingCopySnapshot { .. } => 1,
+        _ => 2,
     });
 
+    Ok(inverse_events)
+}
+
+/// Describe the command which started the given transaction, if it was
+/// recorded. A transaction which holds a whole sequencing operation, such as a
+/// rebase, is described along with the number of commits which it applied.
+fn describe_transaction_command(
+    event_log_db: &EventLogDb,
+    event_tx_id: EventTransactionId,
+    events: &[Event],
+) -> eyre::Result<Option<String>> {
+    let command = event_log_db.get_transaction_command(event_tx_id)?;
+    let operation_name = match event_log_db.get_sequencing_operation_name(event_tx_id)? {
+        Some(operation_name) => operation_name,
+        None => return Ok(command),
+    };
+
+    let num_commits = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::CommitEvent { commit_oid, .. } => Some(*commit_oid),
+            Event::RewriteEvent {
+                new_commit_oid: MaybeZeroOid::NonZero(new_commit_oid),
+                ..
+            } => Some(*new_commit_oid),
+            _ => None,
+        })
+        .unique()
+        .count();
+    let command = command.unwrap_or_else(|| format!("git {}", operation_name));
+    Ok(Some(format!(
+        "{} ({})",
+        command,
+        Pluralize {
+            amount: num_commits.try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string()
+    )))
+}
+
+/// If the given transaction holds a reset, such as from `git reset --hard`,
+/// describe how undoing it restores the branch which was reset.
+fn describe_reset_restoration(
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    event_tx_id: EventTransactionId,
+) -> eyre::Result<Option<StyledString>> {
+    let ResetInfo { ref_name, old_oid } = match event_log_db.get_reset_info(event_tx_id)? {
+        Some(reset_info) => reset_info,
+        None => return Ok(None),
+    };
+    Ok(Some(
+        StyledStringBuilder::new()
+            .append_plain("Restore ")
+            .append_plain(CategorizedReferenceName::new(&ref_name).friendly_describe())
+            .append_plain(" to ")
+            .append(repo.friendly_describe_commit_from_oid(old_oid)?)
+            .append_plain(" (before reset)")
+            .build(),
+    ))
+}
+
+/// Describe the commands which started the transactions recorded after the
+/// given cursor, from most to least recent. Transactions without a recorded
+/// command are omitted. Undoing a reset is described as restoring the branch
+/// which was reset.
+fn describe_undone_commands(
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+) -> eyre::Result<Vec<String>> {
+    let tx_events = event_replayer
+        .get_events_since_cursor(event_cursor)
+        .iter()
+        .group_by(|event| event.get_event_tx_id())
+        .into_iter()
+        .map(|(event_tx_id, events)| (event_tx_id, events.cloned().collect_vec()))
+        .collect_vec();
+    let mut undone_commands = Vec::new();
+    for (event_tx_id, events) in tx_events.into_iter().rev() {
+        if let Some(command) = describe_transaction_command(event_log_db, event_tx_id, &events)? {
+            let reset_description =
+                match describe_reset_restoration(repo, event_log_db, event_tx_id)? {
+                    Some(reset_description) => format!(
+                        ": {}",
+                        printable_styled_string(effects.get_glyphs(), reset_description)?
+                    ),
+                    None => String::new(),
+                };
+            undone_commands.push(format!(
+                "{} (transaction {}){}",
+                command, event_tx_id, reset_description
+            ));
+        }
+    }
+    Ok(undone_commands)
+}
+
+/// An operation such as a conflicted `git merge` which is in progress, and
+/// which has to be aborted before `HEAD` can be moved.
+#[derive(Debug)]
+struct OperationToAbort {
+    operation_type: String,
+    conflicted_paths: Vec<PathBuf>,
+}
+
+impl OperationToAbort {
+    fn describe(&self) -> String {
+        let Self {
+            operation_type,
+            conflicted_paths,
+        } = self;
+        if conflicted_paths.is_empty() {
+            format!("Will abort the in-progress {}.", operation_type)
+        } else {
+            format!(
+                "Will abort the in-progress {}, discarding conflicts in {} (they will be saved to a working copy snapshot).",
+                operation_type,
+                Pluralize {
+                    amount: conflicted_paths.len().try_into().unwrap(),
+                    singular: "file",
+                    plural: "files",
+                }
+                .to_string()
+            )
+        }
+    }
+}
+
+/// Determine whether applying the given inverse events would move `HEAD`
+/// while a merge, cherry-pick, or revert is in progress. If so, the operation
+/// has to be aborted first, since its state (such as `MERGE_HEAD` and the
+/// conflicted index) would no longer make sense at the new `HEAD`.
+///
+/// Other multi-step operations, such as rebases, aren't handled here.
+#[instrument]
+fn get_operation_to_abort(
+    repo: &Repo,
+    git_run_info: &GitRunInfo,
+    inverse_events: &[Event],
+) -> eyre::Result<Option<OperationToAbort>> {
+    let moves_head = inverse_events.iter().any(|event| match event {
+        Event::RefUpdateEvent { ref_name, .. } => ref_name == "HEAD",
+        _ => false,
+    });
+    if !moves_head {
+        return Ok(None);
+    }
+
+    let operation_type = match repo.get_current_operation_type() {
+        Some(operation_type @ ("merge" | "cherry-pick" | "revert")) => operation_type.to_string(),
+        Some(_) | None => return Ok(None),
+    };
+    let conflicted_paths = repo
+        .get_status(git_run_info, None)?
+        .into_iter()
+        .filter(|status_entry| status_entry.index_status == FileStatus::Unmerged)
+        .map(|status_entry| status_entry.path)
+        .collect();
+    Ok(Some(OperationToAbort {
+        operation_type,
+        conflicted_paths,
+    }))
+}
+
+/// Abort the given in-progress operation, saving any uncommitted changes
+/// (including the contents of conflicted files) to a working copy snapshot
+/// first.
+#[instrument]
+fn abort_operation(
+    effects: &Effects,
+    repo: &Repo,
+    git_run_info: &GitRunInfo,
+    event_log_db: &mut EventLogDb,
+    event_tx_id: EventTransactionId,
+    operation_to_abort: &OperationToAbort,
+) -> eyre::Result<()> {
+    let OperationToAbort {
+        operation_type,
+        conflicted_paths,
+    } = operation_to_abort;
+
+    // A snapshot can't be taken of an index with conflicts in it, so stage
+    // the conflicted files as they are in the working copy (conflict markers
+    // and all). The index is about to be discarded anyway.
+    if !conflicted_paths.is_empty() {
+        let mut args = vec!["add".to_string(), "--all".to_string(), "--".to_string()];
+        args.extend(
+            conflicted_paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned()),
+        );
+        git_run_info
+            .run_silent(repo, Some(event_tx_id), &args, Default::default())
+            .wrap_err("Staging conflicted files")?;
+    }
+
+    let snapshot_oid = create_snapshot(repo, git_run_info, event_log_db, event_tx_id)?;
+    if let Some(snapshot_oid) = snapshot_oid {
+        writeln!(
+            effects.get_output_stream(),
+            "Saved uncommitted changes to working copy snapshot {}.",
+            snapshot_oid
+        )?;
+    }
+
+    // Resetting the index and working copy also removes `MERGE_HEAD`,
+    // `CHERRY_PICK_HEAD`, `REVERT_HEAD`, and the associated message files.
+    discard_uncommitted_changes(repo, git_run_info, event_tx_id)?;
+    if operation_type != "merge" {
+        // Forget about the rest of a multi-commit cherry-pick or revert, if
+        // any, without touching `HEAD`.
+        git_run_info.run_silent(
+            repo,
+            Some(event_tx_id),
+            &[operation_type.as_str(), "--quit"],
+            GitRunOpts {
+                treat_git_failure_as_error: false,
+            },
+        )?;
+    }
+
+    writeln!(
+        effects.get_output_stream(),
+        "Aborted the in-progress {}.",
+        operation_type
+    )?;
+    Ok(())
+}
+
+/// Restore the repository to the state at `event_cursor` by applying the
+/// inverses of all events which have happened since then.
+#[instrument(skip(in_))]
+fn restore_cursor(
+    in_: &mut impl Read,
+    effects: &Effects,
+    repo: &Repo,
+    git_run_info: &GitRunInfo,
+    event_log_db: &mut EventLogDb,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    now: SystemTime,
+    event_tx_id: EventTransactionId,
+    skip_confirmation: bool,
+    force: bool,
+    show_diff: bool,
+) -> eyre::Result<isize> {
+    let inverse_events = make_inverse_events(repo, event_replayer, event_cursor, now, event_tx_id)?;
     if inverse_events.is_empty() {
         writeln!(
             effects.get_output_stream(),
@@ -653,17 +1606,63 @@ fn undo_events(
         return Ok(0);
     }
 
+    let undone_commands =
+        describe_undone_commands(effects, repo, event_log_db, event_replayer, event_cursor)?;
+    if !undone_commands.is_empty() {
+        writeln!(effects.get_output_stream(), "Will undo these commands:")?;
+        for (i, undone_command) in undone_commands.into_iter().enumerate() {
+            writeln!(effects.get_output_stream(), "{}. {}", i + 1, undone_command)?;
+        }
+    }
+
     writeln!(effects.get_output_stream(), "Will apply these actions:")?;
-    let events = describe_events_numbered(repo, &inverse_events)?;
-    for line in events {
+    let mut lines = describe_events_numbered(repo, &inverse_events, describe_inverse_event)?;
+    if show_diff {
+        let events_since_cursor = event_replayer.get_events_since_cursor(event_cursor);
+        lines.extend(describe_ref_movement_diffs(repo, events_since_cursor)?);
+    }
+    for line in lines {
         writeln!(
             effects.get_output_stream(),
             "{}",
             printable_styled_string(effects.get_glyphs(), line)?
         )?;
     }
+    let operation_to_abort = get_operation_to_abort(repo, git_run_info, &inverse_events)?;
+    if let Some(operation_to_abort) = &operation_to_abort {
+        writeln!(
+            effects.get_output_stream(),
+            "{}",
+            operation_to_abort.describe()
+        )?;
+    }
 
-    let confirmed = {
+    // Aborting an in-progress operation already saves its changes to a
+    // working copy snapshot, so only refuse if there's nothing to abort.
+    let will_check_out = inverse_events.iter().any(|event| {
+        matches!(
+            event,
+            Event::RefUpdateEvent { ref_name, .. } if ref_name == "HEAD"
+        )
+    });
+    if will_check_out
+        && operation_to_abort.is_none()
+        && !force
+        && !repo.get_status(git_run_info, None)?.is_empty()
+    {
+        write!(
+            effects.get_output_stream(),
+            "\
+This operation would check out a different commit, but you have uncommitted
+changes in your working copy which might be overwritten as a result.
+Commit your changes and then try again, or retry with --force to save them to
+a working copy snapshot first.
+"
+        )?;
+        return Ok(1);
+    }
+
+    let confirmed = skip_confirmation || {
         write!(effects.get_output_stream(), "Confirm? [yN] ")?;
         let mut user_input = String::new();
         let mut reader = BufReader::new(in_);
@@ -687,7 +1686,20 @@ fn undo_events(
     }
     .to_string();
 
+    if let Some(operation_to_abort) = &operation_to_abort {
+        abort_operation(
+            effects,
+            repo,
+            git_run_info,
+            event_log_db,
+            event_tx_id,
+            operation_to_abort,
+        )?;
+    }
+
     let mut result = 0;
+    let mut is_head_unborn = false;
+    let mut should_render_smartlog = false;
     for event in inverse_events.into_iter() {
         match event {
             Event::RefUpdateEvent {
@@ -698,22 +1710,156 @@ fn undo_events(
                 new_oid: MaybeZeroOid::NonZero(new_ref),
                 message: _,
             } if ref_name == "HEAD" => {
+                // Save any uncommitted changes before discarding them, so
+                // that they can't interfere with the checkout. They can be
+                // restored by undoing this operation.
+                let snapshot_oid = create_snapshot(repo, git_run_info, event_log_db, event_tx_id)?;
+                if let Some(snapshot_oid) = snapshot_oid {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Saved uncommitted changes to working copy snapshot {}.",
+                        snapshot_oid
+                    )?;
+                    discard_uncommitted_changes(repo, git_run_info, event_tx_id)?;
+                }
+
                 let target_oid: OsString = new_ref.to_string().into();
                 // Most likely the user wanted to perform an actual checkout in
                 // this case, rather than just update `HEAD` (and be left with a
                 // dirty working copy). The `Git` command will update the event
                 // log appropriately, as it will invoke our hooks.
-                let exit_code = check_out_commit(
-                    effects,
-                    git_run_info,
-                    Some(event_tx_id),
-                    target_oid.as_os_str(),
-                    &["--detach"],
-                )
-                .wrap_err("Updating to previous HEAD location")?;
+                let exit_code = if repo.get_head_info()?.is_unborn() {
+                    // The main branch may not have been recreated yet, so the
+                    // smartlog can't be rendered until the other events have
+                    // been applied.
+                    should_render_smartlog = true;
+                    git_run_info
+                        .run(
+                            effects,
+                            Some(event_tx_id),
+                            &[
+                                OsStr::new("checkout"),
+                                target_oid.as_os_str(),
+                                OsStr::new("--detach"),
+                            ],
+                        )
+                        .wrap_err("Updating to previous HEAD location")?
+                } else {
+                    check_out_commit(
+                        effects,
+                        git_run_info,
+                        Some(event_tx_id),
+                        target_oid.as_os_str(),
+                        &["--detach"],
+                    )
+                    .wrap_err("Updating to previous HEAD location")?
+                };
                 if exit_code != 0 {
                     result = exit_code;
+
+                    // Put the uncommitted changes back where they were.
+                    if let Some(snapshot_oid) = snapshot_oid {
+                        restore_snapshot(repo, git_run_info, event_tx_id, snapshot_oid)?;
+                    }
+                }
+            }
+            Event::RefUpdateEvent {
+                timestamp: _,
+                event_tx_id: _,
+                ref ref_name,
+                old_oid: MaybeZeroOid::NonZero(_),
+                new_oid: MaybeZeroOid::Zero,
+                message: _,
+            } if ref_name == "HEAD" => {
+                // `HEAD` may need to be attached to the main branch below,
+                // which isn't possible for a remote-tracking branch. Check
+                // before touching the working copy.
+                let main_branch_reference_name = repo.get_main_branch_reference_name()?;
+                if repo.get_head_info()?.is_detached() {
+                    if let reference_name @ CategorizedReferenceName::RemoteBranch { .. } =
+                        CategorizedReferenceName::new(&main_branch_reference_name)
+                    {
+                        eyre::bail!(
+                            "Cannot attach HEAD to the main branch {}, because it's a remote-tracking branch",
+                            reference_name.render_suffix()
+                        );
+                    }
+                }
+
+                let snapshot_oid = create_snapshot(repo, git_run_info, event_log_db, event_tx_id)?;
+                if let Some(snapshot_oid) = snapshot_oid {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Saved uncommitted changes to working copy snapshot {}.",
+                        snapshot_oid
+                    )?;
                 }
+
+                // Remove all tracked files, as if no commit were checked out.
+                // Untracked files are left alone.
+                git_run_info
+                    .run_silent(
+                        repo,
+                        Some(event_tx_id),
+                        &["rm", "-r", "-f", "--quiet", "--ignore-unmatch", "--", ":/"],
+                        Default::default(),
+                    )
+                    .wrap_err("Removing tracked files")?;
+
+                // `HEAD` becomes unborn once the branch that it's attached to
+                // is deleted below, so make sure that it's attached to one.
+                let head_info = repo.get_head_info()?;
+                if head_info.is_detached() {
+                    repo.set_head_symbolic(&main_branch_reference_name)?;
+                }
+                is_head_unborn = true;
+
+                // No hook was invoked, so record the event here so that the
+                // undo can be redone.
+                event_log_db.add_events(vec![event])?;
+            }
+            Event::WorkingCopySnapshot {
+                timestamp: _,
+                event_tx_id: _,
+                head_oid,
+                commit_oid,
+                ref_name: _,
+            } => {
+                if repo.find_commit(commit_oid)?.is_none() {
+                    // The snapshot's reference may not have survived if the
+                    // repository was copied from elsewhere.
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Working copy snapshot {} is unavailable, not restoring it.",
+                        commit_oid
+                    )?;
+                } else if repo.get_head_info()?.oid == head_oid.into() {
+                    restore_snapshot(repo, git_run_info, event_tx_id, commit_oid)?;
+                } else {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Working copy snapshot {} was taken at a different commit, not restoring it.",
+                        commit_oid
+                    )?;
+                }
+            }
+            Event::RefUpdateEvent {
+                timestamp: _,
+                event_tx_id: _,
+                ref ref_name,
+                old_oid,
+                new_oid,
+                message: _,
+            } if is_stash_ref(ref_name) => {
+                apply_stash_update(
+                    effects,
+                    repo,
+                    git_run_info,
+                    event_log_db,
+                    event_tx_id,
+                    old_oid,
+                    new_oid,
+                )?;
             }
             Event::RefUpdateEvent {
                 timestamp: _,
@@ -728,13 +1874,30 @@ fn undo_events(
             Event::RefUpdateEvent {
                 timestamp: _,
                 event_tx_id: _,
-                ref_name,
+                ref ref_name,
                 old_oid: MaybeZeroOid::NonZero(_),
                 new_oid: MaybeZeroOid::Zero,
                 message: _,
-            } => match repo.find_reference(&ref_name)? {
+            } => match repo.find_reference(ref_name)? {
                 Some(mut reference) => {
+                    // If `HEAD` is attached to the branch being deleted, then
+                    // detach it first. Otherwise, `HEAD` would be left pointing
+                    // to a nonexistent branch, i.e. it would become unborn,
+                    // which is only desired if no commits had been made yet.
+                    // If the branch is recreated later, `HEAD` stays detached,
+                    // as it does for any other checkout that's undone.
+                    let head_info = repo.get_head_info()?;
+                    if !is_head_unborn
+                        && head_info.reference_name.as_deref() == Some(ref_name.as_os_str())
+                    {
+                        repo.detach_head(&head_info)?;
+                    }
+
                     reference.delete().wrap_err("Applying `RefUpdateEvent`")?;
+                    // Reference updates made via `git2` don't invoke the
+                    // `reference-transaction` hook, so record the event here.
+                    // Otherwise, the undo couldn't be redone later.
+                    event_log_db.add_events(vec![event])?;
                 }
                 None => {
                     writeln!(
@@ -747,7 +1910,7 @@ fn undo_events(
             Event::RefUpdateEvent {
                 timestamp: _,
                 event_tx_id: _,
-                ref_name,
+                ref ref_name,
                 old_oid: MaybeZeroOid::Zero,
                 new_oid: MaybeZeroOid::NonZero(new_oid),
                 message: _,
@@ -755,13 +1918,15 @@ fn undo_events(
             | Event::RefUpdateEvent {
                 timestamp: _,
                 event_tx_id: _,
-                ref_name,
+                ref ref_name,
                 old_oid: MaybeZeroOid::NonZero(_),
                 new_oid: MaybeZeroOid::NonZero(new_oid),
                 message: _,
             } => {
                 // Create or update the given reference.
-                repo.create_reference(&ref_name, new_oid, true, "branchless undo")?;
+                repo.create_reference(ref_name, new_oid, true, "branchless undo")?;
+                // As above, record the event since no hook was invoked.
+                event_log_db.add_events(vec![event])?;
             }
             Event::CommitEvent { .. }
             | Event::ObsoleteEvent { .. }
@@ -772,6 +1937,15 @@ fn undo_events(
         }
     }
 
+    // Undoing rewinds the state to an earlier point in time, so rebuild the
+    // smartlog cache from scratch, rather than relying on the inverse events
+    // having been applied on top of it.
+    event_log_db.invalidate_smartlog_cache()?;
+
+    if should_render_smartlog {
+        smartlog(effects, git_run_info, &Default::default())?;
+    }
+
     writeln!(
         effects.get_output_stream(),
         "Applied {}.",
@@ -780,13 +1954,34 @@ fn undo_events(
     Ok(result)
 }
 
-/// Restore the repository to a previous state interactively.
+/// Restore the repository to a previous state.
+///
+/// If `event_tx_id` is set, the repository is restored to its state
+/// immediately after that event transaction. Otherwise, if neither
+/// `num_transactions` nor `skip_confirmation` is set, the user selects the
+/// state to return to interactively. Otherwise, the repository is restored to
+/// its state `num_transactions` event transactions ago (defaulting to the most
+/// recent transaction).
+///
+/// If `show_diff` is set, the diffstat for each reference which would be moved
+/// is shown along with the actions to be applied.
 #[instrument]
-pub fn undo(effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<isize> {
-    let repo = Repo::from_current_dir()?;
+pub fn undo(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    num_transactions: Option<usize>,
+    event_tx_id: Option<&str>,
+    skip_confirmation: bool,
+    force: bool,
+    show_diff: bool,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    warn_if_repo_copied(effects, &repo)?;
     let references_snapshot = repo.get_references_snapshot()?;
     let conn = repo.get_db_conn()?;
     let mut event_log_db = EventLogDb::new(&conn)?;
+    // Make sure that stash entries dropped by `git stash pop` can be undone.
+    sync_stash_events(&repo, &mut event_log_db, SystemTime::now())?;
     let mut event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
     let dag = {
         // Don't let `event_cursor` leak from this scope, since we intend to
@@ -802,13 +1997,91 @@ pub fn undo(effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<isize>
         )?
     };
 
-    let event_cursor = {
-        let result = with_siv(effects, |effects, siv| {
-            select_past_event(siv, &effects, &repo, &dag, &mut event_replayer)
-        })?;
-        match result {
-            Some(event_cursor) => event_cursor,
-            None => return Ok(0),
+    let event_cursor = match (event_tx_id, num_transactions, skip_confirmation) {
+        (Some(event_tx_id), _, _) => {
+            let event_tx_id_range = event_replayer.get_event_tx_id_range();
+            let event_cursor = match (event_tx_id.parse(), event_tx_id_range) {
+                (Ok(parsed_event_tx_id), Some((min_event_tx_id, max_event_tx_id)))
+                    if min_event_tx_id <= parsed_event_tx_id
+                        && parsed_event_tx_id <= max_event_tx_id =>
+                {
+                    event_replayer.make_cursor_after_transaction(parsed_event_tx_id)
+                }
+                _ => None,
+            };
+            match (event_cursor, event_tx_id_range) {
+                (Some(event_cursor), _) => event_cursor,
+                (None, Some((min_event_tx_id, max_event_tx_id))) => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "No events were recorded for event transaction {}. Valid event transaction IDs range from {} to {}.",
+                        event_tx_id,
+                        min_event_tx_id,
+                        max_event_tx_id,
+                    )?;
+                    return Ok(1);
+                }
+                (None, None) => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "No events were recorded for event transaction {}. There are no recorded events.",
+                        event_tx_id,
+                    )?;
+                    return Ok(1);
+                }
+            }
+        }
+
+        (None, None, false) => {
+            let result = with_siv(effects, |effects, siv| {
+                select_past_event(
+                    siv,
+                    &effects,
+                    &repo,
+                    &event_log_db,
+                    &dag,
+                    &mut event_replayer,
+                )
+            })?;
+            match result {
+                Some(event_cursor) => event_cursor,
+                None => return Ok(0),
+            }
+        }
+
+        (None, num_transactions, _) => {
+            // Start from the state that the repository was most recently
+            // restored to, so that repeated undos keep going further back in
+            // time, rather than undoing the previous undo.
+            let num_transactions = num_transactions.unwrap_or(1);
+            let transactions = get_transactions(&event_replayer);
+            let UndoRedoState {
+                current_cursor,
+                redo_cursors: _,
+                num_undo_redo_transactions: _,
+            } = get_undo_redo_state(&event_log_db, &event_replayer, &transactions)?;
+
+            let num_available_transactions = transactions
+                .iter()
+                .filter(|(_event_tx_id, first_event_id)| {
+                    *first_event_id < current_cursor.get_event_id()
+                })
+                .count();
+            if num_transactions > num_available_transactions {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Only {} available to undo.",
+                    Pluralize {
+                        amount: num_available_transactions.try_into()?,
+                        singular: "transaction is",
+                        plural: "transactions are",
+                    }
+                    .to_string()
+                )?;
+            }
+
+            let num_transactions: isize = num_transactions.try_into()?;
+            event_replayer.advance_cursor_by_transaction(current_cursor, -num_transactions)
         }
     };
 
@@ -820,6 +2093,281 @@ pub fn undo(effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<isize>
         &mut event_log_db,
         &event_replayer,
         event_cursor,
+        skip_confirmation,
+        force,
+        show_diff,
+    )?;
+    Ok(result)
+}
+
+/// The actions which `git undo` would take to restore the repository to an
+/// earlier state. Produced by `plan_undo` and consumed by `apply_undo_plan`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UndoPlan {
+    /// The ID of the event which the repository will be restored to. Applying
+    /// the plan undoes every event recorded from this one onwards, including
+    /// any events recorded after the plan was made.
+    pub event_id: isize,
+
+    /// Descriptions of the actions which will be applied, in order.
+    pub actions: Vec<String>,
+}
+
+/// Determine how to undo the most recent `num_transactions` transactions,
+/// without modifying the repository. As with `git undo -n`, transactions which
+/// were themselves undo operations are skipped over.
+#[instrument]
+pub fn plan_undo(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    num_transactions: usize,
+) -> eyre::Result<UndoPlan> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+
+    let transactions = get_transactions(&event_replayer);
+    let UndoRedoState {
+        current_cursor,
+        redo_cursors: _,
+        num_undo_redo_transactions: _,
+    } = get_undo_redo_state(&event_log_db, &event_replayer, &transactions)?;
+    let num_transactions: isize = num_transactions.try_into()?;
+    let event_cursor =
+        event_replayer.advance_cursor_by_transaction(current_cursor, -num_transactions);
+
+    let inverse_events = make_inverse_events(
+        &repo,
+        &event_replayer,
+        event_cursor,
+        SystemTime::now(),
+        EventTransactionId::placeholder(),
+    )?;
+    let mut actions = describe_events_numbered(&repo, &inverse_events, describe_inverse_event)?
+        .into_iter()
+        .map(|line| printable_styled_string(&Glyphs::text(), line))
+        .collect::<eyre::Result<Vec<String>>>()?;
+    if let Some(operation_to_abort) = get_operation_to_abort(&repo, git_run_info, &inverse_events)?
+    {
+        actions.push(operation_to_abort.describe());
+    }
+    Ok(UndoPlan {
+        event_id: event_cursor.get_event_id(),
+        actions,
+    })
+}
+
+/// Restore the repository to the state described by `plan`, without asking
+/// for confirmation.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
+pub fn apply_undo_plan(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    plan: &UndoPlan,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_cursor(plan.event_id);
+    undo_events(
+        &mut std::io::empty(),
+        effects,
+        &repo,
+        git_run_info,
+        &mut event_log_db,
+        &event_replayer,
+        event_cursor,
+        true,
+        false,
+        false,
+    )
+}
+
+/// An undo or redo operation recorded in the event log.
+#[derive(Clone, Copy, Debug)]
+enum UndoRedoTransaction {
+    Undo { restored_event_id: isize },
+    Redo { restored_event_id: isize },
+}
+
+impl UndoRedoTransaction {
+    fn parse(message: &str) -> Option<Self> {
+        if let Some(event_id) = message.strip_prefix(UNDO_TRANSACTION_MESSAGE_PREFIX) {
+            let restored_event_id = event_id.parse().ok()?;
+            Some(UndoRedoTransaction::Undo { restored_event_id })
+        } else if let Some(event_id) = message.strip_prefix(REDO_TRANSACTION_MESSAGE_PREFIX) {
+            let restored_event_id = event_id.parse().ok()?;
+            Some(UndoRedoTransaction::Redo { restored_event_id })
+        } else {
+            None
+        }
+    }
+}
+
+/// Group the events in the event log by transaction. Returns the transaction
+/// ID and the ID of the first event for each transaction, from least to most
+/// recent.
+fn get_transactions(event_replayer: &EventReplayer) -> Vec<(EventTransactionId, isize)> {
+    let events = event_replayer.get_events_since_cursor(event_replayer.make_cursor(0));
+    let mut transactions: Vec<(EventTransactionId, isize)> = Vec::new();
+    for (event_id, event) in (0..).zip(events.iter()) {
+        let event_tx_id = event.get_event_tx_id();
+        match transactions.last() {
+            Some((last_event_tx_id, _)) if *last_event_tx_id == event_tx_id => {}
+            _ => transactions.push((event_tx_id, event_id)),
+        }
+    }
+    transactions
+}
+
+/// The effect of the undo and redo operations at the end of the event log.
+struct UndoRedoState {
+    /// The cursor which the repository was most recently restored to. If the
+    /// most recent transaction was not an undo or redo operation, this is the
+    /// default cursor.
+    current_cursor: EventCursor,
+
+    /// The cursors which were in effect before each undo operation that has
+    /// not yet been redone, from least to most recent.
+    redo_cursors: Vec<EventCursor>,
+
+    /// The number of consecutive undo and redo transactions at the end of the
+    /// event log.
+    num_undo_redo_transactions: usize,
+}
+
+#[instrument(skip(transactions))]
+fn get_undo_redo_state(
+    event_log_db: &EventLogDb,
+    event_replayer: &EventReplayer,
+    transactions: &[(EventTransactionId, isize)],
+) -> eyre::Result<UndoRedoState> {
+    let mut undo_redo_transactions = Vec::new();
+    for (event_tx_id, first_event_id) in transactions.iter().rev() {
+        match event_log_db
+            .get_transaction_message(*event_tx_id)?
+            .as_deref()
+            .and_then(UndoRedoTransaction::parse)
+        {
+            Some(transaction) => undo_redo_transactions.push((transaction, *first_event_id)),
+            None => break,
+        }
+    }
+    undo_redo_transactions.reverse();
+
+    let mut current_cursor = match undo_redo_transactions.first() {
+        Some((_transaction, first_event_id)) => event_replayer.make_cursor(*first_event_id),
+        None => event_replayer.make_default_cursor(),
+    };
+    let mut redo_cursors = Vec::new();
+    for (transaction, _first_event_id) in undo_redo_transactions.iter() {
+        match transaction {
+            UndoRedoTransaction::Undo { restored_event_id } => {
+                redo_cursors.push(current_cursor);
+                current_cursor = event_replayer.make_cursor(*restored_event_id);
+            }
+            UndoRedoTransaction::Redo { restored_event_id } => {
+                let restored_cursor = event_replayer.make_cursor(*restored_event_id);
+                while let Some(redo_cursor) = redo_cursors.pop() {
+                    if redo_cursor == restored_cursor {
+                        break;
+                    }
+                }
+                current_cursor = restored_cursor;
+            }
+        }
+    }
+
+    Ok(UndoRedoState {
+        current_cursor,
+        redo_cursors,
+        num_undo_redo_transactions: undo_redo_transactions.len(),
+    })
+}
+
+/// Reapply the changes reverted by the most recent `num_undos` undo
+/// operations.
+#[instrument]
+pub fn redo(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    num_undos: Option<usize>,
+    skip_confirmation: bool,
+    force: bool,
+) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, &repo, &event_log_db)?;
+
+    let transactions = get_transactions(&event_replayer);
+    let UndoRedoState {
+        current_cursor: _,
+        redo_cursors,
+        num_undo_redo_transactions,
+    } = get_undo_redo_state(&event_log_db, &event_replayer, &transactions)?;
+
+    if redo_cursors.is_empty() {
+        if num_undo_redo_transactions > 0 {
+            writeln!(
+                effects.get_output_stream(),
+                "All undo operations have already been redone."
+            )?;
+            return Ok(1);
+        }
+
+        for (event_tx_id, _first_event_id) in transactions.iter().rev() {
+            if let Some(UndoRedoTransaction::Undo { .. }) = event_log_db
+                .get_transaction_message(*event_tx_id)?
+                .as_deref()
+                .and_then(UndoRedoTransaction::parse)
+            {
+                writeln!(
+                    effects.get_output_stream(),
+                    "\
+Events have been recorded since the last undo operation, so it can no longer be redone.
+Run git undo to return to an earlier state instead."
+                )?;
+                return Ok(1);
+            }
+        }
+
+        writeln!(
+            effects.get_output_stream(),
+            "There are no undo operations to redo."
+        )?;
+        return Ok(1);
+    }
+
+    let num_undos = num_undos.unwrap_or(1).clamp(1, redo_cursors.len());
+    let event_cursor = redo_cursors[redo_cursors.len() - num_undos];
+
+    let now = SystemTime::now();
+    let event_tx_id = event_log_db.make_transaction_id(
+        now,
+        format!(
+            "{}{}",
+            REDO_TRANSACTION_MESSAGE_PREFIX,
+            event_cursor.get_event_id()
+        ),
+    )?;
+    let result = restore_cursor(
+        &mut stdin(),
+        effects,
+        &repo,
+        git_run_info,
+        &mut event_log_db,
+        &event_replayer,
+        event_cursor,
+        now,
+        event_tx_id,
+        skip_confirmation,
+        force,
+        false,
     )?;
     Ok(result)
 }
@@ -839,10 +2387,11 @@ pub mod testing {
         siv: CursiveRunner<CursiveRunnable>,
         effects: &Effects,
         repo: &Repo,
+        event_log_db: &EventLogDb,
         dag: &Dag,
         event_replayer: &mut EventReplayer,
     ) -> eyre::Result<Option<EventCursor>> {
-        super::select_past_event(siv, effects, repo, dag, event_replayer)
+        super::select_past_event(siv, effects, repo, event_log_db, dag, event_replayer)
     }
 
     pub fn undo_events(
@@ -853,6 +2402,9 @@ pub mod testing {
         event_log_db: &mut EventLogDb,
         event_replayer: &EventReplayer,
         event_cursor: EventCursor,
+        skip_confirmation: bool,
+        force: bool,
+        show_diff: bool,
     ) -> eyre::Result<isize> {
         super::undo_events(
             in_,
@@ -862,6 +2414,9 @@ pub mod testing {
             event_log_db,
             event_replayer,
             event_cursor,
+            skip_confirmation,
+            force,
+            show_diff,
         )
     }
 }
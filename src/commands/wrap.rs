@@ -54,13 +54,22 @@ fn make_event_tx_id<S: AsRef<str> + std::fmt::Debug>(
     let conn = repo.get_db_conn()?;
     let event_log_db = EventLogDb::new(&conn)?;
     let event_tx_id = {
-        let message = args.first().map(|s| s.as_ref()).unwrap_or("wrap");
-        event_log_db.make_transaction_id(now, message)?
+        // Label the transaction with the wrapped command line, so that it can
+        // be identified later.
+        let message = std::iter::once("git")
+            .chain(args.iter().map(|arg| arg.as_ref()))
+            .join(" ");
+        event_log_db.make_transaction_id(now, &message)?
     };
     Ok(event_tx_id)
 }
 
 /// Run the provided Git command, but wrapped in an event transaction.
+///
+/// Any events recorded by the hooks while the command is running are added to
+/// the same transaction, so that they can be undone together. If this is
+/// itself being run inside a wrapped command, then the outer transaction is
+/// reused.
 pub fn wrap<S: AsRef<str> + std::fmt::Debug>(
     git_run_info: &GitRunInfo,
     args: &[S],
@@ -126,23 +135,13 @@ mod tests {
                 ),
                 ref_name: "HEAD",
                 old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
-                new_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
-                message: None,
-            },
-            RefUpdateEvent {
-                timestamp: 0.0,
-                event_tx_id: EventTransactionId(
-                    3,
-                ),
-                ref_name: "HEAD",
-                old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
                 new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
                 message: None,
             },
             RefUpdateEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    3,
+                    2,
                 ),
                 ref_name: "refs/heads/foo",
                 old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
@@ -152,14 +151,14 @@ mod tests {
             CommitEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    4,
+                    3,
                 ),
                 commit_oid: NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
             },
             RefUpdateEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    5,
+                    4,
                 ),
                 ref_name: "HEAD",
                 old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
@@ -169,7 +168,7 @@ mod tests {
             RefUpdateEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    5,
+                    4,
                 ),
                 ref_name: "refs/heads/foo",
                 old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
@@ -179,14 +178,14 @@ mod tests {
             CommitEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    6,
+                    5,
                 ),
                 commit_oid: NonZeroOid(96d1c37a3d4363611c49f7e52186e189a04c531f),
             },
             RefUpdateEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    7,
+                    6,
                 ),
                 ref_name: "HEAD",
                 old_oid: 96d1c37a3d4363611c49f7e52186e189a04c531f,
@@ -196,7 +195,7 @@ mod tests {
             RefUpdateEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    8,
+                    7,
                 ),
                 ref_name: "HEAD",
                 old_oid: 0000000000000000000000000000000000000000,
@@ -206,7 +205,7 @@ mod tests {
             RefUpdateEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    8,
+                    7,
                 ),
                 ref_name: "HEAD",
                 old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
@@ -216,7 +215,7 @@ mod tests {
             RefUpdateEvent {
                 timestamp: 0.0,
                 event_tx_id: EventTransactionId(
-                    8,
+                    7,
                 ),
                 ref_name: "refs/heads/master",
                 old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
@@ -0,0 +1,145 @@
+//! Keep track of named groups of hidden commits, created by `git hide
+//! --archive`.
+//!
+//! Archiving commits hides them as usual, but also records them under the given
+//! name in the database, so that the whole group can be found and restored
+//! later. Archived commits are kept reachable by `git branchless gc` for as
+//! long as their group exists, rather than only for
+//! `branchless.gc.retentionDays` after they were hidden.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use tracing::instrument;
+
+use crate::core::eventlog::init_db_schema;
+use crate::git::NonZeroOid;
+
+/// A named group of archived commits.
+#[derive(Clone, Debug)]
+pub struct ArchiveGroup {
+    /// The name of the group.
+    pub name: String,
+
+    /// The commits in the group.
+    pub commit_oids: Vec<NonZeroOid>,
+
+    /// When the commits were most recently added to the group.
+    pub timestamp: SystemTime,
+}
+
+/// Wrapper around the archive groups stored in the database.
+pub struct ArchiveDb<'conn> {
+    conn: &'conn rusqlite::Connection,
+}
+
+impl std::fmt::Debug for ArchiveDb<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<ArchiveDb>")
+    }
+}
+
+impl<'conn> ArchiveDb<'conn> {
+    /// Constructor.
+    #[instrument]
+    pub fn new(conn: &'conn rusqlite::Connection) -> eyre::Result<Self> {
+        init_db_schema(conn)?;
+        Ok(ArchiveDb { conn })
+    }
+
+    /// Add the given commits to the group with the given name, creating it if
+    /// it doesn't exist.
+    #[instrument]
+    pub fn add_commits(
+        &self,
+        name: &str,
+        commit_oids: &[NonZeroOid],
+        now: SystemTime,
+    ) -> eyre::Result<()> {
+        let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+        for commit_oid in commit_oids {
+            self.conn.execute(
+                "
+INSERT OR REPLACE INTO archive_commits (name, commit_oid, timestamp)
+VALUES (:name, :commit_oid, :timestamp)
+",
+                rusqlite::named_params! {
+                    ":name": name,
+                    ":commit_oid": commit_oid.to_string(),
+                    ":timestamp": timestamp,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Get all of the groups, sorted by name.
+    #[instrument]
+    pub fn get_groups(&self) -> eyre::Result<Vec<ArchiveGroup>> {
+        let mut stmt = self.conn.prepare(
+            "
+SELECT name, commit_oid, timestamp
+FROM archive_commits
+ORDER BY name, timestamp, rowid
+",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![], |row| {
+            let name: String = row.get("name")?;
+            let commit_oid: String = row.get("commit_oid")?;
+            let timestamp: f64 = row.get("timestamp")?;
+            Ok((name, commit_oid, timestamp))
+        })?;
+
+        let mut result: Vec<ArchiveGroup> = Vec::new();
+        for row in rows {
+            let (name, commit_oid, timestamp) = row?;
+            let commit_oid: NonZeroOid = commit_oid.parse()?;
+            let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs_f64(timestamp);
+            match result.last_mut() {
+                Some(group) if group.name == name => {
+                    group.commit_oids.push(commit_oid);
+                    group.timestamp = group.timestamp.max(timestamp);
+                }
+                Some(_) | None => result.push(ArchiveGroup {
+                    name,
+                    commit_oids: vec![commit_oid],
+                    timestamp,
+                }),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Get the group with the given name, if it exists.
+    #[instrument]
+    pub fn get_group(&self, name: &str) -> eyre::Result<Option<ArchiveGroup>> {
+        let groups = self.get_groups()?;
+        Ok(groups.into_iter().find(|group| group.name == name))
+    }
+
+    /// Delete the group with the given name. Returns whether the group
+    /// existed.
+    #[instrument]
+    pub fn delete_group(&self, name: &str) -> eyre::Result<bool> {
+        let num_rows = self.conn.execute(
+            "
+DELETE FROM archive_commits
+WHERE name = :name
+",
+            rusqlite::named_params! {
+                ":name": name,
+            },
+        )?;
+        Ok(num_rows > 0)
+    }
+
+    /// Get the commits belonging to any group, which should be kept reachable.
+    #[instrument]
+    pub fn get_archived_commits(&self) -> eyre::Result<HashSet<NonZeroOid>> {
+        Ok(self
+            .get_groups()?
+            .into_iter()
+            .flat_map(|group| group.commit_oids)
+            .collect())
+    }
+}
@@ -0,0 +1,181 @@
+//! Apply the hooks and options which `git commit` uses for commit messages
+//! written by the user, for the commits which git-branchless creates itself.
+
+use eyre::Context;
+use tracing::instrument;
+
+use crate::core::config::{get_commit_signoff, get_commit_template_path};
+use crate::core::effects::Effects;
+use crate::core::eventlog::EventTransactionId;
+use crate::git::{GitRunInfo, Repo};
+use crate::opts::CommitOptions;
+use crate::tui::{clean_up_message, MessageCleanupMode};
+
+/// Read the file configured with `commit.template`, if any, to use as the
+/// initial contents of the editor when writing the message of a new commit.
+#[instrument]
+pub fn get_commit_template(git_run_info: &GitRunInfo, repo: &Repo) -> eyre::Result<Option<String>> {
+    let path = match get_commit_template_path(repo)? {
+        Some(path) => git_run_info.working_directory.join(path),
+        None => return Ok(None),
+    };
+    let template = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Reading commit template from {:?}", &path))?;
+    Ok(Some(template))
+}
+
+/// Run the `pre-commit` hook, unless `--no-verify` was passed. Returns `false`
+/// if the hook rejected the commit, in which case its output has already been
+/// shown.
+#[instrument]
+pub fn run_pre_commit_hook(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_tx_id: EventTransactionId,
+    options: &CommitOptions,
+) -> eyre::Result<bool> {
+    if options.no_verify {
+        return Ok(true);
+    }
+    git_run_info.run_hook_checked(effects, repo, "pre-commit", event_tx_id, &[] as &[&str])
+}
+
+/// Add a `Signed-off-by` trailer to `message` if requested, and then run the
+/// `commit-msg` hook on it, unless `--no-verify` was passed. Returns the
+/// resulting message, or `None` if the hook rejected it.
+#[instrument]
+pub fn prepare_commit_message(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_tx_id: EventTransactionId,
+    message: &str,
+    options: &CommitOptions,
+) -> eyre::Result<Option<String>> {
+    let message = if options.signoff || get_commit_signoff(repo)? {
+        let committer_ident = get_committer_ident(git_run_info, repo)?;
+        add_trailer(message, &format!("Signed-off-by: {}", committer_ident))
+    } else {
+        message.to_owned()
+    };
+    if options.no_verify {
+        return Ok(Some(message));
+    }
+    run_commit_msg_hook(effects, git_run_info, repo, event_tx_id, &message)
+}
+
+/// Run the `commit-msg` hook on `message`, which the hook may edit in place.
+/// Returns the resulting message, or `None` if the hook rejected it, in which
+/// case its output has already been shown.
+#[instrument]
+pub fn run_commit_msg_hook(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_tx_id: EventTransactionId,
+    message: &str,
+) -> eyre::Result<Option<String>> {
+    let path = repo.get_path().join("COMMIT_EDITMSG");
+    std::fs::write(&path, message)
+        .wrap_err_with(|| format!("Writing commit message to {:?}", &path))?;
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| eyre::eyre!("Could not convert path to UTF-8 string: {:?}", &path))?;
+    if !git_run_info.run_hook_checked(effects, repo, "commit-msg", event_tx_id, &[path_str])? {
+        return Ok(None);
+    }
+
+    let message = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Reading commit message from {:?}", &path))?;
+    // As with `git commit`, clean up the message again in case the hook
+    // edited it.
+    let message = clean_up_message(
+        repo,
+        &message,
+        MessageCleanupMode::from_config(repo, false)?,
+    )?;
+    Ok(Some(message))
+}
+
+/// Get the name and email of the current committer, as Git would determine
+/// them (including from the `GIT_COMMITTER_NAME` family of environment
+/// variables).
+fn get_committer_ident(git_run_info: &GitRunInfo, repo: &Repo) -> eyre::Result<String> {
+    let ident = git_run_info.run_silent(
+        repo,
+        None,
+        &["var", "GIT_COMMITTER_IDENT"],
+        Default::default(),
+    )?;
+    let ident = String::from_utf8(ident.stdout).wrap_err("Decoding committer identity")?;
+    // The identity is followed by a timestamp, which isn't wanted here.
+    match ident.rfind('>') {
+        Some(index) => Ok(ident[..=index].to_string()),
+        None => eyre::bail!("Could not parse committer identity: {:?}", ident),
+    }
+}
+
+fn is_trailer_line(line: &str) -> bool {
+    match line.split_once(": ") {
+        Some((token, _value)) => {
+            !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        }
+        None => false,
+    }
+}
+
+/// Add `trailer` to the end of `message`, as `git commit --signoff` would: to
+/// the last paragraph if it already consists of trailers, and as a new
+/// paragraph otherwise. Nothing is added if the trailer is already present.
+fn add_trailer(message: &str, trailer: &str) -> String {
+    let message = message.trim_end();
+    let (has_body, last_paragraph) = match message.rsplit_once("\n\n") {
+        Some((_rest, last_paragraph)) => (true, last_paragraph),
+        None => (false, message),
+    };
+    let has_trailers = has_body && last_paragraph.lines().all(is_trailer_line);
+    if has_trailers && last_paragraph.lines().any(|line| line == trailer) {
+        return format!("{}\n", message);
+    }
+
+    let separator = if message.is_empty() {
+        ""
+    } else if has_trailers {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    format!("{}{}{}\n", message, separator, trailer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_trailer() {
+        let trailer = "Signed-off-by: Foo <foo@example.com>";
+        assert_eq!(
+            add_trailer("foo\n", trailer),
+            "foo\n\nSigned-off-by: Foo <foo@example.com>\n"
+        );
+        assert_eq!(
+            add_trailer("foo\n\nbar baz\n", trailer),
+            "foo\n\nbar baz\n\nSigned-off-by: Foo <foo@example.com>\n"
+        );
+        assert_eq!(
+            add_trailer("foo\n\nIssue: ABC-123\n", trailer),
+            "foo\n\nIssue: ABC-123\nSigned-off-by: Foo <foo@example.com>\n"
+        );
+        assert_eq!(
+            add_trailer("foo\n\nSigned-off-by: Foo <foo@example.com>\n", trailer),
+            "foo\n\nSigned-off-by: Foo <foo@example.com>\n"
+        );
+        // The summary line is never treated as a trailer.
+        assert_eq!(
+            add_trailer("Issue: ABC-123\n", trailer),
+            "Issue: ABC-123\n\nSigned-off-by: Foo <foo@example.com>\n"
+        );
+    }
+}
@@ -1,40 +1,103 @@
 //! Accesses repo-specific configuration.
 
+use std::convert::TryInto;
+use std::ffi::OsStr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use tracing::instrument;
 
 use crate::git::{ConfigRead, Repo};
 
 /// Get the path where Git hooks are stored on disk.
+///
+/// As with Git, a relative `core.hooksPath` is interpreted relative to the
+/// root of the working copy (or the repository path, for a bare repository).
 #[instrument]
 pub fn get_core_hooks_path(repo: &Repo) -> eyre::Result<PathBuf> {
-    repo.get_readonly_config()?
-        .get_or_else("core.hooksPath", || repo.get_path().join("hooks"))
+    let hooks_path: Option<PathBuf> = repo.get_readonly_config()?.get("core.hooksPath")?;
+    let hooks_path = match hooks_path {
+        Some(hooks_path) if hooks_path.is_relative() => repo
+            .get_working_copy_path()
+            .unwrap_or_else(|| repo.get_path())
+            .join(hooks_path),
+        Some(hooks_path) => hooks_path,
+        None => repo.get_path().join("hooks"),
+    };
+    Ok(hooks_path)
 }
 
 /// Get the configured name of the main branch.
+///
+/// If several main branches are configured via `branchless.core.mainBranches`
+/// and `branchless.core.mainBranch` isn't set, this is the first of them.
 #[instrument]
 pub fn get_main_branch_name(repo: &Repo) -> eyre::Result<String> {
     let config = repo.get_readonly_config()?;
     let main_branch_name: Option<String> = config.get("branchless.core.mainBranch")?;
     let main_branch_name = match main_branch_name {
         Some(main_branch_name) => main_branch_name,
-        None => {
-            // Deprecated; use `branchless.core.mainBranch` instead.
-            config
-                .get("branchless.mainBranch")?
-                .unwrap_or_else(|| "master".to_string())
-        }
+        None => match config
+            .get_all("branchless.core.mainBranches")?
+            .into_iter()
+            .next()
+        {
+            Some(main_branch_name) => main_branch_name,
+            None => {
+                // Deprecated; use `branchless.core.mainBranch` instead.
+                config
+                    .get("branchless.mainBranch")?
+                    .unwrap_or_else(|| "master".to_string())
+            }
+        },
     };
     Ok(main_branch_name)
 }
 
+/// Get the names of all the main branches. Commits reachable from any of
+/// them are considered public.
+///
+/// The first entry is always the one returned by `get_main_branch_name`. The
+/// rest come from the multivariable `branchless.core.mainBranches` key, such
+/// as for release branches which are maintained alongside the main branch.
+#[instrument]
+pub fn get_main_branch_names(repo: &Repo) -> eyre::Result<Vec<String>> {
+    let main_branch_name = get_main_branch_name(repo)?;
+    let mut result = vec![main_branch_name];
+    for main_branch_name in repo
+        .get_readonly_config()?
+        .get_all("branchless.core.mainBranches")?
+    {
+        if !result.contains(&main_branch_name) {
+            result.push(main_branch_name);
+        }
+    }
+    Ok(result)
+}
+
+/// The number of worker threads to use when carrying out work for many commits
+/// in parallel, as set by `branchless.core.jobs`. A value of `0` (the default)
+/// means to use one thread per available core.
+#[instrument]
+pub fn get_core_jobs(repo: &Repo) -> eyre::Result<usize> {
+    let jobs: i32 = repo
+        .get_readonly_config()?
+        .get_or("branchless.core.jobs", 0)?;
+    Ok(jobs.max(0).try_into()?)
+}
+
+/// Environment variable which Git consults before `init.defaultBranch` when
+/// determining the name of the initial branch.
+const GIT_DEFAULT_INITIAL_BRANCH_NAME_ENV_VAR: &str = "GIT_TEST_DEFAULT_INITIAL_BRANCH_NAME";
+
 /// Get the default init branch name.
 #[instrument]
 pub fn get_default_branch_name(repo: &Repo) -> eyre::Result<Option<String>> {
     let config = repo.get_readonly_config()?;
-    let default_branch_name: Option<String> = config.get("init.defaultBranch")?;
+    let default_branch_name: Option<String> = config.get_or_env(
+        "init.defaultBranch",
+        GIT_DEFAULT_INITIAL_BRANCH_NAME_ENV_VAR,
+    )?;
     Ok(default_branch_name)
 }
 
@@ -46,6 +109,82 @@ pub fn get_restack_preserve_timestamps(repo: &Repo) -> eyre::Result<bool> {
         .get_or("branchless.restack.preserveTimestamps", false)
 }
 
+/// If `true`, sign the commits created when rewriting commits, in the same
+/// way as `git commit` would. Defaults to the value of `commit.gpgsign`, so
+/// setting `branchless.rewrite.sign` to `false` disables signing for
+/// git-branchless only.
+#[instrument]
+pub fn get_rewrite_sign(repo: &Repo) -> eyre::Result<bool> {
+    let config = repo.get_readonly_config()?;
+    let commit_gpgsign = config.get_or("commit.gpgsign", false)?;
+    config.get_or("branchless.rewrite.sign", commit_gpgsign)
+}
+
+/// If `true`, run the `commit-msg` hook on the messages of commits which are
+/// rewritten in-memory (such as by `git move` or `git restack`). Hooks which
+/// check the contents of commits aren't run for rewritten commits.
+#[instrument]
+pub fn get_rewrite_run_commit_msg_hook(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.rewrite.runCommitMsgHook", false)
+}
+
+/// If `true`, add a `Signed-off-by` trailer to the messages of commits
+/// written by the user, as if `--signoff` were passed.
+#[instrument]
+pub fn get_commit_signoff(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?.get_or("format.signOff", false)
+}
+
+/// Get the path to the file used as the initial contents of the editor when
+/// writing the message of a new commit, if any.
+#[instrument]
+pub fn get_commit_template_path(repo: &Repo) -> eyre::Result<Option<PathBuf>> {
+    repo.get_readonly_config()?.get("commit.template")
+}
+
+/// If `true`, fold `fixup!`, `squash!`, and `amend!` commits into the commits
+/// which they target when rewriting commits, as `git rebase --autosquash`
+/// would.
+#[instrument]
+pub fn get_rebase_autosquash(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("rebase.autoSquash", false)
+}
+
+/// If `true`, after amending a commit, restack its descendants onto the
+/// amended commit.
+#[instrument]
+pub fn get_amend_auto_restack(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.amend.autoRestack", true)
+}
+
+/// If `true`, navigation and rewrite commands finish by displaying a compact
+/// smartlog of the current stack, rather than the full smartlog. Only takes
+/// effect when output is written to a terminal.
+#[instrument]
+pub fn get_auto_smartlog(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.autoSmartlog", false)
+}
+
+/// Environment variable which, if set to `1` or `0`, forces the compact
+/// smartlog after navigation and rewrite commands on or off, regardless of
+/// `branchless.autoSmartlog` and whether output is written to a terminal. Set
+/// by the `--smartlog` and `--no-smartlog` flags.
+pub const BRANCHLESS_AUTO_SMARTLOG_ENV_VAR: &str = "BRANCHLESS_AUTO_SMARTLOG";
+
+/// Whether the compact smartlog was forced on or off via the
+/// `BRANCHLESS_AUTO_SMARTLOG` environment variable, if at all.
+pub fn get_auto_smartlog_from_env() -> Option<bool> {
+    match std::env::var(BRANCHLESS_AUTO_SMARTLOG_ENV_VAR).as_deref() {
+        Ok("1") => Some(true),
+        Ok("0") => Some(false),
+        _ => None,
+    }
+}
+
 /// If `true`, when advancing to a "next" commit, prompt interactively to
 /// if there is ambiguity in which commit to advance to.
 #[instrument]
@@ -54,6 +193,56 @@ pub fn get_next_interactive(repo: &Repo) -> eyre::Result<bool> {
         .get_or("branchless.next.interactive", false)
 }
 
+/// If `true`, when navigating to another commit with `git next`, `git prev`,
+/// or `git branchless checkout`, merge uncommitted changes into the
+/// destination commit, as if `--merge` had been passed.
+#[instrument]
+pub fn get_navigation_auto_merge(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.navigation.autoMerge", false)
+}
+
+/// If `true`, when navigating to a commit which has been rewritten, check out
+/// the newest version of the commit instead.
+#[instrument]
+pub fn get_navigation_auto_switch_to_rewritten(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.navigation.autoSwitchToRewritten", false)
+}
+
+/// The number of days for which working copy snapshots are kept before
+/// being deleted by `git branchless gc`.
+#[instrument]
+pub fn get_snapshot_max_age_days(repo: &Repo) -> eyre::Result<i32> {
+    repo.get_readonly_config()?
+        .get_or("branchless.snapshots.maxAgeDays", 14)
+}
+
+/// The number of days for which hidden commits are kept reachable, so that
+/// they can be restored with `git undo`, before being released by `git
+/// branchless gc`.
+#[instrument]
+pub fn get_gc_retention_days(repo: &Repo) -> eyre::Result<i32> {
+    repo.get_readonly_config()?
+        .get_or("branchless.gc.retentionDays", 14)
+}
+
+/// If set, the number of days after which event log transactions are pruned
+/// by the `pre-auto-gc` hook. Pruning is disabled if unset.
+#[instrument]
+pub fn get_eventlog_auto_prune_days(repo: &Repo) -> eyre::Result<Option<i32>> {
+    repo.get_readonly_config()?
+        .get("branchless.eventlog.autoPruneDays")
+}
+
+/// The number of most recent event log transactions which are never pruned,
+/// regardless of their age.
+#[instrument]
+pub fn get_eventlog_prune_keep_transactions(repo: &Repo) -> eyre::Result<i32> {
+    repo.get_readonly_config()?
+        .get_or("branchless.eventlog.pruneKeepTransactions", 100)
+}
+
 /// Config key for `get_restack_warn_abandoned`.
 pub const RESTACK_WARN_ABANDONED_CONFIG_KEY: &str = "branchless.restack.warnAbandoned";
 
@@ -85,3 +274,147 @@ pub fn get_commit_descriptors_relative_time(repo: &Repo) -> eyre::Result<bool> {
     repo.get_readonly_config()?
         .get_or("branchless.commitDescriptors.relativeTime", true)
 }
+
+/// The `branchless.smartlog.annotationCmd` setting: a command which is given
+/// the OIDs of the commits displayed in the smartlog on stdin, one per line,
+/// and writes back `<oid>\t<annotation>` lines to be displayed next to those
+/// commits.
+#[instrument]
+pub fn get_smartlog_annotation_command(repo: &Repo) -> eyre::Result<Option<String>> {
+    repo.get_readonly_config()?
+        .get("branchless.smartlog.annotationCmd")
+}
+
+/// How long to wait for the `branchless.smartlog.annotationCmd` command before
+/// giving up and rendering the smartlog without annotations.
+#[instrument]
+pub fn get_smartlog_annotation_timeout(repo: &Repo) -> eyre::Result<Duration> {
+    let timeout_ms: i32 = repo
+        .get_readonly_config()?
+        .get_or("branchless.smartlog.annotationTimeoutMs", 500)?;
+    Ok(Duration::from_millis(timeout_ms.max(0).try_into()?))
+}
+
+/// If `true`, show the entries in the stash in the smartlog, each attached to
+/// the commit it was created on top of.
+#[instrument]
+pub fn get_smartlog_show_stashes(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.smartlog.showStashes", false)
+}
+
+/// If `true`, mark the draft commits in the smartlog which touch any of the
+/// paths modified in the working copy (see `git smartlog --touches`).
+#[instrument]
+pub fn get_smartlog_mark_related_commits(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.smartlog.markRelatedCommits", false)
+}
+
+/// The `branchless.submit.messageCmd` setting: a command which is run once per
+/// `git submit`, with a JSON description of the submitted stack on stdin, so
+/// that it can create or update pull requests for the stack.
+#[instrument]
+pub fn get_submit_message_command(repo: &Repo) -> eyre::Result<Option<String>> {
+    repo.get_readonly_config()?
+        .get("branchless.submit.messageCmd")
+}
+
+/// The patterns of reference names whose updates should not be recorded in the
+/// event log, and which shouldn't cause commits to be considered visible.
+///
+/// Set the multivar `branchless.eventlog.ignoreRefs` to override the defaults.
+/// Each value is a glob pattern, such as `refs/pipelines/*`.
+#[instrument]
+pub fn get_eventlog_ref_ignore_patterns(repo: &Repo) -> eyre::Result<Vec<String>> {
+    let config = repo.get_readonly_config()?;
+    let patterns = config.get_all("branchless.eventlog.ignoreRefs")?;
+    if !patterns.is_empty() {
+        return Ok(patterns);
+    }
+
+    // Deprecated; use `branchless.eventlog.ignoreRefs` instead.
+    let patterns: Option<String> = config.get("branchless.eventlog.refIgnorePatterns")?;
+    let patterns = match patterns {
+        Some(patterns) => patterns.split_whitespace().map(|s| s.to_string()).collect(),
+        None => ["refs/remotes/*", "refs/notes/*", "refs/branchless/*"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+    };
+    Ok(patterns)
+}
+
+/// Environment variable which, if set to `1`, causes hooks to run even if
+/// git-branchless hasn't been initialized in the repository, creating the
+/// `.git/branchless` directory as needed. Otherwise, such hooks do nothing.
+pub const BRANCHLESS_INITIALIZE_ON_DEMAND_ENV_VAR: &str = "BRANCHLESS_INITIALIZE_ON_DEMAND";
+
+/// Whether or not hooks should run in a repository where git-branchless hasn't
+/// been initialized.
+pub fn get_initialize_on_demand() -> bool {
+    std::env::var_os(BRANCHLESS_INITIALIZE_ON_DEMAND_ENV_VAR).as_deref() == Some(OsStr::new("1"))
+}
+
+/// Environment variable which, if set to `1`, suppresses informational output
+/// from hooks. Takes precedence over `branchless.hooks.quiet`.
+pub const BRANCHLESS_QUIET_ENV_VAR: &str = "BRANCHLESS_QUIET";
+
+/// Environment variable which, if set to `0`, disables the progress lines that
+/// are otherwise logged periodically during long-running operations when
+/// stderr is not a terminal.
+pub const BRANCHLESS_PROGRESS_LOG_ENV_VAR: &str = "BRANCHLESS_PROGRESS_LOG";
+
+/// Whether or not to periodically log the operations in progress when stderr
+/// is not a terminal.
+pub fn get_progress_log_enabled() -> bool {
+    std::env::var_os(BRANCHLESS_PROGRESS_LOG_ENV_VAR).as_deref() != Some(OsStr::new("0"))
+}
+
+/// If `true`, suppress informational output from hooks, such as the
+/// `branchless: processing ...` lines printed after each commit. Errors are
+/// still printed.
+#[instrument]
+pub fn get_hooks_quiet(repo: &Repo) -> eyre::Result<bool> {
+    let quiet = repo
+        .get_readonly_config()?
+        .get_or_env("branchless.hooks.quiet", BRANCHLESS_QUIET_ENV_VAR)?;
+    Ok(quiet.unwrap_or(false))
+}
+
+/// If `false`, the given Git hook (such as `reference-transaction`) does
+/// nothing when invoked, and `git branchless init` installs it as an inert
+/// stub. The events which the hook would have recorded are lost, so e.g.
+/// branch updates can't be undone while the `reference-transaction` hook is
+/// disabled.
+#[instrument]
+pub fn get_hook_enabled(repo: &Repo, hook_type: &str) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or(format!("branchless.hooks.{}.enabled", hook_type), true)
+}
+
+/// Environment variable which, if set to `json`, is equivalent to passing
+/// `--output=json`.
+pub const BRANCHLESS_OUTPUT_ENV_VAR: &str = "BRANCHLESS_OUTPUT";
+
+/// The output format requested via the `BRANCHLESS_OUTPUT` environment
+/// variable, if any.
+pub fn get_output_format_from_env() -> Option<String> {
+    std::env::var(BRANCHLESS_OUTPUT_ENV_VAR).ok()
+}
+
+/// The `branchless.output.color` setting, which controls whether ANSI color
+/// codes are written. One of `always`, `never`, or `auto`. Overridden by the
+/// `--color` flag.
+#[instrument]
+pub fn get_output_color(repo: &Repo) -> eyre::Result<Option<String>> {
+    repo.get_readonly_config()?.get("branchless.output.color")
+}
+
+/// The `branchless.output.glyphs` setting, which controls whether Unicode
+/// box-drawing characters are used. One of `unicode`, `ascii`, or `auto`.
+/// Overridden by the `--glyphs` flag.
+#[instrument]
+pub fn get_output_glyphs(repo: &Repo) -> eyre::Result<Option<String>> {
+    repo.get_readonly_config()?.get("branchless.output.glyphs")
+}
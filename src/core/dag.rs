@@ -2,10 +2,12 @@
 //! allows for efficient graph queries.
 
 use std::borrow::Borrow;
+use std::cell::{Cell, OnceCell};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::iter::FromIterator;
+use std::time::Duration;
 
 use eden_dag::ops::DagPersistent;
 use eden_dag::DagAlgorithm;
@@ -14,8 +16,11 @@ use itertools::Itertools;
 use tracing::{instrument, trace, warn};
 
 use crate::core::effects::{Effects, OperationType};
-use crate::core::eventlog::{CommitActivityStatus, EventCursor, EventReplayer};
-use crate::git::{Commit, MaybeZeroOid, NonZeroOid, Repo, RepoReferencesSnapshot};
+use crate::core::eventlog::{init_db_schema, CommitActivityStatus, EventCursor, EventReplayer};
+use crate::git::{
+    AmbiguousBranchName, Commit, CommitGraph, MaybeZeroOid, NonZeroOid, Repo,
+    RepoReferencesSnapshot, ResolvedBranchName,
+};
 
 impl From<NonZeroOid> for eden_dag::VertexName {
     fn from(oid: NonZeroOid) -> Self {
@@ -79,7 +84,9 @@ pub struct Dag {
     /// this is an empty set.
     pub head_commit: CommitSet,
 
-    /// A set containing the commit that the main branch currently points to.
+    /// A set containing the commits that the main branches currently point
+    /// to. This contains more than one commit if additional main branches are
+    /// configured via `branchless.core.mainBranches`.
     pub main_branch_commit: CommitSet,
 
     /// A set containing all commits currently pointed to by local branches.
@@ -92,6 +99,10 @@ pub struct Dag {
     /// A set containing all commits that have been determined to be obsolete by
     /// the `EventReplayer`.
     pub obsolete_commits: CommitSet,
+
+    merge_base_cache: MergeBaseCache,
+    merge_base_compute_count: Cell<usize>,
+    merge_base_cache_hit_count: Cell<usize>,
 }
 
 impl Dag {
@@ -134,6 +145,7 @@ impl Dag {
         let RepoReferencesSnapshot {
             head_oid,
             main_branch_oid,
+            additional_main_branch_oids,
             branch_oid_to_names,
         } = references_snapshot;
 
@@ -165,7 +177,15 @@ impl Dag {
             Some(head_oid) => CommitSet::from(*head_oid),
             None => CommitSet::empty(),
         };
-        let main_branch_commit = CommitSet::from(*main_branch_oid);
+        let main_branch_commit = CommitSet::from_iter(
+            main_branch_oid
+                .iter()
+                .chain(additional_main_branch_oids.iter())
+                .copied()
+                .map(CommitVertex::from)
+                .map(Ok)
+                .collect_vec(),
+        );
         let branch_commits = CommitSet::from_iter(
             branch_oid_to_names
                 .keys()
@@ -182,6 +202,9 @@ impl Dag {
             branch_commits,
             observed_commits,
             obsolete_commits,
+            merge_base_cache: MergeBaseCache::open(repo),
+            merge_base_compute_count: Cell::new(0),
+            merge_base_cache_hit_count: Cell::new(0),
         })
     }
 
@@ -206,6 +229,26 @@ impl Dag {
         let (effects, _progress) = effects.start_operation(OperationType::UpdateCommitGraph);
         let _effects = effects;
 
+        // The parents of the commits at the boundary of a shallow clone aren't
+        // available, so treat those commits as roots, rather than adding
+        // placeholders for their missing parents.
+        let shallow_commit_oids = repo
+            .get_shallow_commit_oids()
+            .map_err(|err| anyhow::anyhow!("{:?}", err))
+            .map_err(eden_dag::errors::BackendError::Other)?;
+
+        let num_visited_commits = Cell::new(0);
+        let commit_graph: OnceCell<Option<CommitGraph>> = OnceCell::new();
+        let get_commit_graph = || {
+            commit_graph.get_or_init(|| match CommitGraph::open(repo) {
+                Ok(commit_graph) => commit_graph,
+                Err(err) => {
+                    warn!(?err, "Could not read commit-graph");
+                    None
+                }
+            })
+        };
+
         let parent_func = |v: CommitVertex| -> eden_dag::Result<Vec<CommitVertex>> {
             use eden_dag::errors::BackendError;
             trace!(?v, "visiting Git commit");
@@ -217,6 +260,26 @@ impl Dag {
                 MaybeZeroOid::NonZero(oid) => oid,
                 MaybeZeroOid::Zero => return Ok(Vec::new()),
             };
+            if shallow_commit_oids.contains(&oid) {
+                return Ok(Vec::new());
+            }
+
+            num_visited_commits.set(num_visited_commits.get() + 1);
+            if num_visited_commits.get() > COMMIT_GRAPH_MIN_VISITED_COMMITS {
+                if let Some(commit_graph) = get_commit_graph() {
+                    match commit_graph.get_parent_oids(oid) {
+                        Ok(Some(parent_oids)) => {
+                            return Ok(parent_oids.into_iter().map(CommitVertex::from).collect())
+                        }
+                        // The commit was created after the commit-graph was
+                        // last written.
+                        Ok(None) => {}
+                        Err(err) => {
+                            warn!(?err, ?oid, "Could not look up commit in commit-graph");
+                        }
+                    }
+                }
+            }
 
             let commit = repo
                 .find_commit(oid)
@@ -289,16 +352,51 @@ impl Dag {
         lhs_oid: NonZeroOid,
         rhs_oid: NonZeroOid,
     ) -> eyre::Result<Option<NonZeroOid>> {
+        self.get_merge_base_oid(effects, lhs_oid, rhs_oid)
+    }
+
+    /// Look up the merge-base of the given pair of OIDs in the merge-base
+    /// cache, computing and caching it if it's not there.
+    fn get_merge_base_oid(
+        &self,
+        effects: &Effects,
+        lhs_oid: NonZeroOid,
+        rhs_oid: NonZeroOid,
+    ) -> eyre::Result<Option<NonZeroOid>> {
+        if let Some(merge_base_oid) = self.merge_base_cache.get(lhs_oid, rhs_oid) {
+            self.merge_base_cache_hit_count
+                .set(self.merge_base_cache_hit_count.get() + 1);
+            return Ok(merge_base_oid);
+        }
+
+        let (_effects, _progress) = effects.start_operation(OperationType::GetMergeBase);
+        self.merge_base_compute_count
+            .set(self.merge_base_compute_count.get() + 1);
         let set = vec![CommitVertex::from(lhs_oid), CommitVertex::from(rhs_oid)];
         let set = self
             .inner
             .sort(&CommitSet::from_static_names(set))
             .wrap_err("Sorting DAG vertex set")?;
         let vertex = self.inner.gca_one(set).wrap_err("Computing merge-base")?;
-        match vertex {
-            None => Ok(None),
-            Some(vertex) => Ok(Some(vertex.to_hex().parse()?)),
-        }
+        let merge_base_oid = match vertex {
+            None => None,
+            Some(vertex) => Some(vertex.to_hex().parse()?),
+        };
+        self.merge_base_cache
+            .insert(lhs_oid, rhs_oid, merge_base_oid);
+        Ok(merge_base_oid)
+    }
+
+    /// Get the number of merge-bases which this DAG has computed, rather than
+    /// looked up in the merge-base cache.
+    pub fn get_merge_base_compute_count(&self) -> usize {
+        self.merge_base_compute_count.get()
+    }
+
+    /// Get the number of merge-bases which this DAG has looked up in the
+    /// merge-base cache.
+    pub fn get_merge_base_cache_hit_count(&self) -> usize {
+        self.merge_base_cache_hit_count.get()
     }
 
     /// Get the range of OIDs from `parent_oid` to `child_oid`. Note that there
@@ -339,7 +437,8 @@ impl Dag {
         &*self.inner.borrow()
     }
 
-    /// Return the set of commits which are public (checked into the main branch).
+    /// Return the set of commits which are public (checked into any of the
+    /// main branches).
     pub fn query_public_commits(&self) -> eyre::Result<CommitSet> {
         let public_commits = self.query().ancestors(self.main_branch_commit.clone())?;
         Ok(public_commits)
@@ -374,6 +473,88 @@ impl Dag {
         Ok(active_heads)
     }
 
+    /// Query the set of visible commits, i.e. the ancestors of the active
+    /// heads which have been observed and are not obsolete.
+    pub fn query_visible_commits(&self) -> eyre::Result<CommitSet> {
+        let public_commits = self.query_public_commits()?;
+        let active_heads = self.query_active_heads(
+            &public_commits,
+            &self.observed_commits.difference(&self.obsolete_commits),
+        )?;
+        let visible_commits = self.query().ancestors(active_heads)?;
+        Ok(visible_commits)
+    }
+
+    /// Query the set of draft commits, i.e. the visible commits which are not
+    /// public.
+    pub fn query_draft_commits(&self) -> eyre::Result<CommitSet> {
+        let visible_commits = self.query_visible_commits()?;
+        let public_commits = self.query_public_commits()?;
+        Ok(visible_commits.difference(&public_commits))
+    }
+
+    /// Query the set of draft commits in the stacks containing any of the
+    /// given commits. A stack consists of the draft descendants of a draft
+    /// commit with no draft parents.
+    pub fn query_stack_commits(&self, commits: CommitSet) -> eyre::Result<CommitSet> {
+        let draft_commits = self.query_draft_commits()?;
+        let stack_roots = self.query().roots(
+            self.query()
+                .ancestors(commits)?
+                .intersection(&draft_commits),
+        )?;
+        let stack_commits = self.query().descendants(stack_roots)?;
+        Ok(stack_commits.intersection(&draft_commits))
+    }
+
+    /// Find the root of the stack containing the given commit: the first
+    /// ancestor of the commit whose parent is public.
+    #[instrument]
+    pub fn find_stack_root(&self, oid: NonZeroOid) -> eyre::Result<StackRoot> {
+        let draft_ancestors = self
+            .query()
+            .ancestors(CommitSet::from(oid))?
+            .intersection(&self.query_draft_commits()?);
+        if draft_ancestors.is_empty()? {
+            return Ok(StackRoot::Public);
+        }
+
+        // If the stack contains a merge, then there may be several paths
+        // back to the main branch, so there's no single root.
+        let mut merge_oids = Vec::new();
+        for ancestor_oid in commit_set_to_vec(&draft_ancestors)? {
+            if self
+                .query()
+                .parent_names(CommitVertex::from(ancestor_oid))?
+                .len()
+                > 1
+            {
+                merge_oids.push(ancestor_oid);
+            }
+        }
+        if !merge_oids.is_empty() {
+            return Ok(StackRoot::Ambiguous { merge_oids });
+        }
+        match commit_set_to_vec(&self.query().roots(draft_ancestors)?)?.as_slice() {
+            [root_oid] => Ok(StackRoot::Root(*root_oid)),
+            _ => eyre::bail!("Stack without merges should have exactly one root"),
+        }
+    }
+
+    /// Find the commit which the given commits are based on: the only parent
+    /// of their roots which isn't itself one of the commits. For a stack, this
+    /// is its merge-base with the main branch. Returns `None` if the set is
+    /// empty, or if its roots don't have exactly one such parent between them.
+    #[instrument]
+    pub fn find_base_commit(&self, commits: &CommitSet) -> eyre::Result<Option<NonZeroOid>> {
+        let roots = self.query().roots(commits.clone())?;
+        let parents = self.query().parents(roots)?.difference(commits);
+        match commit_set_to_vec(&parents)?.as_slice() {
+            [base_oid] => Ok(Some(*base_oid)),
+            _ => Ok(None),
+        }
+    }
+
     /// Find a shortest path between the given commits.
     ///
     /// This is particularly important for multi-parent commits (i.e. merge commits).
@@ -419,7 +600,8 @@ impl Dag {
     }
 
     /// Find a path from the provided head to its merge-base with the main
-    /// branch.
+    /// branch. If there are several main branches, the path ends at the
+    /// nearest merge-base with any of them.
     #[instrument]
     pub fn find_path_to_main_branch(
         &self,
@@ -427,9 +609,26 @@ impl Dag {
         head: CommitSet,
     ) -> eyre::Result<Option<CommitSet>> {
         // FIXME: this assumes that there is only one merge-base with the main branch.
-        let merge_base = {
-            let (_effects, _progress) = effects.start_operation(OperationType::GetMergeBase);
-            self.query().gca_one(self.main_branch_commit.union(&head))?
+        //
+        // With a single main branch commit, the merge-base depends only on the
+        // pair of commits, so it can be looked up in the merge-base cache.
+        let merge_base = match (
+            commit_set_to_vec(&head)?.as_slice(),
+            commit_set_to_vec(&self.main_branch_commit)?.as_slice(),
+        ) {
+            ([head_oid], [main_branch_oid]) => self
+                .get_merge_base_oid(effects, *head_oid, *main_branch_oid)?
+                .map(CommitVertex::from),
+            _ => {
+                let (_effects, _progress) = effects.start_operation(OperationType::GetMergeBase);
+                self.merge_base_compute_count
+                    .set(self.merge_base_compute_count.get() + 1);
+                let common_ancestors = self
+                    .query()
+                    .ancestors(head.clone())?
+                    .intersection(&self.query_public_commits()?);
+                self.query().heads(common_ancestors)?.first()?
+            }
         };
         let merge_base = match merge_base {
             Some(merge_base) => merge_base,
@@ -445,6 +644,180 @@ impl Dag {
     }
 }
 
+/// The number of commits to look up with libgit2 while syncing the DAG before
+/// switching to Git's commit-graph file, if there is one. The whole file is
+/// read into memory, which is only worthwhile when many commits need to be
+/// visited, such as when the DAG is first built for a large repository.
+const COMMIT_GRAPH_MIN_VISITED_COMMITS: usize = 1000;
+
+/// The maximum number of entries to keep in the merge-base cache. When there
+/// are more, the oldest entries are evicted.
+const MAX_MERGE_BASE_CACHE_ENTRIES: usize = 10_000;
+
+/// How long to wait for another process writing to the merge-base cache.
+const MERGE_BASE_CACHE_BUSY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Persistent cache of merge-bases, stored in the SQLite database, so that
+/// each command doesn't have to recompute the merge-bases of the draft commits
+/// with the main branch. The merge-base of a given pair of commits never
+/// changes, so entries are never invalidated. When the main branch moves,
+/// the entries for its old commit are no longer looked up, and are
+/// eventually evicted.
+///
+/// The cache is best-effort: if the database can't be read or written, such
+/// as when another process is holding it, then the merge-base is computed as
+/// usual.
+struct MergeBaseCache {
+    conn: Option<rusqlite::Connection>,
+    has_new_entries: Cell<bool>,
+}
+
+impl MergeBaseCache {
+    fn open(repo: &Repo) -> Self {
+        let conn = match repo.get_db_conn().and_then(|conn| {
+            init_db_schema(&conn)?;
+            conn.busy_timeout(MERGE_BASE_CACHE_BUSY_TIMEOUT)?;
+            Ok(conn)
+        }) {
+            Ok(conn) => Some(conn),
+            Err(err) => {
+                warn!(?err, "Could not open merge-base cache");
+                None
+            }
+        };
+        MergeBaseCache {
+            conn,
+            has_new_entries: Cell::new(false),
+        }
+    }
+
+    /// The merge-base is symmetric, so store each pair in a canonical order.
+    fn make_key(lhs_oid: NonZeroOid, rhs_oid: NonZeroOid) -> (String, String) {
+        let (lhs_oid, rhs_oid) = if lhs_oid <= rhs_oid {
+            (lhs_oid, rhs_oid)
+        } else {
+            (rhs_oid, lhs_oid)
+        };
+        (lhs_oid.to_string(), rhs_oid.to_string())
+    }
+
+    /// Look up the merge-base of the given commits. The outer `Option` is
+    /// `None` if the merge-base isn't in the cache, and the inner `Option` is
+    /// `None` if the commits have no merge-base.
+    fn get(&self, lhs_oid: NonZeroOid, rhs_oid: NonZeroOid) -> Option<Option<NonZeroOid>> {
+        let result = (|| -> eyre::Result<Option<Option<NonZeroOid>>> {
+            let conn = match &self.conn {
+                Some(conn) => conn,
+                None => return Ok(None),
+            };
+            let (lhs_oid, rhs_oid) = Self::make_key(lhs_oid, rhs_oid);
+            let mut stmt = conn.prepare_cached(
+                "
+SELECT merge_base_oid
+FROM merge_base_cache
+WHERE lhs_oid = :lhs_oid AND rhs_oid = :rhs_oid
+",
+            )?;
+            let mut rows = stmt.query(rusqlite::named_params! {
+                ":lhs_oid": lhs_oid,
+                ":rhs_oid": rhs_oid,
+            })?;
+            let merge_base_oid: Option<String> = match rows.next()? {
+                Some(row) => row.get("merge_base_oid")?,
+                None => return Ok(None),
+            };
+            match merge_base_oid {
+                Some(merge_base_oid) => Ok(Some(Some(merge_base_oid.parse()?))),
+                None => Ok(Some(None)),
+            }
+        })();
+        match result {
+            Ok(merge_base_oid) => merge_base_oid,
+            Err(err) => {
+                warn!(?err, ?lhs_oid, ?rhs_oid, "Could not read merge-base cache");
+                None
+            }
+        }
+    }
+
+    fn insert(&self, lhs_oid: NonZeroOid, rhs_oid: NonZeroOid, merge_base_oid: Option<NonZeroOid>) {
+        let result = (|| -> eyre::Result<()> {
+            let conn = match &self.conn {
+                Some(conn) => conn,
+                None => return Ok(()),
+            };
+            let (lhs_oid, rhs_oid) = Self::make_key(lhs_oid, rhs_oid);
+            conn.execute(
+                "
+INSERT OR REPLACE INTO merge_base_cache (lhs_oid, rhs_oid, merge_base_oid)
+VALUES (:lhs_oid, :rhs_oid, :merge_base_oid)
+",
+                rusqlite::named_params! {
+                    ":lhs_oid": lhs_oid,
+                    ":rhs_oid": rhs_oid,
+                    ":merge_base_oid": merge_base_oid.map(|oid| oid.to_string()),
+                },
+            )?;
+            self.has_new_entries.set(true);
+            Ok(())
+        })();
+        if let Err(err) = result {
+            warn!(?err, ?lhs_oid, ?rhs_oid, "Could not write merge-base cache");
+        }
+    }
+
+    /// Evict the oldest entries if there are too many. Entries are replaced
+    /// rather than updated, so the newest entries have the largest row IDs.
+    fn evict(&self) -> eyre::Result<()> {
+        let conn = match &self.conn {
+            Some(conn) => conn,
+            None => return Ok(()),
+        };
+        conn.execute(
+            "
+DELETE FROM merge_base_cache
+WHERE rowid <= (
+    SELECT rowid
+    FROM merge_base_cache
+    ORDER BY rowid DESC
+    LIMIT 1 OFFSET :max_entries
+)
+",
+            rusqlite::named_params! {
+                ":max_entries": MAX_MERGE_BASE_CACHE_ENTRIES,
+            },
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for MergeBaseCache {
+    fn drop(&mut self) {
+        if self.has_new_entries.get() {
+            if let Err(err) = self.evict() {
+                warn!(?err, "Could not evict entries from merge-base cache");
+            }
+        }
+    }
+}
+
+/// The result of `Dag::find_stack_root`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StackRoot {
+    /// The commit is part of a stack with the given root.
+    Root(NonZeroOid),
+
+    /// The commit is public, so it's not part of any stack.
+    Public,
+
+    /// The stack containing the commit has merge commits in it, so it
+    /// doesn't have a single root.
+    Ambiguous {
+        /// The merge commits in the stack.
+        merge_oids: Vec<NonZeroOid>,
+    },
+}
+
 impl std::fmt::Debug for Dag {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "<Dag>")
@@ -527,6 +900,10 @@ pub enum ResolveCommitsResult<'repo> {
         /// The identifier of the commit, as provided by the user.
         commit: String,
     },
+
+    /// The first name which could refer to several branches, whose names
+    /// differ only in case or Unicode normalization.
+    AmbiguousName(AmbiguousBranchName),
 }
 
 /// Parse strings which refer to commits, such as:
@@ -543,7 +920,14 @@ pub fn resolve_commits<'repo>(
 ) -> eyre::Result<ResolveCommitsResult<'repo>> {
     let mut commits = Vec::new();
     for hash in hashes {
-        let commit = match repo.revparse_single_commit(&hash)? {
+        let commit = match repo.resolve_branch_name(&hash)? {
+            ResolvedBranchName::Found(reference) => reference.peel_to_commit()?,
+            ResolvedBranchName::Ambiguous(ambiguous_name) => {
+                return Ok(ResolveCommitsResult::AmbiguousName(ambiguous_name))
+            }
+            ResolvedBranchName::NotFound => repo.revparse_single_commit(&hash)?,
+        };
+        let commit = match commit {
             Some(commit) => commit,
             None => return Ok(ResolveCommitsResult::CommitNotFound { commit: hash }),
         };
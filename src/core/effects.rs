@@ -12,6 +12,7 @@ use std::time::{Duration, Instant};
 use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use serde_json::{json, Map, Value};
 use tracing::warn;
 
 use crate::core::formatting::Glyphs;
@@ -19,6 +20,7 @@ use crate::core::formatting::Glyphs;
 #[allow(missing_docs)]
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum OperationType {
+    ApplyPatch,
     BuildRebasePlan,
     CalculateDiff,
     CalculatePatchId,
@@ -30,10 +32,15 @@ pub enum OperationType {
     GetMergeBase,
     GetTouchedPaths,
     GetUpstreamPatchIds,
+    ImportReflog,
     InitializeRebase,
     MakeGraph,
+    MergeCommits,
     ProcessEvents,
+    RebaseCommits,
     RunGitCommand(Arc<String>),
+    RunTests,
+    UpdateBranches,
     UpdateCommitGraph,
     WalkCommits,
 }
@@ -41,6 +48,7 @@ pub enum OperationType {
 impl ToString for OperationType {
     fn to_string(&self) -> String {
         let s = match self {
+            OperationType::ApplyPatch => "Applying patches",
             OperationType::BuildRebasePlan => "Building rebase plan",
             OperationType::CalculateDiff => "Computing diffs",
             OperationType::CalculatePatchId => "Hashing commit contents",
@@ -52,12 +60,17 @@ impl ToString for OperationType {
             OperationType::GetMergeBase => "Calculating merge-bases",
             OperationType::GetTouchedPaths => "Getting touched paths",
             OperationType::GetUpstreamPatchIds => "Enumerating patch IDs",
+            OperationType::ImportReflog => "Importing reflog",
             OperationType::InitializeRebase => "Initializing rebase",
             OperationType::MakeGraph => "Examining local history",
+            OperationType::MergeCommits => "Merging commits",
             OperationType::ProcessEvents => "Processing events",
+            OperationType::RebaseCommits => "Rewriting commits",
             OperationType::RunGitCommand(command) => {
                 return format!("Running Git command: {}", &command)
             }
+            OperationType::RunTests => "Running tests",
+            OperationType::UpdateBranches => "Updating branches",
             OperationType::UpdateCommitGraph => "Updating commit graph",
             OperationType::WalkCommits => "Walking commits",
         };
@@ -65,21 +78,195 @@ impl ToString for OperationType {
     }
 }
 
+impl OperationType {
+    /// A stable machine-readable identifier for this operation type, as used
+    /// in JSON output.
+    pub fn get_id(&self) -> &'static str {
+        match self {
+            OperationType::ApplyPatch => "apply_patch",
+            OperationType::BuildRebasePlan => "build_rebase_plan",
+            OperationType::CalculateDiff => "calculate_diff",
+            OperationType::CalculatePatchId => "calculate_patch_id",
+            OperationType::CheckForCycles => "check_for_cycles",
+            OperationType::DetectDuplicateCommits => "detect_duplicate_commits",
+            OperationType::FilterByTouchedPaths => "filter_by_touched_paths",
+            OperationType::FilterCommits => "filter_commits",
+            OperationType::FindPathToMergeBase => "find_path_to_merge_base",
+            OperationType::GetMergeBase => "get_merge_base",
+            OperationType::GetTouchedPaths => "get_touched_paths",
+            OperationType::GetUpstreamPatchIds => "get_upstream_patch_ids",
+            OperationType::ImportReflog => "import_reflog",
+            OperationType::InitializeRebase => "initialize_rebase",
+            OperationType::MakeGraph => "make_graph",
+            OperationType::MergeCommits => "merge_commits",
+            OperationType::ProcessEvents => "process_events",
+            OperationType::RebaseCommits => "rebase_commits",
+            OperationType::RunGitCommand(_) => "run_git_command",
+            OperationType::RunTests => "run_tests",
+            OperationType::UpdateBranches => "update_branches",
+            OperationType::UpdateCommitGraph => "update_commit_graph",
+            OperationType::WalkCommits => "walk_commits",
+        }
+    }
+}
+
+/// A progress notification, as recorded by
+/// `Effects::new_record_progress_for_test`.
+#[allow(missing_docs)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProgressEvent {
+    Start(OperationType),
+    Progress {
+        operation_type: OperationType,
+        current: usize,
+        total: usize,
+    },
+    Status {
+        operation_type: OperationType,
+        status: String,
+    },
+    End(OperationType),
+}
+
+/// A shared handle to an arbitrary output sink provided by a library caller.
+#[derive(Clone)]
+struct SharedWriter(Arc<Mutex<Box<dyn WriteIo + Send>>>);
+
+impl std::fmt::Debug for SharedWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<SharedWriter>")
+    }
+}
+
+impl SharedWriter {
+    fn write_str(&self, s: &str) {
+        let mut writer = self.0.lock().unwrap();
+        if let Err(err) = writer.write_all(s.as_bytes()).and_then(|()| writer.flush()) {
+            warn!(?err, "Failed to write to output sink");
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 enum OutputDest {
     Stdout,
     Suppress,
+    /// Write output to the provided sinks, without reporting progress.
+    Writer {
+        stdout: SharedWriter,
+        stderr: SharedWriter,
+    },
     BufferForTest {
         stdout: Arc<Mutex<Vec<u8>>>,
         stderr: Arc<Mutex<Vec<u8>>>,
     },
+    RecordProgressForTest {
+        events: Arc<Mutex<Vec<ProgressEvent>>>,
+    },
+    /// Write newline-delimited JSON records to stdout, for consumption by
+    /// editor integrations and other tools.
+    Json {
+        progress: Arc<Mutex<HashMap<OperationType, (usize, usize)>>>,
+        result_fields: Arc<Mutex<Map<String, Value>>>,
+    },
+}
+
+impl OutputDest {
+    fn record_progress_event(&self, event: ProgressEvent) {
+        match self {
+            OutputDest::RecordProgressForTest { events } => {
+                events.lock().unwrap().push(event);
+            }
+
+            OutputDest::Json {
+                progress,
+                result_fields: _,
+            } => {
+                let record = match event {
+                    ProgressEvent::Start(operation_type) => json!({
+                        "type": "operation_start",
+                        "operation": operation_type.get_id(),
+                        "description": operation_type.to_string(),
+                    }),
+                    ProgressEvent::Progress {
+                        operation_type,
+                        current,
+                        total,
+                    } => {
+                        progress
+                            .lock()
+                            .unwrap()
+                            .insert(operation_type.clone(), (current, total));
+                        json!({
+                            "type": "progress",
+                            "operation": operation_type.get_id(),
+                            "current": current,
+                            "total": total,
+                        })
+                    }
+                    ProgressEvent::Status {
+                        operation_type,
+                        status,
+                    } => json!({
+                        "type": "status",
+                        "operation": operation_type.get_id(),
+                        "status": status,
+                    }),
+                    ProgressEvent::End(operation_type) => {
+                        progress.lock().unwrap().remove(&operation_type);
+                        json!({
+                            "type": "operation_end",
+                            "operation": operation_type.get_id(),
+                        })
+                    }
+                };
+                write_json_record(&record);
+            }
+
+            OutputDest::Stdout
+            | OutputDest::Suppress
+            | OutputDest::Writer { .. }
+            | OutputDest::BufferForTest { .. } => {}
+        }
+    }
+
+    /// Emit each complete line in `buffer` as a JSON message record, leaving
+    /// any trailing partial line in the buffer.
+    fn write_json_messages(buffer: &mut String, level: &str) {
+        let mut new_buffer = String::new();
+        for line in take(buffer).split_inclusive('\n') {
+            match line.strip_suffix('\n') {
+                Some(line) => write_json_record(&json!({
+                    "type": "message",
+                    "level": level,
+                    "text": line,
+                })),
+                None => new_buffer.push_str(line),
+            }
+        }
+        *buffer = new_buffer;
+    }
+}
+
+/// Write a single JSON record to stdout, followed by a newline.
+fn write_json_record(record: &Value) {
+    let stdout = stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "{}", record).unwrap();
+    stdout.flush().unwrap();
 }
 
+/// When progress can't be rendered interactively (i.e. when stderr is not a
+/// terminal), log the operations which are still in progress at this interval
+/// instead.
+const NON_INTERACTIVE_PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 struct OperationState {
     operation_type: OperationType,
     progress_bar: ProgressBar,
     has_meter: bool,
+    status: Option<String>,
     start_times: Vec<Instant>,
     elapsed_duration: Duration,
 }
@@ -95,6 +282,56 @@ impl OperationState {
         self.progress_bar.inc(increment.try_into().unwrap());
     }
 
+    pub fn set_status(&mut self, status: String) {
+        self.status = Some(status);
+    }
+
+    fn get_elapsed_duration(&self) -> Duration {
+        match self.start_times.iter().min() {
+            None => self.elapsed_duration,
+            Some(start_time) => {
+                let additional_duration = Instant::now().saturating_duration_since(*start_time);
+                self.elapsed_duration + additional_duration
+            }
+        }
+    }
+
+    fn get_message(&self) -> String {
+        let elapsed_duration = self.get_elapsed_duration();
+        match &self.status {
+            None => format!(
+                "{} ({:.1}s)",
+                self.operation_type.to_string(),
+                elapsed_duration.as_secs_f64(),
+            ),
+            Some(status) => format!(
+                "{}: {} ({:.1}s)",
+                self.operation_type.to_string(),
+                status,
+                elapsed_duration.as_secs_f64(),
+            ),
+        }
+    }
+
+    /// Describe the operation in a single line, for when the progress bar
+    /// can't be rendered.
+    pub fn log_line(&self) -> Option<String> {
+        if self.start_times.is_empty() {
+            return None;
+        }
+        let message = self.get_message();
+        if self.has_meter {
+            Some(format!(
+                "{} {}/{}",
+                message,
+                self.progress_bar.position(),
+                self.progress_bar.length()
+            ))
+        } else {
+            Some(message)
+        }
+    }
+
     pub fn tick(&self) {
         lazy_static! {
             static ref CHECKMARK: String = console::style("✓").green().to_string();
@@ -108,19 +345,7 @@ impl OperationState {
                 .tick_strings(&[&CHECKMARK, &CHECKMARK]);
         }
 
-        let elapsed_duration = match self.start_times.iter().min() {
-            None => self.elapsed_duration,
-            Some(start_time) => {
-                let additional_duration = Instant::now().saturating_duration_since(*start_time);
-                self.elapsed_duration + additional_duration
-            }
-        };
-
-        self.progress_bar.set_message(format!(
-            "{} ({:.1}s)",
-            self.operation_type.to_string(),
-            elapsed_duration.as_secs_f64(),
-        ));
+        self.progress_bar.set_message(self.get_message());
         self.progress_bar
             .set_style(match (self.start_times.as_slice(), self.has_meter) {
                 ([], _) => FINISHED_PROGRESS_STYLE.clone(),
@@ -141,6 +366,7 @@ pub struct Effects {
     updater_thread_handle: Arc<RwLock<UpdaterThreadHandle>>,
     nesting_level: usize,
     operation_states: Arc<RwLock<HashMap<OperationType, OperationState>>>,
+    is_output_suppressed: bool,
 }
 
 impl std::fmt::Debug for Effects {
@@ -159,8 +385,10 @@ struct UpdaterThreadHandle {
 }
 
 fn spawn_progress_updater_thread(
+    dest: &OutputDest,
     multi_progress: &Arc<MultiProgress>,
     operation_states: &Arc<RwLock<HashMap<OperationType, OperationState>>>,
+    should_log_progress: bool,
 ) -> Arc<RwLock<UpdaterThreadHandle>> {
     multi_progress.set_draw_target(ProgressDrawTarget::hidden());
     let multi_progress = Arc::downgrade(multi_progress);
@@ -168,6 +396,7 @@ fn spawn_progress_updater_thread(
     let handle = Arc::new(RwLock::new(UpdaterThreadHandle { is_visible: false }));
 
     thread::spawn({
+        let dest = dest.clone();
         let handle = Arc::clone(&handle);
         move || {
             // Don't start displaying progress immediately, since if the operation
@@ -184,15 +413,50 @@ fn spawn_progress_updater_thread(
                 handle.is_visible = true;
             }
 
+            let is_interactive = console::user_attended_stderr();
+            let mut last_log_time = Instant::now();
             loop {
                 // Drop the `Arc` after this block, before the sleep, to make sure
                 // that progress bars aren't kept alive longer than they should be.
                 match operation_states.upgrade() {
                     None => return,
                     Some(operation_states) => {
-                        let operation_states = operation_states.read().unwrap();
-                        for operation_state in operation_states.values() {
-                            operation_state.tick();
+                        let log_lines: Vec<String> = {
+                            let operation_states = operation_states.read().unwrap();
+                            if is_interactive {
+                                for operation_state in operation_states.values() {
+                                    operation_state.tick();
+                                }
+                                Vec::new()
+                            } else if !should_log_progress || operation_states.is_empty() {
+                                last_log_time = Instant::now();
+                                Vec::new()
+                            } else if last_log_time.elapsed() >= NON_INTERACTIVE_PROGRESS_INTERVAL {
+                                last_log_time = Instant::now();
+                                operation_states
+                                    .values()
+                                    .filter_map(|operation_state| operation_state.log_line())
+                                    .sorted()
+                                    .collect()
+                            } else {
+                                Vec::new()
+                            }
+                        };
+
+                        // Write the log lines the same way as
+                        // `Effects::get_error_stream` would, once the lock on
+                        // the operation states has been released.
+                        if !log_lines.is_empty() {
+                            let mut error_stream = ErrorStream {
+                                dest: dest.clone(),
+                                buffer: Default::default(),
+                                updater_thread_handle: Arc::clone(&handle),
+                                operation_states: Arc::clone(&operation_states),
+                            };
+                            for log_line in log_lines {
+                                // Writing to an `ErrorStream` can't fail.
+                                let _ = writeln!(error_stream, "branchless: {}", log_line);
+                            }
                         }
                     }
                 }
@@ -208,17 +472,91 @@ fn spawn_progress_updater_thread(
 impl Effects {
     /// Constructor. Writes to stdout.
     pub fn new(glyphs: Glyphs) -> Self {
+        Self::new_with_progress(glyphs, true)
+    }
+
+    /// Constructor. Writes to stdout and renders progress in the terminal, but
+    /// doesn't periodically log the operations in progress when stderr is not
+    /// a terminal. Used for hooks, whose output is interleaved with that of
+    /// the Git command which invoked them.
+    pub fn new_without_progress_log(glyphs: Glyphs) -> Self {
+        Self::new_with_progress(glyphs, false)
+    }
+
+    fn new_with_progress(glyphs: Glyphs, should_log_progress: bool) -> Self {
+        let dest = OutputDest::Stdout;
         let multi_progress = Default::default();
         let operation_states = Default::default();
-        let updater_thread_handle =
-            spawn_progress_updater_thread(&multi_progress, &operation_states);
+        let updater_thread_handle = spawn_progress_updater_thread(
+            &dest,
+            &multi_progress,
+            &operation_states,
+            should_log_progress,
+        );
         Effects {
             glyphs,
-            dest: OutputDest::Stdout,
+            dest,
             multi_progress,
             updater_thread_handle,
             nesting_level: Default::default(),
             operation_states,
+            is_output_suppressed: false,
+        }
+    }
+
+    /// Constructor. Writes to stdout, but doesn't report progress.
+    pub fn new_without_progress(glyphs: Glyphs) -> Self {
+        let multi_progress: Arc<MultiProgress> = Default::default();
+        multi_progress.set_draw_target(ProgressDrawTarget::hidden());
+        Effects {
+            glyphs,
+            dest: OutputDest::Stdout,
+            multi_progress,
+            updater_thread_handle: Default::default(),
+            nesting_level: Default::default(),
+            operation_states: Default::default(),
+            is_output_suppressed: false,
+        }
+    }
+
+    /// Constructor. Writes newline-delimited JSON records to stdout instead of
+    /// human-readable output and progress indicators.
+    pub fn new_json(glyphs: Glyphs) -> Self {
+        let multi_progress: Arc<MultiProgress> = Default::default();
+        multi_progress.set_draw_target(ProgressDrawTarget::hidden());
+        Effects {
+            glyphs,
+            dest: OutputDest::Json {
+                progress: Default::default(),
+                result_fields: Default::default(),
+            },
+            multi_progress,
+            updater_thread_handle: Default::default(),
+            nesting_level: Default::default(),
+            operation_states: Default::default(),
+            is_output_suppressed: false,
+        }
+    }
+
+    /// Constructor. Writes output and error messages to the provided sinks
+    /// instead of stdout and stderr, and doesn't report progress. This is
+    /// intended for library callers which want to capture output.
+    pub fn new_from_writers(
+        glyphs: Glyphs,
+        stdout: impl WriteIo + Send + 'static,
+        stderr: impl WriteIo + Send + 'static,
+    ) -> Self {
+        Effects {
+            glyphs,
+            dest: OutputDest::Writer {
+                stdout: SharedWriter(Arc::new(Mutex::new(Box::new(stdout)))),
+                stderr: SharedWriter(Arc::new(Mutex::new(Box::new(stderr)))),
+            },
+            multi_progress: Default::default(),
+            updater_thread_handle: Default::default(),
+            nesting_level: Default::default(),
+            operation_states: Default::default(),
+            is_output_suppressed: false,
         }
     }
 
@@ -231,6 +569,7 @@ impl Effects {
             updater_thread_handle: Default::default(),
             nesting_level: Default::default(),
             operation_states: Default::default(),
+            is_output_suppressed: false,
         }
     }
 
@@ -250,6 +589,26 @@ impl Effects {
             updater_thread_handle: Default::default(),
             nesting_level: Default::default(),
             operation_states: Default::default(),
+            is_output_suppressed: false,
+        }
+    }
+
+    /// Constructor. Suppresses all output, but records progress notifications
+    /// into the provided buffer.
+    pub fn new_record_progress_for_test(
+        glyphs: Glyphs,
+        events: &Arc<Mutex<Vec<ProgressEvent>>>,
+    ) -> Self {
+        Effects {
+            glyphs,
+            dest: OutputDest::RecordProgressForTest {
+                events: Arc::clone(events),
+            },
+            multi_progress: Default::default(),
+            updater_thread_handle: Default::default(),
+            nesting_level: Default::default(),
+            operation_states: Default::default(),
+            is_output_suppressed: false,
         }
     }
 
@@ -264,6 +623,62 @@ impl Effects {
         }
     }
 
+    /// Suppress informational output written to the output stream, such as
+    /// when hooks are running in quiet mode. Output written to the error stream
+    /// is still shown.
+    pub fn suppress_output(&self) -> Self {
+        Self {
+            is_output_suppressed: true,
+            ..self.clone()
+        }
+    }
+
+    /// Whether output is being written as JSON records.
+    pub fn is_json_output(&self) -> bool {
+        matches!(self.dest, OutputDest::Json { .. })
+    }
+
+    /// Attach a field to the final `result` record. Does nothing unless output
+    /// is being written as JSON records.
+    pub fn set_result_field(&self, key: &str, value: Value) {
+        if let OutputDest::Json {
+            progress: _,
+            result_fields,
+        } = &self.dest
+        {
+            result_fields.lock().unwrap().insert(key.to_string(), value);
+        }
+    }
+
+    /// Write the final `result` record, including any fields set with
+    /// `set_result_field`. Does nothing unless output is being written as JSON
+    /// records.
+    pub fn write_result_record(&self, exit_code: i32) {
+        if let OutputDest::Json {
+            progress: _,
+            result_fields,
+        } = &self.dest
+        {
+            let mut record = Map::new();
+            record.insert("type".to_string(), json!("result"));
+            record.insert("exit_code".to_string(), json!(exit_code));
+            record.extend(take(&mut *result_fields.lock().unwrap()));
+            write_json_record(&Value::Object(record));
+        }
+    }
+
+    /// Write an `error` record describing `err`. Does nothing unless output is
+    /// being written as JSON records.
+    pub fn write_error_record(&self, err: &eyre::Report) {
+        if let OutputDest::Json { .. } = &self.dest {
+            write_json_record(&json!({
+                "type": "error",
+                "message": err.to_string(),
+                "causes": err.chain().skip(1).map(|cause| cause.to_string()).collect_vec(),
+            }));
+        }
+    }
+
     /// Start reporting progress for the specified operation type.
     ///
     /// A progress spinner is shown until the returned `ProgressHandle` is
@@ -290,9 +705,14 @@ impl Effects {
         };
         match self.dest {
             OutputDest::Stdout => {}
-            OutputDest::Suppress | OutputDest::BufferForTest { .. } => {
+            OutputDest::Suppress | OutputDest::Writer { .. } | OutputDest::BufferForTest { .. } => {
                 return (self.clone(), progress)
             }
+            OutputDest::RecordProgressForTest { .. } | OutputDest::Json { .. } => {
+                self.dest
+                    .record_progress_event(ProgressEvent::Start(operation_type));
+                return (self.clone(), progress);
+            }
         }
 
         let now = Instant::now();
@@ -310,6 +730,7 @@ impl Effects {
                     progress_bar,
                     start_times: Vec::new(),
                     has_meter: false,
+                    status: None,
                     elapsed_duration: Default::default(),
                 };
                 operation_state.tick();
@@ -325,6 +746,11 @@ impl Effects {
     }
 
     fn on_notify_progress(&self, operation_type: OperationType, current: usize, total: usize) {
+        self.dest.record_progress_event(ProgressEvent::Progress {
+            operation_type: operation_type.clone(),
+            current,
+            total,
+        });
         let mut operation_states = self.operation_states.write().unwrap();
         let operation_state = match operation_states.get_mut(&operation_type) {
             Some(operation_state) => operation_state,
@@ -335,6 +761,22 @@ impl Effects {
     }
 
     fn on_notify_progress_inc(&self, operation_type: OperationType, increment: usize) {
+        if let OutputDest::Json {
+            progress,
+            result_fields: _,
+        } = &self.dest
+        {
+            let current_progress = progress.lock().unwrap().get(&operation_type).copied();
+            if let Some((current, total)) = current_progress {
+                self.dest.record_progress_event(ProgressEvent::Progress {
+                    operation_type,
+                    current: current + increment,
+                    total,
+                });
+            }
+            return;
+        }
+
         let mut operation_states = self.operation_states.write().unwrap();
         let operation_state = match operation_states.get_mut(&operation_type) {
             Some(operation_state) => operation_state,
@@ -344,10 +786,31 @@ impl Effects {
         operation_state.inc_progress(increment);
     }
 
+    fn on_notify_status(&self, operation_type: OperationType, status: String) {
+        self.dest.record_progress_event(ProgressEvent::Status {
+            operation_type: operation_type.clone(),
+            status: status.clone(),
+        });
+        let mut operation_states = self.operation_states.write().unwrap();
+        let operation_state = match operation_states.get_mut(&operation_type) {
+            Some(operation_state) => operation_state,
+            None => return,
+        };
+
+        operation_state.set_status(status);
+    }
+
     fn on_drop_progress_handle(&self, operation_type: OperationType) {
         match self.dest {
             OutputDest::Stdout => {}
-            OutputDest::Suppress | OutputDest::BufferForTest { .. } => return,
+            OutputDest::Suppress | OutputDest::Writer { .. } | OutputDest::BufferForTest { .. } => {
+                return
+            }
+            OutputDest::RecordProgressForTest { .. } | OutputDest::Json { .. } => {
+                self.dest
+                    .record_progress_event(ProgressEvent::End(operation_type));
+                return;
+            }
         }
 
         let now = Instant::now();
@@ -402,6 +865,24 @@ impl Effects {
     /// Create a stream that can be written to. The output might go to stdout or
     /// be rendered specially in the terminal.
     pub fn get_output_stream(&self) -> OutputStream {
+        let dest = if self.is_output_suppressed {
+            OutputDest::Suppress
+        } else {
+            self.dest.clone()
+        };
+        OutputStream {
+            dest,
+            buffer: Default::default(),
+            updater_thread_handle: Arc::clone(&self.updater_thread_handle),
+            operation_states: Arc::clone(&self.operation_states),
+        }
+    }
+
+    /// Create a stream for output which is essential to understanding the
+    /// result of the command, such as the outcome of a rewrite or the reason
+    /// that it failed. Unlike `get_output_stream`, this output is still written
+    /// when informational output is suppressed (see `suppress_output`).
+    pub fn get_result_stream(&self) -> OutputStream {
         OutputStream {
             dest: self.dest.clone(),
             buffer: Default::default(),
@@ -560,14 +1041,23 @@ impl Write for OutputStream {
                 self.flush();
             }
 
-            OutputDest::Suppress => {
+            OutputDest::Suppress | OutputDest::RecordProgressForTest { .. } => {
                 // Do nothing.
             }
 
+            OutputDest::Writer { stdout, stderr: _ } => {
+                stdout.write_str(s);
+            }
+
             OutputDest::BufferForTest { stdout, stderr: _ } => {
                 let mut buffer = stdout.lock().unwrap();
                 write!(buffer, "{}", s).unwrap();
             }
+
+            OutputDest::Json { .. } => {
+                self.buffer.push_str(s);
+                OutputDest::write_json_messages(&mut self.buffer, "info");
+            }
         }
         Ok(())
     }
@@ -575,6 +1065,13 @@ impl Write for OutputStream {
 
 impl Drop for OutputStream {
     fn drop(&mut self) {
+        if let OutputDest::Json { .. } = self.dest {
+            if !self.buffer.is_empty() {
+                self.buffer.push('\n');
+                OutputDest::write_json_messages(&mut self.buffer, "info");
+            }
+            return;
+        }
         WriteProgress::drop(self)
     }
 }
@@ -619,14 +1116,23 @@ impl Write for ErrorStream {
                 self.flush();
             }
 
-            OutputDest::Suppress => {
+            OutputDest::Suppress | OutputDest::RecordProgressForTest { .. } => {
                 // Do nothing.
             }
 
+            OutputDest::Writer { stdout: _, stderr } => {
+                stderr.write_str(s);
+            }
+
             OutputDest::BufferForTest { stdout: _, stderr } => {
                 let mut buffer = stderr.lock().unwrap();
                 write!(buffer, "{}", s).unwrap();
             }
+
+            OutputDest::Json { .. } => {
+                self.buffer.push_str(s);
+                OutputDest::write_json_messages(&mut self.buffer, "warning");
+            }
         }
         Ok(())
     }
@@ -634,6 +1140,13 @@ impl Write for ErrorStream {
 
 impl Drop for ErrorStream {
     fn drop(&mut self) {
+        if let OutputDest::Json { .. } = self.dest {
+            if !self.buffer.is_empty() {
+                self.buffer.push('\n');
+                OutputDest::write_json_messages(&mut self.buffer, "warning");
+            }
+            return;
+        }
         WriteProgress::drop(self);
     }
 }
@@ -669,6 +1182,13 @@ impl ProgressHandle<'_> {
         self.effects
             .on_notify_progress_inc(self.operation_type.clone(), increment);
     }
+
+    /// Notify the progress meter of what the current operation is working on,
+    /// such as the commit currently being processed.
+    pub fn notify_status(&self, status: impl Into<String>) {
+        self.effects
+            .on_notify_status(self.operation_type.clone(), status.into());
+    }
 }
 
 #[cfg(test)]
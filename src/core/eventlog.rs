@@ -9,22 +9,44 @@ use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
 
 use std::str::FromStr;
 use std::time::{Duration, SystemTime};
 
+use color_eyre::Help;
 use eyre::Context;
-use tracing::{error, instrument};
+use rusqlite::OptionalExtension;
+use tracing::{error, instrument, warn};
 
+use crate::core::config::get_eventlog_ref_ignore_patterns;
 use crate::core::effects::{Effects, OperationType};
 use crate::git::{
     CategorizedReferenceName, MaybeZeroOid, NonZeroOid, Repo, RepoReferencesSnapshot,
+    SequencingOperation,
 };
 
 /// When this environment variable is set, we reuse the ID for the transaction
 /// which the caller has already started.
 pub const BRANCHLESS_TRANSACTION_ID_ENV_VAR: &str = "BRANCHLESS_TRANSACTION_ID";
 
+/// How long to wait for another process to release its lock on the database
+/// before giving up.
+const DB_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Determine whether the given error was caused by another process holding a
+/// lock on the database for longer than we were willing to wait.
+pub fn is_database_busy_error(err: &eyre::Report) -> bool {
+    err.chain()
+        .any(|err| match err.downcast_ref::<rusqlite::Error>() {
+            Some(rusqlite::Error::SqliteFailure(err, _)) => matches!(
+                err.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            ),
+            _ => false,
+        })
+}
+
 // Wrapper around the row stored directly in the database.
 #[derive(Clone, Debug)]
 struct Row {
@@ -53,13 +75,22 @@ struct Row {
 ///
 /// Unlike in a database, there is no specific guarantee that an event
 /// transaction is an atomic unit of work.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct EventTransactionId(isize);
 
-impl ToString for EventTransactionId {
-    fn to_string(&self) -> String {
+impl EventTransactionId {
+    /// A transaction ID which doesn't correspond to any transaction in the
+    /// event log. Suitable for events which are only ever displayed, and never
+    /// recorded.
+    pub(crate) fn placeholder() -> Self {
+        EventTransactionId(0)
+    }
+}
+
+impl Display for EventTransactionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let EventTransactionId(event_id) = self;
-        event_id.to_string()
+        write!(f, "{}", event_id)
     }
 }
 
@@ -119,6 +150,9 @@ pub enum Event {
         new_oid: MaybeZeroOid,
 
         /// A message associated with the rewrite, if any.
+        ///
+        /// For updates to `HEAD` in a linked worktree, this is the name of the
+        /// worktree, since all worktrees share the same event log.
         message: Option<OsString>,
     },
 
@@ -165,6 +199,29 @@ pub enum Event {
         /// The OID of the commit that was unobsoleted.
         commit_oid: NonZeroOid,
     },
+
+    /// Indicates that the working copy was snapshotted, so that any
+    /// uncommitted changes can be restored later (such as by `git undo`).
+    ///
+    /// See the `snapshot` module for details on how snapshots are stored.
+    WorkingCopySnapshot {
+        /// The timestamp of the event.
+        timestamp: f64,
+
+        /// The transaction ID of the event.
+        event_tx_id: EventTransactionId,
+
+        /// The OID of the commit which `HEAD` pointed to when the snapshot
+        /// was taken.
+        head_oid: MaybeZeroOid,
+
+        /// The OID of the snapshot commit.
+        commit_oid: NonZeroOid,
+
+        /// The name of the reference which `HEAD` was attached to when the
+        /// snapshot was taken, if any.
+        ref_name: Option<OsString>,
+    },
 }
 
 impl Event {
@@ -176,6 +233,7 @@ impl Event {
             Event::CommitEvent { timestamp, .. } => timestamp,
             Event::ObsoleteEvent { timestamp, .. } => timestamp,
             Event::UnobsoleteEvent { timestamp, .. } => timestamp,
+            Event::WorkingCopySnapshot { timestamp, .. } => timestamp,
         };
         SystemTime::UNIX_EPOCH + Duration::from_secs_f64(*timestamp)
     }
@@ -188,6 +246,7 @@ impl Event {
             Event::CommitEvent { event_tx_id, .. } => *event_tx_id,
             Event::ObsoleteEvent { event_tx_id, .. } => *event_tx_id,
             Event::UnobsoleteEvent { event_tx_id, .. } => *event_tx_id,
+            Event::WorkingCopySnapshot { event_tx_id, .. } => *event_tx_id,
         }
     }
 }
@@ -268,6 +327,22 @@ impl From<Event> for Row {
                 ref_name: None,
                 message: None,
             },
+
+            Event::WorkingCopySnapshot {
+                timestamp,
+                event_tx_id: EventTransactionId(event_tx_id),
+                head_oid,
+                commit_oid,
+                ref_name,
+            } => Row {
+                timestamp,
+                event_tx_id,
+                type_: String::from("snapshot"),
+                ref1: Some(head_oid.to_string().into()),
+                ref2: Some(commit_oid.to_string().into()),
+                ref_name,
+                message: None,
+            },
         }
     }
 }
@@ -356,6 +431,18 @@ fn try_from_row_helper(row: &Row) -> Result<Event, eyre::Error> {
             }
         }
 
+        "snapshot" => {
+            let head_oid = get_oid(&ref1, "head OID")?;
+            let commit_oid: NonZeroOid = get_oid(&ref2, "snapshot commit OID")?.try_into()?;
+            Event::WorkingCopySnapshot {
+                timestamp,
+                event_tx_id,
+                head_oid,
+                commit_oid,
+                ref_name,
+            }
+        }
+
         other => eyre::bail!("Unknown event type {}", other),
     };
     Ok(event)
@@ -375,6 +462,19 @@ impl TryFrom<Row> for Event {
     }
 }
 
+/// A row in the event log which couldn't be converted into an `Event`.
+#[derive(Debug)]
+pub struct InvalidEvent {
+    /// The SQLite row ID of the event, used to delete it.
+    rowid: i64,
+
+    /// The transaction ID recorded for the event.
+    pub event_tx_id: EventTransactionId,
+
+    /// A description of why the row couldn't be converted.
+    pub reason: String,
+}
+
 /// Stores `Event`s on disk.
 pub struct EventLogDb<'conn> {
     conn: &'conn rusqlite::Connection,
@@ -386,8 +486,62 @@ impl std::fmt::Debug for EventLogDb<'_> {
     }
 }
 
+/// Configure the connection so that concurrent Git operations (such as a fetch
+/// in one terminal and a commit in another) can write to the event log without
+/// failing immediately.
+#[instrument]
+fn configure_connection(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.busy_timeout(DB_BUSY_TIMEOUT)
+        .wrap_err("Setting database busy timeout")?;
+    // Write-ahead logging lets readers proceed while another process is
+    // writing. The setting is persisted in the database file.
+    let _journal_mode: String = conn
+        .pragma_update_and_check(None, "journal_mode", &"WAL", |row| row.get(0))
+        .wrap_err("Setting database journal mode")?;
+    // `NORMAL` is safe from corruption in WAL mode, and avoids an `fsync` for
+    // every transaction.
+    conn.pragma_update(None, "synchronous", &"NORMAL")
+        .wrap_err("Setting database synchronous mode")?;
+    Ok(())
+}
+
+/// Add a column to a table created by an older version of git-branchless,
+/// which wouldn't have been created with that column.
+#[instrument]
+fn add_column_if_missing(
+    conn: &rusqlite::Connection,
+    table_name: &str,
+    column_name: &str,
+    column_type: &str,
+) -> eyre::Result<()> {
+    let has_column = |conn: &rusqlite::Connection| -> eyre::Result<bool> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table_name))?;
+        let column_names: rusqlite::Result<Vec<String>> = stmt
+            .query_map(rusqlite::params![], |row| row.get("name"))?
+            .collect();
+        Ok(column_names?.iter().any(|name| name == column_name))
+    };
+    if has_column(conn)? {
+        return Ok(());
+    }
+
+    match conn.execute(
+        &format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table_name, column_name, column_type
+        ),
+        rusqlite::params![],
+    ) {
+        Ok(_) => Ok(()),
+        // Another process may have added the column since we checked.
+        Err(_) if has_column(conn)? => Ok(()),
+        Err(err) => Err(err)
+            .wrap_err_with(|| format!("Adding column `{}` to `{}` table", column_name, table_name)),
+    }
+}
+
 #[instrument]
-fn init_tables(conn: &rusqlite::Connection) -> eyre::Result<()> {
+fn create_event_tables(conn: &rusqlite::Connection) -> eyre::Result<()> {
     conn.execute(
         "
 CREATE TABLE IF NOT EXISTS event_log (
@@ -415,51 +569,476 @@ CREATE TABLE IF NOT EXISTS event_transactions (
     -- later?)
     event_tx_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
 
-    message TEXT
+    message TEXT,
+
+    -- The command line which started the transaction, for display purposes.
+    command TEXT
 )
 ",
         rusqlite::params![],
     )
     .wrap_err("Creating `event_transactions` table")?;
+    Ok(())
+}
+
+#[instrument]
+fn add_event_transactions_command_column(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    add_column_if_missing(conn, "event_transactions", "command", "TEXT")
+}
+
+#[instrument]
+fn create_event_log_prunes_table(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS event_log_prunes (
+    timestamp REAL NOT NULL,
+
+    -- Events belonging to transactions before this one may have been deleted.
+    event_tx_id INTEGER NOT NULL
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `event_log_prunes` table")?;
+    Ok(())
+}
+
+#[instrument]
+fn create_smartlog_cache_tables(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS smartlog_cache_info (
+    -- The cache is only valid if these match the current values.
+    version INTEGER NOT NULL,
+    main_branch_oid TEXT,
+    ref_ignore_patterns TEXT NOT NULL,
+
+    -- The `rowid` of the last event which has been applied to the cache.
+    last_rowid INTEGER NOT NULL,
+
+    -- The `rowid` of the last event which wasn't ignored by the replayer.
+    last_retained_rowid INTEGER,
+
+    -- The number of events which weren't ignored by the replayer.
+    num_events INTEGER NOT NULL
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `smartlog_cache_info` table")?;
+
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS smartlog_cache_commits (
+    oid TEXT NOT NULL PRIMARY KEY,
+
+    -- The latest event affecting the commit, and how it was classified.
+    event_id INTEGER NOT NULL,
+    is_visible INTEGER NOT NULL,
+    timestamp REAL NOT NULL,
+    type TEXT NOT NULL,
+    event_tx_id INTEGER NOT NULL,
+    old_ref TEXT,
+    new_ref TEXT,
+    ref_name TEXT,
+    message TEXT
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `smartlog_cache_commits` table")?;
+
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS smartlog_cache_refs (
+    ref_name TEXT NOT NULL PRIMARY KEY,
+    oid TEXT NOT NULL
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `smartlog_cache_refs` table")?;
+    Ok(())
+}
+
+#[instrument]
+fn create_test_results_table(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS test_results (
+    commit_oid TEXT NOT NULL,
+    command TEXT NOT NULL,
+    exit_code INTEGER NOT NULL,
+    timestamp REAL NOT NULL,
+    PRIMARY KEY (commit_oid, command)
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `test_results` table")?;
+    Ok(())
+}
+
+#[instrument]
+fn create_git_version_cache_table(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS git_version_cache (
+    path TEXT NOT NULL PRIMARY KEY,
+    mtime_nanos INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    output TEXT NOT NULL
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `git_version_cache` table")?;
+    Ok(())
+}
+
+#[instrument]
+fn create_merge_base_cache_table(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS merge_base_cache (
+    lhs_oid TEXT NOT NULL,
+    rhs_oid TEXT NOT NULL,
+    merge_base_oid TEXT,
+    PRIMARY KEY (lhs_oid, rhs_oid)
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `merge_base_cache` table")?;
+    Ok(())
+}
+
+#[instrument]
+fn create_touched_paths_cache_table(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS touched_paths_cache (
+    commit_oid TEXT NOT NULL PRIMARY KEY,
+    paths TEXT NOT NULL
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `touched_paths_cache` table")?;
+    Ok(())
+}
+
+#[instrument]
+fn create_archive_commits_table(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.execute(
+        "
+CREATE TABLE IF NOT EXISTS archive_commits (
+    name TEXT NOT NULL,
+    commit_oid TEXT NOT NULL,
+    timestamp REAL NOT NULL,
+    PRIMARY KEY (name, commit_oid)
+)
+",
+        rusqlite::params![],
+    )
+    .wrap_err("Creating `archive_commits` table")?;
+    Ok(())
+}
+
+/// A change to the database schema, applied to databases created by older
+/// versions of git-branchless.
+///
+/// Databases created before the schema was versioned may already have had
+/// some migrations applied, so each migration must be idempotent.
+struct Migration {
+    description: &'static str,
+    apply: fn(&rusqlite::Connection) -> eyre::Result<()>,
+}
+
+/// The migrations which bring a database up to date, in order. The schema
+/// version of a database is the number of these which have been applied to
+/// it, so new migrations must only ever be appended.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "create `event_log` and `event_transactions` tables",
+        apply: create_event_tables,
+    },
+    Migration {
+        description: "add `command` column to `event_transactions` table",
+        apply: add_event_transactions_command_column,
+    },
+    Migration {
+        description: "create `event_log_prunes` table",
+        apply: create_event_log_prunes_table,
+    },
+    Migration {
+        description: "create smartlog cache tables",
+        apply: create_smartlog_cache_tables,
+    },
+    Migration {
+        description: "create `test_results` table",
+        apply: create_test_results_table,
+    },
+    Migration {
+        description: "create `git_version_cache` table",
+        apply: create_git_version_cache_table,
+    },
+    Migration {
+        description: "create `merge_base_cache` table",
+        apply: create_merge_base_cache_table,
+    },
+    Migration {
+        description: "create `touched_paths_cache` table",
+        apply: create_touched_paths_cache_table,
+    },
+    Migration {
+        description: "create `archive_commits` table",
+        apply: create_archive_commits_table,
+    },
+];
+
+/// The schema version of databases written by this version of
+/// git-branchless.
+pub const EVENT_LOG_SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Determine whether the given table exists in the database.
+fn has_table(conn: &rusqlite::Connection, table_name: &str) -> eyre::Result<bool> {
+    let count: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = :name",
+            rusqlite::named_params! {
+                ":name": table_name,
+            },
+            |row| row.get(0),
+        )
+        .wrap_err_with(|| format!("Checking for `{}` table", table_name))?;
+    Ok(count > 0)
+}
+
+/// Get the schema version recorded in the database. Databases created before
+/// the schema was versioned don't have one, and are treated as version 0.
+fn read_schema_version(conn: &rusqlite::Connection) -> eyre::Result<i64> {
+    if !has_table(conn, "schema_version")? {
+        return Ok(0);
+    }
+    let version: Option<i64> = conn
+        .query_row(
+            "SELECT version FROM schema_version",
+            rusqlite::params![],
+            |row| row.get("version"),
+        )
+        .optional()
+        .wrap_err("Reading schema version")?;
+    Ok(version.unwrap_or(0))
+}
+
+fn check_schema_version_supported(version: i64) -> eyre::Result<()> {
+    if version > EVENT_LOG_SCHEMA_VERSION {
+        Err(eyre::eyre!(
+            "The event log database has schema version {}, but this version of git-branchless only supports up to schema version {}",
+            version,
+            EVENT_LOG_SCHEMA_VERSION
+        ))
+        .suggestion(
+            "The database was written by a newer version of git-branchless. Upgrade git-branchless, or move `.git/branchless/db.sqlite3` aside to start over with an empty event log (which can't undo to earlier states).",
+        )
+    } else {
+        Ok(())
+    }
+}
+
+/// Copy the database file before migrating it, so that the original can be
+/// restored if the migration goes wrong. In-memory databases aren't copied.
+#[instrument]
+fn back_up_database(conn: &rusqlite::Connection, version: i64) -> eyre::Result<()> {
+    let path: String = conn
+        .query_row(
+            "SELECT file FROM pragma_database_list WHERE name = 'main'",
+            rusqlite::params![],
+            |row| row.get("file"),
+        )
+        .wrap_err("Finding database file")?;
+    if path.is_empty() {
+        return Ok(());
+    }
 
+    // Another process may have started migrating the database already.
+    let backup_path = format!("{}.schema-v{}.backup", path, version);
+    if std::path::Path::new(&backup_path).exists() {
+        return Ok(());
+    }
+    conn.execute(
+        "VACUUM INTO :path",
+        rusqlite::named_params! {
+            ":path": backup_path,
+        },
+    )
+    .wrap_err_with(|| format!("Backing up database to {:?}", backup_path))?;
+    Ok(())
+}
+
+/// Bring the database schema up to date, by applying any migrations which
+/// haven't been applied yet in a single transaction.
+#[instrument]
+fn migrate_schema(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    let version = read_schema_version(conn)?;
+    if version == EVENT_LOG_SCHEMA_VERSION {
+        return Ok(());
+    }
+    check_schema_version_supported(version)?;
+
+    // A newly-created database has no data to lose.
+    if has_table(conn, "event_log")? || has_table(conn, "event_transactions")? {
+        back_up_database(conn, version)?;
+    }
+
+    let tx = rusqlite::Transaction::new_unchecked(conn, rusqlite::TransactionBehavior::Immediate)?;
+    // Another process may have migrated the database in the meantime.
+    let version = read_schema_version(&tx)?;
+    check_schema_version_supported(version)?;
+    let first_migration = usize::try_from(version)?;
+    for migration in &MIGRATIONS[first_migration..] {
+        (migration.apply)(&tx)
+            .wrap_err_with(|| format!("Migrating event log: {}", migration.description))?;
+    }
+    tx.execute_batch(
+        "
+CREATE TABLE IF NOT EXISTS schema_version (
+    -- The number of migrations which have been applied. There's only one row.
+    version INTEGER NOT NULL
+);
+DELETE FROM schema_version;
+",
+    )
+    .wrap_err("Creating `schema_version` table")?;
+    tx.execute(
+        "INSERT INTO schema_version (version) VALUES (:version)",
+        rusqlite::named_params! {
+            ":version": EVENT_LOG_SCHEMA_VERSION,
+        },
+    )
+    .wrap_err("Writing schema version")?;
+    tx.commit()?;
     Ok(())
 }
 
+/// Configure the database connection and bring the schema up to date. This
+/// covers every table in the database, including those which aren't part of
+/// the event log, so it should be called before using any of them on a
+/// connection which hasn't been passed to `EventLogDb::new`.
+#[instrument]
+pub fn init_db_schema(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    configure_connection(conn)?;
+    migrate_schema(conn)?;
+    Ok(())
+}
+
+/// Read the event columns of a row from the `event_log` table, or from another
+/// table which has the same columns.
+fn read_row(row: &rusqlite::Row) -> rusqlite::Result<Row> {
+    let timestamp: f64 = row.get("timestamp")?;
+    let event_tx_id: isize = row.get("event_tx_id")?;
+    let type_: String = row.get("type")?;
+    let ref_name: Option<String> = row.get("ref_name")?;
+    let old_ref: Option<String> = row.get("old_ref")?;
+    let new_ref: Option<String> = row.get("new_ref")?;
+    let message: Option<String> = row.get("message")?;
+    Ok(Row {
+        timestamp,
+        event_tx_id,
+        type_,
+        ref_name: ref_name.map(OsString::from),
+        ref1: old_ref.map(OsString::from),
+        ref2: new_ref.map(OsString::from),
+        message: message.map(OsString::from),
+    })
+}
+
+fn invalidate_smartlog_cache(conn: &rusqlite::Connection) -> eyre::Result<()> {
+    conn.execute_batch(
+        "
+DELETE FROM smartlog_cache_info;
+DELETE FROM smartlog_cache_commits;
+DELETE FROM smartlog_cache_refs;
+",
+    )
+    .wrap_err("Invalidating smartlog cache")?;
+    Ok(())
+}
+
+/// The version of the format of the smartlog cache tables. Increment this
+/// whenever the way that events are replayed changes, so that existing caches
+/// are rebuilt.
+const SMARTLOG_CACHE_VERSION: i64 = 1;
+
+/// Determines whether the smartlog cache can be used for the repository in its
+/// current state.
+#[derive(Debug, PartialEq, Eq)]
+struct SmartlogCacheKey {
+    main_branch_oid: Option<String>,
+    ref_ignore_patterns: String,
+}
+
+/// The state of an `EventReplayer` as of the last event which was applied to
+/// the smartlog cache.
+#[derive(Debug)]
+struct SmartlogCache {
+    last_rowid: i64,
+    last_retained_rowid: Option<i64>,
+    last_retained_event: Option<Event>,
+    num_events: isize,
+    commit_history: HashMap<NonZeroOid, Vec<EventInfo>>,
+    ref_locations: HashMap<OsString, NonZeroOid>,
+}
+
+/// The changes to write to the smartlog cache.
+#[derive(Debug)]
+struct SmartlogCacheUpdate<'a> {
+    /// The `last_rowid` of the cache which these changes are based on, or
+    /// `None` if the cache should be rebuilt from scratch.
+    base_last_rowid: Option<i64>,
+    last_rowid: i64,
+    last_retained_rowid: Option<i64>,
+    num_events: isize,
+    commits: Vec<(NonZeroOid, &'a EventInfo)>,
+    ref_locations: Vec<(&'a OsStr, Option<NonZeroOid>)>,
+}
+
 impl<'conn> EventLogDb<'conn> {
     /// Constructor.
     #[instrument]
     pub fn new(conn: &'conn rusqlite::Connection) -> eyre::Result<Self> {
-        init_tables(conn)?;
+        init_db_schema(conn)?;
         Ok(EventLogDb { conn })
     }
 
-    /// Add events in the given order to the database, in a transaction.
+    /// Get the schema version of the database (see `EVENT_LOG_SCHEMA_VERSION`).
+    #[instrument]
+    pub fn get_schema_version(&self) -> eyre::Result<i64> {
+        read_schema_version(self.conn)
+    }
+
+    /// Add events in the given order to the database, in a single transaction
+    /// using a single prepared statement. If the database is locked by another
+    /// process for longer than the busy timeout, the write is retried once.
     ///
     /// Args:
     /// * events: The events to add.
     #[instrument]
     pub fn add_events(&mut self, events: Vec<Event>) -> eyre::Result<()> {
-        let tx = self.conn.unchecked_transaction()?;
-        for event in events {
-            let Row {
-                timestamp,
-                type_,
-                event_tx_id,
-                ref1,
-                ref2,
-                ref_name,
-                message,
-            } = Row::from(event);
-
-            // FIXME: it would be ideal to use BLOBs to store the reference
-            // names instead of TEXT, so that we can represent esoteric
-            // reference names (which are derived from path names).
-            let ref1 = ref1.map(|x| x.to_string_lossy().into_owned());
-            let ref2 = ref2.map(|x| x.to_string_lossy().into_owned());
-            let ref_name = ref_name.map(|x| x.to_string_lossy().into_owned());
-            let message = message.map(|x| x.to_string_lossy().into_owned());
+        match self.add_events_inner(events.clone()) {
+            Err(err) if is_database_busy_error(&err) => {
+                warn!(?err, "Database was busy while adding events, retrying");
+                self.add_events_inner(events)
+            }
+            result => result,
+        }
+    }
 
-            tx.execute(
+    fn add_events_inner(&self, events: Vec<Event>) -> eyre::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
                 "
 INSERT INTO event_log VALUES (
     :timestamp,
@@ -471,109 +1050,933 @@ INSERT INTO event_log VALUES (
     :message
 )
             ",
-                rusqlite::named_params! {
-                    ":timestamp": timestamp,
-                    ":type": &type_,
-                    ":event_tx_id": event_tx_id,
-                    ":old_ref": &ref1,
-                    ":new_ref": &ref2,
-                    ":ref_name": &ref_name,
-                    ":message": &message,
-                },
             )?;
+            for event in events {
+                let Row {
+                    timestamp,
+                    type_,
+                    event_tx_id,
+                    ref1,
+                    ref2,
+                    ref_name,
+                    message,
+                } = Row::from(event);
+
+                // FIXME: it would be ideal to use BLOBs to store the reference
+                // names instead of TEXT, so that we can represent esoteric
+                // reference names (which are derived from path names).
+                let ref1 = ref1.map(|x| x.to_string_lossy().into_owned());
+                let ref2 = ref2.map(|x| x.to_string_lossy().into_owned());
+                let ref_name = ref_name.map(|x| x.to_string_lossy().into_owned());
+                let message = message.map(|x| x.to_string_lossy().into_owned());
+
+                stmt.execute(rusqlite::named_params! {
+                    ":timestamp": timestamp,
+                    ":type": &type_,
+                    ":event_tx_id": event_tx_id,
+                    ":old_ref": &ref1,
+                    ":new_ref": &ref2,
+                    ":ref_name": &ref_name,
+                    ":message": &message,
+                })?;
+            }
         }
         tx.commit()?;
         Ok(())
     }
 
+    /// Load the raw rows of the event log, along with their SQLite row IDs,
+    /// ordered from oldest to newest.
+    fn get_rows(&self) -> eyre::Result<Vec<(i64, Row)>> {
+        self.get_rows_after(0)
+    }
+
+    /// Load the raw rows of the event log whose SQLite row IDs are greater
+    /// than `rowid`, ordered from oldest to newest.
+    fn get_rows_after(&self, rowid: i64) -> eyre::Result<Vec<(i64, Row)>> {
+        let mut stmt = self.conn.prepare(
+            "
+SELECT rowid, timestamp, type, event_tx_id, old_ref, new_ref, ref_name, message
+FROM event_log
+WHERE rowid > :rowid
+ORDER BY rowid ASC
+",
+        )?;
+        let rows: rusqlite::Result<Vec<(i64, Row)>> = stmt
+            .query_map(rusqlite::named_params! { ":rowid": rowid }, |row| {
+                Ok((row.get("rowid")?, read_row(row)?))
+            })?
+            .collect();
+        Ok(rows?)
+    }
+
     /// Get all the events in the database.
     ///
     /// Returns: All the events in the database, ordered from oldest to newest.
     #[instrument]
-
     pub fn get_events(&self) -> eyre::Result<Vec<Event>> {
-        let mut stmt = self.conn.prepare(
-            "
+        self.get_rows()?
+            .into_iter()
+            .map(|(_rowid, row)| Event::try_from(row))
+            .collect()
+    }
+
+    /// Get all the events in the database, along with their SQLite row IDs,
+    /// which can be passed to `prune_events`.
+    ///
+    /// Returns: All the events in the database, ordered from oldest to newest.
+    #[instrument]
+    pub fn get_events_with_rowids(&self) -> eyre::Result<Vec<(i64, Event)>> {
+        self.get_rows()?
+            .into_iter()
+            .map(|(rowid, row)| Ok((rowid, Event::try_from(row)?)))
+            .collect()
+    }
+
+    /// Get the events in the database whose SQLite row IDs are greater than
+    /// `rowid`, along with their row IDs.
+    ///
+    /// Returns: The events, ordered from oldest to newest.
+    #[instrument]
+    fn get_events_with_rowids_after(&self, rowid: i64) -> eyre::Result<Vec<(i64, Event)>> {
+        self.get_rows_after(rowid)?
+            .into_iter()
+            .map(|(rowid, row)| Ok((rowid, Event::try_from(row)?)))
+            .collect()
+    }
+
+    /// Discard the state cached by `EventReplayer::from_event_log_db_incremental`,
+    /// so that it's rebuilt from all the events the next time that it's used.
+    /// This must be called whenever events are changed or deleted, rather than
+    /// added.
+    #[instrument]
+    pub fn invalidate_smartlog_cache(&self) -> eyre::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        invalidate_smartlog_cache(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Load the smartlog cache, or `None` if it's missing or can't be used
+    /// with `key`.
+    #[instrument]
+    fn read_smartlog_cache(&self, key: &SmartlogCacheKey) -> eyre::Result<Option<SmartlogCache>> {
+        let info = self
+            .conn
+            .query_row(
+                "
+SELECT version, main_branch_oid, ref_ignore_patterns, last_rowid, last_retained_rowid, num_events
+FROM smartlog_cache_info
+",
+                rusqlite::params![],
+                |row| {
+                    let version: i64 = row.get("version")?;
+                    let cache_key = SmartlogCacheKey {
+                        main_branch_oid: row.get("main_branch_oid")?,
+                        ref_ignore_patterns: row.get("ref_ignore_patterns")?,
+                    };
+                    let last_rowid: i64 = row.get("last_rowid")?;
+                    let last_retained_rowid: Option<i64> = row.get("last_retained_rowid")?;
+                    let num_events: isize = row.get("num_events")?;
+                    Ok((
+                        version,
+                        cache_key,
+                        last_rowid,
+                        last_retained_rowid,
+                        num_events,
+                    ))
+                },
+            )
+            .optional()
+            .wrap_err("Querying smartlog cache info")?;
+        let (last_rowid, last_retained_rowid, num_events) = match info {
+            Some((version, cache_key, last_rowid, last_retained_rowid, num_events))
+                if version == SMARTLOG_CACHE_VERSION && &cache_key == key =>
+            {
+                (last_rowid, last_retained_rowid, num_events)
+            }
+            _ => return Ok(None),
+        };
+
+        // If the event log no longer contains the events which the cache was
+        // based on, then it's stale.
+        let max_rowid: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT MAX(rowid) FROM event_log",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .wrap_err("Querying event log size")?;
+        if last_rowid > max_rowid.unwrap_or_default() {
+            return Ok(None);
+        }
+        let last_retained_event = match last_retained_rowid {
+            None => None,
+            Some(last_retained_rowid) => {
+                let row = self
+                    .conn
+                    .query_row(
+                        "
 SELECT timestamp, type, event_tx_id, old_ref, new_ref, ref_name, message
 FROM event_log
-ORDER BY rowid ASC
+WHERE rowid = :rowid
 ",
-        )?;
-        let rows: rusqlite::Result<Vec<Row>> = stmt
-            .query_map(rusqlite::params![], |row| {
-                let timestamp: f64 = row.get("timestamp")?;
-                let event_tx_id: isize = row.get("event_tx_id")?;
-                let type_: String = row.get("type")?;
-                let ref_name: Option<String> = row.get("ref_name")?;
-                let old_ref: Option<String> = row.get("old_ref")?;
-                let new_ref: Option<String> = row.get("new_ref")?;
-                let message: Option<String> = row.get("message")?;
-
-                Ok(Row {
+                        rusqlite::named_params! { ":rowid": last_retained_rowid },
+                        read_row,
+                    )
+                    .optional()
+                    .wrap_err("Querying last retained event")?;
+                match row {
+                    Some(row) => Some(Event::try_from(row)?),
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        let mut commit_history: HashMap<NonZeroOid, Vec<EventInfo>> = HashMap::new();
+        {
+            let mut stmt = self.conn.prepare(
+                "
+SELECT oid, event_id, is_visible, timestamp, type, event_tx_id, old_ref, new_ref, ref_name, message
+FROM smartlog_cache_commits
+",
+            )?;
+            let rows: rusqlite::Result<Vec<(String, isize, bool, Row)>> = stmt
+                .query_map(rusqlite::params![], |row| {
+                    Ok((
+                        row.get("oid")?,
+                        row.get("event_id")?,
+                        row.get("is_visible")?,
+                        read_row(row)?,
+                    ))
+                })?
+                .collect();
+            for (oid, id, is_visible, row) in rows? {
+                let event_info = EventInfo {
+                    id,
+                    event: Event::try_from(row)?,
+                    event_classification: if is_visible {
+                        EventClassification::Show
+                    } else {
+                        EventClassification::Hide
+                    },
+                };
+                commit_history.insert(oid.parse()?, vec![event_info]);
+            }
+        }
+
+        let mut ref_locations: HashMap<OsString, NonZeroOid> = HashMap::new();
+        {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT ref_name, oid FROM smartlog_cache_refs")?;
+            let rows: rusqlite::Result<Vec<(String, String)>> = stmt
+                .query_map(rusqlite::params![], |row| {
+                    Ok((row.get("ref_name")?, row.get("oid")?))
+                })?
+                .collect();
+            for (ref_name, oid) in rows? {
+                ref_locations.insert(OsString::from(ref_name), oid.parse()?);
+            }
+        }
+
+        Ok(Some(SmartlogCache {
+            last_rowid,
+            last_retained_rowid,
+            last_retained_event,
+            num_events,
+            commit_history,
+            ref_locations,
+        }))
+    }
+
+    /// Write the given changes to the smartlog cache. If another process has
+    /// updated the cache since it was read, then nothing is written.
+    #[instrument]
+    fn write_smartlog_cache(
+        &self,
+        key: &SmartlogCacheKey,
+        update: SmartlogCacheUpdate,
+    ) -> eyre::Result<()> {
+        let SmartlogCacheUpdate {
+            base_last_rowid,
+            last_rowid,
+            last_retained_rowid,
+            num_events,
+            commits,
+            ref_locations,
+        } = update;
+
+        let tx = self.conn.unchecked_transaction()?;
+        match base_last_rowid {
+            Some(base_last_rowid) => {
+                let current_last_rowid: Option<i64> = tx
+                    .query_row(
+                        "SELECT last_rowid FROM smartlog_cache_info",
+                        rusqlite::params![],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                if current_last_rowid != Some(base_last_rowid) {
+                    return Ok(());
+                }
+            }
+            None => invalidate_smartlog_cache(&tx)?,
+        }
+
+        {
+            let mut stmt = tx.prepare_cached(
+                "
+INSERT OR REPLACE INTO smartlog_cache_commits VALUES (
+    :oid,
+    :event_id,
+    :is_visible,
+    :timestamp,
+    :type,
+    :event_tx_id,
+    :old_ref,
+    :new_ref,
+    :ref_name,
+    :message
+)
+",
+            )?;
+            for (oid, event_info) in commits {
+                let EventInfo {
+                    id,
+                    event,
+                    event_classification,
+                } = event_info;
+                let Row {
                     timestamp,
-                    event_tx_id,
                     type_,
-                    ref_name: ref_name.map(OsString::from),
-                    ref1: old_ref.map(OsString::from),
-                    ref2: new_ref.map(OsString::from),
-                    message: message.map(OsString::from),
-                })
-            })?
+                    event_tx_id,
+                    ref1,
+                    ref2,
+                    ref_name,
+                    message,
+                } = Row::from(event.clone());
+                let is_visible = match event_classification {
+                    EventClassification::Show => true,
+                    EventClassification::Hide => false,
+                };
+                stmt.execute(rusqlite::named_params! {
+                    ":oid": oid.to_string(),
+                    ":event_id": id,
+                    ":is_visible": is_visible,
+                    ":timestamp": timestamp,
+                    ":type": &type_,
+                    ":event_tx_id": event_tx_id,
+                    ":old_ref": ref1.map(|x| x.to_string_lossy().into_owned()),
+                    ":new_ref": ref2.map(|x| x.to_string_lossy().into_owned()),
+                    ":ref_name": ref_name.map(|x| x.to_string_lossy().into_owned()),
+                    ":message": message.map(|x| x.to_string_lossy().into_owned()),
+                })?;
+            }
+        }
+
+        for (ref_name, oid) in ref_locations {
+            let ref_name = ref_name.to_string_lossy();
+            match oid {
+                Some(oid) => tx.execute(
+                    "INSERT OR REPLACE INTO smartlog_cache_refs VALUES (:ref_name, :oid)",
+                    rusqlite::named_params! {
+                        ":ref_name": ref_name,
+                        ":oid": oid.to_string(),
+                    },
+                )?,
+                None => tx.execute(
+                    "DELETE FROM smartlog_cache_refs WHERE ref_name = :ref_name",
+                    rusqlite::named_params! {
+                        ":ref_name": ref_name,
+                    },
+                )?,
+            };
+        }
+
+        let SmartlogCacheKey {
+            main_branch_oid,
+            ref_ignore_patterns,
+        } = key;
+        tx.execute("DELETE FROM smartlog_cache_info", rusqlite::params![])?;
+        tx.execute(
+            "
+INSERT INTO smartlog_cache_info VALUES (
+    :version,
+    :main_branch_oid,
+    :ref_ignore_patterns,
+    :last_rowid,
+    :last_retained_rowid,
+    :num_events
+)
+",
+            rusqlite::named_params! {
+                ":version": SMARTLOG_CACHE_VERSION,
+                ":main_branch_oid": main_branch_oid,
+                ":ref_ignore_patterns": ref_ignore_patterns,
+                ":last_rowid": last_rowid,
+                ":last_retained_rowid": last_retained_rowid,
+                ":num_events": num_events,
+            },
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Permanently delete the events with the given row IDs, along with any
+    /// transactions before `pruned_before_event_tx_id` which no longer have
+    /// events, and record that history before that transaction is incomplete.
+    #[instrument]
+    pub fn prune_events(
+        &self,
+        now: SystemTime,
+        rowids: &[i64],
+        pruned_before_event_tx_id: EventTransactionId,
+    ) -> eyre::Result<()> {
+        let EventTransactionId(pruned_before_event_tx_id) = pruned_before_event_tx_id;
+        let timestamp = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .wrap_err("Calculating event log prune timestamp")?
+            .as_secs_f64();
+
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM event_log WHERE rowid = :rowid")?;
+            for rowid in rowids {
+                stmt.execute(rusqlite::named_params! {
+                    ":rowid": rowid,
+                })?;
+            }
+        }
+        tx.execute(
+            "
+DELETE FROM event_transactions
+WHERE event_tx_id < :event_tx_id
+AND event_tx_id NOT IN (SELECT event_tx_id FROM event_log)
+",
+            rusqlite::named_params! {
+                ":event_tx_id": pruned_before_event_tx_id,
+            },
+        )
+        .wrap_err("Deleting pruned event transactions")?;
+        tx.execute(
+            "INSERT INTO event_log_prunes VALUES (:timestamp, :event_tx_id)",
+            rusqlite::named_params! {
+                ":timestamp": timestamp,
+                ":event_tx_id": pruned_before_event_tx_id,
+            },
+        )
+        .wrap_err("Recording event log prune")?;
+        invalidate_smartlog_cache(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Get the ID of the earliest transaction whose history is complete, if
+    /// events have ever been pruned from the database. Earlier transactions may
+    /// be missing some or all of their events.
+    #[instrument]
+    pub fn get_pruned_before_event_tx_id(&self) -> eyre::Result<Option<EventTransactionId>> {
+        let event_tx_id: Option<isize> = self
+            .conn
+            .query_row(
+                "SELECT MAX(event_tx_id) FROM event_log_prunes",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .wrap_err("Querying event log prunes")?;
+        Ok(event_tx_id.map(EventTransactionId))
+    }
+
+    /// Reclaim the space used by deleted events.
+    #[instrument]
+    pub fn vacuum(&self) -> eyre::Result<()> {
+        self.conn
+            .execute_batch("VACUUM")
+            .wrap_err("Vacuuming database")?;
+        // Otherwise, the vacuumed database stays in the write-ahead log until
+        // the next automatic checkpoint.
+        self.conn
+            .query_row(
+                "PRAGMA wal_checkpoint(TRUNCATE)",
+                rusqlite::params![],
+                |_row| Ok(()),
+            )
+            .wrap_err("Checkpointing database")?;
+        Ok(())
+    }
+
+    /// Get all the events in the database which can be loaded, along with the
+    /// rows which can't be (such as those left behind by an interrupted or
+    /// incompatible write). Unlike `get_events`, this doesn't fail if the
+    /// event log contains invalid rows.
+    ///
+    /// Returns: The valid events, ordered from oldest to newest, and the
+    /// invalid events.
+    #[instrument]
+    pub fn get_events_and_invalid_events(&self) -> eyre::Result<(Vec<Event>, Vec<InvalidEvent>)> {
+        let mut events = Vec::new();
+        let mut invalid_events = Vec::new();
+        for (rowid, row) in self.get_rows()? {
+            match try_from_row_helper(&row) {
+                Ok(event) => events.push(event),
+                Err(err) => invalid_events.push(InvalidEvent {
+                    rowid,
+                    event_tx_id: EventTransactionId(row.event_tx_id),
+                    reason: err.to_string(),
+                }),
+            }
+        }
+        Ok((events, invalid_events))
+    }
+
+    /// Permanently delete the given invalid events from the database.
+    #[instrument]
+    pub fn delete_invalid_events(&self, invalid_events: &[InvalidEvent]) -> eyre::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for InvalidEvent { rowid, .. } in invalid_events {
+            tx.execute(
+                "DELETE FROM event_log WHERE rowid = :rowid",
+                rusqlite::named_params! {
+                    ":rowid": rowid,
+                },
+            )?;
+        }
+        invalidate_smartlog_cache(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Get the IDs of event transactions which have events in the database,
+    /// but which were never recorded in the transactions table (such as if the
+    /// process was interrupted while writing the transaction).
+    #[instrument]
+    pub fn get_orphaned_transaction_ids(&self) -> eyre::Result<Vec<EventTransactionId>> {
+        let mut stmt = self.conn.prepare(
+            "
+SELECT DISTINCT event_tx_id
+FROM event_log
+WHERE event_tx_id NOT IN (SELECT event_tx_id FROM event_transactions)
+ORDER BY event_tx_id ASC
+",
+        )?;
+        let event_tx_ids: rusqlite::Result<Vec<isize>> = stmt
+            .query_map(rusqlite::params![], |row| row.get("event_tx_id"))?
             .collect();
-        let rows = rows?;
-        rows.into_iter().map(Event::try_from).collect()
+        Ok(event_tx_ids?.into_iter().map(EventTransactionId).collect())
+    }
+
+    /// Record an entry in the transactions table for a transaction which has
+    /// events but no entry of its own, so that it can be displayed and undone
+    /// like any other transaction.
+    #[instrument]
+    pub fn restore_orphaned_transaction(
+        &self,
+        event_tx_id: EventTransactionId,
+        message: &str,
+    ) -> eyre::Result<()> {
+        let EventTransactionId(event_tx_id) = event_tx_id;
+        self.conn
+            .execute(
+                "
+            INSERT INTO event_transactions
+            (timestamp, event_tx_id, message)
+            SELECT MIN(timestamp), :event_tx_id, :message
+            FROM event_log
+            WHERE event_tx_id = :event_tx_id
+        ",
+                rusqlite::named_params! {
+                    ":event_tx_id": event_tx_id,
+                    ":message": message,
+                },
+            )
+            .wrap_err("Restoring event transaction")?;
+        Ok(())
+    }
+
+    #[instrument(skip(get_command))]
+    fn make_transaction_id_inner(
+        &self,
+        now: SystemTime,
+        message: &str,
+        get_command: impl FnOnce() -> String,
+    ) -> eyre::Result<EventTransactionId> {
+        if let Some(transaction_id) = get_transaction_id_from_env() {
+            return Ok(transaction_id);
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+
+        let timestamp = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .wrap_err("Calculating event transaction timestamp")?
+            .as_secs_f64();
+        let command = get_command();
+        self.conn
+            .execute(
+                "
+            INSERT INTO event_transactions
+            (timestamp, message, command)
+            VALUES
+            (:timestamp, :message, :command)
+        ",
+                rusqlite::named_params! {
+                    ":timestamp": timestamp,
+                    ":message": message,
+                    ":command": command,
+                },
+            )
+            .wrap_err("Creating event transaction")?;
+
+        // Ensure that we query `last_insert_rowid` in a transaction, in case
+        // there's another thread in this process making queries with the same
+        // SQLite connection.
+        let event_tx_id: isize = self.conn.last_insert_rowid().try_into()?;
+        tx.commit()?;
+        Ok(EventTransactionId(event_tx_id))
+    }
+
+    /// Get the message that was recorded when the given event transaction was
+    /// created, if any.
+    #[instrument]
+    pub fn get_transaction_message(
+        &self,
+        event_tx_id: EventTransactionId,
+    ) -> eyre::Result<Option<String>> {
+        let EventTransactionId(event_tx_id) = event_tx_id;
+        let mut stmt = self.conn.prepare(
+            "
+SELECT message
+FROM event_transactions
+WHERE event_tx_id = :event_tx_id
+",
+        )?;
+        let messages: rusqlite::Result<Vec<Option<String>>> = stmt
+            .query_map(
+                rusqlite::named_params! {
+                    ":event_tx_id": event_tx_id,
+                },
+                |row| row.get("message"),
+            )?
+            .collect();
+        Ok(messages?.into_iter().next().flatten())
+    }
+
+    /// Get the command line which started the given event transaction, if it
+    /// was recorded. Transactions created by older versions of git-branchless
+    /// don't have one.
+    #[instrument]
+    pub fn get_transaction_command(
+        &self,
+        event_tx_id: EventTransactionId,
+    ) -> eyre::Result<Option<String>> {
+        let EventTransactionId(event_tx_id) = event_tx_id;
+        let command: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT command FROM event_transactions WHERE event_tx_id = :event_tx_id",
+                rusqlite::named_params! {
+                    ":event_tx_id": event_tx_id,
+                },
+                |row| row.get("command"),
+            )
+            .optional()
+            .wrap_err("Querying event transaction command")?;
+        Ok(command.flatten())
+    }
+
+    /// Create a new event transaction ID to be used to insert subsequent
+    /// `Event`s into the database. The command line of the current process is
+    /// recorded as the command which started the transaction.
+    pub fn make_transaction_id(
+        &self,
+        now: SystemTime,
+        message: impl AsRef<str>,
+    ) -> eyre::Result<EventTransactionId> {
+        self.make_transaction_id_inner(now, message.as_ref(), || {
+            describe_branchless_command(std::env::args_os())
+        })
+    }
+
+    /// Like `make_transaction_id`, but record the provided command as the one
+    /// which started the transaction, rather than the current command line.
+    /// This is used for changes which are detected after the fact, such as
+    /// stash entries which were dropped without a hook being invoked.
+    pub fn make_transaction_id_with_command(
+        &self,
+        now: SystemTime,
+        message: impl AsRef<str>,
+        command: impl AsRef<str>,
+    ) -> eyre::Result<EventTransactionId> {
+        self.make_transaction_id_inner(now, message.as_ref(), || command.as_ref().to_string())
+    }
+
+    /// Create a new event transaction ID for events recorded by a hook. Since
+    /// the command line of the hook itself isn't interesting, the command
+    /// which caused Git to invoke the hook is recorded instead, if it can be
+    /// determined.
+    ///
+    /// If a sequencing operation such as a rebase is in progress, Git invokes
+    /// hooks for each commit which it applies. In that case, the transaction
+    /// started by the first such hook is reused, so that the whole operation
+    /// can be undone in one step.
+    ///
+    /// Args:
+    /// * `repo`: The repository which the hook was invoked for.
+    /// * `hook_name`: The name of the hook, which is also used as the
+    /// transaction message.
+    pub fn make_hook_transaction_id(
+        &self,
+        repo: &Repo,
+        now: SystemTime,
+        hook_name: impl AsRef<str>,
+    ) -> eyre::Result<EventTransactionId> {
+        let hook_name = hook_name.as_ref();
+        let operation = match repo.get_sequencing_operation()? {
+            Some(operation) if get_transaction_id_from_env().is_none() => operation,
+            _ => {
+                return self
+                    .make_transaction_id_inner(now, hook_name, || describe_hook_command(hook_name))
+            }
+        };
+
+        let operation_key = describe_sequencing_operation_key(&operation)?;
+        let transaction_path = repo.get_sequencing_transaction_path();
+        if let Ok(contents) = std::fs::read_to_string(&transaction_path) {
+            if let Some((key, event_tx_id)) = contents.trim_end().rsplit_once(' ') {
+                if key == operation_key {
+                    if let Ok(event_tx_id) = event_tx_id.parse::<EventTransactionId>() {
+                        return Ok(event_tx_id);
+                    }
+                }
+            }
+        }
+
+        let message = format!(
+            "{}{}",
+            SEQUENCING_TRANSACTION_MESSAGE_PREFIX, operation.name
+        );
+        let event_tx_id =
+            self.make_transaction_id_inner(now, &message, || describe_hook_command(hook_name))?;
+        if let Some(parent) = transaction_path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err_with(|| format!("Creating directory {:?}", parent))?;
+        }
+        std::fs::write(
+            &transaction_path,
+            format!("{} {}\n", operation_key, event_tx_id.to_string()),
+        )
+        .wrap_err_with(|| format!("Writing sequencing transaction to {:?}", &transaction_path))?;
+        Ok(event_tx_id)
+    }
+
+    /// Determine whether the given event transaction holds the events of a
+    /// whole sequencing operation, such as a rebase, which were recorded by
+    /// hooks. Returns the name of the operation if so.
+    #[instrument]
+    pub fn get_sequencing_operation_name(
+        &self,
+        event_tx_id: EventTransactionId,
+    ) -> eyre::Result<Option<String>> {
+        let message = self.get_transaction_message(event_tx_id)?;
+        Ok(message.and_then(|message| {
+            message
+                .strip_prefix(SEQUENCING_TRANSACTION_MESSAGE_PREFIX)
+                .map(|name| name.to_string())
+        }))
+    }
+
+    /// Create a new event transaction ID for events recorded by a hook for a
+    /// reset, such as with `git reset --hard`. The branch which was reset and
+    /// its tip before the reset are recorded in the transaction message, so
+    /// that undoing the transaction can be described as restoring the branch.
+    ///
+    /// If the reset is part of a larger operation, such as a rebase or a
+    /// git-branchless command, then the transaction is made as with
+    /// `make_hook_transaction_id` instead, since the reset is only an
+    /// implementation detail of that operation.
+    pub fn make_reset_transaction_id(
+        &self,
+        repo: &Repo,
+        now: SystemTime,
+        hook_name: impl AsRef<str>,
+        reset_info: &ResetInfo,
+    ) -> eyre::Result<EventTransactionId> {
+        let hook_name = hook_name.as_ref();
+        let ResetInfo { ref_name, old_oid } = reset_info;
+        let ref_name = match ref_name.to_str() {
+            Some(ref_name)
+                if get_transaction_id_from_env().is_none()
+                    && repo.get_sequencing_operation()?.is_none() =>
+            {
+                ref_name
+            }
+            _ => return self.make_hook_transaction_id(repo, now, hook_name),
+        };
+
+        let message = format!(
+            "{}{} {}",
+            RESET_TRANSACTION_MESSAGE_PREFIX, ref_name, old_oid
+        );
+        self.make_transaction_id_inner(now, &message, || describe_hook_command(hook_name))
+    }
+
+    /// Determine whether the given event transaction holds the events of a
+    /// reset which were recorded by a hook. Returns the branch which was reset
+    /// and its tip before the reset if so.
+    #[instrument]
+    pub fn get_reset_info(
+        &self,
+        event_tx_id: EventTransactionId,
+    ) -> eyre::Result<Option<ResetInfo>> {
+        let message = match self.get_transaction_message(event_tx_id)? {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+        let reset_info = message
+            .strip_prefix(RESET_TRANSACTION_MESSAGE_PREFIX)
+            .and_then(|reset_info| reset_info.rsplit_once(' '))
+            .and_then(|(ref_name, old_oid)| {
+                let old_oid: NonZeroOid = old_oid.parse().ok()?;
+                Some(ResetInfo {
+                    ref_name: OsString::from(ref_name),
+                    old_oid,
+                })
+            });
+        Ok(reset_info)
+    }
+}
+
+/// A branch which was reset to one of its ancestors, as recorded in the
+/// message of the transaction holding the reset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResetInfo {
+    /// The full name of the branch which was reset, such as `refs/heads/master`.
+    pub ref_name: OsString,
+
+    /// The commit which the branch pointed to before the reset.
+    pub old_oid: NonZeroOid,
+}
+
+/// The prefix of the message of a transaction which holds the events of a
+/// whole sequencing operation. The name of the operation follows it.
+const SEQUENCING_TRANSACTION_MESSAGE_PREFIX: &str = "sequencing ";
+
+/// The prefix of the message of a transaction which holds a reset. The name of
+/// the branch which was reset and its tip before the reset follow it.
+const RESET_TRANSACTION_MESSAGE_PREFIX: &str = "reset ";
+
+/// Get the transaction ID which the caller has already started, if any.
+fn get_transaction_id_from_env() -> Option<EventTransactionId> {
+    std::env::var(BRANCHLESS_TRANSACTION_ID_ENV_VAR)
+        .ok()
+        .and_then(|transaction_id| transaction_id.parse::<EventTransactionId>().ok())
+}
+
+/// Describe the sequencing operation uniquely enough that hooks invoked for
+/// different operations don't share a transaction.
+fn describe_sequencing_operation_key(operation: &SequencingOperation) -> eyre::Result<String> {
+    let SequencingOperation { name, start_time } = operation;
+    let start_time = start_time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .wrap_err("Calculating sequencing operation start time")?;
+    Ok(format!("{} {}", name, start_time.as_nanos()))
+}
+
+/// Quote the given argument for display as part of a command line, if it
+/// would otherwise be ambiguous.
+fn quote_command_arg(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=.,:/@^~+%".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
     }
+}
 
-    #[instrument]
-    fn make_transaction_id_inner(
-        &self,
-        now: SystemTime,
-        message: &str,
-    ) -> eyre::Result<EventTransactionId> {
-        if let Ok(transaction_id) = std::env::var(BRANCHLESS_TRANSACTION_ID_ENV_VAR) {
-            if let Ok(transaction_id) = transaction_id.parse::<EventTransactionId>() {
-                return Ok(transaction_id);
-            }
+fn describe_command_line(args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> String {
+    args.into_iter()
+        .map(|arg| quote_command_arg(&arg.as_ref().to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Describe the invocation of a git-branchless command, the way that the user
+/// would have typed it (such as `git move -s foo -d master`). The executable
+/// might have been invoked as `git branchless move` or as the alias `git move`,
+/// which can't be distinguished, so the shorter form is used.
+fn describe_branchless_command(args: impl IntoIterator<Item = OsString>) -> String {
+    let args = args.into_iter().skip(1).collect::<Vec<_>>();
+    format!("git {}", describe_command_line(args))
+}
+
+/// Describe the command which caused Git to invoke the given hook. This is the
+/// nearest ancestor process which is a Git command (rather than the
+/// `git branchless hook-*` invocation or the shell running the hook script),
+/// if the process tree can be inspected on this platform. Otherwise, the hook
+/// name and the `GIT_REFLOG_ACTION` set by the Git command are used.
+fn describe_hook_command(hook_name: &str) -> String {
+    if let Some(args) = find_invoking_git_command() {
+        return describe_command_line(
+            std::iter::once(OsString::from("git")).chain(args.into_iter().skip(1)),
+        );
+    }
+    match std::env::var("GIT_REFLOG_ACTION") {
+        Ok(reflog_action) if !reflog_action.is_empty() => {
+            format!("{} ({})", hook_name, reflog_action)
         }
+        _ => hook_name.to_string(),
+    }
+}
 
-        let tx = self.conn.unchecked_transaction()?;
+#[cfg(target_os = "linux")]
+fn find_invoking_git_command() -> Option<Vec<OsString>> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
 
-        let timestamp = now
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .wrap_err("Calculating event transaction timestamp")?
-            .as_secs_f64();
-        self.conn
-            .execute(
-                "
-            INSERT INTO event_transactions
-            (timestamp, message)
-            VALUES
-            (:timestamp, :message)
-        ",
-                rusqlite::named_params! {
-                    ":timestamp": timestamp,
-                    ":message": message,
-                },
-            )
-            .wrap_err("Creating event transaction")?;
+    // Hooks are invoked via a handful of intermediate processes, so we don't
+    // need to look very far up the process tree.
+    const MAX_DEPTH: usize = 8;
 
-        // Ensure that we query `last_insert_rowid` in a transaction, in case
-        // there's another thread in this process making queries with the same
-        // SQLite connection.
-        let event_tx_id: isize = self.conn.last_insert_rowid().try_into()?;
-        tx.commit()?;
-        Ok(EventTransactionId(event_tx_id))
-    }
+    let mut pid = std::os::unix::process::parent_id();
+    for _ in 0..MAX_DEPTH {
+        if pid <= 1 {
+            break;
+        }
+        let proc_dir = Path::new("/proc").join(pid.to_string());
 
-    /// Create a new event transaction ID to be used to insert subsequent
-    /// `Event`s into the database.
-    pub fn make_transaction_id(
-        &self,
-        now: SystemTime,
-        message: impl AsRef<str>,
-    ) -> eyre::Result<EventTransactionId> {
-        self.make_transaction_id_inner(now, message.as_ref())
+        let cmdline = std::fs::read(proc_dir.join("cmdline")).ok()?;
+        let args: Vec<OsString> = cmdline
+            .split(|&c| c == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| OsStr::from_bytes(arg).to_os_string())
+            .collect();
+        let is_git = args
+            .first()
+            .and_then(|arg| Path::new(arg).file_name())
+            .map(|exe_name| exe_name == "git")
+            .unwrap_or(false);
+        let is_branchless = args.get(1).map(|arg| arg == "branchless").unwrap_or(false);
+        if is_git && !is_branchless {
+            return Some(args);
+        }
+
+        // The parent PID is the second field after the executable name, which
+        // is parenthesized and may itself contain spaces or parentheses.
+        let stat = std::fs::read_to_string(proc_dir.join("stat")).ok()?;
+        let (_, fields) = stat.rsplit_once(')')?;
+        pid = fields.split_whitespace().nth(1)?.parse().ok()?;
     }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn find_invoking_git_command() -> Option<Vec<OsString>> {
+    None
 }
 
 /// Determine whether a given reference is used to keep a commit alive.
@@ -608,6 +2011,56 @@ pub fn should_ignore_ref_updates(ref_name: &OsStr) -> bool {
     )
 }
 
+/// Determines whether or not the given reference matches one of the patterns
+/// configured with `branchless.eventlog.ignoreRefs`.
+///
+/// Patterns are globs: `*` matches any sequence of characters (including `/`)
+/// and `?` matches any single character. Any other character must match
+/// exactly.
+///
+/// Args:
+/// * `ref_name`: The name of the reference to check.
+/// * `patterns`: The patterns of reference names to ignore.
+///
+/// Returns: Whether or not the reference name matches any of the patterns.
+pub fn matches_ref_ignore_patterns(ref_name: &OsStr, patterns: &[String]) -> bool {
+    let ref_name = match ref_name.to_str() {
+        Some(ref_name) => ref_name,
+        None => return false,
+    };
+    patterns
+        .iter()
+        .any(|pattern| glob_matches(pattern.as_bytes(), ref_name.as_bytes()))
+}
+
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut pattern_idx, mut text_idx) = (0, 0);
+    // The position of the last `*` seen in the pattern, and the position in
+    // the text which it's currently assumed to match up to.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while text_idx < text.len() {
+        match pattern.get(pattern_idx) {
+            Some(b'*') => {
+                backtrack = Some((pattern_idx, text_idx));
+                pattern_idx += 1;
+            }
+            Some(c) if *c == b'?' || *c == text[text_idx] => {
+                pattern_idx += 1;
+                text_idx += 1;
+            }
+            _ => match backtrack {
+                Some((star_idx, star_text_idx)) => {
+                    pattern_idx = star_idx + 1;
+                    text_idx = star_text_idx + 1;
+                    backtrack = Some((star_idx, text_idx));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[pattern_idx..].iter().all(|c| *c == b'*')
+}
+
 #[derive(Debug)]
 enum EventClassification {
     Show,
@@ -656,6 +2109,14 @@ pub struct EventCursor {
     event_id: isize,
 }
 
+impl EventCursor {
+    /// Get the ID of the event immediately after this cursor. This can be
+    /// passed to `EventReplayer::make_cursor` to reconstruct the cursor later.
+    pub fn get_event_id(&self) -> isize {
+        self.event_id
+    }
+}
+
 /// Processes events in order and determine the repo's visible commits.
 pub struct EventReplayer {
     /// Events are numbered starting from zero.
@@ -664,12 +2125,30 @@ pub struct EventReplayer {
     /// The list of observed events.
     events: Vec<Event>,
 
+    /// The number of events before the first one in `events`. When the
+    /// replayer is restored from the smartlog cache, the earlier events aren't
+    /// available, although their effects on `commit_history` and
+    /// `ref_locations` are.
+    num_compacted_events: isize,
+
+    /// The number of events which were loaded from the event log to construct
+    /// this replayer, rather than being restored from the smartlog cache.
+    num_loaded_events: usize,
+
     /// The name of the reference representing the main branch.
     main_branch_reference_name: OsString,
 
     /// The events that have affected each commit.
     commit_history: HashMap<NonZeroOid, Vec<EventInfo>>,
 
+    /// Patterns of reference names whose updates should be ignored. See
+    /// `get_eventlog_ref_ignore_patterns`.
+    ref_ignore_patterns: Vec<String>,
+
+    /// If events have been pruned from the event log, the ID of the earliest
+    /// transaction whose history is complete.
+    pruned_before_event_tx_id: Option<EventTransactionId>,
+
     /// Map from ref names to ref locations (an OID or another ref name). Works
     /// around https://github.com/arxanas/git-branchless/issues/7.
     ///
@@ -690,18 +2169,23 @@ impl std::fmt::Debug for EventReplayer {
 }
 
 impl EventReplayer {
-    fn new_inner(main_branch_reference_name: OsString) -> Self {
+    fn new_inner(main_branch_reference_name: OsString, ref_ignore_patterns: Vec<String>) -> Self {
         EventReplayer {
             id_counter: 0,
             events: vec![],
+            num_compacted_events: 0,
+            num_loaded_events: 0,
             main_branch_reference_name,
+            ref_ignore_patterns,
+            pruned_before_event_tx_id: None,
             commit_history: HashMap::new(),
             ref_locations: HashMap::new(),
         }
     }
 
+    #[cfg(test)]
     fn new(main_branch_reference_name: impl Into<OsString>) -> Self {
-        Self::new_inner(main_branch_reference_name.into())
+        Self::new_inner(main_branch_reference_name.into(), Vec::new())
     }
 
     /// Construct the replayer from all the events in the database.
@@ -714,17 +2198,170 @@ impl EventReplayer {
         effects: &Effects,
         repo: &Repo,
         event_log_db: &EventLogDb,
+    ) -> eyre::Result<Self> {
+        let events = event_log_db.get_events()?;
+        let mut result = Self::from_events(effects, repo, &events)?;
+        result.pruned_before_event_tx_id = event_log_db.get_pruned_before_event_tx_id()?;
+        Ok(result)
+    }
+
+    /// Construct the replayer from the events in the database, like
+    /// `from_event_log_db`, but only process the events which were added since
+    /// the last call, starting from the state cached in the database by that
+    /// call. The cache is rebuilt from all the events if it's missing or out
+    /// of date, such as if the main branch has moved.
+    ///
+    /// Since the events from before the cache was written aren't loaded, the
+    /// resulting replayer only has complete information at the default cursor.
+    /// It shouldn't be used for queries about the references at a given time,
+    /// or about previous points in time.
+    #[instrument]
+    pub fn from_event_log_db_incremental(
+        effects: &Effects,
+        repo: &Repo,
+        event_log_db: &EventLogDb,
     ) -> eyre::Result<Self> {
         let (_effects, _progress) = effects.start_operation(OperationType::ProcessEvents);
 
-        let main_branch_reference_name = repo.get_main_branch_reference()?.get_name()?;
-        let mut result = EventReplayer::new(main_branch_reference_name);
-        for event in event_log_db.get_events()? {
+        let main_branch_reference_name = repo.get_main_branch_reference_name()?;
+        let ref_ignore_patterns = get_eventlog_ref_ignore_patterns(repo)?;
+        let key = SmartlogCacheKey {
+            main_branch_oid: repo.get_main_branch_oid()?.map(|oid| oid.to_string()),
+            ref_ignore_patterns: ref_ignore_patterns.join("\n"),
+        };
+        let mut result = EventReplayer::new_inner(main_branch_reference_name, ref_ignore_patterns);
+        result.pruned_before_event_tx_id = event_log_db.get_pruned_before_event_tx_id()?;
+
+        let cache = match event_log_db.read_smartlog_cache(&key) {
+            Ok(cache) => cache,
+            Err(err) => {
+                warn!(?err, "Could not read smartlog cache");
+                None
+            }
+        };
+        let (base_last_rowid, mut last_retained_rowid) = match cache {
+            Some(SmartlogCache {
+                last_rowid,
+                last_retained_rowid,
+                last_retained_event,
+                num_events,
+                commit_history,
+                ref_locations,
+            }) => {
+                result.events.extend(last_retained_event);
+                result.num_compacted_events = num_events - result.get_num_events();
+                result.id_counter = num_events;
+                result.commit_history = commit_history;
+                result.ref_locations = ref_locations;
+                (Some(last_rowid), last_retained_rowid)
+            }
+            None => (None, None),
+        };
+
+        let num_previous_events = result.events.len();
+        let mut last_rowid = base_last_rowid.unwrap_or_default();
+        for (rowid, event) in event_log_db.get_events_with_rowids_after(last_rowid)? {
+            let num_events = result.events.len();
             result.process_event(&event);
+            if result.events.len() > num_events {
+                last_retained_rowid = Some(rowid);
+            }
+            last_rowid = rowid;
+            result.num_loaded_events += 1;
+        }
+        if base_last_rowid == Some(last_rowid) {
+            return Ok(result);
+        }
+
+        // Only the commits and references affected by the new events need to
+        // be updated in the cache, unless it's being rebuilt.
+        let (commit_oids, ref_names): (HashSet<NonZeroOid>, HashSet<&OsStr>) = match base_last_rowid
+        {
+            Some(_) => {
+                let mut commit_oids = HashSet::new();
+                let mut ref_names = HashSet::new();
+                for event in result.events[num_previous_events..].iter() {
+                    match event {
+                        Event::RewriteEvent {
+                            old_commit_oid,
+                            new_commit_oid,
+                            ..
+                        } => {
+                            commit_oids.extend(Option::<NonZeroOid>::from(*old_commit_oid));
+                            commit_oids.extend(Option::<NonZeroOid>::from(*new_commit_oid));
+                        }
+                        Event::CommitEvent { commit_oid, .. }
+                        | Event::ObsoleteEvent { commit_oid, .. }
+                        | Event::UnobsoleteEvent { commit_oid, .. } => {
+                            commit_oids.insert(*commit_oid);
+                        }
+                        Event::RefUpdateEvent { ref_name, .. } => {
+                            ref_names.insert(ref_name.as_os_str());
+                        }
+                        Event::WorkingCopySnapshot { .. } => {}
+                    }
+                }
+                (commit_oids, ref_names)
+            }
+            None => (
+                result.commit_history.keys().copied().collect(),
+                result
+                    .ref_locations
+                    .keys()
+                    .map(|ref_name| ref_name.as_os_str())
+                    .collect(),
+            ),
+        };
+        let update = SmartlogCacheUpdate {
+            base_last_rowid,
+            last_rowid,
+            last_retained_rowid,
+            num_events: result.get_num_events(),
+            commits: commit_oids
+                .into_iter()
+                .filter_map(|oid| {
+                    let event_info = result.commit_history.get(&oid)?.last()?;
+                    Some((oid, event_info))
+                })
+                .collect(),
+            ref_locations: ref_names
+                .into_iter()
+                .map(|ref_name| (ref_name, result.ref_locations.get(ref_name).copied()))
+                .collect(),
+        };
+        if let Err(err) = event_log_db.write_smartlog_cache(&key, update) {
+            warn!(?err, "Could not write smartlog cache");
         }
         Ok(result)
     }
 
+    /// Get the number of events which were loaded from the event log to
+    /// construct this replayer. This is less than the total number of events if
+    /// it was constructed with `from_event_log_db_incremental`.
+    pub fn get_num_loaded_events(&self) -> usize {
+        self.num_loaded_events
+    }
+
+    /// Construct the replayer from the given events, rather than from all the
+    /// events in the database.
+    ///
+    /// Args:
+    /// * `events`: The events to replay, ordered from oldest to newest.
+    ///
+    /// Returns: The constructed replayer.
+    pub fn from_events(effects: &Effects, repo: &Repo, events: &[Event]) -> eyre::Result<Self> {
+        let (_effects, _progress) = effects.start_operation(OperationType::ProcessEvents);
+
+        let main_branch_reference_name = repo.get_main_branch_reference_name()?;
+        let ref_ignore_patterns = get_eventlog_ref_ignore_patterns(repo)?;
+        let mut result = EventReplayer::new_inner(main_branch_reference_name, ref_ignore_patterns);
+        for event in events {
+            result.process_event(event);
+        }
+        result.num_loaded_events = events.len();
+        Ok(result)
+    }
+
     /// Process the given event.
     ///
     /// This also sets the event cursor to point to immediately after the event
@@ -736,7 +2373,9 @@ impl EventReplayer {
     pub fn process_event(&mut self, event: &Event) {
         // Drop non-meaningful ref-update events.
         if let Event::RefUpdateEvent { ref_name, .. } = event {
-            if should_ignore_ref_updates(ref_name) {
+            if should_ignore_ref_updates(ref_name)
+                || matches_ref_ignore_patterns(ref_name, &self.ref_ignore_patterns)
+            {
                 return;
             }
         }
@@ -838,6 +2477,10 @@ impl EventReplayer {
                     event: event.clone(),
                     event_classification: EventClassification::Show,
                 }),
+
+            // Snapshot commits are not shown to the user, so they're not
+            // included in the `commit_history`.
+            Event::WorkingCopySnapshot { .. } => {}
         };
     }
 
@@ -952,6 +2595,75 @@ impl EventReplayer {
         Some(&event_info.event)
     }
 
+    /// Get the local branches which pointed to a given commit when it was most
+    /// recently hidden with `git hide`, as of the cursor's point in time.
+    /// Branches which pointed to the commit and were deleted as part of the
+    /// same transaction as the hide are also included.
+    ///
+    /// Returns: The full reference names of the branches. If the commit isn't
+    /// currently hidden, or was hidden as the result of a rewrite rather than
+    /// an explicit hide, returns an empty set.
+    pub fn get_cursor_commit_hidden_branch_names(
+        &self,
+        cursor: EventCursor,
+        oid: NonZeroOid,
+    ) -> HashSet<OsString> {
+        let (hide_event_id, hide_event_tx_id) =
+            match self.get_cursor_commit_history(cursor, oid).last() {
+                Some(EventInfo {
+                    id,
+                    event: Event::ObsoleteEvent { event_tx_id, .. },
+                    event_classification: EventClassification::Hide,
+                }) => (*id, *event_tx_id),
+                _ => return HashSet::new(),
+            };
+
+        let mut ref_name_to_oid: HashMap<&OsString, NonZeroOid> = HashMap::new();
+        let mut deleted_ref_names: HashSet<&OsString> = HashSet::new();
+        let cursor_event_id = self.get_event_index(cursor.event_id);
+        for (event_id, event) in (self.num_compacted_events..).zip(&self.events[..cursor_event_id])
+        {
+            if let Event::RefUpdateEvent {
+                event_tx_id,
+                ref_name,
+                old_oid,
+                new_oid,
+                ..
+            } = event
+            {
+                if event_id < hide_event_id {
+                    match new_oid {
+                        MaybeZeroOid::NonZero(new_oid) => {
+                            ref_name_to_oid.insert(ref_name, *new_oid);
+                        }
+                        MaybeZeroOid::Zero => {
+                            ref_name_to_oid.remove(ref_name);
+                        }
+                    }
+                }
+                if *event_tx_id == hide_event_tx_id
+                    && *old_oid == MaybeZeroOid::NonZero(oid)
+                    && *new_oid == MaybeZeroOid::Zero
+                {
+                    deleted_ref_names.insert(ref_name);
+                }
+            }
+        }
+
+        ref_name_to_oid
+            .into_iter()
+            .filter_map(|(ref_name, ref_oid)| if ref_oid == oid { Some(ref_name) } else { None })
+            .chain(deleted_ref_names)
+            .filter(|ref_name| {
+                matches!(
+                    CategorizedReferenceName::new(ref_name),
+                    CategorizedReferenceName::LocalBranch { .. }
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
     /// Get all OIDs which have been observed so far. This should be the set of
     /// non-inactive commits.
     pub fn get_cursor_oids(&self, cursor: EventCursor) -> HashSet<NonZeroOid> {
@@ -967,9 +2679,23 @@ impl EventReplayer {
             .collect()
     }
 
+    /// Determine whether events from the given transaction, or from earlier
+    /// transactions, may have been pruned from the event log.
+    pub fn is_event_tx_id_pruned(&self, event_tx_id: EventTransactionId) -> bool {
+        match self.pruned_before_event_tx_id {
+            Some(pruned_before_event_tx_id) => event_tx_id < pruned_before_event_tx_id,
+            None => false,
+        }
+    }
+
+    /// Determine whether any events have been pruned from the event log.
+    pub fn has_pruned_events(&self) -> bool {
+        self.pruned_before_event_tx_id.is_some()
+    }
+
     /// Create an event cursor pointing to immediately after the last event.
     pub fn make_default_cursor(&self) -> EventCursor {
-        self.make_cursor(self.events.len().try_into().unwrap())
+        self.make_cursor(self.get_num_events())
     }
 
     /// Create an event cursor pointing to immediately after the provided event ID.
@@ -977,8 +2703,12 @@ impl EventReplayer {
     /// If the event ID is too low or too high, it will be clamped to the valid
     /// range for event IDs.
     pub fn make_cursor(&self, event_id: isize) -> EventCursor {
-        let event_id = if event_id < 0 { 0 } else { event_id };
-        let num_events: isize = self.events.len().try_into().unwrap();
+        let event_id = if event_id < self.num_compacted_events {
+            self.num_compacted_events
+        } else {
+            event_id
+        };
+        let num_events = self.get_num_events();
         let event_id = if event_id > num_events {
             num_events
         } else {
@@ -987,6 +2717,16 @@ impl EventReplayer {
         EventCursor { event_id }
     }
 
+    fn get_num_events(&self) -> isize {
+        let num_retained_events: isize = self.events.len().try_into().unwrap();
+        self.num_compacted_events + num_retained_events
+    }
+
+    /// Get the index into `events` of the event with the given ID.
+    fn get_event_index(&self, event_id: isize) -> usize {
+        (event_id - self.num_compacted_events).try_into().unwrap()
+    }
+
     /// Advance the event cursor by the specified number of events.
     ///
     /// Args:
@@ -1075,34 +2815,97 @@ impl EventReplayer {
         }
     }
 
+    /// Create an event cursor pointing to the position immediately after the
+    /// last event in the given transaction.
+    ///
+    /// Returns: The cursor, or `None` if no events were recorded as part of
+    /// the transaction.
+    pub fn make_cursor_after_transaction(
+        &self,
+        event_tx_id: EventTransactionId,
+    ) -> Option<EventCursor> {
+        let event_index = self
+            .events
+            .iter()
+            .rposition(|event| event.get_event_tx_id() == event_tx_id)?;
+        let event_index: isize = event_index.try_into().unwrap();
+        Some(EventCursor {
+            event_id: self.num_compacted_events + event_index + 1,
+        })
+    }
+
+    /// Get the lowest and highest IDs of the transactions which have events
+    /// recorded, or `None` if there are no events.
+    pub fn get_event_tx_id_range(&self) -> Option<(EventTransactionId, EventTransactionId)> {
+        let event_tx_ids = self.events.iter().map(|event| event.get_event_tx_id().0);
+        let min = event_tx_ids.clone().min()?;
+        let max = event_tx_ids.max()?;
+        Some((EventTransactionId(min), EventTransactionId(max)))
+    }
+
     /// Get the OID of `HEAD` at the cursor's point in time.
     ///
     /// Returns: The OID pointed to by `HEAD` at that time, or `None` if `HEAD`
     /// was never observed.
     fn get_cursor_head_oid(&self, cursor: EventCursor) -> Option<NonZeroOid> {
-        let cursor_event_id: usize = cursor.event_id.try_into().unwrap();
+        let cursor_event_id = self.get_event_index(cursor.event_id);
         self.events[0..cursor_event_id]
             .iter()
             .rev()
             .find_map(|event| {
                 match &event {
+                    // `HEAD` is only ever recorded as being deleted when `git
+                    // undo` returns it to being unborn.
                     Event::RefUpdateEvent {
-                        ref_name,
-                        new_oid: MaybeZeroOid::NonZero(new_oid),
-                        ..
-                    } if ref_name == "HEAD" => Some(*new_oid),
+                        ref_name, new_oid, ..
+                    } if ref_name == "HEAD" => Some(Option::<NonZeroOid>::from(*new_oid)),
                     Event::RefUpdateEvent { .. } => None,
 
                     // Not strictly necessary, but helps to compensate in case
                     // the user is not running Git v2.29 or above, and therefore
                     // doesn't have the corresponding `RefUpdateEvent`.
-                    Event::CommitEvent { commit_oid, .. } => Some(*commit_oid),
+                    Event::CommitEvent { commit_oid, .. } => Some(Some(*commit_oid)),
 
                     Event::RewriteEvent { .. }
                     | Event::ObsoleteEvent { .. }
-                    | Event::UnobsoleteEvent { .. } => None,
+                    | Event::UnobsoleteEvent { .. }
+                    | Event::WorkingCopySnapshot { .. } => None,
                 }
             })
+            .flatten()
+    }
+
+    /// Get the positions that `HEAD` has been at before the cursor, from most
+    /// recent to least recent. Consecutive duplicate positions are collapsed,
+    /// so each entry differs from the one before it. Positions where `HEAD`
+    /// was unborn are skipped.
+    pub fn get_cursor_head_oid_history(&self, cursor: EventCursor) -> Vec<NonZeroOid> {
+        let cursor_event_id = self.get_event_index(cursor.event_id);
+        let mut result: Vec<NonZeroOid> = Vec::new();
+        let mut push = |oid: MaybeZeroOid| {
+            if let MaybeZeroOid::NonZero(oid) = oid {
+                if result.last() != Some(&oid) {
+                    result.push(oid);
+                }
+            }
+        };
+        for event in self.events[0..cursor_event_id].iter().rev() {
+            match event {
+                // Only the new OID is used, since the old OID isn't always
+                // known to the hook which recorded the event.
+                Event::RefUpdateEvent {
+                    ref_name, new_oid, ..
+                } if ref_name == "HEAD" => push(*new_oid),
+
+                Event::RefUpdateEvent { .. }
+                | Event::CommitEvent { .. }
+                | Event::RewriteEvent { .. }
+                | Event::ObsoleteEvent { .. }
+                | Event::UnobsoleteEvent { .. }
+                | Event::WorkingCopySnapshot { .. } => {}
+            }
+        }
+        result
     }
 
     fn get_cursor_branch_oid(
@@ -1110,7 +2913,7 @@ impl EventReplayer {
         cursor: EventCursor,
         reference_name: &OsStr,
     ) -> eyre::Result<Option<NonZeroOid>> {
-        let cursor_event_id: usize = cursor.event_id.try_into().unwrap();
+        let cursor_event_id = self.get_event_index(cursor.event_id);
         let oid = self.events[0..cursor_event_id]
             .iter()
             .rev()
@@ -1141,11 +2944,11 @@ impl EventReplayer {
         &self,
         cursor: EventCursor,
         repo: &Repo,
-    ) -> eyre::Result<NonZeroOid> {
-        let main_branch_reference_name = repo.get_main_branch_reference()?.get_name()?;
+    ) -> eyre::Result<Option<NonZeroOid>> {
+        let main_branch_reference_name = repo.get_main_branch_reference_name()?;
         let main_branch_oid = self.get_cursor_branch_oid(cursor, &main_branch_reference_name)?;
         match main_branch_oid {
-            Some(main_branch_oid) => Ok(main_branch_oid),
+            Some(main_branch_oid) => Ok(Some(main_branch_oid)),
             None => {
                 // Assume the main branch just hasn't been observed moving yet,
                 // so its value at the current time is fine to use.
@@ -1154,6 +2957,28 @@ impl EventReplayer {
         }
     }
 
+    /// Get the reference names and OIDs of the additional main branches at the
+    /// cursor's point in time. Like `get_cursor_main_branch_oid`, the current
+    /// location is used for a branch which hasn't been observed moving yet.
+    fn get_cursor_additional_main_branch_oids(
+        &self,
+        cursor: EventCursor,
+        repo: &Repo,
+    ) -> eyre::Result<Vec<(OsString, NonZeroOid)>> {
+        let mut result = Vec::new();
+        for reference in repo.get_additional_main_branch_references()? {
+            let reference_name = reference.get_name()?;
+            let oid = match self.get_cursor_branch_oid(cursor, &reference_name)? {
+                Some(oid) => Some(oid),
+                None => reference.peel_to_commit()?.map(|commit| commit.get_oid()),
+            };
+            if let Some(oid) = oid {
+                result.push((reference_name, oid));
+            }
+        }
+        Ok(result)
+    }
+
     /// Get the mapping of branch OIDs to names at the cursor's point in
     /// time.
     ///
@@ -1170,7 +2995,7 @@ impl EventReplayer {
         repo: &Repo,
     ) -> eyre::Result<HashMap<NonZeroOid, HashSet<OsString>>> {
         let mut ref_name_to_oid: HashMap<&OsString, NonZeroOid> = HashMap::new();
-        let cursor_event_id: usize = cursor.event_id.try_into().unwrap();
+        let cursor_event_id = self.get_event_index(cursor.event_id);
         for event in self.events[..cursor_event_id].iter() {
             match event {
                 Event::RefUpdateEvent {
@@ -1203,11 +3028,18 @@ impl EventReplayer {
             }
         }
 
-        let main_branch_oid = self.get_cursor_main_branch_oid(cursor, repo)?;
-        result
-            .entry(main_branch_oid)
-            .or_insert_with(HashSet::new)
-            .insert(self.main_branch_reference_name.clone());
+        if let Some(main_branch_oid) = self.get_cursor_main_branch_oid(cursor, repo)? {
+            result
+                .entry(main_branch_oid)
+                .or_insert_with(HashSet::new)
+                .insert(self.main_branch_reference_name.clone());
+        }
+        for (reference_name, oid) in self.get_cursor_additional_main_branch_oids(cursor, repo)? {
+            result
+                .entry(oid)
+                .or_insert_with(HashSet::new)
+                .insert(reference_name);
+        }
         Ok(result)
     }
 
@@ -1219,10 +3051,16 @@ impl EventReplayer {
     ) -> eyre::Result<RepoReferencesSnapshot> {
         let head_oid = self.get_cursor_head_oid(cursor);
         let main_branch_oid = self.get_cursor_main_branch_oid(cursor, repo)?;
+        let additional_main_branch_oids = self
+            .get_cursor_additional_main_branch_oids(cursor, repo)?
+            .into_iter()
+            .map(|(_reference_name, oid)| oid)
+            .collect();
         let branch_oid_to_names = self.get_cursor_branch_oid_to_names(cursor, repo)?;
         Ok(RepoReferencesSnapshot {
             head_oid,
             main_branch_oid,
+            additional_main_branch_oids,
             branch_oid_to_names,
         })
     }
@@ -1232,10 +3070,10 @@ impl EventReplayer {
     /// Returns: A tuple of event ID and the event that most recently happened.
     /// If no event was before the event cursor, returns `None` instead.
     pub fn get_event_before_cursor(&self, cursor: EventCursor) -> Option<(isize, &Event)> {
-        if cursor.event_id == 0 {
+        if cursor.event_id <= self.num_compacted_events {
             None
         } else {
-            let previous_cursor_event_id: usize = (cursor.event_id - 1).try_into().unwrap();
+            let previous_cursor_event_id = self.get_event_index(cursor.event_id - 1);
             Some((cursor.event_id, &self.events[previous_cursor_event_id]))
         }
     }
@@ -1255,7 +3093,7 @@ impl EventReplayer {
             event_id: curr_event_id,
         } = cursor;
         let tx_events =
-            &self.events[prev_event_id.try_into().unwrap()..curr_event_id.try_into().unwrap()];
+            &self.events[self.get_event_index(prev_event_id)..self.get_event_index(curr_event_id)];
         match tx_events {
             [] => None,
             events => Some((prev_event_id + 1, events)),
@@ -1267,7 +3105,7 @@ impl EventReplayer {
     /// Returns: An ordered list of events that have happened since the event
     /// cursor, from least recent to most recent.
     pub fn get_events_since_cursor(&self, cursor: EventCursor) -> &[Event] {
-        let cursor_event_id: usize = cursor.event_id.try_into().unwrap();
+        let cursor_event_id = self.get_event_index(cursor.event_id);
         &self.events[cursor_event_id..]
     }
 }
@@ -1299,6 +3137,9 @@ pub mod testing {
             Event::UnobsoleteEvent {
                 ref mut timestamp, ..
             } => *timestamp = 0.0,
+            Event::WorkingCopySnapshot {
+                ref mut timestamp, ..
+            } => *timestamp = 0.0,
         }
         event
     }
@@ -1351,6 +3192,175 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_migrate_event_transactions_command() -> eyre::Result<()> {
+        let conn = rusqlite::Connection::open_in_memory()?;
+        conn.execute(
+            "
+CREATE TABLE event_transactions (
+    timestamp REAL NOT NULL,
+    event_tx_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    message TEXT
+)
+",
+            rusqlite::params![],
+        )?;
+        conn.execute(
+            "INSERT INTO event_transactions (timestamp, message) VALUES (0.0, 'old')",
+            rusqlite::params![],
+        )?;
+
+        let event_log_db = EventLogDb::new(&conn)?;
+        let old_event_tx_id = EventTransactionId(1);
+        assert_eq!(
+            event_log_db.get_transaction_message(old_event_tx_id)?,
+            Some("old".to_string())
+        );
+        assert_eq!(event_log_db.get_transaction_command(old_event_tx_id)?, None);
+
+        let new_event_tx_id =
+            event_log_db.make_transaction_id_inner(SystemTime::now(), "new", || {
+                describe_hook_command("new")
+            })?;
+        assert!(event_log_db
+            .get_transaction_command(new_event_tx_id)?
+            .is_some());
+
+        // Opening the database again shouldn't try to add the column again.
+        EventLogDb::new(&conn)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_unversioned_database() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("db.sqlite3");
+        let backup_path = temp_dir.path().join("db.sqlite3.schema-v0.backup");
+
+        // Build a database in the shape written before the schema was
+        // versioned.
+        {
+            let conn = rusqlite::Connection::open(&db_path)?;
+            conn.execute_batch(
+                "
+CREATE TABLE event_log (
+    timestamp REAL NOT NULL,
+    type TEXT NOT NULL,
+    event_tx_id INTEGER NOT NULL,
+    old_ref TEXT,
+    new_ref TEXT,
+    ref_name TEXT,
+    message TEXT
+);
+CREATE TABLE event_transactions (
+    timestamp REAL NOT NULL,
+    event_tx_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    message TEXT
+);
+INSERT INTO event_transactions (timestamp, message) VALUES (0.0, 'old');
+INSERT INTO event_log (timestamp, type, event_tx_id, ref_name, old_ref, new_ref)
+    VALUES (0.0, 'ref-move', 1, 'refs/heads/foo', 'abc', 'def');
+",
+            )?;
+        }
+
+        {
+            let conn = rusqlite::Connection::open(&db_path)?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            assert_eq!(event_log_db.get_schema_version()?, EVENT_LOG_SCHEMA_VERSION);
+            assert_eq!(
+                event_log_db.get_transaction_message(EventTransactionId(1))?,
+                Some("old".to_string())
+            );
+            assert_eq!(event_log_db.get_events()?.len(), 1);
+            assert!(has_table(&conn, "test_results")?);
+            assert!(has_table(&conn, "git_version_cache")?);
+            assert!(has_table(&conn, "merge_base_cache")?);
+            assert!(has_table(&conn, "touched_paths_cache")?);
+            assert!(has_table(&conn, "archive_commits")?);
+        }
+        assert!(backup_path.exists());
+
+        // The backup holds the database as it was before migrating.
+        {
+            let conn = rusqlite::Connection::open(&backup_path)?;
+            assert_eq!(read_schema_version(&conn)?, 0);
+            assert!(!has_table(&conn, "event_log_prunes")?);
+        }
+
+        // Once migrated, opening the database again shouldn't run the
+        // migrations (or take a backup) again.
+        std::fs::remove_file(&backup_path)?;
+        {
+            let conn = rusqlite::Connection::open(&db_path)?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            assert_eq!(event_log_db.get_events()?.len(), 1);
+        }
+        assert!(!backup_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_newer_schema_version() -> eyre::Result<()> {
+        let conn = rusqlite::Connection::open_in_memory()?;
+        EventLogDb::new(&conn)?;
+        conn.execute(
+            "UPDATE schema_version SET version = :version",
+            rusqlite::named_params! {
+                ":version": EVENT_LOG_SCHEMA_VERSION + 1,
+            },
+        )?;
+
+        let err = EventLogDb::new(&conn).map(|_| ()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("but this version of git-branchless only supports up to schema version"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe_command_line() {
+        assert_eq!(
+            describe_command_line(["git", "move", "-s", "foo", "-d", "master"]),
+            "git move -s foo -d master"
+        );
+        assert_eq!(
+            describe_command_line(["git", "commit", "-m", "it's done", ""]),
+            r"git commit -m 'it'\''s done' ''"
+        );
+        assert_eq!(
+            describe_branchless_command(
+                vec!["/usr/bin/git-branchless", "move", "-x", "HEAD~"]
+                    .into_iter()
+                    .map(OsString::from)
+            ),
+            "git move -x HEAD~"
+        );
+    }
+
+    #[test]
+    fn test_matches_ref_ignore_patterns() {
+        let patterns = vec![
+            "refs/notes/*".to_string(),
+            "refs/heads/ci-*-build".to_string(),
+            "refs/stash".to_string(),
+            "refs/tags/v?".to_string(),
+        ];
+        let matches = |ref_name: &str| matches_ref_ignore_patterns(OsStr::new(ref_name), &patterns);
+        assert!(matches("refs/notes/commits"));
+        assert!(matches("refs/notes/foo/bar"));
+        assert!(matches("refs/heads/ci-123-build"));
+        assert!(matches("refs/heads/ci--build"));
+        assert!(matches("refs/stash"));
+        assert!(matches("refs/tags/v1"));
+        assert!(!matches("HEAD"));
+        assert!(!matches("refs/heads/master"));
+        assert!(!matches("refs/heads/ci-123-build-2"));
+        assert!(!matches("refs/stash-other"));
+        assert!(!matches("refs/tags/v10"));
+    }
+
     #[test]
     fn test_different_event_transaction_ids() -> eyre::Result<()> {
         let git = make_git()?;
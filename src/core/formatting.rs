@@ -85,6 +85,13 @@ pub struct Glyphs {
     /// currently checked out. (This is an unusual situation.)
     pub commit_main_obsolete_head: &'static str,
 
+    /// Cursor for an entry in the stash.
+    pub commit_stash: &'static str,
+
+    /// Marker for a commit which touches the paths being highlighted in the
+    /// smartlog, such as the paths modified in the working copy.
+    pub commit_related: &'static str,
+
     /// Bullet-point character for a list of newline-separated items.
     pub bullet_point: &'static str,
 
@@ -107,14 +114,28 @@ pub struct Glyphs {
 impl Glyphs {
     /// Make the `Glyphs` object appropriate for `stdout`.
     pub fn detect() -> Self {
-        let color_support = concolor_control::get(concolor_control::Stream::Stdout);
-        if color_support.color() {
+        if Self::detect_color_support() {
             Glyphs::pretty()
         } else {
             Glyphs::text()
         }
     }
 
+    /// Whether `stdout` supports colors, according to the terminal and the
+    /// `NO_COLOR` and `CLICOLOR_FORCE` environment variables.
+    pub fn detect_color_support() -> bool {
+        concolor_control::get(concolor_control::Stream::Stdout).color()
+    }
+
+    /// Set whether ANSI escape codes should be written, independently of the
+    /// set of characters used.
+    pub fn with_ansi_escape_codes(self, should_write_ansi_escape_codes: bool) -> Self {
+        Glyphs {
+            should_write_ansi_escape_codes,
+            ..self
+        }
+    }
+
     /// Glyphs used for output to a text file or non-TTY.
     pub fn text() -> Self {
         Glyphs {
@@ -131,6 +152,8 @@ impl Glyphs {
             commit_main_head: "@",
             commit_main_obsolete: "X",
             commit_main_obsolete_head: "%",
+            commit_stash: "s",
+            commit_related: "*",
             bullet_point: "-",
             cycle_arrow: ">",
             cycle_horizontal_line: "-",
@@ -156,6 +179,8 @@ impl Glyphs {
             commit_main_head: "◆",
             commit_main_obsolete: "✕",
             commit_main_obsolete_head: "❖",
+            commit_stash: "◌",
+            commit_related: "✎",
             bullet_point: "•",
             cycle_arrow: "ᐅ",
             cycle_horizontal_line: "─",
@@ -334,7 +359,9 @@ fn render_style_as_ansi(content: &str, style: Style) -> eyre::Result<String> {
         output
     };
 
-    Ok(output.to_string())
+    // The caller has already decided to write escape codes, so don't let
+    // `console` drop them when `stdout` isn't a terminal.
+    Ok(output.force_styling(true).to_string())
 }
 
 /// Write the provided string to `out`, using ANSI escape codes as necessary to
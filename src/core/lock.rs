@@ -0,0 +1,230 @@
+//! Prevent more than one command which rewrites commits from running in the
+//! same repository at once.
+//!
+//! Commands such as `git move` and `git restack` plan their changes based on
+//! the state of the repository when they start. If two of them run
+//! concurrently, they can rewrite the same commits independently, leaving
+//! behind duplicated commits. To avoid this, each such command holds a lock
+//! file under `.git/branchless` for as long as it runs. The lock file records
+//! the process ID and the command, so that a lock left behind by a process
+//! which has since exited can be detected and removed.
+//!
+//! Hooks never take the lock, since they're invoked by Git operations which
+//! the locking commands themselves run.
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::io::Write as IoWrite;
+use std::path::{Path, PathBuf};
+
+use eyre::Context;
+use tracing::{instrument, warn};
+
+use crate::core::effects::Effects;
+use crate::git::Repo;
+
+/// The contents of an existing lock file.
+#[derive(Debug)]
+struct LockOwner {
+    pid: Option<u32>,
+    command: String,
+}
+
+impl LockOwner {
+    fn parse(contents: &str) -> Self {
+        let mut lines = contents.lines();
+        let pid = lines.next().and_then(|line| line.trim().parse().ok());
+        let command = lines
+            .next()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .unwrap_or_else(|| "unknown command".to_string());
+        LockOwner { pid, command }
+    }
+
+    fn describe(&self) -> String {
+        match self.pid {
+            Some(pid) => format!("{}, pid {}", self.command, pid),
+            None => self.command.clone(),
+        }
+    }
+}
+
+/// Determine whether the process with the given ID is still running. If this
+/// can't be determined, the process is assumed to be running.
+#[cfg(unix)]
+fn is_process_running(pid: u32) -> bool {
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(pid) => pid,
+        Err(_) => return true,
+    };
+
+    // Signal 0 only checks whether the signal could be sent. Sending it fails
+    // with `EPERM` if the process exists but belongs to another user, so only
+    // `ESRCH` means that the process is gone.
+    // SAFETY: `kill` with signal 0 doesn't affect the target process.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ESRCH) => false,
+        Some(libc::EPERM) => true,
+        _ => {
+            warn!(?err, "Could not check whether process is running");
+            true
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn is_process_running(_pid: u32) -> bool {
+    true
+}
+
+/// A held lock on the repository. The lock is released when this value is
+/// dropped.
+#[derive(Debug)]
+pub struct OperationLock {
+    path: PathBuf,
+}
+
+impl Drop for OperationLock {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(?err, path = ?self.path, "Could not remove operation lock file");
+        }
+    }
+}
+
+/// Acquire the lock for the repository on behalf of `command` (such as `git
+/// move`). If another process holds the lock, an error message is printed and
+/// `None` is returned. A lock held by a process which is no longer running is
+/// removed with a warning, as is any existing lock if `force_unlock` is set.
+#[instrument]
+pub fn acquire_operation_lock(
+    effects: &Effects,
+    repo: &Repo,
+    command: &str,
+    force_unlock: bool,
+) -> eyre::Result<Option<OperationLock>> {
+    let path = repo.get_operation_lock_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .wrap_err_with(|| format!("Creating directory for lock file at {:?}", &path))?;
+    }
+
+    loop {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                writeln!(file, "{}", std::process::id())?;
+                writeln!(file, "{}", command)?;
+                return Ok(Some(OperationLock { path }));
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("Creating lock file at {:?}", &path))
+            }
+        }
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            // The lock was released in the meantime.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("Reading lock file at {:?}", &path))
+            }
+        };
+        let owner = LockOwner::parse(&contents);
+
+        if force_unlock {
+            writeln!(
+                effects.get_error_stream(),
+                "branchless: removing the lock held by another git-branchless operation ({})",
+                owner.describe()
+            )?;
+        } else if matches!(owner.pid, Some(pid) if !is_process_running(pid)) {
+            writeln!(
+                effects.get_error_stream(),
+                "branchless: removing a stale lock left by a git-branchless operation which is no longer running ({})",
+                owner.describe()
+            )?;
+        } else {
+            writeln!(
+                effects.get_result_stream(),
+                "Another git-branchless operation ({}) is in progress.",
+                owner.describe()
+            )?;
+            writeln!(
+                effects.get_result_stream(),
+                "If it is no longer running, retry with: git branchless --force-unlock <command>"
+            )?;
+            return Ok(None);
+        }
+
+        remove_lock_file(&path, &contents)?;
+    }
+}
+
+/// Remove the lock file at `path`, but only if it still has the given
+/// contents. Another process may have removed the same stale lock and taken
+/// the lock itself since the contents were read, so the lock file is first
+/// moved aside atomically, and put back if it turns out to belong to that
+/// process.
+fn remove_lock_file(path: &Path, expected_contents: &str) -> eyre::Result<()> {
+    let stale_path = path.with_extension(format!("stale.{}", std::process::id()));
+    match std::fs::rename(path, &stale_path) {
+        Ok(()) => {}
+        // The lock was released in the meantime.
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err)
+                .wrap_err_with(|| format!("Moving lock file from {:?} to {:?}", path, &stale_path))
+        }
+    }
+
+    let contents = std::fs::read_to_string(&stale_path)
+        .wrap_err_with(|| format!("Reading lock file at {:?}", &stale_path))?;
+    if contents != expected_contents {
+        // Put the lock back without replacing any lock which was taken in the
+        // meantime.
+        if let Err(err) = std::fs::hard_link(&stale_path, path) {
+            warn!(
+                ?err,
+                ?path,
+                "Could not restore lock file held by another process"
+            );
+        }
+    }
+
+    std::fs::remove_file(&stale_path)
+        .wrap_err_with(|| format!("Removing lock file at {:?}", &stale_path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_lock_file_taken_by_other_process() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("lock");
+
+        // Another process removed the stale lock and took the lock itself
+        // after its contents were read.
+        std::fs::write(&path, "2\ngit move\n")?;
+        remove_lock_file(&path, "1\ngit restack\n")?;
+        assert_eq!(std::fs::read_to_string(&path)?, "2\ngit move\n");
+
+        remove_lock_file(&path, "2\ngit move\n")?;
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 0);
+
+        Ok(())
+    }
+}
@@ -1,9 +1,16 @@
 //! Core algorithms and data structures.
 
-pub mod config;
+pub(crate) mod archive;
+pub(crate) mod commit_hooks;
+pub(crate) mod config;
 pub mod dag;
 pub mod effects;
 pub mod eventlog;
 pub mod formatting;
+pub(crate) mod lock;
 pub mod node_descriptors;
+pub(crate) mod pool;
+pub mod revset;
 pub mod rewrite;
+pub(crate) mod snapshot;
+pub(crate) mod stash;
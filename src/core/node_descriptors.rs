@@ -3,23 +3,40 @@
 //! These are rendered inline in the smartlog, between the commit hash and the
 //! commit message.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::ffi::OsStr;
+use std::fmt::Write;
+use std::io::{Read, Write as WriteIo};
 use std::ops::Add;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
 use std::time::{Duration, SystemTime};
 
 use cursive::theme::BaseColor;
 use cursive::utils::markup::StyledString;
+use eyre::{eyre, Context};
+use itertools::Itertools;
 use lazy_static::lazy_static;
+use rayon::prelude::*;
+use rayon::ThreadPool;
 use regex::Regex;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
 use crate::core::config::{
     get_commit_descriptors_branches, get_commit_descriptors_differential_revision,
-    get_commit_descriptors_relative_time,
+    get_commit_descriptors_relative_time, get_smartlog_annotation_command,
+    get_smartlog_annotation_timeout,
 };
-use crate::git::{CategorizedReferenceName, Commit, NonZeroOid, Repo, RepoReferencesSnapshot};
+use crate::core::effects::Effects;
+use crate::core::eventlog::init_db_schema;
+use crate::core::pool::with_worker_repo;
+use crate::git::{
+    CategorizedReferenceName, Commit, GitRunInfo, NonZeroOid, Repo, RepoReferencesSnapshot,
+};
+use crate::util::get_sh;
 
 use super::eventlog::{Event, EventCursor, EventReplayer};
 use super::formatting::StyledStringBuilder;
@@ -53,6 +70,20 @@ impl<'repo> NodeObject<'repo> {
 
 /// Interface to display information about a node in the smartlog.
 pub trait NodeDescriptor {
+    /// Compute the descriptions of the given commits ahead of time, so that
+    /// any expensive work for each commit can be carried out in parallel using
+    /// `pool`. `describe_node` is still called for each commit afterwards.
+    ///
+    /// The default implementation does nothing.
+    fn prefetch(
+        &mut self,
+        _repo: &Repo,
+        _pool: &ThreadPool,
+        _oids: &[NonZeroOid],
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+
     /// Provide a description of the given commit.
     ///
     /// A return value of `None` indicates that this commit descriptor was
@@ -129,6 +160,7 @@ impl NodeDescriptor for CommitMessageDescriptor {
 pub struct ObsolescenceExplanationDescriptor<'a> {
     event_replayer: &'a EventReplayer,
     event_cursor: EventCursor,
+    prefetched: HashMap<NonZeroOid, Option<StyledString>>,
 }
 
 impl<'a> ObsolescenceExplanationDescriptor<'a> {
@@ -137,25 +169,18 @@ impl<'a> ObsolescenceExplanationDescriptor<'a> {
         Ok(ObsolescenceExplanationDescriptor {
             event_replayer,
             event_cursor,
+            prefetched: Default::default(),
         })
     }
-}
 
-impl<'a> NodeDescriptor for ObsolescenceExplanationDescriptor<'a> {
-    fn describe_node(&mut self, object: &NodeObject) -> eyre::Result<Option<StyledString>> {
+    fn describe_oid(&self, oid: NonZeroOid) -> Option<StyledString> {
         let event = self
             .event_replayer
-            .get_cursor_commit_latest_event(self.event_cursor, object.get_oid());
-
-        let event = match event {
-            Some(event) => event,
-            None => return Ok(None),
-        };
-
-        let result = match event {
+            .get_cursor_commit_latest_event(self.event_cursor, oid)?;
+        match event {
             Event::RewriteEvent { .. } => {
                 let rewrite_target =
-                    find_rewrite_target(self.event_replayer, self.event_cursor, object.get_oid());
+                    find_rewrite_target(self.event_replayer, self.event_cursor, oid);
                 rewrite_target.map(|rewritten_oid| {
                     StyledString::styled(
                         format!("(rewritten as {})", &rewritten_oid.to_string()[..8]),
@@ -171,9 +196,35 @@ impl<'a> NodeDescriptor for ObsolescenceExplanationDescriptor<'a> {
 
             Event::RefUpdateEvent { .. }
             | Event::CommitEvent { .. }
-            | Event::UnobsoleteEvent { .. } => None,
-        };
-        Ok(result)
+            | Event::UnobsoleteEvent { .. }
+            | Event::WorkingCopySnapshot { .. } => None,
+        }
+    }
+}
+
+impl<'a> NodeDescriptor for ObsolescenceExplanationDescriptor<'a> {
+    fn prefetch(
+        &mut self,
+        _repo: &Repo,
+        pool: &ThreadPool,
+        oids: &[NonZeroOid],
+    ) -> eyre::Result<()> {
+        let this = &*self;
+        let prefetched = pool.install(|| {
+            oids.par_iter()
+                .map(|oid| (*oid, this.describe_oid(*oid)))
+                .collect()
+        });
+        self.prefetched = prefetched;
+        Ok(())
+    }
+
+    fn describe_node(&mut self, object: &NodeObject) -> eyre::Result<Option<StyledString>> {
+        let oid = object.get_oid();
+        match self.prefetched.remove(&oid) {
+            Some(result) => Ok(result),
+            None => Ok(self.describe_oid(oid)),
+        }
     }
 }
 
@@ -227,7 +278,8 @@ impl<'a> NodeDescriptor for BranchesDescriptor<'a> {
                         reference_name @ CategorizedReferenceName::RemoteBranch { .. } => {
                             format!("remote {}", reference_name.render_suffix())
                         }
-                        reference_name @ CategorizedReferenceName::OtherRef { .. } => {
+                        reference_name @ CategorizedReferenceName::Stash { .. }
+                        | reference_name @ CategorizedReferenceName::OtherRef { .. } => {
                             format!("ref {}", reference_name.render_suffix())
                         }
                     },
@@ -247,13 +299,17 @@ impl<'a> NodeDescriptor for BranchesDescriptor<'a> {
 #[derive(Debug)]
 pub struct DifferentialRevisionDescriptor {
     is_enabled: bool,
+    prefetched: HashMap<NonZeroOid, Option<String>>,
 }
 
 impl DifferentialRevisionDescriptor {
     /// Constructor.
     pub fn new(repo: &Repo) -> eyre::Result<Self> {
         let is_enabled = get_commit_descriptors_differential_revision(repo)?;
-        Ok(DifferentialRevisionDescriptor { is_enabled })
+        Ok(DifferentialRevisionDescriptor {
+            is_enabled,
+            prefetched: Default::default(),
+        })
     }
 }
 
@@ -275,6 +331,35 @@ $",
 }
 
 impl NodeDescriptor for DifferentialRevisionDescriptor {
+    fn prefetch(
+        &mut self,
+        repo: &Repo,
+        pool: &ThreadPool,
+        oids: &[NonZeroOid],
+    ) -> eyre::Result<()> {
+        if !self.is_enabled {
+            return Ok(());
+        }
+        let repo_path = repo.get_path();
+        let prefetched = pool.install(|| {
+            oids.par_iter()
+                .map(|oid| {
+                    with_worker_repo(repo_path, |repo| {
+                        let diff_number = match repo.find_commit(*oid)? {
+                            Some(commit) => {
+                                extract_diff_number(&commit.get_message_raw()?.to_string_lossy())
+                            }
+                            None => None,
+                        };
+                        Ok((*oid, diff_number))
+                    })
+                })
+                .collect::<eyre::Result<_>>()
+        })?;
+        self.prefetched = prefetched;
+        Ok(())
+    }
+
     #[instrument]
     fn describe_node(&mut self, object: &NodeObject) -> eyre::Result<Option<StyledString>> {
         if !self.is_enabled {
@@ -285,7 +370,11 @@ impl NodeDescriptor for DifferentialRevisionDescriptor {
             NodeObject::GarbageCollected { oid: _ } => return Ok(None),
         };
 
-        let diff_number = match extract_diff_number(&commit.get_message_raw()?.to_string_lossy()) {
+        let diff_number = match self.prefetched.remove(&commit.get_oid()) {
+            Some(diff_number) => diff_number,
+            None => extract_diff_number(&commit.get_message_raw()?.to_string_lossy()),
+        };
+        let diff_number = match diff_number {
             Some(diff_number) => diff_number,
             None => return Ok(None),
         };
@@ -368,6 +457,266 @@ impl NodeDescriptor for RelativeTimeDescriptor {
     }
 }
 
+/// Display annotations provided by the external command configured with
+/// `branchless.smartlog.annotationCmd`, such as code review or CI status.
+#[derive(Debug)]
+pub struct AnnotationDescriptor {
+    annotations: HashMap<NonZeroOid, String>,
+}
+
+impl AnnotationDescriptor {
+    /// Constructor. Runs the annotation command once for all of the provided
+    /// commits, if one is configured.
+    pub fn new(
+        effects: &Effects,
+        git_run_info: &GitRunInfo,
+        repo: &Repo,
+        oids: &[NonZeroOid],
+    ) -> eyre::Result<Self> {
+        let command = match get_smartlog_annotation_command(repo)? {
+            Some(command) if !oids.is_empty() => command,
+            Some(_) | None => {
+                return Ok(AnnotationDescriptor {
+                    annotations: Default::default(),
+                })
+            }
+        };
+        let timeout = get_smartlog_annotation_timeout(repo)?;
+
+        let output = match run_annotation_command(git_run_info, repo, &command, oids, timeout)? {
+            Ok(output) => output,
+            Err(reason) => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "branchless: smartlog annotations omitted: {}",
+                    reason
+                )?;
+                return Ok(AnnotationDescriptor {
+                    annotations: Default::default(),
+                });
+            }
+        };
+
+        let mut annotations: HashMap<NonZeroOid, String> = HashMap::new();
+        for line in output.lines() {
+            let (oid, annotation) = match line.split_once('\t') {
+                Some(parts) => parts,
+                None => continue,
+            };
+            let oid: NonZeroOid = match oid.trim().parse() {
+                Ok(oid) => oid,
+                Err(err) => {
+                    warn!(?line, ?err, "Invalid OID in smartlog annotation");
+                    continue;
+                }
+            };
+            let annotation = annotation.trim();
+            if annotation.is_empty() {
+                continue;
+            }
+            annotations
+                .entry(oid)
+                .and_modify(|existing| {
+                    existing.push(' ');
+                    existing.push_str(annotation);
+                })
+                .or_insert_with(|| annotation.to_string());
+        }
+        Ok(AnnotationDescriptor { annotations })
+    }
+}
+
+/// Run the annotation command, writing the OIDs to its stdin. Returns the
+/// command's stdout, or a user-facing reason why no annotations are available.
+fn run_annotation_command(
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    command: &str,
+    oids: &[NonZeroOid],
+    timeout: Duration,
+) -> eyre::Result<Result<String, String>> {
+    let mut child = Command::new(get_sh().ok_or_else(|| eyre!("could not get sh"))?)
+        .arg("-c")
+        .arg(command)
+        .current_dir(
+            repo.get_working_copy_path()
+                .unwrap_or_else(|| repo.get_path()),
+        )
+        .env_clear()
+        .envs(git_run_info.env.iter())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .wrap_err_with(|| format!("Running smartlog annotation command: {}", command))?;
+
+    let input: String = oids.iter().map(|oid| format!("{}\n", oid)).collect();
+    let mut stdin = child.stdin.take().unwrap();
+    thread::spawn(move || {
+        // The command may exit without reading all of its input, so ignore
+        // errors due to the pipe being closed.
+        let _ignored = stdin.write_all(input.as_bytes());
+    });
+
+    // Read the output on another thread, so that we can give up on it if the
+    // command takes too long.
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let result = stdout.read_to_end(&mut output).map(|_| output);
+        let _ignored = tx.send(result);
+    });
+
+    let output = match rx.recv_timeout(timeout) {
+        Ok(output) => output.wrap_err("Reading smartlog annotation command output")?,
+        Err(_) => {
+            let _ignored = child.kill();
+            let _ignored = child.wait();
+            return Ok(Err(format!(
+                "`{}` did not finish within {}ms",
+                command,
+                timeout.as_millis()
+            )));
+        }
+    };
+    let exit_status = child
+        .wait()
+        .wrap_err("Waiting for smartlog annotation command")?;
+    if !exit_status.success() {
+        return Ok(Err(format!(
+            "`{}` failed with exit code {}",
+            command,
+            exit_status.code().unwrap_or(1)
+        )));
+    }
+    Ok(Ok(String::from_utf8_lossy(&output).into_owned()))
+}
+
+impl NodeDescriptor for AnnotationDescriptor {
+    #[instrument]
+    fn describe_node(&mut self, object: &NodeObject) -> eyre::Result<Option<StyledString>> {
+        let result = self
+            .annotations
+            .get(&object.get_oid())
+            .map(|annotation| StyledString::styled(annotation.as_str(), BaseColor::Black.light()));
+        Ok(result)
+    }
+}
+
+/// Mark the draft commits which touch any of the given paths, such as the
+/// paths modified in the working copy. Amending those modifications into one
+/// of the other commits is likely to cause merge conflicts later.
+#[derive(Debug)]
+pub struct RelatedCommitDescriptor {
+    marker: &'static str,
+    related_oids: HashSet<NonZeroOid>,
+}
+
+impl RelatedCommitDescriptor {
+    /// Constructor. Determines which of the provided commits touch any of
+    /// `paths`, or any path under them if they're directories. The paths
+    /// touched by each commit are cached in the database, since they never
+    /// change for a given commit.
+    pub fn new(
+        effects: &Effects,
+        repo: &Repo,
+        conn: &rusqlite::Connection,
+        oids: &[NonZeroOid],
+        paths: &HashSet<PathBuf>,
+    ) -> eyre::Result<Self> {
+        let mut related_oids = HashSet::new();
+        if !paths.is_empty() {
+            init_db_schema(conn)?;
+            for oid in oids {
+                let touched_paths = match read_cached_touched_paths(conn, *oid)? {
+                    Some(touched_paths) => touched_paths,
+                    None => {
+                        let commit = match repo.find_commit(*oid)? {
+                            Some(commit) => commit,
+                            None => continue,
+                        };
+                        let touched_paths = repo.get_paths_touched_by_commit(&commit)?;
+                        write_cached_touched_paths(conn, *oid, touched_paths.as_ref())?;
+                        touched_paths
+                    }
+                };
+                let is_related = touched_paths
+                    .iter()
+                    .flatten()
+                    .any(|touched_path| paths.iter().any(|path| touched_path.starts_with(path)));
+                if is_related {
+                    related_oids.insert(*oid);
+                }
+            }
+        }
+        Ok(RelatedCommitDescriptor {
+            marker: effects.get_glyphs().commit_related,
+            related_oids,
+        })
+    }
+}
+
+/// Read the paths touched by the given commit from the cache. The inner value
+/// is `None` if the commit is a merge commit (see
+/// `Repo::get_paths_touched_by_commit`).
+fn read_cached_touched_paths(
+    conn: &rusqlite::Connection,
+    commit_oid: NonZeroOid,
+) -> eyre::Result<Option<Option<HashSet<PathBuf>>>> {
+    let mut stmt = conn.prepare(
+        "
+SELECT paths
+FROM touched_paths_cache
+WHERE commit_oid = :commit_oid
+",
+    )?;
+    let mut rows = stmt.query(rusqlite::named_params! {
+        ":commit_oid": commit_oid.to_string(),
+    })?;
+    let paths: String = match rows.next()? {
+        Some(row) => row.get("paths")?,
+        None => return Ok(None),
+    };
+    match serde_json::from_str::<Option<Vec<PathBuf>>>(&paths) {
+        Ok(paths) => Ok(Some(paths.map(|paths| paths.into_iter().collect()))),
+        Err(err) => {
+            warn!(?commit_oid, ?err, "Invalid entry in touched paths cache");
+            Ok(None)
+        }
+    }
+}
+
+fn write_cached_touched_paths(
+    conn: &rusqlite::Connection,
+    commit_oid: NonZeroOid,
+    paths: Option<&HashSet<PathBuf>>,
+) -> eyre::Result<()> {
+    let paths = serde_json::to_string(&paths.map(|paths| paths.iter().sorted().collect_vec()))?;
+    conn.execute(
+        "
+INSERT OR REPLACE INTO touched_paths_cache (commit_oid, paths)
+VALUES (:commit_oid, :paths)
+",
+        rusqlite::named_params! {
+            ":commit_oid": commit_oid.to_string(),
+            ":paths": paths,
+        },
+    )?;
+    Ok(())
+}
+
+impl NodeDescriptor for RelatedCommitDescriptor {
+    #[instrument]
+    fn describe_node(&mut self, object: &NodeObject) -> eyre::Result<Option<StyledString>> {
+        if !self.related_oids.contains(&object.get_oid()) {
+            return Ok(None);
+        }
+        let result = StyledString::styled(self.marker, BaseColor::Yellow.light());
+        Ok(Some(result))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::ops::Sub;
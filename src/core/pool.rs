@@ -0,0 +1,64 @@
+//! A pool of worker threads for carrying out work for many commits in
+//! parallel.
+//!
+//! A `git2::Repository` can't be shared between threads, so each worker thread
+//! opens its own handle to the repository, which is available via
+//! `with_worker_repo`.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tracing::instrument;
+
+use crate::core::config::get_core_jobs;
+use crate::git::Repo;
+
+thread_local! {
+    static WORKER_REPO: RefCell<Option<(PathBuf, Repo)>> = Default::default();
+}
+
+/// Get the number of worker threads which `make_repo_thread_pool` would use
+/// for the repository.
+pub fn get_num_jobs(repo: &Repo) -> eyre::Result<usize> {
+    let jobs = get_core_jobs(repo)?;
+    let jobs = if jobs == 0 {
+        std::thread::available_parallelism()
+            .map(|jobs| jobs.get())
+            .unwrap_or(1)
+    } else {
+        jobs
+    };
+    Ok(jobs)
+}
+
+/// Create a thread pool for carrying out work on `repo`, with as many threads
+/// as configured by `branchless.core.jobs`.
+#[instrument]
+pub fn make_repo_thread_pool(repo: &Repo) -> eyre::Result<ThreadPool> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(get_num_jobs(repo)?)
+        .build()?;
+    Ok(pool)
+}
+
+/// Call `f` with the current thread's handle to the repository at
+/// `repo_path`, opening it if this thread hasn't done so yet.
+///
+/// The handle is kept for later calls on the same thread, so this should be
+/// called from the threads of a pool created with `make_repo_thread_pool`,
+/// rather than from the main thread.
+pub fn with_worker_repo<T>(
+    repo_path: &Path,
+    f: impl FnOnce(&Repo) -> eyre::Result<T>,
+) -> eyre::Result<T> {
+    WORKER_REPO.with(|worker_repo| {
+        let mut worker_repo = worker_repo.borrow_mut();
+        let is_open = matches!(&*worker_repo, Some((path, _repo)) if path == repo_path);
+        if !is_open {
+            *worker_repo = Some((repo_path.to_owned(), Repo::from_dir(repo_path)?));
+        }
+        let (_path, repo) = worker_repo.as_ref().unwrap();
+        f(repo)
+    })
+}
@@ -0,0 +1,1357 @@
+//! Parse and evaluate revset expressions, which describe sets of commits.
+//!
+//! The syntax is loosely based on Mercurial's revsets:
+//!
+//! - `abc123`, `my-branch`, `HEAD~2`: a single commit, as resolved by `git
+//!   rev-parse`. Names containing other characters can be quoted, as in
+//!   `"my branch"`.
+//! - `draft()`, `stack(foo)`: a call to one of the functions listed in
+//!   `eval_function`.
+//! - `x | y`: the commits in either `x` or `y`.
+//! - `x & y`: the commits in both `x` and `y`.
+//! - `x - y`: the commits in `x` but not in `y`.
+//! - `x..y`: the ancestors of `y` which aren't ancestors of `x`, as in `git
+//!   log x..y`.
+//! - `x::y`: the commits on the paths from `x` to `y`, including both `x` and
+//!   `y`.
+//! - `(x)`: grouping.
+//!
+//! `..` and `::` bind most tightly and can't be chained. `&` and `-` bind more
+//! tightly than `|`, and all of the operators are left-associative. Since
+//! branch names often contain hyphens, a `-` directly followed by a name
+//! character continues the current name, so differences of names should be
+//! written with spaces, as in `foo - bar`.
+
+use std::fmt::{Display, Write};
+
+use eden_dag::DagAlgorithm;
+use itertools::Itertools;
+use regex::Regex;
+use tracing::instrument;
+
+use crate::core::dag::{commit_set_to_vec, CommitSet, Dag};
+use crate::core::effects::{Effects, OperationType};
+use crate::git::{AmbiguousBranchName, Commit, Repo, ResolvedBranchName};
+
+/// A parsed revset expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    /// The name of a commit, branch, or other reference.
+    Name(String),
+
+    /// A function call with the given name and arguments. The set operators
+    /// are parsed into calls to `union`, `intersection`, and `difference`,
+    /// and the range operators into calls to `only` and `range`.
+    FunctionCall(String, Vec<Expr>),
+}
+
+impl Expr {
+    /// Determine whether the expression contains a range, as written with `..`
+    /// or `::`, or with the equivalent `only` and `range` functions.
+    pub fn contains_range(&self) -> bool {
+        match self {
+            Expr::Name(_) => false,
+            Expr::FunctionCall(name, args) => {
+                name == "only" || name == "range" || args.iter().any(Expr::contains_range)
+            }
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Name(name) => {
+                // Only quote the name if it wouldn't be parsed back as the same
+                // name otherwise.
+                let is_plain_name = match tokenize(name).as_deref() {
+                    Ok([(_position, Token::Name(token))]) => token == name,
+                    _ => false,
+                };
+                if is_plain_name {
+                    write!(f, "{}", name)
+                } else {
+                    write!(f, "\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+                }
+            }
+            Expr::FunctionCall(name, args) => {
+                write!(
+                    f,
+                    "{}({})",
+                    name,
+                    args.iter().map(Expr::to_string).join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// An error produced when parsing a revset expression.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The expression contained a character which can't start any token.
+    UnexpectedCharacter {
+        /// The byte offset of the character in the expression.
+        position: usize,
+
+        /// The unexpected character.
+        character: char,
+    },
+
+    /// A quoted name was not closed before the end of the expression.
+    UnterminatedString {
+        /// The byte offset of the opening quote in the expression.
+        position: usize,
+    },
+
+    /// A token appeared where it wasn't allowed.
+    UnexpectedToken {
+        /// The byte offset of the token in the expression.
+        position: usize,
+
+        /// The unexpected token, as written in the expression.
+        token: String,
+
+        /// A description of what was expected instead.
+        expected: &'static str,
+    },
+
+    /// The expression ended before it was complete.
+    UnexpectedEnd {
+        /// A description of what was expected instead.
+        expected: &'static str,
+    },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedCharacter {
+                position,
+                character,
+            } => write!(
+                f,
+                "unexpected character '{}' at position {}",
+                character, position
+            ),
+            ParseError::UnterminatedString { position } => {
+                write!(f, "unterminated string starting at position {}", position)
+            }
+            ParseError::UnexpectedToken {
+                position,
+                token,
+                expected,
+            } => write!(
+                f,
+                "expected {}, but got '{}' at position {}",
+                expected, token, position
+            ),
+            ParseError::UnexpectedEnd { expected } => {
+                write!(
+                    f,
+                    "expected {}, but reached the end of the expression",
+                    expected
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    String(String),
+    LParen,
+    RParen,
+    Comma,
+    Union,
+    Intersection,
+    Difference,
+    Range,
+    DagRange,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "{}", name),
+            Token::String(string) => write!(f, "\"{}\"", string),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::Union => write!(f, "|"),
+            Token::Intersection => write!(f, "&"),
+            Token::Difference => write!(f, "-"),
+            Token::Range => write!(f, ".."),
+            Token::DagRange => write!(f, "::"),
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || "_/.~^@{}:+#".contains(c)
+}
+
+/// Determine whether the range operator `..` or `::` starts at the iterator's
+/// position. Neither can appear in a valid reference name, so they always end
+/// the preceding name.
+fn is_range_operator_start(chars: &std::iter::Peekable<std::str::CharIndices>) -> bool {
+    let mut lookahead = chars.clone();
+    matches!(
+        (lookahead.next(), lookahead.next()),
+        (Some((_, '.')), Some((_, '.'))) | (Some((_, ':')), Some((_, ':')))
+    )
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((position, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '|' => Token::Union,
+            '&' => Token::Intersection,
+            '-' => Token::Difference,
+            '.' if chars.peek().map(|(_, c)| *c) == Some('.') => {
+                chars.next();
+                Token::Range
+            }
+            ':' if chars.peek().map(|(_, c)| *c) == Some(':') => {
+                chars.next();
+                Token::DagRange
+            }
+
+            '"' | '\'' => {
+                let quote = c;
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(ParseError::UnterminatedString { position }),
+                        Some((_, c)) if c == quote => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => string.push(c),
+                            None => return Err(ParseError::UnterminatedString { position }),
+                        },
+                        Some((_, c)) => string.push(c),
+                    }
+                }
+                Token::String(string)
+            }
+
+            c if is_name_char(c) => {
+                let mut name = c.to_string();
+                loop {
+                    if is_range_operator_start(&chars) {
+                        break;
+                    }
+                    match chars.peek() {
+                        Some((_, c)) if is_name_char(*c) => {
+                            name.push(*c);
+                            chars.next();
+                        }
+                        Some((_, '-')) => {
+                            let mut lookahead = chars.clone();
+                            lookahead.next();
+                            match lookahead.peek() {
+                                Some((_, c)) if is_name_char(*c) => {
+                                    name.push('-');
+                                    chars.next();
+                                }
+                                _ => break,
+                            }
+                        }
+                        _ => break,
+                    }
+                }
+                Token::Name(name)
+            }
+
+            character => {
+                return Err(ParseError::UnexpectedCharacter {
+                    position,
+                    character,
+                })
+            }
+        };
+        tokens.push((position, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_position, token)| token)
+    }
+
+    fn next(&mut self) -> Option<(usize, Token)> {
+        let result = self.tokens.get(self.index).cloned();
+        if result.is_some() {
+            self.index += 1;
+        }
+        result
+    }
+
+    fn error(&self, expected: &'static str) -> ParseError {
+        match self.tokens.get(self.index) {
+            Some((position, token)) => ParseError::UnexpectedToken {
+                position: *position,
+                token: token.to_string(),
+                expected,
+            },
+            None => ParseError::UnexpectedEnd { expected },
+        }
+    }
+
+    fn expect(&mut self, expected_token: Token, expected: &'static str) -> Result<(), ParseError> {
+        if self.peek() == Some(&expected_token) {
+            self.index += 1;
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+
+    /// `union := intersection ('|' intersection)*`
+    fn parse_union(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_intersection()?;
+        while self.peek() == Some(&Token::Union) {
+            self.index += 1;
+            let rhs = self.parse_intersection()?;
+            lhs = Expr::FunctionCall("union".to_string(), vec![lhs, rhs]);
+        }
+        Ok(lhs)
+    }
+
+    /// `intersection := range (('&' | '-') range)*`
+    fn parse_intersection(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_range()?;
+        loop {
+            let function_name = match self.peek() {
+                Some(Token::Intersection) => "intersection",
+                Some(Token::Difference) => "difference",
+                _ => break,
+            };
+            self.index += 1;
+            let rhs = self.parse_range()?;
+            lhs = Expr::FunctionCall(function_name.to_string(), vec![lhs, rhs]);
+        }
+        Ok(lhs)
+    }
+
+    /// `range := atom (('..' | '::') atom)?`
+    fn parse_range(&mut self) -> Result<Expr, ParseError> {
+        let lhs = self.parse_atom()?;
+        match self.peek() {
+            Some(Token::Range) => {
+                self.index += 1;
+                let rhs = self.parse_atom()?;
+                Ok(Expr::FunctionCall("only".to_string(), vec![rhs, lhs]))
+            }
+            Some(Token::DagRange) => {
+                self.index += 1;
+                let rhs = self.parse_atom()?;
+                Ok(Expr::FunctionCall("range".to_string(), vec![lhs, rhs]))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    /// `atom := name | string | name '(' (union (',' union)*)? ')' | '(' union ')'`
+    fn parse_atom(&mut self) -> Result<Expr, ParseError> {
+        const EXPECTED: &str = "a name, function call, or parenthesized expression";
+        match self.peek() {
+            Some(Token::Name(_)) => {
+                let name = match self.next() {
+                    Some((_, Token::Name(name))) => name,
+                    _ => unreachable!("Name token was just peeked"),
+                };
+                if self.peek() != Some(&Token::LParen) {
+                    return Ok(Expr::Name(name));
+                }
+                self.index += 1;
+
+                let mut args = Vec::new();
+                if self.peek() == Some(&Token::RParen) {
+                    self.index += 1;
+                    return Ok(Expr::FunctionCall(name, args));
+                }
+                loop {
+                    args.push(self.parse_union()?);
+                    match self.peek() {
+                        Some(Token::Comma) => self.index += 1,
+                        Some(Token::RParen) => {
+                            self.index += 1;
+                            return Ok(Expr::FunctionCall(name, args));
+                        }
+                        _ => return Err(self.error("',' or ')'")),
+                    }
+                }
+            }
+
+            Some(Token::String(string)) => {
+                let expr = Expr::Name(string.clone());
+                self.index += 1;
+                Ok(expr)
+            }
+
+            Some(Token::LParen) => {
+                self.index += 1;
+                let expr = self.parse_union()?;
+                self.expect(Token::RParen, "')'")?;
+                Ok(expr)
+            }
+
+            Some(_) | None => Err(self.error(EXPECTED)),
+        }
+    }
+}
+
+/// Parse the given revset expression.
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens, index: 0 };
+    let expr = parser.parse_union()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(_) => Err(parser.error("an operator or the end of the expression")),
+    }
+}
+
+/// An error produced when evaluating a revset expression.
+#[derive(Debug)]
+pub enum EvalError {
+    /// A name didn't resolve to any commit.
+    UnboundName {
+        /// The name as written in the expression.
+        name: String,
+    },
+
+    /// A name didn't exactly match any branch, but matched several branches
+    /// whose names differ only in case or Unicode normalization.
+    AmbiguousName(AmbiguousBranchName),
+
+    /// There is no function with the given name.
+    UnboundFunction {
+        /// The name of the function.
+        name: String,
+    },
+
+    /// A function was called with the wrong number of arguments.
+    ArityMismatch {
+        /// The name of the function.
+        function_name: String,
+
+        /// A description of the number of arguments the function accepts.
+        expected_arity: &'static str,
+
+        /// The number of arguments which were passed.
+        actual_arity: usize,
+    },
+
+    /// A function which takes a pattern was passed some other expression.
+    ExpectedPattern {
+        /// The name of the function.
+        function_name: String,
+
+        /// The argument passed instead of a pattern.
+        arg: Expr,
+    },
+
+    /// A pattern was not a valid regular expression.
+    InvalidPattern {
+        /// The pattern, without its `re:` prefix.
+        pattern: String,
+
+        /// The error produced when compiling the pattern.
+        message: String,
+    },
+
+    /// Some other error occurred, such as an error accessing the repository.
+    Other(eyre::Error),
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::UnboundName { name } => write!(f, "Commit not found: {}", name),
+            EvalError::AmbiguousName(ambiguous_name) => write!(f, "{}", ambiguous_name),
+            EvalError::UnboundFunction { name } => write!(f, "Unknown function: {}", name),
+            EvalError::ArityMismatch {
+                function_name,
+                expected_arity,
+                actual_arity,
+            } => write!(
+                f,
+                "Wrong number of arguments to function {}: expected {}, got {}",
+                function_name, expected_arity, actual_arity
+            ),
+            EvalError::ExpectedPattern { function_name, arg } => write!(
+                f,
+                "Expected a pattern as the argument to function {}, but got: {}",
+                function_name, arg
+            ),
+            EvalError::InvalidPattern { pattern, message } => {
+                write!(f, "Invalid regular expression {}: {}", pattern, message)
+            }
+            EvalError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<eyre::Error> for EvalError {
+    fn from(err: eyre::Error) -> Self {
+        EvalError::Other(err)
+    }
+}
+
+impl From<eden_dag::Error> for EvalError {
+    fn from(err: eden_dag::Error) -> Self {
+        EvalError::Other(err.into())
+    }
+}
+
+/// A pattern to match text against, as passed to `author` or `message`. A
+/// pattern starting with `re:` is a regular expression, and any other pattern
+/// matches text containing it as a substring.
+enum Pattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Result<Self, EvalError> {
+        match pattern.strip_prefix("re:") {
+            Some(pattern) => match Regex::new(pattern) {
+                Ok(regex) => Ok(Pattern::Regex(regex)),
+                Err(err) => Err(EvalError::InvalidPattern {
+                    pattern: pattern.to_string(),
+                    message: err.to_string(),
+                }),
+            },
+            None => Ok(Pattern::Substring(pattern.to_string())),
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Pattern::Substring(substring) => text.contains(substring.as_str()),
+            Pattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+struct Context<'a> {
+    effects: &'a Effects,
+    repo: &'a Repo,
+    dag: &'a mut Dag,
+}
+
+impl Context<'_> {
+    fn query_draft_commits(&self) -> Result<CommitSet, EvalError> {
+        Ok(self.dag.query_draft_commits()?)
+    }
+
+    fn filter_draft_commits(
+        &self,
+        predicate: impl Fn(&Commit) -> eyre::Result<bool>,
+    ) -> Result<CommitSet, EvalError> {
+        let (_effects, _progress) = self.effects.start_operation(OperationType::FilterCommits);
+        let mut result = Vec::new();
+        for commit_oid in commit_set_to_vec(&self.query_draft_commits()?)? {
+            if let Some(commit) = self.repo.find_commit(commit_oid)? {
+                if predicate(&commit)? {
+                    result.push(commit_oid);
+                }
+            }
+        }
+        Ok(result.into_iter().collect())
+    }
+}
+
+fn expect_no_args(function_name: &str, args: &[Expr]) -> Result<(), EvalError> {
+    match args {
+        [] => Ok(()),
+        args => Err(EvalError::ArityMismatch {
+            function_name: function_name.to_string(),
+            expected_arity: "0",
+            actual_arity: args.len(),
+        }),
+    }
+}
+
+fn eval_one_arg(
+    ctx: &mut Context,
+    function_name: &str,
+    args: &[Expr],
+) -> Result<CommitSet, EvalError> {
+    match args {
+        [arg] => eval_expr(ctx, arg),
+        args => Err(EvalError::ArityMismatch {
+            function_name: function_name.to_string(),
+            expected_arity: "1",
+            actual_arity: args.len(),
+        }),
+    }
+}
+
+fn eval_two_args(
+    ctx: &mut Context,
+    function_name: &str,
+    args: &[Expr],
+) -> Result<(CommitSet, CommitSet), EvalError> {
+    match args {
+        [lhs, rhs] => Ok((eval_expr(ctx, lhs)?, eval_expr(ctx, rhs)?)),
+        args => Err(EvalError::ArityMismatch {
+            function_name: function_name.to_string(),
+            expected_arity: "2",
+            actual_arity: args.len(),
+        }),
+    }
+}
+
+fn eval_pattern_arg(function_name: &str, args: &[Expr]) -> Result<Pattern, EvalError> {
+    match args {
+        [Expr::Name(pattern)] => Pattern::new(pattern),
+        [arg] => Err(EvalError::ExpectedPattern {
+            function_name: function_name.to_string(),
+            arg: arg.clone(),
+        }),
+        args => Err(EvalError::ArityMismatch {
+            function_name: function_name.to_string(),
+            expected_arity: "1",
+            actual_arity: args.len(),
+        }),
+    }
+}
+
+/// Evaluate a call to one of the following functions:
+///
+/// - `all()`: all visible commits.
+/// - `none()`: no commits.
+/// - `public()`: the commits on the main branch.
+/// - `draft()`: the visible commits which aren't on the main branch.
+/// - `main()`: the commit at the tip of the main branch.
+/// - `branches()`: the commits pointed to by local branches.
+/// - `ancestors(x)`: the ancestors of `x`, including `x` itself.
+/// - `descendants(x)`: the visible descendants of `x`, including `x` itself.
+/// - `only(x, y)`: the ancestors of `x` which aren't ancestors of `y`, as
+///   written with `y..x`.
+/// - `range(x, y)`: the descendants of `x` which are ancestors of `y`, as
+///   written with `x::y`.
+/// - `stack(x)`: the draft commits in the stacks containing `x`, or `HEAD` if
+///   `x` is not provided.
+/// - `author(pattern)`: the draft commits whose author name or email matches
+///   `pattern`.
+/// - `message(pattern)`: the draft commits whose message matches `pattern`.
+/// - `union(x, y)`, `intersection(x, y)`, `difference(x, y)`: the set
+///   operations, as written with `|`, `&`, and `-`.
+///
+/// Only draft commits are searched by `author` and `message`, since searching
+/// the entire history of the main branch could be very slow.
+fn eval_function(
+    ctx: &mut Context,
+    function_name: &str,
+    args: &[Expr],
+) -> Result<CommitSet, EvalError> {
+    match function_name {
+        "all" => {
+            expect_no_args(function_name, args)?;
+            Ok(ctx.dag.query_visible_commits()?)
+        }
+
+        "none" => {
+            expect_no_args(function_name, args)?;
+            Ok(CommitSet::empty())
+        }
+
+        "public" => {
+            expect_no_args(function_name, args)?;
+            Ok(ctx.dag.query_public_commits()?)
+        }
+
+        "draft" => {
+            expect_no_args(function_name, args)?;
+            ctx.query_draft_commits()
+        }
+
+        "main" => {
+            expect_no_args(function_name, args)?;
+            Ok(ctx.dag.main_branch_commit.clone())
+        }
+
+        "branches" => {
+            expect_no_args(function_name, args)?;
+            Ok(ctx.dag.branch_commits.clone())
+        }
+
+        "ancestors" => {
+            let commits = eval_one_arg(ctx, function_name, args)?;
+            Ok(ctx.dag.query().ancestors(commits)?)
+        }
+
+        "descendants" => {
+            let commits = eval_one_arg(ctx, function_name, args)?;
+            let visible_commits = ctx.dag.query_visible_commits()?.union(&commits);
+            let descendants = ctx.dag.query().descendants(commits)?;
+            Ok(descendants.intersection(&visible_commits))
+        }
+
+        "only" => {
+            let (commits, excluded_commits) = eval_two_args(ctx, function_name, args)?;
+            let ancestors = ctx.dag.query().ancestors(commits)?;
+            let excluded_ancestors = ctx.dag.query().ancestors(excluded_commits)?;
+            Ok(ancestors.difference(&excluded_ancestors))
+        }
+
+        "range" => {
+            let (roots, heads) = eval_two_args(ctx, function_name, args)?;
+            Ok(ctx.dag.query().range(roots, heads)?)
+        }
+
+        "stack" => {
+            let commits = match args {
+                [] => eval_expr(ctx, &Expr::Name("HEAD".to_string()))?,
+                args => eval_one_arg(ctx, function_name, args).map_err(|err| match err {
+                    EvalError::ArityMismatch {
+                        function_name,
+                        expected_arity: _,
+                        actual_arity,
+                    } => EvalError::ArityMismatch {
+                        function_name,
+                        expected_arity: "0 or 1",
+                        actual_arity,
+                    },
+                    err => err,
+                })?,
+            };
+            Ok(ctx.dag.query_stack_commits(commits)?)
+        }
+
+        "author" => {
+            let pattern = eval_pattern_arg(function_name, args)?;
+            ctx.filter_draft_commits(|commit| {
+                let author = commit.get_author();
+                Ok(
+                    matches!(author.get_name(), Some(name) if pattern.matches(name))
+                        || matches!(author.get_email(), Some(email) if pattern.matches(email)),
+                )
+            })
+        }
+
+        "message" => {
+            let pattern = eval_pattern_arg(function_name, args)?;
+            ctx.filter_draft_commits(|commit| {
+                Ok(pattern.matches(&commit.get_message_raw()?.to_string_lossy()))
+            })
+        }
+
+        "union" => {
+            let (lhs, rhs) = eval_two_args(ctx, function_name, args)?;
+            Ok(lhs.union(&rhs))
+        }
+
+        "intersection" => {
+            let (lhs, rhs) = eval_two_args(ctx, function_name, args)?;
+            Ok(lhs.intersection(&rhs))
+        }
+
+        "difference" => {
+            let (lhs, rhs) = eval_two_args(ctx, function_name, args)?;
+            Ok(lhs.difference(&rhs))
+        }
+
+        _ => Err(EvalError::UnboundFunction {
+            name: function_name.to_string(),
+        }),
+    }
+}
+
+fn eval_expr(ctx: &mut Context, expr: &Expr) -> Result<CommitSet, EvalError> {
+    match expr {
+        Expr::Name(name) => {
+            let commit = match ctx.repo.resolve_branch_name(name)? {
+                ResolvedBranchName::Found(reference) => reference.peel_to_commit()?,
+                ResolvedBranchName::Ambiguous(ambiguous_name) => {
+                    return Err(EvalError::AmbiguousName(ambiguous_name))
+                }
+                ResolvedBranchName::NotFound => ctx.repo.revparse_single_commit(name)?,
+            };
+            let commit = match commit {
+                Some(commit) => commit,
+                None => return Err(EvalError::UnboundName { name: name.clone() }),
+            };
+            let commits = CommitSet::from(commit.get_oid());
+            ctx.dag
+                .sync_from_oids(ctx.effects, ctx.repo, CommitSet::empty(), commits.clone())?;
+            Ok(commits)
+        }
+        Expr::FunctionCall(function_name, args) => eval_function(ctx, function_name, args),
+    }
+}
+
+/// Evaluate the given revset expression against the DAG. Any commits named in
+/// the expression are added to the DAG if they're not already present.
+#[instrument]
+pub fn eval(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &mut Dag,
+    expr: &Expr,
+) -> Result<CommitSet, EvalError> {
+    let mut ctx = Context { effects, repo, dag };
+    eval_expr(&mut ctx, expr)
+}
+
+/// An error produced when resolving a revset expression provided by the user.
+#[derive(Debug)]
+pub enum ResolveError {
+    /// The expression could not be parsed.
+    ParseError {
+        /// The expression, as provided by the user.
+        expr: String,
+
+        /// The parse error.
+        error: ParseError,
+    },
+
+    /// The expression could not be evaluated.
+    EvalError {
+        /// The expression, as provided by the user.
+        expr: String,
+
+        /// The evaluation error.
+        error: EvalError,
+    },
+}
+
+impl ResolveError {
+    /// Describe the error to the user.
+    pub fn describe(&self, effects: &Effects) -> eyre::Result<()> {
+        match self {
+            ResolveError::ParseError { expr, error } => writeln!(
+                effects.get_output_stream(),
+                "Parse error for expression '{}': {}",
+                expr,
+                error
+            )?,
+            ResolveError::EvalError { expr: _, error } => {
+                writeln!(effects.get_output_stream(), "{}", error)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse and evaluate each of the given revset expressions, such as the
+/// commits passed on the command-line. Errors accessing the repository are
+/// returned in the outer `Result`, and errors in the expressions themselves in
+/// the inner `Result`.
+#[instrument]
+pub fn resolve_revsets(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &mut Dag,
+    exprs: &[String],
+) -> eyre::Result<Result<Vec<CommitSet>, ResolveError>> {
+    let mut commit_sets = Vec::new();
+    for expr in exprs {
+        let parsed_expr = match parse(expr) {
+            Ok(parsed_expr) => parsed_expr,
+            Err(error) => {
+                return Ok(Err(ResolveError::ParseError {
+                    expr: expr.clone(),
+                    error,
+                }))
+            }
+        };
+        match eval(effects, repo, dag, &parsed_expr) {
+            Ok(commits) => commit_sets.push(commits),
+            Err(EvalError::Other(err)) => return Err(err),
+            Err(error) => {
+                return Ok(Err(ResolveError::EvalError {
+                    expr: expr.clone(),
+                    error,
+                }))
+            }
+        }
+    }
+    Ok(Ok(commit_sets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::core::dag::sort_commit_set;
+    use crate::core::eventlog::{EventLogDb, EventReplayer};
+    use crate::core::formatting::Glyphs;
+    use crate::testing::{make_git, Git, GitRunOptions, GitWrapper};
+
+    fn parse_to_string(input: &str) -> String {
+        match parse(input) {
+            Ok(expr) => expr.to_string(),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_parse_names_and_functions() {
+        assert_eq!(parse("foo"), Ok(Expr::Name("foo".to_string())));
+        assert_eq!(
+            parse("draft()"),
+            Ok(Expr::FunctionCall("draft".to_string(), vec![]))
+        );
+        assert_eq!(
+            parse(" stack ( foo ) "),
+            Ok(Expr::FunctionCall(
+                "stack".to_string(),
+                vec![Expr::Name("foo".to_string())]
+            ))
+        );
+        assert_eq!(parse_to_string("union(a, b)"), "union(a, b)");
+        assert_eq!(parse_to_string("HEAD~2"), "HEAD~2");
+        assert_eq!(parse_to_string("HEAD^"), "HEAD^");
+        assert_eq!(parse_to_string("@{-1}"), "@{-1}");
+        assert_eq!(parse_to_string("origin/main"), "origin/main");
+        assert_eq!(
+            parse_to_string("ancestors(descendants(foo))"),
+            "ancestors(descendants(foo))"
+        );
+    }
+
+    #[test]
+    fn test_parse_hyphens_and_quotes() {
+        assert_eq!(parse_to_string("my-branch"), "my-branch");
+        assert_eq!(parse_to_string("foo - bar"), "difference(foo, bar)");
+        assert_eq!(parse_to_string("foo -bar"), "difference(foo, bar)");
+        assert_eq!(parse_to_string("foo- bar"), "difference(foo, bar)");
+        assert_eq!(
+            parse_to_string("draft()-public()"),
+            "difference(draft(), public())"
+        );
+        assert_eq!(parse(r#""foo bar""#), Ok(Expr::Name("foo bar".to_string())));
+        assert_eq!(parse("'foo'"), Ok(Expr::Name("foo".to_string())));
+        assert_eq!(
+            parse(r#""a \"quoted\" \\ name""#),
+            Ok(Expr::Name(r#"a "quoted" \ name"#.to_string()))
+        );
+        assert_eq!(
+            parse_to_string(r#"message("fix bug")"#),
+            r#"message("fix bug")"#
+        );
+        assert_eq!(parse_to_string(r#""-foo""#), r#""-foo""#);
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        assert_eq!(parse_to_string("a | b & c"), "union(a, intersection(b, c))");
+        assert_eq!(parse_to_string("a & b | c"), "union(intersection(a, b), c)");
+        assert_eq!(
+            parse_to_string("a - b & c"),
+            "intersection(difference(a, b), c)"
+        );
+        assert_eq!(parse_to_string("a | b - c"), "union(a, difference(b, c))");
+        assert_eq!(parse_to_string("a | b | c"), "union(union(a, b), c)");
+        assert_eq!(
+            parse_to_string("a - b - c"),
+            "difference(difference(a, b), c)"
+        );
+        assert_eq!(
+            parse_to_string("(a | b) & c"),
+            "intersection(union(a, b), c)"
+        );
+        assert_eq!(
+            parse_to_string("a - (b - c)"),
+            "difference(a, difference(b, c))"
+        );
+        assert_eq!(
+            parse_to_string("stack(a | b, c & d)"),
+            "stack(union(a, b), intersection(c, d))"
+        );
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(parse_to_string("foo..bar"), "only(bar, foo)");
+        assert_eq!(parse_to_string("foo::bar"), "range(foo, bar)");
+        assert_eq!(parse_to_string("HEAD~2..HEAD"), "only(HEAD, HEAD~2)");
+        assert_eq!(parse_to_string("a.b..c.d"), "only(c.d, a.b)");
+        assert_eq!(parse_to_string("@{-1}::HEAD^"), "range(@{-1}, HEAD^)");
+        assert_eq!(
+            parse_to_string("a..b | c::d & e"),
+            "union(only(b, a), intersection(range(c, d), e))"
+        );
+        assert_eq!(
+            parse_to_string("stack(a)::(b | c)"),
+            "range(stack(a), union(b, c))"
+        );
+        assert_eq!(
+            parse_to_string("a..b..c"),
+            "error: expected an operator or the end of the expression, but got '..' at position 4"
+        );
+        assert_eq!(
+            parse_to_string("a::"),
+            "error: expected a name, function call, or parenthesized expression, but reached the end of the expression"
+        );
+    }
+
+    #[test]
+    fn test_contains_range() {
+        assert!(parse("a..b").unwrap().contains_range());
+        assert!(parse("stack(a::b) - c").unwrap().contains_range());
+        assert!(parse("only(a, b)").unwrap().contains_range());
+        assert!(!parse("stack(a) - b").unwrap().contains_range());
+        assert!(!parse("HEAD~2").unwrap().contains_range());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_to_string(""),
+            "error: expected a name, function call, or parenthesized expression, but reached the end of the expression"
+        );
+        assert_eq!(
+            parse_to_string("foo |"),
+            "error: expected a name, function call, or parenthesized expression, but reached the end of the expression"
+        );
+        assert_eq!(
+            parse_to_string("& foo"),
+            "error: expected a name, function call, or parenthesized expression, but got '&' at position 0"
+        );
+        assert_eq!(
+            parse_to_string("foo bar"),
+            "error: expected an operator or the end of the expression, but got 'bar' at position 4"
+        );
+        assert_eq!(
+            parse_to_string("foo)"),
+            "error: expected an operator or the end of the expression, but got ')' at position 3"
+        );
+        assert_eq!(
+            parse_to_string("(foo"),
+            "error: expected ')', but reached the end of the expression"
+        );
+        assert_eq!(
+            parse_to_string("stack(foo bar)"),
+            "error: expected ',' or ')', but got 'bar' at position 10"
+        );
+        assert_eq!(
+            parse_to_string("stack(foo,)"),
+            "error: expected a name, function call, or parenthesized expression, but got ')' at position 10"
+        );
+        assert_eq!(
+            parse_to_string("foo ! bar"),
+            "error: unexpected character '!' at position 4"
+        );
+        assert_eq!(
+            parse_to_string(r#"message("foo)"#),
+            "error: unterminated string starting at position 8"
+        );
+        assert_eq!(
+            parse_to_string(r#""foo\"#),
+            "error: unterminated string starting at position 0"
+        );
+        assert_eq!(
+            parse_to_string(r#""foo"(bar)"#),
+            r#"error: expected an operator or the end of the expression, but got '(' at position 5"#
+        );
+    }
+
+    /// Evaluate the expression, and return the summaries of the matching
+    /// commits in topological order, or the error message.
+    fn eval_to_summaries(git: &Git, input: &str) -> eyre::Result<Vec<String>> {
+        let effects = Effects::new_suppress_for_test(Glyphs::text());
+        let repo = git.get_repo()?;
+        let references_snapshot = repo.get_references_snapshot()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+        let event_cursor = event_replayer.make_default_cursor();
+        let mut dag = Dag::open_and_sync(
+            &effects,
+            &repo,
+            &event_replayer,
+            event_cursor,
+            &references_snapshot,
+        )?;
+
+        let expr = match parse(input) {
+            Ok(expr) => expr,
+            Err(err) => return Ok(vec![format!("parse error: {}", err)]),
+        };
+        let commits = match eval(&effects, &repo, &mut dag, &expr) {
+            Ok(commits) => commits,
+            Err(err) => return Ok(vec![format!("eval error: {}", err)]),
+        };
+        let mut result = Vec::new();
+        for commit in sort_commit_set(&repo, &dag, &commits)? {
+            result.push(commit.get_summary()?.to_string_lossy().into_owned());
+        }
+        Ok(result)
+    }
+
+    /// Make a repository with the following commit graph, where `HEAD` points
+    /// to `test5`:
+    ///
+    /// ```text
+    /// O create initial.txt
+    /// |
+    /// O create test1.txt (master)
+    /// |\
+    /// | o create test2.txt
+    /// | |
+    /// | o create test3.txt (foo)
+    /// |
+    /// o create test4.txt
+    /// |
+    /// @ create test5.txt (by Other Person)
+    /// ```
+    fn make_fixture_graph() -> eyre::Result<GitWrapper> {
+        let git = make_git()?;
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.detach_head()?;
+        git.commit_file("test2", 2)?;
+        git.commit_file("test3", 3)?;
+        git.run(&["branch", "foo"])?;
+        git.run(&["checkout", "master"])?;
+        git.detach_head()?;
+        git.commit_file("test4", 4)?;
+        git.write_file("test5", "test5 contents\n")?;
+        git.run(&["add", "test5.txt"])?;
+        git.run_with_options(
+            &[
+                "commit",
+                "-m",
+                "create test5.txt",
+                "--author",
+                "Other Person <other@example.com>",
+            ],
+            &GitRunOptions {
+                time: 5,
+                ..Default::default()
+            },
+        )?;
+        Ok(git)
+    }
+
+    #[test]
+    fn test_eval_functions() -> eyre::Result<()> {
+        let git = make_fixture_graph()?;
+
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "draft()")?, @r###"
+        [
+            "create test2.txt",
+            "create test3.txt",
+            "create test4.txt",
+            "create test5.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "public()")?, @r###"
+        [
+            "create initial.txt",
+            "create test1.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "main()")?, @r###"
+        [
+            "create test1.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "branches()")?, @r###"
+        [
+            "create test1.txt",
+            "create test3.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "none()")?, @r###"
+        []
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "stack()")?, @r###"
+        [
+            "create test4.txt",
+            "create test5.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "stack(foo~)")?, @r###"
+        [
+            "create test2.txt",
+            "create test3.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "stack(master)")?, @r###"
+        []
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "ancestors(foo)")?, @r###"
+        [
+            "create initial.txt",
+            "create test1.txt",
+            "create test2.txt",
+            "create test3.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "descendants(master)")?, @r###"
+        [
+            "create test1.txt",
+            "create test2.txt",
+            "create test3.txt",
+            "create test4.txt",
+            "create test5.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "author(Other)")?, @r###"
+        [
+            "create test5.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(
+            eval_to_summaries(&git, "author('re:^other@')")?,
+            @r###"
+        [
+            "create test5.txt",
+        ]
+        "###
+        );
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "message(test3)")?, @r###"
+        [
+            "create test3.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(
+            eval_to_summaries(&git, "message('re:test[24]')")?,
+            @r###"
+        [
+            "create test2.txt",
+            "create test4.txt",
+        ]
+        "###
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_set_operations() -> eyre::Result<()> {
+        let git = make_fixture_graph()?;
+
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "foo | HEAD")?, @r###"
+        [
+            "create test3.txt",
+            "create test5.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(
+            eval_to_summaries(&git, "ancestors(foo) & ancestors(HEAD)")?,
+            @r###"
+        [
+            "create initial.txt",
+            "create test1.txt",
+        ]
+        "###
+        );
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "draft() - stack(foo)")?, @r###"
+        [
+            "create test4.txt",
+            "create test5.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(
+            eval_to_summaries(&git, "descendants(master) - foo | main()")?,
+            @r###"
+        [
+            "create test1.txt",
+            "create test2.txt",
+            "create test4.txt",
+            "create test5.txt",
+        ]
+        "###
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_ranges() -> eyre::Result<()> {
+        let git = make_fixture_graph()?;
+
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "master..foo")?, @r###"
+        [
+            "create test2.txt",
+            "create test3.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "foo..HEAD")?, @r###"
+        [
+            "create test4.txt",
+            "create test5.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "master::HEAD")?, @r###"
+        [
+            "create test1.txt",
+            "create test4.txt",
+            "create test5.txt",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "foo::HEAD")?, @"[]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_errors() -> eyre::Result<()> {
+        let git = make_fixture_graph()?;
+
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "nonexistent")?, @r###"
+        [
+            "eval error: Commit not found: nonexistent",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "foo | nonexistent()")?, @r###"
+        [
+            "eval error: Unknown function: nonexistent",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "draft(foo)")?, @r###"
+        [
+            "eval error: Wrong number of arguments to function draft: expected 0, got 1",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "stack(foo, HEAD)")?, @r###"
+        [
+            "eval error: Wrong number of arguments to function stack: expected 0 or 1, got 2",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "ancestors()")?, @r###"
+        [
+            "eval error: Wrong number of arguments to function ancestors: expected 1, got 0",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "message(draft())")?, @r###"
+        [
+            "eval error: Expected a pattern as the argument to function message, but got: draft()",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "author('re:(')")?, @r###"
+        [
+            "eval error: Invalid regular expression (: regex parse error:\n    (\n    ^\nerror: unclosed group",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(eval_to_summaries(&git, "foo &")?, @r###"
+        [
+            "parse error: expected a name, function call, or parenthesized expression, but reached the end of the expression",
+        ]
+        "###);
+
+        Ok(())
+    }
+}
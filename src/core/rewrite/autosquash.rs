@@ -0,0 +1,254 @@
+//! Fold `fixup!`, `squash!`, and `amend!` commits into the commits which they
+//! target, as `git rebase --autosquash` would.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::core::effects::Effects;
+use crate::core::formatting::printable_styled_string;
+use crate::git::{NonZeroOid, Repo};
+
+use super::plan::{FixupKind, OidOrLabel, RebaseCommand};
+
+const FIXUP_PREFIXES: &[(&str, FixupKind)] = &[
+    ("fixup! ", FixupKind::Fixup),
+    ("squash! ", FixupKind::Squash),
+    ("amend! ", FixupKind::Amend),
+];
+
+/// If `summary` is the summary line of a `fixup!`, `squash!`, or `amend!`
+/// commit, return the kind of fixup and the description of the target commit.
+fn parse_fixup_summary(summary: &str) -> Option<(FixupKind, &str)> {
+    let (kind, mut target) = FIXUP_PREFIXES
+        .iter()
+        .find_map(|(prefix, kind)| summary.strip_prefix(prefix).map(|target| (*kind, target)))?;
+
+    // `git commit --fixup` on a fixup commit adds another prefix, but the
+    // result still refers to the same target.
+    while let Some(rest) = FIXUP_PREFIXES
+        .iter()
+        .find_map(|(prefix, _kind)| target.strip_prefix(prefix))
+    {
+        target = rest;
+    }
+    Some((kind, target.trim()))
+}
+
+/// Determine which of `candidate_oids` (ordered from oldest to newest) the
+/// fixup commit refers to with `target`. Like Git, prefer an exact match of
+/// the summary line, then a commit hash, then a prefix of the summary line.
+fn find_fixup_target(
+    repo: &Repo,
+    candidate_oids: &[NonZeroOid],
+    target: &str,
+) -> eyre::Result<Option<NonZeroOid>> {
+    let mut summaries = Vec::new();
+    for oid in candidate_oids {
+        let commit = repo.find_commit_or_fail(*oid)?;
+        summaries.push((*oid, commit.get_summary()?.to_string_lossy().into_owned()));
+    }
+
+    if let Some((oid, _summary)) = summaries.iter().find(|(_oid, summary)| summary == target) {
+        return Ok(Some(*oid));
+    }
+    let is_oid_prefix = target.len() >= 4
+        && target
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_uppercase());
+    if is_oid_prefix {
+        if let Some(oid) = candidate_oids
+            .iter()
+            .find(|oid| oid.to_string().starts_with(target))
+        {
+            return Ok(Some(*oid));
+        }
+    }
+    Ok(summaries
+        .iter()
+        .find(|(_oid, summary)| summary.starts_with(target))
+        .map(|(oid, _summary)| *oid))
+}
+
+/// Rearrange `commands` so that each `fixup!`, `squash!`, or `amend!` commit is
+/// folded into its target commit immediately after that commit is applied.
+///
+/// Only commits which are ancestors of the fixup commit in the rewritten graph
+/// are considered as targets. If the target isn't being rewritten, the fixup
+/// commit is left as an ordinary commit, and a warning is printed.
+pub(super) fn autosquash_rebase_commands(
+    effects: &Effects,
+    repo: &Repo,
+    commands: Vec<RebaseCommand>,
+) -> eyre::Result<Vec<RebaseCommand>> {
+    // Determine the parent of each applied commit in the rewritten graph, or
+    // `None` if its parent isn't being rewritten.
+    let mut plan_parents: HashMap<NonZeroOid, Option<NonZeroOid>> = HashMap::new();
+    let mut picked_oids: Vec<NonZeroOid> = Vec::new();
+    {
+        let mut labels: HashMap<&str, Option<NonZeroOid>> = HashMap::new();
+        let mut current_oid = None;
+        for command in commands.iter() {
+            match command {
+                RebaseCommand::CreateLabel { label_name } => {
+                    labels.insert(label_name.as_str(), current_oid);
+                }
+                RebaseCommand::Reset {
+                    target: OidOrLabel::Oid(_),
+                } => {
+                    current_oid = None;
+                }
+                RebaseCommand::Reset {
+                    target: OidOrLabel::Label(label_name),
+                } => {
+                    current_oid = labels.get(label_name.as_str()).copied().flatten();
+                }
+                RebaseCommand::Pick { commit_oid } => {
+                    plan_parents.insert(*commit_oid, current_oid);
+                    picked_oids.push(*commit_oid);
+                    current_oid = Some(*commit_oid);
+                }
+                RebaseCommand::Merge { commit_oid, .. } => {
+                    plan_parents.insert(*commit_oid, current_oid);
+                    current_oid = Some(*commit_oid);
+                }
+                RebaseCommand::RegisterExtraPostRewriteHook
+                | RebaseCommand::DetectEmptyCommit { .. }
+                | RebaseCommand::SkipUpstreamAppliedCommit { .. }
+                | RebaseCommand::DropMergeCommit { .. }
+                | RebaseCommand::Fixup { .. } => {}
+            }
+        }
+    }
+
+    let mut folded_oids: HashMap<NonZeroOid, NonZeroOid> = HashMap::new();
+    let mut fixups: HashMap<NonZeroOid, Vec<(NonZeroOid, FixupKind)>> = HashMap::new();
+    for commit_oid in picked_oids {
+        let commit = repo.find_commit_or_fail(commit_oid)?;
+        let summary = commit.get_summary()?.to_string_lossy().into_owned();
+        let (kind, target) = match parse_fixup_summary(&summary) {
+            Some(fixup) => fixup,
+            None => continue,
+        };
+
+        let mut ancestor_oids = Vec::new();
+        let mut current_oid = plan_parents.get(&commit_oid).copied().flatten();
+        while let Some(oid) = current_oid {
+            ancestor_oids.push(oid);
+            current_oid = plan_parents.get(&oid).copied().flatten();
+        }
+        ancestor_oids.reverse();
+
+        match find_fixup_target(repo, &ancestor_oids, target)? {
+            Some(target_oid) => {
+                // If the target is itself folded into another commit, then
+                // fold this commit into that one as well.
+                let target_oid = folded_oids.get(&target_oid).copied().unwrap_or(target_oid);
+                folded_oids.insert(commit_oid, target_oid);
+                fixups
+                    .entry(target_oid)
+                    .or_default()
+                    .push((commit_oid, kind));
+            }
+            None => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Not folding {} into another commit, as its target commit is not being rewritten.",
+                    printable_styled_string(effects.get_glyphs(), commit.friendly_describe()?)?
+                )?;
+            }
+        }
+    }
+    if folded_oids.is_empty() {
+        return Ok(commands);
+    }
+
+    let folded_oids: HashSet<NonZeroOid> = folded_oids.into_keys().collect();
+    let mut result = Vec::with_capacity(commands.len());
+    for command in commands {
+        let target_oid = match &command {
+            RebaseCommand::Pick { commit_oid }
+            | RebaseCommand::DetectEmptyCommit { commit_oid }
+                if folded_oids.contains(commit_oid) =>
+            {
+                continue;
+            }
+            RebaseCommand::Pick { commit_oid } | RebaseCommand::Merge { commit_oid, .. } => {
+                Some(*commit_oid)
+            }
+            _ => None,
+        };
+        result.push(command);
+        if let Some(target_oid) = target_oid {
+            for (commit_oid, kind) in fixups.remove(&target_oid).unwrap_or_default() {
+                result.push(RebaseCommand::Fixup {
+                    commit_oid,
+                    target_oid,
+                    kind,
+                });
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Compute the message for the result of folding a commit with message
+/// `fixup_message` into a commit with message `target_message`. Returns `None`
+/// if the message of the target commit should be kept as-is.
+///
+/// Like Git, the summary line of the fixup commit is dropped, since it only
+/// describes the target commit.
+pub(super) fn make_fixup_message(
+    kind: FixupKind,
+    target_message: &str,
+    fixup_message: &str,
+) -> Option<String> {
+    let fixup_body = fixup_message
+        .split_once('\n')
+        .map(|(_summary, body)| body.trim())
+        .unwrap_or_default();
+    match kind {
+        FixupKind::Fixup => None,
+        FixupKind::Squash if fixup_body.is_empty() => None,
+        FixupKind::Squash => Some(format!("{}\n\n{}\n", target_message.trim_end(), fixup_body)),
+        FixupKind::Amend if fixup_body.is_empty() => None,
+        FixupKind::Amend => Some(format!("{}\n", fixup_body)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fixup_summary() {
+        assert_eq!(parse_fixup_summary("create test1.txt"), None);
+        assert_eq!(
+            parse_fixup_summary("fixup! create test1.txt"),
+            Some((FixupKind::Fixup, "create test1.txt"))
+        );
+        assert_eq!(
+            parse_fixup_summary("squash! fixup! create test1.txt"),
+            Some((FixupKind::Squash, "create test1.txt"))
+        );
+        assert_eq!(
+            parse_fixup_summary("amend! create test1.txt"),
+            Some((FixupKind::Amend, "create test1.txt"))
+        );
+    }
+
+    #[test]
+    fn test_make_fixup_message() {
+        assert_eq!(
+            make_fixup_message(FixupKind::Fixup, "foo\n", "fixup! foo\n"),
+            None
+        );
+        assert_eq!(
+            make_fixup_message(FixupKind::Squash, "foo\n\nbody\n", "squash! foo\n\nmore\n"),
+            Some("foo\n\nbody\n\nmore\n".to_string())
+        );
+        assert_eq!(
+            make_fixup_message(FixupKind::Amend, "foo\n", "amend! foo\n\nbar\n\nbaz\n"),
+            Some("bar\n\nbaz\n".to_string())
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Guard against rewriting commits which have already been published.
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use eden_dag::DagAlgorithm;
+use tracing::instrument;
+
+use crate::core::config::get_main_branch_names;
+use crate::core::dag::{sort_commit_set, CommitSet, CommitVertex, Dag};
+use crate::core::effects::Effects;
+use crate::core::formatting::printable_styled_string;
+use crate::git::{CategorizedReferenceName, NonZeroOid, Repo};
+
+/// Check that none of the given commits, which are about to be rewritten, are
+/// public (i.e. ancestors of any of the main branches). Rewriting a public commit
+/// causes the local history to diverge from the shared history, which is
+/// rarely intended.
+///
+/// If any of them are public, prints an error naming them and returns
+/// `false`, unless `force_rewrite_public` is set. Even then, the commit that a
+/// remote-tracking main branch points to can't be rewritten, since that
+/// branch can't be moved to the rewritten commit.
+#[instrument(skip(commit_oids))]
+pub fn check_rewritten_commits_are_draft(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &Dag,
+    commit_oids: impl IntoIterator<Item = NonZeroOid>,
+    force_rewrite_public: bool,
+) -> eyre::Result<bool> {
+    let commits: CommitSet = commit_oids.into_iter().collect();
+
+    let mut main_branches = Vec::new();
+    for main_branch_name in get_main_branch_names(repo)? {
+        let reference = match repo.find_main_branch_reference_by_name(&main_branch_name)? {
+            Some(reference) => reference,
+            None => continue,
+        };
+        // A local main branch with an upstream resolves to the upstream, but
+        // the local branch can still be moved.
+        let is_remote = matches!(
+            CategorizedReferenceName::new(&reference.get_name()?),
+            CategorizedReferenceName::RemoteBranch { .. }
+        ) && repo
+            .find_branch(
+                main_branch_name
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(&main_branch_name),
+                git2::BranchType::Local,
+            )?
+            .is_none();
+        if let Some(commit) = reference.peel_to_commit()? {
+            main_branches.push((
+                main_branch_name,
+                CommitVertex::from(commit.get_oid()),
+                is_remote,
+            ));
+        }
+    }
+
+    if force_rewrite_public {
+        let mut result = true;
+        for (main_branch_name, main_branch_vertex, is_remote) in main_branches {
+            if is_remote && commits.contains(&main_branch_vertex)? {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Refusing to rewrite {}: the main branch {} is a remote-tracking branch, so it can't be moved to the rewritten commit.",
+                    printable_styled_string(
+                        effects.get_glyphs(),
+                        repo.friendly_describe_commit_from_oid(NonZeroOid::try_from(
+                            main_branch_vertex
+                        )?)?
+                    )?,
+                    main_branch_name,
+                )?;
+                result = false;
+            }
+        }
+        return Ok(result);
+    }
+
+    let public_commits = dag.query_public_commits()?.intersection(&commits);
+    let public_commits = sort_commit_set(repo, dag, &public_commits)?;
+    if public_commits.is_empty() {
+        return Ok(true);
+    }
+
+    for commit in public_commits {
+        let commit_vertex = CommitVertex::from(commit.get_oid());
+        let mut main_branch_name = None;
+        for (name, main_branch_vertex, _is_remote) in main_branches.iter() {
+            if dag
+                .query()
+                .is_ancestor(commit_vertex.clone(), main_branch_vertex.clone())?
+            {
+                main_branch_name = Some(name);
+                break;
+            }
+        }
+        let main_branch_name = match main_branch_name {
+            Some(main_branch_name) => main_branch_name.as_str(),
+            None => "the main branch",
+        };
+        writeln!(
+            effects.get_output_stream(),
+            "Refusing to rewrite {}: it is a public commit on {}.",
+            printable_styled_string(effects.get_glyphs(), commit.friendly_describe()?)?,
+            main_branch_name,
+        )?;
+    }
+    writeln!(
+        effects.get_output_stream(),
+        "(Pass --force-rewrite-public to rewrite public commits anyway)"
+    )?;
+    Ok(false)
+}
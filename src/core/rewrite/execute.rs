@@ -7,38 +7,83 @@ use std::path::PathBuf;
 use std::time::SystemTime;
 
 use eyre::Context;
+use itertools::Itertools;
 use os_str_bytes::OsStrBytes;
-use tracing::warn;
+use serde_json::json;
+use tracing::{instrument, warn};
 
-use crate::core::effects::Effects;
-use crate::core::eventlog::EventTransactionId;
-use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::commands::smartlog::{should_show_stack_smartlog, smartlog};
+use crate::core::effects::{Effects, OperationType};
+use crate::core::eventlog::{Event, EventTransactionId};
+use crate::core::formatting::{printable_styled_string, Glyphs, Pluralize};
 use crate::git::{
-    check_out_commit, GitRunInfo, MaybeZeroOid, NonZeroOid, Repo, ResolvedReferenceInfo,
+    check_out_commit, CategorizedReferenceName, GitRunInfo, GitRunOpts, MaybeZeroOid, NonZeroOid,
+    Repo, ResolvedBranchName, ResolvedReferenceInfo, WorktreeInfo,
 };
 
 use super::plan::RebasePlan;
 
+/// The name of the file in the rebase state directory which records the event
+/// transaction ID of the operation which started an on-disk rebase, so that the
+/// rest of the rebase can be recorded in the same transaction when it's resumed.
+const REBASE_EVENT_TX_ID_FILE_NAME: &str = "branchless-event-tx-id";
+
+/// Get the event transaction ID of the on-disk rebase in progress, if it was
+/// started by `git-branchless`.
+pub fn get_rebase_event_tx_id(repo: &Repo) -> eyre::Result<Option<EventTransactionId>> {
+    let event_tx_id_file_path = repo
+        .get_rebase_state_dir_path()
+        .join(REBASE_EVENT_TX_ID_FILE_NAME);
+    let contents = match std::fs::read_to_string(&event_tx_id_file_path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).wrap_err_with(|| {
+                format!(
+                    "Reading event transaction ID from: {:?}",
+                    &event_tx_id_file_path
+                )
+            })
+        }
+    };
+    let event_tx_id = contents.trim().parse().wrap_err_with(|| {
+        format!(
+            "Parsing event transaction ID from: {:?}",
+            &event_tx_id_file_path
+        )
+    })?;
+    Ok(Some(event_tx_id))
+}
+
 /// Given a list of rewritten OIDs, move the branches attached to those OIDs
 /// from their old commits to their new commits. Invoke the
 /// `reference-transaction` hook when done.
+///
+/// All of the branches are updated in a single reference transaction, so if
+/// any of them can't be moved, then none of them are moved. The exception is
+/// branches whose names differ from another reference's name only in case or
+/// Unicode normalization: on a case-insensitive filesystem, their loose
+/// reference files would collide, so they're written directly to
+/// `packed-refs` afterwards instead.
+///
+/// Returns an exit code (0 denotes successful exit).
+#[instrument]
 pub fn move_branches<'a>(
     effects: &Effects,
     git_run_info: &GitRunInfo,
     repo: &'a Repo,
     event_tx_id: EventTransactionId,
     rewritten_oids_map: &'a HashMap<NonZeroOid, MaybeZeroOid>,
-) -> eyre::Result<()> {
+) -> eyre::Result<isize> {
+    let (effects, _progress) = effects.start_operation(OperationType::UpdateBranches);
+    let effects = &effects;
     let branch_oid_to_names = repo.get_branch_oid_to_names()?;
 
-    // We may experience an error in the case of a branch move. Ideally, we
-    // would use `git2::Transaction::commit`, which stops the transaction at the
-    // first error, but we don't know which references we successfully committed
-    // in that case. Instead, we just do things non-atomically and record which
-    // ones succeeded. See https://github.com/libgit2/libgit2/issues/5918
+    // Collect all of the branch moves before applying any of them, so that we
+    // don't leave the branches in a partially-updated state if a rewritten
+    // commit turns out to be missing.
     let mut branch_moves: Vec<(NonZeroOid, MaybeZeroOid, &OsStr)> = Vec::new();
-    let mut branch_move_err: Option<eyre::Error> = None;
-    'outer: for (old_oid, names) in branch_oid_to_names.iter() {
+    for (old_oid, names) in branch_oid_to_names.iter() {
         let new_oid = match rewritten_oids_map.get(old_oid) {
             Some(new_oid) => new_oid,
             None => continue,
@@ -48,52 +93,83 @@ pub fn move_branches<'a>(
         names.sort_unstable();
         match new_oid {
             MaybeZeroOid::NonZero(new_oid) => {
-                let new_commit = match repo.find_commit_or_fail(*new_oid).wrap_err_with(|| {
+                repo.find_commit_or_fail(*new_oid).wrap_err_with(|| {
                     format!(
                         "Could not find newly-rewritten commit with old OID: {:?}, new OID: {:?}",
                         old_oid, new_oid,
                     )
-                }) {
-                    Ok(commit) => commit,
-                    Err(err) => {
-                        branch_move_err = Some(err);
-                        break 'outer;
-                    }
-                };
-
+                })?;
                 for name in names {
-                    if let Err(err) =
-                        repo.create_reference(name, new_commit.get_oid(), true, "move branches")
-                    {
-                        branch_move_err = Some(err);
-                        break 'outer;
-                    }
                     branch_moves.push((*old_oid, MaybeZeroOid::NonZero(*new_oid), name));
                 }
             }
 
             MaybeZeroOid::Zero => {
                 for name in names {
-                    match repo.find_reference(name) {
-                        Ok(Some(mut reference)) => {
-                            if let Err(err) = reference.delete() {
-                                branch_move_err = Some(err);
-                                break 'outer;
-                            }
-                        }
-                        Ok(None) => {
-                            warn!(?name, "Reference not found, not deleting")
-                        }
-                        Err(err) => {
-                            branch_move_err = Some(err);
-                            break 'outer;
-                        }
-                    };
+                    if repo.find_reference(name)?.is_none() {
+                        warn!(?name, "Reference not found, not deleting");
+                        continue;
+                    }
                     branch_moves.push((*old_oid, MaybeZeroOid::Zero, name));
                 }
             }
         }
     }
+    if branch_moves.is_empty() {
+        return Ok(0);
+    }
+
+    let mut updates: Vec<(&OsStr, MaybeZeroOid)> = Vec::new();
+    let mut packed_updates: Vec<(&OsStr, MaybeZeroOid)> = Vec::new();
+    let colliding_reference_names = repo.get_colliding_reference_names()?;
+    for (_old_oid, new_oid, name) in branch_moves.iter() {
+        if colliding_reference_names.contains(*name) {
+            packed_updates.push((*name, *new_oid));
+        } else {
+            updates.push((*name, *new_oid));
+        }
+    }
+    let branch_move_result = repo
+        .update_references_atomically(&updates, "move branches")
+        .and_then(|()| repo.update_packed_references(&packed_updates));
+    let branch_move_err = match branch_move_result {
+        Ok(()) => None,
+        Err(err) => {
+            // The references are all locked before any of them are updated,
+            // so usually none of them will have moved. But if committing the
+            // transaction failed partway through, then record the moves which
+            // did take effect, so that they can be undone.
+            let mut applied_branch_moves = Vec::new();
+            for (old_oid, new_oid, name) in branch_moves {
+                let current_oid = match repo.resolve_branch_name(&name.to_string_lossy())? {
+                    ResolvedBranchName::Found(reference) => repo.resolve_reference(&reference)?.oid,
+                    ResolvedBranchName::Ambiguous(_) | ResolvedBranchName::NotFound => None,
+                };
+                if MaybeZeroOid::from(current_oid) == new_oid {
+                    applied_branch_moves.push((old_oid, new_oid, name));
+                }
+            }
+            branch_moves = applied_branch_moves;
+            Some(err)
+        }
+    };
+
+    effects.set_result_field(
+        "moved_branches",
+        branch_moves
+            .iter()
+            .map(|(old_oid, new_oid, name)| {
+                json!({
+                    "name": name.to_string_lossy(),
+                    "old_oid": old_oid.to_string(),
+                    "new_oid": match new_oid {
+                        MaybeZeroOid::NonZero(new_oid) => Some(new_oid.to_string()),
+                        MaybeZeroOid::Zero => None,
+                    },
+                })
+            })
+            .collect(),
+    );
 
     let branch_moves_stdin: Vec<u8> = branch_moves
         .into_iter()
@@ -120,11 +196,262 @@ pub fn move_branches<'a>(
         Some(branch_moves_stdin),
     )?;
     match branch_move_err {
-        Some(err) => Err(err),
-        None => Ok(()),
+        Some(err) => {
+            writeln!(
+                effects.get_error_stream(),
+                "Failed to move branches: {:#}",
+                err
+            )?;
+            Ok(1)
+        }
+        None => Ok(0),
     }
 }
 
+/// Determine where branches should be moved to after a rebase.
+///
+/// Commits which were skipped during the rebase are rewritten to nothing, but
+/// any branches pointing to them should move to the new location of the
+/// skipped commit's parent, rather than being deleted.
+pub fn get_branch_rewritten_oids(
+    rewritten_oids: &HashMap<NonZeroOid, MaybeZeroOid>,
+    skipped_commit_parent_oids: &HashMap<NonZeroOid, NonZeroOid>,
+) -> HashMap<NonZeroOid, MaybeZeroOid> {
+    rewritten_oids
+        .iter()
+        .map(|(old_oid, new_oid)| {
+            let new_oid = match (new_oid, skipped_commit_parent_oids.get(old_oid)) {
+                (MaybeZeroOid::Zero, Some(parent_oid)) => MaybeZeroOid::NonZero(*parent_oid),
+                (new_oid, _) => *new_oid,
+            };
+            (*old_oid, new_oid)
+        })
+        .collect()
+}
+
+/// Find the worktrees other than the current one whose `HEAD` points to one of
+/// the given commits, and so would be left pointing to an obsolete commit if
+/// that commit were rewritten.
+fn find_affected_worktrees(
+    repo: &Repo,
+    commit_oids: &HashSet<NonZeroOid>,
+) -> eyre::Result<Vec<WorktreeInfo>> {
+    let current_worktree_name = repo.get_worktree_name();
+    Ok(repo
+        .list_worktrees()?
+        .into_iter()
+        .filter(|worktree| {
+            worktree.name.as_deref() != current_worktree_name
+                && matches!(worktree.head_oid, Some(head_oid) if commit_oids.contains(&head_oid))
+        })
+        .collect())
+}
+
+/// Describe a worktree and the commit it has checked out, for display to the
+/// user.
+fn describe_worktree(repo: &Repo, worktree: &WorktreeInfo) -> eyre::Result<String> {
+    let checked_out = match &worktree.branch_name {
+        Some(branch_name) => CategorizedReferenceName::new(branch_name).friendly_describe(),
+        None => "detached HEAD".to_string(),
+    };
+    let commit_description = match worktree.head_oid {
+        Some(head_oid) => printable_styled_string(
+            &Glyphs::text(),
+            repo.friendly_describe_commit_from_oid(head_oid)?,
+        )?,
+        None => String::new(),
+    };
+    Ok(format!(
+        "{} ({}): {}",
+        worktree.path.display(),
+        checked_out,
+        commit_description
+    ))
+}
+
+/// Check whether the rewrite can proceed, given the other worktrees which have
+/// commits to be rewritten checked out. If it can't, describe why and return
+/// `false`.
+///
+/// The worktrees are only updated after an in-memory rebase, and only if they
+/// don't have uncommitted changes, so this is checked before rewriting
+/// anything, rather than leaving a partial result.
+fn check_affected_worktrees(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    affected_worktrees: &[WorktreeInfo],
+    update_worktrees: bool,
+    force_on_disk: bool,
+) -> eyre::Result<bool> {
+    if !update_worktrees {
+        writeln!(
+            effects.get_result_stream(),
+            "These worktrees have commits checked out which would be rewritten, which would leave them pointing to the old versions of the commits:"
+        )?;
+        for worktree in affected_worktrees {
+            writeln!(
+                effects.get_result_stream(),
+                "- {}",
+                describe_worktree(repo, worktree)?
+            )?;
+        }
+        writeln!(
+            effects.get_result_stream(),
+            "To check out the rewritten commits in those worktrees afterwards, retry with: --update-worktrees"
+        )?;
+        return Ok(false);
+    }
+
+    if force_on_disk {
+        writeln!(
+            effects.get_result_stream(),
+            "Other worktrees can only be updated after an in-memory rebase, but an on-disk rebase was requested."
+        )?;
+        return Ok(false);
+    }
+
+    let mut result = true;
+    for worktree in affected_worktrees {
+        let worktree_repo = Repo::from_dir(&worktree.path)?;
+        if !worktree_repo.get_status(git_run_info, None)?.is_empty() {
+            writeln!(
+                effects.get_result_stream(),
+                "Cannot update worktree {}, since it has uncommitted changes.",
+                describe_worktree(repo, worktree)?
+            )?;
+            result = false;
+        }
+    }
+    Ok(result)
+}
+
+/// Check out the rewritten versions of the commits which the given worktrees
+/// had checked out. Worktrees with a branch checked out are updated to match
+/// the branch, which has already been moved; detached worktrees are checked
+/// out to the rewritten commit directly.
+fn check_out_rewritten_commits_in_worktrees(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    event_tx_id: EventTransactionId,
+    worktrees: &[WorktreeInfo],
+    rewritten_oids: &HashMap<NonZeroOid, MaybeZeroOid>,
+) -> eyre::Result<isize> {
+    let mut exit_code = 0;
+    for worktree in worktrees {
+        let old_oid = match worktree.head_oid {
+            Some(old_oid) => old_oid,
+            None => continue,
+        };
+        let new_oid = match rewritten_oids.get(&old_oid) {
+            Some(MaybeZeroOid::NonZero(new_oid)) => *new_oid,
+            Some(MaybeZeroOid::Zero) | None => continue,
+        };
+        let worktree_repo = Repo::from_dir(&worktree.path)?;
+        let current_oid = worktree_repo.get_head_info()?.oid;
+        let args = if current_oid == Some(new_oid) {
+            // `HEAD` is attached to a branch which was moved, so only the
+            // index and working copy need to be updated.
+            vec![
+                "read-tree".to_string(),
+                "-m".to_string(),
+                "-u".to_string(),
+                old_oid.to_string(),
+                new_oid.to_string(),
+            ]
+        } else if current_oid == Some(old_oid) && worktree.branch_name.is_none() {
+            vec![
+                "checkout".to_string(),
+                "--detach".to_string(),
+                new_oid.to_string(),
+            ]
+        } else {
+            continue;
+        };
+
+        let result = git_run_info.run_silent(
+            &worktree_repo,
+            Some(event_tx_id),
+            &args,
+            GitRunOpts {
+                treat_git_failure_as_error: false,
+            },
+        )?;
+        if result.exit_code == 0 {
+            writeln!(
+                effects.get_output_stream(),
+                "Updated worktree at {} to: {}",
+                worktree.path.display(),
+                printable_styled_string(
+                    effects.get_glyphs(),
+                    worktree_repo.friendly_describe_commit_from_oid(new_oid)?
+                )?
+            )?;
+        } else {
+            writeln!(
+                effects.get_result_stream(),
+                "Failed to update worktree at {}: {}",
+                worktree.path.display(),
+                String::from_utf8_lossy(&result.stderr).trim()
+            )?;
+            exit_code = 1;
+        }
+    }
+    Ok(exit_code)
+}
+
+/// Point `HEAD` (and the branch that it's attached to, if any) to the
+/// rewritten version of the commit that it currently points to, if that commit
+/// appears in `rewritten_oids`.
+///
+/// The working copy isn't touched, so this should only be used when the
+/// rewritten commit has the same tree as the original commit. The references
+/// are updated without going through Git, so no hooks are invoked to record
+/// the updates; instead, the events recording them are returned.
+pub fn move_head_to_rewritten_commit(
+    repo: &Repo,
+    now: SystemTime,
+    event_tx_id: EventTransactionId,
+    rewritten_oids: &HashMap<NonZeroOid, NonZeroOid>,
+) -> eyre::Result<Vec<Event>> {
+    let head_info = repo.get_head_info()?;
+    let (head_oid, rewritten_head_oid) = match head_info.oid {
+        Some(head_oid) => match rewritten_oids.get(&head_oid) {
+            Some(rewritten_head_oid) => (head_oid, *rewritten_head_oid),
+            None => return Ok(Vec::new()),
+        },
+        None => return Ok(Vec::new()),
+    };
+    match &head_info.reference_name {
+        Some(reference_name) => {
+            repo.create_reference(
+                reference_name,
+                rewritten_head_oid,
+                true,
+                "branchless: moving checked-out branch",
+            )?;
+        }
+        None => repo.set_head(rewritten_head_oid)?,
+    }
+
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let updated_ref_names = std::iter::once(OsString::from("HEAD")).chain(
+        head_info
+            .reference_name
+            .map(|ref_name| ref_name.into_owned()),
+    );
+    Ok(updated_ref_names
+        .map(|ref_name| Event::RefUpdateEvent {
+            timestamp,
+            event_tx_id,
+            ref_name,
+            old_oid: head_oid.into(),
+            new_oid: rewritten_head_oid.into(),
+            message: None,
+        })
+        .collect())
+}
+
 /// After a rebase, check out the appropriate new `HEAD`. This can be difficult
 /// because the commit might have been rewritten, dropped, or have a branch
 /// pointing to it which also needs to be checked out.
@@ -133,6 +460,7 @@ pub fn move_branches<'a>(
 /// `HEAD` should be in the event that the original commit was skipped. If the
 /// caller doesn't think that the previous `HEAD` commit was skipped, then they
 /// should pass in `None`.
+#[instrument]
 pub fn check_out_updated_head(
     effects: &Effects,
     git_run_info: &GitRunInfo,
@@ -236,6 +564,25 @@ pub fn check_out_updated_head(
         return Ok(0);
     }
 
+    if let ResolvedReferenceInfo {
+        oid: Some(oid),
+        reference_name: Some(reference_name),
+    } = &checkout_target
+    {
+        if head_info.oid == Some(*oid) {
+            // The branch wasn't rewritten, and `HEAD` was only detached from
+            // it for the duration of the rebase. Reattach it directly rather
+            // than calling `git checkout`, since the working copy is already
+            // up-to-date. Still show the smartlog, as `git checkout` would
+            // have.
+            repo.set_head_symbolic(reference_name)?;
+            if !should_show_stack_smartlog(effects, repo)? {
+                smartlog(effects, git_run_info, &Default::default())?;
+            }
+            return Ok(0);
+        }
+    }
+
     let checkout_target: Cow<OsStr> = match &checkout_target {
         ResolvedReferenceInfo {
             oid: None,
@@ -250,14 +597,10 @@ pub fn check_out_updated_head(
         ResolvedReferenceInfo {
             oid: _,
             reference_name: Some(reference_name),
-        } => {
-            // FIXME: we could check to see if the OIDs are the same and, if so,
-            // reattach or detach `HEAD` manually without having to call `git checkout`.
-            match checkout_target.get_branch_name()? {
-                Some(branch_name) => Cow::Owned(branch_name),
-                None => Cow::Borrowed(reference_name),
-            }
-        }
+        } => match checkout_target.get_branch_name()? {
+            Some(branch_name) => Cow::Owned(branch_name),
+            None => Cow::Borrowed(reference_name),
+        },
     };
 
     let result = check_out_commit(
@@ -280,15 +623,32 @@ pub struct MergeConflictInfo {
 }
 
 impl MergeConflictInfo {
+    /// Attach the merge conflict to the `result` record when writing JSON
+    /// output.
+    fn set_result_field(&self, effects: &Effects) {
+        effects.set_result_field(
+            "merge_conflict",
+            json!({
+                "commit_oid": self.commit_oid.to_string(),
+                "conflicting_paths": self
+                    .conflicting_paths
+                    .iter()
+                    .map(|path| path.to_string_lossy())
+                    .sorted()
+                    .collect_vec(),
+            }),
+        );
+    }
+
     /// Describe the merge conflict in a user-friendly way and advise to rerun
     /// with `--merge`.
     pub fn describe(&self, effects: &Effects, repo: &Repo) -> eyre::Result<()> {
         writeln!(
-            effects.get_output_stream(),
+            effects.get_result_stream(),
             "This operation would cause a merge conflict:"
         )?;
         writeln!(
-            effects.get_output_stream(),
+            effects.get_result_stream(),
             "{} ({}) {}",
             effects.get_glyphs().bullet_point,
             Pluralize {
@@ -303,7 +663,7 @@ impl MergeConflictInfo {
             )?
         )?;
         writeln!(
-            effects.get_output_stream(),
+            effects.get_result_stream(),
             "To resolve merge conflicts, retry this operation with the --merge option."
         )?;
         Ok(())
@@ -311,22 +671,32 @@ impl MergeConflictInfo {
 }
 
 mod in_memory {
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
     use std::ffi::OsString;
     use std::fmt::Write;
 
     use eyre::Context;
-    use indicatif::{ProgressBar, ProgressStyle};
+    use itertools::Itertools;
+    use rayon::prelude::*;
     use tracing::{instrument, warn};
 
     use crate::commands::gc::mark_commit_reachable;
-    use crate::core::effects::Effects;
+    use crate::core::commit_hooks::run_commit_msg_hook;
+    use crate::core::config::{get_rewrite_run_commit_msg_hook, get_rewrite_sign};
+    use crate::core::effects::{Effects, OperationType, OutputStream, ProgressHandle};
+    use crate::core::eventlog::EventTransactionId;
     use crate::core::formatting::printable_styled_string;
-    use crate::core::rewrite::execute::check_out_updated_head;
+    use crate::core::pool::{get_num_jobs, make_repo_thread_pool, with_worker_repo};
+    use crate::core::rewrite::autosquash::make_fixup_message;
+    use crate::core::rewrite::execute::{
+        check_out_rewritten_commits_in_worktrees, check_out_updated_head, find_affected_worktrees,
+        get_branch_rewritten_oids,
+    };
     use crate::core::rewrite::move_branches;
     use crate::core::rewrite::plan::{OidOrLabel, RebaseCommand, RebasePlan};
     use crate::git::{
-        CherryPickFastError, CherryPickFastOptions, GitRunInfo, MaybeZeroOid, NonZeroOid, Repo,
+        CherryPickFastError, CherryPickFastOptions, Commit, GitRunInfo, MaybeZeroOid, NonZeroOid,
+        Repo, Signature, Tree,
     };
 
     use super::{ExecuteRebasePlanOptions, MergeConflictInfo};
@@ -341,95 +711,277 @@ mod in_memory {
             /// - This doesn't capture if `HEAD` was pointing to a branch. The
             /// caller will need to figure that out.
             new_head_oid: Option<NonZeroOid>,
+
+            /// For each commit which was skipped, the OID of the commit which
+            /// it would have been applied on top of.
+            skipped_commit_parent_oids: HashMap<NonZeroOid, NonZeroOid>,
         },
         CannotRebaseMergeCommit {
             commit_oid: NonZeroOid,
         },
         MergeConflict(MergeConflictInfo),
+        CommitMsgHookFailed {
+            commit_oid: NonZeroOid,
+        },
     }
 
-    #[instrument]
-    pub fn rebase_in_memory(
+    /// Create the rebased version of `commit_to_apply`, with the same message.
+    /// If `should_run_commit_msg_hook` is set, then the `commit-msg` hook is
+    /// run on the message first, and `None` is returned if it rejects the
+    /// message.
+    fn create_rebased_commit(
         effects: &Effects,
+        git_run_info: &GitRunInfo,
         repo: &Repo,
-        rebase_plan: &RebasePlan,
-        options: &ExecuteRebasePlanOptions,
-    ) -> eyre::Result<RebaseInMemoryResult> {
-        if let Some(merge_commit_oid) =
-            rebase_plan
-                .commands
+        event_tx_id: EventTransactionId,
+        should_run_commit_msg_hook: bool,
+        committer_signature: &Signature,
+        commit_to_apply: &Commit,
+        commit_tree: &Tree,
+        parents: Vec<&Commit>,
+    ) -> eyre::Result<Option<NonZeroOid>> {
+        if should_run_commit_msg_hook {
+            let message = commit_to_apply
+                .get_message_raw()?
+                .to_string_lossy()
+                .into_owned();
+            match run_commit_msg_hook(effects, git_run_info, repo, event_tx_id, &message)? {
+                None => return Ok(None),
+                Some(new_message) if new_message != message => {
+                    let rebased_commit_oid = repo.create_commit(
+                        None,
+                        &commit_to_apply.get_author(),
+                        committer_signature,
+                        &new_message,
+                        commit_tree,
+                        parents,
+                    )?;
+                    return Ok(Some(rebased_commit_oid));
+                }
+                Some(_) => {}
+            }
+        }
+
+        let rebased_commit_oid = repo.create_commit_with_message_from(
+            &commit_to_apply.get_author(),
+            committer_signature,
+            commit_to_apply,
+            commit_tree,
+            parents,
+        )?;
+        Ok(Some(rebased_commit_oid))
+    }
+
+    fn is_pick_command(command: &RebaseCommand) -> bool {
+        match command {
+            RebaseCommand::CreateLabel { .. }
+            | RebaseCommand::Reset { .. }
+            | RebaseCommand::RegisterExtraPostRewriteHook
+            | RebaseCommand::DetectEmptyCommit { .. } => false,
+            RebaseCommand::Pick { .. }
+            | RebaseCommand::Merge { .. }
+            | RebaseCommand::SkipUpstreamAppliedCommit { .. }
+            | RebaseCommand::DropMergeCommit { .. }
+            | RebaseCommand::Fixup { .. } => true,
+        }
+    }
+
+    /// Split the commands of the rebase plan into groups which can be applied
+    /// independently of each other, such as the commands for stacks which are
+    /// rooted at different commits. Each group after the first starts by
+    /// resetting to a commit which isn't being rewritten, and doesn't refer to
+    /// labels created in any other group.
+    fn split_independent_commands(commands: &[RebaseCommand]) -> Vec<&[RebaseCommand]> {
+        let mut groups: Vec<(usize, HashSet<&str>)> = Vec::new();
+        let mut segment_start = 0;
+        while segment_start < commands.len() {
+            let segment_end = commands[segment_start + 1..]
                 .iter()
-                .find_map(|command| match command {
-                    RebaseCommand::Merge {
-                        commit_oid,
-                        commits_to_merge: _,
-                    } => Some(commit_oid),
-                    RebaseCommand::CreateLabel { .. }
-                    | RebaseCommand::Reset { .. }
-                    | RebaseCommand::Pick { .. }
-                    | RebaseCommand::RegisterExtraPostRewriteHook
-                    | RebaseCommand::DetectEmptyCommit { .. }
-                    | RebaseCommand::SkipUpstreamAppliedCommit { .. } => None,
+                .position(|command| {
+                    matches!(
+                        command,
+                        RebaseCommand::Reset {
+                            target: OidOrLabel::Oid(_)
+                        }
+                    )
                 })
-        {
-            return Ok(RebaseInMemoryResult::CannotRebaseMergeCommit {
-                commit_oid: *merge_commit_oid,
+                .map(|index| segment_start + 1 + index)
+                .unwrap_or_else(|| commands.len());
+            let segment = &commands[segment_start..segment_end];
+
+            let mut created_labels: HashSet<&str> = HashSet::new();
+            let mut used_labels: HashSet<&str> = HashSet::new();
+            for command in segment {
+                match command {
+                    RebaseCommand::CreateLabel { label_name } => {
+                        created_labels.insert(label_name);
+                    }
+                    RebaseCommand::Reset {
+                        target: OidOrLabel::Label(label_name),
+                    } => {
+                        used_labels.insert(label_name);
+                    }
+                    RebaseCommand::Merge {
+                        commit_oid: _,
+                        commits_to_merge,
+                    } => {
+                        for commit_to_merge in commits_to_merge {
+                            if let OidOrLabel::Label(label_name) = commit_to_merge {
+                                used_labels.insert(label_name);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // If this segment refers to labels created in an earlier group,
+            // then it has to be applied after that group, so merge it (and
+            // every group in between) into that group.
+            let dependency_index = groups.iter().position(|(_start, group_labels)| {
+                used_labels
+                    .iter()
+                    .any(|label_name| group_labels.contains(label_name))
             });
+            match dependency_index {
+                Some(dependency_index) => {
+                    let merged_labels = groups
+                        .drain(dependency_index + 1..)
+                        .flat_map(|(_start, group_labels)| group_labels)
+                        .collect_vec();
+                    let (_start, group_labels) = &mut groups[dependency_index];
+                    group_labels.extend(merged_labels);
+                    group_labels.extend(created_labels);
+                }
+                None => groups.push((segment_start, created_labels)),
+            }
+            segment_start = segment_end;
+        }
+
+        let mut result = Vec::new();
+        for (index, (start, _labels)) in groups.iter().enumerate() {
+            let end = match groups.get(index + 1) {
+                Some((next_start, _labels)) => *next_start,
+                None => commands.len(),
+            };
+            result.push(&commands[*start..end]);
+        }
+        result
+    }
+
+    /// The parts of the rebase which are shared between the groups of
+    /// commands applied by `apply_rebase_commands`.
+    struct RebaseContext<'a> {
+        git_run_info: &'a GitRunInfo,
+        options: &'a ExecuteRebasePlanOptions,
+        should_run_commit_msg_hook: bool,
+        num_picks: usize,
+
+        /// Only commits which are followed by a `DetectEmptyCommit` command
+        /// should be skipped if they become empty.
+        drop_if_empty_oids: HashSet<NonZeroOid>,
+    }
+
+    /// The state of the rebase as a group of commands is applied.
+    struct RebaseState {
+        /// The number of picks which have been applied, including those in
+        /// groups before this one.
+        i: usize,
+
+        current_oid: NonZeroOid,
+        labels: HashMap<String, NonZeroOid>,
+        rewritten_oids: Vec<(NonZeroOid, MaybeZeroOid)>,
+
+        /// For each commit which was skipped, the OID of the commit which it
+        /// would have been applied on top of, in the order that they were
+        /// skipped.
+        skipped_commit_parent_oids: Vec<(NonZeroOid, NonZeroOid)>,
+
+        /// If set, the output for the applied commands is collected here,
+        /// rather than written directly, so that the output of groups which
+        /// are applied in parallel can be written in order afterwards.
+        output_buffer: Option<String>,
+    }
+
+    enum RebaseOutputStream<'a> {
+        Stream(OutputStream),
+        Buffer(&'a mut String),
+    }
+
+    impl Write for RebaseOutputStream<'_> {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            match self {
+                RebaseOutputStream::Stream(stream) => stream.write_str(s),
+                RebaseOutputStream::Buffer(buffer) => buffer.write_str(s),
+            }
         }
+    }
 
+    impl RebaseState {
+        fn new(i: usize, current_oid: NonZeroOid, should_buffer_output: bool) -> Self {
+            Self {
+                i,
+                current_oid,
+                labels: Default::default(),
+                rewritten_oids: Default::default(),
+                skipped_commit_parent_oids: Default::default(),
+                output_buffer: if should_buffer_output {
+                    Some(String::new())
+                } else {
+                    None
+                },
+            }
+        }
+
+        fn get_output_stream(&mut self, effects: &Effects) -> RebaseOutputStream<'_> {
+            match &mut self.output_buffer {
+                Some(buffer) => RebaseOutputStream::Buffer(buffer),
+                None => RebaseOutputStream::Stream(effects.get_output_stream()),
+            }
+        }
+    }
+
+    /// Apply the given group of commands from the rebase plan. Returns the
+    /// reason that the rebase can't continue, if any.
+    fn apply_rebase_commands(
+        effects: &Effects,
+        progress: &ProgressHandle,
+        repo: &Repo,
+        context: &RebaseContext,
+        commands: &[RebaseCommand],
+        state: &mut RebaseState,
+    ) -> eyre::Result<Option<RebaseInMemoryResult>> {
+        let RebaseContext {
+            git_run_info,
+            options,
+            should_run_commit_msg_hook,
+            num_picks,
+            drop_if_empty_oids,
+        } = context;
+        let should_run_commit_msg_hook = *should_run_commit_msg_hook;
+        let num_picks = *num_picks;
         let ExecuteRebasePlanOptions {
             now,
             // Transaction ID will be passed to the `post-rewrite` hook via
             // environment variable.
-            event_tx_id: _,
+            event_tx_id,
             preserve_timestamps,
             force_in_memory: _,
             force_on_disk: _,
             resolve_merge_conflicts: _, // May be needed once we can resolve merge conflicts in memory.
+            update_worktrees: _,
         } = options;
 
-        let mut current_oid = rebase_plan.first_dest_oid;
-        let mut labels: HashMap<String, NonZeroOid> = HashMap::new();
-        let mut rewritten_oids: Vec<(NonZeroOid, MaybeZeroOid)> = Vec::new();
-
-        // Normally, we can determine the new `HEAD` OID by looking at the
-        // rewritten commits. However, if `HEAD` pointed to a commit that was
-        // skipped, then the rewritten OID is zero. In that case, we need to
-        // delete the branch (responsibility of the caller) and choose a
-        // different `HEAD` OID.
-        let head_oid = repo.get_head_info()?.oid;
-        let mut skipped_head_new_oid = None;
-        let mut maybe_set_skipped_head_new_oid = |skipped_head_oid, current_oid| {
-            if Some(skipped_head_oid) == head_oid {
-                skipped_head_new_oid.get_or_insert(current_oid);
-            }
-        };
-
-        let mut i = 0;
-        let num_picks = rebase_plan
-            .commands
-            .iter()
-            .filter(|command| match command {
-                RebaseCommand::CreateLabel { .. }
-                | RebaseCommand::Reset { .. }
-                | RebaseCommand::RegisterExtraPostRewriteHook
-                | RebaseCommand::DetectEmptyCommit { .. } => false,
-                RebaseCommand::Pick { .. }
-                | RebaseCommand::Merge { .. }
-                | RebaseCommand::SkipUpstreamAppliedCommit { .. } => true,
-            })
-            .count();
-
-        for command in rebase_plan.commands.iter() {
+        for command in commands {
             match command {
                 RebaseCommand::CreateLabel { label_name } => {
-                    labels.insert(label_name.clone(), current_oid);
+                    state.labels.insert(label_name.clone(), state.current_oid);
                 }
 
                 RebaseCommand::Reset {
                     target: OidOrLabel::Label(label_name),
                 } => {
-                    current_oid = match labels.get(label_name) {
+                    state.current_oid = match state.labels.get(label_name) {
                         Some(oid) => *oid,
                         None => eyre::bail!("BUG: no associated OID for label: {}", label_name),
                     };
@@ -438,84 +990,80 @@ mod in_memory {
                 RebaseCommand::Reset {
                     target: OidOrLabel::Oid(commit_oid),
                 } => {
-                    current_oid = *commit_oid;
+                    state.current_oid = *commit_oid;
                 }
 
                 RebaseCommand::Pick { commit_oid } => {
                     let current_commit = repo
-                        .find_commit_or_fail(current_oid)
+                        .find_commit_or_fail(state.current_oid)
                         .wrap_err("Finding current commit")?;
                     let commit_to_apply = repo
                         .find_commit_or_fail(*commit_oid)
                         .wrap_err("Finding commit to apply")?;
-                    i += 1;
+                    state.i += 1;
 
-                    let commit_description = printable_styled_string(
-                        effects.get_glyphs(),
-                        commit_to_apply.friendly_describe()?,
-                    )?;
-                    let commit_num = format!("[{}/{}]", i, num_picks);
-                    let progress_template = format!("{} {{spinner}} {{wide_msg}}", commit_num);
-                    let progress = ProgressBar::new_spinner();
-                    progress.set_style(
-                        ProgressStyle::default_spinner().template(progress_template.trim()),
-                    );
-                    progress.set_message("Starting");
-                    progress.enable_steady_tick(100);
+                    let commit_num = format!("[{}/{}]", state.i, num_picks);
+                    progress.notify_progress(state.i, num_picks);
+                    progress.notify_status(commit_to_apply.get_summary()?.to_string_lossy());
 
                     if commit_to_apply.get_parent_count() > 1 {
                         warn!(
                             ?commit_oid,
                             "BUG: Merge commit should have been detected during planning phase"
                         );
-                        return Ok(RebaseInMemoryResult::CannotRebaseMergeCommit {
+                        return Ok(Some(RebaseInMemoryResult::CannotRebaseMergeCommit {
                             commit_oid: *commit_oid,
-                        });
+                        }));
                     };
 
-                    progress
-                        .set_message(format!("Applying patch for commit: {}", commit_description));
-                    let commit_tree = match repo.cherry_pick_fast(
-                        &commit_to_apply,
-                        &current_commit,
-                        &CherryPickFastOptions {
-                            reuse_parent_tree_if_possible: true,
-                        },
-                    )? {
+                    let cherry_pick_result = {
+                        let (_effects, _progress) =
+                            effects.start_operation(OperationType::ApplyPatch);
+                        repo.cherry_pick_fast(
+                            &commit_to_apply,
+                            &current_commit,
+                            &CherryPickFastOptions {
+                                reuse_parent_tree_if_possible: true,
+                            },
+                        )?
+                    };
+                    let commit_tree = match cherry_pick_result {
                         Ok(rebased_commit) => rebased_commit,
                         Err(CherryPickFastError::MergeConflict { conflicting_paths }) => {
-                            return Ok(RebaseInMemoryResult::MergeConflict(MergeConflictInfo {
-                                commit_oid: *commit_oid,
-                                conflicting_paths,
-                            }))
+                            return Ok(Some(RebaseInMemoryResult::MergeConflict(
+                                MergeConflictInfo {
+                                    commit_oid: *commit_oid,
+                                    conflicting_paths,
+                                },
+                            )))
                         }
                     };
 
-                    let commit_message = commit_to_apply.get_message_raw()?;
-                    let commit_message = commit_message.to_str().ok_or_else(|| {
-                        eyre::eyre!(
-                            "Could not decode commit message for commit: {:?}",
-                            commit_oid
-                        )
-                    })?;
-
-                    progress
-                        .set_message(format!("Committing to repository: {}", commit_description));
                     let committer_signature = if *preserve_timestamps {
                         commit_to_apply.get_committer()
                     } else {
                         commit_to_apply.get_committer().update_timestamp(*now)?
                     };
-                    let rebased_commit_oid = repo
-                        .create_commit(
-                            None,
-                            &commit_to_apply.get_author(),
-                            &committer_signature,
-                            commit_message,
-                            &commit_tree,
-                            vec![&current_commit],
-                        )
-                        .wrap_err("Applying rebased commit")?;
+                    let rebased_commit_oid = match create_rebased_commit(
+                        effects,
+                        git_run_info,
+                        repo,
+                        *event_tx_id,
+                        should_run_commit_msg_hook,
+                        &committer_signature,
+                        &commit_to_apply,
+                        &commit_tree,
+                        vec![&current_commit],
+                    )
+                    .wrap_err("Applying rebased commit")?
+                    {
+                        Some(rebased_commit_oid) => rebased_commit_oid,
+                        None => {
+                            return Ok(Some(RebaseInMemoryResult::CommitMsgHookFailed {
+                                commit_oid: *commit_oid,
+                            }))
+                        }
+                    };
 
                     let rebased_commit = repo
                         .find_commit_or_fail(rebased_commit_oid)
@@ -524,26 +1072,26 @@ mod in_memory {
                         effects.get_glyphs(),
                         repo.friendly_describe_commit_from_oid(rebased_commit_oid)?,
                     )?;
-                    if rebased_commit.is_empty() {
-                        rewritten_oids.push((*commit_oid, MaybeZeroOid::Zero));
-                        maybe_set_skipped_head_new_oid(*commit_oid, current_oid);
+                    if rebased_commit.is_empty() && drop_if_empty_oids.contains(commit_oid) {
+                        state.rewritten_oids.push((*commit_oid, MaybeZeroOid::Zero));
+                        state
+                            .skipped_commit_parent_oids
+                            .push((*commit_oid, state.current_oid));
 
-                        progress.finish_and_clear();
                         writeln!(
-                            effects.get_output_stream(),
-                            "[{}/{}] Skipped now-empty commit: {}",
-                            i,
-                            num_picks,
+                            state.get_output_stream(effects),
+                            "{} Skipped now-empty commit: {}",
+                            commit_num,
                             commit_description
                         )?;
                     } else {
-                        rewritten_oids
+                        state
+                            .rewritten_oids
                             .push((*commit_oid, MaybeZeroOid::NonZero(rebased_commit_oid)));
-                        current_oid = rebased_commit_oid;
+                        state.current_oid = rebased_commit_oid;
 
-                        progress.finish_and_clear();
                         writeln!(
-                            effects.get_output_stream(),
+                            state.get_output_stream(effects),
                             "{} Committed as: {}",
                             commit_num,
                             commit_description
@@ -553,42 +1101,296 @@ mod in_memory {
 
                 RebaseCommand::Merge {
                     commit_oid,
-                    commits_to_merge: _,
+                    commits_to_merge,
                 } => {
-                    warn!(
-                        ?commit_oid,
-                        "BUG: Merge commit should have been detected when starting in-memory rebase"
-                    );
-                    return Ok(RebaseInMemoryResult::CannotRebaseMergeCommit {
-                        commit_oid: *commit_oid,
-                    });
+                    let commit_to_apply = repo
+                        .find_commit_or_fail(*commit_oid)
+                        .wrap_err("Finding merge commit to apply")?;
+                    state.i += 1;
+
+                    let commit_num = format!("[{}/{}]", state.i, num_picks);
+                    progress.notify_progress(state.i, num_picks);
+                    progress.notify_status(commit_to_apply.get_summary()?.to_string_lossy());
+
+                    // The merge is always made into the rebase head, which
+                    // becomes the first parent.
+                    let mut parent_oids = vec![state.current_oid];
+                    for commit_to_merge in commits_to_merge {
+                        let parent_oid = match commit_to_merge {
+                            OidOrLabel::Oid(oid) => *oid,
+                            OidOrLabel::Label(label_name) => match state.labels.get(label_name) {
+                                Some(oid) => *oid,
+                                None => {
+                                    eyre::bail!("BUG: no associated OID for label: {}", label_name)
+                                }
+                            },
+                        };
+                        parent_oids.push(parent_oid);
+                    }
+                    let parents = parent_oids
+                        .into_iter()
+                        .map(|parent_oid| repo.find_commit_or_fail(parent_oid))
+                        .collect::<eyre::Result<Vec<Commit>>>()?;
+
+                    // If the parents have the same contents as before, then
+                    // the original merge resolution still applies, so we can
+                    // reuse its tree directly.
+                    let original_parents = commit_to_apply.get_parents();
+                    let are_parent_trees_unchanged = original_parents.len() == parents.len()
+                        && original_parents.iter().zip(parents.iter()).try_fold(
+                            true,
+                            |acc, (original_parent, parent)| -> eyre::Result<bool> {
+                                Ok(acc
+                                    && original_parent.get_tree()?.get_oid()
+                                        == parent.get_tree()?.get_oid())
+                            },
+                        )?;
+                    let commit_tree = if are_parent_trees_unchanged {
+                        commit_to_apply.get_tree()?
+                    } else {
+                        match parents.as_slice() {
+                            [our_commit, their_commit] => {
+                                let merge_result = {
+                                    let (_effects, _progress) =
+                                        effects.start_operation(OperationType::MergeCommits);
+                                    repo.merge_commits(our_commit, their_commit)?
+                                };
+                                match merge_result {
+                                    Ok(merged_tree) => merged_tree,
+                                    Err(CherryPickFastError::MergeConflict {
+                                        conflicting_paths,
+                                    }) => {
+                                        return Ok(Some(RebaseInMemoryResult::MergeConflict(
+                                            MergeConflictInfo {
+                                                commit_oid: *commit_oid,
+                                                conflicting_paths,
+                                            },
+                                        )))
+                                    }
+                                }
+                            }
+                            _ => {
+                                return Ok(Some(RebaseInMemoryResult::CannotRebaseMergeCommit {
+                                    commit_oid: *commit_oid,
+                                }));
+                            }
+                        }
+                    };
+
+                    let committer_signature = if *preserve_timestamps {
+                        commit_to_apply.get_committer()
+                    } else {
+                        commit_to_apply.get_committer().update_timestamp(*now)?
+                    };
+                    let rebased_commit_oid = match create_rebased_commit(
+                        effects,
+                        git_run_info,
+                        repo,
+                        *event_tx_id,
+                        should_run_commit_msg_hook,
+                        &committer_signature,
+                        &commit_to_apply,
+                        &commit_tree,
+                        parents.iter().collect(),
+                    )
+                    .wrap_err("Applying rebased merge commit")?
+                    {
+                        Some(rebased_commit_oid) => rebased_commit_oid,
+                        None => {
+                            return Ok(Some(RebaseInMemoryResult::CommitMsgHookFailed {
+                                commit_oid: *commit_oid,
+                            }))
+                        }
+                    };
+                    state
+                        .rewritten_oids
+                        .push((*commit_oid, MaybeZeroOid::NonZero(rebased_commit_oid)));
+                    state.current_oid = rebased_commit_oid;
+
+                    let commit_description = printable_styled_string(
+                        effects.get_glyphs(),
+                        repo.friendly_describe_commit_from_oid(rebased_commit_oid)?,
+                    )?;
+                    writeln!(
+                        state.get_output_stream(effects),
+                        "{} Committed as: {}",
+                        commit_num,
+                        commit_description
+                    )?;
+                }
+
+                RebaseCommand::DropMergeCommit { commit_oid } => {
+                    state.i += 1;
+                    let commit_num = format!("[{}/{}]", state.i, num_picks);
+                    state.rewritten_oids.push((*commit_oid, MaybeZeroOid::Zero));
+                    state
+                        .skipped_commit_parent_oids
+                        .push((*commit_oid, state.current_oid));
+
+                    let commit_description = printable_styled_string(
+                        effects.get_glyphs(),
+                        repo.friendly_describe_commit_from_oid(*commit_oid)?,
+                    )?;
+                    writeln!(
+                        state.get_output_stream(effects),
+                        "{} Dropped merge commit: {}",
+                        commit_num,
+                        commit_description
+                    )?;
                 }
 
                 RebaseCommand::SkipUpstreamAppliedCommit { commit_oid } => {
-                    let progress = ProgressBar::new_spinner();
-                    i += 1;
-                    let commit_num = format!("[{}/{}]", i, num_picks);
-                    let progress_template = format!("{} {{spinner}} {{wide_msg}}", commit_num);
-                    progress.set_style(
-                        ProgressStyle::default_spinner().template(progress_template.trim()),
-                    );
+                    state.i += 1;
+                    let commit_num = format!("[{}/{}]", state.i, num_picks);
+                    progress.notify_progress(state.i, num_picks);
 
                     let commit = repo.find_commit_or_fail(*commit_oid)?;
-                    rewritten_oids.push((*commit_oid, MaybeZeroOid::Zero));
-                    maybe_set_skipped_head_new_oid(*commit_oid, current_oid);
+                    state.rewritten_oids.push((*commit_oid, MaybeZeroOid::Zero));
+                    state
+                        .skipped_commit_parent_oids
+                        .push((*commit_oid, state.current_oid));
 
-                    progress.finish_and_clear();
                     let commit_description = commit.friendly_describe()?;
                     let commit_description =
                         printable_styled_string(effects.get_glyphs(), commit_description)?;
                     writeln!(
-                        effects.get_output_stream(),
+                        state.get_output_stream(effects),
                         "{} Skipped commit (was already applied upstream): {}",
                         commit_num,
                         commit_description
                     )?;
                 }
 
+                RebaseCommand::Fixup {
+                    commit_oid,
+                    target_oid,
+                    kind,
+                } => {
+                    let current_commit = repo
+                        .find_commit_or_fail(state.current_oid)
+                        .wrap_err("Finding current commit")?;
+                    let commit_to_apply = repo
+                        .find_commit_or_fail(*commit_oid)
+                        .wrap_err("Finding fixup commit to apply")?;
+                    state.i += 1;
+
+                    let commit_num = format!("[{}/{}]", state.i, num_picks);
+                    progress.notify_progress(state.i, num_picks);
+                    progress.notify_status(commit_to_apply.get_summary()?.to_string_lossy());
+
+                    let cherry_pick_result = {
+                        let (_effects, _progress) =
+                            effects.start_operation(OperationType::ApplyPatch);
+                        repo.cherry_pick_fast(
+                            &commit_to_apply,
+                            &current_commit,
+                            &CherryPickFastOptions {
+                                reuse_parent_tree_if_possible: true,
+                            },
+                        )?
+                    };
+                    let commit_tree = match cherry_pick_result {
+                        Ok(rebased_commit) => rebased_commit,
+                        Err(CherryPickFastError::MergeConflict { conflicting_paths }) => {
+                            return Ok(Some(RebaseInMemoryResult::MergeConflict(
+                                MergeConflictInfo {
+                                    commit_oid: *commit_oid,
+                                    conflicting_paths,
+                                },
+                            )))
+                        }
+                    };
+
+                    // The target commit may have been skipped (such as if it
+                    // became empty), in which case there's nothing to fold the
+                    // fixup commit into, so apply it as a normal commit.
+                    let is_target_applied =
+                        state.rewritten_oids.iter().any(|(source_oid, dest_oid)| {
+                            source_oid == target_oid
+                                && *dest_oid == MaybeZeroOid::NonZero(state.current_oid)
+                        });
+                    if !is_target_applied {
+                        let committer_signature = if *preserve_timestamps {
+                            commit_to_apply.get_committer()
+                        } else {
+                            commit_to_apply.get_committer().update_timestamp(*now)?
+                        };
+                        let rebased_commit_oid = repo
+                            .create_commit_with_message_from(
+                                &commit_to_apply.get_author(),
+                                &committer_signature,
+                                &commit_to_apply,
+                                &commit_tree,
+                                vec![&current_commit],
+                            )
+                            .wrap_err("Applying rebased commit")?;
+                        state
+                            .rewritten_oids
+                            .push((*commit_oid, MaybeZeroOid::NonZero(rebased_commit_oid)));
+                        state.current_oid = rebased_commit_oid;
+
+                        let commit_description = printable_styled_string(
+                            effects.get_glyphs(),
+                            repo.friendly_describe_commit_from_oid(rebased_commit_oid)?,
+                        )?;
+                        writeln!(
+                            state.get_output_stream(effects),
+                            "{} Committed as: {}",
+                            commit_num,
+                            commit_description
+                        )?;
+                        continue;
+                    }
+
+                    let message = make_fixup_message(
+                        *kind,
+                        &current_commit.get_message_raw()?.to_string_lossy(),
+                        &commit_to_apply.get_message_raw()?.to_string_lossy(),
+                    );
+                    let parents = current_commit.get_parents();
+                    let folded_commit_oid = match message {
+                        None => repo.create_commit_with_message_from(
+                            &current_commit.get_author(),
+                            &current_commit.get_committer(),
+                            &current_commit,
+                            &commit_tree,
+                            parents.iter().collect(),
+                        ),
+                        Some(message) => repo.create_commit(
+                            None,
+                            &current_commit.get_author(),
+                            &current_commit.get_committer(),
+                            message.as_str(),
+                            &commit_tree,
+                            parents.iter().collect(),
+                        ),
+                    }
+                    .wrap_err("Applying fixup commit")?;
+
+                    // Everything which was rewritten into the target commit
+                    // is now rewritten into the folded commit instead.
+                    for (_source_oid, dest_oid) in state.rewritten_oids.iter_mut() {
+                        if *dest_oid == MaybeZeroOid::NonZero(state.current_oid) {
+                            *dest_oid = MaybeZeroOid::NonZero(folded_commit_oid);
+                        }
+                    }
+                    state
+                        .rewritten_oids
+                        .push((*commit_oid, MaybeZeroOid::NonZero(folded_commit_oid)));
+                    state.current_oid = folded_commit_oid;
+
+                    let commit_description = printable_styled_string(
+                        effects.get_glyphs(),
+                        repo.friendly_describe_commit_from_oid(folded_commit_oid)?,
+                    )?;
+                    writeln!(
+                        state.get_output_stream(effects),
+                        "{} Folded into: {}",
+                        commit_num,
+                        commit_description
+                    )?;
+                }
+
                 RebaseCommand::RegisterExtraPostRewriteHook
                 | RebaseCommand::DetectEmptyCommit { .. } => {
                     // Do nothing. We'll carry out post-rebase operations after the
@@ -596,6 +1398,143 @@ mod in_memory {
                 }
             }
         }
+        Ok(None)
+    }
+
+    /// Create the rebased commits in-memory, without updating any references.
+    /// If `run_hooks` is not set, then the `commit-msg` hook isn't run, even
+    /// if `branchless.rewrite.runCommitMsgHook` is set.
+    #[instrument]
+    pub fn rebase_in_memory(
+        effects: &Effects,
+        git_run_info: &GitRunInfo,
+        repo: &Repo,
+        rebase_plan: &RebasePlan,
+        options: &ExecuteRebasePlanOptions,
+        run_hooks: bool,
+    ) -> eyre::Result<RebaseInMemoryResult> {
+        let should_run_commit_msg_hook = run_hooks && get_rewrite_run_commit_msg_hook(repo)?;
+
+        // Normally, we can determine the new `HEAD` OID by looking at the
+        // rewritten commits. However, if `HEAD` pointed to a commit that was
+        // skipped, then the rewritten OID is zero. In that case, we need to
+        // delete the branch (responsibility of the caller) and choose a
+        // different `HEAD` OID.
+        //
+        // Similarly, any branches pointing to a skipped commit are moved to the
+        // commit that it would have been applied on top of.
+        let head_oid = repo.get_head_info()?.oid;
+
+        let num_picks = rebase_plan
+            .commands
+            .iter()
+            .filter(|command| is_pick_command(command))
+            .count();
+        let drop_if_empty_oids: HashSet<NonZeroOid> = rebase_plan
+            .commands
+            .iter()
+            .filter_map(|command| match command {
+                RebaseCommand::DetectEmptyCommit { commit_oid } => Some(*commit_oid),
+                _ => None,
+            })
+            .collect();
+        let context = RebaseContext {
+            git_run_info,
+            options,
+            should_run_commit_msg_hook,
+            num_picks,
+            drop_if_empty_oids,
+        };
+
+        let (effects, progress) = effects.start_operation(OperationType::RebaseCommits);
+        let effects = &effects;
+        progress.notify_progress(0, num_picks);
+
+        // Independent groups of commands can be applied in parallel. However,
+        // the `commit-msg` hook is always run on the same message file, and
+        // the signing program may prompt for a passphrase, so neither can be
+        // run in parallel.
+        let groups = split_independent_commands(&rebase_plan.commands);
+        let is_parallel = groups.len() > 1
+            && !should_run_commit_msg_hook
+            && !get_rewrite_sign(repo)?
+            && get_num_jobs(repo)? > 1;
+        let results: Vec<eyre::Result<(RebaseState, Option<RebaseInMemoryResult>)>> = if is_parallel
+        {
+            let mut first_pick_indexes = Vec::new();
+            let mut num_preceding_picks = 0;
+            for commands in groups.iter() {
+                first_pick_indexes.push(num_preceding_picks);
+                num_preceding_picks += commands
+                    .iter()
+                    .filter(|command| is_pick_command(command))
+                    .count();
+            }
+
+            let pool = make_repo_thread_pool(repo)?;
+            let repo_path = repo.get_path();
+            let context = &context;
+            let progress = &progress;
+            pool.install(|| {
+                groups
+                    .par_iter()
+                    .zip(first_pick_indexes.into_par_iter())
+                    .map(|(commands, first_pick_index)| {
+                        with_worker_repo(repo_path, |repo| {
+                            let mut state = RebaseState::new(
+                                first_pick_index,
+                                rebase_plan.first_dest_oid,
+                                true,
+                            );
+                            let result = apply_rebase_commands(
+                                effects, progress, repo, context, commands, &mut state,
+                            )?;
+                            Ok((state, result))
+                        })
+                    })
+                    .collect()
+            })
+        } else {
+            let mut state = RebaseState::new(0, rebase_plan.first_dest_oid, false);
+            let result = apply_rebase_commands(
+                effects,
+                &progress,
+                repo,
+                &context,
+                &rebase_plan.commands,
+                &mut state,
+            );
+            vec![result.map(|result| (state, result))]
+        };
+
+        let mut rewritten_oids: Vec<(NonZeroOid, MaybeZeroOid)> = Vec::new();
+        let mut skipped_head_new_oid = None;
+        let mut skipped_commit_parent_oids: HashMap<NonZeroOid, NonZeroOid> = HashMap::new();
+        for result in results {
+            let (state, result) = result?;
+            let RebaseState {
+                i: _,
+                current_oid: _,
+                labels: _,
+                rewritten_oids: group_rewritten_oids,
+                skipped_commit_parent_oids: group_skipped_commit_parent_oids,
+                output_buffer,
+            } = state;
+            if let Some(output) = output_buffer {
+                write!(effects.get_output_stream(), "{}", output)?;
+            }
+            if let Some(result) = result {
+                return Ok(result);
+            }
+
+            rewritten_oids.extend(group_rewritten_oids);
+            for (skipped_oid, parent_oid) in group_skipped_commit_parent_oids {
+                if Some(skipped_oid) == head_oid {
+                    skipped_head_new_oid.get_or_insert(parent_oid);
+                }
+                skipped_commit_parent_oids.insert(skipped_oid, parent_oid);
+            }
+        }
 
         let new_head_oid: Option<NonZeroOid> = match head_oid {
             None => {
@@ -640,15 +1579,18 @@ mod in_memory {
         Ok(RebaseInMemoryResult::Succeeded {
             rewritten_oids,
             new_head_oid,
+            skipped_commit_parent_oids,
         })
     }
 
+    #[instrument]
     pub fn post_rebase_in_memory(
         effects: &Effects,
         git_run_info: &GitRunInfo,
         repo: &Repo,
         rewritten_oids: &[(NonZeroOid, MaybeZeroOid)],
         skipped_head_updated_oid: Option<NonZeroOid>,
+        skipped_commit_parent_oids: &HashMap<NonZeroOid, NonZeroOid>,
         options: &ExecuteRebasePlanOptions,
     ) -> eyre::Result<isize> {
         let ExecuteRebasePlanOptions {
@@ -658,6 +1600,7 @@ mod in_memory {
             force_in_memory: _,
             force_on_disk: _,
             resolve_merge_conflicts: _,
+            update_worktrees,
         } = options;
 
         // Note that if an OID has been mapped to multiple other OIDs, then the last
@@ -671,6 +1614,14 @@ mod in_memory {
             }
         }
 
+        // Find the other worktrees to update before moving any branches, since
+        // their `HEAD`s may be attached to those branches.
+        let affected_worktrees = if *update_worktrees {
+            find_affected_worktrees(repo, &rewritten_oids_map.keys().copied().collect())?
+        } else {
+            Vec::new()
+        };
+
         let head_info = repo.get_head_info()?;
         if head_info.oid.is_some() {
             // Avoid moving the branch which HEAD points to, or else the index will show
@@ -678,13 +1629,16 @@ mod in_memory {
             repo.detach_head(&head_info)?;
         }
 
-        move_branches(
+        let exit_code = move_branches(
             effects,
             git_run_info,
             repo,
             *event_tx_id,
-            &rewritten_oids_map,
+            &get_branch_rewritten_oids(&rewritten_oids_map, skipped_commit_parent_oids),
         )?;
+        if exit_code != 0 {
+            return Ok(exit_code);
+        }
 
         // Call the `post-rewrite` hook only after moving branches so that we don't
         // produce a spurious abandoned-branch warning.
@@ -711,23 +1665,44 @@ mod in_memory {
             &head_info,
             skipped_head_updated_oid,
         )?;
-        Ok(exit_code)
+        let worktrees_exit_code = check_out_rewritten_commits_in_worktrees(
+            effects,
+            git_run_info,
+            *event_tx_id,
+            &affected_worktrees,
+            &get_branch_rewritten_oids(&rewritten_oids_map, skipped_commit_parent_oids),
+        )?;
+        if exit_code != 0 {
+            Ok(exit_code)
+        } else {
+            Ok(worktrees_exit_code)
+        }
     }
 }
 
 mod on_disk {
+    use std::convert::TryInto;
     use std::ffi::OsStr;
     use std::fmt::Write;
 
     use eyre::Context;
+    use itertools::Itertools;
     use os_str_bytes::OsStrBytes;
-    use tracing::instrument;
+    use tracing::{instrument, warn};
 
     use crate::core::effects::{Effects, OperationType};
-    use crate::core::rewrite::plan::RebasePlan;
-    use crate::git::{GitRunInfo, Repo};
+    use crate::core::rewrite::plan::{RebaseCommand, RebasePlan};
+    use crate::core::rewrite::rewrite_hooks::UPDATE_REFS_FILE_NAME;
+    use crate::git::{
+        GitRunInfo, GitRunStreamingOpts, GitVersion, MaybeZeroOid, NonZeroOid, Repo,
+        ResolvedReferenceInfo,
+    };
+
+    use super::{ExecuteRebasePlanOptions, REBASE_EVENT_TX_ID_FILE_NAME};
 
-    use super::ExecuteRebasePlanOptions;
+    /// The first version of Git which supports the `update-ref` rebase
+    /// command, as used by `git rebase --update-refs`.
+    const MIN_UPDATE_REFS_GIT_VERSION: GitVersion = GitVersion(2, 38, 0);
 
     pub enum Error {
         ChangedFilesInRepository,
@@ -743,11 +1718,12 @@ mod on_disk {
     ) -> eyre::Result<Result<(), Error>> {
         let ExecuteRebasePlanOptions {
             now: _,
-            event_tx_id: _,
+            event_tx_id,
             preserve_timestamps,
             force_in_memory: _,
             force_on_disk: _,
             resolve_merge_conflicts: _,
+            update_worktrees: _,
         } = options;
 
         let (effects, _progress) = effects.start_operation(OperationType::InitializeRebase);
@@ -834,13 +1810,27 @@ mod on_disk {
             },
         )?;
 
+        // Have Git keep track of where each branch should go, as with `git
+        // rebase --update-refs`, so that the branches travel with their
+        // commits even if the user edits the rebase plan while resolving a
+        // conflict. The branches themselves are moved in the `post-rewrite`
+        // hook.
+        let use_update_refs = match git_run_info.get_git_version(repo) {
+            Ok(version) => version >= MIN_UPDATE_REFS_GIT_VERSION,
+            Err(err) => {
+                warn!(?err, "Could not determine Git version");
+                false
+            }
+        };
+        let (todo_lines, update_refs) =
+            make_todo_lines(repo, rebase_plan, &head_info, use_update_refs)?;
+
         let todo_file_path = rebase_state_dir.join("git-rebase-todo");
         std::fs::write(
             &todo_file_path,
-            rebase_plan
-                .commands
+            todo_lines
                 .iter()
-                .map(|command| format!("{}\n", command.to_string()))
+                .map(|line| format!("{}\n", line))
                 .collect::<String>(),
         )
         .wrap_err_with(|| {
@@ -850,12 +1840,33 @@ mod on_disk {
             )
         })?;
 
+        if !update_refs.is_empty() {
+            // Git fills in the new OID for each branch when it executes the
+            // corresponding `update-ref` command.
+            let update_refs_file_path = rebase_state_dir.join(UPDATE_REFS_FILE_NAME);
+            std::fs::write(
+                &update_refs_file_path,
+                update_refs
+                    .iter()
+                    .map(|(ref_name, old_oid)| {
+                        format!("{}\n{}\n{}\n", ref_name, old_oid, MaybeZeroOid::Zero)
+                    })
+                    .collect::<String>(),
+            )
+            .wrap_err_with(|| format!("Writing `update-refs` to: {:?}", &update_refs_file_path))?;
+        }
+
         let end_file_path = rebase_state_dir.join("end");
-        std::fs::write(
-            end_file_path.as_path(),
-            format!("{}\n", rebase_plan.commands.len()),
-        )
-        .wrap_err_with(|| format!("Writing `end` to: {:?}", end_file_path.as_path()))?;
+        std::fs::write(end_file_path.as_path(), format!("{}\n", todo_lines.len()))
+            .wrap_err_with(|| format!("Writing `end` to: {:?}", end_file_path.as_path()))?;
+
+        let event_tx_id_file_path = rebase_state_dir.join(REBASE_EVENT_TX_ID_FILE_NAME);
+        std::fs::write(&event_tx_id_file_path, event_tx_id.to_string()).wrap_err_with(|| {
+            format!(
+                "Writing event transaction ID to: {:?}",
+                &event_tx_id_file_path
+            )
+        })?;
 
         // Corresponds to the `--empty=keep` flag. We'll drop the commits later once
         // we find out that they're empty.
@@ -887,6 +1898,94 @@ mod on_disk {
         Ok(Ok(()))
     }
 
+    /// The name of a branch to be moved with `update-ref`, and the commit which
+    /// it currently points to.
+    type UpdateRef = (String, NonZeroOid);
+
+    /// Render the rebase plan as lines of `git-rebase-todo`. If
+    /// `use_update_refs` is set, then an `update-ref` command is added for each
+    /// branch pointing to a commit being rewritten, after the last command
+    /// which applies that commit, and the branch names and their current OIDs
+    /// are returned.
+    ///
+    /// The currently checked-out branch is left for Git to move when the
+    /// rebase finishes, as `git rebase --update-refs` would.
+    fn make_todo_lines(
+        repo: &Repo,
+        rebase_plan: &RebasePlan,
+        head_info: &ResolvedReferenceInfo,
+        use_update_refs: bool,
+    ) -> eyre::Result<(Vec<String>, Vec<UpdateRef>)> {
+        if !use_update_refs {
+            let lines = rebase_plan
+                .commands
+                .iter()
+                .map(|command| command.to_string())
+                .collect();
+            return Ok((lines, Vec::new()));
+        }
+
+        let branch_oid_to_names = repo.get_branch_oid_to_names()?;
+        let head_reference_name = head_info.reference_name.as_deref();
+        let mut lines = Vec::new();
+        let mut update_refs = Vec::new();
+        let mut pending_update_refs: Vec<UpdateRef> = Vec::new();
+        for command in rebase_plan.commands.iter() {
+            match command {
+                RebaseCommand::Reset { .. }
+                | RebaseCommand::Pick { .. }
+                | RebaseCommand::Merge { .. } => {
+                    // The rebase head is about to move away from the commit
+                    // which the pending branches should point to.
+                    for (ref_name, old_oid) in pending_update_refs.drain(..) {
+                        lines.push(format!("update-ref {}", ref_name));
+                        update_refs.push((ref_name, old_oid));
+                    }
+                }
+                RebaseCommand::CreateLabel { .. }
+                | RebaseCommand::RegisterExtraPostRewriteHook
+                | RebaseCommand::DetectEmptyCommit { .. }
+                | RebaseCommand::SkipUpstreamAppliedCommit { .. }
+                | RebaseCommand::DropMergeCommit { .. }
+                | RebaseCommand::Fixup { .. } => {}
+            }
+            lines.push(command.to_string());
+
+            // Branches pointing to skipped, dropped, or folded commits end up
+            // at the rebase head, just like the branches of applied commits.
+            let commit_oid = match command {
+                RebaseCommand::Pick { commit_oid }
+                | RebaseCommand::Merge { commit_oid, .. }
+                | RebaseCommand::SkipUpstreamAppliedCommit { commit_oid }
+                | RebaseCommand::DropMergeCommit { commit_oid }
+                | RebaseCommand::Fixup { commit_oid, .. } => *commit_oid,
+                RebaseCommand::CreateLabel { .. }
+                | RebaseCommand::Reset { .. }
+                | RebaseCommand::RegisterExtraPostRewriteHook
+                | RebaseCommand::DetectEmptyCommit { .. } => continue,
+            };
+            let names = match branch_oid_to_names.get(&commit_oid) {
+                Some(names) => names,
+                None => continue,
+            };
+            for name in names.iter().sorted() {
+                if Some(name.as_os_str()) == head_reference_name {
+                    continue;
+                }
+                // Non-UTF-8 branch names can't be written to the rebase plan,
+                // so they're moved in the `post-rewrite` hook instead.
+                if let Some(name) = name.to_str() {
+                    pending_update_refs.push((name.to_string(), commit_oid));
+                }
+            }
+        }
+        for (ref_name, old_oid) in pending_update_refs {
+            lines.push(format!("update-ref {}", ref_name));
+            update_refs.push((ref_name, old_oid));
+        }
+        Ok((lines, update_refs))
+    }
+
     /// Rebase on-disk. We don't use `git2`'s `Rebase` machinery because it ends up
     /// being too slow.
     ///
@@ -908,6 +2007,7 @@ mod on_disk {
             force_in_memory: _,
             force_on_disk: _,
             resolve_merge_conflicts: _,
+            update_worktrees: _,
         } = options;
 
         match write_rebase_state_to_disk(effects, git_run_info, repo, rebase_plan, options)? {
@@ -919,8 +2019,21 @@ mod on_disk {
             effects.get_output_stream(),
             "Calling Git for on-disk rebase..."
         )?;
-        let exit_code = git_run_info.run(effects, Some(*event_tx_id), &["rebase", "--continue"])?;
-        Ok(Ok(exit_code))
+        let (effects, _progress) = effects.start_operation(OperationType::RebaseCommits);
+        let result = git_run_info.run_streaming(
+            &effects,
+            repo,
+            Some(*event_tx_id),
+            // The rebase should only update the submodule pointers, and not
+            // check out the submodules themselves, even if the user has set
+            // `submodule.recurse`.
+            &["-c", "submodule.recurse=false", "rebase", "--continue"],
+            GitRunStreamingOpts {
+                treat_git_failure_as_error: false,
+                ..Default::default()
+            },
+        )?;
+        Ok(Ok(result.exit_code.try_into()?))
     }
 }
 
@@ -947,6 +2060,11 @@ pub struct ExecuteRebasePlanOptions {
     /// Whether or not an attempt should be made to resolve merge conflicts,
     /// rather than failing-fast.
     pub resolve_merge_conflicts: bool,
+
+    /// If `true`, check out the rewritten commits in any other worktrees
+    /// which have the original commits checked out. Otherwise, refuse to
+    /// rewrite commits which are checked out in other worktrees.
+    pub update_worktrees: bool,
 }
 
 /// The result of executing a rebase plan.
@@ -969,8 +2087,33 @@ pub enum ExecuteRebasePlanResult {
     },
 }
 
+/// Predict whether executing the provided rebase plan would cause a merge
+/// conflict, by carrying out the rebase in-memory without updating any
+/// references or the working copy. No hooks are run.
+///
+/// This is only a prediction: it returns `None` if the rebase couldn't be
+/// carried out in-memory for some other reason (such as an octopus merge
+/// commit), and an on-disk rebase may resolve some conflicts differently.
+pub fn predict_merge_conflict(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    rebase_plan: &RebasePlan,
+    options: &ExecuteRebasePlanOptions,
+) -> eyre::Result<Option<MergeConflictInfo>> {
+    use in_memory::*;
+    let effects = effects.suppress_output();
+    match rebase_in_memory(&effects, git_run_info, repo, rebase_plan, options, false)? {
+        RebaseInMemoryResult::MergeConflict(merge_conflict) => Ok(Some(merge_conflict)),
+        RebaseInMemoryResult::Succeeded { .. }
+        | RebaseInMemoryResult::CannotRebaseMergeCommit { .. }
+        | RebaseInMemoryResult::CommitMsgHookFailed { .. } => Ok(None),
+    }
+}
+
 /// Execute the provided rebase plan. Returns the exit status (zero indicates
 /// success).
+#[instrument]
 pub fn execute_rebase_plan(
     effects: &Effects,
     git_run_info: &GitRunInfo,
@@ -985,8 +2128,29 @@ pub fn execute_rebase_plan(
         force_in_memory,
         force_on_disk,
         resolve_merge_conflicts,
+        update_worktrees,
     } = options;
 
+    let affected_worktrees = find_affected_worktrees(
+        repo,
+        &rebase_plan
+            .get_rewritten_commit_oids()
+            .into_iter()
+            .collect(),
+    )?;
+    if !affected_worktrees.is_empty()
+        && !check_affected_worktrees(
+            effects,
+            git_run_info,
+            repo,
+            &affected_worktrees,
+            *update_worktrees,
+            *force_on_disk,
+        )?
+    {
+        return Ok(ExecuteRebasePlanResult::Failed { exit_code: 1 });
+    }
+
     if !force_on_disk {
         use in_memory::*;
         writeln!(
@@ -994,27 +2158,32 @@ pub fn execute_rebase_plan(
             "Attempting rebase in-memory..."
         )?;
 
-        match rebase_in_memory(effects, repo, rebase_plan, options)? {
+        match rebase_in_memory(effects, git_run_info, repo, rebase_plan, options, true)? {
             RebaseInMemoryResult::Succeeded {
                 rewritten_oids,
                 new_head_oid,
+                skipped_commit_parent_oids,
             } => {
-                post_rebase_in_memory(
+                let exit_code = post_rebase_in_memory(
                     effects,
                     git_run_info,
                     repo,
                     &rewritten_oids,
                     new_head_oid,
+                    &skipped_commit_parent_oids,
                     options,
                 )?;
-                writeln!(effects.get_output_stream(), "In-memory rebase succeeded.")?;
+                if exit_code != 0 {
+                    return Ok(ExecuteRebasePlanResult::Failed { exit_code });
+                }
+                writeln!(effects.get_result_stream(), "In-memory rebase succeeded.")?;
                 return Ok(ExecuteRebasePlanResult::Succeeded);
             }
 
             RebaseInMemoryResult::CannotRebaseMergeCommit { commit_oid } => {
                 writeln!(
                     effects.get_output_stream(),
-                    "Merge commits currently can't be rebased in-memory."
+                    "Merge commits with more than two parents currently can't be re-merged in-memory."
                 )?;
                 writeln!(
                     effects.get_output_stream(),
@@ -1026,7 +2195,25 @@ pub fn execute_rebase_plan(
                 )?;
             }
 
+            RebaseInMemoryResult::CommitMsgHookFailed { commit_oid } => {
+                // Retrying on-disk wouldn't run the hook, so don't.
+                writeln!(
+                    effects.get_result_stream(),
+                    "The commit-msg hook failed for commit: {}",
+                    printable_styled_string(
+                        effects.get_glyphs(),
+                        repo.friendly_describe_commit_from_oid(commit_oid)?
+                    )?,
+                )?;
+                writeln!(
+                    effects.get_result_stream(),
+                    "Aborting, since branchless.rewrite.runCommitMsgHook is set."
+                )?;
+                return Ok(ExecuteRebasePlanResult::Failed { exit_code: 1 });
+            }
+
             RebaseInMemoryResult::MergeConflict(merge_conflict) => {
+                merge_conflict.set_result_field(effects);
                 if !resolve_merge_conflicts
                     // If an in-memory rebase was forced, don't suggest to the user
                     // that they can re-run with `--merge`, since that still won't
@@ -1059,10 +2246,16 @@ pub fn execute_rebase_plan(
         // again with an on-disk rebase.
         if *force_in_memory {
             writeln!(
-                effects.get_output_stream(),
+                effects.get_result_stream(),
                 "Aborting since an in-memory rebase was requested."
             )?;
             return Ok(ExecuteRebasePlanResult::Failed { exit_code: 1 });
+        } else if !affected_worktrees.is_empty() {
+            writeln!(
+                effects.get_result_stream(),
+                "Aborting, since other worktrees can only be updated after an in-memory rebase."
+            )?;
+            return Ok(ExecuteRebasePlanResult::Failed { exit_code: 1 });
         } else {
             writeln!(effects.get_output_stream(), "Trying again on-disk...")?;
         }
@@ -1075,7 +2268,7 @@ pub fn execute_rebase_plan(
             Ok(exit_code) => return Ok(ExecuteRebasePlanResult::Failed { exit_code }),
             Err(Error::ChangedFilesInRepository) => {
                 write!(
-                    effects.get_output_stream(),
+                    effects.get_result_stream(),
                     "\
 This operation would modify the working copy, but you have uncommitted changes
 in your working copy which might be overwritten as a result.
@@ -1086,12 +2279,12 @@ Commit your changes and then try again.
             }
             Err(Error::OperationAlreadyInProgress { operation_type }) => {
                 writeln!(
-                    effects.get_output_stream(),
+                    effects.get_result_stream(),
                     "A {} operation is already in progress.",
                     operation_type
                 )?;
                 writeln!(
-                    effects.get_output_stream(),
+                    effects.get_result_stream(),
                     "Run git {0} --continue or git {0} --abort to resolve it and proceed.",
                     operation_type
                 )?;
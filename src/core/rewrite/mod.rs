@@ -1,13 +1,17 @@
 //! Tools for editing the commit graph.
 
+mod autosquash;
+mod draft;
 mod evolve;
 mod execute;
 mod plan;
 pub mod rewrite_hooks;
 
+pub use draft::check_rewritten_commits_are_draft;
 pub use evolve::{find_abandoned_children, find_rewrite_target};
 pub use execute::{
-    execute_rebase_plan, move_branches, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
-    MergeConflictInfo,
+    check_out_updated_head, execute_rebase_plan, get_rebase_event_tx_id, move_branches,
+    move_head_to_rewritten_commit, predict_merge_conflict, ExecuteRebasePlanOptions,
+    ExecuteRebasePlanResult, MergeConflictInfo,
 };
-pub use plan::{BuildRebasePlanOptions, RebasePlanBuilder};
+pub use plan::{BuildRebasePlanOptions, RebasePlan, RebasePlanBuilder};
@@ -1,5 +1,5 @@
-use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
 use std::fmt::Write;
 use std::ops::Sub;
 use std::path::PathBuf;
@@ -7,17 +7,16 @@ use std::path::PathBuf;
 use chashmap::CHashMap;
 use eden_dag::DagAlgorithm;
 use itertools::Itertools;
-use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+use rayon::{prelude::*, ThreadPool};
 use tracing::{instrument, warn};
 
-use crate::core::dag::{commit_set_to_vec, CommitSet, Dag};
+use crate::core::dag::{commit_set_to_vec, CommitSet, CommitVertex, Dag};
 use crate::core::effects::{Effects, OperationType};
-use crate::core::formatting::printable_styled_string;
-use crate::git::{Commit, NonZeroOid, PatchId, Repo};
+use crate::core::formatting::{printable_styled_string, Pluralize};
+use crate::core::pool::{make_repo_thread_pool, with_worker_repo};
+use crate::git::{CategorizedReferenceName, Commit, NonZeroOid, PatchId, Repo};
 
-thread_local! {
-    static REPO: RefCell<Option<Repo>> = Default::default();
-}
+use super::autosquash::autosquash_rebase_commands;
 
 #[derive(Debug)]
 pub enum OidOrLabel {
@@ -70,6 +69,36 @@ pub enum RebaseCommand {
     /// The commit that would have been applied to the rebase head was already
     /// applied upstream. Skip it and record it in the `rewritten-list`.
     SkipUpstreamAppliedCommit { commit_oid: NonZeroOid },
+
+    /// The merge commit would have been re-created on top of the rebase head,
+    /// but merge commits were requested to be dropped. Skip it and record it in
+    /// the `rewritten-list`.
+    DropMergeCommit { commit_oid: NonZeroOid },
+
+    /// Apply the provided `fixup!`, `squash!`, or `amend!` commit on top of the
+    /// rebase head, which is the rewritten version of `target_oid`, and meld
+    /// the two into a single commit.
+    Fixup {
+        commit_oid: NonZeroOid,
+        target_oid: NonZeroOid,
+        kind: FixupKind,
+    },
+}
+
+/// How to combine a commit with the commit that it's folded into, as
+/// determined by the prefix of its summary line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FixupKind {
+    /// `fixup!`: keep the message of the target commit.
+    Fixup,
+
+    /// `squash!`: append the message of the fixup commit to the message of
+    /// the target commit.
+    Squash,
+
+    /// `amend!`: replace the message of the target commit with the message of
+    /// the fixup commit.
+    Amend,
 }
 
 /// Represents a sequence of commands that can be executed to carry out a rebase
@@ -112,7 +141,273 @@ impl ToString for RebaseCommand {
                     commit_oid
                 )
             }
+            RebaseCommand::DropMergeCommit { commit_oid } => {
+                format!("exec git branchless hook-drop-merge-commit {}", commit_oid)
+            }
+            RebaseCommand::Fixup {
+                commit_oid,
+                target_oid: _,
+                kind,
+            } => match kind {
+                FixupKind::Fixup => format!("fixup {}", commit_oid),
+                FixupKind::Squash => format!("squash {}", commit_oid),
+                FixupKind::Amend => format!("fixup -C {}", commit_oid),
+            },
+        }
+    }
+}
+
+impl RebasePlan {
+    /// Get the OIDs of the commits which would be rewritten (or skipped, and
+    /// therefore made obsolete) by executing this rebase plan.
+    pub fn get_rewritten_commit_oids(&self) -> Vec<NonZeroOid> {
+        self.commands
+            .iter()
+            .filter_map(|command| match command {
+                RebaseCommand::Pick { commit_oid }
+                | RebaseCommand::Merge {
+                    commit_oid,
+                    commits_to_merge: _,
+                }
+                | RebaseCommand::SkipUpstreamAppliedCommit { commit_oid }
+                | RebaseCommand::DropMergeCommit { commit_oid }
+                | RebaseCommand::Fixup { commit_oid, .. } => Some(*commit_oid),
+                RebaseCommand::CreateLabel { .. }
+                | RebaseCommand::Reset { .. }
+                | RebaseCommand::RegisterExtraPostRewriteHook
+                | RebaseCommand::DetectEmptyCommit { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Render the commits which would be rewritten by executing this rebase
+    /// plan as a tree, one line per commit. Each existing commit which would
+    /// become a new parent is listed unindented, followed by the commits which
+    /// would be applied on top of it.
+    pub fn describe_rewritten_commits(
+        &self,
+        effects: &Effects,
+        repo: &Repo,
+    ) -> eyre::Result<Vec<String>> {
+        #[derive(Clone, Copy)]
+        enum Parent {
+            Existing(NonZeroOid),
+            Rewritten(NonZeroOid),
+        }
+
+        let glyphs = effects.get_glyphs();
+        let describe_commit = |oid: NonZeroOid| -> eyre::Result<String> {
+            printable_styled_string(glyphs, repo.friendly_describe_commit_from_oid(oid)?)
+        };
+
+        // First, determine the new parent of each rewritten commit by
+        // simulating the rebase head as the commands are applied.
+        let mut entries: Vec<(&RebaseCommand, NonZeroOid, Parent)> = Vec::new();
+        let mut labels: HashMap<&str, Parent> = HashMap::new();
+        let mut current = Parent::Existing(self.first_dest_oid);
+        for command in self.commands.iter() {
+            match command {
+                RebaseCommand::CreateLabel { label_name } => {
+                    labels.insert(label_name.as_str(), current);
+                }
+                RebaseCommand::Reset {
+                    target: OidOrLabel::Oid(oid),
+                } => {
+                    current = Parent::Existing(*oid);
+                }
+                RebaseCommand::Reset {
+                    target: OidOrLabel::Label(label_name),
+                } => {
+                    if let Some(parent) = labels.get(label_name.as_str()) {
+                        current = *parent;
+                    }
+                }
+                RebaseCommand::RegisterExtraPostRewriteHook
+                | RebaseCommand::DetectEmptyCommit { commit_oid: _ } => {}
+                RebaseCommand::SkipUpstreamAppliedCommit { commit_oid }
+                | RebaseCommand::DropMergeCommit { commit_oid }
+                | RebaseCommand::Fixup { commit_oid, .. } => {
+                    // The next commit is applied on top of the same parent.
+                    entries.push((command, *commit_oid, current));
+                }
+                RebaseCommand::Pick { commit_oid }
+                | RebaseCommand::Merge {
+                    commit_oid,
+                    commits_to_merge: _,
+                } => {
+                    entries.push((command, *commit_oid, current));
+                    current = Parent::Rewritten(*commit_oid);
+                }
+            }
+        }
+
+        // Then render the commits as a tree. Commits are only indented
+        // further where the tree branches, so that long stacks stay readable.
+        let mut num_children: HashMap<NonZeroOid, usize> = HashMap::new();
+        for (command, _commit_oid, parent) in entries.iter() {
+            if let RebaseCommand::Fixup { .. } = command {
+                // Folded commits are rendered below their targets, but don't
+                // make the tree branch.
+                continue;
+            }
+            if let Parent::Rewritten(parent_oid) = parent {
+                *num_children.entry(*parent_oid).or_default() += 1;
+            }
+        }
+        let mut lines: Vec<String> = Vec::new();
+        let mut depths: HashMap<NonZeroOid, usize> = HashMap::new();
+        let mut last_printed_dest_oid = None;
+        for (command, commit_oid, parent) in entries.iter() {
+            let depth = match parent {
+                Parent::Existing(dest_oid) => {
+                    if last_printed_dest_oid != Some(*dest_oid) {
+                        lines.push(describe_commit(*dest_oid)?);
+                        last_printed_dest_oid = Some(*dest_oid);
+                    }
+                    0
+                }
+                Parent::Rewritten(parent_oid) => {
+                    let parent_depth = depths.get(parent_oid).copied().unwrap_or_default();
+                    if num_children.get(parent_oid).copied().unwrap_or_default() > 1 {
+                        parent_depth + 1
+                    } else {
+                        parent_depth
+                    }
+                }
+            };
+            let suffix = match command {
+                RebaseCommand::SkipUpstreamAppliedCommit { .. } => {
+                    " (already applied upstream, will be skipped)".to_string()
+                }
+                RebaseCommand::Merge { .. } => " (merge commit)".to_string(),
+                RebaseCommand::DropMergeCommit { .. } => {
+                    " (merge commit, will be dropped)".to_string()
+                }
+                RebaseCommand::Fixup { target_oid, .. } => {
+                    format!(" (will be folded into {})", &target_oid.to_string()[..8])
+                }
+                _ => String::new(),
+            };
+            lines.push(format!(
+                "{}{} {}{}",
+                "  ".repeat(depth + 1),
+                glyphs.bullet_point,
+                describe_commit(*commit_oid)?,
+                suffix
+            ));
+            depths.insert(*commit_oid, depth);
+        }
+        Ok(lines)
+    }
+
+    /// Describe what executing this rebase plan would do, without executing
+    /// it. The rewritten commits are rendered as a tree, followed by any
+    /// branches which would be moved and whether `HEAD` would be moved.
+    ///
+    /// Also reports problems which would stop the plan from being carried out
+    /// as requested. Returns `false` if there were any such problems.
+    pub fn describe_dry_run(
+        &self,
+        effects: &Effects,
+        repo: &Repo,
+        dag: &Dag,
+        force_in_memory: bool,
+    ) -> eyre::Result<bool> {
+        let glyphs = effects.get_glyphs();
+        let mut output = effects.get_output_stream();
+        let describe_commit = |oid: NonZeroOid| -> eyre::Result<String> {
+            printable_styled_string(glyphs, repo.friendly_describe_commit_from_oid(oid)?)
+        };
+
+        let lines = self.describe_rewritten_commits(effects, repo)?;
+        let rewritten_oids = self.get_rewritten_commit_oids();
+        let octopus_merge_commit_oids = self
+            .commands
+            .iter()
+            .filter_map(|command| match command {
+                RebaseCommand::Merge {
+                    commit_oid,
+                    commits_to_merge,
+                } if commits_to_merge.len() > 1 => Some(*commit_oid),
+                _ => None,
+            })
+            .collect_vec();
+
+        writeln!(
+            output,
+            "This operation would rewrite {}:",
+            Pluralize {
+                amount: rewritten_oids.len().try_into()?,
+                singular: "commit",
+                plural: "commits",
+            }
+            .to_string()
+        )?;
+        for line in lines {
+            writeln!(output, "{}", line)?;
+        }
+
+        let branch_oid_to_names = repo.get_branch_oid_to_names()?;
+        let branch_names: Vec<String> = rewritten_oids
+            .iter()
+            .filter_map(|oid| branch_oid_to_names.get(oid))
+            .flatten()
+            .map(|name| -> eyre::Result<String> {
+                let name = CategorizedReferenceName::new(name).remove_prefix()?;
+                Ok(name.to_string_lossy().into_owned())
+            })
+            .collect::<eyre::Result<_>>()?;
+        if !branch_names.is_empty() {
+            writeln!(
+                output,
+                "This operation would move {}: {}",
+                Pluralize {
+                    amount: branch_names.len().try_into()?,
+                    singular: "branch",
+                    plural: "branches",
+                }
+                .to_string(),
+                branch_names.iter().sorted().join(", ")
+            )?;
+        }
+
+        if let Some(head_oid) = repo.get_head_info()?.oid {
+            if rewritten_oids.contains(&head_oid) {
+                writeln!(
+                    output,
+                    "This operation would check out the rewritten version of HEAD: {}",
+                    describe_commit(head_oid)?
+                )?;
+            }
+        }
+
+        let mut problems: Vec<String> = Vec::new();
+        let public_commits = dag.query_public_commits()?;
+        for oid in rewritten_oids.iter() {
+            if public_commits.contains(&CommitVertex::from(*oid))? {
+                problems.push(format!(
+                    "The commit is on the main branch, but would be rewritten: {}",
+                    describe_commit(*oid)?
+                ));
+            }
+        }
+        if force_in_memory {
+            for oid in octopus_merge_commit_oids.iter() {
+                problems.push(format!(
+                    "Octopus merge commits might not be able to be rebased in-memory: {}",
+                    describe_commit(*oid)?
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            writeln!(output, "This operation would encounter problems:")?;
+            for problem in problems.iter() {
+                writeln!(output, "{} {}", glyphs.bullet_point, problem)?;
+            }
         }
+
+        writeln!(output, "This was a dry run, so no changes were made.")?;
+        Ok(problems.is_empty())
     }
 }
 
@@ -143,6 +438,12 @@ struct BuildState {
     /// to hit the same merge commit).
     merge_commit_parent_labels: HashMap<NonZeroOid, String>,
 
+    /// Copy of `drop_merges` in `BuildRebasePlanOptions`.
+    drop_merges: bool,
+
+    /// Copy of `keep_empty` in `BuildRebasePlanOptions`.
+    keep_empty: bool,
+
     /// Cache mapping from commit OID to the paths changed in the diff for that
     /// commit. The value is `None` if the commit doesn't have an associated
     /// diff (i.e. is a merge commit).
@@ -180,6 +481,18 @@ pub struct BuildRebasePlanOptions {
     /// patch IDs in the to-be-rebased commits. Commits which have patch IDs
     /// which are already upstream are skipped.
     pub detect_duplicate_commits_via_patch_id: bool,
+
+    /// Drop merge commits instead of re-creating them, so that only the
+    /// first-parent history of the moved commits is replayed.
+    pub drop_merges: bool,
+
+    /// Keep commits which become empty after being rewritten, rather than
+    /// skipping them.
+    pub keep_empty: bool,
+
+    /// Fold `fixup!`, `squash!`, and `amend!` commits into the commits which
+    /// they target, as `git rebase --autosquash` would.
+    pub autosquash: bool,
 }
 
 /// An error caused when attempting to build a rebase plan.
@@ -198,7 +511,7 @@ impl BuildRebasePlanError {
         match self {
             BuildRebasePlanError::ConstraintCycle { cycle_oids } => {
                 writeln!(
-                    effects.get_output_stream(),
+                    effects.get_result_stream(),
                     "This operation failed because it would introduce a cycle:"
                 )?;
 
@@ -221,7 +534,7 @@ impl BuildRebasePlanError {
                         (glyphs.cycle_vertical_line, " ", " ")
                     };
                     writeln!(
-                        effects.get_output_stream(),
+                        effects.get_result_stream(),
                         "{}{}{} {}",
                         char1,
                         char2,
@@ -292,20 +605,57 @@ impl<'repo> RebasePlanBuilder<'repo> {
                     commit_oid: current_commit.get_oid(),
                 });
             } else if current_commit.get_parent_count() > 1 {
-                // This is a merge commit. We need to make sure that all parent
-                // commits have been applied, and only then proceed with
-                // applying this commit. Note that parent commits may or may not
-                // be part of the set of commits to rebase (i.e. may or may not
-                // be mentioned in the constraints).
-                let commits_to_merge: Option<Vec<OidOrLabel>> = current_commit
-                    .get_parent_oids()
-                    .into_iter()
-                    .filter(|parent_oid| {
-                        // Don't include the parent which was the previous HEAD
-                        // commit, since we need to merge into that commit.
-                        *parent_oid != previous_head_oid
-                    })
-                    .map(|parent_oid| -> Option<OidOrLabel> {
+                // This is a merge commit.
+                let parent_oids = current_commit.get_parent_oids();
+
+                // Determine which parent of the merge commit the rebase head
+                // stands in for. If we arrived here from one of the parents,
+                // then it's that parent. Otherwise, the merge commit itself is
+                // being moved onto a new destination, in which case we replace
+                // the parent which was rewritten (as when restacking), or else
+                // the first parent.
+                let replaced_parent_oid = if parent_oids.contains(&previous_head_oid) {
+                    previous_head_oid
+                } else {
+                    let mut obsolete_parent_oid = None;
+                    for parent_oid in parent_oids.iter() {
+                        if self
+                            .dag
+                            .obsolete_commits
+                            .contains(&CommitVertex::from(*parent_oid))?
+                        {
+                            obsolete_parent_oid = Some(*parent_oid);
+                            break;
+                        }
+                    }
+                    obsolete_parent_oid.unwrap_or(parent_oids[0])
+                };
+
+                if state.drop_merges {
+                    // Only replay the merge commit's children once, when
+                    // arriving from the first of its parents which is being
+                    // moved (if any).
+                    let first_moved_parent_oid = parent_oids
+                        .iter()
+                        .find(|parent_oid| state.commits_to_move.contains(parent_oid));
+                    if let Some(first_moved_parent_oid) = first_moved_parent_oid {
+                        if replaced_parent_oid == previous_head_oid
+                            && *first_moved_parent_oid != previous_head_oid
+                        {
+                            return Ok(acc);
+                        }
+                    }
+
+                    acc.push(RebaseCommand::DropMergeCommit {
+                        commit_oid: current_commit.get_oid(),
+                    });
+                } else {
+                    // We need to make sure that all parent commits have been
+                    // applied, and only then proceed with applying this
+                    // commit. Note that parent commits may or may not be part
+                    // of the set of commits to rebase (i.e. may or may not be
+                    // mentioned in the constraints).
+                    let resolve_parent = |parent_oid: NonZeroOid| -> Option<OidOrLabel> {
                         let does_parent_commit_need_rebase =
                             state.commits_to_move.contains(&parent_oid);
                         if does_parent_commit_need_rebase {
@@ -320,26 +670,49 @@ impl<'repo> RebasePlanBuilder<'repo> {
                             state
                                 .merge_commit_parent_labels
                                 .get(&parent_oid)
-                                .map(|parent_oid| OidOrLabel::Label(parent_oid.clone()))
+                                .map(|label_name| OidOrLabel::Label(label_name.clone()))
                         } else {
                             // This parent commit was not supposed to be
                             // rebased, so its OID won't change and we can
                             // address it OID directly.
                             Some(OidOrLabel::Oid(parent_oid))
                         }
-                    })
-                    .collect();
-
-                if let Some(commits_to_merge) = commits_to_merge {
-                    // All parents have been committed.
-                    acc.push(RebaseCommand::Merge {
-                        commit_oid: current_commit.get_oid(),
-                        commits_to_merge,
-                    });
-                } else {
-                    // Wait for the caller to come back to this commit
-                    // later and then proceed to any child commits.
-                    return Ok(acc);
+                    };
+                    let parent_targets: Option<Vec<OidOrLabel>> = parent_oids
+                        .iter()
+                        .map(|parent_oid| {
+                            if *parent_oid == replaced_parent_oid {
+                                resolve_parent(previous_head_oid)
+                            } else {
+                                resolve_parent(*parent_oid)
+                            }
+                        })
+                        .collect();
+
+                    match parent_targets {
+                        Some(parent_targets) => {
+                            // All parents have been committed. The merge is
+                            // always made into the rebase head, so make sure
+                            // that it's at the first parent in order to
+                            // preserve the order of the parents.
+                            let mut parent_targets = parent_targets.into_iter();
+                            let first_parent_target = parent_targets.next().unwrap();
+                            if parent_oids[0] != replaced_parent_oid {
+                                acc.push(RebaseCommand::Reset {
+                                    target: first_parent_target,
+                                });
+                            }
+                            acc.push(RebaseCommand::Merge {
+                                commit_oid: current_commit.get_oid(),
+                                commits_to_merge: parent_targets.collect(),
+                            });
+                        }
+                        None => {
+                            // Wait for the caller to come back to this commit
+                            // later and then proceed to any child commits.
+                            return Ok(acc);
+                        }
+                    }
                 }
             } else {
                 // Normal one-parent commit (or a zero-parent commit?), just
@@ -347,9 +720,14 @@ impl<'repo> RebasePlanBuilder<'repo> {
                 acc.push(RebaseCommand::Pick {
                     commit_oid: current_commit.get_oid(),
                 });
-                acc.push(RebaseCommand::DetectEmptyCommit {
-                    commit_oid: current_commit.get_oid(),
-                });
+                // Commits which were empty to begin with were presumably
+                // created that way on purpose, so only skip commits which
+                // become empty as a result of the rebase.
+                if !state.keep_empty && !current_commit.is_empty() {
+                    acc.push(RebaseCommand::DetectEmptyCommit {
+                        commit_oid: current_commit.get_oid(),
+                    });
+                }
             }
             acc
         };
@@ -443,6 +821,12 @@ impl<'repo> RebasePlanBuilder<'repo> {
         Ok(())
     }
 
+    fn is_explicitly_moved(&self, oid: NonZeroOid) -> bool {
+        self.initial_constraints
+            .values()
+            .any(|child_oids| child_oids.contains(&oid))
+    }
+
     #[instrument]
     fn collect_descendants(
         &self,
@@ -457,6 +841,15 @@ impl<'repo> RebasePlanBuilder<'repo> {
             .intersection(visible_commits);
         let children_oids = commit_set_to_vec(&children_oids)?;
         for child_oid in children_oids {
+            if self.is_explicitly_moved(child_oid) {
+                // This child was given its own destination via
+                // `move_subtree`, so it no longer follows its original
+                // parent. This is what lets a plan move a commit's
+                // descendants elsewhere before moving the commit itself
+                // (such as when splicing commits into the middle of a
+                // stack).
+                continue;
+            }
             acc.push(Constraint {
                 parent_oid: current_oid,
                 child_oid,
@@ -472,15 +865,7 @@ impl<'repo> RebasePlanBuilder<'repo> {
     /// want to create, not just a list of constraints.
     fn add_descendant_constraints(&self, state: &mut BuildState) -> eyre::Result<()> {
         let all_descendants_of_constrained_nodes = {
-            let public_commits = self.dag.query_public_commits()?;
-            let active_heads = self.dag.query_active_heads(
-                &public_commits,
-                &self
-                    .dag
-                    .observed_commits
-                    .difference(&self.dag.obsolete_commits),
-            )?;
-            let visible_commits = self.dag.query().ancestors(active_heads)?;
+            let visible_commits = self.dag.query_visible_commits()?;
 
             let mut acc = Vec::new();
             for parent_oid in state.constraints.values().flatten().cloned() {
@@ -585,12 +970,17 @@ impl<'repo> RebasePlanBuilder<'repo> {
             dump_rebase_constraints,
             dump_rebase_plan,
             detect_duplicate_commits_via_patch_id,
+            drop_merges,
+            keep_empty,
+            autosquash,
         } = options;
         let mut state = BuildState {
             constraints: self.initial_constraints.clone(),
             commits_to_move: Default::default(), // filled in by `add_descendant_constraints`
             used_labels: Default::default(),
             merge_commit_parent_labels: Default::default(),
+            drop_merges: *drop_merges,
+            keep_empty: *keep_empty,
             touched_paths_cache: Default::default(),
         };
 
@@ -618,6 +1008,12 @@ impl<'repo> RebasePlanBuilder<'repo> {
             return Ok(Err(err));
         }
 
+        // Computing patch IDs requires the contents of every upstream commit,
+        // which would have to be fetched one object at a time from the
+        // promisor remote in a partial clone.
+        let detect_duplicate_commits_via_patch_id =
+            *detect_duplicate_commits_via_patch_id && !self.repo.is_partial_clone()?;
+
         let roots = self.find_roots(&state);
         let mut acc = vec![RebaseCommand::RegisterExtraPostRewriteHook];
         let mut first_dest_oid = None;
@@ -631,7 +1027,7 @@ impl<'repo> RebasePlanBuilder<'repo> {
                 target: OidOrLabel::Oid(parent_oid),
             });
 
-            let upstream_patch_ids = if *detect_duplicate_commits_via_patch_id {
+            let upstream_patch_ids = if detect_duplicate_commits_via_patch_id {
                 let (effects, _progress) =
                     effects.start_operation(OperationType::DetectDuplicateCommits);
                 self.get_upstream_patch_ids(&effects, &mut state, child_oid, parent_oid)?
@@ -648,6 +1044,11 @@ impl<'repo> RebasePlanBuilder<'repo> {
             )?;
         }
 
+        let acc = if *autosquash {
+            autosquash_rebase_commands(&effects, self.repo, acc)?
+        } else {
+            acc
+        };
         Self::check_all_commits_included_in_rebase_plan(&state, acc.as_slice());
 
         let rebase_plan = first_dest_oid.map(|first_dest_oid| RebasePlan {
@@ -678,7 +1079,9 @@ impl<'repo> RebasePlanBuilder<'repo> {
                     commit_oid,
                     commits_to_merge: _,
                 }
-                | RebaseCommand::SkipUpstreamAppliedCommit { commit_oid } => Some(*commit_oid),
+                | RebaseCommand::SkipUpstreamAppliedCommit { commit_oid }
+                | RebaseCommand::DropMergeCommit { commit_oid }
+                | RebaseCommand::Fixup { commit_oid, .. } => Some(*commit_oid),
             })
             .collect();
         let missing_commit_oids: HashSet<NonZeroOid> = state
@@ -725,7 +1128,7 @@ impl<'repo> RebasePlanBuilder<'repo> {
             Some(path) => path,
         };
 
-        let pool = self.make_pool(self.repo)?;
+        let pool = make_repo_thread_pool(self.repo)?;
 
         let path = {
             let touched_commits = state
@@ -748,14 +1151,12 @@ impl<'repo> RebasePlanBuilder<'repo> {
                 .into_iter()
                 .map(|commit| commit.get_oid())
                 .collect_vec();
-
+            let repo_path = self.repo.get_path();
             pool.install(|| {
                 path_oids
                     .into_par_iter()
                     .map(|commit_oid| -> eyre::Result<Option<PatchId>> {
-                        REPO.with(|repo| {
-                            let repo = repo.borrow();
-                            let repo = repo.as_ref().expect("Could not get thread-local repo");
+                        with_worker_repo(repo_path, |repo| {
                             let commit = match repo.find_commit(commit_oid)? {
                                 Some(commit) => commit,
                                 None => return Ok(None),
@@ -772,24 +1173,6 @@ impl<'repo> RebasePlanBuilder<'repo> {
         Ok(result)
     }
 
-    #[instrument]
-    fn make_pool(&self, repo: &Repo) -> eyre::Result<ThreadPool> {
-        let repo_path = repo.get_path().to_owned();
-        let pool = ThreadPoolBuilder::new()
-            .start_handler(move |_index| {
-                REPO.with(|thread_repo| -> eyre::Result<()> {
-                    let mut thread_repo = thread_repo.borrow_mut();
-                    if thread_repo.is_none() {
-                        *thread_repo = Some(Repo::from_dir(&repo_path)?);
-                    }
-                    Ok(())
-                })
-                .expect("Could not clone repo for thread");
-            })
-            .build()?;
-        Ok(pool)
-    }
-
     fn filter_path_to_merge_base_commits(
         &self,
         effects: &Effects,
@@ -815,6 +1198,7 @@ impl<'repo> RebasePlanBuilder<'repo> {
                 .map(|commit| commit.get_oid())
                 .collect_vec();
             let touched_paths_cache = &state.touched_paths_cache;
+            let repo_path = self.repo.get_path();
             pool.install(|| {
                 path.into_par_iter()
                     .map(|commit_oid| {
@@ -829,10 +1213,7 @@ impl<'repo> RebasePlanBuilder<'repo> {
                             }
                         }
 
-                        REPO.with(|repo| {
-                            let repo = repo.borrow();
-                            let repo = repo.as_ref().expect("Could not get thread-local repo");
-
+                        with_worker_repo(repo_path, |repo| {
                             let commit = match repo.find_commit(commit_oid)? {
                                 Some(commit) => commit,
                                 None => return Ok(None),
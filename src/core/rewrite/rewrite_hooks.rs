@@ -1,5 +1,6 @@
 //! Hooks used to have Git call back into `git-branchless` for various functionality.
 
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::ffi::{OsStr, OsString};
@@ -15,6 +16,7 @@ use itertools::Itertools;
 use tempfile::NamedTempFile;
 use tracing::instrument;
 
+use crate::commands::gc::mark_commit_reachable;
 use crate::core::config::{get_restack_warn_abandoned, RESTACK_WARN_ABANDONED_CONFIG_KEY};
 use crate::core::dag::Dag;
 use crate::core::effects::Effects;
@@ -24,7 +26,7 @@ use crate::git::{
     CategorizedReferenceName, GitRunInfo, MaybeZeroOid, NonZeroOid, Repo, ResolvedReferenceInfo,
 };
 
-use super::execute::check_out_updated_head;
+use super::execute::{check_out_updated_head, get_branch_rewritten_oids};
 use super::{find_abandoned_children, move_branches};
 
 #[instrument(skip(stream))]
@@ -36,6 +38,9 @@ fn read_rewritten_list_entries(
     for line in reader.lines() {
         let line = line?;
         let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
         match *line.split(' ').collect::<Vec<_>>().as_slice() {
             [old_commit_oid, new_commit_oid, ..] => {
                 let old_commit_oid: NonZeroOid = old_commit_oid.parse()?;
@@ -107,10 +112,14 @@ pub fn hook_post_rewrite(
     let now = SystemTime::now();
     let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
 
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_env()?;
     let conn = repo.get_db_conn()?;
     let mut event_log_db = EventLogDb::new(&conn)?;
-    let event_tx_id = event_log_db.make_transaction_id(now, "hook-post-rewrite")?;
+    let event_tx_id = event_log_db.make_hook_transaction_id(
+        &repo,
+        now,
+        format!("hook-post-rewrite {}", rewrite_type),
+    )?;
 
     let (rewritten_oids, events) = {
         let rewritten_oids = read_rewritten_list_entries(&mut stdin().lock())?;
@@ -146,6 +155,24 @@ pub fn hook_post_rewrite(
 
     event_log_db.add_events(events)?;
 
+    // Commits rewritten by external tools (such as `git rebase` run directly)
+    // won't have been marked as reachable yet.
+    for new_commit_oid in rewritten_oids.values() {
+        if let MaybeZeroOid::NonZero(new_commit_oid) = new_commit_oid {
+            if repo.find_commit(*new_commit_oid)?.is_some() {
+                mark_commit_reachable(&repo, *new_commit_oid)?;
+            }
+        }
+    }
+
+    // Git moves the branches listed for `git rebase --update-refs` only after
+    // calling this hook, so they shouldn't be reported as abandoned here.
+    let update_refs = get_update_refs(&repo)?;
+    let git_moved_branch_names: HashSet<OsString> = update_refs
+        .iter()
+        .map(|(ref_name, _old_oid, _new_oid)| ref_name.clone())
+        .collect();
+
     if repo
         .get_rebase_state_dir_path()
         .join(EXTRA_POST_REWRITE_FILE_NAME)
@@ -154,7 +181,46 @@ pub fn hook_post_rewrite(
         // Make sure to resolve `ORIG_HEAD` before we potentially delete the
         // branch it points to, so that we can get the original OID of `HEAD`.
         let previous_head_info = get_previous_head_info(&repo)?;
-        move_branches(effects, git_run_info, &repo, event_tx_id, &rewritten_oids)?;
+        let skipped_commit_parent_oids = get_skipped_commit_parent_oids(&repo)?;
+        let mut branch_rewritten_oids =
+            get_branch_rewritten_oids(&rewritten_oids, &skipped_commit_parent_oids);
+
+        // Git has already worked out where the branches that it's tracking
+        // should go, but it won't move them until after this hook, by which
+        // point we'll have checked out the new `HEAD` and rendered the
+        // smartlog. Move them now along with the other branches, and remove
+        // them from Git's list, so that they're moved only once.
+        for (ref_name, old_oid, new_oid) in update_refs.iter() {
+            let new_oid = match new_oid {
+                MaybeZeroOid::NonZero(_) => *new_oid,
+                // The `update-ref` command never ran, such as if it was
+                // removed from the rebase plan.
+                MaybeZeroOid::Zero => continue,
+            };
+            match branch_rewritten_oids.insert(*old_oid, new_oid) {
+                Some(expected_oid) if expected_oid != new_oid => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "branchless: Git moved {} to {}, rather than {}",
+                        CategorizedReferenceName::new(ref_name).friendly_describe(),
+                        new_oid,
+                        expected_oid,
+                    )?;
+                }
+                Some(_) | None => {}
+            }
+        }
+        let exit_code = move_branches(
+            effects,
+            git_run_info,
+            &repo,
+            event_tx_id,
+            &branch_rewritten_oids,
+        )?;
+        if exit_code != 0 {
+            eyre::bail!("Could not move your branches.");
+        }
+        clear_update_refs(&repo)?;
 
         let skipped_head_updated_oid = get_updated_head_oid(&repo)?;
         let exit_code = check_out_updated_head(
@@ -179,6 +245,7 @@ pub fn hook_post_rewrite(
             &conn,
             &event_log_db,
             rewritten_oids.keys().copied(),
+            &git_moved_branch_names,
         )?;
     }
 
@@ -186,13 +253,44 @@ pub fn hook_post_rewrite(
 }
 
 fn get_previous_head_info(repo: &Repo) -> eyre::Result<ResolvedReferenceInfo> {
-    match repo.find_reference(OsStr::new("ORIG_HEAD"))? {
-        None => Ok(ResolvedReferenceInfo {
-            oid: None,
-            reference_name: None,
-        }),
-        Some(reference) => Ok(repo.resolve_reference(&reference)?),
-    }
+    let oid = match repo.find_reference(OsStr::new("ORIG_HEAD"))? {
+        None => None,
+        Some(reference) => repo.resolve_reference(&reference)?.oid,
+    };
+
+    // `ORIG_HEAD` only records the commit, so consult `head-name` to find out
+    // whether a branch was checked out when the rebase started. (This is reset
+    // to `detached HEAD` if the `HEAD` commit was skipped, in which case the
+    // original value is saved separately.)
+    let rebase_state_dir_path = repo.get_rebase_state_dir_path();
+    let head_name_file_path = if rebase_state_dir_path
+        .join(ORIGINAL_HEAD_NAME_FILE_NAME)
+        .exists()
+    {
+        rebase_state_dir_path.join(ORIGINAL_HEAD_NAME_FILE_NAME)
+    } else {
+        rebase_state_dir_path.join("head-name")
+    };
+    let reference_name = match std::fs::read_to_string(&head_name_file_path) {
+        Ok(head_name) => {
+            let head_name = head_name.trim();
+            if head_name.starts_with("refs/") {
+                Some(Cow::Owned(OsString::from(head_name)))
+            } else {
+                None
+            }
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(err)
+                .wrap_err_with(|| format!("Reading head-name from: {:?}", &head_name_file_path))
+        }
+    };
+
+    Ok(ResolvedReferenceInfo {
+        oid,
+        reference_name,
+    })
 }
 #[instrument(skip(old_commit_oids))]
 fn warn_abandoned(
@@ -201,6 +299,7 @@ fn warn_abandoned(
     conn: &rusqlite::Connection,
     event_log_db: &EventLogDb,
     old_commit_oids: impl IntoIterator<Item = NonZeroOid>,
+    git_moved_branch_names: &HashSet<OsString>,
 ) -> eyre::Result<()> {
     // The caller will have added events to the event log database, so make sure
     // to construct a fresh `EventReplayer` here.
@@ -228,7 +327,12 @@ fn warn_abandoned(
             all_abandoned_children.extend(abandoned_children.iter());
             if let Some(branch_names) = references_snapshot.branch_oid_to_names.get(&old_commit_oid)
             {
-                all_abandoned_branches.extend(branch_names.iter().map(OsString::as_os_str));
+                all_abandoned_branches.extend(
+                    branch_names
+                        .iter()
+                        .filter(|branch_name| !git_moved_branch_names.contains(*branch_name))
+                        .map(OsString::as_os_str),
+                );
             }
         }
         (all_abandoned_children, all_abandoned_branches)
@@ -277,7 +381,8 @@ fn warn_abandoned(
             .bold()
             .yellow();
 
-        print!(
+        write!(
+            effects.get_output_stream(),
             "\
 branchless: {warning_message}
 branchless: Consider running one of the following:
@@ -298,7 +403,7 @@ branchless:   - {config_command}: suppress this message
                 RESTACK_WARN_ABANDONED_CONFIG_KEY
             ))
             .bold(),
-        );
+        )?;
     }
 
     Ok(())
@@ -306,12 +411,50 @@ branchless:   - {config_command}: suppress this message
 
 const EXTRA_POST_REWRITE_FILE_NAME: &str = "branchless_do_extra_post_rewrite";
 
+/// The file in which Git records the branches to move when the rebase
+/// finishes, as with `git rebase --update-refs`. Each branch is recorded as
+/// three lines: the reference name, its original OID, and the OID to move it
+/// to, which is zero until the corresponding `update-ref` command has run.
+pub(super) const UPDATE_REFS_FILE_NAME: &str = "update-refs";
+
+#[instrument]
+fn get_update_refs(repo: &Repo) -> eyre::Result<Vec<(OsString, NonZeroOid, MaybeZeroOid)>> {
+    let file_name = repo.get_rebase_state_dir_path().join(UPDATE_REFS_FILE_NAME);
+    let contents = match std::fs::read_to_string(&file_name) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Default::default()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut result = Vec::new();
+    for chunk in &contents.lines().chunks(3) {
+        match *chunk.collect::<Vec<_>>().as_slice() {
+            [ref_name, old_oid, new_oid] => {
+                result.push((OsString::from(ref_name), old_oid.parse()?, new_oid.parse()?));
+            }
+            ref lines => eyre::bail!("Invalid update-refs entry: {:?}", lines),
+        }
+    }
+    Ok(result)
+}
+
+#[instrument]
+fn clear_update_refs(repo: &Repo) -> eyre::Result<()> {
+    let file_name = repo.get_rebase_state_dir_path().join(UPDATE_REFS_FILE_NAME);
+    match std::fs::remove_file(&file_name) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err).wrap_err_with(|| format!("Removing {:?}", &file_name)),
+    }
+}
+
 /// In order to handle the case of a commit being skipped and its corresponding
 /// branch being deleted, we need to store our own copy of the original `HEAD`
 /// OID, and then replace it once the rebase is about to conclude. We can't do
 /// it earlier, because if the user aborts the rebase after the commit has been
 /// skipped, then they would be returned to the wrong commit.
 const UPDATED_HEAD_FILE_NAME: &str = "branchless_updated_head";
+const ORIGINAL_HEAD_NAME_FILE_NAME: &str = "branchless_original_head_name";
 
 #[instrument]
 fn save_updated_head_oid(repo: &Repo, updated_head_oid: NonZeroOid) -> eyre::Result<()> {
@@ -320,7 +463,21 @@ fn save_updated_head_oid(repo: &Repo, updated_head_oid: NonZeroOid) -> eyre::Res
         .join(UPDATED_HEAD_FILE_NAME);
     std::fs::write(dest_file_name, updated_head_oid.to_string())?;
 
+    // Git would move the checked-out branch to the last rebased commit, so
+    // detach it here instead. The branch itself is moved along with the other
+    // branches pointing to the skipped commit, and then checked out again, so
+    // save its name for later.
     let head_name_file_name = repo.get_rebase_state_dir_path().join("head-name");
+    let original_head_name_file_name = repo
+        .get_rebase_state_dir_path()
+        .join(ORIGINAL_HEAD_NAME_FILE_NAME);
+    if !original_head_name_file_name.exists() {
+        match std::fs::copy(&head_name_file_name, &original_head_name_file_name) {
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
     std::fs::write(head_name_file_name, "detached HEAD")?;
 
     Ok(())
@@ -338,12 +495,58 @@ fn get_updated_head_oid(repo: &Repo) -> eyre::Result<Option<NonZeroOid>> {
     }
 }
 
+const SKIPPED_COMMIT_PARENTS_FILE_NAME: &str = "branchless_skipped_commit_parents";
+
+/// Record the commit that the skipped commit would have been applied on top
+/// of, so that any branches pointing to the skipped commit can be moved there
+/// once the rebase concludes.
+#[instrument]
+fn save_skipped_commit_parent_oid(
+    repo: &Repo,
+    skipped_commit_oid: NonZeroOid,
+    parent_oid: NonZeroOid,
+) -> eyre::Result<()> {
+    let file_name = repo
+        .get_rebase_state_dir_path()
+        .join(SKIPPED_COMMIT_PARENTS_FILE_NAME);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&file_name)
+        .wrap_err_with(|| format!("Opening skipped commits file: {:?}", &file_name))?;
+    writeln!(file, "{} {}", skipped_commit_oid, parent_oid)?;
+    Ok(())
+}
+
+#[instrument]
+fn get_skipped_commit_parent_oids(repo: &Repo) -> eyre::Result<HashMap<NonZeroOid, NonZeroOid>> {
+    let file_name = repo
+        .get_rebase_state_dir_path()
+        .join(SKIPPED_COMMIT_PARENTS_FILE_NAME);
+    let contents = match std::fs::read_to_string(&file_name) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Default::default()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut result = HashMap::new();
+    for line in contents.lines() {
+        match *line.split(' ').collect::<Vec<_>>().as_slice() {
+            [skipped_commit_oid, parent_oid] => {
+                result.insert(skipped_commit_oid.parse()?, parent_oid.parse()?);
+            }
+            _ => eyre::bail!("Invalid skipped commit line: {:?}", &line),
+        }
+    }
+    Ok(result)
+}
+
 /// For rebases, register that extra cleanup actions should be taken when the
 /// rebase finishes and calls the post-rewrite hook. We don't want to change the
 /// behavior of `git rebase` itself, except when called via `git-branchless`, so
 /// that the user's expectations aren't unexpectedly subverted.
 pub fn hook_register_extra_post_rewrite_hook() -> eyre::Result<()> {
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_env()?;
     let file_name = repo
         .get_rebase_state_dir_path()
         .join(EXTRA_POST_REWRITE_FILE_NAME);
@@ -358,7 +561,7 @@ pub fn hook_drop_commit_if_empty(
     effects: &Effects,
     old_commit_oid: NonZeroOid,
 ) -> eyre::Result<()> {
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_env()?;
     let head_info = repo.get_head_info()?;
     let head_oid = match head_info.oid {
         Some(head_oid) => head_oid,
@@ -393,6 +596,7 @@ pub fn hook_drop_commit_if_empty(
     if Some(old_commit_oid) == orig_head_oid {
         save_updated_head_oid(&repo, only_parent_oid)?;
     }
+    save_skipped_commit_parent_oid(&repo, old_commit_oid, only_parent_oid)?;
     add_rewritten_list_entries(
         &repo.get_tempfile_dir(),
         &repo.get_rebase_state_dir_path().join("rewritten-list"),
@@ -411,25 +615,46 @@ pub fn hook_skip_upstream_applied_commit(
     effects: &Effects,
     commit_oid: NonZeroOid,
 ) -> eyre::Result<()> {
-    let repo = Repo::from_current_dir()?;
+    let repo = Repo::from_env()?;
     let commit = repo.find_commit_or_fail(commit_oid)?;
     writeln!(
         effects.get_output_stream(),
         "Skipping commit (was already applied upstream): {}",
         printable_styled_string(effects.get_glyphs(), commit.friendly_describe()?)?
     )?;
+    skip_commit(&repo, commit_oid)
+}
+
+/// For rebases, if merge commits were requested to be dropped, skip the merge
+/// commit without attempting to re-create it.
+pub fn hook_drop_merge_commit(effects: &Effects, commit_oid: NonZeroOid) -> eyre::Result<()> {
+    let repo = Repo::from_env()?;
+    let commit = repo.find_commit_or_fail(commit_oid)?;
+    writeln!(
+        effects.get_output_stream(),
+        "Dropping merge commit: {}",
+        printable_styled_string(effects.get_glyphs(), commit.friendly_describe()?)?
+    )?;
+    skip_commit(&repo, commit_oid)
+}
 
+/// Record that the given commit was skipped during the rebase, so that it's
+/// hidden afterwards.
+fn skip_commit(repo: &Repo, commit_oid: NonZeroOid) -> eyre::Result<()> {
+    let current_head_oid = repo.get_head_info()?.oid;
     if let Some(orig_head_reference) = repo.find_reference(OsStr::new("ORIG_HEAD"))? {
         let resolved_orig_head = repo.resolve_reference(&orig_head_reference)?;
         if let Some(original_head_oid) = resolved_orig_head.oid {
             if original_head_oid == commit_oid {
-                let current_head_oid = repo.get_head_info()?.oid;
                 if let Some(current_head_oid) = current_head_oid {
-                    save_updated_head_oid(&repo, current_head_oid)?;
+                    save_updated_head_oid(repo, current_head_oid)?;
                 }
             }
         }
     }
+    if let Some(current_head_oid) = current_head_oid {
+        save_skipped_commit_parent_oid(repo, commit_oid, current_head_oid)?;
+    }
     add_rewritten_list_entries(
         &repo.get_tempfile_dir(),
         &repo.get_rebase_state_dir_path().join("rewritten-list"),
@@ -0,0 +1,198 @@
+//! Take snapshots of the working copy, so that uncommitted changes which are
+//! overwritten by a checkout can be restored later (such as by `git undo`).
+//!
+//! A snapshot is a commit of the same form as the ones which `git stash`
+//! creates: its tree is the state of the working copy, its first parent is
+//! the commit that `HEAD` pointed to, and its second parent is a commit whose
+//! tree is the state of the index. Untracked files are not included, since
+//! checking out a commit never overwrites them.
+//!
+//! Snapshot commits are kept alive by references under
+//! `refs/branchless/snapshots/`, and each snapshot is recorded in the event
+//! log as an `Event::WorkingCopySnapshot`. They're never shown in the
+//! smartlog, and are deleted by `git branchless gc` once they're older than
+//! the configured maximum age.
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use eyre::Context;
+use tracing::instrument;
+
+use crate::core::eventlog::{Event, EventLogDb, EventTransactionId};
+use crate::git::{get_diff_stat, FileDiffStat, GitRunInfo, NonZeroOid, Reference, Repo};
+
+/// The prefix of the references which keep snapshot commits alive.
+pub const SNAPSHOT_REF_PREFIX: &str = "refs/branchless/snapshots/";
+
+/// Determine whether the given reference is used to keep a working copy
+/// snapshot alive.
+pub fn is_snapshot_ref(ref_name: &OsStr) -> bool {
+    match ref_name.to_str() {
+        None => false,
+        Some(ref_name) => ref_name.starts_with(SNAPSHOT_REF_PREFIX),
+    }
+}
+
+/// Snapshot the working copy and record the snapshot in the event log.
+///
+/// Returns the OID of the snapshot commit, or `None` if there were no
+/// uncommitted changes to tracked files, in which case no snapshot is taken.
+/// No snapshot is taken if `HEAD` is unborn either, since a snapshot commit
+/// needs a parent commit.
+#[instrument]
+pub fn create_snapshot(
+    repo: &Repo,
+    git_run_info: &GitRunInfo,
+    event_log_db: &mut EventLogDb,
+    event_tx_id: EventTransactionId,
+) -> eyre::Result<Option<NonZeroOid>> {
+    let head_info = repo.get_head_info()?;
+    if head_info.is_unborn() || repo.get_status(git_run_info, Some(event_tx_id))?.is_empty() {
+        return Ok(None);
+    }
+
+    let result = git_run_info.run_silent(
+        repo,
+        Some(event_tx_id),
+        &["stash", "create", "branchless: working copy snapshot"],
+        Default::default(),
+    )?;
+    let stdout = String::from_utf8(result.stdout).wrap_err("Decoding snapshot commit OID")?;
+    let commit_oid: NonZeroOid = match stdout.trim() {
+        "" => return Ok(None),
+        commit_oid => commit_oid.parse()?,
+    };
+
+    let ref_name = format!("{}{}", SNAPSHOT_REF_PREFIX, commit_oid);
+    eyre::ensure!(
+        Reference::is_valid_name(&ref_name),
+        format!("Invalid ref name for working copy snapshot: {}", ref_name)
+    );
+    repo.create_reference(
+        OsStr::new(&ref_name),
+        commit_oid,
+        true,
+        "branchless: creating working copy snapshot",
+    )
+    .wrap_err("Creating snapshot reference")?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs_f64();
+    event_log_db.add_events(vec![Event::WorkingCopySnapshot {
+        timestamp,
+        event_tx_id,
+        head_oid: head_info.oid.into(),
+        commit_oid,
+        ref_name: head_info
+            .reference_name
+            .map(|reference_name| reference_name.into_owned()),
+    }])?;
+    Ok(Some(commit_oid))
+}
+
+/// Discard all uncommitted changes to tracked files in the working copy and
+/// index. Callers should take a snapshot first if the changes might be
+/// needed later.
+#[instrument]
+pub fn discard_uncommitted_changes(
+    repo: &Repo,
+    git_run_info: &GitRunInfo,
+    event_tx_id: EventTransactionId,
+) -> eyre::Result<()> {
+    git_run_info.run_silent(
+        repo,
+        Some(event_tx_id),
+        &["reset", "--hard", "--quiet"],
+        Default::default(),
+    )?;
+    Ok(())
+}
+
+/// Replace the uncommitted changes in the working copy and index with the
+/// ones stored in the given snapshot. `HEAD` should already point to the
+/// commit that the snapshot was taken at.
+#[instrument]
+pub fn restore_snapshot(
+    repo: &Repo,
+    git_run_info: &GitRunInfo,
+    event_tx_id: EventTransactionId,
+    snapshot_oid: NonZeroOid,
+) -> eyre::Result<()> {
+    discard_uncommitted_changes(repo, git_run_info, event_tx_id)?;
+    git_run_info
+        .run_silent(
+            repo,
+            Some(event_tx_id),
+            &[
+                "stash",
+                "apply",
+                "--index",
+                "--quiet",
+                &snapshot_oid.to_string(),
+            ],
+            Default::default(),
+        )
+        .wrap_err("Applying working copy snapshot")?;
+    Ok(())
+}
+
+/// Get the paths with uncommitted changes (in either the working copy or the
+/// index) stored in the given snapshot. Returns `None` if the snapshot commit
+/// no longer exists or isn't of the expected form.
+#[instrument]
+pub fn get_snapshot_changed_paths(
+    repo: &Repo,
+    snapshot_oid: NonZeroOid,
+) -> eyre::Result<Option<HashSet<PathBuf>>> {
+    let snapshot_commit = match repo.find_commit(snapshot_oid)? {
+        Some(snapshot_commit) => snapshot_commit,
+        None => return Ok(None),
+    };
+    let (head_oid, index_oid) = match snapshot_commit.get_parent_oids().as_slice() {
+        [head_oid, index_oid] => (*head_oid, *index_oid),
+        _ => return Ok(None),
+    };
+
+    let mut result = HashSet::new();
+    for new_oid in [snapshot_oid, index_oid] {
+        let diff_stat = get_diff_stat(repo, head_oid, new_oid)?;
+        result.extend(diff_stat.files.into_iter().map(|file| match file {
+            FileDiffStat::Text { path, .. } | FileDiffStat::Binary { path } => path,
+        }));
+    }
+    Ok(Some(result))
+}
+
+/// Find the references to snapshots which were taken more than `max_age_days`
+/// days before `now`.
+#[instrument]
+pub fn find_expired_snapshot_references(
+    repo: &Repo,
+    now: SystemTime,
+    max_age_days: i32,
+) -> eyre::Result<Vec<Reference<'_>>> {
+    let now = i64::try_from(now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs())?;
+    let cutoff = now - i64::from(max_age_days.max(0)) * 24 * 60 * 60;
+
+    let mut result = Vec::new();
+    for reference in repo.get_all_references()? {
+        if !is_snapshot_ref(&reference.get_name()?) {
+            continue;
+        }
+
+        let is_expired = match reference.peel_to_commit()? {
+            Some(commit) => commit.get_time().seconds() < cutoff,
+            // Not a commit, so it's not a snapshot we created.
+            None => true,
+        };
+        if is_expired {
+            result.push(reference);
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,287 @@
+//! Keep track of the entries created by `git stash`.
+//!
+//! Git stores the stash as the reflog of `refs/stash`, where each entry is a
+//! commit whose first parent is the commit that `HEAD` pointed to when the
+//! stash entry was created. Entries can be dropped by rewriting the reflog
+//! (such as with `git stash pop`), which doesn't invoke the
+//! `reference-transaction` hook, so the event log is brought up to date with
+//! the stash by `sync_stash_events` instead.
+//!
+//! Stash updates are recorded as `Event::RefUpdateEvent`s for `refs/stash`,
+//! but each one describes a single stash entry rather than the reference
+//! itself: creating an entry is recorded as an update from the zero OID to
+//! the entry's commit, and dropping an entry as an update from the entry's
+//! commit to the zero OID. This way, the inverse of each event can be
+//! applied on its own by `git undo`.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use tracing::instrument;
+
+use crate::core::effects::Effects;
+use crate::core::eventlog::{Event, EventLogDb, EventTransactionId};
+use crate::git::{GitRunInfo, MaybeZeroOid, NonZeroOid, Repo};
+
+/// The reference whose reflog holds the stash entries.
+pub const STASH_REF_NAME: &str = "refs/stash";
+
+/// The message recorded for event transactions created by `sync_stash_events`.
+const STASH_DROP_TRANSACTION_MESSAGE: &str = "stash drop";
+
+/// Determine whether the given reference is the one which holds the stash
+/// entries.
+pub fn is_stash_ref(ref_name: &OsStr) -> bool {
+    ref_name == STASH_REF_NAME
+}
+
+/// An entry in the stash.
+#[derive(Clone, Debug)]
+pub struct StashEntry {
+    /// The index of the entry, as in `stash@{<index>}`. The most recent entry
+    /// has index 0.
+    pub index: usize,
+
+    /// The OID of the stash commit.
+    pub commit_oid: NonZeroOid,
+
+    /// The message describing the entry, such as `WIP on master: abc123 foo`.
+    pub message: Option<String>,
+}
+
+impl StashEntry {
+    /// Get the name of the entry for use with `git stash`, such as
+    /// `stash@{0}`.
+    pub fn get_name(&self) -> String {
+        format!("stash@{{{}}}", self.index)
+    }
+}
+
+/// Get the entries in the stash, from most to least recent.
+#[instrument]
+pub fn get_stash_entries(repo: &Repo) -> eyre::Result<Vec<StashEntry>> {
+    if repo.find_reference(OsStr::new(STASH_REF_NAME))?.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let entries = repo
+        .get_reflog_entries(OsStr::new(STASH_REF_NAME), usize::MAX)?
+        .into_iter()
+        .rev()
+        .filter_map(|entry| match entry.new_oid {
+            MaybeZeroOid::NonZero(commit_oid) => Some((commit_oid, entry.message)),
+            MaybeZeroOid::Zero => None,
+        })
+        .enumerate()
+        .map(|(index, (commit_oid, message))| StashEntry {
+            index,
+            commit_oid,
+            message,
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Convert an update to `refs/stash` reported by the `reference-transaction`
+/// hook into the form recorded in the event log (see the module
+/// documentation). Git doesn't always report the previous value of
+/// `refs/stash` when creating a new entry, and the previous entry isn't
+/// dropped in that case anyways.
+pub fn normalize_stash_update(
+    old_oid: MaybeZeroOid,
+    new_oid: MaybeZeroOid,
+) -> (MaybeZeroOid, MaybeZeroOid) {
+    match new_oid {
+        MaybeZeroOid::NonZero(_) => (MaybeZeroOid::Zero, new_oid),
+        MaybeZeroOid::Zero => (old_oid, new_oid),
+    }
+}
+
+/// Get the commits of the stash entries which exist according to the events
+/// recorded in the event log.
+fn get_recorded_stash_commits(events: &[Event]) -> HashSet<NonZeroOid> {
+    let mut result = HashSet::new();
+    for event in events {
+        if let Event::RefUpdateEvent {
+            ref_name,
+            old_oid,
+            new_oid,
+            ..
+        } = event
+        {
+            if !is_stash_ref(ref_name) {
+                continue;
+            }
+            if let MaybeZeroOid::NonZero(old_oid) = old_oid {
+                result.remove(old_oid);
+            }
+            if let MaybeZeroOid::NonZero(new_oid) = new_oid {
+                result.insert(*new_oid);
+            }
+        }
+    }
+    result
+}
+
+/// Find the stash commits which should be kept reachable so that they can be
+/// restored with `git undo`: those which are in the stash according to the
+/// event log, and those which were created or dropped no earlier than
+/// `cutoff`.
+pub fn find_recent_stash_commits(events: &[Event], cutoff: SystemTime) -> HashSet<NonZeroOid> {
+    let mut result = get_recorded_stash_commits(events);
+    for event in events {
+        if let Event::RefUpdateEvent {
+            ref_name,
+            old_oid,
+            new_oid,
+            ..
+        } = event
+        {
+            if !is_stash_ref(ref_name) || event.get_timestamp() < cutoff {
+                continue;
+            }
+            result.extend([old_oid, new_oid].iter().filter_map(|oid| match oid {
+                MaybeZeroOid::NonZero(oid) => Some(*oid),
+                MaybeZeroOid::Zero => None,
+            }));
+        }
+    }
+    result
+}
+
+/// Record an event for each stash entry which was dropped without the event
+/// log being notified, such as by `git stash pop`, so that it can be restored
+/// with `git undo`.
+#[instrument]
+pub fn sync_stash_events(
+    repo: &Repo,
+    event_log_db: &mut EventLogDb,
+    now: SystemTime,
+) -> eyre::Result<()> {
+    let events = event_log_db.get_events()?;
+    let recorded_stash_commits = get_recorded_stash_commits(&events);
+    if recorded_stash_commits.is_empty() {
+        return Ok(());
+    }
+
+    let stash_commits: HashSet<NonZeroOid> = get_stash_entries(repo)?
+        .into_iter()
+        .map(|entry| entry.commit_oid)
+        .collect();
+    let mut dropped_stash_commits: Vec<NonZeroOid> = recorded_stash_commits
+        .difference(&stash_commits)
+        .copied()
+        .collect();
+    if dropped_stash_commits.is_empty() {
+        return Ok(());
+    }
+    dropped_stash_commits.sort_unstable();
+
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let event_tx_id = event_log_db.make_transaction_id_with_command(
+        now,
+        STASH_DROP_TRANSACTION_MESSAGE,
+        "git stash drop",
+    )?;
+    event_log_db.add_events(
+        dropped_stash_commits
+            .into_iter()
+            .map(|commit_oid| Event::RefUpdateEvent {
+                timestamp,
+                event_tx_id,
+                ref_name: STASH_REF_NAME.into(),
+                old_oid: MaybeZeroOid::NonZero(commit_oid),
+                new_oid: MaybeZeroOid::Zero,
+                message: None,
+            })
+            .collect(),
+    )?;
+    Ok(())
+}
+
+/// Apply an update to the stash which was recorded in the form described in
+/// the module documentation: if `new_oid` isn't in the stash, add it as the
+/// most recent entry, and if `old_oid` is in the stash, drop it.
+#[instrument]
+pub fn apply_stash_update(
+    effects: &Effects,
+    repo: &Repo,
+    git_run_info: &GitRunInfo,
+    event_log_db: &mut EventLogDb,
+    event_tx_id: EventTransactionId,
+    old_oid: MaybeZeroOid,
+    new_oid: MaybeZeroOid,
+) -> eyre::Result<()> {
+    let stash_entries = get_stash_entries(repo)?;
+
+    if let MaybeZeroOid::NonZero(new_oid) = new_oid {
+        if stash_entries
+            .iter()
+            .any(|entry| entry.commit_oid == new_oid)
+        {
+            writeln!(
+                effects.get_output_stream(),
+                "Stash entry {} already exists, not restoring it.",
+                new_oid
+            )?;
+        } else {
+            match repo.find_commit(new_oid)? {
+                Some(commit) => {
+                    let message = commit.get_summary()?;
+                    // The `reference-transaction` hook records the new entry.
+                    git_run_info.run_silent(
+                        repo,
+                        Some(event_tx_id),
+                        &[
+                            "stash",
+                            "store",
+                            "--quiet",
+                            "-m",
+                            &message.to_string_lossy(),
+                            &new_oid.to_string(),
+                        ],
+                        Default::default(),
+                    )?;
+                }
+                None => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "Stash entry {} is unavailable, not restoring it.",
+                        new_oid
+                    )?;
+                }
+            }
+        }
+    }
+
+    if let MaybeZeroOid::NonZero(old_oid) = old_oid {
+        if let Some(entry) = stash_entries
+            .iter()
+            .find(|entry| entry.commit_oid == old_oid)
+        {
+            git_run_info.run_silent(
+                repo,
+                Some(event_tx_id),
+                &["stash", "drop", "--quiet", &entry.get_name()],
+                Default::default(),
+            )?;
+            // Dropping an entry rewrites the reflog, which doesn't invoke the
+            // `reference-transaction` hook, so record the event here.
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs_f64();
+            event_log_db.add_events(vec![Event::RefUpdateEvent {
+                timestamp,
+                event_tx_id,
+                ref_name: STASH_REF_NAME.into(),
+                old_oid: MaybeZeroOid::NonZero(old_oid),
+                new_oid: MaybeZeroOid::Zero,
+                message: None,
+            }])?;
+        }
+    }
+
+    Ok(())
+}
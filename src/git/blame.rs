@@ -0,0 +1,58 @@
+//! Determine which commit last changed each line of a file, in the style of
+//! `git blame`.
+
+use std::path::Path;
+
+use eyre::Context;
+use tracing::instrument;
+
+use super::oid::make_non_zero_oid;
+use super::repo::wrap_git_error;
+use super::{NonZeroOid, Repo};
+
+/// The commit which last changed a given line of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlamedLine {
+    /// The commit which last changed the line.
+    pub commit_oid: NonZeroOid,
+
+    /// The index (0-based) of the line in that commit's version of the file.
+    pub orig_line_index: usize,
+}
+
+/// Blame each line of the file at `path` as of the given commit. The result
+/// has one entry per line of the file. Returns `None` if the file doesn't
+/// exist in that commit.
+#[instrument]
+pub fn blame_file(
+    repo: &Repo,
+    commit_oid: NonZeroOid,
+    path: &Path,
+) -> eyre::Result<Option<Vec<BlamedLine>>> {
+    let commit = repo.find_commit_or_fail(commit_oid)?;
+    if commit.get_tree()?.get_path(path)?.is_none() {
+        return Ok(None);
+    }
+
+    let mut blame_options = git2::BlameOptions::new();
+    blame_options.newest_commit(commit_oid.inner);
+    let blame = repo
+        .inner
+        .blame_file(path, Some(&mut blame_options))
+        .map_err(wrap_git_error)
+        .wrap_err_with(|| format!("Blaming file {:?} at commit {:?}", path, commit_oid))?;
+
+    let mut result = Vec::new();
+    for hunk in blame.iter() {
+        let commit_oid = make_non_zero_oid(hunk.orig_commit_id());
+        // Line numbers reported by `libgit2` are 1-based.
+        let orig_start = hunk.orig_start_line().saturating_sub(1);
+        for i in 0..hunk.lines_in_hunk() {
+            result.push(BlamedLine {
+                commit_oid,
+                orig_line_index: orig_start + i,
+            });
+        }
+    }
+    Ok(Some(result))
+}
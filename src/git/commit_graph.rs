@@ -0,0 +1,365 @@
+//! Read Git's commit-graph file, which stores the parents of each commit in
+//! an indexed form, so that the commit graph can be traversed without
+//! inflating each commit object.
+//!
+//! See `Documentation/technical/commit-graph-format.txt` in the Git source
+//! tree for a description of the file format.
+
+use std::cmp::Ordering;
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+
+use eyre::{eyre, Context};
+use tracing::instrument;
+
+use super::{ConfigRead, NonZeroOid, Repo};
+
+const SIGNATURE: &[u8] = b"CGPH";
+const VERSION: u8 = 1;
+const HASH_VERSION_SHA1: u8 = 1;
+const HASH_LEN: usize = 20;
+const HEADER_LEN: usize = 8;
+const CHUNK_LOOKUP_ENTRY_LEN: usize = 12;
+const OID_FANOUT_LEN: usize = 256 * 4;
+const COMMIT_DATA_LEN: usize = HASH_LEN + 16;
+
+const CHUNK_ID_OID_FANOUT: &[u8] = b"OIDF";
+const CHUNK_ID_OID_LOOKUP: &[u8] = b"OIDL";
+const CHUNK_ID_COMMIT_DATA: &[u8] = b"CDAT";
+const CHUNK_ID_EXTRA_EDGES: &[u8] = b"EDGE";
+
+/// Parent position indicating that there is no such parent.
+const PARENT_NONE: u32 = 0x7000_0000;
+
+/// Flag on the second parent position indicating that it's an index into the
+/// extra edges chunk, which lists the second and later parents of an octopus
+/// merge commit.
+const PARENT_EXTRA_EDGES: u32 = 0x8000_0000;
+
+/// Flag on an entry in the extra edges chunk indicating that it's the last
+/// parent of the commit.
+const EXTRA_EDGE_LAST: u32 = 0x8000_0000;
+
+fn read_u32(data: &[u8], offset: usize) -> eyre::Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or_else(|| eyre!("Reading past end of commit-graph at offset {}", offset))?;
+    Ok(u32::from_be_bytes(bytes.try_into()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> eyre::Result<u64> {
+    let bytes = data
+        .get(offset..offset + 8)
+        .ok_or_else(|| eyre!("Reading past end of commit-graph at offset {}", offset))?;
+    Ok(u64::from_be_bytes(bytes.try_into()?))
+}
+
+/// A single commit-graph file. If the commit-graph is split into a chain of
+/// files, each one is a layer containing the commits which aren't in any of
+/// the layers below it.
+struct CommitGraphLayer {
+    data: Vec<u8>,
+    num_commits: usize,
+    oid_fanout_offset: usize,
+    oid_lookup_offset: usize,
+    commit_data_offset: usize,
+    extra_edges_offset: Option<usize>,
+}
+
+impl CommitGraphLayer {
+    fn parse(data: Vec<u8>) -> eyre::Result<Self> {
+        if data.get(..SIGNATURE.len()) != Some(SIGNATURE) {
+            eyre::bail!("Invalid commit-graph signature");
+        }
+        match data.get(4..HEADER_LEN) {
+            Some([version, hash_version, _num_chunks, _num_base_graphs])
+                if *version == VERSION && *hash_version == HASH_VERSION_SHA1 => {}
+            Some(header) => eyre::bail!("Unsupported commit-graph version: {:?}", header),
+            None => eyre::bail!("Truncated commit-graph header"),
+        }
+        let num_chunks = usize::from(data[6]);
+
+        let mut oid_fanout_offset = None;
+        let mut oid_lookup_offset = None;
+        let mut commit_data_offset = None;
+        let mut extra_edges_offset = None;
+        for i in 0..num_chunks {
+            let entry_offset = HEADER_LEN + i * CHUNK_LOOKUP_ENTRY_LEN;
+            let chunk_id = data
+                .get(entry_offset..entry_offset + 4)
+                .ok_or_else(|| eyre!("Truncated commit-graph chunk lookup"))?;
+            let chunk_offset = usize::try_from(read_u64(&data, entry_offset + 4)?)?;
+            match chunk_id {
+                CHUNK_ID_OID_FANOUT => oid_fanout_offset = Some(chunk_offset),
+                CHUNK_ID_OID_LOOKUP => oid_lookup_offset = Some(chunk_offset),
+                CHUNK_ID_COMMIT_DATA => commit_data_offset = Some(chunk_offset),
+                CHUNK_ID_EXTRA_EDGES => extra_edges_offset = Some(chunk_offset),
+                _ => {}
+            }
+        }
+        let oid_fanout_offset =
+            oid_fanout_offset.ok_or_else(|| eyre!("Missing commit-graph OID fanout chunk"))?;
+        let oid_lookup_offset =
+            oid_lookup_offset.ok_or_else(|| eyre!("Missing commit-graph OID lookup chunk"))?;
+        let commit_data_offset =
+            commit_data_offset.ok_or_else(|| eyre!("Missing commit-graph commit data chunk"))?;
+
+        let num_commits =
+            usize::try_from(read_u32(&data, oid_fanout_offset + OID_FANOUT_LEN - 4)?)?;
+        if oid_lookup_offset + num_commits * HASH_LEN > data.len()
+            || commit_data_offset + num_commits * COMMIT_DATA_LEN > data.len()
+        {
+            eyre::bail!("Truncated commit-graph chunk");
+        }
+
+        Ok(CommitGraphLayer {
+            data,
+            num_commits,
+            oid_fanout_offset,
+            oid_lookup_offset,
+            commit_data_offset,
+            extra_edges_offset,
+        })
+    }
+
+    fn get_oid_bytes(&self, index: usize) -> &[u8] {
+        let offset = self.oid_lookup_offset + index * HASH_LEN;
+        &self.data[offset..offset + HASH_LEN]
+    }
+
+    /// Find the index of the given commit in this layer.
+    fn find_index(&self, oid: NonZeroOid) -> eyre::Result<Option<usize>> {
+        let oid = oid.as_bytes();
+        let first_byte = usize::from(oid[0]);
+        let start = match first_byte {
+            0 => 0,
+            first_byte => read_u32(&self.data, self.oid_fanout_offset + (first_byte - 1) * 4)?,
+        };
+        let end = read_u32(&self.data, self.oid_fanout_offset + first_byte * 4)?;
+        let (start, end) = (usize::try_from(start)?, usize::try_from(end)?);
+        if start > end || end > self.num_commits {
+            eyre::bail!("Invalid commit-graph OID fanout");
+        }
+
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.get_oid_bytes(mid).cmp(oid) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(Some(mid)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Git's commit-graph, which may be split into a chain of several files.
+pub struct CommitGraph {
+    /// The layers of the commit-graph, starting with the base layer. The
+    /// position of a commit in the commit-graph is its index in its layer, plus
+    /// the number of commits in the layers below it.
+    layers: Vec<CommitGraphLayer>,
+}
+
+impl std::fmt::Debug for CommitGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<CommitGraph layers={}>", self.layers.len())
+    }
+}
+
+fn read_file_if_exists(path: &Path) -> eyre::Result<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).wrap_err_with(|| format!("Reading commit-graph file at: {:?}", path)),
+    }
+}
+
+impl CommitGraph {
+    /// Read the commit-graph for the given repository. Returns `None` if the
+    /// repository doesn't have one, or if it's disabled via
+    /// `core.commitGraph`.
+    #[instrument]
+    pub fn open(repo: &Repo) -> eyre::Result<Option<Self>> {
+        if !repo
+            .get_readonly_config()?
+            .get_or("core.commitGraph", true)?
+        {
+            return Ok(None);
+        }
+
+        let info_dir = repo.get_common_path().join("objects").join("info");
+        if let Some(data) = read_file_if_exists(&info_dir.join("commit-graph"))? {
+            let layer = CommitGraphLayer::parse(data).wrap_err("Parsing commit-graph")?;
+            return Ok(Some(CommitGraph {
+                layers: vec![layer],
+            }));
+        }
+
+        let chain_dir = info_dir.join("commit-graphs");
+        let chain = match read_file_if_exists(&chain_dir.join("commit-graph-chain"))? {
+            Some(chain) => String::from_utf8(chain).wrap_err("Decoding commit-graph chain")?,
+            None => return Ok(None),
+        };
+        let mut layers = Vec::new();
+        for hash in chain
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+        {
+            let path = chain_dir.join(format!("graph-{}.graph", hash));
+            let data = read_file_if_exists(&path)?
+                .ok_or_else(|| eyre!("Missing commit-graph file: {:?}", &path))?;
+            let layer = CommitGraphLayer::parse(data)
+                .wrap_err_with(|| format!("Parsing commit-graph file: {:?}", &path))?;
+            layers.push(layer);
+        }
+        if layers.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(CommitGraph { layers }))
+        }
+    }
+
+    fn get_oid_at_position(&self, position: usize) -> eyre::Result<NonZeroOid> {
+        let mut index = position;
+        for layer in self.layers.iter() {
+            if index < layer.num_commits {
+                return NonZeroOid::try_from(layer.get_oid_bytes(index));
+            }
+            index -= layer.num_commits;
+        }
+        eyre::bail!("Invalid commit-graph position: {}", position)
+    }
+
+    /// Get the parents of the given commit. Returns `None` if the commit isn't
+    /// in the commit-graph, such as if it was created after the commit-graph
+    /// was last written.
+    pub fn get_parent_oids(&self, oid: NonZeroOid) -> eyre::Result<Option<Vec<NonZeroOid>>> {
+        let layer_index = self.layers.iter().rev().find_map(|layer| {
+            layer
+                .find_index(oid)
+                .transpose()
+                .map(|index| (layer, index))
+        });
+        let (layer, index) = match layer_index {
+            Some((layer, index)) => (layer, index?),
+            None => return Ok(None),
+        };
+
+        let parents_offset = layer.commit_data_offset + index * COMMIT_DATA_LEN + HASH_LEN;
+        let first_parent = read_u32(&layer.data, parents_offset)?;
+        let second_parent = read_u32(&layer.data, parents_offset + 4)?;
+        let mut parent_oids = Vec::new();
+        if first_parent == PARENT_NONE {
+            return Ok(Some(parent_oids));
+        }
+        parent_oids.push(self.get_oid_at_position(usize::try_from(first_parent)?)?);
+
+        if second_parent == PARENT_NONE {
+            // Not a merge commit.
+        } else if second_parent & PARENT_EXTRA_EDGES != 0 {
+            let extra_edges_offset = layer
+                .extra_edges_offset
+                .ok_or_else(|| eyre!("Missing commit-graph extra edges chunk"))?;
+            let mut edge_index = usize::try_from(second_parent & !PARENT_EXTRA_EDGES)?;
+            loop {
+                let edge = read_u32(&layer.data, extra_edges_offset + edge_index * 4)?;
+                parent_oids
+                    .push(self.get_oid_at_position(usize::try_from(edge & !EXTRA_EDGE_LAST)?)?);
+                if edge & EXTRA_EDGE_LAST != 0 {
+                    break;
+                }
+                edge_index += 1;
+            }
+        } else {
+            parent_oids.push(self.get_oid_at_position(usize::try_from(second_parent)?)?);
+        }
+        Ok(Some(parent_oids))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::make_git;
+
+    fn assert_parents_match(repo: &Repo, commit_graph: &CommitGraph) -> eyre::Result<()> {
+        let head_oid = repo.get_head_info()?.oid.unwrap();
+        let mut oids = vec![head_oid];
+        let mut num_checked = 0;
+        while let Some(oid) = oids.pop() {
+            let commit = repo.find_commit_or_fail(oid)?;
+            let parent_oids = commit.get_parent_oids();
+            assert_eq!(
+                commit_graph.get_parent_oids(oid)?,
+                Some(parent_oids.clone()),
+                "Parents of {:?}",
+                oid
+            );
+            oids.extend(parent_oids);
+            num_checked += 1;
+        }
+        assert!(num_checked > 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_graph_parents() -> eyre::Result<()> {
+        let git = make_git()?;
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["branch", "foo"])?;
+        git.run(&["branch", "bar"])?;
+        git.commit_file("test2", 2)?;
+        git.run(&["checkout", "foo"])?;
+        git.commit_file("test3", 3)?;
+        git.run(&["checkout", "bar"])?;
+        git.commit_file("test4", 4)?;
+        git.run(&["checkout", "master"])?;
+        git.run(&["merge", "--no-edit", "foo", "bar"])?;
+
+        let repo = git.get_repo()?;
+        assert!(CommitGraph::open(&repo)?.is_none());
+
+        git.run(&["commit-graph", "write", "--reachable"])?;
+        let commit_graph = CommitGraph::open(&repo)?.unwrap();
+        assert_parents_match(&repo, &commit_graph)?;
+        // The octopus merge's parents after the first are stored separately.
+        let merge_oid = repo.get_head_info()?.oid.unwrap();
+        assert_eq!(
+            commit_graph
+                .get_parent_oids(merge_oid)?
+                .map(|oids| oids.len()),
+            Some(3)
+        );
+
+        let new_oid = git.commit_file("test5", 5)?;
+        assert_eq!(commit_graph.get_parent_oids(new_oid)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_graph_chain() -> eyre::Result<()> {
+        let git = make_git()?;
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["commit-graph", "write", "--reachable", "--split=no-merge"])?;
+        git.commit_file("test3", 3)?;
+        git.commit_file("test4", 4)?;
+        git.run(&["commit-graph", "write", "--reachable", "--split=no-merge"])?;
+
+        let repo = git.get_repo()?;
+        let commit_graph = CommitGraph::open(&repo)?.unwrap();
+        assert_eq!(commit_graph.layers.len(), 2);
+        assert_parents_match(&repo, &commit_graph)?;
+
+        Ok(())
+    }
+}
@@ -2,18 +2,98 @@ use std::fmt::Display;
 use std::path::{Path, PathBuf};
 
 use eyre::Context;
-use tracing::instrument;
+use regex::Regex;
+use tracing::{instrument, warn};
 
 use super::repo::wrap_git_error;
 
 /// Wrapper around the config values stored on disk for Git.
 pub struct Config {
     inner: git2::Config,
+
+    /// Entries passed via the `GIT_CONFIG_COUNT` family of environment
+    /// variables, in order, with normalized keys. These take precedence over
+    /// the entries in `inner`.
+    env_entries: Vec<(String, String)>,
 }
 
 impl From<git2::Config> for Config {
     fn from(config: git2::Config) -> Self {
-        Config { inner: config }
+        Config {
+            inner: config,
+            env_entries: Vec::new(),
+        }
+    }
+}
+
+/// Normalize a config key for comparison. Section and variable names are
+/// case-insensitive, but subsection names are case-sensitive.
+fn normalize_config_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first_dot), Some(last_dot)) => format!(
+            "{}{}{}",
+            key[..first_dot].to_lowercase(),
+            &key[first_dot..last_dot],
+            key[last_dot..].to_lowercase()
+        ),
+        _ => key.to_lowercase(),
+    }
+}
+
+/// Read the config entries passed via the `GIT_CONFIG_COUNT`,
+/// `GIT_CONFIG_KEY_<n>`, and `GIT_CONFIG_VALUE_<n>` environment variables, in
+/// the same way as Git.
+#[instrument]
+fn get_env_config_entries() -> eyre::Result<Vec<(String, String)>> {
+    let count = match std::env::var("GIT_CONFIG_COUNT") {
+        Ok(count) if !count.is_empty() => count,
+        _ => return Ok(Vec::new()),
+    };
+    let count: usize = count
+        .parse()
+        .map_err(|_| eyre::eyre!("Bogus count in GIT_CONFIG_COUNT: {:?}", count))?;
+
+    let mut result = Vec::new();
+    for i in 0..count {
+        let key = std::env::var(format!("GIT_CONFIG_KEY_{}", i))
+            .wrap_err_with(|| format!("Missing config key GIT_CONFIG_KEY_{}", i))?;
+        let value = std::env::var(format!("GIT_CONFIG_VALUE_{}", i))
+            .wrap_err_with(|| format!("Missing config value GIT_CONFIG_VALUE_{}", i))?;
+        result.push((normalize_config_key(&key), value));
+    }
+    Ok(result)
+}
+
+/// Get the path of the global config file which Git would write to, i.e. the
+/// file that `git config --global` modifies.
+///
+/// This is the file named by `GIT_CONFIG_GLOBAL` if it's set. Otherwise, it's
+/// `~/.gitconfig`, unless that file doesn't exist but
+/// `$XDG_CONFIG_HOME/git/config` (by default `~/.config/git/config`) does.
+#[instrument]
+pub fn get_global_config_path() -> eyre::Result<PathBuf> {
+    if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return Ok(PathBuf::from(path));
+    }
+
+    let home_dir = match std::env::var_os("HOME") {
+        Some(home_dir) => PathBuf::from(home_dir),
+        None => eyre::bail!("Could not determine the global config path: HOME is not set"),
+    };
+    let home_config_path = home_dir.join(".gitconfig");
+    if home_config_path.exists() {
+        return Ok(home_config_path);
+    }
+
+    let xdg_config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(xdg_config_home) if !xdg_config_home.is_empty() => PathBuf::from(xdg_config_home),
+        _ => home_dir.join(".config"),
+    };
+    let xdg_config_path = xdg_config_dir.join("git").join("config");
+    if xdg_config_path.exists() {
+        Ok(xdg_config_path)
+    } else {
+        Ok(home_config_path)
     }
 }
 
@@ -72,9 +152,17 @@ impl Display for ConfigValue {
 pub trait GetConfigValue<V> {
     /// Get the given type of value from the config object.
     fn get_from_config(config: &Config, key: impl AsRef<str>) -> eyre::Result<Option<V>>;
+
+    /// Parse the given type of value from a string, such as one provided via
+    /// an environment variable, in the same way as Git.
+    fn parse_config_value(value: &str) -> eyre::Result<V>;
 }
 
 impl GetConfigValue<String> for String {
+    fn parse_config_value(value: &str) -> eyre::Result<String> {
+        Ok(value.to_string())
+    }
+
     fn get_from_config(config: &Config, key: impl AsRef<str>) -> eyre::Result<Option<String>> {
         #[instrument]
         fn inner(config: &Config, key: &str) -> eyre::Result<Option<String>> {
@@ -93,6 +181,17 @@ impl GetConfigValue<String> for String {
 }
 
 impl GetConfigValue<bool> for bool {
+    fn parse_config_value(value: &str) -> eyre::Result<bool> {
+        match value.to_lowercase().as_str() {
+            "true" | "yes" | "on" => Ok(true),
+            "false" | "no" | "off" | "" => Ok(false),
+            value => match i32::parse_config_value(value) {
+                Ok(value) => Ok(value != 0),
+                Err(_) => eyre::bail!("Invalid boolean config value: {:?}", value),
+            },
+        }
+    }
+
     fn get_from_config(config: &Config, key: impl AsRef<str>) -> eyre::Result<Option<bool>> {
         #[instrument]
         fn inner(config: &Config, key: &str) -> eyre::Result<Option<bool>> {
@@ -110,7 +209,48 @@ impl GetConfigValue<bool> for bool {
     }
 }
 
+impl GetConfigValue<i32> for i32 {
+    fn parse_config_value(value: &str) -> eyre::Result<i32> {
+        let (digits, factor) = match value.chars().last().map(|c| c.to_ascii_lowercase()) {
+            Some('k') => (&value[..value.len() - 1], 1024),
+            Some('m') => (&value[..value.len() - 1], 1024 * 1024),
+            Some('g') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+            _ => (value, 1),
+        };
+        digits
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .and_then(|value| value.checked_mul(factor))
+            .ok_or_else(|| eyre::eyre!("Invalid int config value: {:?}", value))
+    }
+
+    fn get_from_config(config: &Config, key: impl AsRef<str>) -> eyre::Result<Option<i32>> {
+        #[instrument]
+        fn inner(config: &Config, key: &str) -> eyre::Result<Option<i32>> {
+            let value = match config.inner.get_i32(key) {
+                Ok(value) => Some(value),
+                Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+                Err(err) => {
+                    return Err(wrap_git_error(err)).wrap_err("Looking up int value for config key")
+                }
+            };
+            Ok(value)
+        }
+        inner(config, key.as_ref())
+    }
+}
+
 impl GetConfigValue<PathBuf> for PathBuf {
+    fn parse_config_value(value: &str) -> eyre::Result<PathBuf> {
+        match (value.strip_prefix("~/"), std::env::var_os("HOME")) {
+            (Some(relative_path), Some(home_dir)) => {
+                Ok(PathBuf::from(home_dir).join(relative_path))
+            }
+            _ => Ok(PathBuf::from(value)),
+        }
+    }
+
     fn get_from_config(config: &Config, key: impl AsRef<str>) -> eyre::Result<Option<PathBuf>> {
         #[instrument]
         fn inner(config: &Config, key: &str) -> eyre::Result<Option<PathBuf>> {
@@ -156,6 +296,40 @@ pub trait ConfigRead {
             None => Ok(default()),
         }
     }
+
+    /// Same as `get`, but if the environment variable `env_var` is set to a
+    /// non-empty value, it takes precedence over the config key. Values of
+    /// `env_var` which can't be parsed are ignored.
+    fn get_or_env<V: GetConfigValue<V>, S: AsRef<str>>(
+        &self,
+        key: S,
+        env_var: &str,
+    ) -> eyre::Result<Option<V>> {
+        match std::env::var(env_var) {
+            Ok(value) if !value.is_empty() => match V::parse_config_value(&value) {
+                Ok(value) => return Ok(Some(value)),
+                Err(err) => {
+                    warn!(
+                        ?env_var,
+                        ?value,
+                        ?err,
+                        "Ignoring invalid environment variable"
+                    );
+                }
+            },
+            _ => {}
+        }
+        self.get(key)
+    }
+
+    /// Get all values of a multivariable config key, in the order that Git
+    /// reads them. Returns an empty list if the key isn't set.
+    fn get_all(&self, key: impl AsRef<str>) -> eyre::Result<Vec<String>>;
+
+    /// Get all config entries whose names match the provided regex, in the
+    /// order that Git reads them. Later entries take precedence over earlier
+    /// ones with the same name.
+    fn list(&self, name_regex: &str) -> eyre::Result<Vec<(String, String)>>;
 }
 
 impl ConfigRead for Config {
@@ -165,7 +339,69 @@ impl ConfigRead for Config {
 
     /// Get a config key of one of various possible types.
     fn get<V: GetConfigValue<V>, S: AsRef<str>>(&self, key: S) -> eyre::Result<Option<V>> {
-        V::get_from_config(self, key)
+        let normalized_key = normalize_config_key(key.as_ref());
+        match self
+            .env_entries
+            .iter()
+            .rev()
+            .find(|(entry_key, _value)| entry_key == &normalized_key)
+        {
+            Some((_key, value)) => Ok(Some(V::parse_config_value(value)?)),
+            None => V::get_from_config(self, key),
+        }
+    }
+
+    #[instrument(skip(key))]
+    fn get_all(&self, key: impl AsRef<str>) -> eyre::Result<Vec<String>> {
+        let normalized_key = normalize_config_key(key.as_ref());
+        let env_values = self
+            .env_entries
+            .iter()
+            .filter(|(entry_key, _value)| entry_key == &normalized_key)
+            .map(|(_key, value)| value.clone());
+        let entries = match self.inner.multivar(key.as_ref(), None) {
+            Ok(entries) => entries,
+            Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(env_values.collect()),
+            Err(err) => {
+                return Err(wrap_git_error(err))
+                    .wrap_err("Looking up multivar values for config key")
+            }
+        };
+        let mut result = Vec::new();
+        for entry in &entries {
+            let entry = entry.map_err(wrap_git_error)?;
+            result.push(String::from_utf8_lossy(entry.value_bytes()).into_owned());
+        }
+        result.extend(env_values);
+        Ok(result)
+    }
+
+    #[instrument]
+    fn list(&self, name_regex: &str) -> eyre::Result<Vec<(String, String)>> {
+        let entries = self
+            .inner
+            .entries(Some(name_regex))
+            .map_err(wrap_git_error)
+            .wrap_err("Listing config entries")?;
+        let mut result = Vec::new();
+        for entry in &entries {
+            let entry = entry.map_err(wrap_git_error)?;
+            if let Some(name) = entry.name() {
+                let value = String::from_utf8_lossy(entry.value_bytes()).into_owned();
+                result.push((name.to_string(), value));
+            }
+        }
+
+        if !self.env_entries.is_empty() {
+            let name_regex = Regex::new(name_regex).wrap_err("Compiling config name regex")?;
+            result.extend(
+                self.env_entries
+                    .iter()
+                    .filter(|(key, _value)| name_regex.is_match(key))
+                    .cloned(),
+            );
+        }
+        Ok(result)
     }
 }
 
@@ -201,7 +437,37 @@ impl Config {
     #[instrument]
     pub fn open(path: &Path) -> eyre::Result<Self> {
         let inner = git2::Config::open(path).map_err(wrap_git_error)?;
-        Ok(Config { inner })
+        Ok(Config::from(inner))
+    }
+
+    /// Open the global configuration file, as determined by
+    /// `get_global_config_path`, for writing.
+    #[instrument]
+    pub fn open_global() -> eyre::Result<Self> {
+        let path = get_global_config_path()?;
+        Self::open(&path)
+    }
+
+    /// Apply the environment variables which Git uses to override the
+    /// configuration, but which libgit2 doesn't respect:
+    ///
+    /// - `GIT_CONFIG_GLOBAL` replaces the global configuration file.
+    /// - The entries passed via `GIT_CONFIG_COUNT`, `GIT_CONFIG_KEY_<n>`, and
+    /// `GIT_CONFIG_VALUE_<n>` take precedence over all configuration files.
+    #[instrument]
+    pub fn with_env_overrides(mut self) -> eyre::Result<Self> {
+        if let Some(global_config_path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+            self.inner
+                .add_file(
+                    Path::new(&global_config_path),
+                    git2::ConfigLevel::Global,
+                    true,
+                )
+                .map_err(wrap_git_error)
+                .wrap_err("Adding config file from GIT_CONFIG_GLOBAL")?;
+        }
+        self.env_entries = get_env_config_entries()?;
+        Ok(self)
     }
 
     #[instrument]
@@ -273,3 +539,32 @@ impl ConfigWrite for Config {
         self.remove_multivar_inner(key.as_ref(), regex.as_ref())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_config_values() -> eyre::Result<()> {
+        assert_eq!(
+            normalize_config_key("Branchless.Core.MainBranch"),
+            "branchless.Core.mainbranch"
+        );
+        assert_eq!(
+            normalize_config_key("Init.DefaultBranch"),
+            "init.defaultbranch"
+        );
+
+        assert!(bool::parse_config_value("Yes")?);
+        assert!(bool::parse_config_value("1")?);
+        assert!(!bool::parse_config_value("")?);
+        assert!(!bool::parse_config_value("off")?);
+        assert!(bool::parse_config_value("maybe").is_err());
+
+        assert_eq!(i32::parse_config_value("12")?, 12);
+        assert_eq!(i32::parse_config_value("2k")?, 2048);
+        assert!(i32::parse_config_value("3g").is_err());
+
+        Ok(())
+    }
+}
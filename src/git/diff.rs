@@ -0,0 +1,410 @@
+//! Summarize the differences between two commits, in the style of `git diff
+//! --stat`, render them as patches, and split uncommitted changes into hunks.
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use eyre::Context;
+use tracing::instrument;
+
+use super::repo::wrap_git_error;
+use super::{NonZeroOid, Repo, Tree};
+
+/// The maximum number of files to list individually when rendering a
+/// `DiffStat`. Larger diffs are rendered only as a summary line.
+pub const MAX_DIFF_STAT_FILES: usize = 25;
+
+/// The maximum width of the `+`/`-` graph drawn for each file.
+const MAX_GRAPH_WIDTH: usize = 40;
+
+/// The change to a single file in a `DiffStat`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileDiffStat {
+    /// A text file, for which the number of changed lines is known.
+    Text {
+        /// The path to the file.
+        path: PathBuf,
+
+        /// The number of added lines.
+        num_insertions: usize,
+
+        /// The number of removed lines.
+        num_deletions: usize,
+    },
+
+    /// A binary file, which doesn't have meaningful line counts.
+    Binary {
+        /// The path to the file.
+        path: PathBuf,
+    },
+}
+
+impl FileDiffStat {
+    fn get_path(&self) -> &PathBuf {
+        match self {
+            FileDiffStat::Text { path, .. } | FileDiffStat::Binary { path } => path,
+        }
+    }
+}
+
+/// The files changed between two commits, along with the number of lines
+/// changed in each.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffStat {
+    /// The changed files, in the order that Git reports them.
+    pub files: Vec<FileDiffStat>,
+}
+
+impl DiffStat {
+    /// Get the total number of added lines across all text files.
+    pub fn get_num_insertions(&self) -> usize {
+        self.files
+            .iter()
+            .map(|file| match file {
+                FileDiffStat::Text { num_insertions, .. } => *num_insertions,
+                FileDiffStat::Binary { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// Get the total number of removed lines across all text files.
+    pub fn get_num_deletions(&self) -> usize {
+        self.files
+            .iter()
+            .map(|file| match file {
+                FileDiffStat::Text { num_deletions, .. } => *num_deletions,
+                FileDiffStat::Binary { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// Render the diffstat as lines of text, like `git diff --stat` would. If
+    /// more than `max_files` files were changed, then only the summary line is
+    /// rendered.
+    pub fn render(&self, max_files: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.files.len() <= max_files {
+            let path_width = self
+                .files
+                .iter()
+                .map(|file| file.get_path().to_string_lossy().len())
+                .max()
+                .unwrap_or_default();
+            let max_num_changes = self
+                .files
+                .iter()
+                .map(|file| match file {
+                    FileDiffStat::Text {
+                        num_insertions,
+                        num_deletions,
+                        ..
+                    } => num_insertions + num_deletions,
+                    FileDiffStat::Binary { .. } => 0,
+                })
+                .max()
+                .unwrap_or_default();
+            let count_width = if self
+                .files
+                .iter()
+                .any(|file| matches!(file, FileDiffStat::Binary { .. }))
+            {
+                max_num_changes.to_string().len().max("Bin".len())
+            } else {
+                max_num_changes.to_string().len()
+            };
+
+            for file in self.files.iter() {
+                let path = file.get_path().to_string_lossy();
+                let line = match file {
+                    FileDiffStat::Text {
+                        path: _,
+                        num_insertions,
+                        num_deletions,
+                    } => {
+                        let num_changes = num_insertions + num_deletions;
+                        let (num_plus, num_minus) = if max_num_changes <= MAX_GRAPH_WIDTH {
+                            (*num_insertions, *num_deletions)
+                        } else {
+                            // Scale the graph down, but always show at least
+                            // one character for a nonzero count.
+                            let scale = |n: usize| match n {
+                                0 => 0,
+                                n => (n * MAX_GRAPH_WIDTH / max_num_changes).max(1),
+                            };
+                            (scale(*num_insertions), scale(*num_deletions))
+                        };
+                        format!(
+                            " {:path_width$} | {:>count_width$} {}{}",
+                            path,
+                            num_changes,
+                            "+".repeat(num_plus),
+                            "-".repeat(num_minus),
+                            path_width = path_width,
+                            count_width = count_width,
+                        )
+                    }
+                    FileDiffStat::Binary { path: _ } => format!(
+                        " {:path_width$} | {:>count_width$}",
+                        path,
+                        "Bin",
+                        path_width = path_width,
+                        count_width = count_width,
+                    ),
+                };
+                lines.push(line.trim_end().to_string());
+            }
+        }
+        lines.push(self.render_summary());
+        lines
+    }
+
+    /// Render the summary line of the diffstat, such as `2 files changed, 3
+    /// insertions(+), 1 deletion(-)`.
+    pub fn render_summary(&self) -> String {
+        let pluralize = |amount: usize, singular: &str, plural: &str| {
+            format!("{} {}", amount, if amount == 1 { singular } else { plural })
+        };
+
+        let mut summary = vec![pluralize(self.files.len(), "file changed", "files changed")];
+        let num_insertions = self.get_num_insertions();
+        if num_insertions > 0 {
+            summary.push(pluralize(num_insertions, "insertion(+)", "insertions(+)"));
+        }
+        let num_deletions = self.get_num_deletions();
+        if num_deletions > 0 {
+            summary.push(pluralize(num_deletions, "deletion(-)", "deletions(-)"));
+        }
+        format!(" {}", summary.join(", "))
+    }
+}
+
+/// Compute the diffstat between the trees of the two given commits.
+#[instrument]
+pub fn get_diff_stat(
+    repo: &Repo,
+    old_commit_oid: NonZeroOid,
+    new_commit_oid: NonZeroOid,
+) -> eyre::Result<DiffStat> {
+    let old_commit = repo.find_commit_or_fail(old_commit_oid)?;
+    let old_tree = old_commit.get_tree()?;
+    let new_commit = repo.find_commit_or_fail(new_commit_oid)?;
+    let new_tree = new_commit.get_tree()?;
+    let diff = repo
+        .inner
+        .diff_tree_to_tree(Some(&old_tree.inner), Some(&new_tree.inner), None)
+        .wrap_err_with(|| {
+            format!(
+                "Calculating diff between: {:?} and {:?}",
+                old_commit_oid, new_commit_oid
+            )
+        })?;
+
+    let mut files = Vec::new();
+    for delta_idx in 0..diff.deltas().len() {
+        let patch = match git2::Patch::from_diff(&diff, delta_idx).map_err(wrap_git_error)? {
+            Some(patch) => patch,
+            None => continue,
+        };
+        let delta = patch.delta();
+        let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+            Some(path) => path.to_path_buf(),
+            None => continue,
+        };
+        let file = if delta.flags().is_binary() {
+            FileDiffStat::Binary { path }
+        } else {
+            let (_num_context, num_insertions, num_deletions) =
+                patch.line_stats().map_err(wrap_git_error)?;
+            FileDiffStat::Text {
+                path,
+                num_insertions,
+                num_deletions,
+            }
+        };
+        files.push(file);
+    }
+    Ok(DiffStat { files })
+}
+
+/// Render the changes from `old_tree` (or from an empty tree, if not provided)
+/// to `new_tree` as a patch, in the style of `git diff --binary`, so that the
+/// result can be applied with `git apply`.
+#[instrument]
+pub fn get_patch_text(
+    repo: &Repo,
+    old_tree: Option<&Tree>,
+    new_tree: &Tree,
+) -> eyre::Result<Vec<u8>> {
+    let mut diff_options = git2::DiffOptions::new();
+    diff_options.show_binary(true);
+    let diff = repo
+        .inner
+        .diff_tree_to_tree(
+            old_tree.map(|tree| &tree.inner),
+            Some(&new_tree.inner),
+            Some(&mut diff_options),
+        )
+        .map_err(wrap_git_error)
+        .wrap_err("Calculating diff between trees")?;
+
+    let mut result = Vec::new();
+    diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+        if let origin @ ('+' | '-' | ' ') = line.origin() {
+            result.push(origin as u8);
+        }
+        result.extend(line.content());
+        true
+    })
+    .map_err(wrap_git_error)
+    .wrap_err("Printing patch")?;
+    Ok(result)
+}
+
+/// A contiguous run of changed lines in a text file, as produced by a diff
+/// without any context lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    /// The path to the file.
+    pub path: PathBuf,
+
+    /// The index (0-based) of the first removed line in the old version of
+    /// the file. If no lines were removed, this is the index of the line
+    /// which the added lines were inserted before.
+    pub old_start: usize,
+
+    /// The lines removed from the old version of the file, including their
+    /// line endings.
+    pub old_lines: Vec<Vec<u8>>,
+
+    /// The lines added in the new version of the file, including their line
+    /// endings.
+    pub new_lines: Vec<Vec<u8>>,
+}
+
+/// The uncommitted changes to tracked files, split into hunks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UncommittedHunks {
+    /// The hunks of changes to text files which exist both before and after
+    /// the changes.
+    pub hunks: Vec<Hunk>,
+
+    /// The files whose changes can't be represented as hunks, such as binary
+    /// files, or files which were added, deleted, renamed, or had their mode
+    /// changed.
+    pub other_paths: Vec<PathBuf>,
+}
+
+/// Compute the changes from the given tree to the index (if `staged` is set)
+/// or to the working copy, split into hunks without context lines, so that
+/// adjacent changed lines are only grouped together if they're contiguous.
+/// Untracked files are ignored.
+#[instrument]
+pub fn get_uncommitted_hunks(
+    repo: &Repo,
+    tree: &Tree,
+    staged: bool,
+) -> eyre::Result<UncommittedHunks> {
+    let mut diff_options = git2::DiffOptions::new();
+    diff_options.context_lines(0);
+    let diff = if staged {
+        repo.inner
+            .diff_tree_to_index(Some(&tree.inner), None, Some(&mut diff_options))
+    } else {
+        repo.inner
+            .diff_tree_to_workdir_with_index(Some(&tree.inner), Some(&mut diff_options))
+    }
+    .map_err(wrap_git_error)
+    .wrap_err("Calculating diff of uncommitted changes")?;
+
+    let mut result = UncommittedHunks::default();
+    for delta_idx in 0..diff.deltas().len() {
+        let patch = match git2::Patch::from_diff(&diff, delta_idx).map_err(wrap_git_error)? {
+            Some(patch) => patch,
+            None => continue,
+        };
+        let delta = patch.delta();
+        let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+            Some(path) => path.to_path_buf(),
+            None => continue,
+        };
+        let is_text_modification = delta.status() == git2::Delta::Modified
+            && !delta.flags().is_binary()
+            && delta.old_file().mode() == delta.new_file().mode()
+            && delta.new_file().mode() != git2::FileMode::Link;
+        if !is_text_modification {
+            result.other_paths.push(path);
+            continue;
+        }
+
+        for hunk_idx in 0..patch.num_hunks() {
+            let (diff_hunk, num_lines) = patch.hunk(hunk_idx).map_err(wrap_git_error)?;
+            let mut old_lines = Vec::new();
+            let mut new_lines = Vec::new();
+            for line_idx in 0..num_lines {
+                let line = patch
+                    .line_in_hunk(hunk_idx, line_idx)
+                    .map_err(wrap_git_error)?;
+                match line.origin_value() {
+                    git2::DiffLineType::Deletion => old_lines.push(line.content().to_vec()),
+                    git2::DiffLineType::Addition => new_lines.push(line.content().to_vec()),
+                    _ => {}
+                }
+            }
+
+            // Git reports the start of a hunk which only adds lines as the
+            // line after which the lines are added.
+            let old_start = usize::try_from(diff_hunk.old_start())?;
+            let old_start = if old_lines.is_empty() {
+                old_start
+            } else {
+                old_start.saturating_sub(1)
+            };
+            result.hunks.push(Hunk {
+                path: path.clone(),
+                old_start,
+                old_lines,
+                new_lines,
+            });
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_diff_stat() {
+        let diff_stat = DiffStat {
+            files: vec![
+                FileDiffStat::Text {
+                    path: PathBuf::from("foo.txt"),
+                    num_insertions: 3,
+                    num_deletions: 1,
+                },
+                FileDiffStat::Binary {
+                    path: PathBuf::from("image.png"),
+                },
+                FileDiffStat::Text {
+                    path: PathBuf::from("big.txt"),
+                    num_insertions: 0,
+                    num_deletions: 200,
+                },
+            ],
+        };
+        insta::assert_debug_snapshot!(diff_stat.render(MAX_DIFF_STAT_FILES), @r###"
+        [
+            " foo.txt   |   4 +-",
+            " image.png | Bin",
+            " big.txt   | 200 ----------------------------------------",
+            " 3 files changed, 3 insertions(+), 201 deletions(-)",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(diff_stat.render(2), @r###"
+        [
+            " 3 files changed, 3 insertions(+), 201 deletions(-)",
+        ]
+        "###);
+    }
+}
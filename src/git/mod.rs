@@ -1,17 +1,28 @@
 //! Tools for interfacing with the Git repository.
 
+mod blame;
+mod commit_graph;
 mod config;
+mod diff;
 mod oid;
 mod repo;
 mod run;
+mod sign;
 mod tree;
 
-pub use config::{Config, ConfigRead, ConfigValue, ConfigWrite};
+pub use blame::{blame_file, BlamedLine};
+pub use commit_graph::CommitGraph;
+pub use config::{get_global_config_path, Config, ConfigRead, ConfigValue, ConfigWrite};
+pub use diff::{
+    get_diff_stat, get_patch_text, get_uncommitted_hunks, DiffStat, FileDiffStat, Hunk,
+    UncommittedHunks, MAX_DIFF_STAT_FILES,
+};
 pub use oid::{MaybeZeroOid, NonZeroOid};
 pub use repo::{
-    AmendFastOptions, Branch, CategorizedReferenceName, CherryPickFastError, CherryPickFastOptions,
-    Commit, Diff, FileStatus, GitVersion, PatchId, Reference, ReferenceTarget, Repo,
-    RepoReferencesSnapshot, ResolvedReferenceInfo, StatusEntry,
+    AmbiguousBranchName, AmendFastOptions, Branch, CategorizedReferenceName, CherryPickFastError,
+    CherryPickFastOptions, Commit, Diff, FileStatus, GitVersion, PatchId, Pathspecs, Reference,
+    ReferenceTarget, ReflogEntry, Repo, RepoReferencesSnapshot, ResolvedBranchName,
+    ResolvedReferenceInfo, SequencingOperation, Signature, StatusEntry, WorktreeInfo,
 };
-pub use run::{check_out_commit, GitRunInfo};
-pub use tree::Tree;
+pub use run::{check_out_commit, GitRunInfo, GitRunOpts, GitRunStreamingOpts};
+pub use tree::{hydrate_tree, Tree};
@@ -15,6 +15,7 @@ use std::convert::{TryFrom, TryInto};
 use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::SystemTime;
 
 use chrono::{DateTime, Local, TimeZone, Utc};
@@ -27,10 +28,13 @@ use lazy_static::lazy_static;
 use os_str_bytes::{OsStrBytes, OsStringBytes};
 use regex::bytes::Regex;
 use tracing::{instrument, warn};
+use unicode_normalization::UnicodeNormalization;
 
-use crate::core::config::get_main_branch_name;
+use crate::core::config::{
+    get_eventlog_ref_ignore_patterns, get_main_branch_name, get_main_branch_names, get_rewrite_sign,
+};
 use crate::core::effects::{Effects, OperationType};
-use crate::core::eventlog::EventTransactionId;
+use crate::core::eventlog::{matches_ref_ignore_patterns, EventTransactionId};
 use crate::core::formatting::StyledStringBuilder;
 use crate::core::node_descriptors::{
     render_node_descriptors, CommitMessageDescriptor, CommitOidDescriptor, NodeObject,
@@ -38,12 +42,14 @@ use crate::core::node_descriptors::{
 use crate::git::config::{Config, ConfigRead};
 use crate::git::oid::{make_non_zero_oid, MaybeZeroOid, NonZeroOid};
 use crate::git::run::GitRunInfo;
+use crate::git::sign::{add_signature_header, CommitSigner};
 use crate::git::tree::{dehydrate_tree, get_changed_paths_between_trees, hydrate_tree, Tree};
 
 /// Convert a `git2::Error` into an `eyre::Error` with an auto-generated message.
 pub(super) fn wrap_git_error(error: git2::Error) -> eyre::Error {
     eyre::eyre!("Git error {:?}: {}", error.code(), error.message())
 }
+
 /// A snapshot of information about a certain reference. Updates to the
 /// reference after this value is obtained are not reflected.
 ///
@@ -73,6 +79,18 @@ pub struct ResolvedReferenceInfo<'a> {
 }
 
 impl<'a> ResolvedReferenceInfo<'a> {
+    /// Determine whether the reference is unborn, i.e. it doesn't point to a
+    /// commit yet.
+    pub fn is_unborn(&self) -> bool {
+        self.oid.is_none()
+    }
+
+    /// Determine whether the reference is detached, i.e. it points directly to
+    /// a commit rather than to a branch.
+    pub fn is_detached(&self) -> bool {
+        self.reference_name.is_none()
+    }
+
     /// Get the name of the branch, if any. Returns `None` if `HEAD` is
     /// detached.  The `refs/heads/` prefix, if any, is stripped.
     pub fn get_branch_name(&self) -> eyre::Result<Option<OsString>> {
@@ -99,25 +117,42 @@ pub struct GitVersion(pub isize, pub isize, pub isize);
 impl FromStr for GitVersion {
     type Err = eyre::Error;
 
+    /// Parse the output of `git version`. Vendors and distributions append
+    /// all kinds of suffixes to the version number, such as
+    /// `2.32.1 (Apple Git-133)`, `2.37.1.windows.1`, or `2.38.0-rc2`, so only
+    /// the leading numeric part of each of the first three components is
+    /// considered. A missing patch number is treated as `0`.
     #[instrument]
     fn from_str(output: &str) -> eyre::Result<GitVersion> {
-        let output = output.trim();
-        let words = output.split(&[' ', '-'][..]).collect::<Vec<&str>>();
-        let version_str = match &words.as_slice() {
-            [_git, _version, version_str, ..] => version_str,
-            _ => eyre::bail!("Could not parse Git version output: {:?}", output),
-        };
-        match version_str.split('.').collect::<Vec<&str>>().as_slice() {
-            [major, minor, patch, ..] => {
-                let major = major.parse()?;
-                let minor = minor.parse()?;
+        fn parse_leading_number(component: &str) -> Option<isize> {
+            let end = component
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(component.len());
+            component[..end].parse().ok()
+        }
 
+        let output = output.trim();
+        let version_str = output
+            .strip_prefix("git version")
+            .unwrap_or(output)
+            .trim_start();
+        let version_str = version_str
+            .split(|c: char| c.is_whitespace() || c == '(')
+            .next()
+            .unwrap_or_default();
+        let mut components = version_str.split('.');
+        let major = components.next().and_then(parse_leading_number);
+        let minor = components.next().and_then(parse_leading_number);
+        match (major, minor) {
+            (Some(major), Some(minor)) => {
                 // Example version without a real patch number: `2.33.GIT`.
-                let patch: isize = patch.parse().unwrap_or_default();
-
+                let patch = components
+                    .next()
+                    .and_then(parse_leading_number)
+                    .unwrap_or_default();
                 Ok(GitVersion(major, minor, patch))
             }
-            _ => eyre::bail!("Could not parse Git version string: {}", version_str),
+            _ => eyre::bail!("Could not parse Git version output: {:?}", output),
         }
     }
 }
@@ -170,16 +205,52 @@ pub struct RepoReferencesSnapshot {
     /// The location of the `HEAD` reference. This may be `None` if `HEAD` is unborn.
     pub head_oid: Option<NonZeroOid>,
 
-    /// The location of the main branch.
-    pub main_branch_oid: NonZeroOid,
+    /// The location of the main branch. This may be `None` if `HEAD` is
+    /// unborn and the main branch hasn't been created yet.
+    pub main_branch_oid: Option<NonZeroOid>,
+
+    /// The locations of any additional main branches configured via
+    /// `branchless.core.mainBranches`. Additional main branches which don't
+    /// exist are omitted.
+    pub additional_main_branch_oids: Vec<NonZeroOid>,
 
     /// A mapping from commit OID to the branches which point to that commit.
     pub branch_oid_to_names: HashMap<NonZeroOid, HashSet<OsString>>,
 }
 
+/// An entry in the reflog of a reference.
+#[derive(Clone, Debug)]
+pub struct ReflogEntry {
+    /// The OID that the reference pointed to before the update.
+    pub old_oid: MaybeZeroOid,
+
+    /// The OID that the reference pointed to after the update.
+    pub new_oid: MaybeZeroOid,
+
+    /// The time of the update.
+    pub timestamp: SystemTime,
+
+    /// The message describing the update, such as `commit: fix bug`.
+    pub message: Option<String>,
+}
+
+/// An operation which applies a sequence of commits one at a time, such as a
+/// rebase or a multi-commit cherry-pick, and which is currently in progress.
+/// Git invokes hooks for each commit which it applies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequencingOperation {
+    /// The name of the operation, such as `rebase` or `cherry-pick`.
+    pub name: String,
+
+    /// When the operation was started. This can be used to tell apart
+    /// successive operations of the same kind.
+    pub start_time: SystemTime,
+}
+
 /// Wrapper around `git2::Repository`.
 pub struct Repo {
     pub(super) inner: git2::Repository,
+    commit_load_count: AtomicUsize,
 }
 
 impl std::fmt::Debug for Repo {
@@ -189,11 +260,18 @@ impl std::fmt::Debug for Repo {
 }
 
 impl Repo {
+    fn from_inner(inner: git2::Repository) -> Self {
+        Repo {
+            inner,
+            commit_load_count: AtomicUsize::new(0),
+        }
+    }
+
     /// Get the Git repository associated with the given directory.
     #[instrument]
     pub fn from_dir(path: &Path) -> eyre::Result<Self> {
         let repo = git2::Repository::discover(path).map_err(wrap_git_error)?;
-        Ok(Repo { inner: repo })
+        Ok(Repo::from_inner(repo))
     }
 
     /// Get the Git repository associated with the current directory.
@@ -203,11 +281,24 @@ impl Repo {
         Repo::from_dir(&path)
     }
 
+    /// Get the Git repository specified by the environment, such as via
+    /// `GIT_DIR`, `GIT_WORK_TREE`, and `GIT_COMMON_DIR`. If those aren't set,
+    /// discovers the repository from the current directory.
+    ///
+    /// This should be used by hooks, since Git may invoke them with these
+    /// variables set, in which case the current directory may not correspond
+    /// to the repository being operated on.
+    #[instrument]
+    pub fn from_env() -> eyre::Result<Self> {
+        let repo = git2::Repository::open_from_env().map_err(wrap_git_error)?;
+        Ok(Repo::from_inner(repo))
+    }
+
     /// Open a new copy of the repository.
     pub fn try_clone(&self) -> eyre::Result<Self> {
         let path = self.get_path();
         let repo = git2::Repository::open(path)?;
-        Ok(Repo { inner: repo })
+        Ok(Repo::from_inner(repo))
     }
 
     /// Get the path to the `.git` directory for the repository.
@@ -215,9 +306,136 @@ impl Repo {
         self.inner.path()
     }
 
+    /// Get the path to the common `.git` directory for the repository. For a
+    /// linked worktree, this is the `.git` directory of the main worktree,
+    /// which holds the data shared between all worktrees. Otherwise, this is
+    /// the same as `get_path`.
+    pub fn get_common_path(&self) -> PathBuf {
+        // A linked worktree's `.git` directory contains a `commondir` file
+        // with the (possibly relative) path to the common directory.
+        let path = self.get_path();
+        match std::fs::read_to_string(path.join("commondir")) {
+            Ok(common_dir) => path.join(common_dir.trim_end()),
+            Err(_) => path.to_owned(),
+        }
+    }
+
+    /// If this repository is a linked worktree (as created by `git worktree
+    /// add`), get the name of the worktree. Returns `None` for the main
+    /// worktree.
+    pub fn get_worktree_name(&self) -> Option<&OsStr> {
+        if self.inner.is_worktree() {
+            self.get_path().file_name()
+        } else {
+            None
+        }
+    }
+
+    /// Get all of the worktrees of the repository, including the main worktree
+    /// (unless the repository is bare) and the current worktree. Linked
+    /// worktrees whose directories no longer exist are skipped.
+    #[instrument]
+    pub fn list_worktrees(&self) -> eyre::Result<Vec<WorktreeInfo>> {
+        let common_repo = git2::Repository::open(self.get_common_path())
+            .map_err(wrap_git_error)
+            .wrap_err("Opening main worktree")?;
+
+        let mut worktree_paths: Vec<(Option<OsString>, PathBuf)> = Vec::new();
+        if let Some(working_copy_path) = common_repo.workdir() {
+            worktree_paths.push((None, working_copy_path.to_owned()));
+        }
+        let worktree_names = common_repo
+            .worktrees()
+            .map_err(wrap_git_error)
+            .wrap_err("Listing worktrees")?;
+        for worktree_name in worktree_names.iter().flatten() {
+            let worktree = common_repo
+                .find_worktree(worktree_name)
+                .map_err(wrap_git_error)
+                .wrap_err_with(|| format!("Looking up worktree: {}", worktree_name))?;
+            if worktree.validate().is_err() {
+                continue;
+            }
+            worktree_paths.push((Some(worktree_name.into()), worktree.path().to_owned()));
+        }
+
+        let mut result = Vec::new();
+        for (name, path) in worktree_paths {
+            let worktree_repo = Repo::from_dir(&path)?;
+            let head_info = worktree_repo.get_head_info()?;
+            result.push(WorktreeInfo {
+                name,
+                path,
+                head_oid: head_info.oid,
+                branch_name: head_info
+                    .reference_name
+                    .map(|reference_name| reference_name.into_owned()),
+            });
+        }
+        Ok(result)
+    }
+
     /// Get the path to the `packed-refs` file for the repository.
     pub fn get_packed_refs_path(&self) -> PathBuf {
-        self.inner.path().join("packed-refs")
+        self.get_common_path().join("packed-refs")
+    }
+
+    fn get_shallow_file_path(&self) -> PathBuf {
+        self.get_common_path().join("shallow")
+    }
+
+    /// Determine whether this repository is a shallow clone, i.e. whether some
+    /// commits are missing their parents, as listed in the `.git/shallow`
+    /// file.
+    pub fn is_shallow(&self) -> bool {
+        match std::fs::metadata(self.get_shallow_file_path()) {
+            Ok(metadata) => metadata.len() > 0,
+            Err(_) => false,
+        }
+    }
+
+    /// Get the commits at the boundary of a shallow clone, whose parents are
+    /// not available locally. Returns an empty set if the repository is not a
+    /// shallow clone.
+    #[instrument]
+    pub fn get_shallow_commit_oids(&self) -> eyre::Result<HashSet<NonZeroOid>> {
+        let path = self.get_shallow_file_path();
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("Reading shallow file at: {:?}", &path))
+            }
+        };
+        contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.parse()
+                    .wrap_err_with(|| format!("Parsing shallow commit OID: {:?}", line))
+            })
+            .collect()
+    }
+
+    /// Determine whether this repository is a partial clone, i.e. whether
+    /// objects may be missing locally and fetched on demand from a promisor
+    /// remote.
+    #[instrument]
+    pub fn is_partial_clone(&self) -> eyre::Result<bool> {
+        let config = self.get_readonly_config()?;
+        let partial_clone_remote: Option<String> = config.get("extensions.partialClone")?;
+        if partial_clone_remote.is_some() {
+            return Ok(true);
+        }
+
+        // Recent versions of Git only mark the promisor remote itself.
+        for remote_name in self.get_remote_names()? {
+            if config.get_or(format!("remote.{}.promisor", remote_name), false)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 
     /// Get the path to the directory inside the `.git` directory which contains
@@ -226,6 +444,72 @@ impl Repo {
         self.inner.path().join("rebase-merge")
     }
 
+    /// Determine which sequencing operation, if any, is currently in progress
+    /// in this worktree, based on the state directory which Git keeps for it.
+    ///
+    /// `GIT_REFLOG_ACTION` is only used to name the operation, since it's
+    /// also set by commands such as `git pull` which don't keep any state
+    /// which could identify the operation across hook invocations.
+    #[instrument]
+    pub fn get_sequencing_operation(&self) -> eyre::Result<Option<SequencingOperation>> {
+        // Each state directory contains a file which is written when the
+        // operation starts and not modified afterwards.
+        let candidates = [
+            ("rebase-merge", "orig-head", "rebase"),
+            ("rebase-apply", "orig-head", "rebase"),
+            ("sequencer", "head", "cherry-pick"),
+        ];
+        for (dir_name, file_name, default_name) in candidates {
+            let dir_path = self.get_path().join(dir_name);
+            if !dir_path.is_dir() {
+                continue;
+            }
+
+            // `git am` doesn't write `orig-head`, but it writes `applying` when
+            // it starts instead.
+            let is_am = dir_name == "rebase-apply" && dir_path.join("applying").exists();
+            let file_name = if is_am { "applying" } else { file_name };
+            let metadata = match std::fs::metadata(dir_path.join(file_name)) {
+                Ok(metadata) => metadata,
+                Err(_) => std::fs::metadata(&dir_path)
+                    .wrap_err_with(|| format!("Reading metadata for {:?}", &dir_path))?,
+            };
+            let start_time = metadata.modified()?;
+
+            let name = match std::env::var("GIT_REFLOG_ACTION") {
+                Ok(reflog_action) => match reflog_action.split_whitespace().next() {
+                    Some(name) => name.to_string(),
+                    None => default_name.to_string(),
+                },
+                Err(_) => {
+                    if is_am {
+                        "am".to_string()
+                    } else {
+                        default_name.to_string()
+                    }
+                }
+            };
+            return Ok(Some(SequencingOperation { name, start_time }));
+        }
+        Ok(None)
+    }
+
+    /// Get the file which records the event transaction used for the
+    /// sequencing operation in progress in this worktree (if any).
+    #[instrument]
+    pub fn get_sequencing_transaction_path(&self) -> PathBuf {
+        self.get_path()
+            .join("branchless")
+            .join("sequencing-transaction")
+    }
+
+    /// Get the lock file which is held by commands that rewrite commits (see
+    /// the `lock` module). It's shared by all worktrees, like the event log.
+    #[instrument]
+    pub fn get_operation_lock_path(&self) -> PathBuf {
+        self.get_common_path().join("branchless").join("lock")
+    }
+
     /// Get the path to the working copy for this repository. If the repository
     /// is bare (has no working copy), returns `None`.
     pub fn get_working_copy_path(&self) -> Option<&Path> {
@@ -251,7 +535,15 @@ impl Repo {
             .config()
             .map_err(wrap_git_error)
             .wrap_err("Creating `git2::Config` object")?;
-        Ok(Config::from(config))
+        Config::from(config).with_env_overrides()
+    }
+
+    /// Determine whether git-branchless has been initialized for this
+    /// repository, i.e. whether its `.git/branchless` directory exists. This
+    /// doesn't create any files.
+    #[instrument]
+    pub fn is_branchless_initialized(&self) -> bool {
+        self.get_common_path().join("branchless").is_dir()
     }
 
     /// Get the file where git-branchless-specific Git configuration is stored.
@@ -261,9 +553,22 @@ impl Repo {
     }
 
     /// Get the directory where the DAG for the repository is stored.
+    ///
+    /// In a shallow clone, the DAG treats the shallow boundary commits as root
+    /// commits, so a separate DAG is stored for each set of boundary commits.
+    /// Otherwise, the truncated history would remain in the DAG after the
+    /// repository was deepened.
     #[instrument]
     pub fn get_dag_dir(&self) -> PathBuf {
-        self.get_path().join("branchless").join("dag")
+        let branchless_dir = self.get_common_path().join("branchless");
+        let shallow_contents = match std::fs::read(self.get_shallow_file_path()) {
+            Ok(contents) if !contents.is_empty() => contents,
+            _ => return branchless_dir.join("dag"),
+        };
+        match git2::Oid::hash_object(git2::ObjectType::Blob, &shallow_contents) {
+            Ok(hash) => branchless_dir.join(format!("dag-shallow-{}", &hash.to_string()[..12])),
+            Err(_) => branchless_dir.join("dag"),
+        }
     }
 
     /// Get the directory to store man-pages. Note that this is the `man`
@@ -285,10 +590,12 @@ impl Repo {
         self.get_path().join("branchless").join("tmp")
     }
 
-    /// Get the connection to the SQLite database for this repository.
+    /// Get the connection to the SQLite database for this repository. The
+    /// database is stored in the common `.git` directory, so that all
+    /// worktrees share the same event log.
     #[instrument]
     pub fn get_db_conn(&self) -> eyre::Result<rusqlite::Connection> {
-        let dir = self.get_path().join("branchless");
+        let dir = self.get_common_path().join("branchless");
         std::fs::create_dir_all(&dir).wrap_err("Creating .git/branchless dir")?;
         let path = dir.join("db.sqlite3");
         let conn = rusqlite::Connection::open(&path)
@@ -354,23 +661,113 @@ impl Repo {
         }
     }
 
-    /// Get the `Reference` for the main branch for the repository.
-    pub fn get_main_branch_reference(&self) -> eyre::Result<Reference> {
+    /// Set `HEAD` to be a symbolic reference to the provided reference. Does
+    /// not touch the working copy. If the reference doesn't exist, then `HEAD`
+    /// becomes unborn.
+    #[instrument]
+    pub fn set_head_symbolic(&self, reference_name: &OsStr) -> eyre::Result<()> {
+        let reference_name = reference_name.to_str().ok_or_else(|| {
+            eyre!(
+                "Reference name is not valid UTF-8: {:?}",
+                reference_name.to_string_lossy()
+            )
+        })?;
+        self.inner
+            .set_head(reference_name)
+            .map_err(wrap_git_error)?;
+        Ok(())
+    }
+
+    /// Get the `Reference` for the main branch for the repository, or `None`
+    /// if it doesn't exist.
+    pub fn find_main_branch_reference(&self) -> eyre::Result<Option<Reference<'_>>> {
         let main_branch_name = get_main_branch_name(self)?;
-        match self.find_branch(&main_branch_name, git2::BranchType::Local)? {
+        self.find_main_branch_reference_by_name(&main_branch_name)
+    }
+
+    /// Get the `Reference` for the main branch with the given name, or `None`
+    /// if it doesn't exist. If the local branch has an upstream, the upstream
+    /// branch is returned instead.
+    ///
+    /// The name may be a local branch name (`main`), a remote-tracking branch
+    /// name (`origin/main`), or a full reference name (`refs/heads/main` or
+    /// `refs/remotes/origin/main`).
+    pub fn find_main_branch_reference_by_name(
+        &self,
+        main_branch_name: &str,
+    ) -> eyre::Result<Option<Reference<'_>>> {
+        if let Some(remote_branch_name) = main_branch_name.strip_prefix("refs/remotes/") {
+            let branch = self.find_branch(remote_branch_name, git2::BranchType::Remote)?;
+            return Ok(branch.map(|branch| branch.into_reference()));
+        }
+
+        let main_branch_name = main_branch_name
+            .strip_prefix("refs/heads/")
+            .unwrap_or(main_branch_name);
+        match self.find_branch(main_branch_name, git2::BranchType::Local)? {
             Some(branch) => {
                 let upstream_branch = branch
                     .inner
                     .upstream()
                     .map(|branch| Branch { inner: branch })
                     .unwrap_or_else(|_| branch);
-                Ok(upstream_branch.into_reference())
+                Ok(Some(upstream_branch.into_reference()))
             }
-            None => match self.find_branch(&main_branch_name, git2::BranchType::Remote)? {
-                Some(branch) => Ok(branch.into_reference()),
+            None => match self.find_branch(main_branch_name, git2::BranchType::Remote)? {
+                Some(branch) => Ok(Some(branch.into_reference())),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Get the `Reference`s for the main branches other than the primary one,
+    /// as configured by `branchless.core.mainBranches`. Branches which don't
+    /// exist are skipped.
+    pub fn get_additional_main_branch_references(&self) -> eyre::Result<Vec<Reference<'_>>> {
+        let mut result = Vec::new();
+        for main_branch_name in get_main_branch_names(self)?.into_iter().skip(1) {
+            match self.find_main_branch_reference_by_name(&main_branch_name)? {
+                Some(reference) => result.push(reference),
                 None => {
-                    let suggestion = format!(
-                        r"
+                    warn!(?main_branch_name, "Additional main branch not found");
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Get the name of the reference for the main branch for the repository.
+    ///
+    /// Unlike `get_main_branch`, this succeeds if `HEAD` is unborn and the main
+    /// branch hasn't been created yet, in which case the name of the local
+    /// branch it will be created as is returned.
+    pub fn get_main_branch_reference_name(&self) -> eyre::Result<OsString> {
+        match self.find_main_branch_reference()? {
+            Some(reference) => reference.get_name(),
+            None if self.get_head_info()?.is_unborn() => {
+                let main_branch_name = get_main_branch_name(self)?;
+                if main_branch_name.starts_with("refs/") {
+                    Ok(main_branch_name.into())
+                } else {
+                    Ok(format!("refs/heads/{}", main_branch_name).into())
+                }
+            }
+            None => self.get_main_branch()?.get_name(),
+        }
+    }
+
+    /// Get the `Reference` for the main branch for the repository.
+    ///
+    /// This may be a remote-tracking branch, either because the main branch
+    /// is configured as one (such as `origin/main`), or because the local main
+    /// branch has an upstream. Such a branch can't be checked out or moved
+    /// locally; it's only updated by fetching.
+    pub fn get_main_branch(&self) -> eyre::Result<Reference> {
+        match self.find_main_branch_reference()? {
+            Some(reference) => Ok(reference),
+            None => {
+                let suggestion = format!(
+                    r"
 The main branch {:?} could not be found in your repository
 at path: {:?}.
 These branches exist: {:?}
@@ -378,32 +775,38 @@ Either create it, or update the main branch setting by running:
 
     git config branchless.core.mainBranch <branch>
 ",
-                        get_main_branch_name(self)?,
-                        self.get_path(),
-                        self.get_all_local_branches()?
-                            .into_iter()
-                            .map(|branch| {
-                                branch
-                                    .into_reference()
-                                    .get_name()
-                                    .map(|s| format!("{:?}", s))
-                            })
-                            .collect::<eyre::Result<Vec<String>>>()?,
-                    );
-                    Err(eyre!("Could not find repository main branch")
-                        .with_suggestion(|| suggestion))
-                }
-            },
+                    get_main_branch_name(self)?,
+                    self.get_path(),
+                    self.get_all_local_branches()?
+                        .into_iter()
+                        .map(|branch| {
+                            branch
+                                .into_reference()
+                                .get_name()
+                                .map(|s| format!("{:?}", s))
+                        })
+                        .collect::<eyre::Result<Vec<String>>>()?,
+                );
+                Err(eyre!("Could not find repository main branch").with_suggestion(|| suggestion))
+            }
         }
     }
 
     /// Get the OID corresponding to the main branch.
+    ///
+    /// Returns `None` if `HEAD` is unborn and the main branch hasn't been
+    /// created yet, such as in a freshly-initialized repository. If `HEAD`
+    /// isn't unborn, then a missing main branch is an error.
     #[instrument]
-    pub fn get_main_branch_oid(&self) -> eyre::Result<NonZeroOid> {
-        let main_branch_reference = self.get_main_branch_reference()?;
+    pub fn get_main_branch_oid(&self) -> eyre::Result<Option<NonZeroOid>> {
+        if self.find_main_branch_reference()?.is_none() && self.get_head_info()?.is_unborn() {
+            return Ok(None);
+        }
+
+        let main_branch_reference = self.get_main_branch()?;
         let commit = main_branch_reference.peel_to_commit()?;
         match commit {
-            Some(commit) => Ok(commit.get_oid()),
+            Some(commit) => Ok(Some(commit.get_oid())),
             None => eyre::bail!(
                 "Could not find commit pointed to by main branch: {:?}",
                 main_branch_reference.get_name()?
@@ -414,13 +817,19 @@ Either create it, or update the main branch setting by running:
     /// Get a mapping from OID to the names of branches which point to that OID.
     ///
     /// The returned branch names include the `refs/heads/` prefix, so it must
-    /// be stripped if desired.
+    /// be stripped if desired. Branches matching the patterns in
+    /// `branchless.eventlog.ignoreRefs` are excluded, so that they don't cause
+    /// their commits to be considered visible.
     #[instrument]
     pub fn get_branch_oid_to_names(&self) -> eyre::Result<HashMap<NonZeroOid, HashSet<OsString>>> {
+        let ref_ignore_patterns = get_eventlog_ref_ignore_patterns(self)?;
         let mut result: HashMap<NonZeroOid, HashSet<OsString>> = HashMap::new();
         for branch in self.get_all_local_branches()? {
             let reference = branch.into_reference();
             let reference_name = reference.get_name()?;
+            if matches_ref_ignore_patterns(&reference_name, &ref_ignore_patterns) {
+                continue;
+            }
             let reference_info = self.resolve_reference(&reference)?;
             if let Some(reference_oid) = reference_info.oid {
                 result
@@ -432,25 +841,49 @@ Either create it, or update the main branch setting by running:
 
         // The main branch may be a remote branch, in which case it won't be
         // returned in the iteration above.
-        let main_branch_name = self.get_main_branch_reference()?.get_name()?;
-        let main_branch_oid = self.get_main_branch_oid()?;
-        result
-            .entry(main_branch_oid)
-            .or_insert_with(HashSet::new)
-            .insert(main_branch_name);
+        if let Some(main_branch_oid) = self.get_main_branch_oid()? {
+            let main_branch_name = self.get_main_branch()?.get_name()?;
+            result
+                .entry(main_branch_oid)
+                .or_insert_with(HashSet::new)
+                .insert(main_branch_name);
+        }
+        for reference in self.get_additional_main_branch_references()? {
+            if let Some(commit) = reference.peel_to_commit()? {
+                result
+                    .entry(commit.get_oid())
+                    .or_insert_with(HashSet::new)
+                    .insert(reference.get_name()?);
+            }
+        }
 
         Ok(result)
     }
 
+    /// Get the OIDs of the commits that the additional main branches point to.
+    /// See `get_additional_main_branch_references`.
+    #[instrument]
+    pub fn get_additional_main_branch_oids(&self) -> eyre::Result<Vec<NonZeroOid>> {
+        let mut result = Vec::new();
+        for reference in self.get_additional_main_branch_references()? {
+            if let Some(commit) = reference.peel_to_commit()? {
+                result.push(commit.get_oid());
+            }
+        }
+        Ok(result)
+    }
+
     /// Get the positions of references in the repository.
     pub fn get_references_snapshot(&self) -> eyre::Result<RepoReferencesSnapshot> {
         let head_oid = self.get_head_info()?.oid;
         let main_branch_oid = self.get_main_branch_oid()?;
+        let additional_main_branch_oids = self.get_additional_main_branch_oids()?;
         let branch_oid_to_names = self.get_branch_oid_to_names()?;
 
         Ok(RepoReferencesSnapshot {
             head_oid,
             main_branch_oid,
+            additional_main_branch_oids,
             branch_oid_to_names,
         })
     }
@@ -557,6 +990,58 @@ Either create it, or update the main branch setting by running:
         Ok(Some(Diff { inner: diff }))
     }
 
+    /// Returns status entries for the untracked files in the working copy,
+    /// not including ignored files.
+    #[instrument]
+    pub fn get_untracked_status_entries(&self) -> eyre::Result<Vec<StatusEntry>> {
+        let repo_path = self
+            .get_working_copy_path()
+            .ok_or_else(|| eyre::eyre!("unable to get repo working copy path"))?;
+        let mut status_options = git2::StatusOptions::new();
+        status_options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_ignored(false);
+        let statuses = self
+            .inner
+            .statuses(Some(&mut status_options))
+            .map_err(wrap_git_error)
+            .wrap_err("Getting untracked files")?;
+
+        let mut result = Vec::new();
+        for status in statuses.iter() {
+            if !status.status().contains(git2::Status::WT_NEW) {
+                continue;
+            }
+            let path = PathBuf::from(OsStr::from_raw_bytes(status.path_bytes())?.into_owned());
+            let working_copy_file_mode = match std::fs::symlink_metadata(repo_path.join(&path)) {
+                Ok(metadata) if metadata.file_type().is_symlink() => FileMode::Link,
+                #[cfg(unix)]
+                Ok(metadata) => {
+                    use std::os::unix::fs::PermissionsExt;
+                    if metadata.permissions().mode() & 0o111 != 0 {
+                        FileMode::BlobExecutable
+                    } else {
+                        FileMode::Blob
+                    }
+                }
+                #[cfg(not(unix))]
+                Ok(_metadata) => FileMode::Blob,
+                Err(err) => {
+                    return Err(err).wrap_err_with(|| format!("Reading metadata for {:?}", &path))
+                }
+            };
+            result.push(StatusEntry {
+                index_status: FileStatus::Untracked,
+                working_copy_status: FileStatus::Untracked,
+                working_copy_file_mode,
+                path,
+                orig_path: None,
+            });
+        }
+        Ok(result)
+    }
+
     /// Returns the set of paths currently staged to the repository's index.
     pub fn get_staged_paths(&self) -> eyre::Result<HashSet<PathBuf>> {
         let head_commit_oid = match self.get_head_info()?.oid {
@@ -713,8 +1198,8 @@ Either create it, or update the main branch setting by running:
         // See https://git-scm.com/docs/git-status#_porcelain_format_version_2
         while let Some(line_prefix) = status_bytes.peek() {
             let line = match line_prefix {
-                // Ordinary change entry.
-                b'1' => {
+                // Ordinary change entry or unmerged entry.
+                b'1' | b'u' => {
                     let line = status_bytes
                         .by_ref()
                         .take_while(not_null_terminator)
@@ -762,6 +1247,54 @@ Either create it, or update the main branch setting by running:
         Ok(Reference { inner: reference })
     }
 
+    /// Update the given references in a single reference transaction. Each
+    /// reference is set to point to the given OID, or deleted if the OID is
+    /// zero.
+    ///
+    /// All of the references are locked before any of them are updated, so if
+    /// any of them can't be updated (for example, because another process is
+    /// holding its lock), then none of them are updated.
+    #[instrument]
+    pub fn update_references_atomically(
+        &self,
+        updates: &[(&OsStr, MaybeZeroOid)],
+        log_message: &str,
+    ) -> eyre::Result<()> {
+        let updates = updates
+            .iter()
+            .map(|(name, oid)| match name.to_str() {
+                Some(name) => Ok((name, *oid)),
+                None => eyre::bail!(
+                    "Reference name is not a UTF-8 string (libgit2 limitation): {:?}",
+                    name
+                ),
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let mut transaction = self.inner.transaction().map_err(wrap_git_error)?;
+        for (name, _oid) in updates.iter() {
+            transaction
+                .lock_ref(name)
+                .map_err(wrap_git_error)
+                .wrap_err_with(|| format!("Locking reference: {}", name))?;
+        }
+        for (name, oid) in updates.iter() {
+            match oid {
+                MaybeZeroOid::NonZero(oid) => {
+                    transaction.set_target(name, oid.inner, None, log_message)
+                }
+                MaybeZeroOid::Zero => transaction.remove(name),
+            }
+            .map_err(wrap_git_error)
+            .wrap_err_with(|| format!("Updating reference: {}", name))?;
+        }
+        transaction
+            .commit()
+            .map_err(wrap_git_error)
+            .wrap_err("Committing reference transaction")?;
+        Ok(())
+    }
+
     /// Look up a reference with the given name. Returns `None` if not found.
     #[instrument]
     pub fn find_reference(&self, name: &OsStr) -> eyre::Result<Option<Reference>> {
@@ -795,6 +1328,71 @@ Either create it, or update the main branch setting by running:
         Ok(all_branches)
     }
 
+    /// Get all remote-tracking branches in the repository.
+    #[instrument]
+    pub fn get_all_remote_branches(&self) -> eyre::Result<Vec<Branch>> {
+        let mut all_branches = Vec::new();
+        for branch in self
+            .inner
+            .branches(Some(git2::BranchType::Remote))
+            .map_err(wrap_git_error)
+            .wrap_err("Iterating over all remote branches")?
+        {
+            let (branch, _branch_type) = branch.wrap_err("Accessing individual branch")?;
+            all_branches.push(Branch { inner: branch });
+        }
+        Ok(all_branches)
+    }
+
+    /// Get the names of all the remotes in the repository.
+    #[instrument]
+    pub fn get_remote_names(&self) -> eyre::Result<Vec<String>> {
+        let remotes = self
+            .inner
+            .remotes()
+            .map_err(wrap_git_error)
+            .wrap_err("Listing remotes")?;
+        Ok(remotes
+            .iter()
+            .flatten()
+            .map(|name| name.to_string())
+            .collect())
+    }
+
+    /// Get up to `limit` of the most recent entries in the reflog for the given
+    /// reference, ordered from oldest to newest. Returns an empty list if the
+    /// reference has no reflog.
+    #[instrument]
+    pub fn get_reflog_entries(&self, name: &OsStr, limit: usize) -> eyre::Result<Vec<ReflogEntry>> {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => eyre::bail!(
+                "Reference name is not a UTF-8 string (libgit2 limitation): {:?}",
+                name
+            ),
+        };
+        let reflog = self
+            .inner
+            .reflog(name)
+            .map_err(wrap_git_error)
+            .wrap_err_with(|| format!("Reading reflog for {:?}", name))?;
+        let mut entries: Vec<ReflogEntry> = reflog
+            .iter()
+            .take(limit)
+            .map(|entry| ReflogEntry {
+                old_oid: entry.id_old().into(),
+                new_oid: entry.id_new().into(),
+                timestamp: SystemTime::UNIX_EPOCH
+                    + std::time::Duration::from_secs(
+                        entry.committer().when().seconds().max(0) as u64
+                    ),
+                message: entry.message().map(|message| message.to_owned()),
+            })
+            .collect();
+        entries.reverse();
+        Ok(entries)
+    }
+
     /// Look up the branch with the given name. Returns `None` if not found.
     #[instrument]
     pub fn find_branch(&self, name: &str, branch_type: BranchType) -> eyre::Result<Option<Branch>> {
@@ -805,6 +1403,209 @@ Either create it, or update the main branch setting by running:
         }
     }
 
+    /// Resolve a name provided by the user, such as a branch name, to a
+    /// reference, following the rules Git uses for `<refname>` (see
+    /// `gitrevisions(7)`): the name is tried as a full reference name, then
+    /// under `refs/`, `refs/tags/`, `refs/heads/` and `refs/remotes/`, and
+    /// finally as `refs/remotes/<name>/HEAD`.
+    ///
+    /// Reference names are compared exactly, against the references listed in
+    /// the reference database, rather than by looking up the corresponding
+    /// file. On a case-insensitive filesystem, looking up `Feature-X` might
+    /// otherwise find the loose reference file for `feature-x`. If no name
+    /// matches exactly, but some references differ from one of the candidate
+    /// names only in case or Unicode normalization, then
+    /// `ResolvedBranchName::Ambiguous` is returned instead of picking one.
+    ///
+    /// Names which don't refer to references at all (such as commit hashes or
+    /// `HEAD~`) resolve to `ResolvedBranchName::NotFound`, and should be
+    /// passed to `Repo::revparse_single_commit` instead.
+    #[instrument]
+    pub fn resolve_branch_name(&self, name: &str) -> eyre::Result<ResolvedBranchName<'_>> {
+        let candidate_names: Vec<OsString> = [
+            name.to_string(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+
+        let mut references: HashMap<OsString, Reference> = HashMap::new();
+        for reference in self.get_all_references()? {
+            references.insert(reference.get_name()?, reference);
+        }
+        for candidate_name in candidate_names.iter() {
+            if let Some(reference) = references.remove(candidate_name) {
+                return Ok(ResolvedBranchName::Found(reference));
+            }
+        }
+
+        let folded_candidate_names: HashSet<String> = candidate_names
+            .iter()
+            .map(|candidate_name| fold_reference_name(candidate_name))
+            .collect();
+        let mut candidates: Vec<OsString> = references
+            .into_keys()
+            .filter(|reference_name| {
+                folded_candidate_names.contains(&fold_reference_name(reference_name))
+            })
+            .collect();
+        if candidates.is_empty() {
+            Ok(ResolvedBranchName::NotFound)
+        } else {
+            candidates.sort_unstable();
+            Ok(ResolvedBranchName::Ambiguous(AmbiguousBranchName {
+                name: name.to_string(),
+                candidates,
+            }))
+        }
+    }
+
+    /// Get the names of the references whose names differ from another
+    /// reference's name only in case or Unicode normalization. On a
+    /// case-insensitive or normalizing filesystem, such references can't both
+    /// be stored as loose reference files.
+    #[instrument]
+    pub fn get_colliding_reference_names(&self) -> eyre::Result<HashSet<OsString>> {
+        let mut folded_names: HashMap<String, Vec<OsString>> = HashMap::new();
+        for reference in self.get_all_references()? {
+            let reference_name = reference.get_name()?;
+            folded_names
+                .entry(fold_reference_name(&reference_name))
+                .or_default()
+                .push(reference_name);
+        }
+        Ok(folded_names
+            .into_iter()
+            .filter(|(_folded_name, reference_names)| reference_names.len() > 1)
+            .flat_map(|(_folded_name, reference_names)| reference_names)
+            .collect())
+    }
+
+    /// Update the given references by rewriting the `packed-refs` file
+    /// directly, rather than by writing loose reference files. Each reference
+    /// is set to point to the given OID, or deleted if the OID is zero.
+    ///
+    /// This is used for references whose names collide with other references
+    /// on a case-insensitive or normalizing filesystem, where writing a loose
+    /// reference file would clobber the other reference. A loose reference
+    /// file with exactly the same name as an updated reference is removed, so
+    /// that it doesn't shadow the packed reference. No reflog entries are
+    /// written.
+    #[instrument]
+    pub fn update_packed_references(&self, updates: &[(&OsStr, MaybeZeroOid)]) -> eyre::Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let packed_refs_path = self.get_packed_refs_path();
+        let lock_path = packed_refs_path.with_file_name("packed-refs.lock");
+        let mut lock_file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .wrap_err_with(|| format!("Locking packed references at: {:?}", &lock_path))?;
+
+        let result = (|| -> eyre::Result<()> {
+            let contents = match std::fs::read(&packed_refs_path) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(err) => {
+                    return Err(err).wrap_err_with(|| {
+                        format!("Reading packed references from: {:?}", &packed_refs_path)
+                    })
+                }
+            };
+
+            // Each entry is the reference name, along with its line and any
+            // following peeled line (starting with `^`), for annotated tags.
+            let mut header: Vec<u8> = b"# pack-refs with: peeled fully-peeled sorted \n".to_vec();
+            let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+            for line in contents.split_inclusive(|c| *c == b'\n') {
+                if line.starts_with(b"#") {
+                    header = line.to_vec();
+                } else if line.starts_with(b"^") {
+                    if let Some((_name, entry)) = entries.last_mut() {
+                        entry.extend(line);
+                    }
+                } else if let Some(space_index) = line.iter().position(|c| *c == b' ') {
+                    let name = line[space_index + 1..]
+                        .strip_suffix(b"\n")
+                        .unwrap_or(&line[space_index + 1..]);
+                    entries.push((name.to_vec(), line.to_vec()));
+                }
+            }
+
+            for (name, oid) in updates {
+                let name = name.to_raw_bytes();
+                entries.retain(|(entry_name, _entry)| entry_name.as_slice() != name.as_ref());
+                if let MaybeZeroOid::NonZero(oid) = oid {
+                    let mut entry = oid.to_string().into_bytes();
+                    entry.push(b' ');
+                    entry.extend(name.iter());
+                    entry.push(b'\n');
+                    entries.push((name.to_vec(), entry));
+                }
+            }
+            entries.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+
+            let mut new_contents = header;
+            for (_name, entry) in entries {
+                new_contents.extend(entry);
+            }
+            std::io::Write::write_all(&mut lock_file, &new_contents)
+                .wrap_err("Writing packed references")?;
+            lock_file.sync_all().wrap_err("Writing packed references")?;
+            std::fs::rename(&lock_path, &packed_refs_path).wrap_err_with(|| {
+                format!("Updating packed references at: {:?}", &packed_refs_path)
+            })?;
+            Ok(())
+        })();
+        if result.is_err() {
+            // Ignore any error, since the lock file may have already been
+            // renamed into place.
+            let _ = std::fs::remove_file(&lock_path);
+        }
+        result?;
+
+        for (name, _oid) in updates {
+            self.remove_exact_loose_reference(name)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the loose reference file for the given reference, if there is
+    /// one whose name matches exactly. The directory is listed rather than the
+    /// file being removed by path, since on a case-insensitive filesystem, the
+    /// path could refer to the loose reference file for a different reference.
+    fn remove_exact_loose_reference(&self, name: &OsStr) -> eyre::Result<()> {
+        let path = self.get_common_path().join(name);
+        let (dir, file_name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(file_name)) => (dir, file_name),
+            _ => return Ok(()),
+        };
+        let dir_entries = match std::fs::read_dir(dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("Listing loose references in: {:?}", dir))
+            }
+        };
+        for dir_entry in dir_entries {
+            let dir_entry = dir_entry.wrap_err("Reading directory entry")?;
+            if dir_entry.file_name() == file_name && dir_entry.path().is_file() {
+                std::fs::remove_file(dir_entry.path()).wrap_err_with(|| {
+                    format!("Removing loose reference file: {:?}", dir_entry.path())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
     /// Create a new branch or update an existing branch.
     #[instrument]
     pub fn create_branch(
@@ -825,9 +1626,18 @@ Either create it, or update the main branch setting by running:
             .map_err(wrap_git_error)
     }
 
+    /// Get the number of times that a commit object has been looked up with
+    /// `find_commit` for this repository. This is used to check how many
+    /// commits an operation loads, since doing so dominates the cost of
+    /// rendering the smartlog.
+    pub fn get_commit_load_count(&self) -> usize {
+        self.commit_load_count.load(Ordering::Relaxed)
+    }
+
     /// Look up a commit with the given OID. Returns `None` if not found.
     #[instrument]
     pub fn find_commit(&self, oid: NonZeroOid) -> eyre::Result<Option<Commit>> {
+        self.commit_load_count.fetch_add(1, Ordering::Relaxed);
         match self.inner.find_commit(oid.inner) {
             Ok(commit) => Ok(Some(Commit { inner: commit })),
             Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
@@ -861,7 +1671,8 @@ Either create it, or update the main branch setting by running:
         }
     }
 
-    /// Create a new commit.
+    /// Create a new commit. If `commit.gpgsign` is set, the commit is signed,
+    /// unless `branchless.rewrite.sign` is set to `false`.
     #[instrument]
     pub fn create_commit(
         &self,
@@ -871,6 +1682,34 @@ Either create it, or update the main branch setting by running:
         message: &str,
         tree: &Tree,
         parents: Vec<&Commit>,
+    ) -> eyre::Result<NonZeroOid> {
+        let signer = match self.get_commit_signer()? {
+            Some(signer) => signer,
+            None => {
+                return self
+                    .create_unsigned_commit(update_ref, author, committer, message, tree, parents)
+            }
+        };
+        let contents =
+            self.make_commit_buffer(author, committer, message.as_bytes(), None, tree, parents)?;
+        let oid = self.write_signed_commit(&signer, committer, &contents)?;
+        if let Some(update_ref) = update_ref {
+            self.update_ref_for_commit(update_ref, oid, "commit", message)?;
+        }
+        Ok(oid)
+    }
+
+    /// Create a new commit without signing it, such as for commits which are
+    /// only used internally.
+    #[instrument]
+    fn create_unsigned_commit(
+        &self,
+        update_ref: Option<&str>,
+        author: &Signature,
+        committer: &Signature,
+        message: &str,
+        tree: &Tree,
+        parents: Vec<&Commit>,
     ) -> eyre::Result<NonZeroOid> {
         let parents = parents
             .iter()
@@ -890,6 +1729,220 @@ Either create it, or update the main branch setting by running:
         Ok(make_non_zero_oid(oid))
     }
 
+    /// Create a new commit with the same message as `message_commit`. Unlike
+    /// `create_commit`, this preserves the exact bytes of the message, along
+    /// with its `encoding` header, even if the message isn't valid UTF-8.
+    #[instrument]
+    pub fn create_commit_with_message_from(
+        &self,
+        author: &Signature,
+        committer: &Signature,
+        message_commit: &Commit,
+        tree: &Tree,
+        parents: Vec<&Commit>,
+    ) -> eyre::Result<NonZeroOid> {
+        let message = message_commit.inner.message_raw_bytes();
+        let encoding = message_commit.inner.message_encoding();
+        if encoding.is_none() {
+            if let Ok(message) = std::str::from_utf8(message) {
+                return self.create_commit(None, author, committer, message, tree, parents);
+            }
+        }
+
+        let contents =
+            self.make_commit_buffer(author, committer, message, encoding, tree, parents)?;
+        match self.get_commit_signer()? {
+            Some(signer) => self.write_signed_commit(&signer, committer, &contents),
+            None => self.write_commit_object(&contents),
+        }
+    }
+
+    /// Amend `commit`, replacing any of its author, committer, message, or
+    /// tree with the provided values. Like `create_commit`, the resulting
+    /// commit is signed if `commit.gpgsign` is set.
+    ///
+    /// Returns the OID of the resulting new commit.
+    #[instrument]
+    pub fn amend_commit(
+        &self,
+        commit: &Commit,
+        update_ref: Option<&str>,
+        author: Option<&Signature>,
+        committer: Option<&Signature>,
+        message: Option<&str>,
+        tree: Option<&Tree>,
+    ) -> eyre::Result<NonZeroOid> {
+        let signer = match self.get_commit_signer()? {
+            Some(signer) => signer,
+            None => {
+                let oid = commit
+                    .inner
+                    .amend(
+                        update_ref,
+                        author.map(|author| &author.inner),
+                        committer.map(|committer| &committer.inner),
+                        None,
+                        message,
+                        tree.map(|tree| &tree.inner),
+                    )
+                    .map_err(wrap_git_error)?;
+                return Ok(make_non_zero_oid(oid));
+            }
+        };
+
+        let (original_author, original_committer, original_tree);
+        let author = match author {
+            Some(author) => author,
+            None => {
+                original_author = commit.get_author();
+                &original_author
+            }
+        };
+        let committer = match committer {
+            Some(committer) => committer,
+            None => {
+                original_committer = commit.get_committer();
+                &original_committer
+            }
+        };
+        let tree = match tree {
+            Some(tree) => tree,
+            None => {
+                original_tree = commit.get_tree()?;
+                &original_tree
+            }
+        };
+        let (message_bytes, encoding) = match message {
+            Some(message) => (message.as_bytes(), None),
+            None => (
+                commit.inner.message_raw_bytes(),
+                commit.inner.message_encoding(),
+            ),
+        };
+        let parents = commit.get_parents();
+        let contents = self.make_commit_buffer(
+            author,
+            committer,
+            message_bytes,
+            encoding,
+            tree,
+            parents.iter().collect(),
+        )?;
+        let oid = self.write_signed_commit(&signer, committer, &contents)?;
+        if let Some(update_ref) = update_ref {
+            let summary = String::from_utf8_lossy(message_bytes);
+            self.update_ref_for_commit(update_ref, oid, "commit (amend)", &summary)?;
+        }
+        Ok(oid)
+    }
+
+    /// Format the contents of a commit object with the given message bytes
+    /// and `encoding` header.
+    fn make_commit_buffer(
+        &self,
+        author: &Signature,
+        committer: &Signature,
+        message: &[u8],
+        encoding: Option<&str>,
+        tree: &Tree,
+        parents: Vec<&Commit>,
+    ) -> eyre::Result<Vec<u8>> {
+        // libgit2 only accepts UTF-8 commit messages, so have it format the
+        // commit headers for an empty message, and then splice in the original
+        // encoding header and message bytes ourselves.
+        let parents = parents
+            .iter()
+            .map(|commit| &commit.inner)
+            .collect::<Vec<_>>();
+        let buffer = self
+            .inner
+            .commit_create_buffer(
+                &author.inner,
+                &committer.inner,
+                "",
+                &tree.inner,
+                parents.as_slice(),
+            )
+            .map_err(wrap_git_error)?;
+        let headers = buffer
+            .strip_suffix(b"\n")
+            .ok_or_else(|| eyre::eyre!("Unexpected commit buffer format: {:?}", &*buffer))?;
+        let mut contents = headers.to_vec();
+        if let Some(encoding) = encoding {
+            contents.extend_from_slice(format!("encoding {}\n", encoding).as_bytes());
+        }
+        contents.push(b'\n');
+        contents.extend_from_slice(message);
+        Ok(contents)
+    }
+
+    /// Determine how to sign newly-created commits, or `None` if they
+    /// shouldn't be signed.
+    fn get_commit_signer(&self) -> eyre::Result<Option<CommitSigner>> {
+        if !get_rewrite_sign(self)? {
+            return Ok(None);
+        }
+        let signer = CommitSigner::from_config(&self.get_readonly_config()?)?;
+        Ok(Some(signer))
+    }
+
+    fn write_signed_commit(
+        &self,
+        signer: &CommitSigner,
+        committer: &Signature,
+        contents: &[u8],
+    ) -> eyre::Result<NonZeroOid> {
+        let committer_ident = format!(
+            "{} <{}>",
+            committer.get_name().unwrap_or_default(),
+            committer.get_email().unwrap_or_default()
+        );
+        let signature = signer
+            .sign(contents, &committer_ident, &self.get_tempfile_dir())
+            .wrap_err("Signing commit")?;
+        let contents = add_signature_header(contents, &signature)?;
+        self.write_commit_object(&contents)
+    }
+
+    fn write_commit_object(&self, contents: &[u8]) -> eyre::Result<NonZeroOid> {
+        let oid = self
+            .inner
+            .odb()
+            .map_err(wrap_git_error)?
+            .write(git2::ObjectType::Commit, contents)
+            .map_err(wrap_git_error)?;
+        Ok(make_non_zero_oid(oid))
+    }
+
+    /// Point `update_ref` (or the branch it refers to, if it's a symbolic
+    /// reference) to the newly-created commit, like libgit2 does when passed
+    /// an `update_ref` argument.
+    fn update_ref_for_commit(
+        &self,
+        update_ref: &str,
+        oid: NonZeroOid,
+        log_prefix: &str,
+        message: &str,
+    ) -> eyre::Result<()> {
+        let summary = message.lines().next().unwrap_or_default();
+        let reflog_message = format!("{}: {}", log_prefix, summary);
+        match self.inner.find_reference(update_ref) {
+            Ok(reference) => {
+                let mut reference = reference.resolve().map_err(wrap_git_error)?;
+                reference
+                    .set_target(oid.inner, &reflog_message)
+                    .map_err(wrap_git_error)?;
+            }
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                self.inner
+                    .reference(update_ref, oid.inner, false, &reflog_message)
+                    .map_err(wrap_git_error)?;
+            }
+            Err(err) => return Err(wrap_git_error(err)),
+        }
+        Ok(())
+    }
+
     /// Cherry-pick a commit in memory and return the resulting index.
     #[instrument]
     pub fn cherry_pick_commit(
@@ -905,6 +1958,33 @@ Either create it, or update the main branch setting by running:
         Ok(Index { inner: index })
     }
 
+    /// Merge `their_commit` into `our_commit` in memory and return the
+    /// resulting tree. Unlike `cherry_pick_fast`, this operates on the entire
+    /// index, so it's slower for large repositories.
+    #[instrument]
+    pub fn merge_commits<'repo>(
+        &'repo self,
+        our_commit: &'repo Commit,
+        their_commit: &'repo Commit,
+    ) -> eyre::Result<Result<Tree<'repo>, CherryPickFastError>> {
+        let mut merged_index = Index {
+            inner: self
+                .inner
+                .merge_commits(&our_commit.inner, &their_commit.inner, None)
+                .map_err(wrap_git_error)?,
+        };
+        if merged_index.has_conflicts() {
+            let conflicting_paths = merged_index.get_conflicting_paths()?;
+            return Ok(Err(CherryPickFastError::MergeConflict {
+                conflicting_paths,
+            }));
+        }
+
+        let merged_tree_oid = self.write_index_to_tree(&mut merged_index)?;
+        let merged_tree = self.find_tree_or_fail(merged_tree_oid)?;
+        Ok(Ok(merged_tree))
+    }
+
     /// Cherry-pick a commit in memory and return the resulting tree.
     ///
     /// The `libgit2` routines operate on entire `Index`es, which contain one
@@ -955,28 +2035,7 @@ Either create it, or update the main branch setting by running:
             self.cherry_pick_commit(&dehydrated_patch_commit, &dehydrated_target_commit, 0)?;
         let rebased_tree = {
             if rebased_index.has_conflicts() {
-                let conflicting_paths = {
-                    let mut result = HashSet::new();
-                    for conflict in rebased_index
-                        .inner
-                        .conflicts()
-                        .wrap_err("Getting conflicting paths")?
-                    {
-                        let conflict = conflict.wrap_err("Getting conflicting path")?;
-                        if let Some(ancestor) = conflict.ancestor {
-                            result
-                                .insert(PathBuf::from(OsStrBytes::from_raw_bytes(ancestor.path)?));
-                        }
-                        if let Some(our) = conflict.our {
-                            result.insert(PathBuf::from(OsStrBytes::from_raw_bytes(our.path)?));
-                        }
-                        if let Some(their) = conflict.their {
-                            result.insert(PathBuf::from(OsStrBytes::from_raw_bytes(their.path)?));
-                        }
-                    }
-                    result
-                };
-
+                let conflicting_paths = rebased_index.get_conflicting_paths()?;
                 if conflicting_paths.is_empty() {
                     warn!("BUG: A merge conflict was detected, but there were no entries in `conflicting_paths`. Maybe the wrong index entry was used?")
                 }
@@ -1066,7 +2125,7 @@ Either create it, or update the main branch setting by running:
             vec![]
         };
         let dehydrated_commit_oid = self
-            .create_commit(
+            .create_unsigned_commit(
                 None,
                 &signature,
                 &signature,
@@ -1100,6 +2159,20 @@ Either create it, or update the main branch setting by running:
         }
     }
 
+    /// Read the contents of the blob with the given OID.
+    #[instrument]
+    pub fn read_blob(&self, oid: NonZeroOid) -> eyre::Result<Vec<u8>> {
+        let blob = self.inner.find_blob(oid.inner).map_err(wrap_git_error)?;
+        Ok(blob.content().to_vec())
+    }
+
+    /// Write the given contents into Git's object database as a blob.
+    #[instrument(skip(contents))]
+    pub fn create_blob(&self, contents: &[u8]) -> eyre::Result<NonZeroOid> {
+        let oid = self.inner.blob(contents).map_err(wrap_git_error)?;
+        Ok(make_non_zero_oid(oid))
+    }
+
     /// Write the provided in-memory index as a tree into Git`s object database.
     /// There must be no merge conflicts in the index.
     #[instrument]
@@ -1236,6 +2309,41 @@ Either create it, or update the main branch setting by running:
 
         Ok(amended_tree)
     }
+
+    /// Create a tree consisting of the tree of the (only) parent of `commit`,
+    /// with only the changes that `commit` made to the given paths applied.
+    /// Changes to any other paths are left out.
+    ///
+    /// This is the tree of the first commit when splitting `commit` in two;
+    /// the tree of the second commit is simply the tree of `commit`.
+    #[instrument]
+    pub fn get_partial_commit_tree(
+        &self,
+        commit: &Commit,
+        paths: &HashSet<PathBuf>,
+    ) -> eyre::Result<Tree> {
+        let parents = commit.get_parents();
+        let parent_tree = match parents.as_slice() {
+            [] => None,
+            [only_parent] => Some(only_parent.get_tree()?),
+            [..] => eyre::bail!(
+                "Cannot take the partial tree of a merge commit: {:?}",
+                commit
+            ),
+        };
+        let commit_tree = commit.get_tree()?;
+        let entries: HashMap<PathBuf, Option<(NonZeroOid, i32)>> = paths
+            .iter()
+            .map(|path| {
+                let entry = commit_tree
+                    .get_path(path)?
+                    .map(|entry| (entry.get_oid(), entry.get_filemode()));
+                Ok((path.clone(), entry))
+            })
+            .collect::<eyre::Result<_>>()?;
+        let tree_oid = hydrate_tree(self, parent_tree.as_ref(), entries)?;
+        self.find_tree_or_fail(tree_oid)
+    }
 }
 
 /// The signature of a commit, identifying who it was made by and when it was made.
@@ -1281,6 +2389,18 @@ impl<'repo> Signature<'repo> {
         self.inner.when()
     }
 
+    /// Get the name of the signer. Returns `None` if the name is not valid
+    /// UTF-8.
+    pub fn get_name(&self) -> Option<&str> {
+        self.inner.name()
+    }
+
+    /// Get the email of the signer. Returns `None` if the email is not valid
+    /// UTF-8.
+    pub fn get_email(&self) -> Option<&str> {
+        self.inner.email()
+    }
+
     /// Return the friendly formatted name and email of the signature.
     pub fn friendly_describe(&self) -> Option<String> {
         let name = self.inner.name();
@@ -1320,6 +2440,28 @@ impl Index {
             file_mode: entry.mode,
         })
     }
+
+    /// Get the paths which are involved in a merge conflict in this index.
+    pub fn get_conflicting_paths(&self) -> eyre::Result<HashSet<PathBuf>> {
+        let mut result = HashSet::new();
+        for conflict in self
+            .inner
+            .conflicts()
+            .wrap_err("Getting conflicting paths")?
+        {
+            let conflict = conflict.wrap_err("Getting conflicting path")?;
+            if let Some(ancestor) = conflict.ancestor {
+                result.insert(PathBuf::from(OsStrBytes::from_raw_bytes(ancestor.path)?));
+            }
+            if let Some(our) = conflict.our {
+                result.insert(PathBuf::from(OsStrBytes::from_raw_bytes(our.path)?));
+            }
+            if let Some(their) = conflict.their {
+                result.insert(PathBuf::from(OsStrBytes::from_raw_bytes(their.path)?));
+            }
+        }
+        Ok(result)
+    }
 }
 
 /// A diff between two trees/commits.
@@ -1504,31 +2646,6 @@ impl<'repo> Commit<'repo> {
             (Some(parent_oid), Some(current_oid)) => Ok(Some(parent_oid != current_oid)),
         }
     }
-
-    /// Amend this existing commit.
-    /// Returns the OID of the resulting new commit.
-    #[instrument]
-    pub fn amend_commit(
-        &self,
-        update_ref: Option<&str>,
-        author: Option<&Signature>,
-        committer: Option<&Signature>,
-        message: Option<&str>,
-        tree: Option<&Tree>,
-    ) -> eyre::Result<NonZeroOid> {
-        let oid = self
-            .inner
-            .amend(
-                update_ref,
-                author.map(|author| &author.inner),
-                committer.map(|committer| &committer.inner),
-                None,
-                message,
-                tree.map(|tree| &tree.inner),
-            )
-            .map_err(wrap_git_error)?;
-        Ok(make_non_zero_oid(oid))
-    }
 }
 
 /// The target of a reference.
@@ -1548,6 +2665,82 @@ pub enum ReferenceTarget<'a> {
     },
 }
 
+/// Information about a worktree of the repository, as returned by
+/// `Repo::list_worktrees`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorktreeInfo {
+    /// The name of the worktree, as returned by `Repo::get_worktree_name`, or
+    /// `None` for the main worktree.
+    pub name: Option<OsString>,
+
+    /// The path to the worktree's working copy.
+    pub path: PathBuf,
+
+    /// The commit which the worktree's `HEAD` points to, if it's not unborn.
+    pub head_oid: Option<NonZeroOid>,
+
+    /// The full name of the branch checked out in the worktree, if `HEAD`
+    /// isn't detached.
+    pub branch_name: Option<OsString>,
+}
+
+/// The result of resolving a name provided by the user with
+/// `Repo::resolve_branch_name`.
+#[derive(Debug)]
+pub enum ResolvedBranchName<'repo> {
+    /// The name exactly matched the given reference.
+    Found(Reference<'repo>),
+
+    /// The name didn't exactly match any reference, but some references have
+    /// names which differ from it only in case or Unicode normalization.
+    Ambiguous(AmbiguousBranchName),
+
+    /// The name didn't match any reference.
+    NotFound,
+}
+
+/// A name which didn't exactly match any reference, but which could refer to
+/// one of several references whose names differ from it only in case or
+/// Unicode normalization. On case-insensitive or normalizing filesystems (such
+/// as on macOS), Git may resolve such a name to any one of them, depending on
+/// whether the references are stored as loose files or in `packed-refs`, so we
+/// refuse to guess.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AmbiguousBranchName {
+    /// The name as provided by the user.
+    pub name: String,
+
+    /// The full names of the references which the name could refer to, in
+    /// sorted order.
+    pub candidates: Vec<OsString>,
+}
+
+impl std::fmt::Display for AmbiguousBranchName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Ambiguous name: {} (it differs only in case or Unicode normalization from: {})",
+            self.name,
+            self.candidates
+                .iter()
+                .map(|candidate| candidate.to_string_lossy())
+                .join(", ")
+        )
+    }
+}
+
+/// Normalize a reference name for comparison with other reference names, so
+/// that names which would refer to the same file on a case-insensitive or
+/// normalizing filesystem compare equal.
+fn fold_reference_name(name: &OsStr) -> String {
+    let name: String = name
+        .to_string_lossy()
+        .nfc()
+        .flat_map(char::to_lowercase)
+        .collect();
+    name.nfc().collect()
+}
+
 /// Represents a reference to an object.
 pub struct Reference<'repo> {
     inner: git2::Reference<'repo>,
@@ -1633,6 +2826,12 @@ pub enum CategorizedReferenceName<'a> {
         prefix: &'static str,
     },
 
+    /// The reference `refs/stash`, whose reflog holds the stash entries.
+    Stash {
+        /// The full name of the reference.
+        name: &'a OsStr,
+    },
+
     /// Some other kind of reference which isn't a branch at all.
     OtherRef {
         /// The full name of the reference.
@@ -1654,6 +2853,8 @@ impl<'a> CategorizedReferenceName<'a> {
                 name,
                 prefix: "refs/remotes/",
             }
+        } else if *bytes == *b"refs/stash" {
+            Self::Stash { name }
         } else {
             Self::OtherRef { name }
         }
@@ -1667,7 +2868,7 @@ impl<'a> CategorizedReferenceName<'a> {
         let (name, prefix): (_, &'static str) = match self {
             Self::LocalBranch { name, prefix } => (name, prefix),
             Self::RemoteBranch { name, prefix } => (name, prefix),
-            Self::OtherRef { name } => (name, ""),
+            Self::Stash { name } | Self::OtherRef { name } => (name, ""),
         };
         let bytes = name.to_raw_bytes();
         let bytes = match bytes.strip_prefix(prefix.as_bytes()) {
@@ -1684,7 +2885,7 @@ impl<'a> CategorizedReferenceName<'a> {
         let name = match self {
             Self::LocalBranch { name, prefix: _ } => name,
             Self::RemoteBranch { name, prefix: _ } => name,
-            Self::OtherRef { name } => name,
+            Self::Stash { name } | Self::OtherRef { name } => name,
         };
         name.to_string_lossy().into_owned()
     }
@@ -1696,7 +2897,7 @@ impl<'a> CategorizedReferenceName<'a> {
         let (name, prefix): (_, &'static str) = match self {
             Self::LocalBranch { name, prefix } => (name, prefix),
             Self::RemoteBranch { name, prefix } => (name, prefix),
-            Self::OtherRef { name } => (name, ""),
+            Self::Stash { name } | Self::OtherRef { name } => (name, ""),
         };
         let name = name.to_string_lossy();
         match name.strip_prefix(prefix) {
@@ -1716,6 +2917,7 @@ impl<'a> CategorizedReferenceName<'a> {
             CategorizedReferenceName::RemoteBranch { .. } => {
                 format!("remote branch {}", name)
             }
+            CategorizedReferenceName::Stash { .. } => "stash".to_string(),
             CategorizedReferenceName::OtherRef { .. } => format!("ref {}", name),
         };
         name
@@ -1736,6 +2938,15 @@ impl<'repo> Branch<'repo> {
         Ok(self.inner.get().target().map(make_non_zero_oid))
     }
 
+    /// Get the name of the branch, without the `refs/heads/` or
+    /// `refs/remotes/` prefix. Returns `None` if the name isn't valid UTF-8.
+    pub fn get_name(&self) -> eyre::Result<Option<&str>> {
+        self.inner
+            .name()
+            .map_err(wrap_git_error)
+            .wrap_err("Getting branch name")
+    }
+
     /// Convert the branch into its underlying `Reference`.
     pub fn into_reference(self) -> Reference<'repo> {
         Reference {
@@ -1836,6 +3047,94 @@ impl FromStr for FileMode {
     }
 }
 
+/// A set of pathspecs, as accepted by Git commands, used to limit an
+/// operation to a subset of the paths in the repository. See
+/// `gitglossary(7)`.
+pub struct Pathspecs {
+    inner: Option<git2::Pathspec>,
+    literal_paths: HashSet<PathBuf>,
+}
+
+impl std::fmt::Debug for Pathspecs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<Pathspecs literal_paths={:?}>", self.literal_paths)
+    }
+}
+
+impl Pathspecs {
+    /// Construct the pathspecs from the given command-line arguments, which
+    /// are interpreted relative to `current_dir`. If no arguments are given,
+    /// the pathspecs match all paths.
+    pub fn new(repo: &Repo, current_dir: &Path, pathspecs: &[String]) -> eyre::Result<Self> {
+        if pathspecs.is_empty() {
+            return Ok(Pathspecs {
+                inner: None,
+                literal_paths: HashSet::new(),
+            });
+        }
+
+        let repo_path = repo
+            .get_working_copy_path()
+            .ok_or_else(|| eyre::eyre!("unable to get repo working copy path"))?;
+        let repo_path = std::fs::canonicalize(repo_path)
+            .wrap_err_with(|| format!("Canonicalizing repo path: {:?}", repo_path))?;
+        let current_dir = std::fs::canonicalize(current_dir)
+            .wrap_err_with(|| format!("Canonicalizing current dir: {:?}", current_dir))?;
+        let prefix = current_dir
+            .strip_prefix(&repo_path)
+            .unwrap_or_else(|_| Path::new(""));
+
+        let mut literal_paths = HashSet::new();
+        let mut specs = Vec::new();
+        for pathspec in pathspecs {
+            // Normalize the pathspec to be relative to the root of the
+            // repository, since that's how paths are reported by Git.
+            let mut path = PathBuf::new();
+            for component in prefix.join(pathspec).components() {
+                match component {
+                    std::path::Component::CurDir => {}
+                    std::path::Component::ParentDir => {
+                        if !path.pop() {
+                            eyre::bail!("Path is outside of the repository: {}", pathspec);
+                        }
+                    }
+                    component => path.push(component),
+                }
+            }
+            literal_paths.insert(path.clone());
+            specs.push(path);
+        }
+        let inner = git2::Pathspec::new(specs.iter())
+            .map_err(wrap_git_error)
+            .wrap_err("Parsing pathspecs")?;
+        Ok(Pathspecs {
+            inner: Some(inner),
+            literal_paths,
+        })
+    }
+
+    /// Whether no pathspecs were provided, i.e. all paths are matched.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_none()
+    }
+
+    /// Determine whether the given path (relative to the root of the
+    /// repository) is matched by the pathspecs.
+    pub fn matches(&self, path: &Path) -> bool {
+        match &self.inner {
+            None => true,
+            Some(inner) => inner.matches_path(path, git2::PathspecFlags::DEFAULT),
+        }
+    }
+
+    /// Determine whether the given path (relative to the root of the
+    /// repository) was named exactly by one of the pathspecs, rather than
+    /// being matched by a directory or wildcard.
+    pub fn names_path(&self, path: &Path) -> bool {
+        self.literal_paths.contains(path)
+    }
+}
+
 /// The status of a file in the repo.
 #[derive(Debug, PartialEq, Eq)]
 pub struct StatusEntry {
@@ -1878,6 +3177,42 @@ impl TryFrom<&[u8]> for StatusEntry {
                 r#"(?P<path>[^\x00]+)(\x00(?P<orig_path>[^\x00]+))?$"#              // Path and original path (for renames/copies).
             ))
             .expect("porcelain v2 status line regex");
+
+            /// Parses an unmerged entry of the git porcelain v2 status format.
+            static ref UNMERGED_STATUS_PORCELAIN_V2_REGEXP: Regex = Regex::new(concat!(
+                r#"^u [\w.]{2} "#,                                   // Prefix and conflict type.
+                r#"[\w.]+ "#,                                        // Submodule state.
+                r#"(\d{6} ){3}(?P<working_copy_filemode>\d{6}) "#,  // Stage 1, 2, and 3, and Working Copy file modes.
+                r#"([\w\d]+ ){3}"#,                                  // Stage 1, 2, and 3 object IDs.
+                r#"(?P<path>[^\x00]+)$"#                             // Path.
+            ))
+            .expect("porcelain v2 unmerged status line regex");
+        }
+
+        if let Some(status_line_parts) = UNMERGED_STATUS_PORCELAIN_V2_REGEXP.captures(line) {
+            let working_copy_file_mode = status_line_parts
+                .name("working_copy_filemode")
+                .ok_or_else(|| eyre::eyre!("no working copy filemode in status line"))
+                .and_then(|m| {
+                    std::str::from_utf8(m.as_bytes())
+                        .map_err(|err| {
+                            eyre::eyre!("unable to decode working copy file mode: {:?}", err)
+                        })
+                        .and_then(|working_copy_file_mode| {
+                            working_copy_file_mode.parse::<FileMode>()
+                        })
+                })?;
+            let path = status_line_parts
+                .name("path")
+                .ok_or_else(|| eyre::eyre!("no path in status line"))?
+                .as_bytes();
+            return Ok(StatusEntry {
+                index_status: FileStatus::Unmerged,
+                working_copy_status: FileStatus::Unmerged,
+                working_copy_file_mode,
+                path: PathBuf::from(OsStrBytes::from_raw_bytes(path)?),
+                orig_path: None,
+            });
         }
 
         let status_line_parts = STATUS_PORCELAIN_V2_REGEXP
@@ -1934,32 +3269,37 @@ mod tests {
 
     #[test]
     fn test_parse_git_version_output() {
-        assert_eq!(
-            "git version 12.34.56".parse::<GitVersion>().unwrap(),
-            GitVersion(12, 34, 56)
-        );
-        assert_eq!(
-            "git version 12.34.56\n".parse::<GitVersion>().unwrap(),
-            GitVersion(12, 34, 56)
-        );
-        assert_eq!(
-            "git version 12.34.56.78.abcdef"
-                .parse::<GitVersion>()
-                .unwrap(),
-            GitVersion(12, 34, 56)
-        );
-
-        // See https://github.com/arxanas/git-branchless/issues/69
-        assert_eq!(
-            "git version 2.33.0-rc0".parse::<GitVersion>().unwrap(),
-            GitVersion(2, 33, 0)
-        );
+        let cases = [
+            ("git version 12.34.56", GitVersion(12, 34, 56)),
+            ("git version 12.34.56\n", GitVersion(12, 34, 56)),
+            ("git version 12.34.56.78.abcdef", GitVersion(12, 34, 56)),
+            // See https://github.com/arxanas/git-branchless/issues/69
+            ("git version 2.33.0-rc0", GitVersion(2, 33, 0)),
+            ("git version 2.38.0-rc2", GitVersion(2, 38, 0)),
+            // See https://github.com/arxanas/git-branchless/issues/85
+            ("git version 2.33.GIT", GitVersion(2, 33, 0)),
+            ("git version 2.32.1 (Apple Git-133)", GitVersion(2, 32, 1)),
+            ("git version 2.37.1.windows.1", GitVersion(2, 37, 1)),
+            ("git version 2.38", GitVersion(2, 38, 0)),
+            ("git version 2.38-rc1", GitVersion(2, 38, 0)),
+            ("2.39.0", GitVersion(2, 39, 0)),
+        ];
+        for (output, expected) in cases {
+            assert_eq!(
+                output.parse::<GitVersion>().unwrap(),
+                expected,
+                "parsing {:?}",
+                output
+            );
+        }
 
-        // See https://github.com/arxanas/git-branchless/issues/85
-        assert_eq!(
-            "git version 2.33.GIT".parse::<GitVersion>().unwrap(),
-            GitVersion(2, 33, 0)
-        );
+        for output in ["", "git version", "git version unknown", "hello world"] {
+            assert!(
+                output.parse::<GitVersion>().is_err(),
+                "parsing {:?} should fail",
+                output
+            );
+        }
     }
 
     #[test]
@@ -2228,6 +3568,19 @@ mod tests {
             entry.paths(),
             vec![PathBuf::from("new_file.rs"), PathBuf::from("old_file.rs")]
         );
+
+        assert_eq!(
+            TryInto::<StatusEntry>::try_into(
+                "u UU N... 100644 100644 100644 100644 8cf6c3f8e8f0d3c6b04ff8d8ab4e2bbb3a5e0e3d 5d9474c0309b7ca09a182d888f73b37a8fe1362c 0b6a4c8ea1f40b0f6a9ba2b6d7a4e3ed2f6dc3a8 conflicted file.rs".as_bytes(),
+            ).unwrap(),
+            StatusEntry {
+                index_status: FileStatus::Unmerged,
+                working_copy_status: FileStatus::Unmerged,
+                path: "conflicted file.rs".into(),
+                orig_path: None,
+                working_copy_file_mode: FileMode::Blob,
+            }
+        );
     }
 
     #[test]
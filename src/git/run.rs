@@ -5,22 +5,27 @@ use std::fmt::Write;
 use std::io::{BufRead, BufReader, Read, Write as WriteIo};
 use std::path::PathBuf;
 use std::process::{Command, ExitStatus, Stdio};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
 
 use cursive::theme::BaseColor;
 use cursive::utils::markup::StyledString;
 use eyre::{eyre, Context};
 use itertools::Itertools;
+use lazy_static::lazy_static;
 use os_str_bytes::OsStrBytes;
-use tracing::instrument;
+use tracing::{debug, instrument, warn};
 
-use crate::commands::smartlog::smartlog;
+use crate::commands::smartlog::{should_show_stack_smartlog, smartlog};
 use crate::core::config::get_core_hooks_path;
 use crate::core::effects::{Effects, OperationType};
-use crate::core::eventlog::{EventTransactionId, BRANCHLESS_TRANSACTION_ID_ENV_VAR};
+use crate::core::eventlog::{
+    init_db_schema, EventLogDb, EventTransactionId, BRANCHLESS_TRANSACTION_ID_ENV_VAR,
+};
 use crate::core::formatting::printable_styled_string;
-use crate::git::repo::Repo;
+use crate::core::snapshot::create_snapshot;
+use crate::git::repo::{GitVersion, Repo};
 use crate::util::get_sh;
 
 use super::CategorizedReferenceName;
@@ -48,9 +53,10 @@ impl std::fmt::Debug for GitRunInfo {
     }
 }
 
+/// Options for invoking Git with `GitRunInfo::run_silent`.
 pub struct GitRunOpts {
     /// If set, a non-zero exit code will be treated as an error.
-    treat_git_failure_as_error: bool,
+    pub treat_git_failure_as_error: bool,
 }
 
 impl Default for GitRunOpts {
@@ -61,6 +67,36 @@ impl Default for GitRunOpts {
     }
 }
 
+/// Options for invoking Git with `GitRunInfo::run_streaming`.
+#[derive(Debug)]
+pub struct GitRunStreamingOpts {
+    /// If set, a non-zero exit code will be treated as an error.
+    pub treat_git_failure_as_error: bool,
+
+    /// If set, the child's stdout is shown to the user as it's produced.
+    /// Otherwise, it's only captured, such as when it's machine-readable.
+    pub show_stdout: bool,
+
+    /// If set, and we're attached to a terminal, then the child's output goes
+    /// directly to the terminal rather than being captured, so that it can
+    /// interact with the user (such as by opening an editor).
+    pub use_terminal: bool,
+}
+
+impl Default for GitRunStreamingOpts {
+    fn default() -> Self {
+        Self {
+            treat_git_failure_as_error: true,
+            show_stdout: true,
+            use_terminal: false,
+        }
+    }
+}
+
+/// The prefix for lines of subprocess output written by
+/// `GitRunInfo::run_streaming`.
+const STREAMING_OUTPUT_PREFIX: &str = "[git] ";
+
 pub struct GitRunResult {
     pub exit_code: i32,
     pub stdout: Vec<u8>,
@@ -94,10 +130,52 @@ impl GitRunInfo {
                 None => return,
             };
             let reader = BufReader::new(stream);
-            for line in reader.lines() {
+            for line in reader.split(b'\n') {
                 let line = line.expect("Reading line from subprocess");
-                writeln!(output, "{}", line).expect("Writing line from subprocess");
+                writeln!(output, "{}", String::from_utf8_lossy(&line))
+                    .expect("Writing line from subprocess");
+            }
+        })
+    }
+
+    /// Like `spawn_writer_thread`, but prefixes each line written to `output`
+    /// and returns the raw contents of the stream once it's closed.
+    fn spawn_streaming_thread<
+        InputStream: Read + Send + 'static,
+        OutputStream: Write + Send + 'static,
+    >(
+        &self,
+        stream: Option<InputStream>,
+        mut output: Option<OutputStream>,
+    ) -> JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut captured = Vec::new();
+            let stream = match stream {
+                Some(stream) => stream,
+                None => return captured,
+            };
+            let mut reader = BufReader::new(stream);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                let num_bytes = reader
+                    .read_until(b'\n', &mut line)
+                    .expect("Reading line from subprocess");
+                if num_bytes == 0 {
+                    break;
+                }
+                captured.extend_from_slice(&line);
+                if let Some(output) = output.as_mut() {
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.strip_suffix('\n').unwrap_or(&line);
+                    // Git redraws progress messages in place with carriage
+                    // returns, so prefix each redrawn segment as well.
+                    let line = line.replace('\r', &format!("\r{}", STREAMING_OUTPUT_PREFIX));
+                    writeln!(output, "{}{}", STREAMING_OUTPUT_PREFIX, line)
+                        .expect("Writing line from subprocess");
+                }
             }
+            captured
         })
     }
 
@@ -128,6 +206,7 @@ impl GitRunInfo {
             &args_string
         )?;
 
+        debug!(?args, "Running Git subprocess");
         let mut command = Command::new(path_to_git);
         command.current_dir(working_directory);
         command.args(args);
@@ -186,6 +265,124 @@ impl GitRunInfo {
         )
     }
 
+    fn run_streaming_inner(
+        &self,
+        effects: &Effects,
+        repo: &Repo,
+        event_tx_id: Option<EventTransactionId>,
+        args: &[&OsStr],
+        opts: GitRunStreamingOpts,
+    ) -> eyre::Result<GitRunResult> {
+        let GitRunInfo {
+            path_to_git,
+            working_directory: _,
+            env,
+        } = self;
+        let GitRunStreamingOpts {
+            treat_git_failure_as_error,
+            show_stdout,
+            use_terminal,
+        } = opts;
+
+        let args_string = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect_vec()
+            .join(" ");
+        let command_string = format!("git {}", args_string);
+        let (effects, _progress) =
+            effects.start_operation(OperationType::RunGitCommand(Arc::new(command_string)));
+        writeln!(
+            effects.get_output_stream(),
+            "branchless: running command: {} {}",
+            &path_to_git.to_string_lossy(),
+            &args_string
+        )?;
+
+        let use_terminal =
+            use_terminal && console::user_attended() && console::user_attended_stderr();
+        debug!(?args, "Running Git subprocess");
+        let mut command = Command::new(path_to_git);
+        command.current_dir(
+            repo.get_working_copy_path()
+                .unwrap_or_else(|| repo.get_path()),
+        );
+        command.args(args);
+        command.env_clear();
+        command.envs(env.iter());
+        if let Some(event_tx_id) = event_tx_id {
+            command.env(BRANCHLESS_TRANSACTION_ID_ENV_VAR, event_tx_id.to_string());
+        }
+        // Leave stdin connected, so that Git can prompt for credentials and
+        // the like.
+        command.stdin(Stdio::inherit());
+        if use_terminal {
+            command.stdout(Stdio::inherit());
+            command.stderr(Stdio::inherit());
+        } else {
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+        }
+
+        let mut child = command.spawn().wrap_err("Spawning Git subprocess")?;
+
+        let stdout = child.stdout.take();
+        let stdout_thread = self.spawn_streaming_thread(
+            stdout,
+            if show_stdout {
+                Some(effects.get_output_stream())
+            } else {
+                None
+            },
+        );
+        let stderr = child.stderr.take();
+        let stderr_thread = self.spawn_streaming_thread(stderr, Some(effects.get_error_stream()));
+
+        let exit_status = child
+            .wait()
+            .wrap_err("Waiting for Git subprocess to complete")?;
+        let result = GitRunResult {
+            // On Unix, if the child process was terminated by a signal, we need to call
+            // some Unix-specific functions to access the signal that terminated it. For
+            // simplicity, just return `1` in those cases.
+            exit_code: exit_status.code().unwrap_or(1),
+            stdout: stdout_thread.join().unwrap(),
+            stderr: stderr_thread.join().unwrap(),
+        };
+        if treat_git_failure_as_error && !exit_status.success() {
+            eyre::bail!("Git subprocess failed: {:?}", result);
+        }
+        Ok(result)
+    }
+
+    /// Run Git in a subprocess, showing its output to the user as it's
+    /// produced, with each line prefixed by `[git]`. The output is also
+    /// captured, for error reporting or further processing.
+    ///
+    /// Unlike `run_silent`, this is suitable for long-running commands, such
+    /// as on-disk rebases or network operations, where the user should be able
+    /// to see that progress is being made.
+    ///
+    /// `args` contains the list of arguments to pass to Git, not including the Git
+    /// executable itself.
+    #[instrument]
+    pub fn run_streaming<S: AsRef<OsStr> + std::fmt::Debug>(
+        &self,
+        effects: &Effects,
+        repo: &Repo,
+        event_tx_id: Option<EventTransactionId>,
+        args: &[S],
+        opts: GitRunStreamingOpts,
+    ) -> eyre::Result<GitRunResult> {
+        self.run_streaming_inner(
+            effects,
+            repo,
+            event_tx_id,
+            args.iter().map(AsRef::as_ref).collect_vec().as_slice(),
+            opts,
+        )
+    }
+
     fn run_silent_inner(
         &self,
         repo: &Repo,
@@ -222,6 +419,7 @@ impl GitRunInfo {
             result.extend(args);
             result
         };
+        debug!(?args, "Running Git subprocess");
         let mut command = Command::new(path_to_git);
         command.args(&args);
         command.current_dir(working_directory);
@@ -266,6 +464,108 @@ impl GitRunInfo {
         )
     }
 
+    /// Determine the version of the Git executable.
+    ///
+    /// Spawning a process is relatively expensive (particularly on Windows),
+    /// so the result is cached for the rest of the process, and in the
+    /// repository's database for as long as the Git executable isn't modified.
+    #[instrument]
+    pub fn get_git_version(&self, repo: &Repo) -> eyre::Result<GitVersion> {
+        self.get_git_version_output(repo)?.parse()
+    }
+
+    /// Get the output of `git version` for the Git executable, cached in the
+    /// same way as for `get_git_version`.
+    #[instrument]
+    pub fn get_git_version_output(&self, repo: &Repo) -> eyre::Result<String> {
+        lazy_static! {
+            static ref GIT_VERSION_OUTPUTS: Mutex<HashMap<PathBuf, String>> = Default::default();
+        }
+        let mut outputs = GIT_VERSION_OUTPUTS.lock().unwrap();
+        if let Some(output) = outputs.get(&self.path_to_git) {
+            return Ok(output.clone());
+        }
+
+        let executable_info = self.get_git_executable_info();
+        let conn = match repo.get_db_conn().and_then(|conn| {
+            init_db_schema(&conn)?;
+            Ok(conn)
+        }) {
+            Ok(conn) => Some(conn),
+            Err(err) => {
+                warn!(?err, "Could not open database to cache Git version");
+                None
+            }
+        };
+        let cached_output = match (&conn, &executable_info) {
+            (Some(conn), Some(executable_info)) => {
+                read_cached_git_version_output(conn, executable_info).unwrap_or_else(|err| {
+                    warn!(?err, "Could not read cached Git version");
+                    None
+                })
+            }
+            _ => None,
+        };
+        let output = match cached_output {
+            Some(output) => output,
+            None => {
+                let output = self
+                    .run_silent(repo, None, &["version"], Default::default())
+                    .wrap_err("Determining Git version")?
+                    .stdout;
+                let output =
+                    String::from_utf8(output).wrap_err("Decoding stdout from Git subprocess")?;
+                let output = output.trim().to_owned();
+                if let (Some(conn), Some(executable_info)) = (&conn, &executable_info) {
+                    if let Err(err) =
+                        write_cached_git_version_output(conn, executable_info, &output)
+                    {
+                        warn!(?err, "Could not cache Git version");
+                    }
+                }
+                output
+            }
+        };
+
+        outputs.insert(self.path_to_git.clone(), output.clone());
+        Ok(output)
+    }
+
+    /// Find the Git executable on disk (searching `PATH` if necessary) and get
+    /// the information used to detect whether it has been modified.
+    fn get_git_executable_info(&self) -> Option<GitExecutableInfo> {
+        let path = if self.path_to_git.components().count() > 1 {
+            self.working_directory.join(&self.path_to_git)
+        } else {
+            let paths = self.env.get(OsStr::new("PATH"))?;
+            let mut candidates = Vec::new();
+            for dir in std::env::split_paths(paths) {
+                let candidate = dir.join(&self.path_to_git);
+                if cfg!(windows) {
+                    candidates.push(candidate.with_extension("exe"));
+                }
+                candidates.push(candidate);
+            }
+            candidates.into_iter().find(|path| path.is_file())?
+        };
+        let path = std::fs::canonicalize(path).ok()?;
+        let metadata = std::fs::metadata(&path).ok()?;
+        let mtime = metadata.modified().ok()?;
+        let mtime_nanos = mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()?
+            .as_nanos()
+            .try_into()
+            .ok()?;
+        let size = metadata.len().try_into().ok()?;
+        let path = path.to_str()?.to_owned();
+        Some(GitExecutableInfo {
+            path,
+            mtime_nanos,
+            size,
+        })
+    }
+
     fn run_hook_inner(
         &self,
         effects: &Effects,
@@ -274,10 +574,10 @@ impl GitRunInfo {
         event_tx_id: EventTransactionId,
         args: &[&str],
         stdin: Option<OsString>,
-    ) -> eyre::Result<()> {
+    ) -> eyre::Result<bool> {
         let hook_dir = get_core_hooks_path(repo)?;
         if !hook_dir.exists() {
-            return Ok(());
+            return Ok(true);
         }
 
         let GitRunInfo {
@@ -334,12 +634,13 @@ impl GitRunInfo {
             let stderr = child.stderr.take();
             let stderr_thread = self.spawn_writer_thread(stderr, effects.get_error_stream());
 
-            let _ignored: ExitStatus =
+            let exit_status: ExitStatus =
                 child.wait().wrap_err("Waiting for child process to exit")?;
             stdout_thread.join().unwrap();
             stderr_thread.join().unwrap();
+            return Ok(exit_status.success());
         }
-        Ok(())
+        Ok(true)
     }
 
     /// Run a provided Git hook if it exists for the repository.
@@ -355,19 +656,110 @@ impl GitRunInfo {
         args: &[S],
         stdin: Option<OsString>,
     ) -> eyre::Result<()> {
-        self.run_hook_inner(
+        let _succeeded: bool = self.run_hook_inner(
             effects,
             repo,
             hook_name,
             event_tx_id,
             args.iter().map(AsRef::as_ref).collect_vec().as_slice(),
             stdin,
+        )?;
+        Ok(())
+    }
+
+    /// Run a provided Git hook if it exists for the repository, as with
+    /// `run_hook`. Returns `false` if the hook exited unsuccessfully (such as
+    /// when a `commit-msg` hook rejects a commit message), and `true`
+    /// otherwise.
+    #[instrument]
+    pub fn run_hook_checked<S: AsRef<str> + std::fmt::Debug>(
+        &self,
+        effects: &Effects,
+        repo: &Repo,
+        hook_name: &str,
+        event_tx_id: EventTransactionId,
+        args: &[S],
+    ) -> eyre::Result<bool> {
+        self.run_hook_inner(
+            effects,
+            repo,
+            hook_name,
+            event_tx_id,
+            args.iter().map(AsRef::as_ref).collect_vec().as_slice(),
+            None,
         )
     }
 }
 
+/// Identifies a particular build of the Git executable, so that its cached
+/// version can be discarded if it's replaced or upgraded.
+#[derive(Debug)]
+struct GitExecutableInfo {
+    path: String,
+    mtime_nanos: i64,
+    size: i64,
+}
+
+fn read_cached_git_version_output(
+    conn: &rusqlite::Connection,
+    executable_info: &GitExecutableInfo,
+) -> eyre::Result<Option<String>> {
+    let GitExecutableInfo {
+        path,
+        mtime_nanos,
+        size,
+    } = executable_info;
+    let mut stmt = conn.prepare(
+        "
+SELECT output
+FROM git_version_cache
+WHERE path = :path AND mtime_nanos = :mtime_nanos AND size = :size
+",
+    )?;
+    let mut rows = stmt.query(rusqlite::named_params! {
+        ":path": path,
+        ":mtime_nanos": mtime_nanos,
+        ":size": size,
+    })?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get("output")?)),
+        None => Ok(None),
+    }
+}
+
+fn write_cached_git_version_output(
+    conn: &rusqlite::Connection,
+    executable_info: &GitExecutableInfo,
+    output: &str,
+) -> eyre::Result<()> {
+    let GitExecutableInfo {
+        path,
+        mtime_nanos,
+        size,
+    } = executable_info;
+    conn.execute(
+        "
+INSERT OR REPLACE INTO git_version_cache (path, mtime_nanos, size, output)
+VALUES (:path, :mtime_nanos, :size, :output)
+",
+        rusqlite::named_params! {
+            ":path": path,
+            ":mtime_nanos": mtime_nanos,
+            ":size": size,
+            ":output": output,
+        },
+    )
+    .wrap_err("Writing to `git_version_cache` table")?;
+    Ok(())
+}
+
 /// Checks out the requested commit. If the operation succeeds, then displays
-/// the new smartlog. Otherwise displays a warning message.
+/// the new smartlog (unless the compact smartlog of the current stack will be
+/// displayed instead). Otherwise displays a warning message.
+///
+/// If the working copy has uncommitted changes, a snapshot of it is taken
+/// first, so that the changes can be restored by `git undo` if the checkout
+/// ends up overwriting them.
 pub fn check_out_commit(
     effects: &Effects,
     git_run_info: &GitRunInfo,
@@ -375,6 +767,15 @@ pub fn check_out_commit(
     target: impl AsRef<OsStr> + std::fmt::Debug,
     additional_args: impl IntoIterator<Item = impl AsRef<OsStr>>,
 ) -> eyre::Result<isize> {
+    let repo = Repo::from_dir(&git_run_info.working_directory)?;
+    let conn = repo.get_db_conn()?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = match event_tx_id {
+        Some(event_tx_id) => event_tx_id,
+        None => event_log_db.make_transaction_id(SystemTime::now(), "checkout")?,
+    };
+    create_snapshot(&repo, git_run_info, &mut event_log_db, event_tx_id)?;
+
     let categorized_target = CategorizedReferenceName::new(target.as_ref());
     let target = categorized_target.remove_prefix()?;
 
@@ -384,10 +785,14 @@ pub fn check_out_commit(
         args.extend(additional_args.iter().map(|arg| arg.as_ref()));
         args
     };
-    let result = git_run_info.run(effects, event_tx_id, args.as_slice())?;
+    let result = git_run_info.run(effects, Some(event_tx_id), args.as_slice())?;
 
     if result == 0 {
-        smartlog(effects, git_run_info, &Default::default())?;
+        // Navigation and rewrite commands display the compact smartlog
+        // themselves once they've finished, if enabled.
+        if !should_show_stack_smartlog(effects, &repo)? {
+            smartlog(effects, git_run_info, &Default::default())?;
+        }
     } else {
         writeln!(
             effects.get_output_stream(),
@@ -495,4 +900,37 @@ mod tests {
 
         Ok(())
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_git_version_cached_in_process() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+        let repo = git.get_repo()?;
+
+        let shim_dir = tempfile::tempdir()?;
+        let shim_path = shim_dir.path().join("git");
+        let log_path = shim_dir.path().join("version.log");
+        git.write_counting_git_shim(&shim_path, &log_path, "version")?;
+        let git_run_info = GitRunInfo {
+            path_to_git: shim_path,
+            working_directory: git.repo_path.clone(),
+            env: std::env::vars_os().collect(),
+        };
+        let count_spawns = || -> eyre::Result<usize> {
+            let log = std::fs::read_to_string(&log_path)?;
+            Ok(log.lines().count())
+        };
+
+        let version = git_run_info.get_git_version(&repo)?;
+        assert_eq!(count_spawns()?, 1);
+
+        // Even without the persistent cache, Git isn't spawned again by this
+        // process.
+        std::fs::remove_file(repo.get_path().join("branchless").join("db.sqlite3"))?;
+        assert_eq!(git_run_info.get_git_version(&repo)?, version);
+        assert_eq!(count_spawns()?, 1);
+
+        Ok(())
+    }
 }
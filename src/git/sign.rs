@@ -0,0 +1,201 @@
+//! Signing commits with the program configured for `git commit -S`.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use eyre::Context;
+use tempfile::NamedTempFile;
+use tracing::instrument;
+
+use super::config::ConfigRead;
+
+/// The kind of signature to produce, as determined by the `gpg.format` setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SigningFormat {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+/// Signs commit buffers in the same way as Git would when `commit.gpgsign` is
+/// set.
+#[derive(Clone, Debug)]
+pub struct CommitSigner {
+    format: SigningFormat,
+    program: String,
+    signing_key: Option<String>,
+}
+
+impl CommitSigner {
+    /// Determine how to sign commits from the `gpg.format`, `gpg.program`,
+    /// `gpg.<format>.program`, and `user.signingKey` settings. This doesn't
+    /// check whether signing is enabled.
+    #[instrument(skip(config))]
+    pub fn from_config(config: &impl ConfigRead) -> eyre::Result<Self> {
+        let format = match config.get::<String, _>("gpg.format")?.as_deref() {
+            None | Some("openpgp") => SigningFormat::OpenPgp,
+            Some("x509") => SigningFormat::X509,
+            Some("ssh") => SigningFormat::Ssh,
+            Some(other) => eyre::bail!("Unsupported value for gpg.format: {:?}", other),
+        };
+        let program = match format {
+            SigningFormat::OpenPgp => match config.get("gpg.openpgp.program")? {
+                Some(program) => program,
+                None => config.get_or("gpg.program", "gpg".to_string())?,
+            },
+            SigningFormat::X509 => config.get_or("gpg.x509.program", "gpgsm".to_string())?,
+            SigningFormat::Ssh => config.get_or("gpg.ssh.program", "ssh-keygen".to_string())?,
+        };
+        let signing_key = config.get("user.signingKey")?;
+        Ok(Self {
+            format,
+            program,
+            signing_key,
+        })
+    }
+
+    /// Produce a detached signature for `buffer`, the contents of an unsigned
+    /// commit object. `committer_ident` is used as the signing key for GPG if
+    /// `user.signingKey` isn't set.
+    #[instrument(skip(buffer))]
+    pub fn sign(
+        &self,
+        buffer: &[u8],
+        committer_ident: &str,
+        tempfile_dir: &Path,
+    ) -> eyre::Result<String> {
+        let signature = match self.format {
+            SigningFormat::OpenPgp | SigningFormat::X509 => {
+                let signing_key = self.signing_key.as_deref().unwrap_or(committer_ident);
+                self.sign_gpg(buffer, signing_key)?
+            }
+            SigningFormat::Ssh => self.sign_ssh(buffer, tempfile_dir)?,
+        };
+        if signature.trim().is_empty() {
+            eyre::bail!(
+                "Signing program {:?} produced an empty signature",
+                self.program
+            );
+        }
+        Ok(signature)
+    }
+
+    fn sign_gpg(&self, buffer: &[u8], signing_key: &str) -> eyre::Result<String> {
+        let mut child = Command::new(&self.program)
+            .args(["--status-fd=2", "-bsau", signing_key])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .wrap_err_with(|| format!("Spawning signing program {:?}", self.program))?;
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(buffer)
+            .wrap_err("Writing commit to signing program")?;
+        let output = child
+            .wait_with_output()
+            .wrap_err("Waiting for signing program")?;
+        if !output.status.success() {
+            eyre::bail!(
+                "Signing program {:?} failed with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        String::from_utf8(output.stdout).wrap_err("Decoding signature as UTF-8")
+    }
+
+    fn sign_ssh(&self, buffer: &[u8], tempfile_dir: &Path) -> eyre::Result<String> {
+        let signing_key = match &self.signing_key {
+            Some(signing_key) => signing_key,
+            None => eyre::bail!("user.signingKey must be set to sign commits with gpg.format=ssh"),
+        };
+        std::fs::create_dir_all(tempfile_dir).wrap_err("Creating tempfile dir")?;
+
+        // Like Git, accept either a path to a key file or a literal public key,
+        // in which case `ssh-keygen` looks up the private key in the agent.
+        let literal_key = match signing_key.strip_prefix("key::") {
+            Some(literal_key) => Some(literal_key),
+            None if signing_key.starts_with("ssh-") => Some(signing_key.as_str()),
+            None => None,
+        };
+        let key_file = match literal_key {
+            Some(literal_key) => {
+                let mut key_file = NamedTempFile::new_in(tempfile_dir)
+                    .wrap_err("Creating temporary signing key file")?;
+                key_file
+                    .write_all(literal_key.as_bytes())
+                    .wrap_err("Writing temporary signing key file")?;
+                Some(key_file)
+            }
+            None => None,
+        };
+        let key_path = match &key_file {
+            Some(key_file) => key_file.path().to_owned(),
+            None => match signing_key.strip_prefix("~/") {
+                Some(rest) => match std::env::var_os("HOME") {
+                    Some(home) => PathBuf::from(home).join(rest),
+                    None => PathBuf::from(signing_key),
+                },
+                None => PathBuf::from(signing_key),
+            },
+        };
+
+        let mut buffer_file =
+            NamedTempFile::new_in(tempfile_dir).wrap_err("Creating temporary commit file")?;
+        buffer_file
+            .write_all(buffer)
+            .wrap_err("Writing temporary commit file")?;
+        let mut command = Command::new(&self.program);
+        command.args(["-Y", "sign", "-n", "git", "-f"]);
+        command.arg(&key_path);
+        if key_file.is_some() {
+            command.arg("-U");
+        }
+        command.arg(buffer_file.path());
+        let output = command
+            .stdin(Stdio::null())
+            .output()
+            .wrap_err_with(|| format!("Running signing program {:?}", self.program))?;
+        if !output.status.success() {
+            eyre::bail!(
+                "Signing program {:?} failed with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let mut signature_path = buffer_file.path().as_os_str().to_owned();
+        signature_path.push(".sig");
+        let signature = std::fs::read_to_string(&signature_path)
+            .wrap_err_with(|| format!("Reading signature file {:?}", signature_path));
+        let _ = std::fs::remove_file(&signature_path);
+        signature
+    }
+}
+
+/// Insert `signature` into the headers of the unsigned commit object `buffer`,
+/// as a `gpgsig` header.
+pub fn add_signature_header(buffer: &[u8], signature: &str) -> eyre::Result<Vec<u8>> {
+    let headers_end = match buffer.windows(2).position(|window| window == b"\n\n") {
+        Some(index) => index + 1,
+        None => eyre::bail!("Unexpected commit buffer format: {:?}", buffer),
+    };
+    let mut header = String::from("gpgsig");
+    for line in signature.trim_end_matches('\n').split('\n') {
+        header.push(' ');
+        header.push_str(line);
+        header.push('\n');
+    }
+
+    let mut result = Vec::with_capacity(buffer.len() + header.len());
+    result.extend_from_slice(&buffer[..headers_end]);
+    result.extend_from_slice(header.as_bytes());
+    result.extend_from_slice(&buffer[headers_end..]);
+    Ok(result)
+}
@@ -128,6 +128,9 @@ fn get_changed_paths_between_trees_internal(
                 None => return Ok(ClassifiedEntry::Absent),
             };
 
+            // Gitlinks (submodule pointers) have kind `Commit`, and the
+            // commits they point to usually aren't in this repository, so
+            // they're compared by OID like files, and never looked up.
             let file_mode = entry.filemode_raw();
             match entry.kind() {
                 Some(git2::ObjectType::Tree) => Ok(ClassifiedEntry::Tree(entry.id(), file_mode)),
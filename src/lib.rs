@@ -19,6 +19,7 @@
 #![warn(clippy::all, missing_docs)]
 #![allow(clippy::too_many_arguments, clippy::blocks_in_if_conditions)]
 
+mod api;
 pub mod commands;
 pub mod core;
 pub mod git;
@@ -26,3 +27,5 @@ pub mod opts;
 pub mod testing;
 pub mod tui;
 pub mod util;
+
+pub use api::{amend, init, restack, smartlog, undo, Error, Result};
@@ -14,7 +14,7 @@ pub enum WrappedCommand {
 }
 
 /// Options for moving commits.
-#[derive(Args, Debug)]
+#[derive(Args, Debug, Default)]
 pub struct MoveOptions {
     /// Only attempt to perform an in-memory rebase. If it fails, do not
     /// attempt an on-disk rebase.
@@ -31,6 +31,43 @@ pub struct MoveOptions {
     #[clap(name = "merge", short = 'm', long = "merge")]
     pub resolve_merge_conflicts: bool,
 
+    /// Keep the committer timestamps of rewritten commits, rather than
+    /// updating them to the current time. This is always the case if the
+    /// `branchless.restack.preserveTimestamps` config option is set.
+    #[clap(long = "preserve-timestamps")]
+    pub preserve_timestamps: bool,
+
+    /// Drop merge commits instead of re-creating them on top of the moved
+    /// commits, so that only first-parent history is replayed.
+    #[clap(long = "drop-merges")]
+    pub drop_merges: bool,
+
+    /// Keep commits which become empty when rewritten (for example, because
+    /// their changes were already applied upstream), rather than skipping them.
+    /// Commits which were empty to begin with are always kept.
+    #[clap(long = "keep-empty")]
+    pub keep_empty: bool,
+
+    /// Fold `fixup!`, `squash!`, and `amend!` commits into the commits which
+    /// they target, as `git rebase --autosquash` would. This is always the
+    /// case if the `rebase.autoSquash` config option is set.
+    #[clap(long = "autosquash")]
+    pub autosquash: bool,
+
+    /// Allow rewriting commits which are public (i.e. ancestors of the main
+    /// branch). Rewriting them makes the local history diverge from the
+    /// shared history, so this is refused by default.
+    #[clap(long = "force-rewrite-public")]
+    pub force_rewrite_public: bool,
+
+    /// If any of the rewritten commits are checked out in other worktrees,
+    /// check out their rewritten versions in those worktrees afterwards. The
+    /// worktrees must not have uncommitted changes. Without this option, the
+    /// rewrite is refused, since it would leave those worktrees pointing to
+    /// the old versions of the commits.
+    #[clap(long = "update-worktrees")]
+    pub update_worktrees: bool,
+
     /// Debugging option. Print the constraints used to create the rebase
     /// plan before executing it.
     #[clap(long = "debug-dump-rebase-constraints")]
@@ -42,6 +79,20 @@ pub struct MoveOptions {
     pub dump_rebase_plan: bool,
 }
 
+/// Options for creating commits with a message written by the user, as with
+/// the corresponding options to `git commit`.
+#[derive(Args, Debug, Default)]
+pub struct CommitOptions {
+    /// Don't run the `pre-commit` and `commit-msg` hooks.
+    #[clap(short = 'n', long = "no-verify")]
+    pub no_verify: bool,
+
+    /// Add a `Signed-off-by` trailer for the committer to the commit message.
+    /// This is always the case if the `format.signOff` config option is set.
+    #[clap(short = 's', long = "signoff")]
+    pub signoff: bool,
+}
+
 /// Options for traversing commits.
 #[derive(Args, Debug)]
 pub struct TraverseCommitsOptions {
@@ -76,43 +127,278 @@ pub struct TraverseCommitsOptions {
     )]
     pub interactive: bool,
 
+    /// When going to the parent of a merge commit, go to the parent with this
+    /// number (starting from 1, as in `<commit>^<n>`). Subsequent merge commits
+    /// are traversed via their first parent. Only valid for `git prev`.
+    #[clap(long = "parent", conflicts_with("move-by-branches"))]
+    pub parent: Option<usize>,
+
     /// If the local changes conflict with the destination commit, attempt to
     /// merge them.
     #[clap(short = 'm', long = "merge")]
     pub merge: bool,
 }
 
+/// A subcommand of `git branchless archive`.
+#[derive(Parser)]
+pub enum ArchiveCommand {
+    /// List the archives, along with how many commits each contains and when
+    /// it was created.
+    List,
+
+    /// Display a smartlog of the commits in an archive, including those which
+    /// are hidden.
+    Show {
+        /// The name of the archive.
+        name: String,
+    },
+
+    /// Unhide all of the commits in an archive.
+    Restore {
+        /// The name of the archive.
+        name: String,
+    },
+
+    /// Delete an archive. Any of its commits which are still hidden are no
+    /// longer kept, and will be garbage-collected once they've been hidden
+    /// for longer than `branchless.gc.retentionDays`.
+    Delete {
+        /// The name of the archive.
+        name: String,
+    },
+}
+
 /// FIXME: write man-page text
 #[derive(Parser)]
 pub enum Command {
+    /// Absorb the uncommitted changes into the commits in the current stack
+    /// which last changed the surrounding lines, and restack their
+    /// descendants. Changes which can't be attributed to a single commit in
+    /// the stack are left in the working copy.
+    Absorb {
+        /// Only absorb the changes which are staged in the index.
+        #[clap(long = "staged")]
+        staged: bool,
+
+        /// Print which commits each change would be absorbed into, without
+        /// absorbing them.
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Manage the named groups of hidden commits created by `git hide
+    /// --archive`.
+    Archive {
+        /// The archive operation to carry out.
+        #[clap(subcommand)]
+        command: ArchiveCommand,
+    },
+
     /// Amend the current HEAD commit.
     Amend {
+        /// Use the given message as the commit message. If multiple messages
+        /// are given, they are concatenated as separate paragraphs.
+        #[clap(long = "message", multiple_occurrences(true))]
+        messages: Vec<String>,
+
+        /// Open an editor on the commit message, pre-filled with the current
+        /// message (or the messages passed with `--message`). This can be used
+        /// without any uncommitted changes to reword the current commit.
+        #[clap(short = 'e', long = "edit", alias = "reword")]
+        edit: bool,
+
+        /// Only amend the changes which are staged in the index, even if
+        /// nothing is staged. Unstaged changes are left in the working copy.
+        #[clap(long = "staged")]
+        staged: bool,
+
+        /// Also amend untracked files (limited to the given paths, if any).
+        /// Otherwise, untracked files are only amended if they are named
+        /// explicitly.
+        #[clap(short = 'u', long = "include-untracked")]
+        include_untracked: bool,
+
+        /// Only amend changes to the given paths.
+        pathspecs: Vec<String>,
+
+        /// Don't restack the descendants of the amended commit onto it. This
+        /// is also the default if the `branchless.amend.autoRestack` config
+        /// option is set to `false`.
+        #[clap(long = "no-restack")]
+        no_restack: bool,
+
+        /// Options for creating the amended commit.
+        #[clap(flatten)]
+        commit_options: CommitOptions,
+
         /// Options for moving commits.
         #[clap(flatten)]
         move_options: MoveOptions,
     },
 
+    /// Print diagnostic information to include in a bug report, such as
+    /// version numbers, installed hooks, and recent events. The contents of
+    /// files and diffs are never included, and commit messages are redacted
+    /// unless `--include-messages` is passed.
+    BugReport {
+        /// The number of most recent event log transactions to include.
+        #[clap(short = 'n', long = "num-transactions", default_value = "10")]
+        num_transactions: usize,
+
+        /// Include commit messages in the report.
+        #[clap(long = "include-messages")]
+        include_messages: bool,
+    },
+
     /// Interactively pick a commit to checkout.
     Checkout {
-        /// A query to pre-fill the search text with.
+        /// A query to pre-fill the search text with. Alternatively, pass `-`
+        /// to return to the previous position of `HEAD` (or `@{-<n>}` for the
+        /// `n`th previous position), as recorded in the event log.
         #[clap(default_value = "")]
         initial_query: String,
+
+        /// Also offer commits which have been hidden.
+        #[clap(long = "hidden")]
+        show_hidden_commits: bool,
+
+        /// If the local changes conflict with the destination commit, attempt
+        /// to merge them.
+        #[clap(short = 'm', long = "merge")]
+        merge: bool,
+
+        /// Create a new branch with the given name and check it out, rather
+        /// than prompting for a commit. The branch starts at the commit given
+        /// as the query, or at `HEAD` if none is given.
+        #[clap(
+            short = 'b',
+            value_name = "new-branch",
+            conflicts_with_all(&["show-hidden-commits", "force-branch-name"])
+        )]
+        branch_name: Option<String>,
+
+        /// As with `-b`, but reset the branch to the start point if it already
+        /// exists.
+        #[clap(
+            short = 'B',
+            value_name = "new-branch",
+            conflicts_with = "show-hidden-commits"
+        )]
+        force_branch_name: Option<String>,
+    },
+
+    /// Show the combined changes of the current stack, a commit, or a set of
+    /// commits. The diff is rendered by `git diff` or `git show`, so your diff
+    /// and pager configuration applies.
+    Diff {
+        /// The commits to show. A single commit's patch is shown as with `git
+        /// show`; otherwise, the combined diff of the commits against the
+        /// commit they're based on is shown. Defaults to the commits in the
+        /// current stack up to `HEAD`. Revset expressions are accepted (see
+        /// `git branchless query`).
+        revset: Option<String>,
+
+        /// Show a diffstat instead of the patch.
+        #[clap(long = "stat")]
+        stat: bool,
+
+        /// Show only the names of the changed files.
+        #[clap(long = "name-only", conflicts_with = "stat")]
+        name_only: bool,
+    },
+
+    /// Export commits as a series of patches, in the style of `git
+    /// format-patch`, such as for submitting them by email.
+    Export {
+        /// The commits to export. Defaults to the current stack (`stack()`).
+        /// Revset expressions are accepted (see `git branchless query`).
+        revsets: Vec<String>,
+
+        /// The directory to write the patch files to. Defaults to the current
+        /// directory.
+        #[clap(short = 'o', long = "output-directory", conflicts_with_all(&["mbox", "stdout"]))]
+        output_directory: Option<PathBuf>,
+
+        /// Write all of the patches to the given file in mbox format, rather
+        /// than to separate files.
+        #[clap(long = "mbox", conflicts_with("stdout"))]
+        mbox: Option<PathBuf>,
+
+        /// Write all of the patches to standard output in mbox format, rather
+        /// than to separate files.
+        #[clap(long = "stdout")]
+        stdout: bool,
+
+        /// Mark the patches as the given version of the series, as in `[PATCH
+        /// v2 1/3]`. (Unlike `git format-patch`, there's no `-v` shorthand,
+        /// since `-v` is the global `--verbose` option.)
+        #[clap(long = "reroll-count")]
+        reroll_count: Option<usize>,
+
+        /// Also generate a cover letter, which describes the series as a
+        /// whole.
+        #[clap(long = "cover-letter")]
+        cover_letter: bool,
     },
 
     /// Run internal garbage collection.
-    Gc,
+    Gc {
+        /// Only report how many commits would be released (or events pruned),
+        /// without adding or deleting any references (or events).
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+
+        /// Also delete old events from the event log which are no longer
+        /// needed to determine which commits are visible. Older history will no
+        /// longer be available to `git undo`.
+        #[clap(long = "prune-events")]
+        prune_events: bool,
+
+        /// Prune events from before this time, given as a duration (such as
+        /// `30d` or `12h`) or a date (such as `2021-06-01`). Defaults to
+        /// `branchless.eventlog.autoPruneDays` days ago, or 90 days ago if
+        /// that isn't set.
+        #[clap(long = "before", requires = "prune-events")]
+        before: Option<String>,
+    },
 
     /// Hide the provided commits from the smartlog.
     Hide {
         /// Zero or more commits to hide.
         ///
-        /// Can either be hashes, like `abc123`, or ref-specs, like `HEAD^`.
+        /// Can either be hashes, like `abc123`, ref-specs, like `HEAD^`,
+        /// ranges, like `abc123..def456` or `abc123::def456`, or revset
+        /// expressions, like `stack(HEAD)` (see `git branchless query`).
         commits: Vec<String>,
 
         /// Also recursively hide all visible children commits of the provided
         /// commits.
         #[clap(short = 'r', long = "recursive")]
         recursive: bool,
+
+        /// Allow hiding commits which are public (i.e. ancestors of the main
+        /// branch) when they're selected by a range, like `abc123..def456`.
+        /// This is refused by default.
+        #[clap(long = "force-rewrite-public")]
+        force_rewrite_public: bool,
+
+        /// Also record the hidden commits in an archive with the given name,
+        /// so that they can be found and restored together later (see `git
+        /// branchless archive`). Archived commits aren't garbage-collected.
+        #[clap(long = "archive", value_name = "NAME")]
+        archive: Option<String>,
+    },
+
+    /// Internal use.
+    HelpViewer {
+        /// The directory which the man-pages were installed into for the
+        /// current user, if any.
+        #[clap(long = "man-dir")]
+        man_dir: Option<PathBuf>,
+
+        /// The name of the man page requested by `git help`, such as
+        /// `git-branchless-smartlog`.
+        page: String,
     },
 
     /// Internal use.
@@ -136,6 +422,9 @@ pub enum Command {
         is_branch_checkout: isize,
     },
 
+    /// Internal use.
+    HookPostApplypatch,
+
     /// Internal use.
     HookPostCommit,
 
@@ -160,6 +449,12 @@ pub enum Command {
     /// Internal use.
     HookRegisterExtraPostRewriteHook,
 
+    /// Internal use.
+    HookDropMergeCommit {
+        /// The OID of the merge commit that was dropped.
+        commit_oid: String,
+    },
+
     /// Internal use.
     HookSkipUpstreamAppliedCommit {
         /// The OID of the commit that was skipped.
@@ -176,8 +471,103 @@ pub enum Command {
         ///
         /// If not set, it will be auto-detected. If it can't be auto-detected,
         /// then you will be prompted to enter a value for the main branch name.
-        #[clap(long = "main-branch", conflicts_with = "uninstall")]
-        main_branch_name: Option<String>,
+        ///
+        /// May be provided more than once to configure several main branches,
+        /// such as release branches. Commits reachable from any of them are
+        /// considered public. The first one is the primary main branch.
+        #[clap(
+            long = "main-branch",
+            conflicts_with = "uninstall",
+            multiple_occurrences(true)
+        )]
+        main_branch_names: Vec<String>,
+
+        /// Don't import the existing history of `HEAD` and the local branches
+        /// from their reflogs. Without it, `git undo` can't reach states from
+        /// before initialization.
+        #[clap(long = "no-seed", conflicts_with = "uninstall")]
+        no_seed: bool,
+
+        /// The maximum number of reflog entries to import for each reference,
+        /// and of existing commits to mark as visible.
+        #[clap(
+            long = "seed-depth",
+            default_value = "1000",
+            conflicts_with_all = &["uninstall", "no-seed"]
+        )]
+        seed_depth: usize,
+
+        /// Where to install the hooks if `core.hooksPath` refers to a
+        /// directory outside of this repository, which may be shared with
+        /// other repositories.
+        #[clap(long = "hooks-dir", arg_enum, conflicts_with = "uninstall")]
+        hooks_dir: Option<HooksDirSetting>,
+
+        /// Where to place the git-branchless section in hooks which already
+        /// exist. Use `prepend` if an existing hook exits before reaching the
+        /// end of the script.
+        #[clap(
+            long = "hook-position",
+            arg_enum,
+            default_value = "append",
+            conflicts_with = "uninstall"
+        )]
+        hook_position: HookPosition,
+
+        /// Install the man-pages once for the current user, and configure
+        /// `git help` to use them in every repository, instead of setting up
+        /// this repository. With `--uninstall`, remove them again.
+        #[clap(
+            long = "global",
+            conflicts_with_all = &["main-branch-names", "no-seed", "seed-depth", "hooks-dir", "hook-position"]
+        )]
+        global: bool,
+
+        /// Where to install the man-pages for the current user. Defaults to
+        /// `$XDG_DATA_HOME/git-branchless/man` when `--global` is passed.
+        /// Without `--global`, this repository is set up as well.
+        #[clap(long = "man-dir")]
+        man_dir: Option<PathBuf>,
+
+        /// Only reinstall the hooks of an already-initialized repository,
+        /// such as after changing `branchless.hooks.<hook-type>.enabled`, and
+        /// regenerate any settings which refer to the location the repository
+        /// was copied from, without changing anything else.
+        #[clap(
+            long = "update",
+            conflicts_with_all = &["uninstall", "main-branch-names", "no-seed", "seed-depth", "hooks-dir", "hook-position", "global", "man-dir"]
+        )]
+        update: bool,
+
+        /// Only rewrite the aliases, hooks, and settings left behind by older
+        /// versions of git-branchless into their current forms. This is also
+        /// done as part of initialization.
+        #[clap(
+            long = "repair-aliases",
+            conflicts_with_all = &["uninstall", "main-branch-names", "no-seed", "seed-depth", "hooks-dir", "hook-position", "global", "man-dir", "update"]
+        )]
+        repair_aliases: bool,
+
+        /// If initialization fails partway through, leave the changes made so
+        /// far in place for debugging, rather than rolling them back.
+        #[clap(
+            long = "keep-partial",
+            conflicts_with_all = &["uninstall", "global", "update"]
+        )]
+        keep_partial: bool,
+
+        /// Check that the hooks, aliases, and configuration installed by
+        /// `git branchless init` are still in place, without changing
+        /// anything.
+        #[clap(
+            long = "check",
+            conflicts_with_all = &["uninstall", "main-branch-names", "no-seed", "seed-depth", "hooks-dir", "hook-position", "global", "man-dir", "update", "keep-partial"]
+        )]
+        check: bool,
+
+        /// With `--check`, reinstall anything which fails the checks.
+        #[clap(long = "fix", requires = "check")]
+        fix: bool,
     },
 
     /// Move a subtree of commits from one location to another.
@@ -191,9 +581,12 @@ pub enum Command {
     /// `post-commit` hooks are not called during in-memory rebases.
     Move {
         /// The source commit to move. This commit, and all of its descendants,
-        /// will be moved.
-        #[clap(short = 's', long = "source")]
-        source: Option<String>,
+        /// will be moved. May be provided more than once to move several
+        /// subtrees onto the same destination. If none of `--source`,
+        /// `--base`, or `--exact` is provided, defaults to the root of the
+        /// current stack.
+        #[clap(short = 's', long = "source", multiple_occurrences(true))]
+        source: Vec<String>,
 
         /// A commit inside a subtree to move. The entire subtree, starting from
         /// the main branch, will be moved, not just the commits descending from
@@ -201,11 +594,51 @@ pub enum Command {
         #[clap(short = 'b', long = "base", conflicts_with = "source")]
         base: Option<String>,
 
+        /// A commit to move by itself, without its descendants. May be
+        /// provided more than once, in which case the commits must form a
+        /// contiguous range, and are moved together. Any other children of the
+        /// moved commits are moved onto the parent of the range.
+        #[clap(
+            short = 'x',
+            long = "exact",
+            conflicts_with_all(&["source", "base"]),
+            multiple_occurrences(true)
+        )]
+        exact: Vec<String>,
+
         /// The destination commit to move all source commits onto. If not
-        /// provided, defaults to the current commit.
+        /// provided, defaults to the tip of the main branch. Pass `.` to use
+        /// the current commit.
         #[clap(short = 'd', long = "dest")]
         dest: Option<String>,
 
+        /// Insert the moved commits between the destination commit and its
+        /// children. The children of the destination commit are moved on top
+        /// of the last moved commit.
+        #[clap(short = 'I', long = "insert")]
+        insert: bool,
+
+        /// Continue a move which stopped because of merge conflicts, after
+        /// the conflicts have been resolved.
+        #[clap(
+            long = "continue",
+            conflicts_with_all(&["source", "base", "exact", "dest", "insert", "abort-move"])
+        )]
+        continue_move: bool,
+
+        /// Abort a move which stopped because of merge conflicts, restoring
+        /// the repository to its state before the move.
+        #[clap(
+            long = "abort",
+            conflicts_with_all(&["source", "base", "exact", "dest", "insert", "continue-move"])
+        )]
+        abort_move: bool,
+
+        /// Print the commits which would be rewritten and the branches which
+        /// would be moved, without actually moving anything.
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+
         /// Options for moving commits.
         #[clap(flatten)]
         move_options: MoveOptions,
@@ -218,6 +651,24 @@ pub enum Command {
         traverse_commits_options: TraverseCommitsOptions,
     },
 
+    /// Show the history of a commit: the previous versions which it was
+    /// rewritten from, and the newer versions which it was rewritten into,
+    /// along with the commands which rewrote them.
+    Obslog {
+        /// The commit whose history should be shown. This can be any version
+        /// of the commit, including an obsolete one.
+        #[clap(default_value = "HEAD")]
+        commit: String,
+
+        /// Show the diffstat between each version and the next.
+        #[clap(long = "stat")]
+        stat: bool,
+
+        /// Show the patch between each version and the next.
+        #[clap(short = 'p', long = "patch")]
+        patch: bool,
+    },
+
     /// Move to an earlier commit in the current stack.
     Prev {
         /// Options for traversing commits.
@@ -225,37 +676,373 @@ pub enum Command {
         traverse_commits_options: TraverseCommitsOptions,
     },
 
+    /// Print the commits matching a revset expression, such as `draft()`,
+    /// `stack(foo)`, or `descendants(foo) - bar`.
+    ///
+    /// Names refer to commits as in `git rev-parse`. The available functions
+    /// are `all()`, `none()`, `public()`, `draft()`, `main()`, `branches()`,
+    /// `ancestors(x)`, `descendants(x)`, `only(x, y)`, `range(x, y)`,
+    /// `stack(x)`, `author(pattern)`, and `message(pattern)`, where a pattern
+    /// matches as a substring, or as a regular expression if prefixed with
+    /// `re:`. Sets can be combined with `|` (union), `&` (intersection), and
+    /// `-` (difference). `x..y` is the ancestors of `y` which aren't ancestors
+    /// of `x`, and `x::y` is the commits on the paths from `x` to `y`.
+    Query {
+        /// The revset expression to evaluate.
+        revset: String,
+
+        /// How to print the matching commits.
+        #[clap(long = "format", arg_enum, default_value = "oid")]
+        format: QueryFormat,
+    },
+
+    /// Commit the changes in the working copy. Unlike `git commit`, undoing
+    /// the resulting commit restores the changes to the working copy.
+    Record {
+        /// Use the given message as the commit message. If multiple messages
+        /// are given, they are concatenated as separate paragraphs. Otherwise,
+        /// an editor is opened to write the message.
+        #[clap(
+            short = 'm',
+            long = "message",
+            multiple_occurrences(true),
+            number_of_values = 1
+        )]
+        messages: Vec<String>,
+
+        /// Only commit the changes which are staged in the index. Unstaged
+        /// changes are left in the working copy.
+        #[clap(long = "staged", conflicts_with_all(&["interactive", "pathspecs"]))]
+        staged: bool,
+
+        /// Choose which of the changed files to commit.
+        #[clap(short = 'i', long = "interactive")]
+        interactive: bool,
+
+        /// Only commit changes to the given paths. Untracked files are only
+        /// committed if they are named explicitly.
+        pathspecs: Vec<String>,
+
+        /// Options for creating the commit.
+        #[clap(flatten)]
+        commit_options: CommitOptions,
+    },
+
+    /// Reapply the changes reverted by the most recent `git undo`.
+    ///
+    /// Redo is only possible if no other events have been recorded since the
+    /// undo operation.
+    Redo {
+        /// The number of undo operations to redo. Defaults to 1.
+        #[clap(short = 'n', long = "num-undos")]
+        num_undos: Option<usize>,
+
+        /// Don't prompt for confirmation before applying the redo actions.
+        #[clap(short = 'y', long = "yes")]
+        yes: bool,
+
+        /// Apply the redo actions even if they would check out a different
+        /// commit while the working copy has uncommitted changes. The changes
+        /// are saved to a working copy snapshot, so that undoing the redo
+        /// restores them.
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+    },
+
+    /// Check the event log, hooks, and configuration used by `git-branchless`
+    /// for problems, such as events which refer to commits that no longer
+    /// exist. Exits with a nonzero exit code if any problems were found, unless
+    /// they were all fixed with `--apply`.
+    Repair {
+        /// Fix the problems which were found: delete invalid events, hide
+        /// missing commits, reinstall hooks and configuration, and rebuild the
+        /// commit graph cache.
+        #[clap(long = "apply")]
+        apply: bool,
+    },
+
     /// Fix up commits abandoned by a previous rewrite operation.
     Restack {
-        /// The IDs of the abandoned commits whose descendants should be
-        /// restacked. If not provided, all abandoned commits are restacked.
+        /// The commits whose stacks should be restacked. An abandoned commit
+        /// restacks its own descendants, while any other commit restacks the
+        /// abandoned commits in the stack containing it. If not provided, all
+        /// abandoned commits are restacked. Revset expressions are accepted
+        /// (see `git branchless query`).
         commits: Vec<String>,
 
+        /// Print the restack plan without actually restacking anything. For
+        /// each stack, the plan lists the commits which would be rewritten,
+        /// the commits they would be moved onto, the branches which would be
+        /// moved, and whether a merge conflict is predicted. (The plan is
+        /// also printed before restacking, unless `--quiet` is passed.)
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+
         /// Options for moving commits.
         #[clap(flatten)]
         move_options: MoveOptions,
     },
 
+    /// Change the messages of the given commits, without changing their
+    /// contents, and restack their descendants onto the reworded commits.
+    Reword {
+        /// The commits to reword. If not provided, defaults to `HEAD`. If
+        /// multiple commits are given, an editor is opened for each of them in
+        /// turn, starting with the ancestor-most commit.
+        commits: Vec<String>,
+
+        /// Use the given message as the commit message for all of the commits,
+        /// rather than opening an editor. If multiple messages are given, they
+        /// are concatenated as separate paragraphs.
+        #[clap(
+            short = 'm',
+            long = "message",
+            multiple_occurrences(true),
+            number_of_values = 1
+        )]
+        messages: Vec<String>,
+
+        /// Allow rewording commits which are public (i.e. ancestors of the
+        /// main branch).
+        #[clap(long = "force-rewrite-public")]
+        force_rewrite_public: bool,
+
+        /// Options for creating the reworded commits.
+        #[clap(flatten)]
+        commit_options: CommitOptions,
+    },
+
     /// Display a nice graph of the commits you've recently worked on.
     Smartlog {
         /// Also show commits which have been hidden.
         #[clap(long = "hidden")]
         show_hidden_commits: bool,
+
+        /// Only show the commits matching this revset expression (see `git
+        /// branchless query`), along with their paths to the main branch.
+        revset: Option<String>,
+
+        /// Only show the draft commits which were committed, or last had
+        /// activity such as being rewritten, after this date, along with their
+        /// paths to the main branch. Accepts dates such as `2021-06-01`,
+        /// `2021-06-01 12:00:00 +0100` or `2 weeks ago`.
+        #[clap(long = "since")]
+        since: Option<String>,
+
+        /// Mark the draft commits which touch the given path (or any path under
+        /// it, if it's a directory). Can be passed multiple times. If not
+        /// provided and `branchless.smartlog.markRelatedCommits` is set, the
+        /// commits touching the paths modified in the working copy are marked.
+        #[clap(long = "touches", multiple_occurrences(true), multiple_values(false))]
+        touches: Vec<PathBuf>,
+
+        /// Debugging option. Print how long each stage of rendering the
+        /// smartlog took, and how many commits it loaded, to stderr.
+        #[clap(long = "debug-timing")]
+        debug_timing: bool,
+    },
+
+    /// Split the changes to the given paths out of a commit into a new commit,
+    /// which is inserted before the commit with the remaining changes.
+    Split {
+        /// The commit to split.
+        #[clap(default_value = "HEAD")]
+        commit: String,
+
+        /// The paths whose changes should be moved into the first commit.
+        #[clap(last = true, required = true)]
+        pathspecs: Vec<String>,
+
+        /// Use the given message as the commit message for the second commit,
+        /// rather than opening an editor. If multiple messages are given, they
+        /// are concatenated as separate paragraphs.
+        #[clap(
+            short = 'm',
+            long = "message",
+            multiple_occurrences(true),
+            number_of_values = 1
+        )]
+        messages: Vec<String>,
+
+        /// Allow splitting a commit which is public (i.e. an ancestor of the
+        /// main branch).
+        #[clap(long = "force-rewrite-public")]
+        force_rewrite_public: bool,
+
+        /// Options for creating the second commit.
+        #[clap(flatten)]
+        commit_options: CommitOptions,
+    },
+
+    /// Combine commits into their parent. Their descendants are restacked
+    /// onto the combined commit.
+    Squash {
+        /// The commits to squash. If a single commit is given, it is squashed
+        /// into its parent. If several commits are given, they must form a
+        /// contiguous run of commits, which is squashed into the ancestor-most
+        /// commit of the run. Defaults to `HEAD`. Revset expressions are
+        /// accepted (see `git branchless query`).
+        commits: Vec<String>,
+
+        /// Use the given message as the message of the combined commit, rather
+        /// than concatenating the messages of the squashed commits. If multiple
+        /// messages are given, they are concatenated as separate paragraphs.
+        #[clap(
+            short = 'm',
+            long = "message",
+            multiple_occurrences(true),
+            number_of_values = 1,
+            conflicts_with_all(&["use-child-message", "use-parent-message"])
+        )]
+        messages: Vec<String>,
+
+        /// Use only the message of the descendant-most squashed commit.
+        #[clap(long = "use-child-message", conflicts_with("use-parent-message"))]
+        use_child_message: bool,
+
+        /// Use only the message of the commit being squashed into.
+        #[clap(long = "use-parent-message")]
+        use_parent_message: bool,
+
+        /// Allow squashing commits which are public (i.e. ancestors of the
+        /// main branch).
+        #[clap(long = "force-rewrite-public")]
+        force_rewrite_public: bool,
+
+        /// Options for creating the combined commit.
+        #[clap(flatten)]
+        commit_options: CommitOptions,
+    },
+
+    /// Push the branches in the current stack to their remotes, using
+    /// `--force-with-lease`. Each branch is pushed to its
+    /// `branch.<name>.pushRemote`, `remote.pushDefault`, or `origin`.
+    Submit {
+        /// Push branches which don't exist on the remote yet, and set them as
+        /// the upstream branches of the local branches.
+        #[clap(long = "create")]
+        create: bool,
+
+        /// Push branches even if the remote branch has commits which aren't
+        /// in the local history.
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+
+        /// Print which branches would be pushed, without pushing them.
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+
+        /// Write a description of the submitted stack to this file, such as
+        /// for pasting into a pull request description. The same description
+        /// is passed to `branchless.submit.messageCmd`, if set.
+        #[clap(long = "message-file")]
+        message_file: Option<PathBuf>,
+
+        /// The format in which to write the description to `--message-file`.
+        #[clap(long = "format", arg_enum, default_value = "json")]
+        format: SubmitMessageFormat,
+
+        /// Push the branches in the stacks containing these commits (which
+        /// may be revsets), rather than in the current stack.
+        revsets: Vec<String>,
+    },
+
+    /// Move all stacks of draft commits onto the latest version of the main
+    /// branch. Stacks which would cause a merge conflict are skipped.
+    Sync {
+        /// Run `git fetch` to update the remote references (including the
+        /// main branch's upstream branch) before syncing.
+        #[clap(short = 'p', long = "pull")]
+        pull: bool,
+
+        /// Fold `fixup!`, `squash!`, and `amend!` commits into the commits
+        /// which they target, as `git rebase --autosquash` would. This is
+        /// always the case if the `rebase.autoSquash` config option is set.
+        #[clap(long = "autosquash")]
+        autosquash: bool,
+    },
+
+    /// Run a command on each commit in the current stack, and report which
+    /// commits it failed on. Commits are checked out into temporary
+    /// worktrees, so the working copy is left untouched.
+    Test {
+        /// The command to run on each commit. It's run with `sh -c` in the
+        /// root of the worktree.
+        #[clap(short = 'x', long = "exec")]
+        command: String,
+
+        /// The number of commits to test in parallel, each in its own
+        /// worktree. Pass 0 to use one job per CPU.
+        #[clap(short = 'j', long = "jobs", default_value = "1")]
+        jobs: usize,
+
+        /// Run the command even on commits which it previously passed on.
+        #[clap(long = "no-cache")]
+        no_cache: bool,
+
+        /// The commits to test (which may be revsets), rather than the
+        /// commits in the current stack.
+        revsets: Vec<String>,
     },
 
     /// Browse or return to a previous state of the repository.
-    Undo,
+    ///
+    /// By default, an interactive event browser is opened to select the
+    /// state to return to. If `--yes`, `-n`, or `--event` is passed, the
+    /// browser is skipped.
+    Undo {
+        /// The number of event transactions to undo, without opening the
+        /// interactive event browser. Defaults to 1 if `--yes` is passed. The
+        /// transactions are undone together, so a single `git redo` reapplies
+        /// all of them.
+        #[clap(short = 'n', long = "num-transactions")]
+        num_transactions: Option<usize>,
+
+        /// Restore the repository to the state immediately after the given
+        /// event transaction, without opening the interactive event browser.
+        /// This is the same state as shown for that transaction in the
+        /// browser ("Repo after transaction N").
+        #[clap(
+            long = "event",
+            value_name = "TRANSACTION-ID",
+            conflicts_with = "num-transactions"
+        )]
+        event_tx_id: Option<String>,
+
+        /// Don't prompt for confirmation before applying the undo actions.
+        #[clap(short = 'y', long = "yes")]
+        yes: bool,
+
+        /// Apply the undo actions even if they would check out a different
+        /// commit while the working copy has uncommitted changes. The changes
+        /// are saved to a working copy snapshot, so that redoing the undo
+        /// restores them.
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+
+        /// Show the diffstat for each branch which would be moved (and for
+        /// the commit which would be checked out) along with the undo actions.
+        #[clap(long = "diff")]
+        diff: bool,
+    },
 
     /// Unhide previously-hidden commits from the smartlog.
     Unhide {
         /// Zero or more commits to unhide.
         ///
-        /// Can either be hashes, like `abc123`, or ref-specs, like `HEAD^`.
+        /// Can either be hashes, like `abc123`, ref-specs, like `HEAD^`,
+        /// ranges, like `abc123..def456` or `abc123::def456`, or revset
+        /// expressions, like `stack(HEAD)` (see `git branchless query`).
         commits: Vec<String>,
 
         /// Also recursively unhide all children commits of the provided commits.
         #[clap(short = 'r', long = "recursive")]
         recursive: bool,
+
+        /// Recreate any branches which pointed to the unhidden commits when
+        /// they were hidden, without prompting.
+        #[clap(long = "restore-branches")]
+        restore_branches: bool,
     },
 
     /// Wrap a Git command inside a branchless transaction.
@@ -271,7 +1058,7 @@ pub enum Command {
 }
 
 /// Whether to display terminal colors.
-#[derive(ArgEnum, Clone)]
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ColorSetting {
     /// Automatically determine whether to display colors from the terminal and environment variables.
     /// This is the default behavior.
@@ -282,6 +1069,75 @@ pub enum ColorSetting {
     Never,
 }
 
+/// Which set of characters to use when drawing the commit graph.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlyphsSetting {
+    /// Use Unicode glyphs if colors are being displayed, and ASCII otherwise.
+    /// This is the default behavior.
+    Auto,
+    /// Always use Unicode box-drawing characters.
+    Unicode,
+    /// Always use plain ASCII characters.
+    Ascii,
+}
+
+/// The format in which to write output.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Write human-readable output. This is the default behavior.
+    Text,
+    /// Write newline-delimited JSON records describing progress, messages, and
+    /// the result of the command, for consumption by editor integrations.
+    Json,
+}
+
+/// Where `git branchless init` installs hooks when `core.hooksPath` refers to a
+/// directory outside of the repository.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HooksDirSetting {
+    /// Install the hooks into the repository's own hooks directory, by
+    /// overriding `core.hooksPath` for this repository.
+    Repo,
+    /// Install the hooks into the shared hooks directory. The hooks only invoke
+    /// git-branchless in repositories where it's been initialized. This is the
+    /// default behavior.
+    Shared,
+}
+
+/// Where `git branchless init` places the git-branchless section in a hook which
+/// already exists.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookPosition {
+    /// Place the section at the start of the hook, just after the shebang line,
+    /// so that it runs even if the rest of the hook exits early.
+    Prepend,
+    /// Place the section at the end of the hook. An existing section is updated
+    /// in place instead. This is the default behavior.
+    Append,
+}
+
+/// How to print the commits matched by `git branchless query`.
+#[derive(ArgEnum, Clone, Debug)]
+pub enum QueryFormat {
+    /// Print the full OID of each commit. This is the default behavior.
+    Oid,
+    /// Print the short OID and summary line of each commit.
+    Summary,
+    /// Print a JSON array of objects with the OID and summary of each commit.
+    Json,
+}
+
+/// How to write the stack description for `git submit --message-file`.
+#[derive(ArgEnum, Clone, Debug)]
+pub enum SubmitMessageFormat {
+    /// Write a JSON object describing the commits and branches in the stack,
+    /// as passed to `branchless.submit.messageCmd`. This is the default
+    /// behavior.
+    Json,
+    /// Write a Markdown table of the branches in the stack.
+    Markdown,
+}
+
 /// Branchless workflow for Git.
 ///
 /// See the documentation at https://github.com/arxanas/git-branchless/wiki.
@@ -289,25 +1145,96 @@ pub enum ColorSetting {
 #[clap(version = env!("CARGO_PKG_VERSION"), author = "Waleed Khan <me@waleedkhan.name>")]
 pub struct Opts {
     /// Change to the given directory before executing the rest of the program.
-    /// (The option is called `-C` for symmetry with Git.)
-    #[clap(short = 'C')]
-    pub working_directory: Option<PathBuf>,
+    /// (The option is called `-C` for symmetry with Git.) If passed multiple
+    /// times, each relative path is interpreted relative to the preceding
+    /// one, like `git -C`.
+    #[clap(
+        short = 'C',
+        long = "working-directory",
+        multiple_occurrences = true,
+        multiple_values = false
+    )]
+    pub working_directory: Vec<PathBuf>,
 
-    /// Flag to force enable or disable terminal colors.
-    #[clap(long = "color", arg_enum)]
+    /// Flag to force enable or disable terminal colors. If not provided, the
+    /// `branchless.output.color` config setting is used. In `auto` mode, the
+    /// `NO_COLOR` and `CLICOLOR_FORCE` environment variables are respected.
+    #[clap(long = "color", arg_enum, global = true)]
     pub color: Option<ColorSetting>,
 
+    /// Flag to force Unicode or ASCII glyphs when drawing the commit graph. If
+    /// not provided, the `branchless.output.glyphs` config setting is used.
+    #[clap(long = "glyphs", arg_enum, global = true)]
+    pub glyphs: Option<GlyphsSetting>,
+
+    /// The format in which to write output. If not provided, the
+    /// `BRANCHLESS_OUTPUT` environment variable is used.
+    #[clap(long = "output", arg_enum, global = true)]
+    pub output: Option<OutputFormat>,
+
+    /// After navigation and rewrite commands, display a compact smartlog of
+    /// the current stack rather than the full smartlog, even if output isn't
+    /// written to a terminal. Overrides the `branchless.autoSmartlog` config
+    /// setting.
+    #[clap(long = "smartlog", global = true, conflicts_with = "no-smartlog")]
+    pub smartlog: bool,
+
+    /// Don't display the compact smartlog of the current stack after
+    /// navigation and rewrite commands, even if the `branchless.autoSmartlog`
+    /// config setting is enabled.
+    #[clap(long = "no-smartlog", global = true)]
+    pub no_smartlog: bool,
+
+    /// Only display errors and the results of the subcommand, such as whether
+    /// a rebase succeeded. Informational messages, progress indicators, and
+    /// the plan which `git restack` prints before restacking aren't
+    /// displayed, and neither is output from the hooks which the subcommand
+    /// invokes.
+    #[clap(short = 'q', long = "quiet", global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Display timing information for the operations carried out by the
+    /// subcommand on stderr. Pass twice to also display debugging
+    /// information, such as the Git commands and database queries being run.
+    /// Ignored if the `RUST_LOG` environment variable is set.
+    #[clap(short = 'v', long = "verbose", global = true, parse(from_occurrences))]
+    pub verbose: usize,
+
+    /// If git-branchless hasn't been initialized in this repository,
+    /// initialize it without prompting before running the subcommand.
+    #[clap(short = 'y', long = "yes")]
+    pub yes: bool,
+
+    /// Remove the lock held by another `git-branchless` operation which
+    /// rewrites commits, such as one which was killed, before running the
+    /// subcommand.
+    #[clap(long = "force-unlock", global = true)]
+    pub force_unlock: bool,
+
     /// The `git-branchless` subcommand to run.
     #[clap(subcommand)]
     pub command: Command,
 }
 
+/// The name of the file in a man-page directory which records the version of
+/// `git-branchless` which generated the man-pages in it.
+pub const MAN_PAGES_VERSION_STAMP_FILE_NAME: &str = ".git-branchless-version";
+
 /// Generate and write man-pages into the specified directory.
 ///
 /// The generated files are named things like `man1/git-branchless-smartlog.1`,
 /// so this directory should be of the form `path/to/man`, to ensure that these
 /// files get generated into the correct man-page section.
-pub fn write_man_pages(man_dir: &Path) -> std::io::Result<()> {
+///
+/// If the man-pages in the directory were already generated by this version of
+/// `git-branchless`, nothing is written and `false` is returned.
+pub fn write_man_pages(man_dir: &Path) -> std::io::Result<bool> {
+    let version_stamp_path = man_dir.join(MAN_PAGES_VERSION_STAMP_FILE_NAME);
+    let version = env!("CARGO_PKG_VERSION");
+    if std::fs::read_to_string(&version_stamp_path).ok().as_deref() == Some(version) {
+        return Ok(false);
+    }
+
     let man1_dir = man_dir.join("man1");
     std::fs::create_dir_all(&man1_dir)?;
 
@@ -317,7 +1244,9 @@ pub fn write_man_pages(man_dir: &Path) -> std::io::Result<()> {
         let subcommand_exe_name = format!("git-branchless-{}", subcommand.get_name());
         generate_man_page(&man1_dir, &subcommand_exe_name, subcommand)?;
     }
-    Ok(())
+
+    std::fs::write(&version_stamp_path, version)?;
+    Ok(true)
 }
 
 fn generate_man_page(man1_dir: &Path, name: &str, command: &App) -> std::io::Result<()> {
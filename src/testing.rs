@@ -11,6 +11,7 @@ use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
+use crate::core::config::BRANCHLESS_PROGRESS_LOG_ENV_VAR;
 use crate::git::{GitRunInfo, GitVersion, NonZeroOid, Repo};
 use crate::util::get_sh;
 
@@ -128,6 +129,16 @@ impl Git {
             })
             .into_owned();
 
+        lazy_static! {
+            // The size of loose objects reported by `git branchless gc`
+            // depends on how well the local zlib compresses them.
+            static ref RECLAIMABLE_SIZE_RE: Regex =
+                Regex::new(r"up to \d+ KiB of loose objects").unwrap();
+        }
+        let output = RECLAIMABLE_SIZE_RE
+            .replace_all(&output, "up to <size> KiB of loose objects")
+            .into_owned();
+
         Ok(output)
     }
 
@@ -189,6 +200,9 @@ impl Git {
             ("GIT_EXEC_PATH", git_exec_path.as_os_str().into()),
             ("PATH_TO_GIT", self.path_to_git.as_os_str().into()),
             ("PATH", new_path),
+            // Progress lines are only logged for slow operations, so whether
+            // they appear depends on the load on the test machine.
+            (BRANCHLESS_PROGRESS_LOG_ENV_VAR, "0".into()),
         ];
 
         envs.into_iter()
@@ -323,7 +337,9 @@ impl Git {
         self.run(&["config", "core.autocrlf", "false"])?;
 
         if options.run_branchless_init {
-            self.run(&["branchless", "init"])?;
+            // Don't import the reflog of the initial commit, so that event IDs
+            // in tests start from the same place.
+            self.run(&["branchless", "init", "--no-seed"])?;
         }
 
         Ok(())
@@ -384,6 +400,52 @@ impl Git {
         Ok(())
     }
 
+    /// Write an executable hook script with the given name to the repository's
+    /// hooks directory.
+    #[cfg(unix)]
+    pub fn write_hook(&self, name: &str, contents: &str) -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let hook_path = self.repo_path.join(".git").join("hooks").join(name);
+        fs::write(&hook_path, contents)?;
+        fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    /// Write an executable script at `path` which runs the Git executable, and
+    /// which appends a line to `log_path` whenever `subcommand` is among its
+    /// arguments.
+    /// This can be used to check how many times git-branchless invokes Git
+    /// for a particular operation.
+    #[cfg(unix)]
+    pub fn write_counting_git_shim(
+        &self,
+        path: &Path,
+        log_path: &Path,
+        subcommand: &str,
+    ) -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let contents = format!(
+            "\
+#!/bin/sh
+for arg in \"$@\"; do
+    if [ \"$arg\" = {subcommand:?} ]; then
+        echo {subcommand:?} >>{log_path:?}
+        break
+    fi
+done
+exec {path_to_git:?} \"$@\"
+",
+            subcommand = subcommand,
+            log_path = log_path,
+            path_to_git = self.path_to_git,
+        );
+        fs::write(path, contents)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
     /// Commit a file with default contents. The `time` argument is used to set
     /// the commit timestamp, which is factored into the commit hash.
     #[instrument]
@@ -473,6 +535,12 @@ impl Git {
         Ok(version >= GitVersion(2, 29, 0))
     }
 
+    /// The `--update-refs` option to `git rebase` was introduced in Git v2.38.
+    pub fn supports_rebase_update_refs(&self) -> eyre::Result<bool> {
+        let version = self.get_version()?;
+        Ok(version >= GitVersion(2, 38, 0))
+    }
+
     /// The `log.excludeDecoration` configuration option was introduced in Git
     /// v2.27.
     pub fn supports_log_exclude_decoration(&self) -> eyre::Result<bool> {
@@ -542,7 +610,9 @@ impl GitWrapper {
     /// setup.  This is *not* the same as running `git clone`; it's used to save
     /// initialization time as part of testing optimization.
     ///
-    /// The copied repo will be deleted once the returned value has been dropped.
+    /// The copied repo is updated with `git branchless init --update`, as
+    /// after copying a repository by hand. It will be deleted once the
+    /// returned value has been dropped.
     pub fn duplicate_repo(&self) -> eyre::Result<Self> {
         let repo_dir = tempfile::tempdir()?;
         copy_dir_all(&self.repo_dir, &repo_dir)?;
@@ -550,6 +620,10 @@ impl GitWrapper {
             repo_path: repo_dir.path().to_path_buf(),
             path_to_git: self.git.path_to_git.clone(),
         };
+        // Update the copy so that it isn't reported as having been copied.
+        if git.repo_path.join(".git").join("branchless").exists() {
+            git.run(&["branchless", "init", "--update"])?;
+        }
         Ok(Self { repo_dir, git })
     }
 }
@@ -0,0 +1,178 @@
+//! Edit commit messages with the user's configured editor.
+
+use std::process::Command;
+
+use eyre::{eyre, Context};
+use tracing::instrument;
+
+use crate::git::{ConfigRead, GitRunInfo, Repo};
+use crate::util::get_sh;
+
+/// How to clean up a commit message before committing it, as determined by
+/// the `commit.cleanup` config option. See `git-commit(1)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageCleanupMode {
+    /// Strip leading and trailing empty lines, trailing whitespace, and
+    /// commentary, and collapse consecutive empty lines.
+    Strip,
+
+    /// Same as `Strip`, except that commentary is not removed.
+    Whitespace,
+
+    /// Do not change the message at all.
+    Verbatim,
+
+    /// Same as `Whitespace`, except that everything from the scissors line
+    /// onwards is removed if the message was edited.
+    Scissors,
+}
+
+impl MessageCleanupMode {
+    /// Get the cleanup mode configured for the repository. The `default`
+    /// mode is `Strip` if the message is to be edited, and `Whitespace`
+    /// otherwise.
+    #[instrument]
+    pub fn from_config(repo: &Repo, edited: bool) -> eyre::Result<Self> {
+        let mode: Option<String> = repo.get_readonly_config()?.get("commit.cleanup")?;
+        let mode = match mode.as_deref() {
+            Some("strip") => MessageCleanupMode::Strip,
+            Some("whitespace") => MessageCleanupMode::Whitespace,
+            Some("verbatim") => MessageCleanupMode::Verbatim,
+            Some("scissors") => MessageCleanupMode::Scissors,
+            Some("default") | None => {
+                if edited {
+                    MessageCleanupMode::Strip
+                } else {
+                    MessageCleanupMode::Whitespace
+                }
+            }
+            Some(other) => eyre::bail!("Invalid commit.cleanup mode: {}", other),
+        };
+        Ok(mode)
+    }
+}
+
+/// Get the character which starts a comment line in a commit message, as
+/// determined by the `core.commentChar` config option.
+#[instrument]
+fn get_comment_char(repo: &Repo) -> eyre::Result<char> {
+    let comment_char: Option<String> = repo.get_readonly_config()?.get("core.commentChar")?;
+    let comment_char = match comment_char.as_deref() {
+        None | Some("auto") => '#',
+        Some(comment_char) => comment_char.chars().next().unwrap_or('#'),
+    };
+    Ok(comment_char)
+}
+
+fn make_scissors_line(comment_char: char) -> String {
+    format!(
+        "{} ------------------------ >8 ------------------------",
+        comment_char
+    )
+}
+
+/// Clean up the given commit message according to `mode`, in the same way
+/// that `git commit` would.
+pub fn clean_up_message(
+    repo: &Repo,
+    message: &str,
+    mode: MessageCleanupMode,
+) -> eyre::Result<String> {
+    let comment_char = get_comment_char(repo)?;
+    let message = match mode {
+        MessageCleanupMode::Verbatim => return Ok(message.to_owned()),
+        MessageCleanupMode::Strip | MessageCleanupMode::Whitespace => message,
+        MessageCleanupMode::Scissors => {
+            let scissors_line = make_scissors_line(comment_char);
+            match message.lines().position(|line| line == scissors_line) {
+                Some(index) => {
+                    let offset: usize =
+                        message.lines().take(index).map(|line| line.len() + 1).sum();
+                    &message[..offset]
+                }
+                None => message,
+            }
+        }
+    };
+
+    let comment_char = match mode {
+        MessageCleanupMode::Strip => {
+            let mut buf = [0; 4];
+            let comment_char = comment_char.encode_utf8(&mut buf);
+            // `git2` only supports single-byte comment characters.
+            Some(comment_char.as_bytes()[0])
+        }
+        MessageCleanupMode::Whitespace
+        | MessageCleanupMode::Verbatim
+        | MessageCleanupMode::Scissors => None,
+    };
+    let message =
+        git2::message_prettify(message, comment_char).wrap_err("Cleaning up commit message")?;
+    Ok(message)
+}
+
+/// Open the user's editor (as configured by `GIT_EDITOR`, `core.editor`,
+/// etc.) on the given commit message, and return the cleaned-up result.
+///
+/// The message is written to `.git/COMMIT_EDITMSG`, along with instructions
+/// on how it will be cleaned up, as `git commit` does.
+#[instrument]
+pub fn edit_message(git_run_info: &GitRunInfo, repo: &Repo, message: &str) -> eyre::Result<String> {
+    let mode = MessageCleanupMode::from_config(repo, true)?;
+    let comment_char = get_comment_char(repo)?;
+    let instructions = match mode {
+        MessageCleanupMode::Strip => format!(
+            "\
+{c} Please enter the commit message for your changes. Lines starting
+{c} with '{c}' will be ignored, and an empty message aborts the commit.
+",
+            c = comment_char
+        ),
+        MessageCleanupMode::Scissors => format!(
+            "\
+{scissors}
+{c} Do not modify or remove the line above.
+{c} Everything below it will be ignored.
+",
+            scissors = make_scissors_line(comment_char),
+            c = comment_char
+        ),
+        MessageCleanupMode::Whitespace | MessageCleanupMode::Verbatim => format!(
+            "\
+{c} Please enter the commit message for your changes. Lines starting
+{c} with '{c}' will be kept; you may remove them yourself if you want to.
+{c} An empty message aborts the commit.
+",
+            c = comment_char
+        ),
+    };
+
+    let path = repo.get_path().join("COMMIT_EDITMSG");
+    let contents = format!("{}\n\n{}", message.trim_end(), instructions);
+    std::fs::write(&path, contents)
+        .wrap_err_with(|| format!("Writing commit message to {:?}", &path))?;
+
+    let editor = git_run_info.run_silent(repo, None, &["var", "GIT_EDITOR"], Default::default())?;
+    let editor = String::from_utf8(editor.stdout).wrap_err("Decoding editor command")?;
+    let editor = editor.trim_end();
+    // `git` skips invoking the editor entirely when it's set to ":".
+    if editor != ":" {
+        let exit_status = Command::new(get_sh().ok_or_else(|| eyre!("could not get sh"))?)
+            .current_dir(&git_run_info.working_directory)
+            .arg("-c")
+            .arg(format!("{} \"$@\"", editor))
+            .arg(editor) // "$@" expands "$1" "$2" "$3" ... but we also must specify $0.
+            .arg(&path)
+            .env_clear()
+            .envs(git_run_info.env.iter())
+            .status()
+            .wrap_err_with(|| format!("Invoking editor: {}", editor))?;
+        if !exit_status.success() {
+            eyre::bail!("Editor {} exited with {}", editor, exit_status);
+        }
+    }
+
+    let message = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Reading commit message from {:?}", &path))?;
+    clean_up_message(repo, &message, mode)
+}
@@ -1,8 +1,13 @@
 //! Utilities to control output and render to the terminal.
 
 mod cursive;
+mod editor;
 mod prompt;
 
 pub use self::cursive::testing;
 pub use self::cursive::{with_siv, SingletonView};
-pub use prompt::prompt_select_commit;
+pub use editor::{clean_up_message, edit_message, MessageCleanupMode};
+pub use prompt::{
+    can_prompt_choose_commit, prompt_choose_commit, prompt_choose_or_enter, prompt_choose_paths,
+    prompt_select_commit,
+};
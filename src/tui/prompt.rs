@@ -1,6 +1,225 @@
-use crate::core::node_descriptors::NodeDescriptor;
+use std::fmt::Write;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+
+use console::{Key, Term};
+
+use crate::core::effects::Effects;
+use crate::core::formatting::printable_styled_string;
+use crate::core::node_descriptors::{render_node_descriptors, NodeDescriptor, NodeObject};
 use crate::git::{Commit, NonZeroOid};
 
+/// The keys used to select an entry in `prompt_choose_commit`, in order. `q`
+/// is reserved for cancelling the prompt.
+const CHOOSER_KEYS: &str = "123456789abcdefghijklmnoprstuvwxyz";
+
+/// Whether `prompt_choose_commit` can be used, i.e. whether `stdout` is a
+/// terminal and there are few enough commits to give each one its own key.
+pub fn can_prompt_choose_commit(commits: &[Commit]) -> bool {
+    Term::stdout().is_term() && commits.len() <= CHOOSER_KEYS.len()
+}
+
+/// Print a numbered list of the provided commits and prompt the user to pick
+/// one of them with a single keypress. Returns the OID of the selected commit,
+/// or `None` if the user cancelled the prompt.
+pub fn prompt_choose_commit(
+    effects: &Effects,
+    header: &str,
+    commits: &[Commit],
+    commit_descriptors: &mut [&mut dyn NodeDescriptor],
+) -> eyre::Result<Option<NonZeroOid>> {
+    let glyphs = effects.get_glyphs();
+    writeln!(effects.get_output_stream(), "{}", header)?;
+    for (key, commit) in CHOOSER_KEYS.chars().zip(commits.iter()) {
+        let description = render_node_descriptors(
+            &NodeObject::Commit {
+                commit: commit.clone(),
+            },
+            commit_descriptors,
+        )?;
+        writeln!(
+            effects.get_output_stream(),
+            "  {}) {}",
+            key,
+            printable_styled_string(glyphs, description)?
+        )?;
+    }
+
+    let last_key = CHOOSER_KEYS
+        .chars()
+        .nth(commits.len() - 1)
+        .expect("Too many commits to choose between");
+    let term = Term::stdout();
+    loop {
+        write!(
+            effects.get_output_stream(),
+            "Select a commit [1-{}, q to cancel]: ",
+            last_key
+        )?;
+        let key = match term.read_key() {
+            Ok(key) => key,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Key::Escape,
+            Err(err) => return Err(err.into()),
+        };
+        let selected = match key {
+            Key::Char(c) => CHOOSER_KEYS.chars().position(|key| key == c),
+            _ => None,
+        };
+        match (key, selected) {
+            (_, Some(index)) if index < commits.len() => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "{}",
+                    CHOOSER_KEYS.chars().nth(index).unwrap()
+                )?;
+                return Ok(Some(commits[index].get_oid()));
+            }
+            (Key::Char('q'), _) | (Key::Escape, _) | (Key::Unknown, _) => {
+                writeln!(effects.get_output_stream())?;
+                return Ok(None);
+            }
+            _ => {
+                writeln!(effects.get_output_stream())?;
+            }
+        }
+    }
+}
+
+/// Parse a selection of entries such as `1 3 5-7` into zero-based indexes
+/// into a list of `num_entries` entries. `*` selects all of the entries.
+fn parse_selection(input: &str, num_entries: usize) -> Result<Vec<usize>, String> {
+    let mut indexes = Vec::new();
+    for token in input.split(|c: char| c.is_whitespace() || c == ',') {
+        let parse_number = |number: &str| match number.parse::<usize>() {
+            Ok(number) if 1 <= number && number <= num_entries => Ok(number - 1),
+            _ => Err(token.to_string()),
+        };
+        match token {
+            "" => {}
+            "*" => indexes.extend(0..num_entries),
+            token => match token.split_once('-') {
+                Some((start, end)) => indexes.extend(parse_number(start)?..=parse_number(end)?),
+                None => indexes.push(parse_number(token)?),
+            },
+        }
+    }
+    indexes.sort_unstable();
+    indexes.dedup();
+    Ok(indexes)
+}
+
+/// Print a numbered list of the provided paths and prompt the user to select
+/// some of them, by entering their numbers (or ranges of numbers) on a line.
+/// Returns the selected paths, or `None` if the user selected nothing.
+pub fn prompt_choose_paths(
+    effects: &Effects,
+    in_: &mut impl BufRead,
+    header: &str,
+    paths: &[PathBuf],
+) -> eyre::Result<Option<Vec<PathBuf>>> {
+    writeln!(effects.get_output_stream(), "{}", header)?;
+    for (index, path) in paths.iter().enumerate() {
+        writeln!(
+            effects.get_output_stream(),
+            "  {}) {}",
+            index + 1,
+            path.to_string_lossy()
+        )?;
+    }
+
+    loop {
+        write!(
+            effects.get_output_stream(),
+            "Select files [e.g. `1 3`, `1-{}`, `*` for all, empty to cancel]: ",
+            paths.len()
+        )?;
+        let mut input = String::new();
+        if in_.read_line(&mut input)? == 0 {
+            writeln!(effects.get_output_stream())?;
+            return Ok(None);
+        }
+        match parse_selection(&input, paths.len()) {
+            Ok(indexes) if indexes.is_empty() => return Ok(None),
+            Ok(indexes) => {
+                return Ok(Some(
+                    indexes
+                        .into_iter()
+                        .map(|index| paths[index].clone())
+                        .collect(),
+                ))
+            }
+            Err(token) => {
+                writeln!(effects.get_output_stream(), "Invalid selection: {}", token)?;
+            }
+        }
+    }
+}
+
+/// Print a numbered list of the provided candidates, with the one at
+/// `default_index` highlighted, and prompt the user to either pick one of them
+/// by number or enter a value of their own. An empty line selects the default.
+/// Returns `None` if the input was closed before anything was entered.
+pub fn prompt_choose_or_enter(
+    effects: &Effects,
+    in_: &mut impl BufRead,
+    header: &str,
+    prompt: &str,
+    candidates: &[String],
+    default_index: usize,
+) -> eyre::Result<Option<String>> {
+    if !candidates.is_empty() {
+        writeln!(effects.get_output_stream(), "{}", header)?;
+        for (index, candidate) in candidates.iter().enumerate() {
+            if index == default_index {
+                writeln!(
+                    effects.get_output_stream(),
+                    "> {}) {}",
+                    index + 1,
+                    console::style(candidate).bold()
+                )?;
+            } else {
+                writeln!(
+                    effects.get_output_stream(),
+                    "  {}) {}",
+                    index + 1,
+                    candidate
+                )?;
+            }
+        }
+    }
+
+    loop {
+        if candidates.is_empty() {
+            write!(effects.get_output_stream(), "{}: ", prompt)?;
+        } else {
+            write!(
+                effects.get_output_stream(),
+                "{} [1-{}, or a name; default {}]: ",
+                prompt,
+                candidates.len(),
+                default_index + 1
+            )?;
+        }
+        let mut input = String::new();
+        if in_.read_line(&mut input)? == 0 {
+            writeln!(effects.get_output_stream())?;
+            return Ok(None);
+        }
+        match input.trim() {
+            "" => match candidates.get(default_index) {
+                Some(candidate) => return Ok(Some(candidate.clone())),
+                None => continue,
+            },
+            input => match input.parse::<usize>() {
+                Ok(number) if 1 <= number && number <= candidates.len() => {
+                    return Ok(Some(candidates[number - 1].clone()))
+                }
+                _ => return Ok(Some(input.to_string())),
+            },
+        }
+    }
+}
+
 /// Prompt the user to select a commit from the provided list
 /// of commits, and returns the OID of the selected commit.
 #[cfg(unix)]
@@ -163,3 +382,19 @@ mod skim {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_selection;
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("", 3), Ok(vec![]));
+        assert_eq!(parse_selection("1 3\n", 3), Ok(vec![0, 2]));
+        assert_eq!(parse_selection("3,1-2 2", 3), Ok(vec![0, 1, 2]));
+        assert_eq!(parse_selection("*", 2), Ok(vec![0, 1]));
+        assert_eq!(parse_selection("0", 3), Err("0".to_string()));
+        assert_eq!(parse_selection("2-4", 3), Err("2-4".to_string()));
+        assert_eq!(parse_selection("x", 3), Err("x".to_string()));
+    }
+}
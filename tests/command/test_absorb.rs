@@ -0,0 +1,257 @@
+use branchless::testing::{make_git, GitWrapper};
+
+fn make_stack(git: &GitWrapper) -> eyre::Result<()> {
+    git.init_repo()?;
+    git.detach_head()?;
+    git.write_file("file", "1\n2\n3\n4\n5\n")?;
+    git.run(&["add", "."])?;
+    git.run(&["commit", "-m", "add file"])?;
+    git.write_file("file", "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n")?;
+    git.run(&["commit", "-a", "-m", "extend file"])?;
+    Ok(())
+}
+
+#[test]
+fn test_absorb() -> eyre::Result<()> {
+    let git = make_git()?;
+    make_stack(&git)?;
+
+    git.write_file("file", "1\ntwo\n3\n4\nfive\nsix\n7\neight\n9\n10\n")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "absorb", "--dry-run"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Would absorb into 5ceb9dde add file:
+        - file.txt:2 (+1 -1)
+        Would absorb into 8272066e extend file:
+        - file.txt:8 (+1 -1)
+        Would leave these changes in the working copy, because it could not be attributed to a single commit in the current stack:
+        - file.txt:5 (+2 -2)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "absorb"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Absorbing into 5ceb9dde add file:
+        - file.txt:2 (+1 -1)
+        Absorbing into 8272066e extend file:
+        - file.txt:8 (+1 -1)
+        Leaving these changes in the working copy, because it could not be attributed to a single commit in the current stack:
+        - file.txt:5 (+2 -2)
+        Absorbed 2 hunks into 2 commits.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 7379efab extend file
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout 7379efab2c79a98fc4666f64f14e5d55e5c69671
+        O f777ecc9 (master) create initial.txt
+        |
+        o e8331012 add file
+        |
+        @ 7379efab extend file
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o e8331012 add file
+        |
+        @ 7379efab extend file
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--patch", "--format=%s", "HEAD~2..HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        extend file
+
+        diff --git a/file.txt b/file.txt
+        index 419d37a..372583c 100644
+        --- a/file.txt
+        +++ b/file.txt
+        @@ -3,3 +3,8 @@ two
+         3
+         4
+         5
+        +6
+        +7
+        +eight
+        +9
+        +10
+        add file
+
+        diff --git a/file.txt b/file.txt
+        new file mode 100644
+        index 0000000..419d37a
+        --- /dev/null
+        +++ b/file.txt
+        @@ -0,0 +1,5 @@
+        +1
+        +two
+        +3
+        +4
+        +5
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        diff --git a/file.txt b/file.txt
+        index 372583c..4778bcf 100644
+        --- a/file.txt
+        +++ b/file.txt
+        @@ -2,8 +2,8 @@
+         two
+         3
+         4
+        -5
+        -6
+        +five
+        +six
+         7
+         eight
+         9
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_absorb_staged() -> eyre::Result<()> {
+    let git = make_git()?;
+    make_stack(&git)?;
+
+    git.write_file("file", "1\ntwo\n3\n4\nfive\nsix\n7\n8\n9\n10\n")?;
+    git.run(&["add", "."])?;
+    git.write_file("file", "1\ntwo\n3\n4\nfive\nsix\n7\neight\n9\n10\n")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "absorb", "--staged"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Absorbing into 5ceb9dde add file:
+        - file.txt:2 (+1 -1)
+        Leaving these changes in the working copy, because it could not be attributed to a single commit in the current stack:
+        - file.txt:5 (+2 -2)
+        Absorbed 1 hunk into 1 commit.
+        Attempting rebase in-memory...
+        [1/1] Committed as: c5d7fd3e extend file
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout c5d7fd3ecb616c797bec1e9b3ef410023f105e26
+        O f777ecc9 (master) create initial.txt
+        |
+        o e8331012 add file
+        |
+        @ c5d7fd3e extend file
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o e8331012 add file
+        |
+        @ c5d7fd3e extend file
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--patch", "--format=%s", "HEAD~2..HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        extend file
+
+        diff --git a/file.txt b/file.txt
+        index 419d37a..ed75e4e 100644
+        --- a/file.txt
+        +++ b/file.txt
+        @@ -3,3 +3,8 @@ two
+         3
+         4
+         5
+        +6
+        +7
+        +8
+        +9
+        +10
+        add file
+
+        diff --git a/file.txt b/file.txt
+        new file mode 100644
+        index 0000000..419d37a
+        --- /dev/null
+        +++ b/file.txt
+        @@ -0,0 +1,5 @@
+        +1
+        +two
+        +3
+        +4
+        +5
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff", "--cached"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        diff --git a/file.txt b/file.txt
+        index ed75e4e..fb740ba 100644
+        --- a/file.txt
+        +++ b/file.txt
+        @@ -2,8 +2,8 @@
+         two
+         3
+         4
+        -5
+        -6
+        +five
+        +six
+         7
+         8
+         9
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        diff --git a/file.txt b/file.txt
+        index fb740ba..4778bcf 100644
+        --- a/file.txt
+        +++ b/file.txt
+        @@ -5,6 +5,6 @@ two
+         five
+         six
+         7
+        -8
+        +eight
+         9
+         10
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_absorb_nothing() -> eyre::Result<()> {
+    let git = make_git()?;
+    make_stack(&git)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "absorb"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        There are no uncommitted changes. Nothing to absorb.
+        "###);
+    }
+
+    git.write_file("file", "1\n2\n3\n4\nfive\nsix\n7\n8\n9\n10\n")?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "absorb"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Leaving these changes in the working copy, because it could not be attributed to a single commit in the current stack:
+        - file.txt:5 (+2 -2)
+        Nothing to absorb.
+        "###);
+    }
+
+    Ok(())
+}
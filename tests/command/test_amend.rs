@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use branchless::testing::{make_git, GitRunOptions};
 
 #[test]
@@ -27,7 +29,6 @@ fn test_amend_with_children() -> eyre::Result<()> {
         In-memory rebase succeeded.
         Finished restacking commits.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 7ac317b9d1dd1bbdf46e8ee692b9b9e280f28a50
         O f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
@@ -55,6 +56,448 @@ fn test_amend_with_children() -> eyre::Result<()> {
         This operation would cause a merge conflict:
         - (1 conflicting file) b51f01b6 create test3.txt
         To resolve merge conflicts, retry this operation with the --merge option.
+        Left 1 commit based on the old version of the amended commit, because restacking failed:
+        - b51f01b6 create test3.txt
+        Run `git restack` to move abandoned commits onto the amended commit.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_amend_restack_stack_with_branches() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "bar"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["branch", "baz"])?;
+    git.run(&["checkout", "bar"])?;
+
+    git.write_file("test2", "updated contents")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> reset
+        Attempting rebase in-memory...
+        [1/1] Committed as: b51f01b6 create test3.txt
+        branchless: processing 1 update: branch baz
+        branchless: processing 1 rewritten commit
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 (foo) create test1.txt
+        |
+        @ 7ac317b9 (bar) create test2.txt
+        |
+        o b51f01b6 (baz) create test3.txt
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 (foo) create test1.txt
+        |
+        @ 7ac317b9 (bar) create test2.txt
+        |
+        o b51f01b6 (baz) create test3.txt
+        Amended with 1 uncommitted change.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 (foo) create test1.txt
+        |
+        @ 7ac317b9 (bar) create test2.txt
+        |
+        o b51f01b6 (baz) create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_amend_no_restack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "HEAD^"])?;
+
+    git.write_file("test2", "updated contents")?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend", "--no-restack"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> reset
+        Amended with 1 uncommitted change.
+        Left 1 commit based on the old version of the amended commit, because restacking is disabled:
+        - 70deb1e2 create test3.txt
+        Run `git restack` to move abandoned commits onto the amended commit.
+        "###);
+    }
+
+    git.run(&["config", "branchless.amend.autoRestack", "false"])?;
+    git.write_file("test2", "updated contents again")?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> reset
+        Amended with 1 uncommitted change.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | x 96d1c37a (rewritten as dcf1d6ed) create test2.txt
+        | |
+        | o 70deb1e2 create test3.txt
+        |
+        @ dcf1d6ed create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_amend_message() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "HEAD^"])?;
+
+    git.write_file("test2", "updated contents")?;
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "amend",
+            "--message",
+            "new subject",
+            "--message",
+            "new body  ",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> reset
+        Attempting rebase in-memory...
+        [1/1] Committed as: e96e49b2 create test3.txt
+        branchless: processing 1 rewritten commit
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 888116b5 new subject
+        |
+        o e96e49b2 create test3.txt
+        Amended with 1 uncommitted change.
+        Amended commit message.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "-n", "1", "--format=%B"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        new subject
+
+        new body
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_amend_edit_message() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "HEAD^"])?;
+
+    // Nothing to amend in the working copy, so this only rewords the commit.
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "amend", "--edit"],
+            &GitRunOptions {
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert(
+                        "GIT_EDITOR".to_string(),
+                        "printf 'reworded test2\\n\\n# ignored comment\\n' >".to_string(),
+                    );
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 76de37e0 create test3.txt
+        branchless: processing 1 rewritten commit
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ bf4ffa82 reworded test2
+        |
+        o 76de37e0 create test3.txt
+        Amended commit message.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "-n", "1", "--format=%B"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        reworded test2
+        "###);
+    }
+
+    // An empty message aborts the amend.
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "amend", "--edit"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert(
+                        "GIT_EDITOR".to_string(),
+                        "printf '# only a comment\\n' >".to_string(),
+                    );
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Aborting amend due to empty commit message.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_amend_staged() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend", "--staged"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        There are no staged changes. Nothing to amend.
+        "###);
+    }
+
+    git.write_file("test1", "staged contents")?;
+    git.run(&["add", "test1.txt"])?;
+    git.write_file("test1", "unstaged contents")?;
+    git.write_file("test2", "unstaged contents")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend", "--staged"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 7ae6dcc0 create test2.txt
+        Amended with 1 staged change. (Some uncommitted changes were not amended.)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=", "--patch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        diff --git a/test1.txt b/test1.txt
+        index 7432a8f..322c8b8 100644
+        --- a/test1.txt
+        +++ b/test1.txt
+        @@ -1 +1 @@
+        -test1 contents
+        +staged contents
+        \ No newline at end of file
+        diff --git a/test2.txt b/test2.txt
+        new file mode 100644
+        index 0000000..4e512d2
+        --- /dev/null
+        +++ b/test2.txt
+        @@ -0,0 +1 @@
+        +test2 contents
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff", "--name-only"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        test1.txt
+        test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_amend_pathspecs() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    git.write_file("test1", "updated contents")?;
+    git.write_file("test2", "updated contents")?;
+    git.write_file("untracked1", "untracked contents")?;
+    git.write_file("untracked2", "untracked contents")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend", "test3.txt"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        There are no uncommitted or staged changes in the given paths. Nothing to amend.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend", "test2.txt", "untracked1.txt"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> reset -- test2.txt untracked1.txt
+        Unstaged changes after reset:
+        M	test1.txt
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ a8e267d8 create test2.txt
+        Amended with 2 uncommitted changes.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=", "--name-only"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        test2.txt
+        untracked1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) =
+            git.run(&["ls-files", "--modified", "--others", "--exclude-standard"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        untracked2.txt
+        test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend", "--include-untracked"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> reset
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ d1f518fb create test2.txt
+        Amended with 2 uncommitted changes.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) =
+            git.run(&["ls-files", "--modified", "--others", "--exclude-standard"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_amend_public_commit() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.write_file("test1", "updated contents")?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "amend"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Refusing to rewrite 62fc20d2 create test1.txt: it is a public commit on master.
+        (Pass --force-rewrite-public to rewrite public commits anyway)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "amend", "--force-rewrite-public"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> reset
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        :
+        @ 3b98a960 (master) create test1.txt
+        Amended with 1 uncommitted change.
         "###);
     }
 
@@ -77,7 +520,6 @@ fn test_amend_rename() -> eyre::Result<()> {
         branchless: running command: <git-executable> diff --quiet
         No abandoned commits to restack.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout f6b255388219264f4bcd258a3020d262c2d7b03e
         O f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
@@ -114,7 +556,6 @@ fn test_amend_delete() -> eyre::Result<()> {
         branchless: running command: <git-executable> reset
         No abandoned commits to restack.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout f0f07277a6448cac370e6023ab379ec0c601ccfe
         O f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
@@ -158,8 +599,6 @@ fn test_amend_with_working_copy() -> eyre::Result<()> {
         branchless: running command: <git-executable> diff --quiet
         No abandoned commits to restack.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout f8e4ba1be5cefcf22e831f51b1525b0be8215a31
-        M	test2.txt
         O f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
@@ -175,7 +614,6 @@ fn test_amend_with_working_copy() -> eyre::Result<()> {
         branchless: running command: <git-executable> reset
         No abandoned commits to restack.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 2e69581cb466962fa85e5918f29af6d2925fdd6f
         O f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
@@ -207,7 +645,6 @@ fn test_amend_head() -> eyre::Result<()> {
         branchless: running command: <git-executable> reset
         No abandoned commits to restack.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 3b98a960e6ebde39a933c25413b43bce8c0fd128
         O f777ecc9 (master) create initial.txt
         |
         @ 3b98a960 create test1.txt
@@ -230,7 +667,6 @@ fn test_amend_head() -> eyre::Result<()> {
         branchless: running command: <git-executable> diff --quiet
         No abandoned commits to restack.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 685ef311b070a460b7c86a9aed068be563978021
         O f777ecc9 (master) create initial.txt
         |
         @ 685ef311 create test1.txt
@@ -264,7 +700,6 @@ fn test_amend_executable() -> eyre::Result<()> {
         branchless: running command: <git-executable> diff --quiet
         No abandoned commits to restack.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout f00ec4b5a81438f4e792ca5576a290b16fed8fdb
         O f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
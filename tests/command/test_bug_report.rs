@@ -0,0 +1,116 @@
+use branchless::testing::make_git;
+
+fn get_section<'a>(report: &'a str, header: &str) -> &'a str {
+    let start = report
+        .find(header)
+        .unwrap_or_else(|| panic!("Section {:?} not found in report: {}", header, report));
+    let section = &report[start..];
+    let section = match section.find('\n') {
+        Some(end_of_header) => &section[end_of_header + 1..],
+        None => "",
+    };
+    match section.find("\n## ") {
+        Some(end) => &section[..end],
+        None => section,
+    }
+}
+
+#[test]
+fn test_bug_report() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    let (stdout, _stderr) = git.run(&["branchless", "bug-report"])?;
+    assert!(stdout.starts_with("```\n# git-branchless bug report\n"));
+    assert!(stdout.ends_with("\n```\n"));
+    for header in [
+        "## Versions",
+        "## Config",
+        "## Installation",
+        "## Hooks",
+        "## Event log (last 10 transactions)",
+        "## Smartlog",
+        "## Tracing log",
+    ] {
+        assert!(
+            stdout.contains(header),
+            "Missing {:?} in: {}",
+            header,
+            stdout
+        );
+    }
+
+    assert!(get_section(&stdout, "## Versions").contains("event log schema: "));
+    assert!(get_section(&stdout, "## Config").contains("branchless.core.mainbranch = master"));
+    assert!(!get_section(&stdout, "## Installation").contains("[FAILED]"));
+    assert!(get_section(&stdout, "## Hooks").contains("### reference-transaction "));
+    assert!(!stdout.contains("create test1.txt"));
+    assert!(!stdout.contains("create test2.txt"));
+    assert!(get_section(&stdout, "## Event log").contains("(message: <redacted>)"));
+
+    insta::assert_snapshot!(get_section(&stdout, "## Smartlog"), @r###"
+    O f777ecc9 (master)
+    |
+    o 62fc20d2
+    |
+    @ 96d1c37a
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_bug_report_include_messages() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    let (stdout, _stderr) =
+        git.run(&["branchless", "bug-report", "--include-messages", "-n", "1"])?;
+    assert!(stdout.contains("## Event log (last 1 transactions)"));
+    let event_log_section = get_section(&stdout, "## Event log");
+    assert!(!event_log_section.contains("<redacted>"));
+    assert!(event_log_section.contains(r#"(message: "create test2.txt")"#));
+    assert!(!event_log_section.contains("create test1.txt"));
+    insta::assert_snapshot!(get_section(&stdout, "## Smartlog"), @r###"
+    O f777ecc9 (master) create initial.txt
+    |
+    o 62fc20d2 create test1.txt
+    |
+    @ 96d1c37a create test2.txt
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_bug_report_broken_hook_markers() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    let hook_path = git.repo_path.join(".git").join("hooks").join("post-commit");
+    let contents = std::fs::read_to_string(&hook_path)?;
+    std::fs::write(&hook_path, contents.replace("## END BRANCHLESS CONFIG", ""))?;
+
+    let (stdout, _stderr) = git.run(&["branchless", "bug-report"])?;
+    let hooks_section = get_section(&stdout, "## Hooks");
+    let post_commit_line = hooks_section
+        .lines()
+        .find(|line| line.starts_with("### post-commit "))
+        .unwrap();
+    assert!(post_commit_line.ends_with(": installed, markers NOT intact"));
+    let post_merge_line = hooks_section
+        .lines()
+        .find(|line| line.starts_with("### post-merge "))
+        .unwrap();
+    assert!(post_merge_line.ends_with(": installed, markers intact"));
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+#[test]
+fn test_diff_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "diff", "--stat"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff 62fc20d2a290daea0d52bdc2ed2ad4be6491010e 355e173bf9c5d2efac2e451da0cdad3fb82b869a --stat
+        [git]  test2.txt | 1 +
+        [git]  test3.txt | 1 +
+        [git]  test4.txt | 1 +
+        [git]  3 files changed, 3 insertions(+)
+        "###);
+
+        let (expected_stdout, _stderr) = git.run(&["diff", "master..HEAD", "--stat"])?;
+        let stdout: String = stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("[git] "))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_eq!(stdout, expected_stdout);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "diff", "HEAD~", "--name-only"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> show 70deb1e28791d8e7dd5a1f0c871a51b91282562f --name-only
+        [git] commit 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+        [git] Author: Testy McTestface <test@example.com>
+        [git] Date:   Thu Oct 29 12:34:56 2020 -0300
+        [git] 
+        [git]     create test3.txt
+        [git] 
+        [git] test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "diff", "HEAD~2::HEAD~", "--name-only"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff 62fc20d2a290daea0d52bdc2ed2ad4be6491010e 70deb1e28791d8e7dd5a1f0c871a51b91282562f --name-only
+        [git] test2.txt
+        [git] test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "diff", "HEAD~2 | HEAD"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @"Cannot show the combined diff of HEAD~2 | HEAD, because they are not based on a single commit.");
+    }
+
+    git.run(&["checkout", "master"])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "diff"])?;
+        insta::assert_snapshot!(stdout, @"There are no commits in the current stack.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+#[test]
+fn test_export_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.write_file("test2", "updated contents\n")?;
+    git.run(&[
+        "commit",
+        "-a",
+        "-m",
+        "update test2.txt",
+        "-m",
+        "This is the body of the commit message.",
+    ])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "export"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        0001-create-test1.txt.patch
+        0002-create-test2.txt.patch
+        0003-update-test2.txt.patch
+        "###);
+    }
+
+    let read_patch = |file_name: &str| -> eyre::Result<String> {
+        Ok(std::fs::read_to_string(git.repo_path.join(file_name))?)
+    };
+    insta::assert_snapshot!(read_patch("0001-create-test1.txt.patch")?, @r###"
+    From 62fc20d2a290daea0d52bdc2ed2ad4be6491010e Mon Sep 17 00:00:00 2001
+    From: Testy McTestface <test@example.com>
+    Date: Thu, 29 Oct 2020 12:34:56 -0100
+    Subject: [PATCH 1/3] create test1.txt
+
+    ---
+     test1.txt | 1 +
+     1 file changed, 1 insertion(+)
+
+    diff --git a/test1.txt b/test1.txt
+    new file mode 100644
+    index 0000000..7432a8f
+    --- /dev/null
+    +++ b/test1.txt
+    @@ -0,0 +1 @@
+    +test1 contents
+    "###);
+    insta::assert_snapshot!(read_patch("0003-update-test2.txt.patch")?, @r###"
+    From 9fa86ce87ccd901e2d457e510d0e35a342e4f2ab Mon Sep 17 00:00:00 2001
+    From: Testy McTestface <test@example.com>
+    Date: Thu, 29 Oct 2020 12:34:56 +0000
+    Subject: [PATCH 3/3] update test2.txt
+
+    This is the body of the commit message.
+
+    ---
+     test2.txt | 2 +-
+     1 file changed, 1 insertion(+), 1 deletion(-)
+
+    diff --git a/test2.txt b/test2.txt
+    index 4e512d2..27e2fc9 100644
+    --- a/test2.txt
+    +++ b/test2.txt
+    @@ -1 +1 @@
+    -test2 contents
+    +updated contents
+    "###);
+
+    // The exported patches should apply cleanly onto the base of the stack.
+    git.run(&["checkout", "master"])?;
+    git.run(&[
+        "am",
+        "0001-create-test1.txt.patch",
+        "0002-create-test2.txt.patch",
+        "0003-update-test2.txt.patch",
+    ])?;
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s%n%b", "master"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        update test2.txt
+        This is the body of the commit message.
+
+        create test2.txt
+
+        create test1.txt
+
+        create initial.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_export_stdout_with_cover_letter() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) =
+            git.run(&["branchless", "export", "--stdout", "--cover-letter", "--reroll-count=2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+        From: Testy McTestface <test@example.com>
+        Date: Thu, 29 Oct 2020 12:34:56 -0200
+        Subject: [PATCH v2 0/2] *** SUBJECT HERE ***
+
+        *** BLURB HERE ***
+
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+
+        Testy McTestface (2):
+          create test1.txt
+          create test2.txt
+
+         test1.txt | 1 +
+         test2.txt | 1 +
+         2 files changed, 2 insertions(+)
+
+        From 62fc20d2a290daea0d52bdc2ed2ad4be6491010e Mon Sep 17 00:00:00 2001
+        From: Testy McTestface <test@example.com>
+        Date: Thu, 29 Oct 2020 12:34:56 -0100
+        Subject: [PATCH v2 1/2] create test1.txt
+
+        ---
+         test1.txt | 1 +
+         1 file changed, 1 insertion(+)
+
+        diff --git a/test1.txt b/test1.txt
+        new file mode 100644
+        index 0000000..7432a8f
+        --- /dev/null
+        +++ b/test1.txt
+        @@ -0,0 +1 @@
+        +test1 contents
+
+        From 96d1c37a3d4363611c49f7e52186e189a04c531f Mon Sep 17 00:00:00 2001
+        From: Testy McTestface <test@example.com>
+        Date: Thu, 29 Oct 2020 12:34:56 -0200
+        Subject: [PATCH v2 2/2] create test2.txt
+
+        ---
+         test2.txt | 1 +
+         1 file changed, 1 insertion(+)
+
+        diff --git a/test2.txt b/test2.txt
+        new file mode 100644
+        index 0000000..4e512d2
+        --- /dev/null
+        +++ b/test2.txt
+        @@ -0,0 +1 @@
+        +test2 contents
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_export_merge_commit() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.run(&["merge", &test1_oid.to_string()])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "export"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Cannot export 91a5ccb4 Merge commit '62fc20d2a290daea0d52bdc2ed2ad4be6491010e' into HEAD, because it is a merge commit.
+        "###);
+    }
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use branchless::testing::{make_git, GitRunOptions};
+use regex::Regex;
 
 #[test]
 fn test_hide_commit() -> eyre::Result<()> {
@@ -241,6 +242,119 @@ fn test_unhide() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_unhide_restore_branches() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["checkout", "master"])?;
+
+    git.run(&["hide", &test2_oid.to_string()])?;
+    git.run(&["branch", "-D", "foo"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) =
+            git.run(&["unhide", "--restore-branches", &test2_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Unhid commit: 96d1c37a create test2.txt
+        Restored branch foo.
+        To hide this commit, run: git hide 96d1c37a
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["rev-parse", "foo"])?;
+        assert_eq!(stdout.trim(), test2_oid.to_string());
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git unhide --restore-branches 96d1c37a3d4363611c49f7e52186e189a04c531f (transaction 11)
+        Will apply these actions:
+        1. Delete branch foo at 96d1c37a create test2.txt
+           
+        2. Hide commit 96d1c37a create test2.txt
+           
+        Applied 2 inverse events.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_unhide_restore_branches_conflict() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["branch", "bar"])?;
+    git.run(&["checkout", "master"])?;
+
+    git.run(&["hide", &test2_oid.to_string()])?;
+    git.run(&["branch", "-D", "foo", "bar"])?;
+    git.run(&["branch", "foo", "HEAD"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["unhide", &test2_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Unhid commit: 96d1c37a create test2.txt
+        Not restoring branch foo, since it now points to: f777ecc9 create initial.txt
+        To hide this commit, run: git hide 96d1c37a
+        To restore branch bar, run: git unhide --restore-branches 96d1c37a
+        "###);
+    }
+    {
+        let (foo_stdout, _stderr) = git.run(&["rev-parse", "foo"])?;
+        let (master_stdout, _stderr) = git.run(&["rev-parse", "master"])?;
+        assert_eq!(foo_stdout, master_stdout);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (foo, master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_hide_recursive() -> eyre::Result<()> {
     let git = make_git()?;
@@ -309,3 +423,315 @@ fn test_hide_recursive() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_hide_revset() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["hide", "draft() - message(test3)"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Hid commit: 62fc20d2 create test1.txt
+        To unhide this commit, run: git unhide 62fc20d2
+        Hid commit: 96d1c37a create test2.txt
+        To unhide this commit, run: git unhide 96d1c37a
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 98b9119d create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["hide", "draft() &"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Parse error for expression 'draft() &': expected a name, function call, or parenthesized expression, but reached the end of the expression
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_hide_range() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    let test5_oid = git.commit_file("test5", 5)?;
+    git.commit_file("test6", 6)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["hide", &format!("{}..{}", test2_oid, test5_oid)])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Hid commit: 70deb1e2 create test3.txt
+        To unhide this commit, run: git unhide 70deb1e2
+        Hid commit: 355e173b create test4.txt
+        To unhide this commit, run: git unhide 355e173b
+        Hid commit: f81d55c0 create test5.txt
+        To unhide this commit, run: git unhide f81d55c0
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        x 70deb1e2 (manually hidden) create test3.txt
+        |
+        x 355e173b (manually hidden) create test4.txt
+        |
+        x f81d55c0 (manually hidden) create test5.txt
+        |
+        @ 2831fb58 create test6.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git hide 96d1c37a3d4363611c49f7e52186e189a04c531f..f81d55c0d520ff8d02ef9294d95156dcb78a5255 (transaction 14)
+        Will apply these actions:
+        1. Unhide commit f81d55c0 create test5.txt
+           
+        2. Unhide commit 355e173b create test4.txt
+           
+        3. Unhide commit 70deb1e2 create test3.txt
+           
+        Applied 3 inverse events.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        |
+        o 355e173b create test4.txt
+        |
+        o f81d55c0 create test5.txt
+        |
+        @ 2831fb58 create test6.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["hide", &format!("{}::{}", test3_oid, test5_oid)])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Hid commit: 70deb1e2 create test3.txt
+        To unhide this commit, run: git unhide 70deb1e2
+        Hid commit: 355e173b create test4.txt
+        To unhide this commit, run: git unhide 355e173b
+        Hid commit: f81d55c0 create test5.txt
+        To unhide this commit, run: git unhide f81d55c0
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["unhide", &format!("{}::{}", test3_oid, test5_oid)])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Unhid commit: 70deb1e2 create test3.txt
+        To hide this commit, run: git hide 70deb1e2
+        Unhid commit: 355e173b create test4.txt
+        To hide this commit, run: git hide 355e173b
+        Unhid commit: f81d55c0 create test5.txt
+        To hide this commit, run: git hide f81d55c0
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_hide_range_errors() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["hide", &format!("master~..{}", test3_oid)],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Refusing to rewrite 62fc20d2 create test1.txt: it is a public commit on master.
+        (Pass --force-rewrite-public to rewrite public commits anyway)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["hide", "HEAD..HEAD~"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @"Expression 'HEAD..HEAD~' did not match any commits.
+");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_hide_archive() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "archive", "list"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        There are no archived commits. To archive commits, run: git hide --archive <name> <commits>
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["hide", "--archive", "experiment", "draft()"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Hid commit: 62fc20d2 create test1.txt
+        To unhide this commit, run: git unhide 62fc20d2
+        Hid commit: 96d1c37a create test2.txt
+        To unhide this commit, run: git unhide 96d1c37a
+        Hid commit: 70deb1e2 create test3.txt
+        To unhide this commit, run: git unhide 70deb1e2
+        Archived as: experiment
+        To restore these commits, run: git branchless archive restore experiment
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "archive", "list"])?;
+        let date_re = Regex::new(r"[0-9]{4}-[0-9]{2}-[0-9]{2} [0-9]{2}:[0-9]{2}")?;
+        let stdout = date_re.replace_all(&stdout, "<date>");
+        insta::assert_snapshot!(stdout, @r###"
+        experiment: 3 commits (3 hidden), archived <date>
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "archive", "show", "experiment"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        x 62fc20d2 (manually hidden) create test1.txt
+        |
+        x 96d1c37a (manually hidden) create test2.txt
+        |
+        x 70deb1e2 (manually hidden) create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "archive", "show", "nonexistent"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        There is no archive named: nonexistent
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "archive", "restore", "experiment"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Unhid commit: 62fc20d2 create test1.txt
+        Unhid commit: 96d1c37a create test2.txt
+        Unhid commit: 70deb1e2 create test3.txt
+        Restored 3 commits from archive: experiment
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "archive", "delete", "experiment"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Deleted archive experiment (3 commits). Any of its commits which are still hidden will be released for garbage collection.
+        "###);
+        let (stdout, _stderr) = git.run(&["branchless", "archive", "list"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        There are no archived commits. To archive commits, run: git hide --archive <name> <commits>
+        "###);
+    }
+
+    Ok(())
+}
@@ -1,7 +1,10 @@
+use std::collections::HashMap;
+
 use regex::Regex;
 
-use crate::util::trim_lines;
+use crate::util::{run_in_pty, trim_lines, PtyAction};
 
+use branchless::core::eventlog::{Event, EventLogDb};
 use branchless::git::GitVersion;
 use branchless::testing::{make_git, GitInitOptions, GitRunOptions};
 use eyre::Context;
@@ -96,6 +99,7 @@ fn test_old_git_version_warning() -> eyre::Result<()> {
         Created config file at <repo-path>/.git/branchless/config
         Auto-detected your main branch as: master
         If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hook: post-applypatch
         Installing hook: post-commit
         Installing hook: post-merge
         Installing hook: post-rewrite
@@ -141,12 +145,14 @@ fn test_init_basic() -> eyre::Result<()> {
         Created config file at <repo-path>/.git/branchless/config
         Auto-detected your main branch as: master
         If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hook: post-applypatch
         Installing hook: post-commit
         Installing hook: post-merge
         Installing hook: post-rewrite
         Installing hook: post-checkout
         Installing hook: pre-auto-gc
         Installing hook: reference-transaction
+        Imported 2 events from existing history.
         Successfully installed git-branchless.
         To uninstall, run: git branchless init --uninstall
         "###);
@@ -155,15 +161,10 @@ fn test_init_basic() -> eyre::Result<()> {
     Ok(())
 }
 
-#[cfg(unix)]
 #[test]
-fn test_init_prompt_for_main_branch() -> eyre::Result<()> {
+fn test_init_prompt_for_main_branch_non_interactive() -> eyre::Result<()> {
     let git = make_git()?;
 
-    if !git.supports_reference_transactions()? {
-        return Ok(());
-    }
-
     git.init_repo_with_options(&GitInitOptions {
         run_branchless_init: false,
         ..Default::default()
@@ -175,37 +176,62 @@ fn test_init_prompt_for_main_branch() -> eyre::Result<()> {
         let (stdout, stderr) = git.run_with_options(
             &["branchless", "init"],
             &GitRunOptions {
+                expected_exit_code: 1,
                 input: Some("bespoke\n".to_string()),
                 ..Default::default()
             },
         )?;
-        insta::assert_snapshot!(stderr, @"");
-        insta::assert_snapshot!(stdout, @r###"
-        Created config file at <repo-path>/.git/branchless/config
-        Your main branch name could not be auto-detected!
-        Examples of a main branch: master, main, trunk, etc.
-        See https://github.com/arxanas/git-branchless/wiki/Concepts#main-branch
-        Enter the name of your main branch: Installing hook: post-commit
-        Installing hook: post-merge
-        Installing hook: post-rewrite
-        Installing hook: post-checkout
-        Installing hook: pre-auto-gc
-        Installing hook: reference-transaction
-        Successfully installed git-branchless.
-        To uninstall, run: git branchless init --uninstall
-        "###);
+        insta::assert_snapshot!(stderr, @"could not auto-detect main branch; pass --main-branch <name>");
+        insta::assert_snapshot!(stdout, @"");
+    }
+    assert!(!git.get_repo()?.is_branchless_initialized());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_init_prompt_for_main_branch() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+
+    git.run(&["branch", "-m", "master", "bespoke"])?;
+    git.run(&["branch", "feature"])?;
+
+    let exit_status = run_in_pty(
+        &git,
+        &["branchless", "init"],
+        &[
+            PtyAction::WaitUntilContains("> 1) bespoke"),
+            PtyAction::WaitUntilContains("2) feature"),
+            PtyAction::WaitUntilContains("[1-2, or a name; default 1]: "),
+            PtyAction::Write("\r"),
+            PtyAction::WaitUntilContains("Successfully installed git-branchless."),
+        ],
+    )?;
+    assert!(exit_status.success());
+
+    {
+        let (stdout, _stderr) = git.run(&["config", "branchless.core.mainBranch"])?;
+        insta::assert_snapshot!(stdout, @"bespoke");
     }
 
     {
         let (stdout, _stderr) = git.run(&["smartlog"])?;
-        insta::assert_snapshot!(stdout, @"@ f777ecc9 (bespoke) create initial.txt
-");
+        insta::assert_snapshot!(stdout, @"@ f777ecc9 (bespoke, feature) create initial.txt");
     }
 
     Ok(())
 }
 
-#[cfg(unix)]
 #[test]
 fn test_main_branch_not_found_error_message() -> eyre::Result<()> {
     let git = make_git()?;
@@ -240,7 +266,7 @@ fn test_main_branch_not_found_error_message() -> eyre::Result<()> {
 
        0: branchless::git::repo::get_main_branch_oid with self=<Git repository at: "<repo-path>/.git/">
           at some/file/path.rs:123
-       1: branchless::commands::smartlog::smartlog with effects=<Output fancy=false> git_run_info=<GitRunInfo path_to_git="<git-executable>" working_directory="<repo-path>" env=not shown> options=SmartlogOptions { show_hidden_commits: false }
+       1: branchless::commands::smartlog::smartlog with effects=<Output fancy=false> git_run_info=<GitRunInfo path_to_git="<git-executable>" working_directory="<repo-path>" env=not shown> options=SmartlogOptions { show_hidden_commits: false, revset: None, since: None, touches: [], debug_timing: false }
           at some/file/path.rs:123
 
     Suggestion:
@@ -278,6 +304,7 @@ fn test_init_uninstall() -> eyre::Result<()> {
         insta::assert_snapshot!(stderr, @"");
         insta::assert_snapshot!(stdout, @r###"
         Removing config file: <repo-path>/.git/branchless/config
+        Uninstalling hook: post-applypatch
         Uninstalling hook: post-commit
         Uninstalling hook: post-merge
         Uninstalling hook: post-rewrite
@@ -290,6 +317,179 @@ fn test_init_uninstall() -> eyre::Result<()> {
     Ok(())
 }
 
+#[cfg(not(feature = "man-pages"))]
+#[test]
+fn test_alias_help_without_man_pages() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    {
+        let (stdout, stderr) = git.run(&["sl", "--help"])?;
+        insta::assert_snapshot!(stderr, @"'sl' is aliased to 'branchless-smartlog'");
+        assert!(stdout.contains("git-branchless smartlog [OPTIONS] [REVSET]"));
+        assert!(stdout.contains("--hidden"));
+        assert!(stdout.contains("--debug-timing"));
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sl"])?;
+        insta::assert_snapshot!(stdout, @"@ f777ecc9 (master) create initial.txt");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_init_uninstall_aliases() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    // Aliases written to the repository's own configuration, in either form.
+    git.run(&["config", "alias.sl", "branchless smartlog"])?;
+    git.run(&["config", "alias.co", "branchless-checkout"])?;
+    git.run(&["config", "alias.branchless-checkout", "branchless checkout"])?;
+    git.run(&["config", "alias.next", "log --oneline"])?;
+
+    git.run(&["branchless", "init", "--uninstall"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["config", "--get-regexp", "^alias\\."])?;
+        insta::assert_snapshot!(stdout, @"alias.next log --oneline");
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_init_global_man_pages() -> eyre::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let git = make_git()?;
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+
+    let global_config_path = git.repo_path.join(".git").join("global-config");
+    let data_dir = git.repo_path.join(".git").join("data");
+    let man_dir = data_dir.join("git-branchless").join("man");
+    let page_path = man_dir.join("man1").join("git-branchless-smartlog.1");
+
+    // A fake `man`, which reports the page it was asked to show.
+    let bin_dir = git.repo_path.join(".git").join("bin");
+    std::fs::create_dir(&bin_dir)?;
+    let man_path = bin_dir.join("man");
+    std::fs::write(&man_path, "#!/bin/sh\necho \"MANPATH=$MANPATH\" \"$@\"\n")?;
+    std::fs::set_permissions(&man_path, std::fs::Permissions::from_mode(0o755))?;
+    let mut path = bin_dir.into_os_string();
+    path.push(":");
+    path.push(git.get_path_for_env());
+
+    let options = GitRunOptions {
+        env: {
+            let mut env = HashMap::new();
+            env.insert(
+                "GIT_CONFIG_GLOBAL".to_string(),
+                global_config_path.to_str().unwrap().to_string(),
+            );
+            env.insert(
+                "XDG_DATA_HOME".to_string(),
+                data_dir.to_str().unwrap().to_string(),
+            );
+            env.insert("PATH".to_string(), path.to_str().unwrap().to_string());
+            env
+        },
+        ..Default::default()
+    };
+
+    {
+        let (stdout, stderr) =
+            git.run_with_options(&["branchless", "init", "--global"], &options)?;
+        insta::assert_snapshot!(stderr, @"");
+        insta::assert_snapshot!(stdout, @r###"
+        Installed man-pages to: <repo-path>/.git/data/git-branchless/man
+        Configured the man-page viewer in: <repo-path>/.git/global-config
+        "###);
+    }
+    assert!(page_path.exists());
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "config",
+            "--file",
+            global_config_path.to_str().unwrap(),
+            "--get-regexp",
+            "^man\\.",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        man.branchless.cmd git branchless help-viewer --man-dir '<repo-path>/.git/data/git-branchless/man'
+        man.viewer branchless
+        "###);
+    }
+
+    // Reinstalling with the same version doesn't regenerate the man-pages.
+    {
+        let modified = std::fs::metadata(&page_path)?.modified()?;
+        let (stdout, _stderr) =
+            git.run_with_options(&["branchless", "init", "--global"], &options)?;
+        insta::assert_snapshot!(stdout, @r###"
+        Man-pages are already up to date in: <repo-path>/.git/data/git-branchless/man
+        Configured the man-page viewer in: <repo-path>/.git/global-config
+        "###);
+        assert_eq!(std::fs::metadata(&page_path)?.modified()?, modified);
+    }
+
+    // The repository uses the man-pages installed for the user, rather than
+    // installing its own.
+    git.run_with_options(&["branchless", "init"], &options)?;
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &[
+                "config",
+                "--file",
+                ".git/branchless/config",
+                "--get-regexp",
+                "^man\\.",
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+    {
+        let (stdout, stderr) = git.run_with_options(&["sl", "--help"], &options)?;
+        insta::assert_snapshot!(stderr, @"'sl' is aliased to 'branchless-smartlog'");
+        insta::assert_snapshot!(stdout, @"MANPATH=<repo-path>/.git/data/git-branchless/man: git-branchless-smartlog");
+    }
+
+    {
+        let (stdout, _stderr) =
+            git.run_with_options(&["branchless", "init", "--global", "--uninstall"], &options)?;
+        insta::assert_snapshot!(stdout, @r###"
+        Removed the man-page viewer from: <repo-path>/.git/global-config
+        Removed man-pages from: <repo-path>/.git/data/git-branchless/man
+        "###);
+    }
+    assert!(!man_dir.exists());
+    {
+        let (stdout, _stderr) = git.run(&[
+            "config",
+            "--file",
+            global_config_path.to_str().unwrap(),
+            "--list",
+        ])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "man-pages")]
 #[test]
 fn test_man_viewer_installed() -> eyre::Result<()> {
@@ -374,8 +574,8 @@ fn test_init_explicit_main_branch_name() -> eyre::Result<()> {
         // over the repo default.
         git.run(&["config", "init.defaultBranch", "repo-default-branch"])?;
 
-        git.run(&["branchless", "init", "--main-branch", "foo"])?;
         git.run(&["checkout", "-b", "foo"])?;
+        git.run(&["branchless", "init", "--main-branch", "foo"])?;
         git.run(&["branch", "-d", "master"])?;
         git.commit_file("test1", 1)?;
 
@@ -389,6 +589,46 @@ fn test_init_explicit_main_branch_name() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_init_multiple_main_branch_names() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+
+    {
+        git.run(&["branch", "release-1.x"])?;
+        git.run(&[
+            "branchless",
+            "init",
+            "--main-branch",
+            "master",
+            "--main-branch",
+            "release-1.x",
+        ])?;
+
+        let (stdout, _stderr) = git.run(&["config", "branchless.core.mainBranch"])?;
+        insta::assert_snapshot!(stdout, @"master");
+        let (stdout, _stderr) =
+            git.run(&["config", "--get-all", "branchless.core.mainBranches"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        master
+        release-1.x
+        "###);
+    }
+
+    {
+        git.run(&["checkout", "release-1.x"])?;
+        git.commit_file("test1", 1)?;
+        let (stdout, _stderr) = git.run(&["branchless", "query", "draft()"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_init_repo_default_branch() -> eyre::Result<()> {
     let git = make_git()?;
@@ -416,6 +656,98 @@ fn test_init_repo_default_branch() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_init_config_env_overrides() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+    git.run(&["checkout", "-b", "trunk"])?;
+    git.run(&["branch", "-d", "master"])?;
+
+    // `init.defaultBranch` is only set in the file named by `GIT_CONFIG_GLOBAL`.
+    let global_config_path = git.repo_path.join(".git").join("global-config");
+    std::fs::write(&global_config_path, "[init]\n\tdefaultBranch = trunk\n")?;
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "init", "--no-seed"],
+            &GitRunOptions {
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert(
+                        "GIT_CONFIG_GLOBAL".to_string(),
+                        global_config_path.to_str().unwrap().to_string(),
+                    );
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Created config file at <repo-path>/.git/branchless/config
+        Auto-detected your main branch as: trunk
+        If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Successfully installed git-branchless.
+        To uninstall, run: git branchless init --uninstall
+        "###);
+    }
+
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "-b", "develop"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "trunk"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (trunk) create test1.txt
+        |
+        o 96d1c37a (develop) create test2.txt
+        "###);
+    }
+
+    // Entries passed via `GIT_CONFIG_COUNT` take precedence over the config
+    // files. Section and variable names are case-insensitive, but subsection
+    // names aren't.
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["smartlog"],
+            &GitRunOptions {
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert("GIT_CONFIG_COUNT".to_string(), "1".to_string());
+                    env.insert(
+                        "GIT_CONFIG_KEY_0".to_string(),
+                        "Branchless.core.MainBranch".to_string(),
+                    );
+                    env.insert("GIT_CONFIG_VALUE_0".to_string(), "develop".to_string());
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (trunk) create test1.txt
+        |
+        O 96d1c37a (develop) create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_hide_branchless_refs_from_git_log() -> eyre::Result<()> {
     let git = make_git()?;
@@ -447,3 +779,864 @@ fn test_hide_branchless_refs_from_git_log() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_init_seed_from_reflog() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+    git.commit_file("test1", 1)?;
+    git.run_with_options(
+        &["checkout", "-b", "feature"],
+        &GitRunOptions {
+            time: 2,
+            ..Default::default()
+        },
+    )?;
+    git.commit_file("test2", 3)?;
+    git.commit_file("test3", 4)?;
+    git.run_with_options(
+        &["checkout", "master"],
+        &GitRunOptions {
+            time: 5,
+            ..Default::default()
+        },
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "init"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Created config file at <repo-path>/.git/branchless/config
+        Auto-detected your main branch as: master
+        If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Imported 13 events from existing history.
+        Successfully installed git-branchless.
+        To uninstall, run: git branchless init --uninstall
+        "###);
+    }
+
+    // The existing stack stays visible even without its branch, since its
+    // commits were marked as visible.
+    git.run(&["branch", "-D", "feature"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (master) create test1.txt
+        |
+        o fc9d60a1 create test2.txt
+        |
+        o 0b751144 create test3.txt
+        "###);
+    }
+
+    // Undo can cross the initialization boundary.
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y", "-n", "2"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git branch -D feature (transaction 7)
+        2. git init (transaction 6)
+        Will apply these actions:
+        1. Check out from 62fc20d2 create test1.txt
+                       to 0b751144 create test3.txt
+        2. Recreate branch feature at 0b751144 create test3.txt
+
+        branchless: running command: <git-executable> checkout 0b751144d4f178c0210276ed17359e2d12e2c93b --detach
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        o fc9d60a1 create test2.txt
+        |
+        @ 0b751144 create test3.txt
+        Applied 2 inverse events.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        o fc9d60a1 create test2.txt
+        |
+        @ 0b751144 (feature) create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_init_shared_hooks_dir() -> eyre::Result<()> {
+    let git1 = make_git()?;
+    let git2 = make_git()?;
+
+    if !git1.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    let hooks_dir = tempfile::tempdir()?;
+    let hooks_dir_path = hooks_dir.path().to_str().unwrap();
+    for git in [&git1, &git2] {
+        git.init_repo_with_options(&GitInitOptions {
+            run_branchless_init: false,
+            ..Default::default()
+        })?;
+        git.run(&["config", "core.hooksPath", hooks_dir_path])?;
+    }
+
+    {
+        let (stdout, _stderr) = git1.run(&["branchless", "init"])?;
+        let stdout = stdout.replace(hooks_dir_path, "<hooks-dir>");
+        insta::assert_snapshot!(stdout, @r###"
+        Created config file at <repo-path>/.git/branchless/config
+        Auto-detected your main branch as: master
+        If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Warning: your hooks directory is outside of this repository: <hooks-dir>
+        It may be shared with other repositories, such as if core.hooksPath is set globally.
+        The hooks installed there will only run git-branchless in repositories where it's been initialized.
+        To install the hooks into this repository instead, run: git branchless init --hooks-dir repo
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Imported 2 events from existing history.
+        Successfully installed git-branchless.
+        To uninstall, run: git branchless init --uninstall
+        "###);
+    }
+    {
+        let hook_contents = std::fs::read_to_string(hooks_dir.path().join("post-commit"))?;
+        insta::assert_snapshot!(hook_contents, @r###"
+        #!/bin/sh
+        ## START BRANCHLESS CONFIG
+
+        # This hooks directory may be shared with other repositories, so only run in
+        # repositories where git-branchless has been initialized.
+        if [ -f "$(git rev-parse --git-common-dir)/branchless/config" ]; then
+        git branchless hook-post-commit "$@"
+        fi
+
+        ## END BRANCHLESS CONFIG
+        "###);
+    }
+
+    // The shared hooks shouldn't do anything in a repository where
+    // git-branchless hasn't been initialized.
+    {
+        let (_stdout, stderr) = git2.run(&["commit", "--allow-empty", "-m", "test"])?;
+        insta::assert_snapshot!(stderr, @"");
+    }
+
+    git2.run(&["branchless", "init"])?;
+    {
+        let (stdout, _stderr) = git2.run(&["branchless", "init", "--uninstall"])?;
+        let stdout = stdout.replace(hooks_dir_path, "<hooks-dir>");
+        insta::assert_snapshot!(stdout, @r###"
+        Removing config file: <repo-path>/.git/branchless/config
+        Not uninstalling hooks from shared hooks directory: <hooks-dir>
+        (Other repositories may be using them. They won't run git-branchless in this repository.)
+        "###);
+    }
+    {
+        let (_stdout, stderr) = git1.run(&["commit", "--allow-empty", "-m", "test"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: processing 2 updates: branch master, ref HEAD
+        branchless: processed commit: 4cd1a9ba test
+        "###);
+    }
+    {
+        let (_stdout, stderr) = git2.run(&["commit", "--allow-empty", "-m", "test"])?;
+        insta::assert_snapshot!(stderr, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git2.run(&["branchless", "init", "--hooks-dir", "repo"])?;
+        let stdout = stdout.replace(hooks_dir_path, "<hooks-dir>");
+        insta::assert_snapshot!(stdout, @r###"
+        Created config file at <repo-path>/.git/branchless/config
+        Auto-detected your main branch as: master
+        If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hooks into <repo-path>/.git/hooks instead of the shared hooks directory <hooks-dir>
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Successfully installed git-branchless.
+        To uninstall, run: git branchless init --uninstall
+        "###);
+    }
+    assert!(git2
+        .repo_path
+        .join(".git")
+        .join("hooks")
+        .join("post-commit")
+        .exists());
+    {
+        let (_stdout, stderr) = git2.run(&["commit", "--allow-empty", "-m", "test"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: processing 2 updates: branch master, ref HEAD
+        branchless: processed commit: 96762c99 test
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git2.run(&["branchless", "init", "--uninstall"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Removing config file: <repo-path>/.git/branchless/config
+        Uninstalling hook: post-applypatch
+        Uninstalling hook: post-commit
+        Uninstalling hook: post-merge
+        Uninstalling hook: post-rewrite
+        Uninstalling hook: post-checkout
+        Uninstalling hook: pre-auto-gc
+        Uninstalling hook: reference-transaction
+        "###);
+    }
+    {
+        let (_stdout, stderr) = git1.run(&["commit", "--allow-empty", "-m", "test"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: processing 2 updates: branch master, ref HEAD
+        branchless: processed commit: 30e99ac1 test
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_init_caches_git_version() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    let shim_dir = tempfile::tempdir()?;
+    let shim_path = shim_dir.path().join("git");
+    let log_path = shim_dir.path().join("version.log");
+    git.write_counting_git_shim(&shim_path, &log_path, "version")?;
+    let run_init = || -> eyre::Result<usize> {
+        git.run_with_options(
+            &["branchless", "init"],
+            &GitRunOptions {
+                env: {
+                    let mut env: HashMap<String, String> = HashMap::new();
+                    env.insert(
+                        "PATH_TO_GIT".to_string(),
+                        shim_path.to_str().unwrap().to_string(),
+                    );
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        let log = std::fs::read_to_string(&log_path).unwrap_or_default();
+        Ok(log.lines().count())
+    };
+
+    // The version is only determined by spawning Git if it's not cached yet.
+    assert_eq!(run_init()?, 1);
+    assert_eq!(run_init()?, 1);
+
+    // Modifying the Git executable invalidates the cached version.
+    {
+        use std::io::Write;
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&shim_path)?
+            .write_all(b"# modified\n")?;
+    }
+    assert_eq!(run_init()?, 2);
+    assert_eq!(run_init()?, 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_init_disabled_hook() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&[
+        "config",
+        "branchless.hooks.reference-transaction.enabled",
+        "false",
+    ])?;
+
+    let get_events = || -> eyre::Result<Vec<Event>> {
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        event_log_db.get_events()
+    };
+    let is_branch_event = |event: &Event, branch_name: &str| match event {
+        Event::RefUpdateEvent { ref_name, .. } => {
+            ref_name.to_string_lossy() == format!("refs/heads/{}", branch_name)
+        }
+        _ => false,
+    };
+
+    // The hook is still installed, but should do nothing when invoked.
+    git.run(&["branch", "foo"])?;
+    assert!(!get_events()?
+        .iter()
+        .any(|event| is_branch_event(event, "foo")));
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "init", "--update"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Disabling hook: reference-transaction
+        Successfully updated git-branchless hooks.
+        "###);
+    }
+    {
+        let hook_path = git
+            .repo_path
+            .join(".git")
+            .join("hooks")
+            .join("reference-transaction");
+        let hook_contents = std::fs::read_to_string(hook_path)?;
+        insta::assert_snapshot!(hook_contents, @r###"
+        #!/bin/sh
+        ## START BRANCHLESS CONFIG
+
+        # This hook has been disabled by `branchless.hooks.reference-transaction.enabled`.
+        # Run `git branchless init --update` after re-enabling it.
+        ## END BRANCHLESS CONFIG
+        "###);
+    }
+
+    git.run(&["branch", "bar"])?;
+    let commit_oid = git.commit_file("test1", 1)?;
+    let events = get_events()?;
+    assert!(!events.iter().any(|event| is_branch_event(event, "bar")));
+    assert!(events.iter().any(|event| matches!(
+        event,
+        Event::CommitEvent { commit_oid: event_commit_oid, .. } if *event_commit_oid == commit_oid
+    )));
+
+    {
+        let (stdout, stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: the reference-transaction hook is disabled (see branchless.hooks.reference-transaction.enabled)
+        branchless: branch updates since it was disabled aren't recorded and can't be undone
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (bar, foo) create initial.txt
+        |
+        @ 62fc20d2 (master) create test1.txt
+        "###);
+    }
+
+    git.run(&[
+        "config",
+        "branchless.hooks.reference-transaction.enabled",
+        "true",
+    ])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "init", "--update"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Successfully updated git-branchless hooks.
+        "###);
+    }
+    git.run(&["branch", "baz"])?;
+    assert!(get_events()?
+        .iter()
+        .any(|event| is_branch_event(event, "baz")));
+
+    Ok(())
+}
+
+#[test]
+fn test_init_unwritable_hooks_dir() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+
+    // Removing the write permission from the hooks directory wouldn't prevent
+    // writing to it if the tests are run as root, so replace it with a file.
+    let hooks_dir = git.repo_path.join(".git").join("hooks");
+    std::fs::remove_dir_all(&hooks_dir)?;
+    std::fs::write(&hooks_dir, "")?;
+    let config_path = git.repo_path.join(".git").join("config");
+    let original_config = std::fs::read_to_string(&config_path)?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "init"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        Hooks directory is not writable: <repo-path>/.git/hooks
+        git-branchless was not installed, and no changes were made.
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    assert_eq!(std::fs::read_to_string(&config_path)?, original_config);
+    assert!(!git.get_repo()?.is_branchless_initialized());
+    git.run_with_options(
+        &["config", "alias.sl"],
+        &GitRunOptions {
+            expected_exit_code: 1,
+            ..Default::default()
+        },
+    )?;
+
+    Ok(())
+}
+
+#[test]
+fn test_init_check() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "init", "--check"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        [ok] hook post-applypatch
+        [ok] hook post-commit
+        [ok] hook post-merge
+        [ok] hook post-rewrite
+        [ok] hook post-checkout
+        [ok] hook pre-auto-gc
+        [ok] hook reference-transaction
+        [ok] alias amend
+        [ok] alias co
+        [ok] alias hide
+        [ok] alias move
+        [ok] alias next
+        [ok] alias prev
+        [ok] alias record
+        [ok] alias redo
+        [ok] alias restack
+        [ok] alias reword
+        [ok] alias sl
+        [ok] alias smartlog
+        [ok] alias split
+        [ok] alias undo
+        [ok] alias unhide
+        [ok] isolated config
+        [ok] main branch master
+        "###);
+    }
+
+    let hook_path = git.repo_path.join(".git").join("hooks").join("post-commit");
+    std::fs::write(
+        &hook_path,
+        "#!/bin/sh\n## START BRANCHLESS CONFIG\necho 'some other tool'\n## END BRANCHLESS CONFIG\n",
+    )?;
+    git.run(&["config", "alias.sl", "log"])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "init", "--check"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        [ok] hook post-applypatch
+        [FAILED] hook post-commit: git-branchless section is out of date
+        [ok] hook post-merge
+        [ok] hook post-rewrite
+        [ok] hook post-checkout
+        [ok] hook pre-auto-gc
+        [ok] hook reference-transaction
+        [ok] alias amend
+        [ok] alias co
+        [ok] alias hide
+        [ok] alias move
+        [ok] alias next
+        [ok] alias prev
+        [ok] alias record
+        [ok] alias redo
+        [ok] alias restack
+        [ok] alias reword
+        [FAILED] alias sl: set to `log` instead of `branchless-smartlog`
+        [ok] alias smartlog
+        [ok] alias split
+        [ok] alias undo
+        [ok] alias unhide
+        [ok] isolated config
+        [ok] main branch master
+        2 checks failed.
+        To reinstall the hooks, run: git branchless init --update
+        To fix these problems automatically, run: git branchless init --check --fix
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "init", "--check", "--fix"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Reinstalling alias: sl
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        [ok] hook post-applypatch
+        [ok] hook post-commit
+        [ok] hook post-merge
+        [ok] hook post-rewrite
+        [ok] hook post-checkout
+        [ok] hook pre-auto-gc
+        [ok] hook reference-transaction
+        [ok] alias amend
+        [ok] alias co
+        [ok] alias hide
+        [ok] alias move
+        [ok] alias next
+        [ok] alias prev
+        [ok] alias record
+        [ok] alias redo
+        [ok] alias restack
+        [ok] alias reword
+        [ok] alias sl
+        [ok] alias smartlog
+        [ok] alias split
+        [ok] alias undo
+        [ok] alias unhide
+        [ok] isolated config
+        [ok] main branch master
+        "###);
+    }
+    git.run(&["branchless", "init", "--check"])?;
+    {
+        let (stdout, _stderr) = git.run(&["config", "alias.sl"])?;
+        insta::assert_snapshot!(stdout, @"branchless-smartlog");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_init_hook_early_exit_conflict() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+    git.commit_file("test1", 1)?;
+
+    let hook_path = git
+        .repo_path
+        .join(".git")
+        .join("hooks")
+        .join("post-checkout");
+    std::fs::write(
+        &hook_path,
+        r#"#!/bin/sh
+command -v git-lfs >/dev/null 2>&1 || { echo >&2 "git-lfs was not found on your path"; exit 0; }
+git lfs post-checkout "$@"
+exit 0
+"#,
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "init", "--no-seed"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Warning: your post-checkout hook is managed by git-lfs; running `git lfs install` or `git lfs update --force` may remove the git-branchless section; run `git branchless init --check` afterwards
+        Warning: your post-checkout hook exits before the branchless section (at `exit 0`); move the branchless section above the exit or re-run init with --hook-position=prepend
+        Created config file at <repo-path>/.git/branchless/config
+        Auto-detected your main branch as: master
+        If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Successfully installed git-branchless.
+        To uninstall, run: git branchless init --uninstall
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "init",
+            "--no-seed",
+            "--hook-position",
+            "prepend",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Warning: your post-checkout hook is managed by git-lfs; running `git lfs install` or `git lfs update --force` may remove the git-branchless section; run `git branchless init --check` afterwards
+        Created config file at <repo-path>/.git/branchless/config
+        Auto-detected your main branch as: master
+        If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Successfully installed git-branchless.
+        To uninstall, run: git branchless init --uninstall
+        "###);
+    }
+
+    {
+        let hook_contents = std::fs::read_to_string(&hook_path)?;
+        insta::assert_snapshot!(hook_contents, @r###"
+        #!/bin/sh
+        ## START BRANCHLESS CONFIG
+
+        git branchless hook-post-checkout "$@"
+        ## END BRANCHLESS CONFIG
+        command -v git-lfs >/dev/null 2>&1 || { echo >&2 "git-lfs was not found on your path"; exit 0; }
+        git lfs post-checkout "$@"
+        exit 0
+        "###);
+    }
+
+    {
+        let (_stdout, stderr) = git.run(&["checkout", "HEAD^"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: processing 1 update: ref HEAD
+        HEAD is now at f777ecc create initial.txt
+        branchless: processing checkout
+        git-lfs was not found on your path
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_init_copied_repo() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.write_file("test1", "uncommitted changes")?;
+    git.run(&["undo", "-y", "--force"])?;
+
+    // Simulate copying the repository from another machine: the stored paths
+    // refer to the original location, and the working copy snapshot didn't
+    // survive the copy.
+    let config_path = ".git/branchless/config";
+    git.run(&[
+        "config",
+        "--file",
+        config_path,
+        "core.hooksPath",
+        "/path/to/original/.git/hooks",
+    ])?;
+    git.run(&[
+        "config",
+        "--file",
+        config_path,
+        "branchless.internal.fingerprint",
+        "original-machine:/path/to/original/.git",
+    ])?;
+    {
+        let (stdout, _stderr) = git.run(&[
+            "for-each-ref",
+            "--format=%(refname)",
+            "refs/branchless/snapshots/",
+        ])?;
+        for ref_name in stdout.lines() {
+            git.run(&["update-ref", "-d", ref_name])?;
+        }
+    }
+    git.run(&["reflog", "expire", "--expire=now", "--all"])?;
+    git.run(&["gc", "--prune=now", "--quiet"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        hint: this repository appears to have been copied; run `git branchless init --update`
+        :
+        @ 96d1c37a (master) create test2.txt
+        "###);
+    }
+
+    {
+        // The hint is only shown once.
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 96d1c37a (master) create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["redo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git undo -y --force (transaction 7)
+        Will apply these actions:
+        1. Check out from 96d1c37a create test2.txt
+                       to 62fc20d2 create test1.txt
+        2. Skip unavailable working copy snapshot ecd2440a
+
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --detach
+        :
+        @ 62fc20d2 create test1.txt
+        |
+        O 96d1c37a (master) create test2.txt
+        Working copy snapshot ecd2440aa114896d828ec7581b015d5c6a2a0c74 is unavailable, not restoring it.
+        Applied 2 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "init", "--update"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Updated core.hooksPath from /path/to/original/.git/hooks to <repo-path>/.git/hooks
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Successfully updated git-branchless hooks.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["config", "core.hooksPath"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        <repo-path>/.git/hooks
+        "###);
+    }
+
+    {
+        // The hint isn't shown again once the repository has been updated.
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 create test1.txt
+        |
+        O 96d1c37a (master) create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_init_repair_aliases() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    // Simulate settings left behind by older versions of git-branchless.
+    git.run(&["config", "alias.sl", "branchless smartlog"])?;
+    git.run(&["config", "alias.mysl", "branchless smartlog --hidden"])?;
+    git.run(&["config", "branchless.mainBranch", "master"])?;
+    std::fs::write(
+        git.repo_path.join(".git/hooks/post-commit"),
+        "#!/bin/sh\ngit branchless hook-post-commit \"$@\"\n",
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "init", "--repair-aliases"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Updated alias.sl in the repository config: branchless smartlog -> branchless-smartlog
+        Removed branchless.mainBranch from the repository config, since it was superseded by branchless.core.mainBranch
+        Replaced legacy hook: post-commit
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["config", "alias.sl"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless-smartlog
+        "###);
+        let (stdout, _stderr) = git.run(&["config", "alias.mysl"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless smartlog --hidden
+        "###);
+        let (stdout, _stderr) = git.run(&["config", "branchless.core.mainBranch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        master
+        "###);
+        let (_stdout, _stderr) = git.run_with_options(
+            &["config", "branchless.mainBranch"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+    }
+
+    {
+        let hook_contents = std::fs::read_to_string(git.repo_path.join(".git/hooks/post-commit"))?;
+        assert!(hook_contents.contains("## START BRANCHLESS CONFIG"));
+        assert_eq!(hook_contents.matches("hook-post-commit").count(), 1);
+    }
+
+    {
+        // Repairing again has no effect.
+        let (stdout, _stderr) = git.run(&["branchless", "init", "--repair-aliases"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        No aliases, hooks, or settings from older versions of git-branchless were found.
+        "###);
+    }
+
+    Ok(())
+}
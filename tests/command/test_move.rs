@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use branchless::testing::{
     make_git, make_git_with_remote_repo, GitInitOptions, GitRunOptions, GitWrapperWithRemoteRepo,
 };
 
 use crate::command::test_restack::remove_rebase_lines;
+use crate::util::trim_lines;
 
 #[test]
 fn test_move_stick() -> eyre::Result<()> {
@@ -210,6 +213,7 @@ fn test_move_with_source_not_in_smartlog() -> eyre::Result<()> {
 
         git.run(&[
             "move",
+            "--force-rewrite-public",
             "--on-disk",
             "-s",
             &test3_oid.to_string(),
@@ -235,6 +239,7 @@ fn test_move_with_source_not_in_smartlog() -> eyre::Result<()> {
         {
             let (stdout, _stderr) = git.run(&[
                 "move",
+                "--force-rewrite-public",
                 "--in-memory",
                 "--debug-dump-rebase-plan",
                 "-s",
@@ -317,7 +322,7 @@ fn test_move_merge_conflict() -> eyre::Result<()> {
 
     {
         let (stdout, _stderr) = git.run_with_options(
-            &["move", "--source", &other_oid.to_string()],
+            &["move", "--source", &other_oid.to_string(), "-d", "."],
             &GitRunOptions {
                 expected_exit_code: 1,
                 ..Default::default()
@@ -339,6 +344,8 @@ fn test_move_merge_conflict() -> eyre::Result<()> {
                 "--merge",
                 "--source",
                 &other_oid.to_string(),
+                "-d",
+                ".",
             ],
             &GitRunOptions {
                 expected_exit_code: 1,
@@ -373,8 +380,9 @@ fn test_move_merge_conflict() -> eyre::Result<()> {
         Trying again on-disk...
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        CONFLICT (add/add): Merge conflict in conflict.txt
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] CONFLICT (add/add): Merge conflict in conflict.txt
+        After resolving the merge conflicts, run `git move --continue` to finish moving commits, or run `git move --abort` to cancel.
         "###);
     }
 
@@ -423,6 +431,7 @@ fn test_move_base() -> eyre::Result<()> {
             &test3_oid.to_string(),
         ])?;
         insta::assert_snapshot!(stdout, @r###"
+        destination: master (bf0d52a6)
         Rebase plan: Some(
             RebasePlan {
                 first_dest_oid: NonZeroOid(bf0d52a607f693201512a43b6b5a70b2a275e0ad),
@@ -452,7 +461,6 @@ fn test_move_base() -> eyre::Result<()> {
         [1/2] Committed as: 44352d00 create test2.txt
         [2/2] Committed as: cf5eb244 create test3.txt
         branchless: processing 2 rewritten commits
-        branchless: running command: <git-executable> checkout master
         :
         @ bf0d52a6 (master) create test4.txt
         |
@@ -478,6 +486,110 @@ fn test_move_base() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_move_default_source_current_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test5", 5)?;
+
+    git.run(&["checkout", &test3_oid.to_string()])?;
+    {
+        let (stdout, _stderr) = git.run(&["move", "-d", "master"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Moving stack rooted at 96d1c37a create test2.txt (3 commits).
+        Attempting rebase in-memory...
+        [1/3] Committed as: d8959220 create test2.txt
+        [2/3] Committed as: f387c237 create test3.txt
+        [3/3] Committed as: 6776ca5b create test4.txt
+        branchless: processing 3 rewritten commits
+        branchless: running command: <git-executable> checkout f387c2375ba2d0d1c72076ee3960841adf924b7b
+        :
+        O ea7aa064 (master) create test5.txt
+        |
+        o d8959220 create test2.txt
+        |
+        @ f387c237 create test3.txt
+        |
+        o 6776ca5b create test4.txt
+        In-memory rebase succeeded.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O ea7aa064 (master) create test5.txt
+        |
+        o d8959220 create test2.txt
+        |
+        @ f387c237 create test3.txt
+        |
+        o 6776ca5b create test4.txt
+        "###);
+    }
+
+    git.run(&["checkout", "master"])?;
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["move", "-d", "HEAD^"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        HEAD is on the main branch, so there is no current stack to move.
+        To choose the commits to move, pass --source, --base, or --exact.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_default_source_ambiguous_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["merge", &test2_oid.to_string()])?;
+    let (merge_oid, _stderr) = git.run(&["rev-parse", "HEAD"])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", merge_oid.trim()])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["move", "-d", "master"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        The root of the current stack can't be determined, because the stack contains 1 merge commit:
+        a4dd9b08 Merge commit '96d1c37a3d4363611c49f7e52186e189a04c531f' into HEAD
+        To choose the commits to move, pass --source, --base, or --exact.
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_move_base_shared() -> eyre::Result<()> {
     let git = make_git()?;
@@ -554,6 +666,7 @@ fn test_move_checkout_new_head() -> eyre::Result<()> {
     {
         let (stdout, _stderr) = git.run(&["move", "--debug-dump-rebase-plan", "-d", "master"])?;
         insta::assert_snapshot!(stdout, @r###"
+        Moving stack rooted at fe65c1fe create test2.txt (1 commit).
         Rebase plan: Some(
             RebasePlan {
                 first_dest_oid: NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
@@ -614,6 +727,7 @@ fn test_move_branch() -> eyre::Result<()> {
     {
         let (stdout, _stderr) = git.run(&[
             "move",
+            "--force-rewrite-public",
             "--debug-dump-rebase-plan",
             "-d",
             &test2_oid.to_string(),
@@ -683,7 +797,7 @@ fn test_move_base_onto_head() -> eyre::Result<()> {
 
     {
         let (stdout, stderr) = git.run_with_options(
-            &["move", "--debug-dump-rebase-plan", "-b", "HEAD^"],
+            &["move", "--debug-dump-rebase-plan", "-b", "HEAD^", "-d", "."],
             &GitRunOptions {
                 expected_exit_code: 1,
                 ..Default::default()
@@ -691,10 +805,8 @@ fn test_move_base_onto_head() -> eyre::Result<()> {
         )?;
         insta::assert_snapshot!(stderr, @"");
         insta::assert_snapshot!(stdout, @r###"
-        This operation failed because it would introduce a cycle:
-        ,-> 70deb1e2 create test3.txt
-        |   96d1c37a create test2.txt
-        `-- 70deb1e2 create test3.txt
+        Cannot move 96d1c37a create test2.txt onto 70deb1e2 create test3.txt, because the destination is a descendant of the source.
+        To move only the source commit, and not its descendants, use the --exact option.
         "###);
     }
 
@@ -737,6 +849,7 @@ fn test_move_force_in_memory() -> eyre::Result<()> {
         )?;
         insta::assert_snapshot!(stderr, @"");
         insta::assert_snapshot!(stdout, @r###"
+        Moving stack rooted at 081b474b conflicting test2 (1 commit).
         Attempting rebase in-memory...
         There was a merge conflict, which currently can't be resolved when rebasing in-memory.
         The conflicting commit was: 081b474b conflicting test2
@@ -777,6 +890,36 @@ fn test_rebase_in_memory_updates_committer_timestamp() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_rebase_in_memory_preserve_timestamps_flag() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.run(&["config", "branchless.restack.preserveTimestamps", "false"])?;
+
+    let repo = git.get_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.detach_head()?;
+    git.run(&["checkout", "HEAD~"])?;
+    git.commit_file("test3", 3)?;
+
+    let original_committer_timestamp = repo
+        .find_commit_or_fail(repo.get_head_info()?.oid.unwrap())?
+        .get_committer()
+        .get_time();
+    git.run(&["move", "--preserve-timestamps", "-d", "master"])?;
+    let updated_committer_timestamp = repo
+        .find_commit_or_fail(repo.get_head_info()?.oid.unwrap())?
+        .get_committer()
+        .get_time();
+
+    assert!(original_committer_timestamp == updated_committer_timestamp);
+
+    Ok(())
+}
+
 #[test]
 fn test_move_in_memory_gc() -> eyre::Result<()> {
     let git = make_git()?;
@@ -871,6 +1014,54 @@ fn test_move_in_memory_gc() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_move_public_commits_refused() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["move", "-s", &test2_oid.to_string(), "-d", "HEAD"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Refusing to rewrite 96d1c37a create test2.txt: it is a public commit on master.
+        Refusing to rewrite 70deb1e2 create test3.txt: it is a public commit on master.
+        (Pass --force-rewrite-public to rewrite public commits anyway)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "move",
+            "--force-rewrite-public",
+            "-s",
+            &test2_oid.to_string(),
+            "-d",
+            "HEAD",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 44352d00 create test2.txt
+        [2/2] Committed as: cf5eb244 create test3.txt
+        branchless: processing 1 update: branch master
+        branchless: processing 2 rewritten commits
+        In-memory rebase succeeded.
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_move_main_branch_commits() -> eyre::Result<()> {
     let git = make_git()?;
@@ -890,6 +1081,7 @@ fn test_move_main_branch_commits() -> eyre::Result<()> {
     {
         let (stdout, _stderr) = git.run(&[
             "move",
+            "--force-rewrite-public",
             "-s",
             &test3_oid.to_string(),
             "-d",
@@ -997,25 +1189,25 @@ fn test_move_branches_after_move() -> eyre::Result<()> {
                 &test1_oid.to_string(),
             ])?;
             insta::assert_snapshot!(stderr, @r###"
-        Executing: git branchless hook-register-extra-post-rewrite-hook
-        branchless: processing 1 update: ref HEAD
-        branchless: processing 1 update: ref HEAD
-        branchless: processed commit: 4838e49b create test3.txt
-        Executing: git branchless hook-detect-empty-commit 70deb1e28791d8e7dd5a1f0c871a51b91282562f
-        branchless: processing 1 update: ref HEAD
-        branchless: processed commit: a2482074 create test4.txt
-        Executing: git branchless hook-detect-empty-commit 355e173bf9c5d2efac2e451da0cdad3fb82b869a
-        branchless: processing 1 update: ref HEAD
-        branchless: processed commit: 566e4341 create test5.txt
-        Executing: git branchless hook-detect-empty-commit f81d55c0d520ff8d02ef9294d95156dcb78a5255
-        branchless: processing 3 rewritten commits
-        branchless: processing 2 updates: branch bar, branch foo
-        Successfully rebased and updated detached HEAD.
+        [git] Executing: git branchless hook-register-extra-post-rewrite-hook
+        [git] branchless: processing 1 update: ref HEAD
+        [git] branchless: processing 1 update: ref HEAD
+        [git] branchless: processed commit: 4838e49b create test3.txt
+        [git] Executing: git branchless hook-detect-empty-commit 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+        [git] branchless: processing 1 update: ref HEAD
+        [git] branchless: processed commit: a2482074 create test4.txt
+        [git] Executing: git branchless hook-detect-empty-commit 355e173bf9c5d2efac2e451da0cdad3fb82b869a
+        [git] branchless: processing 1 update: ref HEAD
+        [git] branchless: processed commit: 566e4341 create test5.txt
+        [git] Executing: git branchless hook-detect-empty-commit f81d55c0d520ff8d02ef9294d95156dcb78a5255
+        [git] branchless: processing 3 rewritten commits
+        [git] branchless: processing 2 updates: branch bar, branch foo
+        [git] Successfully rebased and updated detached HEAD.
         "###);
             insta::assert_snapshot!(stdout, @r###"
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
         "###);
         }
 
@@ -1039,20 +1231,19 @@ fn test_move_branches_after_move() -> eyre::Result<()> {
             // There should be no branches left to restack.
             let (stdout, _stderr) = git.run(&["restack"])?;
             insta::assert_snapshot!(stdout, @r###"
-        No abandoned commits to restack.
-        No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 566e4341a4a9a930fc2bf7ccdfa168e9f266c34a
-        :
-        O 62fc20d2 create test1.txt
-        |\
-        | o 4838e49b (foo) create test3.txt
-        | |
-        | o a2482074 create test4.txt
-        | |
-        | @ 566e4341 (bar) create test5.txt
-        |
-        O 96d1c37a (master) create test2.txt
-        "###);
+            No abandoned commits to restack.
+            No abandoned branches to restack.
+            :
+            O 62fc20d2 create test1.txt
+            |\
+            | o 4838e49b (foo) create test3.txt
+            | |
+            | o a2482074 create test4.txt
+            | |
+            | @ 566e4341 (bar) create test5.txt
+            |
+            O 96d1c37a (master) create test2.txt
+            "###);
         }
     }
 
@@ -1114,20 +1305,19 @@ fn test_move_branches_after_move() -> eyre::Result<()> {
             // There should be no branches left to restack.
             let (stdout, _stderr) = git.run(&["restack"])?;
             insta::assert_snapshot!(stdout, @r###"
-        No abandoned commits to restack.
-        No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 566e4341a4a9a930fc2bf7ccdfa168e9f266c34a
-        :
-        O 62fc20d2 create test1.txt
-        |\
-        | o 4838e49b (foo) create test3.txt
-        | |
-        | o a2482074 create test4.txt
-        | |
-        | @ 566e4341 (bar) create test5.txt
-        |
-        O 96d1c37a (master) create test2.txt
-        "###);
+            No abandoned commits to restack.
+            No abandoned branches to restack.
+            :
+            O 62fc20d2 create test1.txt
+            |\
+            | o 4838e49b (foo) create test3.txt
+            | |
+            | o a2482074 create test4.txt
+            | |
+            | @ 566e4341 (bar) create test5.txt
+            |
+            O 96d1c37a (master) create test2.txt
+            "###);
         }
     }
 
@@ -1146,7 +1336,7 @@ fn test_move_no_reapply_upstream_commits() -> eyre::Result<()> {
 
     git.detach_head()?;
     let test1_oid = git.commit_file("test1", 1)?;
-    git.run(&["branch", "should-be-deleted"])?;
+    git.run(&["branch", "foo"])?;
     let test2_oid = git.commit_file("test2", 2)?;
     git.run(&["checkout", "master"])?;
     git.run(&["cherry-pick", &test1_oid.to_string()])?;
@@ -1159,21 +1349,21 @@ fn test_move_no_reapply_upstream_commits() -> eyre::Result<()> {
         {
             let (stdout, stderr) = git.run(&["move", "--on-disk", "-b", "HEAD", "-d", "master"])?;
             insta::assert_snapshot!(stderr, @r###"
-            Executing: git branchless hook-register-extra-post-rewrite-hook
-            branchless: processing 1 update: ref HEAD
-            Executing: git branchless hook-skip-upstream-applied-commit 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
-            branchless: processing 1 update: ref HEAD
-            branchless: processed commit: fa466332 create test2.txt
-            Executing: git branchless hook-detect-empty-commit 96d1c37a3d4363611c49f7e52186e189a04c531f
-            branchless: processing 2 rewritten commits
-            branchless: processing 1 update: branch should-be-deleted
-            Successfully rebased and updated detached HEAD.
+            [git] Executing: git branchless hook-register-extra-post-rewrite-hook
+            [git] branchless: processing 1 update: ref HEAD
+            [git] Executing: git branchless hook-skip-upstream-applied-commit 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processed commit: fa466332 create test2.txt
+            [git] Executing: git branchless hook-detect-empty-commit 96d1c37a3d4363611c49f7e52186e189a04c531f
+            [git] branchless: processing 2 rewritten commits
+            [git] branchless: processing 1 update: branch foo
+            [git] Successfully rebased and updated detached HEAD.
             "###);
             insta::assert_snapshot!(stdout, @r###"
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        Skipping commit (was already applied upstream): 62fc20d2 create test1.txt
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] Skipping commit (was already applied upstream): 62fc20d2 create test1.txt
         "###);
         }
 
@@ -1181,7 +1371,7 @@ fn test_move_no_reapply_upstream_commits() -> eyre::Result<()> {
             let (stdout, _stderr) = git.run(&["smartlog"])?;
             insta::assert_snapshot!(stdout, @r###"
             :
-            O 047b7ad7 (master) create test1.txt
+            O 047b7ad7 (foo, master) create test1.txt
             |
             @ fa466332 create test2.txt
             "###);
@@ -1203,11 +1393,11 @@ fn test_move_no_reapply_upstream_commits() -> eyre::Result<()> {
             Attempting rebase in-memory...
             [1/2] Skipped commit (was already applied upstream): 62fc20d2 create test1.txt
             [2/2] Committed as: fa466332 create test2.txt
-            branchless: processing 1 update: branch should-be-deleted
+            branchless: processing 1 update: branch foo
             branchless: processing 2 rewritten commits
             branchless: running command: <git-executable> checkout fa46633239bfa767036e41a77b67258286e4ddb9
             :
-            O 047b7ad7 (master) create test1.txt
+            O 047b7ad7 (foo, master) create test1.txt
             |
             @ fa466332 create test2.txt
             In-memory rebase succeeded.
@@ -1217,11 +1407,11 @@ fn test_move_no_reapply_upstream_commits() -> eyre::Result<()> {
         {
             let (stdout, _stderr) = git.run(&["smartlog"])?;
             insta::assert_snapshot!(stdout, @r###"
-        :
-        O 047b7ad7 (master) create test1.txt
-        |
-        @ fa466332 create test2.txt
-        "###);
+            :
+            O 047b7ad7 (foo, master) create test1.txt
+            |
+            @ fa466332 create test2.txt
+            "###);
         }
     }
     Ok(())
@@ -1273,30 +1463,24 @@ fn test_move_no_reapply_squashed_commits() -> eyre::Result<()> {
                 "master",
             ])?;
             insta::assert_snapshot!(stderr, @r###"
-            Executing: git branchless hook-register-extra-post-rewrite-hook
-            branchless: processing 1 update: ref HEAD
-            branchless: processing 1 update: ref HEAD
-            branchless: processed commit: e7bcdd60 create test1.txt
-            Executing: git branchless hook-detect-empty-commit 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
-            branchless: processing 1 update: ref HEAD
-            branchless: processed commit: 12d361aa create test2.txt
-            Executing: git branchless hook-detect-empty-commit 96d1c37a3d4363611c49f7e52186e189a04c531f
-            branchless: processing 1 update: branch master
-            branchless: processing 4 rewritten commits
-            branchless: running command: <git-executable> checkout de4a1fe8f80b830d7d9a5b4adfd79fab3fcdc80c
-            branchless: processing 1 update: ref HEAD
-            HEAD is now at de4a1fe squashed test1 and test2
-            branchless: processing checkout
-            :
-            @ de4a1fe8 (master) squashed test1 and test2
-            Successfully rebased and updated refs/heads/master.
+            [git] Executing: git branchless hook-register-extra-post-rewrite-hook
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processed commit: e7bcdd60 create test1.txt
+            [git] Executing: git branchless hook-detect-empty-commit 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processed commit: 12d361aa create test2.txt
+            [git] Executing: git branchless hook-detect-empty-commit 96d1c37a3d4363611c49f7e52186e189a04c531f
+            [git] branchless: processing 1 update: branch master
+            [git] branchless: processing 4 rewritten commits
+            [git] Successfully rebased and updated refs/heads/master.
             "###);
             insta::assert_snapshot!(stdout, @r###"
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        Skipped now-empty commit: e7bcdd60 create test1.txt
-        Skipped now-empty commit: 12d361aa create test2.txt
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] Skipped now-empty commit: e7bcdd60 create test1.txt
+        [git] Skipped now-empty commit: 12d361aa create test2.txt
         "###);
         }
 
@@ -1333,16 +1517,12 @@ fn test_move_no_reapply_squashed_commits() -> eyre::Result<()> {
                 "-d",
                 "master",
             ])?;
-            insta::assert_snapshot!(stderr, @r###"
-        Switched to branch 'master'
-        branchless: processing checkout
-        "###);
+            insta::assert_snapshot!(stderr, @"");
             insta::assert_snapshot!(stdout, @r###"
             Attempting rebase in-memory...
             [1/2] Skipped now-empty commit: e7bcdd60 create test1.txt
             [2/2] Skipped now-empty commit: 12d361aa create test2.txt
             branchless: processing 2 rewritten commits
-            branchless: running command: <git-executable> checkout master
             :
             @ de4a1fe8 (master) squashed test1 and test2
             In-memory rebase succeeded.
@@ -1361,7 +1541,7 @@ fn test_move_no_reapply_squashed_commits() -> eyre::Result<()> {
 }
 
 #[test]
-fn test_move_delete_checked_out_branch() -> eyre::Result<()> {
+fn test_move_checked_out_branch_of_skipped_commit() -> eyre::Result<()> {
     let git = make_git()?;
 
     if !git.supports_reference_transactions()? {
@@ -1405,43 +1585,42 @@ fn test_move_delete_checked_out_branch() -> eyre::Result<()> {
             git.run(&["checkout", "work"])?;
             let (stdout, stderr) = git.run(&["move", "--on-disk", "-b", "HEAD", "-d", "master"])?;
             insta::assert_snapshot!(stderr, @r###"
-            Executing: git branchless hook-register-extra-post-rewrite-hook
-            branchless: processing 1 update: ref HEAD
-            Executing: git branchless hook-skip-upstream-applied-commit 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
-            Executing: git branchless hook-skip-upstream-applied-commit 96d1c37a3d4363611c49f7e52186e189a04c531f
-            branchless: processing 1 update: ref HEAD
-            branchless: processed commit: 012efd6e create test3.txt
-            Executing: git branchless hook-detect-empty-commit ffcba554683d83de283de084a7d3896e332bbcdb
-            branchless: processing 3 rewritten commits
-            branchless: processing 2 updates: branch more-work, branch work
-            branchless: running command: <git-executable> checkout 91c5ce63686889388daec1120bf57bea8a744bc2
-            Previous HEAD position was 012efd6 create test3.txt
-            branchless: processing 1 update: ref HEAD
-            HEAD is now at 91c5ce6 create test2.txt
-            branchless: processing checkout
-            :
-            @ 91c5ce63 (master) create test2.txt
-            |
-            o 012efd6e (more-work) create test3.txt
-            Successfully rebased and updated detached HEAD.
+            [git] Executing: git branchless hook-register-extra-post-rewrite-hook
+            [git] branchless: processing 1 update: ref HEAD
+            [git] Executing: git branchless hook-skip-upstream-applied-commit 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+            [git] Executing: git branchless hook-skip-upstream-applied-commit 96d1c37a3d4363611c49f7e52186e189a04c531f
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processed commit: 012efd6e create test3.txt
+            [git] Executing: git branchless hook-detect-empty-commit ffcba554683d83de283de084a7d3896e332bbcdb
+            [git] branchless: processing 3 rewritten commits
+            [git] branchless: processing 2 updates: branch more-work, branch work
+            [git] branchless: running command: <git-executable> checkout work
+            [git] Previous HEAD position was 012efd6 create test3.txt
+            [git] Switched to branch 'work'
+            [git] branchless: processing checkout
+            [git] :
+            [git] @ 91c5ce63 (master, work) create test2.txt
+            [git] |
+            [git] o 012efd6e (more-work) create test3.txt
+            [git] Successfully rebased and updated detached HEAD.
             "###);
             insta::assert_snapshot!(stdout, @r###"
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        Skipping commit (was already applied upstream): 62fc20d2 create test1.txt
-        Skipping commit (was already applied upstream): 96d1c37a create test2.txt
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] Skipping commit (was already applied upstream): 62fc20d2 create test1.txt
+        [git] Skipping commit (was already applied upstream): 96d1c37a create test2.txt
         "###);
         }
 
         {
             let (stdout, _stderr) = git.run(&["smartlog"])?;
             insta::assert_snapshot!(stdout, @r###"
-        :
-        @ 91c5ce63 (master) create test2.txt
-        |
-        o 012efd6e (more-work) create test3.txt
-        "###);
+            :
+            @ 91c5ce63 (master, work) create test2.txt
+            |
+            o 012efd6e (more-work) create test3.txt
+            "###);
         }
     }
     // --in-memory
@@ -1451,11 +1630,10 @@ fn test_move_delete_checked_out_branch() -> eyre::Result<()> {
             let (stdout, stderr) =
                 git.run(&["move", "--in-memory", "-b", "HEAD", "-d", "master"])?;
             insta::assert_snapshot!(stderr, @r###"
-        Previous HEAD position was 96d1c37 create test2.txt
-        branchless: processing 1 update: ref HEAD
-        HEAD is now at 91c5ce6 create test2.txt
-        branchless: processing checkout
-        "###);
+            Previous HEAD position was 96d1c37 create test2.txt
+            Switched to branch 'work'
+            branchless: processing checkout
+            "###);
             insta::assert_snapshot!(stdout, @r###"
             Attempting rebase in-memory...
             [1/3] Skipped commit (was already applied upstream): 62fc20d2 create test1.txt
@@ -1463,9 +1641,9 @@ fn test_move_delete_checked_out_branch() -> eyre::Result<()> {
             [3/3] Committed as: 012efd6e create test3.txt
             branchless: processing 2 updates: branch more-work, branch work
             branchless: processing 3 rewritten commits
-            branchless: running command: <git-executable> checkout 91c5ce63686889388daec1120bf57bea8a744bc2
+            branchless: running command: <git-executable> checkout work
             :
-            @ 91c5ce63 (master) create test2.txt
+            @ 91c5ce63 (master, work) create test2.txt
             |
             o 012efd6e (more-work) create test3.txt
             In-memory rebase succeeded.
@@ -1475,11 +1653,11 @@ fn test_move_delete_checked_out_branch() -> eyre::Result<()> {
         {
             let (stdout, _stderr) = git.run(&["smartlog"])?;
             insta::assert_snapshot!(stdout, @r###"
-        :
-        @ 91c5ce63 (master) create test2.txt
-        |
-        o 012efd6e (more-work) create test3.txt
-        "###);
+            :
+            @ 91c5ce63 (master, work) create test2.txt
+            |
+            o 012efd6e (more-work) create test3.txt
+            "###);
         }
     }
 
@@ -1509,6 +1687,7 @@ fn test_move_with_unstaged_changes() -> eyre::Result<()> {
         )?;
         insta::assert_snapshot!(stderr, @"");
         insta::assert_snapshot!(stdout, @r###"
+        Moving stack rooted at 4838e49b create test3.txt (1 commit).
         branchless: running command: <git-executable> diff --quiet
         This operation would modify the working copy, but you have uncommitted changes
         in your working copy which might be overwritten as a result.
@@ -1569,107 +1748,113 @@ fn test_move_merge_commit() -> eyre::Result<()> {
                 "master",
             ])?;
             insta::assert_snapshot!(stdout, @r###"
-        Rebase constraints before adding descendants: [
-            (
-                NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
-                [
-                    NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
-                ],
-            ),
-        ]
-        Rebase constraints after adding descendants: [
-            (
-                NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
-                [
+            Rebase constraints before adding descendants: [
+                (
+                    NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
+                    [
+                        NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
+                    ],
+                ),
+            ]
+            Rebase constraints after adding descendants: [
+                (
+                    NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
+                    [
+                        NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
+                    ],
+                ),
+                (
                     NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
-                ],
-            ),
-            (
-                NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
-                [
-                    NonZeroOid(28790c73f13f38ce0d3beb6cfeb2d818b32bcd09),
-                ],
-            ),
-        ]
-        Rebase plan: Some(
-            RebasePlan {
-                first_dest_oid: NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
-                commands: [
-                    RegisterExtraPostRewriteHook,
-                    Reset {
-                        target: Oid(
-                            NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
-                        ),
-                    },
-                    Pick {
-                        commit_oid: NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
-                    },
-                    DetectEmptyCommit {
-                        commit_oid: NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
-                    },
-                    CreateLabel {
-                        label_name: "merge-parent-4",
-                    },
-                    Merge {
-                        commit_oid: NonZeroOid(28790c73f13f38ce0d3beb6cfeb2d818b32bcd09),
-                        commits_to_merge: [
-                            Oid(
+                    [
+                        NonZeroOid(28790c73f13f38ce0d3beb6cfeb2d818b32bcd09),
+                    ],
+                ),
+            ]
+            Rebase plan: Some(
+                RebasePlan {
+                    first_dest_oid: NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
+                    commands: [
+                        RegisterExtraPostRewriteHook,
+                        Reset {
+                            target: Oid(
+                                NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
+                            ),
+                        },
+                        Pick {
+                            commit_oid: NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
+                        },
+                        DetectEmptyCommit {
+                            commit_oid: NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
+                        },
+                        CreateLabel {
+                            label_name: "merge-parent-4",
+                        },
+                        Reset {
+                            target: Oid(
                                 NonZeroOid(98b9119d16974f372e76cb64a3b77c528fc0b18b),
                             ),
-                        ],
-                    },
-                ],
-            },
-        )
-        branchless: running command: <git-executable> diff --quiet
-        Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        "###);
+                        },
+                        Merge {
+                            commit_oid: NonZeroOid(28790c73f13f38ce0d3beb6cfeb2d818b32bcd09),
+                            commits_to_merge: [
+                                Label(
+                                    "merge-parent-4",
+                                ),
+                            ],
+                        },
+                    ],
+                },
+            )
+            branchless: running command: <git-executable> diff --quiet
+            Calling Git for on-disk rebase...
+            branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+            "###);
             insta::assert_snapshot!(stderr, @r###"
-            Executing: git branchless hook-register-extra-post-rewrite-hook
-            branchless: processing 1 update: ref HEAD
-            branchless: processing 1 update: ref HEAD
-            branchless: processed commit: 96d1c37a create test2.txt
-            Executing: git branchless hook-detect-empty-commit fe65c1fe15584744e649b2c79d4cf9b0d878f92e
-            branchless: processing 1 update: ref refs/rewritten/merge-parent-4
-            branchless: processing 2 rewritten commits
-            branchless: running command: <git-executable> checkout 98b9119d16974f372e76cb64a3b77c528fc0b18b
-            Previous HEAD position was 96a2c4b Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-            branchless: processing 1 update: ref HEAD
-            HEAD is now at 98b9119 create test3.txt
-            branchless: processing checkout
+            [git] Executing: git branchless hook-register-extra-post-rewrite-hook
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processed commit: 96d1c37a create test2.txt
+            [git] Executing: git branchless hook-detect-empty-commit fe65c1fe15584744e649b2c79d4cf9b0d878f92e
+            [git] branchless: processing 1 update: ref refs/rewritten/merge-parent-4
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processing 2 rewritten commits
+            [git] branchless: running command: <git-executable> checkout 98b9119d16974f372e76cb64a3b77c528fc0b18b
+            [git] Previous HEAD position was 5a6a761 Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
+            [git] branchless: processing 1 update: ref HEAD
+            [git] HEAD is now at 98b9119 create test3.txt
+            [git] branchless: processing checkout
+            [git] O f777ecc9 create initial.txt
+            [git] |\
+            [git] | @ 98b9119d create test3.txt
+            [git] | |
+            [git] | o 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
+            [git] |
+            [git] O 62fc20d2 (master) create test1.txt
+            [git] |
+            [git] o 96d1c37a create test2.txt
+            [git] |
+            [git] o 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
+            [git] Successfully rebased and updated detached HEAD.
+            [git] branchless: processing 1 update: ref refs/rewritten/merge-parent-4
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
             O f777ecc9 create initial.txt
             |\
             | @ 98b9119d create test3.txt
             | |
-            | o 96a2c4be Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
+            | o 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
             |
             O 62fc20d2 (master) create test1.txt
             |
             o 96d1c37a create test2.txt
             |
-            o 96a2c4be Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-            Successfully rebased and updated detached HEAD.
-            branchless: processing 1 update: ref refs/rewritten/merge-parent-4
+            o 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
             "###);
         }
-
-        {
-            let (stdout, _stderr) = git.run(&["smartlog"])?;
-            insta::assert_snapshot!(stdout, @r###"
-        O f777ecc9 create initial.txt
-        |\
-        | @ 98b9119d create test3.txt
-        | |
-        | o 96a2c4be Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-        |
-        O 62fc20d2 (master) create test1.txt
-        |
-        o 96d1c37a create test2.txt
-        |
-        o 96a2c4be Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-        "###);
-        }
     }
 
     // --in-memory
@@ -1677,26 +1862,38 @@ fn test_move_merge_commit() -> eyre::Result<()> {
         let git = git.duplicate_repo()?;
 
         {
-            let (stdout, _stderr) = git.run_with_options(
-                &[
-                    "move",
-                    "--in-memory",
-                    "-s",
-                    &test2_oid.to_string(),
-                    "-d",
-                    "master",
-                ],
-                &GitRunOptions {
-                    expected_exit_code: 1,
-                    ..Default::default()
-                },
-            )?;
+            let (stdout, _stderr) = git.run(&[
+                "move",
+                "--in-memory",
+                "-s",
+                &test2_oid.to_string(),
+                "-d",
+                "master",
+            ])?;
             insta::assert_snapshot!(stdout, @r###"
-        Attempting rebase in-memory...
-        Merge commits currently can't be rebased in-memory.
-        The merge commit was: 28790c73 Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-        Aborting since an in-memory rebase was requested.
-        "###);
+            Attempting rebase in-memory...
+            [1/2] Committed as: 96d1c37a create test2.txt
+            [2/2] Committed as: 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
+            branchless: processing 2 rewritten commits
+            In-memory rebase succeeded.
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 create initial.txt
+            |\
+            | @ 98b9119d create test3.txt
+            | |
+            | o 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
+            |
+            O 62fc20d2 (master) create test1.txt
+            |
+            o 96d1c37a create test2.txt
+            |
+            o 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
+            "###);
         }
     }
 
@@ -1706,59 +1903,31 @@ fn test_move_merge_commit() -> eyre::Result<()> {
             let (stdout, stderr) =
                 git.run(&["move", "-s", &test2_oid.to_string(), "-d", "master"])?;
             insta::assert_snapshot!(stdout, @r###"
-        Attempting rebase in-memory...
-        Merge commits currently can't be rebased in-memory.
-        The merge commit was: 28790c73 Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-        Trying again on-disk...
-        branchless: running command: <git-executable> diff --quiet
-        Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        "###);
-            insta::assert_snapshot!(stderr, @r###"
-            Executing: git branchless hook-register-extra-post-rewrite-hook
-            branchless: processing 1 update: ref HEAD
-            branchless: processing 1 update: ref HEAD
-            branchless: processed commit: 96d1c37a create test2.txt
-            Executing: git branchless hook-detect-empty-commit fe65c1fe15584744e649b2c79d4cf9b0d878f92e
-            branchless: processing 1 update: ref refs/rewritten/merge-parent-4
+            Attempting rebase in-memory...
+            [1/2] Committed as: 96d1c37a create test2.txt
+            [2/2] Committed as: 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
             branchless: processing 2 rewritten commits
-            branchless: running command: <git-executable> checkout 98b9119d16974f372e76cb64a3b77c528fc0b18b
-            Previous HEAD position was 96a2c4b Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-            branchless: processing 1 update: ref HEAD
-            HEAD is now at 98b9119 create test3.txt
-            branchless: processing checkout
+            In-memory rebase succeeded.
+            "###);
+            insta::assert_snapshot!(stderr, @"");
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
             O f777ecc9 create initial.txt
             |\
             | @ 98b9119d create test3.txt
             | |
-            | o 96a2c4be Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
+            | o 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
             |
             O 62fc20d2 (master) create test1.txt
             |
             o 96d1c37a create test2.txt
             |
-            o 96a2c4be Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-            Successfully rebased and updated detached HEAD.
-            branchless: processing 1 update: ref refs/rewritten/merge-parent-4
+            o 5a6a761e Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
             "###);
         }
-
-        {
-            let (stdout, _stderr) = git.run(&["smartlog"])?;
-            insta::assert_snapshot!(stdout, @r###"
-        O f777ecc9 create initial.txt
-        |\
-        | @ 98b9119d create test3.txt
-        | |
-        | o 96a2c4be Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-        |
-        O 62fc20d2 (master) create test1.txt
-        |
-        o 96d1c37a create test2.txt
-        |
-        o 96a2c4be Merge commit 'fe65c1fe15584744e649b2c79d4cf9b0d878f92e' into HEAD
-        "###);
-        }
     }
 
     Ok(())
@@ -1808,31 +1977,24 @@ fn test_move_orphaned_root() -> eyre::Result<()> {
         {
             let (stdout, stderr) = git.run(&["move", "--on-disk", "-d", "master"])?;
             insta::assert_snapshot!(stderr, @r###"
-            Executing: git branchless hook-register-extra-post-rewrite-hook
-            branchless: processing 1 update: ref HEAD
-            branchless: processing 1 update: ref HEAD
-            branchless: processed commit: 270b681e new root
-            Executing: git branchless hook-detect-empty-commit da90168b4835f97f1a10bcc12833140056df9157
-            branchless: processing 1 update: ref HEAD
-            branchless: processed commit: 70deb1e2 create test3.txt
-            Executing: git branchless hook-detect-empty-commit fc09f3d9f0b7370dc38e761e3730a856dc5025c2
-            branchless: processing 1 update: branch new-root
-            branchless: processing 3 rewritten commits
-            branchless: running command: <git-executable> checkout 70deb1e28791d8e7dd5a1f0c871a51b91282562f
-            branchless: processing 1 update: ref HEAD
-            HEAD is now at 70deb1e create test3.txt
-            branchless: processing checkout
-            :
-            O 96d1c37a (master) create test2.txt
-            |
-            @ 70deb1e2 (new-root) create test3.txt
-            Successfully rebased and updated refs/heads/new-root.
+            [git] Executing: git branchless hook-register-extra-post-rewrite-hook
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processed commit: 270b681e new root
+            [git] Executing: git branchless hook-detect-empty-commit da90168b4835f97f1a10bcc12833140056df9157
+            [git] branchless: processing 1 update: ref HEAD
+            [git] branchless: processed commit: 70deb1e2 create test3.txt
+            [git] Executing: git branchless hook-detect-empty-commit fc09f3d9f0b7370dc38e761e3730a856dc5025c2
+            [git] branchless: processing 1 update: branch new-root
+            [git] branchless: processing 3 rewritten commits
+            [git] Successfully rebased and updated refs/heads/new-root.
             "###);
             insta::assert_snapshot!(stdout, @r###"
+        Moving stack rooted at da90168b new root (2 commits).
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        Skipped now-empty commit: 270b681e new root
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] Skipped now-empty commit: 270b681e new root
         "###);
         }
 
@@ -1857,6 +2019,7 @@ fn test_move_orphaned_root() -> eyre::Result<()> {
         branchless: processing checkout
         "###);
             insta::assert_snapshot!(stdout, @r###"
+            Moving stack rooted at da90168b new root (2 commits).
             Attempting rebase in-memory...
             [1/2] Skipped now-empty commit: 270b681e new root
             [2/2] Committed as: 70deb1e2 create test3.txt
@@ -1898,10 +2061,7 @@ fn test_move_no_extra_checkout() -> eyre::Result<()> {
     {
         let (stdout, _stderr) = git.run(&["move", "--in-memory", "-s", "HEAD", "-d", "HEAD^"])?;
         insta::assert_snapshot!(stdout, @r###"
-        Attempting rebase in-memory...
-        [1/1] Committed as: 96d1c37a create test2.txt
-        branchless: processing 1 rewritten commit
-        In-memory rebase succeeded.
+        96d1c37a create test2.txt is already a child of 62fc20d2 create test1.txt, so there is nothing to move.
         "###);
     }
 
@@ -1909,11 +2069,7 @@ fn test_move_no_extra_checkout() -> eyre::Result<()> {
         git.run(&["branch", "foo"])?;
         let (stdout, _stderr) = git.run(&["move", "--in-memory", "-s", "HEAD", "-d", "HEAD^"])?;
         insta::assert_snapshot!(stdout, @r###"
-        Attempting rebase in-memory...
-        [1/1] Committed as: 96d1c37a create test2.txt
-        branchless: processing 1 update: branch foo
-        branchless: processing 1 rewritten commit
-        In-memory rebase succeeded.
+        96d1c37a create test2.txt is already a child of 62fc20d2 create test1.txt, so there is nothing to move.
         "###);
     }
 
@@ -1946,11 +2102,12 @@ fn test_move_dest_not_in_dag() -> eyre::Result<()> {
         })?;
         cloned_repo.run(&["branchless", "init", "--main-branch", "other-branch"])?;
 
-        let (stdout, _stderr) = cloned_repo.run(&["move", "-d", "origin/master"])?;
+        let (stdout, _stderr) =
+            cloned_repo.run(&["move", "--force-rewrite-public", "-d", "origin/master"])?;
         insta::assert_snapshot!(stdout, @r###"
         Attempting rebase in-memory...
         [1/1] Committed as: 70deb1e2 create test3.txt
-        branchless: processing 2 updates: branch other-branch, remote branch origin/other-branch
+        branchless: processing 1 update: branch other-branch
         branchless: processing 1 rewritten commit
         branchless: running command: <git-executable> checkout other-branch
         Your branch is up to date with 'origin/other-branch'.
@@ -1988,10 +2145,12 @@ fn test_move_abort_rebase_check_out_old_branch() -> eyre::Result<()> {
         let stdout = remove_rebase_lines(stdout);
 
         insta::assert_snapshot!(stdout, @r###"
+        Moving stack rooted at 8034a0cf create test2.txt (1 commit).
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        CONFLICT (add/add): Merge conflict in test2.txt
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] CONFLICT (add/add): Merge conflict in test2.txt
+        After resolving the merge conflicts, run `git move --continue` to finish moving commits, or run `git move --abort` to cancel.
         "###);
     }
 
@@ -2017,7 +2176,7 @@ fn test_move_orig_head_no_symbolic_reference() -> eyre::Result<()> {
 
     git.run(&["checkout", "-b", "foo"])?;
     // Force `ORIG_HEAD` to be written to disk.
-    git.run(&["move", "-d", "HEAD^", "--on-disk"])?;
+    git.run(&["move", "-b", "HEAD", "-d", "HEAD^", "--on-disk"])?;
     git.detach_head()?;
 
     {
@@ -2079,3 +2238,1667 @@ fn test_move_standalone_no_create_gc_refs() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_move_insert() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "bar"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["branch", "baz"])?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test4_oid = git.commit_file("test4", 4)?;
+    git.run(&["branch", "qux"])?;
+
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&[
+            "move",
+            "--insert",
+            "-s",
+            &test4_oid.to_string(),
+            "-d",
+            &test1_oid.to_string(),
+        ])?;
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 (foo) create test1.txt
+        |
+        @ bf0d52a6 (qux) create test4.txt
+        |
+        o 44352d00 (bar) create test2.txt
+        |
+        o cf5eb244 (baz) create test3.txt
+        "###);
+    }
+
+    // The source commit is already a descendant of the destination, so
+    // inserting it reorders the stack.
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["move", "--insert", "-s", "baz", "-d", "foo"])?;
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 (foo) create test1.txt
+        | |
+        | o 4838e49b (baz) create test3.txt
+        | |
+        | o d742fb97 (bar) create test2.txt
+        |
+        @ 8f7aef57 (qux) create test4.txt
+        "###);
+
+        let (stdout, _stderr) = git.run(&["log", "--format=%s", "master..baz"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test3.txt
+        create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_exact() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["branch", "foo"])?;
+    let test4_oid = git.commit_file("test4", 4)?;
+    git.run(&["branch", "bar"])?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["move", "-x", &test3_oid.to_string(), "-d", "master"])?;
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 96d1c37a create test2.txt
+        | |
+        | o f57e36f5 (bar) create test4.txt
+        |
+        o 98b9119d (foo) create test3.txt
+        "###);
+    }
+
+    // A contiguous range is moved together, preserving its order.
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&[
+            "move",
+            "-x",
+            &test2_oid.to_string(),
+            "-x",
+            &test3_oid.to_string(),
+            "-d",
+            "master",
+        ])?;
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o bf0d52a6 (bar) create test4.txt
+        |
+        o fe65c1fe create test2.txt
+        |
+        o 02067177 (foo) create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &[
+                "move",
+                "-x",
+                &test2_oid.to_string(),
+                "-x",
+                &test4_oid.to_string(),
+                "-d",
+                "master",
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        The commits passed to --exact must form a contiguous range of commits. Move each range separately instead.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_in_memory_leaves_working_copy_untouched() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    for i in 2..=10 {
+        git.commit_file(&format!("test{}", i), i)?;
+    }
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test11", 11)?;
+
+    let get_mtimes = || -> eyre::Result<Vec<(std::ffi::OsString, std::time::SystemTime)>> {
+        let mut mtimes = Vec::new();
+        for entry in std::fs::read_dir(&git.repo_path)? {
+            let entry = entry?;
+            if entry.file_name() != ".git" {
+                mtimes.push((entry.file_name(), entry.metadata()?.modified()?));
+            }
+        }
+        mtimes.sort();
+        Ok(mtimes)
+    };
+    let mtimes_before = get_mtimes()?;
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "move",
+            "--in-memory",
+            "-s",
+            &test1_oid.to_string(),
+            "-d",
+            "master",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/10] Committed as: da5143ce create test1.txt
+        [2/10] Committed as: 8af2a4e5 create test2.txt
+        [3/10] Committed as: fc5026f8 create test3.txt
+        [4/10] Committed as: 3589c1ea create test4.txt
+        [5/10] Committed as: 0f5f84a5 create test5.txt
+        [6/10] Committed as: 01907986 create test6.txt
+        [7/10] Committed as: fdceca85 create test7.txt
+        [8/10] Committed as: 4f6b4668 create test8.txt
+        [9/10] Committed as: e539b9e2 create test9.txt
+        [10/10] Committed as: 26a30d6f create test10.txt
+        branchless: processing 10 rewritten commits
+        :
+        @ fef3bcda (master) create test11.txt
+        |
+        o da5143ce create test1.txt
+        |
+        o 8af2a4e5 create test2.txt
+        |
+        o fc5026f8 create test3.txt
+        |
+        o 3589c1ea create test4.txt
+        |
+        o 0f5f84a5 create test5.txt
+        |
+        o 01907986 create test6.txt
+        |
+        o fdceca85 create test7.txt
+        |
+        o 4f6b4668 create test8.txt
+        |
+        o e539b9e2 create test9.txt
+        |
+        o 26a30d6f create test10.txt
+        In-memory rebase succeeded.
+        "###);
+    }
+
+    assert_eq!(get_mtimes()?, mtimes_before);
+    {
+        let (stdout, _stderr) = git.run(&["symbolic-ref", "HEAD"])?;
+        assert_eq!(stdout, "refs/heads/master\n");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_merge_continue_and_abort() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    let base_oid = git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file_with_contents("conflict", 3, "conflict 1\n")?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["checkout", &base_oid.to_string()])?;
+    git.commit_file_with_contents("conflict", 3, "conflict 2\n")?;
+
+    {
+        let git = git.duplicate_repo()?;
+        git.run_with_options(
+            &[
+                "move",
+                "--merge",
+                "--source",
+                &test2_oid.to_string(),
+                "-d",
+                ".",
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+
+        let (stdout, _stderr) = git.run(&["move", "--abort"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --abort
+        "###);
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        | |
+        | o c6f40a7e (foo) create conflict.txt
+        |
+        @ 60f7e990 create conflict.txt
+        "###);
+    }
+
+    git.run_with_options(
+        &[
+            "move",
+            "--merge",
+            "--source",
+            &test2_oid.to_string(),
+            "-d",
+            ".",
+        ],
+        &GitRunOptions {
+            expected_exit_code: 1,
+            ..Default::default()
+        },
+    )?;
+    git.resolve_file("conflict", "resolved")?;
+    {
+        let (stdout, _stderr) = git.run(&["move", "--continue"])?;
+        let stdout = remove_rebase_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] [detached HEAD 8b96d11] create conflict.txt
+        [git]  1 file changed, 1 insertion(+), 1 deletion(-)
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        @ 60f7e990 create conflict.txt
+        |
+        o 25e9ec10 create test2.txt
+        |
+        o 8b96d11e (foo) create conflict.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git move --merge --source 96d1c37a3d4363611c49f7e52186e189a04c531f -d . (transaction 13)
+        Will apply these actions:
+        1. Check out from 60f7e990 create conflict.txt
+                       to 60f7e990 create conflict.txt
+        2. Move branch foo from 8b96d11e create conflict.txt
+                             to c6f40a7e create conflict.txt
+        3. Rewrite commit 8b96d11e create conflict.txt
+                      as c6f40a7e create conflict.txt
+        4. Rewrite commit 25e9ec10 create test2.txt
+                      as 96d1c37a create test2.txt
+        5. Hide commit 8b96d11e create conflict.txt
+
+        6. Hide commit 25e9ec10 create test2.txt
+
+        branchless: running command: <git-executable> checkout 60f7e990f194ccbe443d099e3cedea9d7ee99cf5 --detach
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        @ 60f7e990 create conflict.txt
+        |
+        o 25e9ec10 create test2.txt
+        |
+        o 8b96d11e (foo) create conflict.txt
+        Applied 6 inverse events.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        | |
+        | o c6f40a7e (foo) create conflict.txt
+        |
+        @ 60f7e990 create conflict.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_abort_hides_rewritten_commits() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    let base_oid = git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file_with_contents("conflict", 3, "conflict 1\n")?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["checkout", &base_oid.to_string()])?;
+    git.commit_file_with_contents("conflict", 3, "conflict 2\n")?;
+
+    git.run_with_options(
+        &[
+            "move",
+            "--merge",
+            "--source",
+            &test2_oid.to_string(),
+            "-d",
+            ".",
+        ],
+        &GitRunOptions {
+            expected_exit_code: 1,
+            ..Default::default()
+        },
+    )?;
+
+    {
+        let (_stdout, stderr) = git.run_with_options(
+            &["move", "--continue", "--abort"],
+            &GitRunOptions {
+                expected_exit_code: 2,
+                ..Default::default()
+            },
+        )?;
+        assert!(stderr.contains("cannot be used with"), "stderr: {}", stderr);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["move", "--abort"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --abort
+        "###);
+    }
+
+    // The copy of `test2` which was committed before the rebase stopped is
+    // hidden again.
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        | |
+        | o c6f40a7e (foo) create conflict.txt
+        |
+        @ 60f7e990 create conflict.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git move --merge --source 96d1c37a3d4363611c49f7e52186e189a04c531f -d . (transaction 13)
+        Will apply these actions:
+        1. Check out from 60f7e990 create conflict.txt
+                       to 60f7e990 create conflict.txt
+        2. Unhide commit 25e9ec10 create test2.txt
+
+        3. Hide commit 25e9ec10 create test2.txt
+
+        branchless: running command: <git-executable> checkout 60f7e990f194ccbe443d099e3cedea9d7ee99cf5 --detach
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        | |
+        | o c6f40a7e (foo) create conflict.txt
+        |
+        @ 60f7e990 create conflict.txt
+        Applied 3 inverse events.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        | |
+        | o c6f40a7e (foo) create conflict.txt
+        |
+        @ 60f7e990 create conflict.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_multiple_sources() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&[
+            "move",
+            "-s",
+            &test1_oid.to_string(),
+            "-s",
+            &test3_oid.to_string(),
+            "-d",
+            "master",
+        ])?;
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 8f7aef57 (master) create test4.txt
+        |\
+        | o 6c398dae create test1.txt
+        | |
+        | o d166405f create test2.txt
+        |
+        o 9799dd98 create test3.txt
+        "###);
+
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git move -s 62fc20d2a290daea0d52bdc2ed2ad4be6491010e -s 98b9119d16974f372e76cb64a3b77c528fc0b18b -d master (transaction 13)
+        Will apply these actions:
+        1. Rewrite commit 9799dd98 create test3.txt
+                      as 98b9119d create test3.txt
+        2. Rewrite commit d166405f create test2.txt
+                      as 96d1c37a create test2.txt
+        3. Rewrite commit 6c398dae create test1.txt
+                      as 62fc20d2 create test1.txt
+        Applied 3 inverse events.
+        "###);
+    }
+
+    // Nested sources are only moved once, along with their ancestor source.
+    {
+        let git = git.duplicate_repo()?;
+        let (stdout, _stderr) = git.run(&[
+            "move",
+            "--debug-dump-rebase-constraints",
+            "-s",
+            &test1_oid.to_string(),
+            "-s",
+            &test2_oid.to_string(),
+            "-d",
+            "master",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Rebase constraints before adding descendants: [
+            (
+                NonZeroOid(8f7aef57d66466a6e0737ae10f67cd98ddecdc66),
+                [
+                    NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
+                ],
+            ),
+        ]
+        Rebase constraints after adding descendants: [
+            (
+                NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
+                [
+                    NonZeroOid(96d1c37a3d4363611c49f7e52186e189a04c531f),
+                ],
+            ),
+            (
+                NonZeroOid(8f7aef57d66466a6e0737ae10f67cd98ddecdc66),
+                [
+                    NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
+                ],
+            ),
+        ]
+        Attempting rebase in-memory...
+        [1/2] Committed as: 6c398dae create test1.txt
+        [2/2] Committed as: d166405f create test2.txt
+        branchless: processing 2 rewritten commits
+        O f777ecc9 create initial.txt
+        |\
+        | o 98b9119d create test3.txt
+        |
+        @ 8f7aef57 (master) create test4.txt
+        |
+        o 6c398dae create test1.txt
+        |
+        o d166405f create test2.txt
+        In-memory rebase succeeded.
+        "###);
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 create initial.txt
+        |\
+        | o 98b9119d create test3.txt
+        |
+        @ 8f7aef57 (master) create test4.txt
+        |
+        o 6c398dae create test1.txt
+        |
+        o d166405f create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_branch_tipped_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "bar"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "foo"])?;
+
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["move", "--in-memory", "-b", "foo", "-d", "master"])?;
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 98b9119d (master) create test3.txt
+        |
+        o 4b9ce31b (bar) create test1.txt
+        |
+        @ 9f77bc5f (foo) create test2.txt
+        "###);
+        let (stdout, _stderr) = git.run(&["symbolic-ref", "HEAD"])?;
+        assert_eq!(stdout, "refs/heads/foo\n");
+    }
+
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["move", "--on-disk", "-b", "foo", "-d", "master"])?;
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 98b9119d (master) create test3.txt
+        |
+        o 4b9ce31b (bar) create test1.txt
+        |
+        @ 9f77bc5f (foo) create test2.txt
+        "###);
+        let (stdout, _stderr) = git.run(&["symbolic-ref", "HEAD"])?;
+        assert_eq!(stdout, "refs/heads/foo\n");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_dry_run() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+
+    let (refs_before, _stderr) = git.run(&["show-ref", "--head"])?;
+    {
+        let (stdout, _stderr) = git.run(&[
+            "move",
+            "--dry-run",
+            "-s",
+            &test1_oid.to_string(),
+            "-d",
+            "master",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        This operation would rewrite 3 commits:
+        8f7aef57 create test4.txt
+          - 62fc20d2 create test1.txt
+            - 4838e49b create test3.txt
+            - 96d1c37a create test2.txt
+        This operation would move 1 branch: foo
+        This operation would check out the rewritten version of HEAD: 62fc20d2 create test1.txt
+        This was a dry run, so no changes were made.
+        "###);
+    }
+    let (refs_after, _stderr) = git.run(&["show-ref", "--head"])?;
+    assert_eq!(refs_before, refs_after);
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &[
+                "move",
+                "--dry-run",
+                "-s",
+                "master",
+                "-d",
+                &test1_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        This operation would rewrite 1 commit:
+        62fc20d2 create test1.txt
+          - 8f7aef57 create test4.txt
+        This operation would move 1 branch: master
+        This operation would encounter problems:
+        - The commit is on the main branch, but would be rewritten: 8f7aef57 create test4.txt
+        This was a dry run, so no changes were made.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_onto_self() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &[
+                "move",
+                "-s",
+                &test1_oid.to_string(),
+                "-d",
+                &test1_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Cannot move 62fc20d2 create test1.txt onto 62fc20d2 create test1.txt, because the destination is the same as the source.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["move", "-s", &test1_oid.to_string(), "-d", "HEAD"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Cannot move 62fc20d2 create test1.txt onto 96d1c37a create test2.txt, because the destination is a descendant of the source.
+        To move only the source commit, and not its descendants, use the --exact option.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_json_output() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    let describe_records = |stdout: &str| -> eyre::Result<String> {
+        let mut lines = Vec::new();
+        for line in stdout.lines() {
+            let record: serde_json::Value = serde_json::from_str(line)?;
+            let line = match record["type"].as_str() {
+                Some("operation_start") => format!("start {}", record["operation"]),
+                Some("operation_end") => format!("end {}", record["operation"]),
+                Some("progress") => format!(
+                    "progress {} {}/{}",
+                    record["operation"], record["current"], record["total"]
+                ),
+                Some("status") => format!("status {} {}", record["operation"], record["status"]),
+                Some("message") => format!("message {} {}", record["level"], record["text"]),
+                _ => record.to_string(),
+            };
+            lines.push(line);
+        }
+        Ok(lines.join("\n"))
+    };
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "move",
+            "--output=json",
+            "--in-memory",
+            "-b",
+            "foo",
+            "-d",
+            "master",
+        ])?;
+        insta::assert_snapshot!(describe_records(&stdout)?, @r###"
+        start "process_events"
+        end "process_events"
+        start "update_commit_graph"
+        end "update_commit_graph"
+        start "update_commit_graph"
+        end "update_commit_graph"
+        start "get_merge_base"
+        end "get_merge_base"
+        start "build_rebase_plan"
+        start "check_for_cycles"
+        end "check_for_cycles"
+        start "detect_duplicate_commits"
+        start "filter_commits"
+        start "filter_by_touched_paths"
+        progress "filter_by_touched_paths" 0/2
+        progress "filter_by_touched_paths" 1/2
+        progress "filter_by_touched_paths" 2/2
+        end "filter_by_touched_paths"
+        end "filter_commits"
+        start "get_upstream_patch_ids"
+        progress "get_upstream_patch_ids" 0/0
+        end "get_upstream_patch_ids"
+        end "detect_duplicate_commits"
+        end "build_rebase_plan"
+        message "info" "Attempting rebase in-memory..."
+        start "rebase_commits"
+        progress "rebase_commits" 0/1
+        progress "rebase_commits" 1/1
+        status "rebase_commits" "create test2.txt"
+        start "apply_patch"
+        end "apply_patch"
+        message "info" "[1/1] Committed as: d742fb97 create test2.txt"
+        end "rebase_commits"
+        start "update_branches"
+        message "info" "branchless: processing 1 update: branch foo"
+        end "update_branches"
+        message "info" "branchless: processing 1 rewritten commit"
+        start "process_events"
+        end "process_events"
+        start "update_commit_graph"
+        end "update_commit_graph"
+        start "make_graph"
+        start "walk_commits"
+        start "get_merge_base"
+        end "get_merge_base"
+        start "find_path_to_merge_base"
+        end "find_path_to_merge_base"
+        start "get_merge_base"
+        end "get_merge_base"
+        start "find_path_to_merge_base"
+        end "find_path_to_merge_base"
+        end "walk_commits"
+        end "make_graph"
+        message "info" ":"
+        message "info" "@ 4838e49b (master) create test3.txt"
+        message "info" "|"
+        message "info" "o d742fb97 (foo) create test2.txt"
+        message "info" "In-memory rebase succeeded."
+        {"exit_code":0,"head_oid":"4838e49b08954becdd17c0900c1179c2c654c627","moved_branches":[{"name":"refs/heads/foo","new_oid":"d742fb97c050479c1d3057d8de0f05190dd3a89f","old_oid":"96d1c37a3d4363611c49f7e52186e189a04c531f"}],"type":"result"}
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["move", "-b", "HEAD", "-d", "nonexistent"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert("BRANCHLESS_OUTPUT".to_string(), "json".to_string());
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(describe_records(&stdout)?, @r###"
+        start "process_events"
+        end "process_events"
+        start "update_commit_graph"
+        end "update_commit_graph"
+        message "info" "Commit not found: nonexistent"
+        {"exit_code":1,"head_oid":"4838e49b08954becdd17c0900c1179c2c654c627","type":"result"}
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_move_non_utf8_commit_message() -> eyre::Result<()> {
+    use os_str_bytes::OsStrBytes;
+    use std::os::unix::ffi::OsStrExt;
+
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.detach_head()?;
+
+    // Create commits whose messages aren't valid UTF-8: one which is
+    // explicitly Latin-1 encoded, and one without any encoding header.
+    let latin1_message: &[u8] = b"caf\xe9 latin-1\n\nbody \xe9\n";
+    let invalid_message: &[u8] = b"invalid \xff\xfe utf-8\n";
+    let message_path = git.repo_path.join("message.txt");
+    std::fs::write(&message_path, latin1_message)?;
+    let (tree, _stderr) = git.run(&["rev-parse", "HEAD^{tree}"])?;
+    let (latin1_oid, _stderr) = git.run(&[
+        "-c",
+        "i18n.commitEncoding=ISO-8859-1",
+        "commit-tree",
+        tree.trim(),
+        "-p",
+        "HEAD",
+        "-F",
+        message_path.to_str().unwrap(),
+    ])?;
+    // `git commit-tree` would reinterpret the invalid bytes as Latin-1, so
+    // write the commit object directly.
+    let mut invalid_commit = format!(
+        "tree {}\nparent {}\n\
+        author Testy McTestface <test@example.com> 0 +0000\n\
+        committer Testy McTestface <test@example.com> 0 +0000\n\n",
+        tree.trim(),
+        latin1_oid.trim(),
+    )
+    .into_bytes();
+    invalid_commit.extend_from_slice(invalid_message);
+    std::fs::write(&message_path, invalid_commit)?;
+    let (invalid_oid, _stderr) = git.run(&[
+        "hash-object",
+        "-t",
+        "commit",
+        "-w",
+        message_path.to_str().unwrap(),
+    ])?;
+    std::fs::remove_file(&message_path)?;
+    git.run(&["checkout", "--quiet", invalid_oid.trim()])?;
+
+    // A branch whose name isn't valid UTF-8 shouldn't break decorations.
+    std::fs::write(
+        git.repo_path
+            .join(".git")
+            .join("refs")
+            .join("heads")
+            .join(std::ffi::OsStr::from_bytes(b"caf\xe9")),
+        format!("{}\n", test2_oid),
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        |
+        O fe65c1fe (caf�, master) create test2.txt
+        |
+        o 2c1c2209 caf� latin-1
+        |
+        @ 868eea80 invalid �� utf-8
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "move",
+            "-s",
+            latin1_oid.trim(),
+            "-d",
+            &test1_oid.to_string(),
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 312a9e36 caf� latin-1
+        [2/2] Committed as: 16cbafc7 invalid �� utf-8
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout 16cbafc72629a0f531895a1c49c8fd9e74475430
+        O f777ecc9 create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 312a9e36 caf� latin-1
+        | |
+        | @ 16cbafc7 invalid �� utf-8
+        |
+        O fe65c1fe (caf�, master) create test2.txt
+        In-memory rebase succeeded.
+        "###);
+    }
+
+    let assert_message =
+        |oid: &str, expected_message: &[u8], expected_encoding: &str| -> eyre::Result<()> {
+            let repo = git.get_repo()?;
+            let commit = repo.find_commit_or_fail(oid.parse()?)?;
+            assert_eq!(
+                commit.get_message_raw()?.to_raw_bytes().as_ref(),
+                expected_message
+            );
+            let (encoding, _stderr) = git.run(&["log", "-1", "--format=%e", oid])?;
+            assert_eq!(encoding.trim(), expected_encoding);
+            Ok(())
+        };
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 312a9e36 caf� latin-1
+        | |
+        | @ 16cbafc7 invalid �� utf-8
+        |
+        O fe65c1fe (caf�, master) create test2.txt
+        "###);
+        let (moved_oid, _stderr) = git.run(&["rev-parse", "HEAD~1"])?;
+        assert_message(moved_oid.trim(), latin1_message, "ISO-8859-1")?;
+        let (restacked_oid, _stderr) = git.run(&["rev-parse", "HEAD"])?;
+        assert_message(restacked_oid.trim(), invalid_message, "")?;
+    }
+
+    {
+        git.write_file("test3", "contents")?;
+        git.run(&["add", "test3.txt"])?;
+        git.run(&["amend"])?;
+        let (amended_oid, _stderr) = git.run(&["rev-parse", "HEAD"])?;
+        assert_message(amended_oid.trim(), invalid_message, "")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_move_sign_commits() -> eyre::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+
+    // A stand-in for `gpg` which emits a fixed signature, and records the
+    // arguments and the buffer which it was asked to sign.
+    let gpg_program = git.repo_path.join(".git").join("dummy-gpg");
+    std::fs::write(
+        &gpg_program,
+        format!(
+            "#!/bin/sh\n\
+            echo \"$@\" >{dir}/gpg-args\n\
+            cat >{dir}/gpg-buffer\n\
+            echo '[GNUPG:] SIG_CREATED ' >&2\n\
+            echo '-----BEGIN PGP SIGNATURE-----'\n\
+            echo\n\
+            echo 'dummy signature'\n\
+            echo '-----END PGP SIGNATURE-----'\n",
+            dir = git.repo_path.join(".git").to_str().unwrap(),
+        ),
+    )?;
+    std::fs::set_permissions(&gpg_program, std::fs::Permissions::from_mode(0o755))?;
+    git.run(&["config", "commit.gpgsign", "true"])?;
+    git.run(&["config", "gpg.program", gpg_program.to_str().unwrap()])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["move", "-x", "HEAD^", "-d", &test1_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: b86e7220 create test2.txt
+        [2/2] Committed as: 662c301f create test3.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout 662c301f22f5cc0fa7bd071c1b4bc4b371d07f27
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o b86e7220 create test2.txt
+        |
+        @ 662c301f create test3.txt
+        In-memory rebase succeeded.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["cat-file", "commit", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        tree 63d8481a2e5d3ba04c7cd0a02605c9abbe7b60fd
+        parent f777ecc9b0db5ed372b2615695191a8a17f79f24
+        author Testy McTestface <test@example.com> 1603985696 -0300
+        committer Testy McTestface <test@example.com> 1603985696 -0300
+        gpgsig -----BEGIN PGP SIGNATURE-----
+         
+         dummy signature
+         -----END PGP SIGNATURE-----
+
+        create test3.txt
+        "###);
+        let gpg_args = std::fs::read_to_string(git.repo_path.join(".git").join("gpg-args"))?;
+        assert_eq!(
+            gpg_args,
+            "--status-fd=2 -bsau Testy McTestface <test@example.com>\n"
+        );
+
+        // The signature should cover the commit without its `gpgsig` header.
+        let gpg_buffer = std::fs::read_to_string(git.repo_path.join(".git").join("gpg-buffer"))?;
+        let unsigned_commit = stdout
+            .lines()
+            .filter(|line| !(line.starts_with("gpgsig ") || line.starts_with(' ')))
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        assert_eq!(gpg_buffer, unsigned_commit);
+    }
+
+    {
+        git.write_file("test3", "updated contents")?;
+        git.run(&["amend"])?;
+        let (stdout, _stderr) = git.run(&["cat-file", "commit", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        tree 5ad3acc39c1f248103e9859817964f782d75c969
+        parent f777ecc9b0db5ed372b2615695191a8a17f79f24
+        author Testy McTestface <test@example.com> 1603985696 -0300
+        committer Testy McTestface <test@example.com> 1603985696 -0300
+        gpgsig -----BEGIN PGP SIGNATURE-----
+         
+         dummy signature
+         -----END PGP SIGNATURE-----
+
+        create test3.txt
+        "###);
+    }
+
+    git.run(&["config", "branchless.rewrite.sign", "false"])?;
+    {
+        git.write_file("test3", "more updated contents")?;
+        git.run(&["amend"])?;
+        let (stdout, _stderr) = git.run(&["cat-file", "commit", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        tree 787edf860978b9a1783c29926e6cf8fdf53d4d75
+        parent f777ecc9b0db5ed372b2615695191a8a17f79f24
+        author Testy McTestface <test@example.com> 1603985696 -0300
+        committer Testy McTestface <test@example.com> 1603985696 -0300
+
+        create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_submodule_pointer() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    // These commits are referred to only by the gitlinks, and don't exist in
+    // the repository, as is typical for submodule commits.
+    let submodule_oid_1 = "1111111111111111111111111111111111111111";
+    let submodule_oid_2 = "2222222222222222222222222222222222222222";
+    let submodule_oid_3 = "3333333333333333333333333333333333333333";
+    let set_submodule_pointer = |oid: &str| -> eyre::Result<()> {
+        git.run(&[
+            "update-index",
+            "--add",
+            "--cacheinfo",
+            &format!("160000,{},sub", oid),
+        ])?;
+        Ok(())
+    };
+
+    git.init_repo()?;
+    std::fs::create_dir(git.repo_path.join("sub"))?;
+    set_submodule_pointer(submodule_oid_1)?;
+    git.run(&["commit", "-m", "add submodule"])?;
+    git.detach_head()?;
+    set_submodule_pointer(submodule_oid_2)?;
+    git.run(&["commit", "-m", "bump submodule"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["move", "--in-memory", "-b", "foo", "-d", "master"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 1baea2ed bump submodule
+        [2/2] Committed as: 09f59ebb create test1.txt
+        branchless: processing 1 update: branch foo
+        branchless: processing 2 rewritten commits
+        :
+        @ 5bfb494f (master) create test2.txt
+        |
+        o 1baea2ed bump submodule
+        |
+        o 09f59ebb (foo) create test1.txt
+        In-memory rebase succeeded.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["ls-tree", "foo^", "sub"])?;
+        insta::assert_snapshot!(stdout, @"160000 commit 2222222222222222222222222222222222222222	sub");
+    }
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+    git.detach_head()?;
+    {
+        let (stdout, _stderr) = git.run(&["move", "--on-disk", "-b", "foo", "-d", "master"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["ls-tree", "foo^", "sub"])?;
+        insta::assert_snapshot!(stdout, @"160000 commit 2222222222222222222222222222222222222222	sub");
+    }
+
+    // Both sides changed the submodule pointer differently.
+    git.run(&["checkout", "master"])?;
+    set_submodule_pointer(submodule_oid_3)?;
+    git.run(&["commit", "-m", "bump submodule differently"])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["move", "--in-memory", "-b", "foo", "-d", "master"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        There was a merge conflict, which currently can't be resolved when rebasing in-memory.
+        The conflicting commit was: 3b19c00a bump submodule
+        Aborting since an in-memory rebase was requested.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_move_on_disk_update_refs() -> eyre::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let git = make_git()?;
+
+    if !git.supports_rebase_update_refs()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    let base_oid = git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file_with_contents("conflict", 3, "conflict 1\n")?;
+    git.run(&["branch", "bar"])?;
+    git.run(&["checkout", &base_oid.to_string()])?;
+    git.commit_file_with_contents("conflict", 3, "conflict 2\n")?;
+
+    // Stand-ins for Git which report a different version, so that we can check
+    // whether the `update-ref` rebase command is used.
+    let make_git_shim = |version: &str| -> eyre::Result<String> {
+        let path = git.repo_path.join(".git").join(format!("git-{}", version));
+        std::fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\n\
+                for arg in \"$@\"; do\n\
+                    if [ \"$arg\" = version ]; then echo 'git version {version}'; exit 0; fi\n\
+                done\n\
+                exec {git} \"$@\"\n",
+                version = version,
+                git = git.path_to_git.to_str().unwrap(),
+            ),
+        )?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        Ok(path.to_str().unwrap().to_string())
+    };
+    let old_git_options = GitRunOptions {
+        expected_exit_code: 1,
+        env: HashMap::from([("PATH_TO_GIT".to_string(), make_git_shim("2.37.0")?)]),
+        ..Default::default()
+    };
+    let new_git_options = GitRunOptions {
+        expected_exit_code: 1,
+        env: HashMap::from([("PATH_TO_GIT".to_string(), make_git_shim("2.38.0")?)]),
+        ..Default::default()
+    };
+
+    {
+        let git = git.duplicate_repo()?;
+        git.run_with_options(
+            &[
+                "move",
+                "--on-disk",
+                "--source",
+                &test2_oid.to_string(),
+                "-d",
+                ".",
+            ],
+            &old_git_options,
+        )?;
+        let rebase_state_dir = git.repo_path.join(".git").join("rebase-merge");
+        let update_refs =
+            std::fs::read_to_string(rebase_state_dir.join("update-refs")).unwrap_or_default();
+        assert_eq!(update_refs, "");
+        let todo = std::fs::read_to_string(rebase_state_dir.join("git-rebase-todo"))?;
+        assert!(!todo.contains("update-ref"));
+    }
+
+    git.run_with_options(
+        &[
+            "move",
+            "--on-disk",
+            "--source",
+            &test2_oid.to_string(),
+            "-d",
+            ".",
+        ],
+        &new_git_options,
+    )?;
+    {
+        let rebase_state_dir = git.repo_path.join(".git").join("rebase-merge");
+        let update_refs = std::fs::read_to_string(rebase_state_dir.join("update-refs"))?;
+        insta::assert_snapshot!(update_refs, @r###"
+        refs/heads/bar
+        c6f40a7ec527352c1652855602c9d9fae7ff0b65
+        0000000000000000000000000000000000000000
+        refs/heads/foo
+        96d1c37a3d4363611c49f7e52186e189a04c531f
+        25e9ec1076a691d4e0b70ba896280005ce43d860
+        "###);
+        let todo = std::fs::read_to_string(rebase_state_dir.join("git-rebase-todo"))?;
+        insta::assert_snapshot!(todo, @r###"
+        exec git branchless hook-detect-empty-commit c6f40a7ec527352c1652855602c9d9fae7ff0b65
+        update-ref refs/heads/bar
+        "###);
+    }
+
+    git.resolve_file("conflict", "resolved")?;
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["move", "--continue"],
+            &GitRunOptions {
+                expected_exit_code: 0,
+                ..new_git_options
+            },
+        )?;
+        let stdout = remove_rebase_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <repo-path>/.git/git-2.38.0 -c submodule.recurse=false rebase --continue
+        [git] [detached HEAD 8b96d11] create conflict.txt
+        [git]  1 file changed, 1 insertion(+), 1 deletion(-)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        @ 60f7e990 create conflict.txt
+        |
+        o 25e9ec10 (foo) create test2.txt
+        |
+        o 8b96d11e (bar) create conflict.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_move_run_commit_msg_hook() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.detach_head()?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.write_hook(
+        "commit-msg",
+        r#"#!/bin/sh
+if ! grep -q '^\[[A-Z]*-[0-9]*\]' "$1"; then
+    echo "commit-msg: message must start with an issue tag" >&2
+    exit 1
+fi
+"#,
+    )?;
+
+    // The hook isn't run for rewritten commits by default.
+    {
+        let git = git.duplicate_repo()?;
+        let (stdout, _stderr) = git.run(&[
+            "move",
+            "--in-memory",
+            "-s",
+            &test3_oid.to_string(),
+            "-d",
+            &test1_oid.to_string(),
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 4838e49b create test3.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout 4838e49b08954becdd17c0900c1179c2c654c627
+        :
+        O 62fc20d2 create test1.txt
+        |\
+        | @ 4838e49b create test3.txt
+        |
+        O 96d1c37a (master) create test2.txt
+        In-memory rebase succeeded.
+        "###);
+    }
+
+    git.run(&["config", "branchless.rewrite.runCommitMsgHook", "true"])?;
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &[
+                "move",
+                "--in-memory",
+                "-s",
+                &test3_oid.to_string(),
+                "-d",
+                &test1_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"commit-msg: message must start with an issue tag");
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        The commit-msg hook failed for commit: 70deb1e2 create test3.txt
+        Aborting, since branchless.rewrite.runCommitMsgHook is set.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_default_dest() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+    git.detach_head()?;
+    git.commit_file("test5", 5)?;
+
+    {
+        let git = git.duplicate_repo()?;
+        let (stdout, _stderr) = git.run(&["move", "-s", &test2_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        destination: master (bf0d52a6)
+        Attempting rebase in-memory...
+        [1/2] Committed as: 44352d00 create test2.txt
+        [2/2] Committed as: cf5eb244 create test3.txt
+        branchless: processing 2 rewritten commits
+        In-memory rebase succeeded.
+        "###);
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O bf0d52a6 (master) create test4.txt
+        |\
+        | o 44352d00 create test2.txt
+        | |
+        | o cf5eb244 create test3.txt
+        |
+        @ 848121cb create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["move", "-s", &test2_oid.to_string(), "-d", "."])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 8676dfd8 create test2.txt
+        [2/2] Committed as: 9a29b30b create test3.txt
+        branchless: processing 2 rewritten commits
+        In-memory rebase succeeded.
+        "###);
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O bf0d52a6 (master) create test4.txt
+        |
+        @ 848121cb create test5.txt
+        |
+        o 8676dfd8 create test2.txt
+        |
+        o 9a29b30b create test3.txt
+        "###);
+    }
+
+    git.run(&["config", "branchless.core.mainBranch", "nonexistent"])?;
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["move", "-s", &test2_oid.to_string()],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        No --dest argument was provided, and the main branch "nonexistent" could not be found to use as a default. To set the main branch, run: git config branchless.core.mainBranch <branch>
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_quiet() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, stderr) =
+            git.run(&["move", "-q", "-s", &test2_oid.to_string(), "-d", "master"])?;
+        insta::assert_snapshot!(stderr, @"");
+        insta::assert_snapshot!(stdout, @r###"
+        In-memory rebase succeeded.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 4838e49b (master) create test3.txt
+        |
+        o d742fb97 create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_move_verbose() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (_stdout, stderr) =
+            git.run(&["move", "-v", "-s", &test2_oid.to_string(), "-d", "master"])?;
+        for span_name in [
+            "execute_rebase_plan",
+            "rebase_in_memory",
+            "post_rebase_in_memory",
+        ] {
+            assert!(
+                stderr.contains(span_name),
+                "span {:?} not found in stderr: {}",
+                span_name,
+                stderr
+            );
+        }
+        assert!(stderr.contains("time.busy="), "stderr: {}", stderr);
+        assert!(
+            !stderr.contains("Running Git subprocess"),
+            "stderr: {}",
+            stderr
+        );
+    }
+
+    {
+        let (_stdout, stderr) = git.run(&["prev", "-vv"])?;
+        assert!(
+            stderr.contains("Running Git subprocess"),
+            "stderr: {}",
+            stderr
+        );
+    }
+
+    Ok(())
+}
@@ -1,10 +1,6 @@
-use std::io::{Read, Write};
-use std::thread;
+use branchless::testing::{make_git, GitRunOptions};
 
-use eyre::eyre;
-
-use branchless::testing::{make_git, Git, GitRunOptions};
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use crate::util::{run_in_pty, PtyAction};
 
 const CARRIAGE_RETURN: &'static str = "\r";
 const END_OF_TEXT: &'static str = "\x03";
@@ -34,8 +30,10 @@ fn test_prev() -> eyre::Result<()> {
                 ..Default::default()
             },
         )?;
-        insta::assert_snapshot!(stdout, @"No more parent commits to go to after traversing 0 parents.
-");
+        insta::assert_snapshot!(stdout, @r###"
+        No more parent commits to go to after traversing 0 parents.
+        Staying at f777ecc9 create initial.txt
+        "###);
         insta::assert_snapshot!(stderr, @"");
     }
 
@@ -113,25 +111,35 @@ fn test_next_ambiguous() -> eyre::Result<()> {
     git.run(&["checkout", "master"])?;
 
     {
-        let (stdout, _stderr) = git.run_with_options(
-            &["next"],
-            &GitRunOptions {
-                expected_exit_code: 1,
-                ..Default::default()
-            },
-        )?;
+        let (stdout, _stderr) = git.run(&["next"])?;
         insta::assert_snapshot!(stdout, @r###"
         Found multiple possible child commits to go to after traversing 0 children:
           - 62fc20d2 create test1.txt (oldest)
           - fe65c1fe create test2.txt
           - 98b9119d create test3.txt (newest)
-        (Pass --oldest (-o), --newest (-n), or --interactive (-i) to select between ambiguous commits)
+        Going to the newest commit by commit date, since there is no terminal to prompt in: 98b9119d create test3.txt
+        (Pass --oldest (-o) or --interactive (-i) to select a different commit)
+        branchless: running command: <git-executable> checkout 98b9119d16974f372e76cb64a3b77c528fc0b18b
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        |\
+        | o fe65c1fe create test2.txt
+        |
+        @ 98b9119d create test3.txt
         "###);
     }
 
+    git.run(&["checkout", "master"])?;
     {
         let (stdout, _stderr) = git.run(&["next", "--oldest"])?;
         insta::assert_snapshot!(stdout, @r###"
+        Found multiple possible child commits to go to after traversing 0 children:
+          - 62fc20d2 create test1.txt (oldest)
+          - fe65c1fe create test2.txt
+          - 98b9119d create test3.txt (newest)
+        Going to the oldest commit by commit date, since --oldest was passed: 62fc20d2 create test1.txt
+        (Pass --newest (-n) or --interactive (-i) to select a different commit)
         branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
         O f777ecc9 (master) create initial.txt
         |\
@@ -147,6 +155,12 @@ fn test_next_ambiguous() -> eyre::Result<()> {
     {
         let (stdout, _stderr) = git.run(&["next", "--newest"])?;
         insta::assert_snapshot!(stdout, @r###"
+        Found multiple possible child commits to go to after traversing 0 children:
+          - 62fc20d2 create test1.txt (oldest)
+          - fe65c1fe create test2.txt
+          - 98b9119d create test3.txt (newest)
+        Going to the newest commit by commit date, since --newest was passed: 98b9119d create test3.txt
+        (Pass --oldest (-o) or --interactive (-i) to select a different commit)
         branchless: running command: <git-executable> checkout 98b9119d16974f372e76cb64a3b77c528fc0b18b
         O f777ecc9 (master) create initial.txt
         |\
@@ -205,6 +219,82 @@ fn test_next_ambiguous_interactive() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+#[cfg(unix)]
+fn test_next_ambiguous_choose() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.commit_file("test5", 5)?;
+    git.run(&["checkout", "HEAD~2"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    run_in_pty(
+        &git,
+        &["next", "3"],
+        &[
+            PtyAction::WaitUntilContains("after traversing 1 child:"),
+            PtyAction::WaitUntilContains("Select a commit [1-2, q to cancel]: "),
+            PtyAction::Write("1"),
+            PtyAction::WaitUntilContains("after traversing 2 children:"),
+            PtyAction::WaitUntilContains("Select a commit [1-2, q to cancel]: "),
+            PtyAction::Write("2"),
+        ],
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        | |\
+        | | o f57e36f5 create test4.txt
+        | |
+        | @ d2e18e38 create test5.txt
+        |
+        o 4838e49b create test3.txt
+        "###);
+    }
+
+    git.run(&["checkout", "master"])?;
+    run_in_pty(
+        &git,
+        &["next", "2"],
+        &[
+            PtyAction::WaitUntilContains("Select a commit [1-2, q to cancel]: "),
+            PtyAction::Write("q"),
+        ],
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        | |\
+        | | o f57e36f5 create test4.txt
+        | |
+        | o d2e18e38 create test5.txt
+        |
+        o 4838e49b create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_next_on_master() -> eyre::Result<()> {
     let git = make_git()?;
@@ -283,6 +373,73 @@ fn test_next_no_more_children() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_navigation_stack_smartlog() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.run(&["config", "branchless.autoSmartlog", "true"])?;
+
+    {
+        // Standard output is not a terminal here, so the full smartlog is shown.
+        let (stdout, _stderr) = git.run(&["next", "--newest"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Found multiple possible child commits to go to after traversing 0 children:
+          - 62fc20d2 create test1.txt (oldest)
+          - 98b9119d create test3.txt (newest)
+        Going to the newest commit by commit date, since --newest was passed: 98b9119d create test3.txt
+        (Pass --oldest (-o) or --interactive (-i) to select a different commit)
+        branchless: running command: <git-executable> checkout 98b9119d16974f372e76cb64a3b77c528fc0b18b
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 96d1c37a create test2.txt
+        |
+        @ 98b9119d create test3.txt
+        "###);
+    }
+
+    git.run(&["checkout", "HEAD^"])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "--smartlog", "next", "--newest"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Found multiple possible child commits to go to after traversing 0 children:
+          - 62fc20d2 create test1.txt (oldest)
+          - 98b9119d create test3.txt (newest)
+        Going to the newest commit by commit date, since --newest was passed: 98b9119d create test3.txt
+        (Pass --oldest (-o) or --interactive (-i) to select a different commit)
+        branchless: running command: <git-executable> checkout 98b9119d16974f372e76cb64a3b77c528fc0b18b
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 98b9119d create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "--no-smartlog", "prev"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout f777ecc9b0db5ed372b2615695191a8a17f79f24
+        @ f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 96d1c37a create test2.txt
+        |
+        o 98b9119d create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 #[cfg(unix)]
 fn test_checkout_pty() -> eyre::Result<()> {
@@ -379,101 +536,61 @@ fn test_checkout_abort() -> eyre::Result<()> {
     Ok(())
 }
 
-enum PtyAction<'a> {
-    Write(&'a str),
-    WaitUntilContains(&'a str),
-}
-
-fn run_in_pty(git: &Git, args: &[&str], inputs: &[PtyAction]) -> eyre::Result<()> {
-    // Use the native pty implementation for the system
-    let pty_system = native_pty_system();
-    let pty_size = PtySize::default();
-    let mut pty = pty_system
-        .openpty(pty_size)
-        .map_err(|e| eyre!("Could not open pty: {}", e))?;
-
-    // Spawn a git instance in the pty.
-    let mut cmd = CommandBuilder::new(&git.path_to_git);
-    cmd.env_clear();
-    for (k, v) in git.get_base_env(0) {
-        cmd.env(k, v);
-    }
-    cmd.env("TERM", "xterm");
-    cmd.args(args);
-    cmd.cwd(&git.repo_path);
+#[test]
+fn test_checkout_no_terminal() -> eyre::Result<()> {
+    let git = make_git()?;
 
-    let mut child = pty
-        .slave
-        .spawn_command(cmd)
-        .map_err(|e| eyre!("Could not spawn child: {}", e))?;
-    let mut reader = pty
-        .master
-        .try_clone_reader()
-        .map_err(|e| eyre!("Could not clone reader: {}", e))?;
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
 
-    let mut parser = vt100::Parser::new(pty_size.rows, pty_size.cols, 0);
-    for action in inputs {
-        match &action {
-            PtyAction::WaitUntilContains(value) => {
-                let mut buffer = [0; 1024];
-                while !parser.screen().contents().contains(value) {
-                    let n = reader.read(&mut buffer)?;
-                    parser.process(&buffer[..n]);
-                }
-            }
-            PtyAction::Write(value) => {
-                write!(pty.master, "{}", value)?;
-                pty.master.flush()?;
-            }
-        }
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "checkout"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        Cannot prompt for a commit to check out, since there is no terminal to prompt in.
+        (To check out a commit non-interactively, run `git checkout <commit>`)
+        "###);
+        insta::assert_snapshot!(stdout, @"");
     }
 
-    thread::spawn(move || {
-        let mut buffer = Vec::new();
-        reader.read_to_end(&mut buffer).expect("finish reading pty");
-    });
-
-    child.wait()?;
-
     Ok(())
 }
 
 #[test]
-fn test_navigation_traverse_all_the_way() -> eyre::Result<()> {
+#[cfg(unix)]
+fn test_checkout_pty_hidden() -> eyre::Result<()> {
     let git = make_git()?;
 
     git.init_repo()?;
-
     git.detach_head()?;
     git.commit_file("test1", 1)?;
-    git.commit_file("test2", 2)?;
-    git.commit_file("test3", 3)?;
-
-    {
-        let (stdout, _stderr) = git.run(&["prev", "-a"])?;
-        insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
-        O f777ecc9 (master) create initial.txt
-        |
-        @ 62fc20d2 create test1.txt
-        |
-        o 96d1c37a create test2.txt
-        |
-        o 70deb1e2 create test3.txt
-        "###);
-    }
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.run(&["hide", &test2_oid.to_string()])?;
 
+    run_in_pty(
+        &git,
+        &["branchless", "checkout", "--hidden"],
+        &[
+            PtyAction::WaitUntilContains("> "),
+            PtyAction::Write("test2"),
+            PtyAction::WaitUntilContains(&format!("> {}", &test2_oid.to_string()[0..6])),
+            PtyAction::Write(CARRIAGE_RETURN),
+        ],
+    )?;
     {
-        let (stdout, _stderr) = git.run(&["next", "-a"])?;
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
         insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> checkout 70deb1e28791d8e7dd5a1f0c871a51b91282562f
         O f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
         |
-        o 96d1c37a create test2.txt
-        |
-        @ 70deb1e2 create test3.txt
+        % 96d1c37a (manually hidden) create test2.txt
         "###);
     }
 
@@ -481,102 +598,774 @@ fn test_navigation_traverse_all_the_way() -> eyre::Result<()> {
 }
 
 #[test]
-fn test_navigation_traverse_branches() -> eyre::Result<()> {
+fn test_checkout_previous() -> eyre::Result<()> {
     let git = make_git()?;
 
     git.init_repo()?;
     git.detach_head()?;
-    git.commit_file("test1", 1)?;
-    git.commit_file("test2", 2)?;
-    git.run(&["branch", "foo"])?;
-    git.commit_file("test3", 3)?;
-    git.commit_file("test4", 4)?;
-    git.run(&["branch", "bar"])?;
-    git.commit_file("test5", 5)?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["checkout", &test2_oid.to_string()])?;
 
     {
-        let (stdout, _stderr) = git.run(&["prev", "-b", "2"])?;
+        let (stdout, _stderr) = git.run(&["branchless", "checkout", "-"])?;
         insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> checkout foo
+        Returning to previous checkout: 62fc20d2 create test1.txt
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
         O f777ecc9 (master) create initial.txt
         |
-        o 62fc20d2 create test1.txt
-        |
-        @ 96d1c37a (foo) create test2.txt
-        |
-        o 70deb1e2 create test3.txt
-        |
-        o 355e173b (bar) create test4.txt
+        @ 62fc20d2 create test1.txt
         |
-        o f81d55c0 create test5.txt
+        o 96d1c37a create test2.txt
         "###);
+    }
 
-        let (stdout, _stderr) = git.run(&["prev", "-a", "-b"])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "checkout", "-"])?;
         insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> checkout foo
+        Returning to previous checkout: 96d1c37a create test2.txt
+        branchless: running command: <git-executable> checkout 96d1c37a3d4363611c49f7e52186e189a04c531f
         O f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
         |
-        @ 96d1c37a (foo) create test2.txt
-        |
-        o 70deb1e2 create test3.txt
-        |
-        o 355e173b (bar) create test4.txt
-        |
-        o f81d55c0 create test5.txt
+        @ 96d1c37a create test2.txt
         "###);
+    }
 
-        let (stdout, _stderr) = git.run(&["prev", "-b"])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "checkout", "@{-4}"])?;
         insta::assert_snapshot!(stdout, @r###"
+        Returning to previous checkout: f777ecc9 create initial.txt
         branchless: running command: <git-executable> checkout master
         @ f777ecc9 (master) create initial.txt
         |
         o 62fc20d2 create test1.txt
         |
-        o 96d1c37a (foo) create test2.txt
-        |
-        o 70deb1e2 create test3.txt
-        |
-        o 355e173b (bar) create test4.txt
-        |
-        o f81d55c0 create test5.txt
+        o 96d1c37a create test2.txt
         "###);
     }
 
     {
-        let (stdout, _stderr) = git.run(&["next", "-b", "2"])?;
-        insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> checkout bar
-        O f777ecc9 (master) create initial.txt
-        |
-        o 62fc20d2 create test1.txt
-        |
-        o 96d1c37a (foo) create test2.txt
-        |
-        o 70deb1e2 create test3.txt
-        |
-        @ 355e173b (bar) create test4.txt
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "checkout", "@{-100}"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        No previous checkout found for @{-100}.
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_checkout_previous_no_history() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "checkout", "-"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        No previous checkout found for -.
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_navigation_onto_rewritten_commit() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.run(&["commit", "--amend", "-m", "amended test2"])?;
+    git.run(&["checkout", &test3_oid.to_string()])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["prev"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout 96d1c37a3d4363611c49f7e52186e189a04c531f
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        | o cb8137ad amended test2
+        |
+        % 96d1c37a (rewritten as cb8137ad) create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        note: this commit has been rewritten as cb8137ad; run `git restack` or `git checkout cb8137ad`
+        note: 1 abandoned child of this commit will be moved onto cb8137ad by `git restack`
+        "###);
+    }
+
+    git.run(&["checkout", &test3_oid.to_string()])?;
+    git.run(&[
+        "config",
+        "branchless.navigation.autoSwitchToRewritten",
+        "true",
+    ])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["prev"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Going to cb8137ad amended test2 instead of 96d1c37a create test2.txt, since it has been rewritten.
+        branchless: running command: <git-executable> checkout cb8137adb1d2a166d27eeaf6bfc39a374748852c
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        | @ cb8137ad amended test2
+        |
+        x 96d1c37a (rewritten as cb8137ad) create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_prev_merge_commit_parent() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run_with_options(
+        &["merge", "--no-ff", "-m", "merge", &test3_oid.to_string()],
+        &GitRunOptions {
+            time: 4,
+            ..Default::default()
+        },
+    )?;
+    let merge_oid = git.get_repo()?.get_head_info()?.oid.unwrap().to_string();
+
+    {
+        let (stdout, _stderr) = git.run(&["prev"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Found multiple possible parent commits to go to after traversing 0 parents:
+          - 62fc20d2 create test1.txt (oldest)
+          - 02067177 create test3.txt (newest)
+        Going to the first parent: 62fc20d2 create test1.txt
+        (Pass --parent <n>, --oldest (-o), --newest (-n), or --interactive (-i) to select a different commit)
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ 62fc20d2 create test1.txt
+        | |
+        | o 590fb9eb merge
+        |
+        o fe65c1fe create test2.txt
+        |
+        o 02067177 create test3.txt
+        |
+        o 590fb9eb merge
+        "###);
+    }
+
+    git.run(&["checkout", &merge_oid])?;
+    {
+        let (stdout, _stderr) = git.run(&["prev", "--parent", "2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout 02067177964ab16eedc74600341b2d9e4e19487e
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 590fb9eb merge
+        |
+        o fe65c1fe create test2.txt
+        |
+        @ 02067177 create test3.txt
+        |
+        o 590fb9eb merge
+        "###);
+    }
+
+    git.run(&["checkout", &merge_oid])?;
+    {
+        let (stdout, _stderr) = git.run(&["prev", "2", "--parent", "2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout fe65c1fe15584744e649b2c79d4cf9b0d878f92e
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 590fb9eb merge
+        |
+        @ fe65c1fe create test2.txt
+        |
+        o 02067177 create test3.txt
+        |
+        o 590fb9eb merge
+        "###);
+    }
+
+    git.run(&["checkout", &merge_oid])?;
+    {
+        let (stdout, _stderr) = git.run(&["prev", "--parent", "1"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ 62fc20d2 create test1.txt
+        | |
+        | o 590fb9eb merge
+        |
+        o fe65c1fe create test2.txt
+        |
+        o 02067177 create test3.txt
+        |
+        o 590fb9eb merge
+        "###);
+    }
+
+    git.run(&["checkout", &merge_oid])?;
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["prev", "--parent", "3"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Cannot go to parent 3 of 590fb9eb merge, as it has 2 parents.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_prev_merge_commit_public_parent() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run_with_options(
+        &["merge", "--no-ff", "-m", "merge", "master"],
+        &GitRunOptions {
+            time: 3,
+            ..Default::default()
+        },
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "--parent", "2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Note: you are now on a public commit.
+        branchless: running command: <git-executable> checkout fe65c1fe15584744e649b2c79d4cf9b0d878f92e
+        :
+        @ fe65c1fe (master) create test2.txt
+        |
+        o 72d8a22d merge
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_prev_merge_commit_choose() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", &test2_oid.to_string()])?;
+    // The first parent is newer than the second parent, so the prompt's
+    // numbering must follow the parent order rather than the commit date.
+    git.run_with_options(
+        &["merge", "--no-ff", "-m", "merge", &test1_oid.to_string()],
+        &GitRunOptions {
+            time: 3,
+            ..Default::default()
+        },
+    )?;
+
+    run_in_pty(
+        &git,
+        &["prev"],
+        &[
+            PtyAction::WaitUntilContains("Select a commit [1-2, q to cancel]: "),
+            PtyAction::Write("2"),
+        ],
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ 62fc20d2 create test1.txt
+        | |
+        | o 53eb2f87 merge
+        |
+        o fe65c1fe create test2.txt
+        |
+        o 53eb2f87 merge
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_navigation_traverse_all_the_way() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "-a"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Went back 2 commits to 62fc20d2 create test1.txt
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["next", "-a"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Advanced 2 commits to 70deb1e2 create test3.txt
+        branchless: running command: <git-executable> checkout 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_navigation_traverse_all_the_way_linear() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["branch", "feature"])?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["next", "--all"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Advanced 3 commits to 70deb1e2 (feature) create test3.txt
+        branchless: running command: <git-executable> checkout feature
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 (feature) create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["next", "--all"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Already at the head of the current stack: 70deb1e2 (feature) create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ## feature
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_navigation_traverse_all_the_way_forked() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["next", "--all"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Found multiple possible child commits to go to after traversing 1 child:
+          - 96d1c37a create test2.txt (oldest)
+          - 4838e49b create test3.txt (newest)
+        Going to the newest commit by commit date, since there is no terminal to prompt in: 4838e49b create test3.txt
+        (Pass --oldest (-o) or --interactive (-i) to select a different commit)
+        Advanced 3 commits to a2482074 create test4.txt
+        branchless: running command: <git-executable> checkout a248207402822b7396cabe0f1011d8a7ce7daf1b
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        |
+        o 4838e49b create test3.txt
+        |
+        @ a2482074 create test4.txt
+        "###);
+    }
+
+    git.run(&["checkout", "master"])?;
+    {
+        let (stdout, _stderr) = git.run(&["next", "--all", "--oldest"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Found multiple possible child commits to go to after traversing 1 child:
+          - 96d1c37a create test2.txt (oldest)
+          - 4838e49b create test3.txt (newest)
+        Going to the oldest commit by commit date, since --oldest was passed: 96d1c37a create test2.txt
+        (Pass --newest (-n) or --interactive (-i) to select a different commit)
+        Advanced 2 commits to 96d1c37a create test2.txt
+        branchless: running command: <git-executable> checkout 96d1c37a3d4363611c49f7e52186e189a04c531f
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | @ 96d1c37a create test2.txt
+        |
+        o 4838e49b create test3.txt
+        |
+        o a2482074 create test4.txt
+        "###);
+    }
+
+    git.run(&["checkout", "master"])?;
+    {
+        let (stdout, _stderr) = git.run(&["next", "--all", "--newest"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Found multiple possible child commits to go to after traversing 1 child:
+          - 96d1c37a create test2.txt (oldest)
+          - 4838e49b create test3.txt (newest)
+        Going to the newest commit by commit date, since --newest was passed: 4838e49b create test3.txt
+        (Pass --oldest (-o) or --interactive (-i) to select a different commit)
+        Advanced 3 commits to a2482074 create test4.txt
+        branchless: running command: <git-executable> checkout a248207402822b7396cabe0f1011d8a7ce7daf1b
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        |
+        o 4838e49b create test3.txt
+        |
+        @ a2482074 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "--all"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Went back 2 commits to 62fc20d2 create test1.txt
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 62fc20d2 create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        |
+        o 4838e49b create test3.txt
+        |
+        o a2482074 create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_navigation_traverse_branches() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    git.run(&["branch", "bar"])?;
+    git.commit_file("test5", 5)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "-b", "2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout foo
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 96d1c37a (foo) create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        |
+        o 355e173b (bar) create test4.txt
+        |
+        o f81d55c0 create test5.txt
+        "###);
+
+        let (stdout, _stderr) = git.run(&["prev", "-a", "-b"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Already at the first branch of the current stack: 96d1c37a (foo) create test2.txt
+        "###);
+
+        let (stdout, _stderr) = git.run(&["prev", "-b"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout master
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a (foo) create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        |
+        o 355e173b (bar) create test4.txt
+        |
+        o f81d55c0 create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["next", "-b", "2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout bar
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a (foo) create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        |
+        @ 355e173b (bar) create test4.txt
+        |
+        o f81d55c0 create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["next", "-a", "-b"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Already at the last branch of the current stack: 355e173b (bar) create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_navigation_traverse_branches_stops_at_main_branch() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.run(&["branch", "release"])?;
+    git.commit_file("test0", 0)?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    git.commit_file("test5", 5)?;
+    git.run(&["branch", "bar"])?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["next", "-b"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout foo
+        O f777ecc9 (release) create initial.txt
         |
-        o f81d55c0 create test5.txt
+        O 6f4fc4fa (master) create test0.txt
+        |
+        o 5eb55279 create test1.txt
+        |
+        @ a4296ff2 (foo) create test2.txt
+        |
+        o 9b682cfb create test3.txt
+        |
+        o c0b94324 create test4.txt
+        |
+        o b5d2f87e (bar) create test5.txt
         "###);
     }
 
     {
-        let (stdout, _stderr) = git.run(&["next", "-a", "-b"])?;
+        let (stdout, _stderr) = git.run(&["next", "-b"])?;
         insta::assert_snapshot!(stdout, @r###"
         branchless: running command: <git-executable> checkout bar
-        O f777ecc9 (master) create initial.txt
+        O f777ecc9 (release) create initial.txt
         |
-        o 62fc20d2 create test1.txt
+        O 6f4fc4fa (master) create test0.txt
         |
-        o 96d1c37a (foo) create test2.txt
+        o 5eb55279 create test1.txt
         |
-        o 70deb1e2 create test3.txt
+        o a4296ff2 (foo) create test2.txt
         |
-        @ 355e173b (bar) create test4.txt
+        o 9b682cfb create test3.txt
         |
-        o f81d55c0 create test5.txt
+        o c0b94324 create test4.txt
+        |
+        @ b5d2f87e (bar) create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["next", "-b"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        No more descendant branches to go to after traversing 0 branches.
+        Staying at b5d2f87e (bar) create test5.txt
+        "###);
+    }
+
+    git.run(&["checkout", "master"])?;
+    {
+        let (stdout, _stderr) = git.run(&["next", "2", "-b"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout bar
+        O f777ecc9 (release) create initial.txt
+        |
+        O 6f4fc4fa (master) create test0.txt
+        |
+        o 5eb55279 create test1.txt
+        |
+        o a4296ff2 (foo) create test2.txt
+        |
+        o 9b682cfb create test3.txt
+        |
+        o c0b94324 create test4.txt
+        |
+        @ b5d2f87e (bar) create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "-b"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout foo
+        O f777ecc9 (release) create initial.txt
+        |
+        O 6f4fc4fa (master) create test0.txt
+        |
+        o 5eb55279 create test1.txt
+        |
+        @ a4296ff2 (foo) create test2.txt
+        |
+        o 9b682cfb create test3.txt
+        |
+        o c0b94324 create test4.txt
+        |
+        o b5d2f87e (bar) create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "-b"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout master
+        O f777ecc9 (release) create initial.txt
+        |
+        @ 6f4fc4fa (master) create test0.txt
+        |
+        o 5eb55279 create test1.txt
+        |
+        o a4296ff2 (foo) create test2.txt
+        |
+        o 9b682cfb create test3.txt
+        |
+        o c0b94324 create test4.txt
+        |
+        o b5d2f87e (bar) create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["prev", "-b"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        No more ancestor branches to go to after traversing 0 branches.
+        Staying at 6f4fc4fa (master) create test0.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ## master
         "###);
     }
 
@@ -597,6 +1386,7 @@ fn test_traverse_branches_ambiguous() -> eyre::Result<()> {
     {
         let (stdout, _stderr) = git.run(&["prev", "--all", "--branch"])?;
         insta::assert_snapshot!(stdout, @r###"
+        Went back 1 branch to 62fc20d2 (bar, foo) create test1.txt
         branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
         O f777ecc9 (master) create initial.txt
         |
@@ -672,6 +1462,13 @@ fn test_checkout_pty_branch() -> eyre::Result<()> {
         "###);
     }
 
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ## master
+        "###);
+    }
+
     Ok(())
 }
 
@@ -728,6 +1525,7 @@ fn test_navigation_merge() -> eyre::Result<()> {
         insta::assert_snapshot!(stdout, @r###"
         branchless: running command: <git-executable> checkout 25497cb08387d7d20aa741398b73ce7f924afdb5
         Failed to check out commit: 25497cb08387d7d20aa741398b73ce7f924afdb5
+        (Pass --merge (-m) to merge your uncommitted changes into the destination commit, or set `branchless.navigation.autoMerge` to `true` to do so by default)
         "###);
         insta::assert_snapshot!(stderr, @r###"
         error: Your local changes to the following files would be overwritten by checkout:
@@ -769,3 +1567,198 @@ fn test_navigation_merge() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_navigation_merge_untouched_file() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.write_file("test1", "uncommitted\n")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "--merge"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --merge
+        M	test1.txt
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        diff --git a/test1.txt b/test1.txt
+        index 7432a8f..2b14cb5 100644
+        --- a/test1.txt
+        +++ b/test1.txt
+        @@ -1 +1 @@
+        -test1 contents
+        +uncommitted
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_navigation_auto_merge_config() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file_with_contents("conflicting", 1, "foo\nbar\n")?;
+    git.commit_file_with_contents("conflicting", 2, "baz\nqux\n")?;
+    git.write_file("conflicting", "foo\nbar\nqux\n")?;
+    git.run(&["config", "branchless.navigation.autoMerge", "true"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["prev"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout 25497cb08387d7d20aa741398b73ce7f924afdb5 --merge
+        M	conflicting.txt
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 25497cb0 create conflicting.txt
+        |
+        o 6dd50913 create conflicting.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        UU conflicting.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_navigation_prev_all_to_stack_root() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    git.commit_file("test5", 5)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "-a"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Went back 4 commits to 62fc20d2 (foo) create test1.txt
+        branchless: running command: <git-executable> checkout foo
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 62fc20d2 (foo) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        |
+        o 355e173b create test4.txt
+        |
+        o f81d55c0 create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["prev", "-a"])?;
+        insta::assert_snapshot!(stdout, @"Already at the root of the current stack: 62fc20d2 (foo) create test1.txt");
+    }
+
+    git.run(&["checkout", "master"])?;
+    {
+        let (stdout, _stderr) = git.run(&["prev", "-a"])?;
+        insta::assert_snapshot!(stdout, @"Not in a stack of draft commits, since this commit is public: f777ecc9 (master) create initial.txt");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_checkout_new_branch() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+
+    {
+        let (stdout, _stderr) =
+            git.run(&["branchless", "checkout", "master", "-b", "feature-y"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> checkout feature-y
+        @ f777ecc9 (feature-y, master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["rev-parse", "feature-y"])?;
+        let (master_stdout, _stderr) = git.run(&["rev-parse", "master"])?;
+        assert_eq!(stdout, master_stdout);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["symbolic-ref", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @"refs/heads/feature-y");
+    }
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "checkout", "-b", "feature-y"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        A branch named feature-y already exists.
+        (Pass -B instead of -b to reset it to the start point)
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git checkout master -b feature-y (transaction 4)
+        Will apply these actions:
+        1. Check out from f777ecc9 create initial.txt
+                       to 62fc20d2 create test1.txt
+        2. Delete branch feature-y at f777ecc9 create initial.txt
+           
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --detach
+        O f777ecc9 (feature-y, master) create initial.txt
+        |
+        @ 62fc20d2 create test1.txt
+        Applied 2 inverse events.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 62fc20d2 create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
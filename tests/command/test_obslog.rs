@@ -0,0 +1,89 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+#[test]
+fn test_obslog_amend_and_reword() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let original_oid = git.commit_file("test2", 2)?;
+
+    git.write_file("test2", "amended once\n")?;
+    git.run(&["commit", "-a", "--amend", "--no-edit"])?;
+    git.write_file("test2", "amended twice\n")?;
+    git.run(&["branchless", "amend"])?;
+    git.run(&["branchless", "reword", "-m", "reworded test2"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "obslog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ cf171cc6 reworded test2
+        | Rewritten by `git reword -m 'reworded test2'`
+        x c5e07459 create test2.txt
+        | Rewritten by `git amend`
+        x 84a289f5 create test2.txt
+        | Rewritten by `git commit -a --amend --no-edit`
+        x 96d1c37a create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "obslog", &original_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ cf171cc6 reworded test2
+        | Rewritten by `git reword -m 'reworded test2'`
+        x c5e07459 create test2.txt
+        | Rewritten by `git amend`
+        x 84a289f5 create test2.txt
+        | Rewritten by `git commit -a --amend --no-edit`
+        x 96d1c37a create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "obslog", "--stat"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ cf171cc6 reworded test2
+        | Rewritten by `git reword -m 'reworded test2'`
+        |  0 files changed
+        x c5e07459 create test2.txt
+        | Rewritten by `git amend`
+        |  test2.txt | 2 +-
+        |  1 file changed, 1 insertion(+), 1 deletion(-)
+        x 84a289f5 create test2.txt
+        | Rewritten by `git commit -a --amend --no-edit`
+        |  test2.txt | 2 +-
+        |  1 file changed, 1 insertion(+), 1 deletion(-)
+        x 96d1c37a create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_obslog_commit_not_found() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "obslog", "nonexistent"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Commit not found: nonexistent
+        "###);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+#[test]
+fn test_query_formats() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "query", "draft()"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        96d1c37a3d4363611c49f7e52186e189a04c531f
+        70deb1e28791d8e7dd5a1f0c871a51b91282562f
+        bf0d52a607f693201512a43b6b5a70b2a275e0ad
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "query",
+            "--format",
+            "summary",
+            "draft() - stack()",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        96d1c37a create test2.txt
+        70deb1e2 create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "query",
+            "--format",
+            "json",
+            "stack() | main()",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        [
+          {"oid": "62fc20d2a290daea0d52bdc2ed2ad4be6491010e", "summary": "create test1.txt"},
+          {"oid": "bf0d52a607f693201512a43b6b5a70b2a275e0ad", "summary": "create test4.txt"}
+        ]
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "query", "--format", "json", "none()"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        []
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_query_errors() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "query", "draft() |"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Parse error for expression 'draft() |': expected a name, function call, or parenthesized expression, but reached the end of the expression
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "query", "stack(nonexistent)"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Commit not found: nonexistent
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "query", "drafts()"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Unknown function: drafts
+        "###);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,277 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+use crate::util::trim_lines;
+
+#[test]
+fn test_record_pathspecs() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.write_file("initial", "updated initial contents\n")?;
+    git.write_file("test1", "updated test1 contents\n")?;
+    git.write_file("test2", "test2 contents\n")?;
+    git.write_file("test3", "test3 contents\n")?;
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "record",
+            "-m",
+            "update test1.txt and create test2.txt",
+            "test1.txt",
+            "test2.txt",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> add -- test2.txt
+        branchless: running command: <git-executable> commit -m update test1.txt and create test2.txt -- test1.txt test2.txt
+        [detached HEAD e61a6a9] update test1.txt and create test2.txt
+         2 files changed, 2 insertions(+), 1 deletion(-)
+         create mode 100644 test2.txt
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ e61a6a94 update test1.txt and create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=%s", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        update test1.txt and create test2.txt
+
+        test1.txt
+        test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) =
+            git.run(&["ls-files", "--modified", "--others", "--exclude-standard"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        test3.txt
+        initial.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "record", "-m", "nothing", "test1.txt"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        There are no uncommitted changes in the given paths. Nothing to record.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_record_staged() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "record", "--staged", "-m", "nothing"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        There are no staged changes. Nothing to record.
+        "###);
+    }
+
+    git.write_file("initial", "updated initial contents\n")?;
+    git.write_file("test1", "updated test1 contents\n")?;
+    git.run(&["add", "test1.txt"])?;
+
+    {
+        let (stdout, _stderr) =
+            git.run(&["branchless", "record", "--staged", "-m", "update test1.txt"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> commit -m update test1.txt
+        [detached HEAD 77bd569] update test1.txt
+         1 file changed, 1 insertion(+), 1 deletion(-)
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 77bd569b update test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=%s", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        update test1.txt
+
+        test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        initial.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_record_interactive() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.write_file("initial", "updated initial contents\n")?;
+    git.write_file("test1", "updated test1 contents\n")?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "record", "-i", "-m", "update test1.txt"],
+            &GitRunOptions {
+                input: Some("3\n2\n".to_string()),
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Changed files:
+          1) initial.txt
+          2) test1.txt
+        Select files [e.g. `1 3`, `1-2`, `*` for all, empty to cancel]: Invalid selection: 3
+        Select files [e.g. `1 3`, `1-2`, `*` for all, empty to cancel]: branchless: running command: <git-executable> commit -m update test1.txt -- test1.txt
+        [detached HEAD 77bd569] update test1.txt
+         1 file changed, 1 insertion(+), 1 deletion(-)
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 77bd569b update test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        initial.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_record_undo_restores_uncommitted_changes() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.write_file("test1", "updated test1 contents\n")?;
+    git.write_file("initial", "updated initial contents\n")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "record", "-m", "update files"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> commit -m update files -- initial.txt test1.txt
+        [detached HEAD 324bf17] update files
+         2 files changed, 2 insertions(+), 2 deletions(-)
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 324bf17b update files
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git record -m 'update files' (transaction 4)
+        Will apply these actions:
+        1. Check out from 324bf17b update files
+                       to 62fc20d2 create test1.txt
+        2. Restore uncommitted changes to 2 files from working copy snapshot 88a770cc
+
+        3. Hide commit 324bf17b update files
+
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --detach
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 62fc20d2 create test1.txt
+        |
+        o 324bf17b update files
+        Applied 3 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["diff", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        initial.txt
+        test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=%s", "--no-patch", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_record_commit_hooks() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.write_hook(
+        "pre-commit",
+        r#"#!/bin/sh
+echo "pre-commit: rejecting commit" >&2
+exit 1
+"#,
+    )?;
+
+    git.write_file("test1", "contents1\n")?;
+    git.run(&["add", "test1.txt"])?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "record", "-m", "foo"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"pre-commit: rejecting commit");
+        insta::assert_snapshot!(stdout, @"branchless: running command: <git-executable> commit -m foo -- test1.txt");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "record", "--no-verify", "-m", "foo"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> commit -m foo --no-verify -- test1.txt
+        [master 8c50170] foo
+         1 file changed, 1 insertion(+)
+         create mode 100644 test1.txt
+        :
+        @ 8c501701 (master) foo
+        "###);
+    }
+
+    Ok(())
+}
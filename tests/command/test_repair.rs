@@ -0,0 +1,262 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+#[test]
+fn test_repair_no_problems() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "repair"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        No problems found.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_missing_commit() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+
+    // Simulate the commit being garbage-collected.
+    let test2_oid = test2_oid.to_string();
+    std::fs::remove_file(
+        git.repo_path
+            .join(".git")
+            .join("objects")
+            .join(&test2_oid[..2])
+            .join(&test2_oid[2..]),
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "repair"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        Problem: Commit 96d1c37a3d4363611c49f7e52186e189a04c531f is visible according to the event log, but it no longer exists
+        To fix these problems, run: git branchless repair --apply
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "repair", "--apply"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        Problem: Commit 96d1c37a3d4363611c49f7e52186e189a04c531f is visible according to the event log, but it no longer exists
+        Hid missing commit: 96d1c37a3d4363611c49f7e52186e189a04c531f
+        Rebuilt commit graph cache
+        All problems were fixed.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "repair"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        No problems found.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_invalid_event() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+
+    {
+        let conn = git.get_repo()?.get_db_conn()?;
+        conn.execute(
+            "
+INSERT INTO event_log
+(timestamp, type, event_tx_id, old_ref, new_ref, ref_name, message)
+VALUES
+(1.0, 'commit', 999, 'not-an-oid', NULL, NULL, NULL),
+(1.0, 'hide', 1000, '62fc20d2a290daea0d52bdc2ed2ad4be6491010e', NULL, NULL, NULL)
+",
+            rusqlite::params![],
+        )?;
+    }
+
+    git.run_with_options(
+        &["smartlog"],
+        &GitRunOptions {
+            expected_exit_code: 101,
+            ..Default::default()
+        },
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "repair", "--apply"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        Problem: An event in transaction 999 could not be loaded: Could not parse OID from string
+        Problem: Transaction 1000 has events, but the transaction itself was never recorded
+        Deleted 1 invalid event
+        Recorded missing transaction 1000
+        Rebuilt commit graph cache
+        All problems were fixed.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        % 62fc20d2 (manually hidden) (master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_hooks_and_config() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    std::fs::remove_file(git.repo_path.join(".git").join("hooks").join("post-commit"))?;
+    git.run(&["config", "--unset-all", "include.path"])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "repair"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        Problem: Hook post-commit is not installed, or its branchless section is damaged
+        Problem: The branchless config file is not included from the repository config
+        To fix these problems, run: git branchless repair --apply
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "repair", "--apply"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        Problem: Hook post-commit is not installed, or its branchless section is damaged
+        Problem: The branchless config file is not included from the repository config
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Created config file at <repo-path>/.git/branchless/config
+        Rebuilt commit graph cache
+        All problems were fixed.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "repair"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        No problems found.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_stale_config_path() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.run(&[
+        "config",
+        "--file",
+        ".git/branchless/config",
+        "core.hooksPath",
+        "/path/to/original/.git/hooks",
+    ])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "repair"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        Problem: Hook post-applypatch is not installed, or its branchless section is damaged
+        Problem: Hook post-commit is not installed, or its branchless section is damaged
+        Problem: Hook post-merge is not installed, or its branchless section is damaged
+        Problem: Hook post-rewrite is not installed, or its branchless section is damaged
+        Problem: Hook post-checkout is not installed, or its branchless section is damaged
+        Problem: Hook pre-auto-gc is not installed, or its branchless section is damaged
+        Problem: Hook reference-transaction is not installed, or its branchless section is damaged
+        Problem: Setting core.hooksPath in the branchless config file refers to another copy of the repository: /path/to/original/.git/hooks
+        To fix these problems, run: git branchless repair --apply
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "repair", "--apply"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Event log schema version: 9
+        Problem: Hook post-applypatch is not installed, or its branchless section is damaged
+        Problem: Hook post-commit is not installed, or its branchless section is damaged
+        Problem: Hook post-merge is not installed, or its branchless section is damaged
+        Problem: Hook post-rewrite is not installed, or its branchless section is damaged
+        Problem: Hook post-checkout is not installed, or its branchless section is damaged
+        Problem: Hook pre-auto-gc is not installed, or its branchless section is damaged
+        Problem: Hook reference-transaction is not installed, or its branchless section is damaged
+        Problem: Setting core.hooksPath in the branchless config file refers to another copy of the repository: /path/to/original/.git/hooks
+        Updated core.hooksPath from /path/to/original/.git/hooks to <repo-path>/.git/hooks
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Rebuilt commit graph cache
+        All problems were fixed.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["config", "core.hooksPath"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        <repo-path>/.git/hooks
+        "###);
+    }
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use branchless::testing::{make_git, GitInitOptions, GitRunOptions};
+use branchless::testing::{make_git, Git, GitInitOptions, GitRunOptions};
 
 /// Remove some of the output from `git rebase`, as it seems to be
 /// non-deterministic as to whether or not it appears.
@@ -57,12 +57,17 @@ fn test_restack_amended_commit() -> eyre::Result<()> {
         let (stdout, _stderr) = git.run(&["restack", "--on-disk"])?;
         let stdout = remove_rebase_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (2 commits to rewrite in total):
+        024c35ce amend test1.txt
+          - 96d1c37a create test2.txt
+          - 70deb1e2 create test3.txt
+        No merge conflicts predicted.
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
         Finished restacking commits.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 024c35ce32dae6b12e981963465ee8a62b7eff9b
         O f777ecc9 (master) create initial.txt
         |
         @ 024c35ce amend test1.txt
@@ -99,12 +104,17 @@ fn test_restack_consecutive_rewrites() -> eyre::Result<()> {
         let stdout = remove_rebase_lines(stdout);
 
         insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (2 commits to rewrite in total):
+        662b451f amend test1.txt v2
+          - 96d1c37a create test2.txt
+          - 70deb1e2 create test3.txt
+        No merge conflicts predicted.
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
         Finished restacking commits.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 662b451fb905b92404787e024af717ced49e3045
         O f777ecc9 (master) create initial.txt
         |
         @ 662b451f amend test1.txt v2
@@ -133,10 +143,12 @@ fn test_move_abandoned_branch() -> eyre::Result<()> {
         let (stdout, _stderr) = git.run(&["restack"])?;
         let stdout = remove_rebase_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Abandoned branches:
+        - branch master will move from 62fc20d2 create test1.txt to 662b451f amend test1.txt v2
         No abandoned commits to restack.
         branchless: processing 1 update: branch master
         Finished restacking branches.
-        branchless: running command: <git-executable> checkout 662b451fb905b92404787e024af717ced49e3045
         :
         @ 662b451f (master) amend test1.txt v2
         "###);
@@ -172,15 +184,20 @@ fn test_amended_initial_commit() -> eyre::Result<()> {
     }
 
     {
-        let (stdout, _stderr) = git.run(&["restack", "--on-disk"])?;
+        let (stdout, _stderr) = git.run(&["restack", "--force-rewrite-public", "--on-disk"])?;
         let stdout = remove_rebase_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        9a9f929a new initial commit
+          - 62fc20d2 create test1.txt
+        Will move branch master along with 62fc20d2 create test1.txt
+        No merge conflicts predicted.
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
         Finished restacking commits.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 9a9f929a0d4f052ff5d58bedd97b2f761120f8ed
         @ 9a9f929a new initial commit
         |
         O 6d85943b (master) create test1.txt
@@ -207,15 +224,20 @@ fn test_restack_amended_master() -> eyre::Result<()> {
     git.run(&["commit", "--amend", "-m", "amended test1"])?;
 
     {
-        let (stdout, _stderr) = git.run(&["restack", "--on-disk"])?;
+        let (stdout, _stderr) = git.run(&["restack", "--force-rewrite-public", "--on-disk"])?;
         let stdout = remove_rebase_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        ae94dc2a amended test1
+          - 96d1c37a create test2.txt
+        Will move branch master along with 96d1c37a create test2.txt
+        No merge conflicts predicted.
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
         Finished restacking commits.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout ae94dc2a748bc0965c88fcf3edac2e30074ff7e2
         :
         @ ae94dc2a amended test1
         |
@@ -242,13 +264,19 @@ fn test_restack_aborts_during_rebase_conflict() -> eyre::Result<()> {
 
     {
         let (stdout, _stderr) = git.run_with_options(
-            &["restack"],
+            &["restack", "--force-rewrite-public"],
             &GitRunOptions {
                 expected_exit_code: 1,
                 ..Default::default()
             },
         )?;
         insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        289a1539 amend test1 with test2 conflict
+          - 96d1c37a create test2.txt
+        Will move branch master along with 96d1c37a create test2.txt
+        Predicted merge conflict (1 conflicting file): 96d1c37a create test2.txt
         Attempting rebase in-memory...
         This operation would cause a merge conflict:
         - (1 conflicting file) 96d1c37a create test2.txt
@@ -258,7 +286,7 @@ fn test_restack_aborts_during_rebase_conflict() -> eyre::Result<()> {
 
     {
         let (stdout, _stderr) = git.run_with_options(
-            &["restack", "--merge"],
+            &["restack", "--force-rewrite-public", "--merge"],
             &GitRunOptions {
                 expected_exit_code: 1,
                 ..Default::default()
@@ -267,14 +295,20 @@ fn test_restack_aborts_during_rebase_conflict() -> eyre::Result<()> {
         let stdout = remove_rebase_lines(stdout);
 
         insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        289a1539 amend test1 with test2 conflict
+          - 96d1c37a create test2.txt
+        Will move branch master along with 96d1c37a create test2.txt
+        Predicted merge conflict (1 conflicting file): 96d1c37a create test2.txt
         Attempting rebase in-memory...
         There was a merge conflict, which currently can't be resolved when rebasing in-memory.
         The conflicting commit was: 96d1c37a create test2.txt
         Trying again on-disk...
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
-        CONFLICT (add/add): Merge conflict in test2.txt
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] CONFLICT (add/add): Merge conflict in test2.txt
         Error: Could not restack commits (exit code 1).
         You can resolve the error and try running `git restack` again.
         "###);
@@ -307,12 +341,17 @@ fn test_restack_multiple_amended() -> eyre::Result<()> {
     {
         let (stdout, _stderr) = git.run(&["restack", "--on-disk"])?;
         insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 2 abandoned commits (2 commits to rewrite in total):
+        22f39285 test2 amended
+          - f10316a7 test3 amended
+          - 355e173b create test4.txt
+        No merge conflicts predicted.
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
         Finished restacking commits.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
         O f777ecc9 (master) create initial.txt
         |
         @ 62fc20d2 create test1.txt
@@ -374,41 +413,43 @@ fn test_restack_single_of_many_commits() -> eyre::Result<()> {
     {
         let (stdout, stderr) = git.run(&["restack", "--on-disk", &test2_oid.to_string()])?;
         insta::assert_snapshot!(stderr, @r###"
-        Executing: git branchless hook-register-extra-post-rewrite-hook
-        branchless: processing 1 update: ref HEAD
-        branchless: processing 1 update: ref HEAD
-        branchless: processed commit: 944f78da create test3.txt
-        Executing: git branchless hook-detect-empty-commit 70deb1e28791d8e7dd5a1f0c871a51b91282562f
-        branchless: processing 1 rewritten commit
-        branchless: running command: <git-executable> checkout 3bd716d57489779ab1daf446f80e66e90b56ead7
-        Previous HEAD position was 944f78d create test3.txt
-        branchless: processing 1 update: ref HEAD
-        HEAD is now at 3bd716d updated test4
-        branchless: processing checkout
-        :
-        O 62fc20d2 (master) create test1.txt
-        |\
-        | @ 3bd716d5 updated test4
-        |\
-        | o 7357d2b7 updated test2
-        | |
-        | o 944f78da create test3.txt
-        |
-        x bf0d52a6 (rewritten as 3bd716d5) create test4.txt
-        |
-        o 848121cb create test5.txt
-        Successfully rebased and updated detached HEAD.
-        branchless: processing 1 update: ref HEAD
-        HEAD is now at 3bd716d updated test4
-        branchless: processing checkout
+        [git] Executing: git branchless hook-register-extra-post-rewrite-hook
+        [git] branchless: processing 1 update: ref HEAD
+        [git] branchless: processing 1 update: ref HEAD
+        [git] branchless: processed commit: 944f78da create test3.txt
+        [git] Executing: git branchless hook-detect-empty-commit 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+        [git] branchless: processing 1 rewritten commit
+        [git] branchless: running command: <git-executable> checkout 3bd716d57489779ab1daf446f80e66e90b56ead7
+        [git] Previous HEAD position was 944f78d create test3.txt
+        [git] branchless: processing 1 update: ref HEAD
+        [git] HEAD is now at 3bd716d updated test4
+        [git] branchless: processing checkout
+        [git] :
+        [git] O 62fc20d2 (master) create test1.txt
+        [git] |\
+        [git] | @ 3bd716d5 updated test4
+        [git] |\
+        [git] | o 7357d2b7 updated test2
+        [git] | |
+        [git] | o 944f78da create test3.txt
+        [git] |
+        [git] x bf0d52a6 (rewritten as 3bd716d5) create test4.txt
+        [git] |
+        [git] o 848121cb create test5.txt
+        [git] Successfully rebased and updated detached HEAD.
         "###);
         insta::assert_snapshot!(stdout, @r###"
+        Skipped abandoned commit outside of the given stacks: 848121cb create test5.txt
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        7357d2b7 updated test2
+          - 70deb1e2 create test3.txt
+        No merge conflicts predicted.
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
-        branchless: running command: <git-executable> rebase --continue
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
         Finished restacking commits.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout 3bd716d57489779ab1daf446f80e66e90b56ead7
         :
         O 62fc20d2 (master) create test1.txt
         |\
@@ -475,7 +516,6 @@ fn test_restack_unobserved_commit() -> eyre::Result<()> {
         insta::assert_snapshot!(stdout, @r###"
         No abandoned commits to restack.
         No abandoned branches to restack.
-        branchless: running command: <git-executable> checkout f4229de371f9e2a77cd9401a1851dbb14d6f69ab
         :
         O 62fc20d2 (master) create test1.txt
         |
@@ -485,3 +525,1156 @@ fn test_restack_unobserved_commit() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_restack_merge_commit() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run_with_options(
+        &["merge", "master", "-m", "Merge master"],
+        &GitRunOptions {
+            time: 3,
+            ..Default::default()
+        },
+    )?;
+    git.commit_file("test3", 4)?;
+    git.run(&["branch", "feature"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O fe65c1fe (master) create test2.txt
+        |
+        o 67458413 Merge master
+        |
+        @ e0940379 (feature) create test3.txt
+        "###);
+    }
+
+    // Parents' trees are unchanged: the merge commit's tree is reused.
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["checkout", &test1_oid.to_string()])?;
+        git.run(&["commit", "--amend", "-m", "reworded test1"])?;
+        {
+            let (stdout, _stderr) = git.run(&["restack"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Restack plan:
+            Stack 1/1: 1 abandoned commit (2 commits to rewrite in total):
+            78e02b4a reworded test1
+              - 67458413 Merge master (merge commit)
+              - e0940379 create test3.txt
+            Will move branch feature along with e0940379 create test3.txt
+            No merge conflicts predicted.
+            Attempting rebase in-memory...
+            [1/2] Committed as: 4e3efd62 Merge master
+            [2/2] Committed as: 32b2d497 create test3.txt
+            branchless: processing 1 update: branch feature
+            branchless: processing 2 rewritten commits
+            In-memory rebase succeeded.
+            Finished restacking commits.
+            No abandoned branches to restack.
+            O f777ecc9 create initial.txt
+            |\
+            | @ 78e02b4a reworded test1
+            | |
+            | o 4e3efd62 Merge master
+            | |
+            | o 32b2d497 (feature) create test3.txt
+            |
+            O fe65c1fe (master) create test2.txt
+            |
+            o 4e3efd62 Merge master
+            |
+            o 32b2d497 (feature) create test3.txt
+            "###);
+        }
+        {
+            let (stdout, _stderr) = git.run(&["log", "--graph", "--format=%h %p %s", "feature"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            * 32b2d49 4e3efd6 create test3.txt
+            *   4e3efd6 78e02b4 fe65c1f Merge master
+            |\  
+            | * fe65c1f f777ecc create test2.txt
+            * | 78e02b4 f777ecc reworded test1
+            |/  
+            * f777ecc  create initial.txt
+            "###);
+        }
+    }
+
+    // Parents' trees have changed: the parents are merged again.
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["checkout", &test1_oid.to_string()])?;
+        git.write_file("test1", "amended contents\n")?;
+        git.run(&["commit", "--amend", "-a", "-m", "amended test1"])?;
+        {
+            let (stdout, _stderr) = git.run(&["restack"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Restack plan:
+            Stack 1/1: 1 abandoned commit (2 commits to rewrite in total):
+            db57f07e amended test1
+              - 67458413 Merge master (merge commit)
+              - e0940379 create test3.txt
+            Will move branch feature along with e0940379 create test3.txt
+            No merge conflicts predicted.
+            Attempting rebase in-memory...
+            [1/2] Committed as: 41897540 Merge master
+            [2/2] Committed as: d3ef81a9 create test3.txt
+            branchless: processing 1 update: branch feature
+            branchless: processing 2 rewritten commits
+            In-memory rebase succeeded.
+            Finished restacking commits.
+            No abandoned branches to restack.
+            O f777ecc9 create initial.txt
+            |\
+            | @ db57f07e amended test1
+            | |
+            | o 41897540 Merge master
+            | |
+            | o d3ef81a9 (feature) create test3.txt
+            |
+            O fe65c1fe (master) create test2.txt
+            |
+            o 41897540 Merge master
+            |
+            o d3ef81a9 (feature) create test3.txt
+            "###);
+        }
+        {
+            let (stdout, _stderr) = git.run(&["log", "--graph", "--format=%h %p %s", "feature"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            * d3ef81a 4189754 create test3.txt
+            *   4189754 db57f07 fe65c1f Merge master
+            |\  
+            | * fe65c1f f777ecc create test2.txt
+            * | db57f07 f777ecc amended test1
+            |/  
+            * f777ecc  create initial.txt
+            "###);
+        }
+    }
+
+    // The merge of the rewritten parents conflicts.
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["checkout", &test1_oid.to_string()])?;
+        git.write_file("test2", "conflicting contents\n")?;
+        git.run(&["add", "test2.txt"])?;
+        git.run(&["commit", "--amend", "-m", "amended test1"])?;
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["restack", "--in-memory"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Restack plan:
+            Stack 1/1: 1 abandoned commit (2 commits to rewrite in total):
+            eec4c65a amended test1
+              - 67458413 Merge master (merge commit)
+              - e0940379 create test3.txt
+            Will move branch feature along with e0940379 create test3.txt
+            Predicted merge conflict (1 conflicting file): 67458413 Merge master
+            Attempting rebase in-memory...
+            There was a merge conflict, which currently can't be resolved when rebasing in-memory.
+            The conflicting commit was: 67458413 Merge master
+            Aborting since an in-memory rebase was requested.
+            Error: Could not restack commits (exit code 1).
+            You can resolve the error and try running `git restack` again.
+            "###);
+        }
+    }
+
+    // With `--drop-merges`, only the first-parent history is replayed.
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["checkout", &test1_oid.to_string()])?;
+        git.run(&["commit", "--amend", "-m", "reworded test1"])?;
+        {
+            let (stdout, _stderr) = git.run(&["restack", "--drop-merges"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Restack plan:
+            Stack 1/1: 1 abandoned commit (2 commits to rewrite in total):
+            78e02b4a reworded test1
+              - 67458413 Merge master (merge commit, will be dropped)
+              - e0940379 create test3.txt
+            Will move branch feature along with e0940379 create test3.txt
+            No merge conflicts predicted.
+            Attempting rebase in-memory...
+            [1/2] Dropped merge commit: 67458413 Merge master
+            [2/2] Committed as: d7e6ad35 create test3.txt
+            branchless: processing 1 update: branch feature
+            branchless: processing 2 rewritten commits
+            In-memory rebase succeeded.
+            Finished restacking commits.
+            No abandoned branches to restack.
+            O f777ecc9 create initial.txt
+            |\
+            | @ 78e02b4a reworded test1
+            | |
+            | o d7e6ad35 (feature) create test3.txt
+            |
+            O fe65c1fe (master) create test2.txt
+            "###);
+        }
+        {
+            let (stdout, _stderr) = git.run(&["log", "--graph", "--format=%h %p %s", "feature"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            * d7e6ad3 78e02b4 create test3.txt
+            * 78e02b4 f777ecc reworded test1
+            * f777ecc  create initial.txt
+            "###);
+        }
+    }
+
+    {
+        let git = git.duplicate_repo()?;
+        git.run(&["checkout", &test1_oid.to_string()])?;
+        git.run(&["commit", "--amend", "-m", "reworded test1"])?;
+        {
+            let (stdout, _stderr) = git.run(&["restack", "--on-disk", "--drop-merges"])?;
+            let stdout: String = stdout
+                .lines()
+                .filter(|line| line.contains("Dropping merge commit"))
+                .collect();
+            insta::assert_snapshot!(stdout, @"[git] Dropping merge commit: 67458413 Merge master");
+        }
+        {
+            let (stdout, _stderr) = git.run(&["log", "--graph", "--format=%h %p %s", "feature"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            * d7e6ad3 78e02b4 create test3.txt
+            * 78e02b4 f777ecc reworded test1
+            * f777ecc  create initial.txt
+            "###);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_only_given_stacks() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test4", 4)?;
+
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amended test1"])?;
+    let amended_test1_oid = git.get_repo()?.get_head_info()?.oid.unwrap();
+    git.run(&["checkout", &test3_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amended test3"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o ae94dc2a amended test1
+        |\
+        | @ dec52eae amended test3
+        |\
+        | x 62fc20d2 (rewritten as ae94dc2a) create test1.txt
+        | |
+        | o 96d1c37a create test2.txt
+        |
+        x 98b9119d (rewritten as dec52eae) (foo) create test3.txt
+        |
+        o 2b633ed7 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["restack", &amended_test1_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Skipped abandoned commit outside of the given stacks: 2b633ed7 create test4.txt
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        ae94dc2a amended test1
+          - 96d1c37a create test2.txt
+        No merge conflicts predicted.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 51452b55 create test2.txt
+        branchless: processing 1 rewritten commit
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        Skipped abandoned branch outside of the given stacks: branch foo
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o ae94dc2a amended test1
+        | |
+        | o 51452b55 create test2.txt
+        |\
+        | @ dec52eae amended test3
+        |
+        x 98b9119d (rewritten as dec52eae) (foo) create test3.txt
+        |
+        o 2b633ed7 create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_skip_now_empty_commit() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["commit", "--allow-empty", "-m", "empty commit"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["branch", "bar"])?;
+
+    // Amend the first commit so that it also contains the changes from the
+    // second commit, which then becomes empty when restacked.
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.write_file("test2", "test2 contents\n")?;
+    git.run(&["add", "."])?;
+    git.run(&["commit", "--amend", "-m", "create test1.txt and test2.txt"])?;
+
+    {
+        let git = git.duplicate_repo()?;
+        let (stdout, _stderr) = git.run(&["restack", "--in-memory"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (3 commits to rewrite in total):
+        c3af27b1 create test1.txt and test2.txt
+          - 96d1c37a create test2.txt
+          - 316b1734 empty commit
+          - 5bfd7e1d create test3.txt
+        Will move branch foo along with 96d1c37a create test2.txt
+        Will move branch bar along with 5bfd7e1d create test3.txt
+        No merge conflicts predicted.
+        Attempting rebase in-memory...
+        [1/3] Skipped now-empty commit: 4f2568f3 create test2.txt
+        [2/3] Committed as: b8d8a571 empty commit
+        [3/3] Committed as: bb7bdd08 create test3.txt
+        branchless: processing 2 updates: branch bar, branch foo
+        branchless: processing 3 rewritten commits
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ c3af27b1 (foo) create test1.txt and test2.txt
+        |
+        o b8d8a571 empty commit
+        |
+        o bb7bdd08 (bar) create test3.txt
+        "###);
+    }
+
+    {
+        let git = git.duplicate_repo()?;
+        let (stdout, _stderr) = git.run(&["restack", "--on-disk"])?;
+        let stdout = remove_rebase_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (3 commits to rewrite in total):
+        c3af27b1 create test1.txt and test2.txt
+          - 96d1c37a create test2.txt
+          - 316b1734 empty commit
+          - 5bfd7e1d create test3.txt
+        Will move branch foo along with 96d1c37a create test2.txt
+        Will move branch bar along with 5bfd7e1d create test3.txt
+        No merge conflicts predicted.
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] Skipped now-empty commit: 4f2568f3 create test2.txt
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ c3af27b1 (foo) create test1.txt and test2.txt
+        |
+        o b8d8a571 empty commit
+        |
+        o bb7bdd08 (bar) create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["restack", "--in-memory", "--keep-empty"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (3 commits to rewrite in total):
+        c3af27b1 create test1.txt and test2.txt
+          - 96d1c37a create test2.txt
+          - 316b1734 empty commit
+          - 5bfd7e1d create test3.txt
+        Will move branch foo along with 96d1c37a create test2.txt
+        Will move branch bar along with 5bfd7e1d create test3.txt
+        No merge conflicts predicted.
+        Attempting rebase in-memory...
+        [1/3] Committed as: 4f2568f3 create test2.txt
+        [2/3] Committed as: c605ab5e empty commit
+        [3/3] Committed as: 6d37d6e1 create test3.txt
+        branchless: processing 2 updates: branch bar, branch foo
+        branchless: processing 3 rewritten commits
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ c3af27b1 create test1.txt and test2.txt
+        |
+        o 4f2568f3 (foo) create test2.txt
+        |
+        o c605ab5e empty commit
+        |
+        o 6d37d6e1 (bar) create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_branches_updated_atomically() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "bar"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["branch", "baz"])?;
+
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amend test1.txt"])?;
+
+    // Simulate another process holding the lock for one of the branches, so
+    // that it can't be updated.
+    let lock_file_path = git.repo_path.join(".git/refs/heads/bar.lock");
+    std::fs::write(&lock_file_path, "")?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["restack", "--in-memory"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        assert!(stderr.contains("Failed to move branches: Locking reference: refs/heads/bar"));
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (2 commits to rewrite in total):
+        024c35ce amend test1.txt
+          - 96d1c37a create test2.txt
+          - 70deb1e2 create test3.txt
+        Will move branch bar along with 96d1c37a create test2.txt
+        Will move branch baz along with 70deb1e2 create test3.txt
+        No merge conflicts predicted.
+        Abandoned branches:
+        - branch foo will move from 62fc20d2 create test1.txt to 024c35ce amend test1.txt
+        Attempting rebase in-memory...
+        [1/2] Committed as: 8cd7de68 create test2.txt
+        [2/2] Committed as: b9a0491a create test3.txt
+        Error: Could not restack commits (exit code 1).
+        You can resolve the error and try running `git restack` again.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branch", "-v"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        * (HEAD detached from 62fc20d) 024c35c amend test1.txt
+          bar                          96d1c37 create test2.txt
+          baz                          70deb1e create test3.txt
+          foo                          62fc20d create test1.txt
+          master                       f777ecc create initial.txt
+        "###);
+    }
+
+    std::fs::remove_file(&lock_file_path)?;
+    {
+        let (stdout, _stderr) = git.run(&["restack", "--in-memory"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (2 commits to rewrite in total):
+        024c35ce amend test1.txt
+          - 96d1c37a create test2.txt
+          - 70deb1e2 create test3.txt
+        Will move branch bar along with 96d1c37a create test2.txt
+        Will move branch baz along with 70deb1e2 create test3.txt
+        No merge conflicts predicted.
+        Abandoned branches:
+        - branch foo will move from 62fc20d2 create test1.txt to 024c35ce amend test1.txt
+        Attempting rebase in-memory...
+        [1/2] Committed as: 8cd7de68 create test2.txt
+        [2/2] Committed as: b9a0491a create test3.txt
+        branchless: processing 2 updates: branch bar, branch baz
+        branchless: processing 2 rewritten commits
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        branchless: processing 1 update: branch foo
+        Finished restacking branches.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 024c35ce (foo) amend test1.txt
+        |
+        o 8cd7de68 (bar) create test2.txt
+        |
+        o b9a0491a (baz) create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_stacks_independently() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+
+    // The first stack can be restacked cleanly, but the second stack has a
+    // merge conflict.
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amend test1"])?;
+    git.run(&["checkout", &test3_oid.to_string()])?;
+    git.write_file("test4", "conflicting test4 contents")?;
+    git.run(&["add", "."])?;
+    git.run(&["commit", "--amend", "-m", "amend test3 with test4 conflict"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ 1fe01b9f amend test3 with test4 conflict
+        |\
+        | o 9e8dbe91 amend test1
+        |\
+        | x 62fc20d2 (rewritten as 9e8dbe91) create test1.txt
+        | |
+        | o 96d1c37a create test2.txt
+        |
+        x 98b9119d (rewritten as 1fe01b9f) create test3.txt
+        |
+        o 2b633ed7 create test4.txt
+        "###);
+    }
+
+    // Restacking in-memory shouldn't touch the working copy.
+    let get_mtime = || git.repo_path.join("test4.txt").metadata()?.modified();
+    let mtime_before = get_mtime()?;
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["restack"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/2: 1 abandoned commit (1 commit to rewrite in total):
+        9e8dbe91 amend test1
+          - 96d1c37a create test2.txt
+        No merge conflicts predicted.
+        Stack 2/2: 1 abandoned commit (1 commit to rewrite in total):
+        1fe01b9f amend test3 with test4 conflict
+          - 2b633ed7 create test4.txt
+        Predicted merge conflict (1 conflicting file): 2b633ed7 create test4.txt
+        Attempting rebase in-memory...
+        [1/1] Committed as: 7ffa9628 create test2.txt
+        branchless: processing 1 rewritten commit
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        Restacked 1 of 2 stacks. The remaining stacks were left for manual resolution:
+        This operation would cause a merge conflict:
+        - (1 conflicting file) 2b633ed7 create test4.txt
+        To resolve merge conflicts, retry this operation with the --merge option.
+        "###);
+    }
+    assert_eq!(get_mtime()?, mtime_before);
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ 1fe01b9f amend test3 with test4 conflict
+        |\
+        | o 9e8dbe91 amend test1
+        | |
+        | o 7ffa9628 create test2.txt
+        |
+        x 98b9119d (rewritten as 1fe01b9f) create test3.txt
+        |
+        o 2b633ed7 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["restack", "--merge"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        let stdout = remove_rebase_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        1fe01b9f amend test3 with test4 conflict
+          - 2b633ed7 create test4.txt
+        Predicted merge conflict (1 conflicting file): 2b633ed7 create test4.txt
+        Attempting rebase in-memory...
+        There was a merge conflict, which currently can't be resolved when rebasing in-memory.
+        The conflicting commit was: 2b633ed7 create test4.txt
+        Trying again on-disk...
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> -c submodule.recurse=false rebase --continue
+        [git] CONFLICT (add/add): Merge conflict in test4.txt
+        Error: Could not restack commits (exit code 1).
+        You can resolve the error and try running `git restack` again.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_revset() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amended test1"])?;
+    git.run(&["checkout", &test3_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amended test3"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["restack", "message('amended test1')"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Skipped abandoned commit outside of the given stacks: 2b633ed7 create test4.txt
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        ae94dc2a amended test1
+          - 96d1c37a create test2.txt
+        No merge conflicts predicted.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 51452b55 create test2.txt
+        branchless: processing 1 rewritten commit
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o ae94dc2a amended test1
+        | |
+        | o 51452b55 create test2.txt
+        |\
+        | @ dec52eae amended test3
+        |
+        x 98b9119d (rewritten as dec52eae) create test3.txt
+        |
+        o 2b633ed7 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["restack", "message("],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Parse error for expression 'message(': expected a name, function call, or parenthesized expression, but reached the end of the expression
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_autosquash() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.write_file("test2", "fixed test2 contents\n")?;
+    git.run(&["commit", "-a", "--fixup", "HEAD"])?;
+    git.write_file("test2", "squashed test2 contents\n")?;
+    git.run(&[
+        "commit",
+        "-a",
+        "-m",
+        "squash! create test2.txt",
+        "-m",
+        "Also update test2.txt.",
+    ])?;
+    git.write_file("test1", "fixed test1 contents\n")?;
+    git.run(&["commit", "-a", "--fixup", &test1_oid.to_string()])?;
+
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amended test1"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["restack", "--autosquash"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Not folding 9c577aea fixup! create test1.txt into another commit, as its target commit is not being rewritten.
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (4 commits to rewrite in total):
+        ae94dc2a amended test1
+          - 96d1c37a create test2.txt
+          - bc71c589 fixup! create test2.txt (will be folded into 96d1c37a)
+          - 66c3c646 squash! create test2.txt (will be folded into 96d1c37a)
+          - 9c577aea fixup! create test1.txt
+        No merge conflicts predicted.
+        Attempting rebase in-memory...
+        [1/4] Committed as: 51452b55 create test2.txt
+        [2/4] Folded into: 60432ad9 create test2.txt
+        [3/4] Folded into: 69bd5568 create test2.txt
+        [4/4] Committed as: 3604239c fixup! create test1.txt
+        branchless: processing 4 rewritten commits
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ ae94dc2a amended test1
+        |
+        o 69bd5568 create test2.txt
+        |
+        o 3604239c fixup! create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%B", "-n", "1", "69bd5568"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test2.txt
+
+        Also update test2.txt.
+        "###);
+        let (stdout, _stderr) = git.run(&["show", "69bd5568:test2.txt"])?;
+        insta::assert_snapshot!(stdout, @"squashed test2 contents");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_autosquash_config() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.write_file("test2", "amended test2 contents\n")?;
+    git.run(&[
+        "commit",
+        "-a",
+        "-m",
+        "amend! create test2.txt",
+        "-m",
+        "reworded test2",
+    ])?;
+    git.commit_file("test3", 3)?;
+
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amended test1"])?;
+
+    git.run(&["config", "rebase.autoSquash", "true"])?;
+    {
+        let (stdout, _stderr) = git.run(&["restack", "--dry-run"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (3 commits to rewrite in total):
+        ae94dc2a amended test1
+          - 96d1c37a create test2.txt
+          - 3e521c66 amend! create test2.txt (will be folded into 96d1c37a)
+          - 521193ba create test3.txt
+        No merge conflicts predicted.
+        This was a dry run, so no changes were made.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["restack"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (3 commits to rewrite in total):
+        ae94dc2a amended test1
+          - 96d1c37a create test2.txt
+          - 3e521c66 amend! create test2.txt (will be folded into 96d1c37a)
+          - 521193ba create test3.txt
+        No merge conflicts predicted.
+        Attempting rebase in-memory...
+        [1/3] Committed as: 51452b55 create test2.txt
+        [2/3] Folded into: 6089879e reworded test2
+        [3/3] Committed as: 12739dcd create test3.txt
+        branchless: processing 3 rewritten commits
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ ae94dc2a amended test1
+        |
+        o 6089879e reworded test2
+        |
+        o 12739dcd create test3.txt
+        "###);
+        let (stdout, _stderr) = git.run(&["show", "6089879e:test2.txt"])?;
+        insta::assert_snapshot!(stdout, @"amended test2 contents");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_independent_stacks_in_parallel() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.commit_file("test4", 4)?;
+    git.commit_file("test5", 5)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amend test1"])?;
+
+    let restack_with_jobs = |jobs: &str| -> eyre::Result<(String, String)> {
+        let git = git.duplicate_repo()?;
+        git.run(&["config", "branchless.core.jobs", jobs])?;
+        let (stdout, _stderr) = git.run(&["restack"])?;
+        let (smartlog, _stderr) = git.run(&["smartlog"])?;
+        Ok((stdout, smartlog))
+    };
+
+    let (stdout, smartlog) = restack_with_jobs("4")?;
+    insta::assert_snapshot!(stdout, @r###"
+    Restack plan:
+    Stack 1/1: 2 abandoned commits (4 commits to rewrite in total):
+    9e8dbe91 amend test1
+      - 96d1c37a create test2.txt
+      - 70deb1e2 create test3.txt
+      - bf0d52a6 create test4.txt
+      - 848121cb create test5.txt
+    No merge conflicts predicted.
+    Attempting rebase in-memory...
+    [1/4] Committed as: 7ffa9628 create test2.txt
+    [2/4] Committed as: 1ba229e6 create test3.txt
+    [3/4] Committed as: d81aaf7f create test4.txt
+    [4/4] Committed as: cc34591a create test5.txt
+    branchless: processing 4 rewritten commits
+    In-memory rebase succeeded.
+    Finished restacking commits.
+    No abandoned branches to restack.
+    O f777ecc9 (master) create initial.txt
+    |
+    @ 9e8dbe91 amend test1
+    |\
+    | o 7ffa9628 create test2.txt
+    | |
+    | o 1ba229e6 create test3.txt
+    |
+    o d81aaf7f create test4.txt
+    |
+    o cc34591a create test5.txt
+    "###);
+    insta::assert_snapshot!(smartlog, @r###"
+    O f777ecc9 (master) create initial.txt
+    |
+    @ 9e8dbe91 amend test1
+    |\
+    | o 7ffa9628 create test2.txt
+    | |
+    | o 1ba229e6 create test3.txt
+    |
+    o d81aaf7f create test4.txt
+    |
+    o cc34591a create test5.txt
+    "###);
+
+    // The output should be the same as when the stacks are restacked one
+    // after the other.
+    assert_eq!(restack_with_jobs("1")?, (stdout, smartlog));
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_dry_run_plan() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amended test1"])?;
+
+    let (smartlog_before, _stderr) = git.run(&["smartlog"])?;
+    insta::assert_snapshot!(smartlog_before, @r###"
+    O f777ecc9 (master) create initial.txt
+    |\
+    | @ ae94dc2a amended test1
+    |
+    x 62fc20d2 (rewritten as ae94dc2a) create test1.txt
+    |\
+    | o 96d1c37a (foo) create test2.txt
+    |
+    o 4838e49b create test3.txt
+    "###);
+
+    {
+        let (stdout, _stderr) = git.run(&["restack", "--dry-run"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 2 abandoned commits (2 commits to rewrite in total):
+        ae94dc2a amended test1
+          - 4838e49b create test3.txt
+          - 96d1c37a create test2.txt
+        Will move branch foo along with 96d1c37a create test2.txt
+        No merge conflicts predicted.
+        This was a dry run, so no changes were made.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        assert_eq!(stdout, smartlog_before);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_case_variant_branches() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test3_oid = git.commit_file("test3", 3)?;
+
+    // Simulate branches which were created on a case-sensitive filesystem, and
+    // so can only coexist in `packed-refs` on a case-insensitive filesystem.
+    git.run(&["update-ref", "refs/heads/Feature-X", &test2_oid.to_string()])?;
+    git.run(&["update-ref", "refs/heads/feature-x", &test3_oid.to_string()])?;
+    git.run(&["pack-refs", "--all"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "query", "Feature-X"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        96d1c37a3d4363611c49f7e52186e189a04c531f
+        "###);
+        let (stdout, _stderr) = git.run(&["branchless", "query", "feature-x"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        98b9119d16974f372e76cb64a3b77c528fc0b18b
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "query", "FEATURE-X"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Ambiguous name: FEATURE-X (it differs only in case or Unicode normalization from: refs/heads/Feature-X, refs/heads/feature-x)
+        "###);
+    }
+
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amend test1.txt"])?;
+    {
+        let (stdout, _stderr) = git.run(&["restack"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        024c35ce amend test1.txt
+          - 96d1c37a create test2.txt
+        Will move branch Feature-X along with 96d1c37a create test2.txt
+        No merge conflicts predicted.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 8cd7de68 create test2.txt
+        branchless: processing 1 update: branch Feature-X
+        branchless: processing 1 rewritten commit
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ 024c35ce amend test1.txt
+        | |
+        | o 8cd7de68 (Feature-X) create test2.txt
+        |
+        o 98b9119d (feature-x) create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show-ref", "--heads"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        8cd7de680cafaba911d09f430d2bafb1169d6e65 refs/heads/Feature-X
+        98b9119d16974f372e76cb64a3b77c528fc0b18b refs/heads/feature-x
+        f777ecc9b0db5ed372b2615695191a8a17f79f24 refs/heads/master
+        "###);
+    }
+
+    // The rewritten branch should have been written to `packed-refs`, rather
+    // than to a loose reference file which could clobber the other branch.
+    let repo = git.get_repo()?;
+    assert!(!repo.get_path().join("refs/heads/Feature-X").exists());
+    assert!(!repo.get_path().join("refs/heads/feature-x").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_restack_branch_checked_out_in_other_worktree() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "-f", "foo"])?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+
+    let worktree_dir = tempfile::tempdir()?;
+    let worktree_path = worktree_dir.path().join("wt");
+    git.run(&["worktree", "add", worktree_path.to_str().unwrap(), "foo"])?;
+    let worktree_git = Git {
+        repo_path: worktree_path.clone(),
+        path_to_git: git.path_to_git.clone(),
+    };
+    let replace_worktree_path =
+        |output: String| output.replace(worktree_path.to_str().unwrap(), "<worktree-path>");
+
+    git.run(&["commit", "--amend", "-m", "amend test1.txt"])?;
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["restack"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(replace_worktree_path(stdout), @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        024c35ce amend test1.txt
+          - 96d1c37a create test2.txt
+        Will move branch foo along with 96d1c37a create test2.txt
+        No merge conflicts predicted.
+        These worktrees have commits checked out which would be rewritten, which would leave them pointing to the old versions of the commits:
+        - <worktree-path> (branch foo): 96d1c37a create test2.txt
+        To check out the rewritten commits in those worktrees afterwards, retry with: --update-worktrees
+        Error: Could not restack commits (exit code 1).
+        You can resolve the error and try running `git restack` again.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["restack", "--update-worktrees"])?;
+        insta::assert_snapshot!(replace_worktree_path(stdout), @r###"
+        Restack plan:
+        Stack 1/1: 1 abandoned commit (1 commit to rewrite in total):
+        024c35ce amend test1.txt
+          - 96d1c37a create test2.txt
+        Will move branch foo along with 96d1c37a create test2.txt
+        No merge conflicts predicted.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 8cd7de68 create test2.txt
+        branchless: processing 1 update: branch foo
+        branchless: processing 1 rewritten commit
+        Updated worktree at <worktree-path> to: 8cd7de68 create test2.txt
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 024c35ce amend test1.txt
+        |
+        o 8cd7de68 (foo) create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = worktree_git.run(&["status", "--short", "--branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ## foo
+        "###);
+        let (stdout, _stderr) = worktree_git.run(&["log", "--oneline", "-3"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        8cd7de6 create test2.txt
+        024c35c amend test1.txt
+        f777ecc create initial.txt
+        "###);
+    }
+
+    Ok(())
+}
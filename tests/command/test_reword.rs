@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use branchless::testing::{make_git, GitRunOptions};
+
+use crate::util::trim_lines;
+
+#[test]
+fn test_reword_middle_of_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "-b", "bar"])?;
+
+    {
+        let (stdout, _stderr) =
+            git.run(&["branchless", "reword", "foo", "-m", "reworded test2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Reworded 1 commit.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 76de37e0 create test3.txt
+        branchless: processing 1 update: branch bar
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout bar
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | x 96d1c37a (rewritten as bf4ffa82) (foo) create test2.txt
+        |
+        o bf4ffa82 reworded test2
+        |
+        @ 76de37e0 (bar) create test3.txt
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        branchless: processing 1 update: branch foo
+        Finished restacking branches.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o bf4ffa82 (foo) reworded test2
+        |
+        @ 76de37e0 (bar) create test3.txt
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o bf4ffa82 (foo) reworded test2
+        |
+        @ 76de37e0 (bar) create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s%d", &test1_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test1.txt
+        create initial.txt (master)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s%d", "bar"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test3.txt (HEAD -> bar)
+        reworded test2 (foo)
+        create test1.txt
+        create initial.txt (master)
+        "###);
+    }
+
+    // The working copy is untouched, since the trees are unchanged.
+    {
+        let (stdout, _stderr) = git.run(&["diff", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git reword foo -m 'reworded test2' (transaction 10)
+        Will apply these actions:
+        1. Check out from 76de37e0 create test3.txt
+                       to 70deb1e2 create test3.txt
+        2. Move branch foo from bf4ffa82 reworded test2
+                             to 96d1c37a create test2.txt
+        3. Rewrite commit 76de37e0 create test3.txt
+                      as 70deb1e2 create test3.txt
+        4. Move branch bar from 76de37e0 create test3.txt
+                             to 70deb1e2 create test3.txt
+        5. Rewrite commit bf4ffa82 reworded test2
+                      as 96d1c37a create test2.txt
+        branchless: running command: <git-executable> checkout 70deb1e28791d8e7dd5a1f0c871a51b91282562f --detach
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | x 96d1c37a (rewritten as bf4ffa82) create test2.txt
+        | |
+        | % 70deb1e2 (rewritten as 76de37e0) create test3.txt
+        |
+        o bf4ffa82 (foo) reworded test2
+        |
+        o 76de37e0 (bar) create test3.txt
+        Applied 5 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s%d", "bar"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test3.txt (HEAD, bar)
+        create test2.txt (foo)
+        create test1.txt
+        create initial.txt (master)
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_reword_multiple_commits() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "reword", "HEAD", "HEAD~3", "HEAD~1"],
+            &GitRunOptions {
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert(
+                        "GIT_EDITOR".to_string(),
+                        "sed -i -e 's/^create/reword/'".to_string(),
+                    );
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Reworded 3 commits.
+        Attempting rebase in-memory...
+        [1/3] Committed as: e5535055 create test2.txt
+        [2/3] Committed as: 0726683e reword test3.txt
+        [3/3] Committed as: ea8effb6 reword test4.txt
+        branchless: processing 3 rewritten commits
+        branchless: running command: <git-executable> checkout ea8effb65d780faabf6411043203558e9f37569d
+        O f777ecc9 (master) create initial.txt
+        |
+        o 734847fd reword test1.txt
+        |
+        o e5535055 create test2.txt
+        |
+        o 0726683e reword test3.txt
+        |
+        @ ea8effb6 reword test4.txt
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 734847fd reword test1.txt
+        |
+        o e5535055 create test2.txt
+        |
+        o 0726683e reword test3.txt
+        |
+        @ ea8effb6 reword test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        reword test4.txt
+        reword test3.txt
+        create test2.txt
+        reword test1.txt
+        create initial.txt
+        "###);
+    }
+
+    // An empty message aborts the reword without changing anything.
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "reword"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert("GIT_EDITOR".to_string(), "printf '' >".to_string());
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Aborting reword due to empty commit message.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_reword_commit_msg_hook() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.write_hook(
+        "commit-msg",
+        r#"#!/bin/sh
+if ! grep -q '^\[[A-Z]*-[0-9]*\]' "$1"; then
+    echo "commit-msg: message must start with an issue tag" >&2
+    exit 1
+fi
+"#,
+    )?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "reword", "-m", "reworded test1"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"commit-msg: message must start with an issue tag");
+        insta::assert_snapshot!(stdout, @"Aborting reword because the commit-msg hook failed.");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "reword",
+            "--signoff",
+            "-m",
+            "[ABC-123] reworded test1",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Reworded 1 commit.
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 021984ee [ABC-123] reworded test1
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "-1", "--format=%B"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        [ABC-123] reworded test1
+
+        Signed-off-by: Testy McTestface <test@example.com>
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "reword",
+            "--no-verify",
+            "-m",
+            "reworded test1",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Reworded 1 commit.
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        @ d93f3da6 reworded test1
+        "###);
+    }
+
+    Ok(())
+}
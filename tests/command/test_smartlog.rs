@@ -1,7 +1,11 @@
+use branchless::core::eventlog::EventLogDb;
 use branchless::testing::{
-    make_git, make_git_with_remote_repo, GitInitOptions, GitRunOptions, GitWrapperWithRemoteRepo,
+    make_git, make_git_with_remote_repo, Git, GitInitOptions, GitRunOptions,
+    GitWrapperWithRemoteRepo,
 };
 
+use crate::util::{run_in_pty, PtyAction};
+
 #[test]
 fn test_init_smartlog() -> eyre::Result<()> {
     let git = make_git()?;
@@ -17,6 +21,86 @@ fn test_init_smartlog() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_smartlog_not_initialized() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "smartlog"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"git-branchless has not been initialized in this repository; run `git branchless init`");
+        insta::assert_snapshot!(stdout, @"");
+    }
+    assert!(!git.repo_path.join(".git").join("branchless").exists());
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "-y", "smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Created config file at <repo-path>/.git/branchless/config
+        Auto-detected your main branch as: master
+        If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Imported 2 events from existing history.
+        Successfully installed git-branchless.
+        To uninstall, run: git branchless init --uninstall
+        @ f777ecc9 (master) create initial.txt
+        "###);
+    }
+    assert!(git.get_repo()?.is_branchless_initialized());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_smartlog_not_initialized_prompt() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+
+    let exit_status = run_in_pty(
+        &git,
+        &["branchless", "smartlog"],
+        &[
+            PtyAction::WaitUntilContains(
+                "git-branchless has not been initialized in this repository",
+            ),
+            PtyAction::WaitUntilContains("Initialize git-branchless now? [yN] "),
+            PtyAction::Write("y\n"),
+            PtyAction::WaitUntilContains("Auto-detected your main branch as: master"),
+            PtyAction::WaitUntilContains("f777ecc9 (master) create initial.txt"),
+        ],
+    )?;
+    assert!(exit_status.success());
+    assert!(git.get_repo()?.is_branchless_initialized());
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @"@ f777ecc9 (master) create initial.txt");
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_show_reachable_commit() -> eyre::Result<()> {
     let git = make_git()?;
@@ -302,6 +386,67 @@ fn test_custom_main_branch() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_multiple_main_branches() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.run(&["branch", "release-1.x"])?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "release-1.x"])?;
+    git.commit_file("test3", 3)?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+    git.run(&["config", "--add", "branchless.core.mainBranches", "master"])?;
+    git.run(&[
+        "config",
+        "--add",
+        "branchless.core.mainBranches",
+        "release-1.x",
+    ])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        : o 96d1c37a create test2.txt
+        :
+        O 98b9119d (release-1.x) create test3.txt
+        |
+        @ 2b633ed7 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "query", "public()"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        f777ecc9b0db5ed372b2615695191a8a17f79f24
+        62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+        98b9119d16974f372e76cb64a3b77c528fc0b18b
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["reword", "release-1.x", "--message", "foo"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Refusing to rewrite 98b9119d create test3.txt: it is a public commit on release-1.x.
+        (Pass --force-rewrite-public to rewrite public commits anyway)
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_main_remote_branch() -> eyre::Result<()> {
     let GitWrapperWithRemoteRepo {
@@ -478,3 +623,817 @@ fn test_active_non_head_main_branch_commit() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_smartlog_unborn_head() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        run_branchless_init: false,
+    })?;
+    git.run(&["branchless", "init", "--main-branch", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        No commits have been made in this repository yet.
+        "###);
+    }
+
+    {
+        // Switching branches doesn't move `HEAD`, which is still unborn.
+        git.run(&["checkout", "-b", "foo"])?;
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        No commits have been made in this repository yet.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_revset() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |\
+        | o 96d1c37a create test2.txt
+        | |
+        | o 70deb1e2 create test3.txt
+        |
+        @ bf0d52a6 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog", "stack()"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        @ bf0d52a6 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog", "message(test3) | main()"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        o 70deb1e2 create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["smartlog", "nonexistent"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Commit not found: nonexistent
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_color_and_glyphs_settings() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+
+    let run_smartlog = |args: &[&str], env: &[(&str, &str)]| -> eyre::Result<(bool, bool)> {
+        let args = [&["branchless"], args].concat();
+        let (stdout, _stderr) = git.run_with_options(
+            &args,
+            &GitRunOptions {
+                env: env
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                ..Default::default()
+            },
+        )?;
+        let has_escape_codes = stdout.contains('\u{1b}');
+        let has_unicode_glyphs = stdout.contains('◇');
+        Ok((has_escape_codes, has_unicode_glyphs))
+    };
+
+    assert_eq!(run_smartlog(&["smartlog"], &[])?, (false, false));
+    assert_eq!(
+        run_smartlog(&["smartlog", "--color=always"], &[])?,
+        (true, true)
+    );
+    assert_eq!(
+        run_smartlog(&["--color=always", "smartlog"], &[])?,
+        (true, true)
+    );
+    assert_eq!(
+        run_smartlog(&["smartlog", "--color=always", "--glyphs=ascii"], &[])?,
+        (true, false)
+    );
+    assert_eq!(
+        run_smartlog(&["smartlog", "--glyphs=unicode"], &[])?,
+        (false, true)
+    );
+    assert_eq!(
+        run_smartlog(&["smartlog"], &[("CLICOLOR_FORCE", "1")])?,
+        (true, true)
+    );
+    assert_eq!(
+        run_smartlog(&["smartlog", "--color=never"], &[("CLICOLOR_FORCE", "1")])?,
+        (false, false)
+    );
+    assert_eq!(
+        run_smartlog(&["smartlog", "--color=auto"], &[("NO_COLOR", "1")])?,
+        (false, false)
+    );
+
+    git.run(&["config", "branchless.output.color", "always"])?;
+    git.run(&["config", "branchless.output.glyphs", "ascii"])?;
+    assert_eq!(run_smartlog(&["smartlog"], &[])?, (true, false));
+    assert_eq!(
+        run_smartlog(&["smartlog", "--color=never", "--glyphs=unicode"], &[])?,
+        (false, true)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_annotation_command() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+
+    // Annotate only some of the commits, and emit a malformed line.
+    git.run(&[
+        "config",
+        "branchless.smartlog.annotationCmd",
+        &format!(
+            "while read oid; do \
+                case $oid in \
+                    {test1}) printf '%s\\tCI: passed\\n' $oid;; \
+                    {test3}) printf '%s\\treview: pending\\n' $oid; printf '%s\\tCI: failed\\n' $oid;; \
+                esac; \
+            done; \
+            echo 'not an annotation'",
+            test1 = test1_oid,
+            test3 = test3_oid,
+        ),
+    ])?;
+    {
+        let (stdout, stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stderr, @"");
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt CI: passed
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt review: pending CI: failed
+        "###);
+    }
+
+    git.run(&[
+        "config",
+        "branchless.smartlog.annotationCmd",
+        "exec sleep 5",
+    ])?;
+    git.run(&["config", "branchless.smartlog.annotationTimeoutMs", "100"])?;
+    {
+        let (stdout, stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stderr, @"branchless: smartlog annotations omitted: `exec sleep 5` did not finish within 100ms");
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt
+        "###);
+    }
+
+    git.run(&["config", "branchless.smartlog.annotationCmd", "exit 2"])?;
+    {
+        let (stdout, stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stderr, @"branchless: smartlog annotations omitted: `exit 2` failed with exit code 2");
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_shallow_clone() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+
+    {
+        original_repo.init_repo()?;
+        original_repo.commit_file("test1", 1)?;
+        original_repo.commit_file("test2", 2)?;
+        original_repo.commit_file("test3", 3)?;
+        original_repo.run(&[
+            "clone",
+            "--depth=1",
+            &format!("file://{}", original_repo.repo_path.to_str().unwrap()),
+            cloned_repo.repo_path.to_str().unwrap(),
+        ])?;
+    }
+
+    {
+        cloned_repo.init_repo_with_options(&GitInitOptions {
+            make_initial_commit: false,
+            ..Default::default()
+        })?;
+        cloned_repo.detach_head()?;
+        cloned_repo.commit_file("test4", 4)?;
+        let (stdout, _stderr) = cloned_repo.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O 70deb1e2 (master, remote origin/master) create test3.txt
+        |
+        @ 355e173b create test4.txt
+        "###);
+    }
+
+    {
+        cloned_repo.run(&["fetch", "--unshallow"])?;
+        let (stdout, _stderr) = cloned_repo.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 70deb1e2 (master, remote origin/master) create test3.txt
+        |
+        @ 355e173b create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_debug_timing() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    for i in 1..=10 {
+        git.commit_file(&format!("main{}", i), i)?;
+    }
+    git.detach_head()?;
+    git.commit_file("test1", 11)?;
+    git.commit_file("test2", 12)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test3", 13)?;
+    git.run(&["checkout", "foo"])?;
+    git.commit_file("test4", 14)?;
+    // Sync the commit graph, so that the commits loaded to do so aren't
+    // included below.
+    git.run(&["smartlog"])?;
+
+    {
+        let (stdout, stderr) = git.run(&["smartlog", "--debug-timing"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 16d6f705 (master) create main10.txt
+        |
+        o e6b802ec create test1.txt
+        |
+        o 53f23dc4 create test2.txt
+        |\
+        | o f2e08f20 create test3.txt
+        |
+        @ 7dabc0cc (foo) create test4.txt
+        "###);
+
+        // Durations vary between runs, so only check the number of commits
+        // loaded by each stage.
+        let stderr: String = stderr
+            .lines()
+            .map(|line| match line.split_once(" in ") {
+                Some((line, _duration)) => format!("{}\n", line),
+                None => format!("{}\n", line),
+            })
+            .collect();
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: timing: replay events: 0 commits loaded, 0 events replayed
+        branchless: timing: sync commit graph: 0 commits loaded
+        branchless: timing: make graph: 2 commits loaded
+        branchless: timing: render graph: 3 commits loaded
+        branchless: timing: merge-bases: 0 computed, 3 cached
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_parallel_descriptors() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&[
+        "commit",
+        "--amend",
+        "-m",
+        "amend test1\n\nDifferential Revision: D123",
+    ])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["commit", "--amend", "-m", "amend test2"])?;
+    git.run(&[
+        "config",
+        "branchless.commitDescriptors.differentialRevision",
+        "true",
+    ])?;
+
+    let smartlog_with_jobs = |jobs: &str| -> eyre::Result<String> {
+        git.run(&["config", "branchless.core.jobs", jobs])?;
+        let (stdout, _stderr) = git.run(&["smartlog", "--hidden"])?;
+        Ok(stdout)
+    };
+
+    let stdout = smartlog_with_jobs("4")?;
+    insta::assert_snapshot!(stdout, @r###"
+    O f777ecc9 (master) create initial.txt
+    |\
+    | o b04ccd89 D123 amend test1
+    | |\
+    | | @ 25e90714 amend test2
+    | |
+    | x 310631dc (rewritten as 25e90714) create test2.txt
+    |
+    x 62fc20d2 (rewritten as b04ccd89) create test1.txt
+    "###);
+
+    // The descriptors should be the same as when they're computed one commit
+    // at a time.
+    assert_eq!(smartlog_with_jobs("1")?, stdout);
+
+    Ok(())
+}
+
+/// Run `git smartlog` and return its output, along with the number of events
+/// which had to be replayed to render it.
+fn run_smartlog_counting_events(git: &Git) -> eyre::Result<(String, usize)> {
+    let (stdout, stderr) = git.run(&["smartlog", "--debug-timing"])?;
+    let num_events = stderr
+        .lines()
+        .find_map(|line| {
+            let line = line.strip_prefix("branchless: timing: replay events: ")?;
+            let (_commits, events) = line.split_once(", ")?;
+            let (num_events, _rest) = events.split_once(' ')?;
+            num_events.parse().ok()
+        })
+        .ok_or_else(|| eyre::eyre!("Could not find number of replayed events in: {}", stderr))?;
+    Ok((stdout, num_events))
+}
+
+/// Run `git smartlog` and return its output, along with the number of
+/// merge-bases which had to be computed to render it, and the number which were
+/// looked up in the merge-base cache instead.
+fn run_smartlog_counting_merge_bases(git: &Git) -> eyre::Result<(String, usize, usize)> {
+    let (stdout, stderr) = git.run(&["smartlog", "--debug-timing"])?;
+    let (num_computed, num_cached) = stderr
+        .lines()
+        .find_map(|line| {
+            let line = line.strip_prefix("branchless: timing: merge-bases: ")?;
+            let (num_computed, num_cached) = line.split_once(" computed, ")?;
+            let num_cached = num_cached.strip_suffix(" cached")?;
+            Some((num_computed.parse().ok()?, num_cached.parse().ok()?))
+        })
+        .ok_or_else(|| eyre::eyre!("Could not find number of merge-bases in: {}", stderr))?;
+    Ok((stdout, num_computed, num_cached))
+}
+
+/// Get the total number of events in the event log.
+fn count_events(git: &Git) -> eyre::Result<usize> {
+    let conn = git.get_repo()?.get_db_conn()?;
+    let events = EventLogDb::new(&conn)?.get_events()?;
+    Ok(events.len())
+}
+
+/// Run `git smartlog` without using the smartlog cache.
+fn run_smartlog_cold(git: &Git) -> eyre::Result<String> {
+    let conn = git.get_repo()?.get_db_conn()?;
+    EventLogDb::new(&conn)?.invalidate_smartlog_cache()?;
+    let (stdout, _stderr) = git.run(&["smartlog"])?;
+    Ok(stdout)
+}
+
+#[test]
+fn test_smartlog_incremental_cache() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["smartlog"])?;
+
+    git.run(&["commit", "--amend", "-m", "amend test2"])?;
+    {
+        let (stdout, num_events) = run_smartlog_counting_events(&git)?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 5ff9fd3b amend test2
+        "###);
+        // Only the events from the amend should be replayed.
+        assert_eq!(num_events, 3);
+        assert_eq!(run_smartlog_cold(&git)?, stdout);
+    }
+
+    {
+        let (_stdout, num_events) = run_smartlog_counting_events(&git)?;
+        assert_eq!(num_events, 0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_cache_stale_or_corrupt() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["commit", "--amend", "-m", "amend test2"])?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["hide", &test1_oid.to_string()])?;
+
+    let expected = run_smartlog_cold(&git)?;
+    insta::assert_snapshot!(expected, @r###"
+    O f777ecc9 (master) create initial.txt
+    |
+    x 62fc20d2 (manually hidden) create test1.txt
+    |\
+    | o 5ff9fd3b amend test2
+    |
+    @ 4838e49b create test3.txt
+    "###);
+
+    let conn = git.get_repo()?.get_db_conn()?;
+    for sql in [
+        // Stale: events which have already been applied to the cache are
+        // replayed again.
+        "UPDATE smartlog_cache_info SET last_rowid = 1",
+        // Stale: events which aren't in the event log anymore were applied.
+        "UPDATE smartlog_cache_info SET last_rowid = last_rowid + 100",
+        "UPDATE smartlog_cache_info SET last_retained_rowid = last_rowid + 100",
+        // Written by a different version.
+        "UPDATE smartlog_cache_info SET version = version + 1",
+        // Corrupt.
+        "UPDATE smartlog_cache_commits SET oid = 'not-an-oid' WHERE rowid = (SELECT MIN(rowid) FROM smartlog_cache_commits)",
+        "UPDATE smartlog_cache_commits SET type = 'not-a-type'",
+        "UPDATE smartlog_cache_refs SET oid = 'not-an-oid'",
+    ] {
+        let (stdout, _num_events) = run_smartlog_counting_events(&git)?;
+        assert_eq!(stdout, expected);
+
+        conn.execute(sql, rusqlite::params![])?;
+        let (stdout, _num_events) = run_smartlog_counting_events(&git)?;
+        assert_eq!(stdout, expected, "after corrupting cache with: {}", sql);
+    }
+
+    // The cache is rebuilt when the main branch moves.
+    {
+        let (_stdout, num_events) = run_smartlog_counting_events(&git)?;
+        assert_eq!(num_events, 0);
+        git.run(&["branch", "-f", "master", "HEAD"])?;
+        let (stdout, num_events) = run_smartlog_counting_events(&git)?;
+        assert_eq!(num_events, count_events(&git)?);
+        assert_eq!(run_smartlog_cold(&git)?, stdout);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_cache_undo_and_repair() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["commit", "--amend", "-m", "amend test2"])?;
+    git.run(&["smartlog"])?;
+
+    git.run(&["undo", "-y"])?;
+    {
+        let (stdout, num_events) = run_smartlog_counting_events(&git)?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | % 5ff9fd3b (rewritten as 96d1c37a) amend test2
+        |
+        o 96d1c37a create test2.txt
+        "###);
+        assert_eq!(num_events, count_events(&git)?);
+        assert_eq!(run_smartlog_cold(&git)?, stdout);
+    }
+
+    {
+        let conn = git.get_repo()?.get_db_conn()?;
+        conn.execute(
+            "
+INSERT INTO event_log
+(timestamp, type, event_tx_id, old_ref, new_ref, ref_name, message)
+VALUES
+(1.0, 'commit', 999, 'not-an-oid', NULL, NULL, NULL)
+",
+            rusqlite::params![],
+        )?;
+    }
+    git.run(&["branchless", "repair", "--apply"])?;
+    {
+        let (stdout, num_events) = run_smartlog_counting_events(&git)?;
+        assert_eq!(num_events, count_events(&git)?);
+        assert_eq!(run_smartlog_cold(&git)?, stdout);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_merge_base_cache() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+
+    let expected = {
+        let (stdout, num_computed, num_cached) = run_smartlog_counting_merge_bases(&git)?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        |
+        @ fe65c1fe create test2.txt
+        "###);
+        assert_eq!((num_computed, num_cached), (3, 0));
+        stdout
+    };
+
+    {
+        // The merge-bases computed by the previous invocation are reused.
+        let (stdout, num_computed, num_cached) = run_smartlog_counting_merge_bases(&git)?;
+        assert_eq!(stdout, expected);
+        assert_eq!((num_computed, num_cached), (0, 3));
+    }
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+    {
+        // The merge-bases with the old main branch commit don't apply anymore.
+        let (stdout, num_computed, num_cached) = run_smartlog_counting_merge_bases(&git)?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        |\
+        | o fe65c1fe create test2.txt
+        |
+        @ 98b9119d (master) create test3.txt
+        "###);
+        assert_eq!((num_computed, num_cached), (4, 0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_since() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test3", 5)?;
+    git.commit_file("test4", 6)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 96d1c37a create test2.txt
+        |
+        o 2e03c11d create test3.txt
+        |
+        @ 3b93df13 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog", "--since", "2020-10-29 16:00:00 +0000"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 2e03c11d create test3.txt
+        |
+        @ 3b93df13 create test4.txt
+        (2 older commits hidden; use --since to adjust)
+        "###);
+    }
+
+    // Older ancestors of recent commits are still drawn.
+    {
+        let (stdout, _stderr) = git.run(&["smartlog", "--since", "2020-10-29 18:00:00 +0000"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 2e03c11d create test3.txt
+        |
+        @ 3b93df13 create test4.txt
+        (2 older commits hidden; use --since to adjust)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "smartlog",
+            "--since",
+            "2020-10-29 14:00:00 +0000",
+            "draft() - stack()",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        "###);
+    }
+
+    // Rewriting a commit counts as recent activity, even though its committer
+    // date is unchanged.
+    git.run(&["checkout", &test2_oid.to_string()])?;
+    git.run(&["commit", "--amend", "-m", "amended test2"])?;
+    git.run(&["checkout", "master"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog", "--since", "2 weeks ago"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o cb8137ad amended test2
+        (2 older commits hidden; use --since to adjust)
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["smartlog", "--since", "last tuesday"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        Could not parse date for --since: "last tuesday" (expected e.g. '2021-06-01', '2021-06-01 12:00:00 +0100' or '2 weeks ago')
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_smartlog_mark_related_commits() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.write_file("test2", "uncommitted changes")?;
+
+    {
+        // Commits aren't marked by default.
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt
+        "###);
+    }
+
+    git.run(&["config", "branchless.smartlog.markRelatedCommits", "true"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt *
+        |
+        @ 70deb1e2 create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog", "--touches", "test3.txt"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt *
+        "###);
+    }
+
+    {
+        // The paths touched by each commit are cached, so tampering with the
+        // cache entry affects the output.
+        let conn = git.get_repo()?.get_db_conn()?;
+        let num_rows = conn.execute(
+            "UPDATE touched_paths_cache SET paths = '[]' WHERE commit_oid = ?",
+            rusqlite::params![test2_oid.to_string()],
+        )?;
+        assert_eq!(num_rows, 1);
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use branchless::testing::{make_git, GitRunOptions};
+
+use crate::util::trim_lines;
+
+#[test]
+fn test_split_middle_of_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.write_file("test2", "test2 contents\n")?;
+    git.write_file("test3", "test3 contents\n")?;
+    git.run(&["add", "."])?;
+    git.run(&["commit", "-m", "create test2.txt and test3.txt"])?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "-b", "bar"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "split",
+            "foo",
+            "-m",
+            "create test3.txt",
+            "--",
+            "test2.txt",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Split commit ee62bcea create test2.txt and test3.txt into:
+        - 4468c17f create test2.txt and test3.txt
+        - 2bbac500 create test3.txt
+        Attempting rebase in-memory...
+        [1/1] Committed as: ab1e2bef create test4.txt
+        branchless: processing 1 update: branch bar
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout bar
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | o 4468c17f create test2.txt and test3.txt
+        | |
+        | o 2bbac500 create test3.txt
+        | |
+        | @ ab1e2bef (bar) create test4.txt
+        |
+        x ee62bcea (rewritten as 2bbac500) (foo) create test2.txt and test3.txt
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        branchless: processing 1 update: branch foo
+        Finished restacking branches.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 4468c17f create test2.txt and test3.txt
+        |
+        o 2bbac500 (foo) create test3.txt
+        |
+        @ ab1e2bef (bar) create test4.txt
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 4468c17f create test2.txt and test3.txt
+        |
+        o 2bbac500 (foo) create test3.txt
+        |
+        @ ab1e2bef (bar) create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=%s", "--name-only", "foo^"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test2.txt and test3.txt
+
+        test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=%s", "--name-only", "foo"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test3.txt
+
+        test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["ls-tree", "--name-only", "foo^"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        initial.txt
+        test1.txt
+        test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s%d", "bar"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test4.txt (HEAD -> bar)
+        create test3.txt (foo)
+        create test2.txt and test3.txt
+        create test1.txt
+        create initial.txt (master)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git split foo -m 'create test3.txt' -- test2.txt (transaction 10)
+        Will apply these actions:
+        1. Check out from ab1e2bef create test4.txt
+                       to f58f0168 create test4.txt
+        2. Move branch foo from 2bbac500 create test3.txt
+                             to ee62bcea create test2.txt and test3.txt
+        3. Rewrite commit ab1e2bef create test4.txt
+                      as f58f0168 create test4.txt
+        4. Move branch bar from ab1e2bef create test4.txt
+                             to f58f0168 create test4.txt
+        5. Rewrite commit 2bbac500 create test3.txt
+                      as ee62bcea create test2.txt and test3.txt
+        6. Hide commit 4468c17f create test2.txt and test3.txt
+
+        branchless: running command: <git-executable> checkout f58f016830785d1df3c10fa587f9de34b3e8721f --detach
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | o 4468c17f create test2.txt and test3.txt
+        | |
+        | o 2bbac500 (foo) create test3.txt
+        | |
+        | o ab1e2bef (bar) create test4.txt
+        |
+        x ee62bcea (rewritten as 2bbac500) create test2.txt and test3.txt
+        |
+        % f58f0168 (rewritten as ab1e2bef) create test4.txt
+        Applied 6 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o ee62bcea (foo) create test2.txt and test3.txt
+        |
+        @ f58f0168 (bar) create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_split_invalid_paths() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.write_file("test1", "test1 contents\n")?;
+    git.write_file("test2", "test2 contents\n")?;
+    git.run(&["add", "."])?;
+    git.run(&["commit", "-m", "create test1.txt and test2.txt"])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "split", "--", "nonexistent.txt"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        The given paths don't match any of the changes in commit: fd1391c4 create test1.txt and test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "split", "--", "test1.txt", "test2.txt"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        The given paths match all of the changes in commit: fd1391c4 create test1.txt and test2.txt
+        Leave some of the changes unmatched to split them into a separate commit.
+        "###);
+    }
+
+    // The message for the second commit is prompted for if not given.
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "split", "--", "test1.txt"],
+            &GitRunOptions {
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert(
+                        "GIT_EDITOR".to_string(),
+                        "printf 'create test2.txt\\n' >".to_string(),
+                    );
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Split commit fd1391c4 create test1.txt and test2.txt into:
+        - 6e31c4c2 create test1.txt and test2.txt
+        - a1ab92e1 create test2.txt
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 6e31c4c2 create test1.txt and test2.txt
+        |
+        @ a1ab92e1 create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_split_commit_hooks_and_template() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.detach_head()?;
+    git.write_file("test1", "contents1\n")?;
+    git.write_file("test2", "contents2\n")?;
+    git.run(&["add", "."])?;
+    git.run(&["commit", "-m", "[ABC-123] create test1.txt and test2.txt"])?;
+
+    git.write_file("template", "[ABC-123] \n")?;
+    git.run(&["config", "commit.template", "template.txt"])?;
+    git.write_hook(
+        "commit-msg",
+        r#"#!/bin/sh
+if ! grep -q '^\[[A-Z]*-[0-9]*\]' "$1"; then
+    echo "commit-msg: message must start with an issue tag" >&2
+    exit 1
+fi
+"#,
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "split", "--", "test2.txt"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert("GIT_EDITOR".to_string(), ":".to_string());
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @"Aborting split because the commit message template was not edited.");
+    }
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &[
+                "branchless",
+                "split",
+                "-m",
+                "create test2.txt",
+                "--",
+                "test2.txt",
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"commit-msg: message must start with an issue tag");
+        insta::assert_snapshot!(stdout, @"Aborting split because the commit-msg hook failed.");
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "split", "--", "test2.txt"],
+            &GitRunOptions {
+                env: {
+                    let mut env = HashMap::new();
+                    env.insert(
+                        "GIT_EDITOR".to_string(),
+                        "printf '[ABC-456] create test2.txt\\n' >".to_string(),
+                    );
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Split commit 13f0687b [ABC-123] create test1.txt and test2.txt into:
+        - 441a6d51 [ABC-123] create test1.txt and test2.txt
+        - 98f506dd [ABC-456] create test2.txt
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 441a6d51 [ABC-123] create test1.txt and test2.txt
+        |
+        @ 98f506dd [ABC-456] create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+use crate::util::trim_lines;
+
+#[test]
+fn test_squash_top_of_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "-b", "bar"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "squash"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Squashed 2 commits into 88439b08 create test2.txt.
+        No abandoned commits to restack.
+        branchless: processing 1 update: branch foo
+        Finished restacking branches.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 88439b08 (bar, foo) create test2.txt
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ 88439b08 (bar, foo) create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=%B", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test2.txt
+
+        create test3.txt
+
+
+        test2.txt
+        test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s%d"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test2.txt (HEAD -> bar, foo)
+        create test1.txt
+        create initial.txt (master)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git squash (transaction 10)
+        Will apply these actions:
+        1. Check out from 88439b08 create test2.txt
+                       to 70deb1e2 create test3.txt
+        2. Move branch foo from 88439b08 create test2.txt
+                             to 96d1c37a create test2.txt
+        3. Move branch bar from 88439b08 create test2.txt
+                             to 70deb1e2 create test3.txt
+        4. Rewrite commit 88439b08 create test2.txt
+                      as 70deb1e2 create test3.txt
+        5. Rewrite commit 88439b08 create test2.txt
+                      as 96d1c37a create test2.txt
+        branchless: running command: <git-executable> checkout 70deb1e28791d8e7dd5a1f0c871a51b91282562f --detach
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | o 88439b08 (bar, foo) create test2.txt
+        |
+        x 96d1c37a (rewritten as 88439b08) create test2.txt
+        |
+        % 70deb1e2 (rewritten as 88439b08) create test3.txt
+        Applied 5 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s%d", "bar"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        create test3.txt (HEAD, bar)
+        create test2.txt (foo)
+        create test1.txt
+        create initial.txt (master)
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_squash_range_with_descendants() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "-b", "foo", &test2_oid.to_string()])?;
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "squash",
+            &test2_oid.to_string(),
+            &test3_oid.to_string(),
+            "-m",
+            "squashed",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Squashed 2 commits into bd780422 squashed.
+        branchless: running command: <git-executable> checkout bd78042293d6fb4432b53acd041739890b8d7da6
+        Attempting rebase in-memory...
+        [1/1] Committed as: 35df7c9b create test4.txt
+        branchless: processing 1 rewritten commit
+        In-memory rebase succeeded.
+        Finished restacking commits.
+        branchless: processing 1 update: branch foo
+        Finished restacking branches.
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ bd780422 (foo) squashed
+        |
+        o 35df7c9b create test4.txt
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        @ bd780422 (foo) squashed
+        |
+        o 35df7c9b create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["show", "--format=%B", "--name-only", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        squashed
+
+
+        test2.txt
+        test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%s%d"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        squashed (HEAD -> foo)
+        create test1.txt
+        create initial.txt (master)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_squash_refusals() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "squash", &test1_oid.to_string()],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Refusing to rewrite f777ecc9 create initial.txt: it is a public commit on master.
+        (Pass --force-rewrite-public to rewrite public commits anyway)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &[
+                "branchless",
+                "squash",
+                &test1_oid.to_string(),
+                &test3_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Cannot squash these commits, because they don't form a contiguous run of commits:
+        - 62fc20d2 create test1.txt
+        - 70deb1e2 create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
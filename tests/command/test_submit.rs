@@ -0,0 +1,342 @@
+use branchless::testing::{make_git, GitRunOptions, GitWrapper};
+
+/// Create a repository with a bare repository as its `origin` remote, onto
+/// which the main branch has been pushed.
+fn make_git_with_bare_remote() -> eyre::Result<(GitWrapper, GitWrapper)> {
+    let remote = make_git()?;
+    remote.run(&["init", "--bare"])?;
+    remote.run(&["config", "user.name", "Testy McTestface"])?;
+    remote.run(&["config", "user.email", "test@example.com"])?;
+
+    let git = make_git()?;
+    git.init_repo()?;
+    git.run(&[
+        "remote",
+        "add",
+        "origin",
+        remote.repo_path.to_str().unwrap(),
+    ])?;
+    git.run(&["push", "origin", "master"])?;
+
+    Ok((git, remote))
+}
+
+#[test]
+fn test_submit_create_and_update() -> eyre::Result<()> {
+    let (git, remote) = make_git_with_bare_remote()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "bar"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "submit"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Skipped branch foo: it does not exist on origin (use --create to push it)
+        Skipped branch bar: it does not exist on origin (use --create to push it)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "submit", "--create", "--dry-run"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Would create branch foo on origin at 62fc20d2
+        Would create branch bar on origin at 96d1c37a
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "submit", "--create"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> push --porcelain origin --force-with-lease=refs/heads/foo: --force-with-lease=refs/heads/bar: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e:refs/heads/foo 96d1c37a3d4363611c49f7e52186e189a04c531f:refs/heads/bar
+        Created branch foo on origin at 62fc20d2
+        Created branch bar on origin at 96d1c37a
+        Pushed 2 branches to origin.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["rev-parse", "--abbrev-ref", "bar@{upstream}"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        origin/bar
+        "###);
+    }
+
+    git.run(&["checkout", "bar"])?;
+    git.run(&["commit", "--amend", "-m", "amended test2"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "submit", "--dry-run"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Branch foo is already up-to-date on origin
+        Would update branch bar on origin: 96d1c37a..cb8137ad
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "submit"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Branch foo is already up-to-date on origin
+        branchless: running command: <git-executable> push --porcelain origin --force-with-lease=refs/heads/bar:96d1c37a3d4363611c49f7e52186e189a04c531f cb8137adb1d2a166d27eeaf6bfc39a374748852c:refs/heads/bar
+        Updated branch bar on origin: 96d1c37a..cb8137ad
+        Pushed 1 branch to origin.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = remote.run(&["log", "--oneline", "bar"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        cb8137a amended test2
+        62fc20d create test1.txt
+        f777ecc create initial.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_submit_lease_rejection() -> eyre::Result<()> {
+    let (git, remote) = make_git_with_bare_remote()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["branchless", "submit", "--create"])?;
+
+    // Simulate someone else pushing a commit to the branch.
+    let (tree_oid, _stderr) = remote.run(&["rev-parse", "master^{tree}"])?;
+    let (remote_commit_oid, _stderr) = remote.run(&[
+        "commit-tree",
+        "-p",
+        "master",
+        "-m",
+        "remote change",
+        tree_oid.trim(),
+    ])?;
+    remote.run(&["update-ref", "refs/heads/foo", remote_commit_oid.trim()])?;
+
+    git.run(&["checkout", "foo"])?;
+    git.run(&["commit", "--amend", "-m", "amended test1"])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "submit"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> push --porcelain origin --force-with-lease=refs/heads/foo:62fc20d2a290daea0d52bdc2ed2ad4be6491010e ae94dc2a748bc0965c88fcf3edac2e30074ff7e2:refs/heads/foo
+        Rejected branch foo: [rejected] (stale info)
+        "###);
+    }
+
+    git.run(&["fetch", "origin"])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "submit"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Skipped branch foo: origin/foo (d6a8b50b) has commits which are not in the local history (use --force to push anyway)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "submit", "--force"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> push --porcelain origin --force-with-lease=refs/heads/foo:d6a8b50b3e06713010571452ead1f37fe806153f ae94dc2a748bc0965c88fcf3edac2e30074ff7e2:refs/heads/foo
+        Updated branch foo on origin: d6a8b50b..ae94dc2a
+        Pushed 1 branch to origin.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = remote.run(&["log", "--oneline", "foo"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ae94dc2 amended test1
+        f777ecc create initial.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_submit_message_command() -> eyre::Result<()> {
+    let (git, remote) = make_git_with_bare_remote()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["branchless", "submit", "--create"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "bar"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["branch", "baz"])?;
+
+    git.run(&[
+        "config",
+        "branchless.submit.messageCmd",
+        "cat >.git/stack.json && echo Posted stack.",
+    ])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "submit", "--create"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Branch foo is already up-to-date on origin
+        branchless: running command: <git-executable> push --porcelain origin --force-with-lease=refs/heads/bar: --force-with-lease=refs/heads/baz: 96d1c37a3d4363611c49f7e52186e189a04c531f:refs/heads/bar 70deb1e28791d8e7dd5a1f0c871a51b91282562f:refs/heads/baz
+        Created branch bar on origin at 96d1c37a
+        Created branch baz on origin at 70deb1e2
+        Pushed 2 branches to origin.
+        Posted stack.
+        "###);
+    }
+
+    {
+        let stack_json = std::fs::read_to_string(git.repo_path.join(".git").join("stack.json"))?;
+        let stack_json = stack_json.replace(remote.repo_path.to_str().unwrap(), "<remote-path>");
+        insta::assert_snapshot!(stack_json, @r###"
+        {
+          "branches": [
+            {
+              "commit_oid": "62fc20d2a290daea0d52bdc2ed2ad4be6491010e",
+              "name": "foo",
+              "new_oid": "62fc20d2a290daea0d52bdc2ed2ad4be6491010e",
+              "old_oid": "62fc20d2a290daea0d52bdc2ed2ad4be6491010e",
+              "remote": "origin",
+              "remote_url": "<remote-path>",
+              "status": "up-to-date"
+            },
+            {
+              "commit_oid": "96d1c37a3d4363611c49f7e52186e189a04c531f",
+              "name": "bar",
+              "new_oid": "96d1c37a3d4363611c49f7e52186e189a04c531f",
+              "old_oid": null,
+              "remote": "origin",
+              "remote_url": "<remote-path>",
+              "status": "created"
+            },
+            {
+              "commit_oid": "70deb1e28791d8e7dd5a1f0c871a51b91282562f",
+              "name": "baz",
+              "new_oid": "70deb1e28791d8e7dd5a1f0c871a51b91282562f",
+              "old_oid": null,
+              "remote": "origin",
+              "remote_url": "<remote-path>",
+              "status": "created"
+            }
+          ],
+          "commits": [
+            {
+              "branches": [
+                "foo"
+              ],
+              "oid": "62fc20d2a290daea0d52bdc2ed2ad4be6491010e",
+              "summary": "create test1.txt"
+            },
+            {
+              "branches": [
+                "bar"
+              ],
+              "oid": "96d1c37a3d4363611c49f7e52186e189a04c531f",
+              "summary": "create test2.txt"
+            },
+            {
+              "branches": [
+                "baz"
+              ],
+              "oid": "70deb1e28791d8e7dd5a1f0c871a51b91282562f",
+              "summary": "create test3.txt"
+            }
+          ],
+          "version": 1
+        }
+        "###);
+    }
+
+    // The branches should still be pushed if the command fails.
+    git.run(&["checkout", "baz"])?;
+    git.run(&["commit", "--amend", "-m", "amended test3"])?;
+    git.run(&["config", "branchless.submit.messageCmd", "exit 3"])?;
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["branchless", "submit"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Branch foo is already up-to-date on origin
+        Branch bar is already up-to-date on origin
+        branchless: running command: <git-executable> push --porcelain origin --force-with-lease=refs/heads/baz:70deb1e28791d8e7dd5a1f0c871a51b91282562f 1a2dc8f0c40b0e2300506407b1b58abb04281e7b:refs/heads/baz
+        Updated branch baz on origin: 70deb1e2..1a2dc8f0
+        Pushed 1 branch to origin.
+        "###);
+        insta::assert_snapshot!(stderr, @r###"
+        The branchless.submit.messageCmd command failed with exit code 3, but the branches were still submitted: exit 3
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = remote.run(&["log", "--oneline", "baz"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        1a2dc8f amended test3
+        96d1c37 create test2.txt
+        62fc20d create test1.txt
+        f777ecc create initial.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_submit_message_file() -> eyre::Result<()> {
+    let (git, _remote) = make_git_with_bare_remote()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "bar"])?;
+
+    let message_path = git.repo_path.join(".git").join("stack.md");
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "submit",
+            "--create",
+            "--dry-run",
+            "--message-file",
+            message_path.to_str().unwrap(),
+            "--format",
+            "markdown",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Would create branch foo on origin at 62fc20d2
+        Would create branch bar on origin at 96d1c37a
+        "###);
+    }
+
+    {
+        let message = std::fs::read_to_string(&message_path)?;
+        insta::assert_snapshot!(message, @r###"
+        | # | Branch | Commit | Status |
+        | --- | --- | --- | --- |
+        | 1 | `foo` | 62fc20d2 create test1.txt | would-create |
+        | 2 | `bar` | 96d1c37a create test2.txt | would-create |
+        "###);
+    }
+
+    Ok(())
+}
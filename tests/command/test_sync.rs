@@ -0,0 +1,274 @@
+use branchless::testing::{
+    make_git, make_git_with_remote_repo, GitInitOptions, GitRunOptions, GitWrapperWithRemoteRepo,
+};
+
+#[test]
+fn test_sync_stacks_onto_main_branch() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    let initial_oid = git.get_repo()?.get_main_branch_oid()?.unwrap();
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", &initial_oid.to_string()])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", &initial_oid.to_string()])?;
+    git.commit_file_with_contents("test4", 4, "conflicting contents\n")?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 5)?;
+    git.detach_head()?;
+    git.commit_file("test5", 6)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 create initial.txt
+        |\
+        | o 62fc20d2 create test1.txt
+        | |
+        | o 96d1c37a create test2.txt
+        |\
+        | o 98b9119d create test3.txt
+        |\
+        | o 5ded80ac create test4.txt
+        |
+        O 256d741f (master) create test4.txt
+        |
+        @ b8ca3a7d create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "sync"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: e45cb3f0 create test1.txt
+        [2/2] Committed as: 3e59690b create test2.txt
+        branchless: processing 2 rewritten commits
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 338bc585 create test3.txt
+        branchless: processing 1 rewritten commit
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        Skipped stack at 5ded80ac create test4.txt due to a merge conflict.
+        Not moving up-to-date stack at b8ca3a7d create test5.txt
+        2 stacks synced, 1 skipped due to conflicts: 5ded80ac
+        To resolve merge conflicts, move the skipped stacks with `git move --merge`.
+        O f777ecc9 create initial.txt
+        |\
+        | o 5ded80ac create test4.txt
+        |
+        O 256d741f (master) create test4.txt
+        |\
+        | o e45cb3f0 create test1.txt
+        | |
+        | o 3e59690b create test2.txt
+        |\
+        | o 338bc585 create test3.txt
+        |
+        @ b8ca3a7d create test5.txt
+        "###);
+    }
+
+    // The synced stacks are now up-to-date, and the conflicting stack is
+    // still skipped.
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "sync"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Not moving up-to-date stack at e45cb3f0 create test1.txt
+        Not moving up-to-date stack at 338bc585 create test3.txt
+        Attempting rebase in-memory...
+        Skipped stack at 5ded80ac create test4.txt due to a merge conflict.
+        Not moving up-to-date stack at b8ca3a7d create test5.txt
+        0 stacks synced, 1 skipped due to conflicts: 5ded80ac
+        To resolve merge conflicts, move the skipped stacks with `git move --merge`.
+        O f777ecc9 create initial.txt
+        |\
+        | o 5ded80ac create test4.txt
+        |
+        O 256d741f (master) create test4.txt
+        |\
+        | o e45cb3f0 create test1.txt
+        | |
+        | o 3e59690b create test2.txt
+        |\
+        | o 338bc585 create test3.txt
+        |
+        @ b8ca3a7d create test5.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_pull() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+
+    if !original_repo.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &[])?;
+
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.detach_head()?;
+    cloned_repo.commit_file("test2", 2)?;
+
+    original_repo.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["branchless", "sync", "--pull"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch --all
+        Attempting rebase in-memory...
+        [1/1] Committed as: d742fb97 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout d742fb97c050479c1d3057d8de0f05190dd3a89f
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        O 4838e49b (remote origin/master) create test3.txt
+        |
+        @ d742fb97 create test2.txt
+        In-memory rebase succeeded.
+        1 stack synced.
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        O 4838e49b (remote origin/master) create test3.txt
+        |
+        @ d742fb97 create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_remote_tracking_main_branch() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+
+    if !original_repo.supports_committer_date_is_author_date()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &[])?;
+
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        run_branchless_init: false,
+    })?;
+    cloned_repo.detach_head()?;
+    cloned_repo.run(&["branch", "-D", "master"])?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["branchless", "init", "--no-seed"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Created config file at <repo-path>/.git/branchless/config
+        Auto-detected your main branch as: origin/master
+        If this is incorrect, run: git config branchless.core.mainBranch <branch>
+        Installing hook: post-applypatch
+        Installing hook: post-commit
+        Installing hook: post-merge
+        Installing hook: post-rewrite
+        Installing hook: post-checkout
+        Installing hook: pre-auto-gc
+        Installing hook: reference-transaction
+        Successfully installed git-branchless.
+        To uninstall, run: git branchless init --uninstall
+        "###);
+    }
+
+    cloned_repo.commit_file("test2", 2)?;
+    original_repo.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["branchless", "sync", "--pull"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch --all
+        Attempting rebase in-memory...
+        [1/1] Committed as: d742fb97 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout d742fb97c050479c1d3057d8de0f05190dd3a89f
+        :
+        O 4838e49b (remote origin/master) create test3.txt
+        |
+        @ d742fb97 create test2.txt
+        In-memory rebase succeeded.
+        1 stack synced.
+        :
+        O 4838e49b (remote origin/master) create test3.txt
+        |
+        @ d742fb97 create test2.txt
+        "###);
+    }
+
+    {
+        cloned_repo.run(&[
+            "config",
+            "branchless.core.mainBranch",
+            "refs/remotes/origin/master",
+        ])?;
+        let (stdout, _stderr) = cloned_repo.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 4838e49b (remote origin/master) create test3.txt
+        |
+        @ d742fb97 create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = cloned_repo.run_with_options(
+            &[
+                "reword",
+                "origin/master",
+                "--force-rewrite-public",
+                "--message",
+                "foo",
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @"Refusing to rewrite 4838e49b create test3.txt: the main branch refs/remotes/origin/master is a remote-tracking branch, so it can't be moved to the rewritten commit.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+/// Fails only on the commit which adds `test2.txt`.
+const FAIL_ON_TEST2: &str = "echo checking; test ! -f test2.txt || test -f test3.txt";
+
+#[test]
+fn test_test_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "test", "-x", FAIL_ON_TEST2],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Passed: 62fc20d2 create test1.txt
+        Failed (exit code 1): 96d1c37a create test2.txt
+            checking
+        Passed: 70deb1e2 create test3.txt
+        Ran `echo checking; test ! -f test2.txt || test -f test3.txt` on 3 commits:
+        passed                62fc20d2 create test1.txt
+        failed (exit code 1)  96d1c37a create test2.txt
+        passed                70deb1e2 create test3.txt
+        2 passed, 1 failed
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["worktree", "list", "--porcelain"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        worktree <repo-path>
+        HEAD 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+        detached
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_test_cache() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+
+    git.run_with_options(
+        &["branchless", "test", "-x", FAIL_ON_TEST2],
+        &GitRunOptions {
+            expected_exit_code: 1,
+            ..Default::default()
+        },
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "test", "-x", FAIL_ON_TEST2],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Passed (cached): 62fc20d2 create test1.txt
+        Passed (cached): 70deb1e2 create test3.txt
+        Failed (exit code 1): 96d1c37a create test2.txt
+            checking
+        Ran `echo checking; test ! -f test2.txt || test -f test3.txt` on 3 commits:
+        passed (cached)       62fc20d2 create test1.txt
+        failed (exit code 1)  96d1c37a create test2.txt
+        passed (cached)       70deb1e2 create test3.txt
+        2 passed, 1 failed
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "test",
+            "-x",
+            FAIL_ON_TEST2,
+            "--no-cache",
+            "HEAD~2",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Passed: 62fc20d2 create test1.txt
+        Ran `echo checking; test ! -f test2.txt || test -f test3.txt` on 1 commit:
+        passed  62fc20d2 create test1.txt
+        1 passed, 0 failed
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_test_jobs() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "test", "-x", FAIL_ON_TEST2, "--jobs", "3"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        // The per-commit results are printed in the order the commits
+        // finish, so only check the summary.
+        let summary = stdout
+            .lines()
+            .skip_while(|line| !line.starts_with("Ran "))
+            .collect::<Vec<_>>()
+            .join("\n");
+        insta::assert_snapshot!(summary, @r###"
+        Ran `echo checking; test ! -f test2.txt || test -f test3.txt` on 3 commits:
+        passed                62fc20d2 create test1.txt
+        failed (exit code 1)  96d1c37a create test2.txt
+        passed                70deb1e2 create test3.txt
+        2 passed, 1 failed
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["worktree", "list", "--porcelain"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        worktree <repo-path>
+        HEAD 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+        detached
+        "###);
+    }
+
+    Ok(())
+}
@@ -8,14 +8,15 @@ use crate::util::trim_lines;
 use branchless::commands::undo::testing::{select_past_event, undo_events};
 use branchless::core::dag::Dag;
 use branchless::core::effects::Effects;
-use branchless::core::eventlog::{EventCursor, EventLogDb, EventReplayer};
+use branchless::core::eventlog::{EventCursor, EventLogDb, EventReplayer, ResetInfo};
 use branchless::core::formatting::Glyphs;
 use branchless::git::{GitRunInfo, Repo};
-use branchless::testing::{make_git, Git};
+use branchless::testing::{make_git, Git, GitInitOptions, GitRunOptions};
 use branchless::tui::testing::{screen_to_string, CursiveTestingBackend, CursiveTestingEvent};
 
 use cursive::event::Key;
 use cursive::CursiveRunnable;
+use regex::Regex;
 
 fn run_select_past_event(
     repo: &Repo,
@@ -38,7 +39,14 @@ fn run_select_past_event(
     let siv = CursiveRunnable::new::<Infallible, _>(move || {
         Ok(CursiveTestingBackend::init(events.clone()))
     });
-    select_past_event(siv.into_runner(), &effects, repo, &dag, &mut event_replayer)
+    select_past_event(
+        siv.into_runner(),
+        &effects,
+        repo,
+        &event_log_db,
+        &dag,
+        &mut event_replayer,
+    )
 }
 
 fn run_undo_events(git: &Git, event_cursor: EventCursor) -> eyre::Result<(isize, String)> {
@@ -67,6 +75,9 @@ fn run_undo_events(git: &Git, event_cursor: EventCursor) -> eyre::Result<(isize,
         &mut event_log_db,
         &event_replayer,
         event_cursor,
+        false,
+        false,
+        false,
     )?;
 
     let stdout = {
@@ -101,24 +112,24 @@ fn test_undo_help() -> eyre::Result<()> {
         ┌───────────────────────────────────────────────────┤─Commit graph ├───────────────────────────────────────────────────┐
         │O f777ecc9 (master) create initial.txt                                                                                │
         │                                                                                                                      │
-        │                                                                                                                      │
-        │                                                                                                                      │
         │        ┌───────────────────────────────────────────┤─How to use ├───────────────────────────────────────────┐        │
         │        │ Use `git undo` to view and revert to previous states of the repository.                            │        │
         │        │                                                                                                    │        │
         │        │ h/?: Show this help.                                                                               │        │
         │        │ q: Quit.                                                                                           │        │
-        │        │ p/n or <left>/<right>: View next/previous state.                                                   │        │
+        │        │ p/n or <left>/<right>: View previous/next state.                                                   │        │
         │        │ g: Go to a provided event ID.                                                                      │        │
+        │        │ /: Search for a state by commit message, branch name, etc.                                         │        │
+        │        │ n/N: Go to the next older/newer search result.                                                     │        │
+        │        │ f: Toggle hiding low-signal events, such as branch updates from fetches.                           │        │
+        │        │ d: Show the files which would change if the repository were reverted to the given state.           │        │
         │        │ <enter>: Revert the repository to the given state (requires confirmation).                         │        │
         │        │                                                                                                    │        │
         │        │ You can also copy a commit hash from the past and manually run `git unhide` or `git rebase` on it. │        │
         │        │                                                                                                    │        │
         │        │                                                                                            <Close> │        │
         │        └────────────────────────────────────────────────────────────────────────────────────────────────────┘        │
-        │                                                                                                                      │
-        │                                                                                                                      │
-        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        └─────────                                                                                                      ───────┘
         ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
         │There are no previous available events.                                                                               │
         └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
@@ -179,7 +190,7 @@ fn test_undo_navigate() -> eyre::Result<()> {
         │                                                                                                                      │
         └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
         ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
-        │Repo after transaction 3 (event 4). Press 'h' for help, 'q' to quit.                                                  │
+        │Repo after transaction 3 (event 4) by `git commit -m 'create test2.txt'`. Press 'h' for help, 'q' to quit.            │
         │1. Check out from 62fc20d2 create test1.txt                                                                           │
         │               to 96d1c37a create test2.txt                                                                           │
         │2. Move branch master from 62fc20d2 create test1.txt                                                                  │
@@ -207,11 +218,99 @@ fn test_undo_navigate() -> eyre::Result<()> {
         │                                                                                                                      │
         └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
         ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
-        │Repo after transaction 4 (event 6). Press 'h' for help, 'q' to quit.                                                  │
+        │Repo after transaction 4 (event 6) by `git commit -m 'create test2.txt'`. Press 'h' for help, 'q' to quit.            │
+        │1. Commit 96d1c37a create test2.txt                                                                                   │
+        │                                                                                                                      │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        "###);
+    };
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_navigate_pruned_events() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["config", "branchless.eventlog.pruneKeepTransactions", "2"])?;
+    git.run(&["branchless", "gc", "--prune-events", "--before", "0s"])?;
+
+    {
+        let screenshot1 = Default::default();
+        let screenshot2 = Default::default();
+        run_select_past_event(
+            &git.get_repo()?,
+            vec![
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot1)),
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot2)),
+                CursiveTestingEvent::Event('q'.into()),
+            ],
+        )?;
+        insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+        ┌───────────────────────────────────────────────────┤─Commit graph ├───────────────────────────────────────────────────┐
+        │:                                                                                                                     │
+        │@ 96d1c37a (master) create test2.txt                                                                                  │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
+        │Repo after transaction 4 (event 4) by `git commit -m 'create test2.txt'`. Press 'h' for help, 'q' to quit. Older      │
+        │history has been pruned.                                                                                              │
         │1. Commit 96d1c37a create test2.txt                                                                                   │
         │                                                                                                                      │
         └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
         "###);
+        insta::assert_snapshot!(screen_to_string(&screenshot2), @r###"
+        ┌───────────────────────────────────────────────────┤─Commit graph ├───────────────────────────────────────────────────┐
+        │:                                                                                                                     │
+        │O 70deb1e2 (master) create test3.txt                                                                                  │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
+        │There are no previous available events. Older history has been pruned.                                                │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        "###);
     };
 
     Ok(())
@@ -264,7 +363,7 @@ fn test_go_to_event() -> eyre::Result<()> {
     │                                                                                                                      │
     └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
     ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
-    │Repo after transaction 4 (event 6). Press 'h' for help, 'q' to quit.                                                  │
+    │Repo after transaction 4 (event 6) by `git commit -m 'create test2.txt'`. Press 'h' for help, 'q' to quit.            │
     │1. Commit 96d1c37a create test2.txt                                                                                   │
     │                                                                                                                      │
     └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
@@ -290,7 +389,7 @@ fn test_go_to_event() -> eyre::Result<()> {
     │                                                                                                                      │
     └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
     ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
-    │Repo after transaction 1 (event 1). Press 'h' for help, 'q' to quit.                                                  │
+    │Repo after transaction 1 (event 1) by `git commit -m 'create test1.txt'`. Press 'h' for help, 'q' to quit.            │
     │1. Check out from f777ecc9 create initial.txt                                                                         │
     │               to 62fc20d2 create test1.txt                                                                           │
     └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
@@ -337,7 +436,7 @@ fn test_undo_hide() -> eyre::Result<()> {
     insta::assert_debug_snapshot!(event_cursor, @r###"
         Some(
             EventCursor {
-                event_id: 9,
+                event_id: 8,
             },
         )
         "###);
@@ -346,13 +445,16 @@ fn test_undo_hide() -> eyre::Result<()> {
     {
         let (exit_code, stdout) = run_undo_events(&git, event_cursor)?;
         insta::assert_snapshot!(stdout, @r###"
-            Will apply these actions:
-            1. Create branch test1 at 62fc20d2 create test1.txt
+        Will undo these commands:
+        1. git branch -D test1 (transaction 9)
+        2. git hide test1 (transaction 8)
+        Will apply these actions:
+        1. Recreate branch test1 at 62fc20d2 create test1.txt
 
-            2. Unhide commit 62fc20d2 create test1.txt
+        2. Unhide commit 62fc20d2 create test1.txt
 
-            Confirm? [yN] Applied 2 inverse events.
-            "###);
+        Confirm? [yN] Applied 2 inverse events.
+        "###);
         assert_eq!(exit_code, 0);
     }
 
@@ -403,6 +505,9 @@ fn test_undo_move_refs() -> eyre::Result<()> {
     {
         let (exit_code, stdout) = run_undo_events(&git, event_cursor)?;
         insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git commit -m 'create test2.txt' (transaction 4)
+        2. git commit -m 'create test2.txt' (transaction 3)
         Will apply these actions:
         1. Check out from 96d1c37a create test2.txt
                        to 62fc20d2 create test1.txt
@@ -473,7 +578,7 @@ fn test_historical_smartlog_visibility() -> eyre::Result<()> {
         │                                                                                                                      │
         └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
         ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
-        │Repo after transaction 3 (event 4). Press 'h' for help, 'q' to quit.                                                  │
+        │Repo after transaction 3 (event 4) by `git hide HEAD`. Press 'h' for help, 'q' to quit.                               │
         │1. Hide commit 62fc20d2 create test1.txt                                                                              │
         │                                                                                                                      │
         └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
@@ -499,7 +604,7 @@ fn test_historical_smartlog_visibility() -> eyre::Result<()> {
         │                                                                                                                      │
         └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
         ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
-        │Repo after transaction 2 (event 3). Press 'h' for help, 'q' to quit.                                                  │
+        │Repo after transaction 2 (event 3) by `git commit -m 'create test1.txt'`. Press 'h' for help, 'q' to quit.            │
         │1. Commit 62fc20d2 create test1.txt                                                                                   │
         │                                                                                                                      │
         └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
@@ -628,6 +733,11 @@ fn test_undo_doesnt_make_working_dir_dirty() -> eyre::Result<()> {
     {
         let (exit_code, stdout) = run_undo_events(&git, event_cursor)?;
         insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git branch bar (transaction 4)
+        2. git commit -m 'create test1.txt' (transaction 3)
+        3. git commit -m 'create test1.txt' (transaction 2)
+        4. git branch foo (transaction 1)
         Will apply these actions:
         1. Check out from 62fc20d2 create test1.txt
                        to f777ecc9 create initial.txt
@@ -681,7 +791,26 @@ fn test_git_bisect_produces_empty_event() -> eyre::Result<()> {
             CursiveTestingEvent::Event(Key::Enter.into()),
         ],
     )?;
-    insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+
+    // Depending on the Git version, `git bisect start` is recorded as either
+    // `git bisect start` or `git bisect--helper start`. Normalize it, keeping
+    // the screen's borders aligned.
+    let bisect_command_re = Regex::new(r"`git bisect(--helper)? start`")?;
+    let screen = screen_to_string(&screenshot1)
+        .lines()
+        .map(|line| {
+            let normalized = bisect_command_re.replace(line, "`git bisect start`");
+            let num_removed_chars = line.chars().count() - normalized.chars().count();
+            match normalized.strip_suffix('│') {
+                Some(prefix) if num_removed_chars > 0 => {
+                    format!("{}{}│", prefix, " ".repeat(num_removed_chars))
+                }
+                _ => normalized.into_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    insta::assert_snapshot!(screen, @r###"
     ┌───────────────────────────────────────────────────┤─Commit graph ├───────────────────────────────────────────────────┐
     │:                                                                                                                     │
     │@ 62fc20d2 (master) create test1.txt                                                                                  │
@@ -702,7 +831,7 @@ fn test_git_bisect_produces_empty_event() -> eyre::Result<()> {
     │                                                                                                                      │
     └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
     ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
-    │Repo after transaction 3 (event 4). Press 'h' for help, 'q' to quit.                                                  │
+    │Repo after transaction 3 (event 4) by `git bisect start`. Press 'h' for help, 'q' to quit.                            │
     │1. Empty event for BISECT_HEAD                                                                                        │
     │   This may be an unsupported use-case; see https://git.io/J0b7z                                                      │
     └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
@@ -725,12 +854,16 @@ fn test_undo_garbage_collected_commit() -> eyre::Result<()> {
     git.commit_file("test2", 2)?;
     git.run(&["hide", "HEAD"])?;
     git.run(&["checkout", "HEAD^"])?;
+    git.run(&["config", "branchless.gc.retentionDays", "0"])?;
 
     {
         let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
         insta::assert_snapshot!(stdout, @r###"
         branchless: collecting garbage
-        branchless: 1 dangling reference deleted
+        branchless: released 1 hidden commit
+        branchless:   1 hidden less than 1 week ago
+        branchless: deleted 1 anchor ref
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
         "###);
     }
     git.run(&["gc", "--prune=now"])?;
@@ -776,7 +909,7 @@ fn test_undo_garbage_collected_commit() -> eyre::Result<()> {
     │                                                                                                                      │
     └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
     ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
-    │Repo after transaction 7 (event 8). Press 'h' for help, 'q' to quit.                                                  │
+    │Repo after transaction 6 (event 7) by `git hide HEAD`. Press 'h' for help, 'q' to quit.                               │
     │1. Hide commit <commit not available: 96d1c37a3d4363611c49f7e52186e189a04c531f>                                       │
     │                                                                                                                      │
     └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
@@ -786,19 +919,1373 @@ fn test_undo_garbage_collected_commit() -> eyre::Result<()> {
     {
         let (exit_code, stdout) = run_undo_events(&git, event_cursor)?;
         insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git pack-refs --all --prune (transaction 10)
+        2. git pack-refs --all --prune (transaction 9)
+        3. git checkout HEAD^ (transaction 8)
+        4. git checkout HEAD^ (transaction 7)
         Will apply these actions:
         1. Check out from 62fc20d2 create test1.txt
                        to <commit not available: 96d1c37a3d4363611c49f7e52186e189a04c531f>
-        2. Move branch master from 62fc20d2 create test1.txt
-                                to 62fc20d2 create test1.txt
-        3. Move branch master from 62fc20d2 create test1.txt
-                                to 62fc20d2 create test1.txt
         Confirm? [yN] branchless: running command: <git-executable> checkout 96d1c37a3d4363611c49f7e52186e189a04c531f --detach
         Failed to check out commit: 96d1c37a3d4363611c49f7e52186e189a04c531f
-        Applied 3 inverse events.
+        Applied 1 inverse event.
         "###);
         assert!(exit_code > 0);
     }
 
     Ok(())
 }
+
+#[test]
+fn test_undo_noninteractive() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.write_file("test1", "new contents")?;
+    git.run(&[
+        "branchless",
+        "wrap",
+        "--",
+        "commit",
+        "-a",
+        "--amend",
+        "-m",
+        "amended test1",
+    ])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ d60c03cf (master) amended test1
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git wrap -- commit -a --amend -m 'amended test1' (transaction 3)
+        Will apply these actions:
+        1. Check out from d60c03cf amended test1
+                       to 62fc20d2 create test1.txt
+        2. Rewrite commit d60c03cf amended test1
+                      as 62fc20d2 create test1.txt
+        3. Hide commit d60c03cf amended test1
+
+        4. Move branch master from d60c03cf amended test1
+                                to 62fc20d2 create test1.txt
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --detach
+        O f777ecc9 create initial.txt
+        |\
+        | % 62fc20d2 (rewritten as d60c03cf) create test1.txt
+        |
+        O d60c03cf (master) amended test1
+        Applied 4 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_reset() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["reset", "--hard", "HEAD~2"])?;
+
+    {
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        let events = event_log_db.get_events()?;
+        let event_tx_id = events.last().unwrap().get_event_tx_id();
+        assert_eq!(
+            event_log_db.get_reset_info(event_tx_id)?,
+            Some(ResetInfo {
+                ref_name: "refs/heads/master".into(),
+                old_oid: test3_oid,
+            })
+        );
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git reset --hard HEAD~2 (transaction 7): Restore branch master to 70deb1e2 create test3.txt (before reset)
+        Will apply these actions:
+        1. Check out from 62fc20d2 create test1.txt
+                       to 70deb1e2 create test3.txt
+        2. Move branch master from 62fc20d2 create test1.txt
+                                to 70deb1e2 create test3.txt
+        branchless: running command: <git-executable> checkout 70deb1e28791d8e7dd5a1f0c871a51b91282562f --detach
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        |
+        @ 70deb1e2 create test3.txt
+        Applied 2 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 70deb1e2 (master) create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_noninteractive_num_transactions() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.write_file("test1", "new contents")?;
+    // Without `git branchless wrap`, each hook invocation is recorded in its
+    // own transaction.
+    git.run(&["commit", "-a", "--amend", "-m", "amended test1"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y", "-n", "3"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git commit -a --amend -m 'amended test1' (transaction 5)
+        2. git commit -a --amend -m 'amended test1' (transaction 4)
+        3. git commit -a --amend -m 'amended test1' (transaction 3)
+        Will apply these actions:
+        1. Check out from d60c03cf amended test1
+                       to 62fc20d2 create test1.txt
+        2. Rewrite commit d60c03cf amended test1
+                      as 62fc20d2 create test1.txt
+        3. Hide commit d60c03cf amended test1
+
+        4. Move branch master from d60c03cf amended test1
+                                to 62fc20d2 create test1.txt
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --detach
+        O f777ecc9 create initial.txt
+        |\
+        | % 62fc20d2 (rewritten as d60c03cf) create test1.txt
+        |
+        O d60c03cf (master) amended test1
+        Applied 4 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_multiple_operations() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    let (original_smartlog, _stderr) = git.run(&["smartlog"])?;
+
+    git.run(&["move", "-x", "HEAD", "-d", &test1_oid.to_string()])?;
+    git.write_file("test3", "amended once")?;
+    git.run(&["amend"])?;
+    git.write_file("test3", "amended twice")?;
+    git.run(&["amend"])?;
+    git.run(&["hide", &test2_oid.to_string()])?;
+    let (final_smartlog, _stderr) = git.run(&["smartlog"])?;
+    insta::assert_snapshot!(final_smartlog, @r###"
+    O f777ecc9 (master) create initial.txt
+    |
+    o 62fc20d2 create test1.txt
+    |
+    @ ba97c07a create test3.txt
+    "###);
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y", "-n", "4"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git hide 96d1c37a3d4363611c49f7e52186e189a04c531f (transaction 13)
+        2. git amend (transaction 11)
+        3. git amend (transaction 9)
+        4. git move -x HEAD -d 62fc20d2a290daea0d52bdc2ed2ad4be6491010e (transaction 8)
+        Will apply these actions:
+        1. Check out from ba97c07a create test3.txt
+                       to 70deb1e2 create test3.txt
+        2. Unhide commit 96d1c37a create test2.txt
+
+        3. Rewrite commit ba97c07a create test3.txt
+                      as 8754b300 create test3.txt
+        4. Rewrite commit 8754b300 create test3.txt
+                      as 4838e49b create test3.txt
+        5. Rewrite commit 4838e49b create test3.txt
+                      as 70deb1e2 create test3.txt
+        branchless: running command: <git-executable> checkout 70deb1e28791d8e7dd5a1f0c871a51b91282562f --detach
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | x 96d1c37a (manually hidden) create test2.txt
+        | |
+        | % 70deb1e2 (rewritten as ba97c07a) create test3.txt
+        |
+        o ba97c07a create test3.txt
+        Applied 5 inverse events.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        assert_eq!(stdout, original_smartlog);
+        let (stdout, _stderr) = git.run(&["status", "--short"])?;
+        assert_eq!(stdout, "");
+    }
+
+    git.run(&["redo", "-y"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        assert_eq!(stdout, final_smartlog);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_num_transactions_exceeds_history() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+    git.run(&["branchless", "wrap", "--", "branch", "foo"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y", "-n", "10"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Only 1 transaction is available to undo.
+        Will undo these commands:
+        1. git wrap -- branch foo (transaction 1)
+        Will apply these actions:
+        1. Delete branch foo at f777ecc9 create initial.txt
+
+        Applied 1 inverse event.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_noninteractive_dirty_working_copy() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.write_file("test1", "uncommitted changes")?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["undo", "-y"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git checkout HEAD^ (transaction 6)
+        Will apply these actions:
+        1. Check out from 62fc20d2 create test1.txt
+                       to 96d1c37a create test2.txt
+        This operation would check out a different commit, but you have uncommitted
+        changes in your working copy which might be overwritten as a result.
+        Commit your changes and then try again, or retry with --force to save them to
+        a working copy snapshot first.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+        assert_eq!(stdout, " M test1.txt\n");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y", "--force"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git checkout HEAD^ (transaction 6)
+        Will apply these actions:
+        1. Check out from 62fc20d2 create test1.txt
+                       to 96d1c37a create test2.txt
+        Saved uncommitted changes to working copy snapshot ecd2440aa114896d828ec7581b015d5c6a2a0c74.
+        branchless: running command: <git-executable> checkout 96d1c37a3d4363611c49f7e52186e189a04c531f --detach
+        :
+        @ 96d1c37a (master) create test2.txt
+        Applied 1 inverse event.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+        assert_eq!(stdout, "");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["redo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git undo -y --force (transaction 8)
+        Will apply these actions:
+        1. Check out from 96d1c37a create test2.txt
+                       to 62fc20d2 create test1.txt
+        2. Restore uncommitted changes to 1 file from working copy snapshot ecd2440a
+
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --detach
+        :
+        @ 62fc20d2 create test1.txt
+        |
+        O 96d1c37a (master) create test2.txt
+        Applied 2 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+        assert_eq!(stdout, " M test1.txt\n");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_redo() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    for name in ["test1", "test2"] {
+        git.write_file(name, "contents")?;
+        git.run(&["add", "."])?;
+        git.run(&["branchless", "wrap", "--", "commit", "-m", name])?;
+    }
+    let (original_smartlog, _stderr) = git.run(&["smartlog"])?;
+    insta::assert_snapshot!(original_smartlog, @r###"
+    :
+    @ 5e4ed3fd (master) test2
+    "###);
+
+    git.run(&["undo", "-y"])?;
+    git.run(&["undo", "-y"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["redo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git undo -y (transaction 4)
+        2. git undo -y (transaction 3)
+        3. git wrap -- commit -m test2 (transaction 2)
+        Will apply these actions:
+        1. Check out from f777ecc9 create initial.txt
+                       to 931ca301 test1
+        2. Unhide commit 931ca301 test1
+
+        3. Unhide commit 5e4ed3fd test2
+
+        4. Hide commit 5e4ed3fd test2
+
+        5. Unhide commit 5e4ed3fd test2
+
+        6. Hide commit 5e4ed3fd test2
+
+        7. Move branch master from f777ecc9 create initial.txt
+                                to 931ca301 test1
+        branchless: running command: <git-executable> checkout 931ca301865e1a1e142551cac892b63dc52031d4 --detach
+        O f777ecc9 (master) create initial.txt
+        |
+        % 931ca301 (manually hidden) test1
+        Applied 7 inverse events.
+        "###);
+    }
+    git.run(&["redo", "-y"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        assert_eq!(stdout, original_smartlog);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["redo", "-y"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        All undo operations have already been redone.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_redo_after_new_events() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["redo", "-y"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        There are no undo operations to redo.
+        "###);
+    }
+
+    git.commit_file("test1", 1)?;
+    git.run(&["undo", "-y"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["redo", "-y"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Events have been recorded since the last undo operation, so it can no longer be redone.
+        Run git undo to return to an earlier state instead.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_restores_deleted_branch() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["branch", "-D", "foo"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git branch -D foo (transaction 4)
+        Will apply these actions:
+        1. Recreate branch foo at 62fc20d2 create test1.txt
+
+        Applied 1 inverse event.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (foo, master) create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git undo -y (transaction 6)
+        2. git branch -D foo (transaction 4)
+        3. git branch foo (transaction 3)
+        Will apply these actions:
+        1. Delete branch foo at 62fc20d2 create test1.txt
+
+        Applied 1 inverse event.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_delete_checked_out_branch() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.run(&["branchless", "wrap", "--", "checkout", "-b", "foo"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git wrap -- checkout -b foo (transaction 3)
+        Will apply these actions:
+        1. Delete branch foo at 62fc20d2 create test1.txt
+
+        Applied 1 inverse event.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ## HEAD (no branch)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (master) create test1.txt
+        "###);
+    }
+
+    // Like other checkouts restored by `git undo` and `git redo`, `HEAD` is
+    // left detached when the branch is recreated, rather than being reattached
+    // to it.
+    {
+        let (stdout, _stderr) = git.run(&["redo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git undo -y (transaction 4)
+        Will apply these actions:
+        1. Recreate branch foo at 62fc20d2 create test1.txt
+
+        Applied 1 inverse event.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ## HEAD (no branch)
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (foo, master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_restores_working_copy_snapshot() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.write_file("test1", "uncommitted changes")?;
+    git.run(&["prev"])?;
+    git.write_file("test1", "more uncommitted changes")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y", "--force"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git prev (transaction 5)
+        Will apply these actions:
+        1. Check out from 62fc20d2 create test1.txt
+                       to 96d1c37a create test2.txt
+        2. Restore uncommitted changes to 1 file from working copy snapshot 4fbe724a
+
+        Saved uncommitted changes to working copy snapshot 9f74956556abde72c464f5ab3920e2eb3abe9820.
+        branchless: running command: <git-executable> checkout 96d1c37a3d4363611c49f7e52186e189a04c531f --detach
+        :
+        @ 96d1c37a (master) create test2.txt
+        Applied 2 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+        assert_eq!(stdout, " M test1.txt\n");
+        let contents = std::fs::read_to_string(git.repo_path.join("test1.txt"))?;
+        assert_eq!(contents, "uncommitted changes");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["redo", "-y", "--force"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git undo -y --force (transaction 6)
+        Will apply these actions:
+        1. Check out from 96d1c37a create test2.txt
+                       to 62fc20d2 create test1.txt
+        2. Restore uncommitted changes to 1 file from working copy snapshot 9f749565
+
+        Saved uncommitted changes to working copy snapshot 20c70e006db9a05044afe45148b6be633c5f2f51.
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --detach
+        :
+        @ 62fc20d2 create test1.txt
+        |
+        O 96d1c37a (master) create test2.txt
+        Applied 2 inverse events.
+        "###);
+    }
+
+    {
+        let contents = std::fs::read_to_string(git.repo_path.join("test1.txt"))?;
+        assert_eq!(contents, "more uncommitted changes");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_amend_restores_uncommitted_changes() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.write_file("test1", "uncommitted changes")?;
+    git.write_file("test2", "uncommitted changes")?;
+    git.delete_file("initial")?;
+    git.run(&["amend"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git amend (transaction 6)
+        Will apply these actions:
+        1. Check out from fc22ceaf create test2.txt
+                       to 96d1c37a create test2.txt
+        2. Restore uncommitted changes to 3 files from working copy snapshot 62b2c19e
+
+        3. Rewrite commit fc22ceaf create test2.txt
+                      as 96d1c37a create test2.txt
+        branchless: running command: <git-executable> checkout 96d1c37a3d4363611c49f7e52186e189a04c531f --detach
+        O f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        |\
+        | % 96d1c37a (rewritten as fc22ceaf) create test2.txt
+        |
+        o fc22ceaf create test2.txt
+        Applied 3 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["rev-parse", "HEAD"])?;
+        assert_eq!(stdout.trim(), test2_oid.to_string());
+        let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+        assert_eq!(stdout, " D initial.txt\n M test1.txt\n M test2.txt\n");
+        let contents = std::fs::read_to_string(git.repo_path.join("test2.txt"))?;
+        assert_eq!(contents, "uncommitted changes");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_gc_expired_working_copy_snapshots() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.write_file("test1", "uncommitted changes")?;
+    git.run(&["prev"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 create test1.txt
+        |
+        O 96d1c37a (master) create test2.txt
+        "###);
+    }
+
+    // The snapshot commit has the (old) dummy commit timestamp, so it's only
+    // kept if the maximum age is large enough.
+    git.run(&["config", "branchless.snapshots.maxAgeDays", "1000000"])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: released 0 hidden commits
+        branchless: deleted 0 anchor refs
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+    }
+
+    git.run(&["config", "--unset", "branchless.snapshots.maxAgeDays"])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: released 0 hidden commits
+        branchless: deleted 1 anchor ref
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["for-each-ref", "refs/branchless/snapshots/"])?;
+        assert_eq!(stdout, "");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_show_diff() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let screenshot1 = Default::default();
+        run_select_past_event(
+            &git.get_repo()?,
+            vec![
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::Event('d'.into()),
+                CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot1)),
+                CursiveTestingEvent::Event(Key::Esc.into()),
+                CursiveTestingEvent::Event('q'.into()),
+            ],
+        )?;
+        insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+        ┌───────────────────────────────────────────────────┤─Commit graph ├───────────────────────────────────────────────────┐
+        │:                                                                                                                     │
+        │@ 62fc20d2 (master) create test1.txt                                                                                  │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                               ┌────────┤─Changes if reverted to this state ├────────┐                                │
+        │                               │ Changes to HEAD from 96d1c37a to 62fc20d2:          │                                │
+        │                               │  test2.txt | 1 -                                    │                                │
+        │                               │  1 file changed, 1 deletion(-)                      │                                │
+        │                               │ Changes to branch master from 96d1c37a to 62fc20d2: │                                │
+        │                               │  test2.txt | 1 -                                    │                                │
+        │                               │  1 file changed, 1 deletion(-)                      │                                │
+        │                               │                                                     │                                │
+        │                               │                                             <Close> │                                │
+        │                               └─────────────────────────────────────────────────────┘                                │
+        │                                                                                                                      │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
+        │Repo after transaction 2 (event 3) by `git commit -m 'create test1.txt'`. Press 'h' for help, 'q' to quit.            │
+        │1. Commit 62fc20d2 create test1.txt                                                                                   │
+        │                                                                                                                      │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["undo", "-n", "4", "--diff"],
+            &GitRunOptions {
+                input: Some("n\n".to_string()),
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git commit -m 'create test2.txt' (transaction 4)
+        2. git commit -m 'create test2.txt' (transaction 3)
+        3. git commit -m 'create test1.txt' (transaction 2)
+        4. git commit -m 'create test1.txt' (transaction 1)
+        Will apply these actions:
+        1. Check out from 96d1c37a create test2.txt
+                       to f777ecc9 create initial.txt
+        2. Hide commit 96d1c37a create test2.txt
+
+        3. Hide commit 62fc20d2 create test1.txt
+
+        4. Move branch master from 96d1c37a create test2.txt
+                                to f777ecc9 create initial.txt
+        Changes to HEAD from 96d1c37a to f777ecc9:
+         test1.txt | 1 -
+         test2.txt | 1 -
+         2 files changed, 2 deletions(-)
+        Changes to branch master from 96d1c37a to f777ecc9:
+         test1.txt | 1 -
+         test2.txt | 1 -
+         2 files changed, 2 deletions(-)
+        Confirm? [yN] Aborted.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_to_event_transaction() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["branch", "bar"])?;
+    git.run(&["branch", "baz"])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["undo", "--event", "999"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        No events were recorded for event transaction 999. Valid event transaction IDs range from 1 to 5.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["undo", "--event", "foo"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        No events were recorded for event transaction foo. Valid event transaction IDs range from 1 to 5.
+        "###);
+    }
+
+    {
+        // Transaction 3 is the creation of `foo`.
+        let (stdout, _stderr) = git.run(&["undo", "--event", "3", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git branch baz (transaction 5)
+        2. git branch bar (transaction 4)
+        Will apply these actions:
+        1. Delete branch baz at 62fc20d2 create test1.txt
+
+        2. Delete branch bar at 62fc20d2 create test1.txt
+
+        Applied 2 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+          foo
+        * master
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_search_and_filter() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["branch", "bar"])?;
+
+    fn type_text(text: &str) -> Vec<CursiveTestingEvent> {
+        text.chars()
+            .map(|c| CursiveTestingEvent::Event(c.into()))
+            .collect()
+    }
+
+    {
+        // Searching is case-insensitive and matches commit messages.
+        let screenshot1 = Default::default();
+        let mut events = vec![CursiveTestingEvent::Event('/'.into())];
+        events.extend(type_text("TEST1"));
+        events.extend(vec![
+            CursiveTestingEvent::Event(Key::Enter.into()),
+            CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot1)),
+            CursiveTestingEvent::Event(Key::Enter.into()),
+        ]);
+        let event_cursor = run_select_past_event(&git.get_repo()?, events)?;
+        insta::assert_debug_snapshot!(event_cursor, @r###"
+        Some(
+            EventCursor {
+                event_id: 5,
+            },
+        )
+        "###);
+        insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+        ┌───────────────────────────────────────────────────┤─Commit graph ├───────────────────────────────────────────────────┐
+        │:                                                                                                                     │
+        │@ 96d1c37a (master) create test2.txt                                                                                  │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
+        │Repo after transaction 3 (event 4) by `git commit -m 'create test2.txt'`. Press 'h' for help, 'q' to quit.            │
+        │1. Check out from 62fc20d2 create test1.txt                                                                           │
+        │               to 96d1c37a create test2.txt                                                                           │
+        │2. Move branch master from 62fc20d2 create test1.txt                                                                  │
+        │                        to 96d1c37a create test2.txt                                                                  │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        "###);
+    }
+
+    {
+        // Repeat the search to find older and newer matches.
+        let mut events = vec![CursiveTestingEvent::Event('/'.into())];
+        events.extend(type_text("create"));
+        events.extend(vec![
+            CursiveTestingEvent::Event(Key::Enter.into()),
+            CursiveTestingEvent::Event('n'.into()),
+            CursiveTestingEvent::Event('n'.into()),
+            CursiveTestingEvent::Event('N'.into()),
+            CursiveTestingEvent::Event(Key::Enter.into()),
+        ]);
+        let event_cursor = run_select_past_event(&git.get_repo()?, events)?;
+        insta::assert_debug_snapshot!(event_cursor, @r###"
+        Some(
+            EventCursor {
+                event_id: 7,
+            },
+        )
+        "###);
+    }
+
+    {
+        // Branch updates are skipped when low-signal events are hidden.
+        let screenshot1 = Default::default();
+        let event_cursor = run_select_past_event(
+            &git.get_repo()?,
+            vec![
+                CursiveTestingEvent::Event('f'.into()),
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot1)),
+                CursiveTestingEvent::Event(Key::Enter.into()),
+            ],
+        )?;
+        insta::assert_debug_snapshot!(event_cursor, @r###"
+        Some(
+            EventCursor {
+                event_id: 6,
+            },
+        )
+        "###);
+        insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+        ┌───────────────────────────────────────────────────┤─Commit graph ├───────────────────────────────────────────────────┐
+        │:                                                                                                                     │
+        │@ 96d1c37a (master) create test2.txt                                                                                  │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        │                                                                                                                      │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        ┌──────────────────────────────────────────────────────┤─Events ├──────────────────────────────────────────────────────┐
+        │Repo after transaction 4 (event 6) by `git commit -m 'create test2.txt'`. Press 'h' for help, 'q' to quit. Low-signal │
+        │events are hidden.                                                                                                    │
+        │1. Commit 96d1c37a create test2.txt                                                                                   │
+        │                                                                                                                      │
+        └──────────────────────────────────────────────────────────────────────────────────────────────────────────────────────┘
+        "###);
+    }
+
+    {
+        let event_cursor = run_select_past_event(
+            &git.get_repo()?,
+            vec![
+                CursiveTestingEvent::Event('p'.into()),
+                CursiveTestingEvent::Event(Key::Enter.into()),
+            ],
+        )?;
+        insta::assert_debug_snapshot!(event_cursor, @r###"
+        Some(
+            EventCursor {
+                event_id: 7,
+            },
+        )
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_to_unborn_head() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        run_branchless_init: false,
+    })?;
+    git.run(&["branchless", "init", "--main-branch", "master"])?;
+
+    git.commit_file("test1", 1)?;
+    git.write_file("untracked", "untracked contents")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y", "-n", "2"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git commit -m 'create test1.txt' (transaction 2)
+        2. git commit -m 'create test1.txt' (transaction 1)
+        Will apply these actions:
+        1. Check out from 6118a39b create test1.txt
+                       to an unborn HEAD (no commits)
+        2. Hide commit 6118a39b create test1.txt
+
+        3. Delete branch master at 6118a39b create test1.txt
+
+        Applied 3 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ?? untracked.txt
+        "###);
+        let (stdout, _stderr) = git.run(&["symbolic-ref", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        refs/heads/master
+        "###);
+        git.run_with_options(
+            &["rev-parse", "--verify", "--quiet", "HEAD"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        No commits have been made in this repository yet.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["redo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git undo -y -n 2 (transaction 3)
+        Will apply these actions:
+        1. Check out to 6118a39b create test1.txt
+
+        2. Recreate branch master at 6118a39b create test1.txt
+
+        3. Unhide commit 6118a39b create test1.txt
+
+        branchless: running command: <git-executable> checkout 6118a39b4dd4c986d17da2123d907ac17696cb85 --detach
+        @ 6118a39b (master) create test1.txt
+        Applied 3 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ?? untracked.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_aborts_conflicted_merge() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "foo"])?;
+    git.write_file("conflict", "foo contents\n")?;
+    git.run(&["add", "."])?;
+    git.run(&["commit", "-m", "foo"])?;
+    git.run(&["checkout", "master"])?;
+    git.write_file("conflict", "master contents\n")?;
+    git.run(&["add", "."])?;
+    git.run(&["commit", "-m", "master"])?;
+    git.run(&["checkout", "foo"])?;
+    git.run_with_options(
+        &["merge", "master"],
+        &GitRunOptions {
+            expected_exit_code: 1,
+            ..Default::default()
+        },
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+        insta::assert_snapshot!(stdout, @"AA conflict.txt");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git checkout foo (transaction 7)
+        Will apply these actions:
+        1. Check out from c6fedbba foo
+                       to 2f270aae master
+        Will abort the in-progress merge, discarding conflicts in 1 file (they will be saved to a working copy snapshot).
+        Saved uncommitted changes to working copy snapshot 30b30e4a61a08ec2b9ba03b4d21c9d86ad10bc27.
+        Aborted the in-progress merge.
+        branchless: running command: <git-executable> checkout 2f270aae97e905a9471aa041750508eaeadf5094 --detach
+        O f777ecc9 create initial.txt
+        |\
+        | o c6fedbba (foo) foo
+        |
+        @ 2f270aae (master) master
+        Applied 1 inverse event.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+    let repo = git.get_repo()?;
+    assert_eq!(repo.get_current_operation_type(), None);
+    for file_name in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"] {
+        assert!(!repo.get_path().join(file_name).exists());
+    }
+
+    // Redoing restores the conflicted file from the working copy snapshot.
+    git.run(&["redo", "-y"])?;
+    {
+        let contents = std::fs::read_to_string(git.repo_path.join("conflict.txt"))?;
+        insta::assert_snapshot!(contents, @r###"
+        <<<<<<< HEAD
+        foo contents
+        =======
+        master contents
+        >>>>>>> master
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_stash_pop() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.write_file("test1", "changed contents\n")?;
+    git.run(&["stash", "push", "-m", "first stash"])?;
+    git.commit_file("test2", 2)?;
+    git.write_file("test2", "changed contents\n")?;
+    git.run(&["stash", "push", "-m", "second stash"])?;
+    git.run(&["config", "branchless.smartlog.showStashes", "true"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 96d1c37a (master) create test2.txt
+        |
+        s stash@{0} 8377feb0 On master: second stash
+        "###);
+    }
+
+    git.run(&["stash", "pop"])?;
+    git.run(&["checkout", "--", "test2.txt"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["stash", "list"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        stash@{0}: On master: first stash
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git stash drop (transaction 9)
+        Will apply these actions:
+        1. Recreate stash at 8377feb0 On master: second stash
+           
+        Applied 1 inverse event.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["stash", "list"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        stash@{0}: On master: second stash
+        stash@{1}: On master: first stash
+        "###);
+    }
+
+    Ok(())
+}
@@ -49,23 +49,13 @@ fn test_wrap_rebase_in_transaction() -> eyre::Result<()> {
             ),
             ref_name: "HEAD",
             old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
-            new_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
-            message: None,
-        },
-        RefUpdateEvent {
-            timestamp: 0.0,
-            event_tx_id: EventTransactionId(
-                3,
-            ),
-            ref_name: "HEAD",
-            old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
             new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
             message: None,
         },
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                3,
+                2,
             ),
             ref_name: "refs/heads/foo",
             old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
@@ -75,14 +65,14 @@ fn test_wrap_rebase_in_transaction() -> eyre::Result<()> {
         CommitEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                4,
+                3,
             ),
             commit_oid: NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
         },
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                5,
+                4,
             ),
             ref_name: "HEAD",
             old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
@@ -92,7 +82,7 @@ fn test_wrap_rebase_in_transaction() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                5,
+                4,
             ),
             ref_name: "refs/heads/foo",
             old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
@@ -102,14 +92,14 @@ fn test_wrap_rebase_in_transaction() -> eyre::Result<()> {
         CommitEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                6,
+                5,
             ),
             commit_oid: NonZeroOid(96d1c37a3d4363611c49f7e52186e189a04c531f),
         },
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                7,
+                6,
             ),
             ref_name: "HEAD",
             old_oid: 96d1c37a3d4363611c49f7e52186e189a04c531f,
@@ -119,7 +109,7 @@ fn test_wrap_rebase_in_transaction() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                8,
+                7,
             ),
             ref_name: "HEAD",
             old_oid: 0000000000000000000000000000000000000000,
@@ -129,7 +119,7 @@ fn test_wrap_rebase_in_transaction() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                8,
+                7,
             ),
             ref_name: "HEAD",
             old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
@@ -139,7 +129,7 @@ fn test_wrap_rebase_in_transaction() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                8,
+                7,
             ),
             ref_name: "refs/heads/master",
             old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
@@ -207,3 +197,91 @@ fn test_wrap_exit_code() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_wrap_script_in_single_transaction() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.run(&["branch", "bar"])?;
+    git.commit_file("test2", 2)?;
+
+    // The alias runs several Git commands, each of which invokes the hooks.
+    // The old values of the branches are passed explicitly so that the hooks
+    // can record them.
+    git.run(&[
+        "branchless",
+        "wrap",
+        "--",
+        "-c",
+        "alias.move-both=!git update-ref refs/heads/foo HEAD foo && git update-ref refs/heads/bar HEAD bar",
+        "move-both",
+    ])?;
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+    let events: Vec<Event> = get_event_replayer_events(&event_replayer)
+        .iter()
+        .map(|event| redact_event_timestamp(event.clone()))
+        .collect();
+    let last_event_tx_id = events.last().unwrap().get_event_tx_id();
+    let wrapped_events: Vec<Event> = events
+        .into_iter()
+        .filter(|event| event.get_event_tx_id() == last_event_tx_id)
+        .collect();
+    insta::assert_debug_snapshot!(wrapped_events, @r###"
+    [
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                8,
+            ),
+            ref_name: "refs/heads/foo",
+            old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            new_oid: 96d1c37a3d4363611c49f7e52186e189a04c531f,
+            message: None,
+        },
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                8,
+            ),
+            ref_name: "refs/heads/bar",
+            old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            new_oid: 96d1c37a3d4363611c49f7e52186e189a04c531f,
+            message: None,
+        },
+    ]
+    "###);
+    insta::assert_debug_snapshot!(event_log_db.get_transaction_message(last_event_tx_id)?, @r###"
+    Some(
+        "git -c alias.move-both=!git update-ref refs/heads/foo HEAD foo && git update-ref refs/heads/bar HEAD bar move-both",
+    )
+    "###);
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git wrap -- -c 'alias.move-both=!git update-ref refs/heads/foo HEAD foo && git update-ref refs/heads/bar HEAD bar' move-both (transaction 8)
+        Will apply these actions:
+        1. Move branch bar from 96d1c37a create test2.txt
+                             to 62fc20d2 create test1.txt
+        2. Move branch foo from 96d1c37a create test2.txt
+                             to 62fc20d2 create test1.txt
+        Applied 2 inverse events.
+        "###);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use branchless::core::dag::{CommitSet, Dag};
+use branchless::core::effects::Effects;
+use branchless::core::eventlog::{EventLogDb, EventReplayer};
+use branchless::core::formatting::Glyphs;
+use branchless::git::Repo;
+use branchless::testing::make_git;
+
+fn open_dag(effects: &Effects, repo: &Repo) -> eyre::Result<Dag> {
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    let dag = Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+    Ok(dag)
+}
+
+#[test]
+fn test_merge_base_cache() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+
+    {
+        let dag = open_dag(&effects, &repo)?;
+        let path = dag.find_path_to_main_branch(&effects, CommitSet::from(test2_oid))?;
+        assert!(path.is_some());
+        assert_eq!(dag.get_merge_base_compute_count(), 1);
+        assert_eq!(dag.get_merge_base_cache_hit_count(), 0);
+    }
+
+    // A later command shouldn't have to compute the merge-base again.
+    {
+        let dag = open_dag(&effects, &repo)?;
+        let path = dag.find_path_to_main_branch(&effects, CommitSet::from(test2_oid))?;
+        assert!(path.is_some());
+        assert_eq!(dag.get_merge_base_compute_count(), 0);
+        assert_eq!(dag.get_merge_base_cache_hit_count(), 1);
+    }
+
+    // Once the main branch moves, the merge-base with the new main branch
+    // commit has to be computed.
+    git.commit_file("test4", 4)?;
+    {
+        let dag = open_dag(&effects, &repo)?;
+        let path = dag.find_path_to_main_branch(&effects, CommitSet::from(test2_oid))?;
+        assert!(path.is_some());
+        assert_eq!(dag.get_merge_base_compute_count(), 1);
+        assert_eq!(dag.get_merge_base_cache_hit_count(), 0);
+    }
+
+    Ok(())
+}
@@ -1,8 +1,10 @@
 use branchless::core::effects::Effects;
 use branchless::core::eventlog::testing::{get_event_replayer_events, redact_event_timestamp};
-use branchless::core::eventlog::{Event, EventLogDb, EventReplayer};
+use branchless::core::eventlog::{Event, EventLogDb, EventReplayer, EventTransactionId};
 use branchless::core::formatting::Glyphs;
+use branchless::git::Repo;
 use branchless::testing::make_git;
+use std::time::SystemTime;
 
 #[test]
 fn test_git_v2_31_events() -> eyre::Result<()> {
@@ -49,23 +51,13 @@ fn test_git_v2_31_events() -> eyre::Result<()> {
             ),
             ref_name: "HEAD",
             old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
-            new_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
-            message: None,
-        },
-        RefUpdateEvent {
-            timestamp: 0.0,
-            event_tx_id: EventTransactionId(
-                3,
-            ),
-            ref_name: "HEAD",
-            old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
             new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
             message: None,
         },
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                3,
+                2,
             ),
             ref_name: "refs/heads/test1",
             old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
@@ -75,14 +67,14 @@ fn test_git_v2_31_events() -> eyre::Result<()> {
         CommitEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                4,
+                3,
             ),
             commit_oid: NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
         },
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                5,
+                4,
             ),
             ref_name: "HEAD",
             old_oid: 0000000000000000000000000000000000000000,
@@ -92,7 +84,7 @@ fn test_git_v2_31_events() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                6,
+                5,
             ),
             ref_name: "HEAD",
             old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
@@ -102,7 +94,7 @@ fn test_git_v2_31_events() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                7,
+                6,
             ),
             ref_name: "HEAD",
             old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
@@ -112,21 +104,21 @@ fn test_git_v2_31_events() -> eyre::Result<()> {
         CommitEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                8,
+                7,
             ),
             commit_oid: NonZeroOid(fe65c1fe15584744e649b2c79d4cf9b0d878f92e),
         },
         ObsoleteEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                9,
+                8,
             ),
             commit_oid: NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
         },
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                10,
+                9,
             ),
             ref_name: "refs/heads/test1",
             old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
@@ -138,3 +130,113 @@ fn test_git_v2_31_events() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_concurrent_event_writes() -> eyre::Result<()> {
+    const NUM_WRITERS: usize = 8;
+    const NUM_EVENTS_PER_WRITER: usize = 25;
+
+    let git = make_git()?;
+    git.init_repo()?;
+    let commit_oid = git.commit_file("test1", 1)?;
+
+    let num_events_before = {
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        event_log_db.get_events()?.len()
+    };
+
+    let handles: Vec<_> = (0..NUM_WRITERS)
+        .map(|_| {
+            let repo_path = git.repo_path.clone();
+            std::thread::spawn(move || -> eyre::Result<()> {
+                let repo = Repo::from_dir(&repo_path)?;
+                let conn = repo.get_db_conn()?;
+                let mut event_log_db = EventLogDb::new(&conn)?;
+                for _ in 0..NUM_EVENTS_PER_WRITER {
+                    let event_tx_id =
+                        event_log_db.make_transaction_id(SystemTime::now(), "concurrent write")?;
+                    event_log_db.add_events(vec![Event::CommitEvent {
+                        timestamp: 0.0,
+                        event_tx_id,
+                        commit_oid,
+                    }])?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("Writer thread panicked")?;
+    }
+
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    assert_eq!(
+        event_log_db.get_events()?.len(),
+        num_events_before + NUM_WRITERS * NUM_EVENTS_PER_WRITER
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_transaction_commands() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&[
+        "branchless",
+        "move",
+        "-s",
+        &test2_oid.to_string(),
+        "-d",
+        "master",
+    ])?;
+    let test3_oid = git.commit_file("test3", 3)?;
+
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let events = event_log_db.get_events()?;
+    let find_event_tx_id = |predicate: &dyn Fn(&Event) -> bool| -> EventTransactionId {
+        events
+            .iter()
+            .find(|event| predicate(event))
+            .expect("Event should have been recorded")
+            .get_event_tx_id()
+    };
+
+    let move_event_tx_id = find_event_tx_id(&|event| {
+        matches!(event, Event::RewriteEvent { old_commit_oid, .. }
+            if *old_commit_oid == test2_oid.into())
+    });
+    insta::assert_debug_snapshot!(
+        event_log_db.get_transaction_command(move_event_tx_id)?,
+        @r###"
+    Some(
+        "git move -s 96d1c37a3d4363611c49f7e52186e189a04c531f -d master",
+    )
+    "###);
+
+    let commit_event_tx_id = find_event_tx_id(
+        &|event| matches!(event, Event::CommitEvent { commit_oid, .. } if *commit_oid == test3_oid),
+    );
+    let commit_command = event_log_db.get_transaction_command(commit_event_tx_id)?;
+    if cfg!(target_os = "linux") {
+        insta::assert_debug_snapshot!(commit_command, @r###"
+        Some(
+            "git commit -m 'create test3.txt'",
+        )
+        "###);
+    } else {
+        // The invoking Git command can't be determined on other platforms.
+        assert_eq!(commit_command, Some("hook-post-commit".to_string()));
+    }
+
+    Ok(())
+}
@@ -1,7 +1,9 @@
 use branchless::core::eventlog::testing::redact_event_timestamp;
-use branchless::core::eventlog::EventLogDb;
-use branchless::testing::make_git;
+use branchless::core::eventlog::{Event, EventLogDb};
+use branchless::git::MaybeZeroOid;
+use branchless::testing::{make_git, Git};
 use itertools::Itertools;
+use std::time::SystemTime;
 
 #[test]
 fn test_gc() -> eyre::Result<()> {
@@ -32,7 +34,49 @@ o 62fc20d2 create test1.txt
         let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
         insta::assert_snapshot!(stdout, @r###"
         branchless: collecting garbage
-        branchless: 1 dangling reference deleted
+        branchless: released 0 hidden commits
+        branchless: deleted 0 anchor refs
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+    }
+
+    // The hidden commit is still within the retention period, so it should
+    // survive Git's garbage collection and be restorable with `git undo`.
+    git.run(&["gc", "--prune=now"])?;
+    {
+        let repo = git.get_repo()?;
+        assert!(repo.revparse_single_commit("62fc20d2")?.is_some())
+    }
+    git.run(&["undo", "-y"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 create test1.txt
+        "###);
+    }
+
+    git.run(&["hide", "62fc20d2"])?;
+    git.run(&["config", "branchless.gc.retentionDays", "0"])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "gc", "--dry-run"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: would release 1 hidden commit
+        branchless:   1 hidden less than 1 week ago
+        branchless: would delete 1 anchor ref
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: released 1 hidden commit
+        branchless:   1 hidden less than 1 week ago
+        branchless: deleted 1 anchor ref
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
         "###);
     }
 
@@ -52,6 +96,87 @@ o 62fc20d2 create test1.txt
     Ok(())
 }
 
+/// Convert the output of `git branchless gc --dry-run` into the output that a
+/// real run is expected to produce.
+fn dry_run_to_past_tense(output: &str) -> String {
+    output
+        .replace("would anchor", "anchored")
+        .replace("would release", "released")
+        .replace("would delete", "deleted")
+        .replace("would prune", "pruned")
+}
+
+fn get_gc_refs(git: &Git) -> eyre::Result<Vec<String>> {
+    let (stdout, _stderr) =
+        git.run(&["for-each-ref", "--format=%(refname)", "refs/branchless/"])?;
+    Ok(stdout.lines().map(|line| line.to_string()).collect())
+}
+
+#[test]
+fn test_gc_dry_run_matches_real_run() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.run(&["hide", "-r", &test1_oid.to_string()])?;
+    git.run(&["config", "branchless.gc.retentionDays", "0"])?;
+    git.run(&["config", "branchless.eventlog.pruneKeepTransactions", "2"])?;
+
+    let gc_refs_before = get_gc_refs(&git)?;
+    let num_events_before = {
+        let conn = git.get_repo()?.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        event_log_db.get_events()?.len()
+    };
+
+    let args = ["branchless", "gc", "--prune-events", "--before", "0s"];
+    let dry_run_stdout = {
+        let (stdout, _stderr) = git.run(&[&args[..], &["--dry-run"]].concat())?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: would release 3 hidden commits
+        branchless:   3 hidden less than 1 week ago
+        branchless: would delete 3 anchor refs
+        branchless: would prune 6 events from 6 transactions
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+        stdout
+    };
+    assert_eq!(get_gc_refs(&git)?, gc_refs_before);
+
+    {
+        let (stdout, _stderr) = git.run(&args)?;
+        assert_eq!(stdout, dry_run_to_past_tense(&dry_run_stdout));
+    }
+
+    // The real run should have had exactly the effects described by the dry
+    // run.
+    let gc_refs_after = get_gc_refs(&git)?;
+    let deleted_gc_refs = gc_refs_before
+        .iter()
+        .filter(|ref_name| !gc_refs_after.contains(ref_name))
+        .cloned()
+        .collect_vec();
+    let expected_deleted_gc_refs = [test1_oid, test2_oid, test3_oid]
+        .iter()
+        .map(|oid| format!("refs/branchless/{}", oid))
+        .sorted()
+        .collect_vec();
+    assert_eq!(deleted_gc_refs, expected_deleted_gc_refs);
+    let num_events_after = {
+        let conn = git.get_repo()?.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        event_log_db.get_events()?.len()
+    };
+    insta::assert_snapshot!(format!("{} events pruned", num_events_before - num_events_after), @"6 events pruned");
+
+    Ok(())
+}
+
 #[test]
 fn test_gc_reference_transaction() -> eyre::Result<()> {
     let git = make_git()?;
@@ -66,12 +191,16 @@ fn test_gc_reference_transaction() -> eyre::Result<()> {
     git.commit_file("test2", 2)?;
     git.run(&["hide", "HEAD"])?;
     git.run(&["checkout", "HEAD^"])?;
+    git.run(&["config", "branchless.gc.retentionDays", "0"])?;
 
     {
         let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
         insta::assert_snapshot!(stdout, @r###"
         branchless: collecting garbage
-        branchless: 1 dangling reference deleted
+        branchless: released 1 hidden commit
+        branchless:   1 hidden less than 1 week ago
+        branchless: deleted 1 anchor ref
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
         "###);
     }
 
@@ -130,37 +259,27 @@ fn test_gc_reference_transaction() -> eyre::Result<()> {
             ),
             ref_name: "HEAD",
             old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
-            new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
-            message: None,
-        },
-        RefUpdateEvent {
-            timestamp: 0.0,
-            event_tx_id: EventTransactionId(
-                5,
-            ),
-            ref_name: "HEAD",
-            old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
             new_oid: 96d1c37a3d4363611c49f7e52186e189a04c531f,
             message: None,
         },
         CommitEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                6,
+                5,
             ),
             commit_oid: NonZeroOid(96d1c37a3d4363611c49f7e52186e189a04c531f),
         },
         ObsoleteEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                7,
+                6,
             ),
             commit_oid: NonZeroOid(96d1c37a3d4363611c49f7e52186e189a04c531f),
         },
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                8,
+                7,
             ),
             ref_name: "HEAD",
             old_oid: 0000000000000000000000000000000000000000,
@@ -170,7 +289,7 @@ fn test_gc_reference_transaction() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                9,
+                8,
             ),
             ref_name: "HEAD",
             old_oid: 96d1c37a3d4363611c49f7e52186e189a04c531f,
@@ -180,7 +299,7 @@ fn test_gc_reference_transaction() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                10,
+                9,
             ),
             ref_name: "refs/heads/master",
             old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
@@ -190,7 +309,7 @@ fn test_gc_reference_transaction() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                11,
+                10,
             ),
             ref_name: "refs/heads/master",
             old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
@@ -202,3 +321,223 @@ fn test_gc_reference_transaction() -> eyre::Result<()> {
 
     Ok(())
 }
+
+fn get_db_size(git: &Git) -> eyre::Result<u64> {
+    let dir = git.repo_path.join(".git").join("branchless");
+    let mut size = 0;
+    for file_name in ["db.sqlite3", "db.sqlite3-wal"] {
+        if let Ok(metadata) = std::fs::metadata(dir.join(file_name)) {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+#[test]
+fn test_gc_prune_events() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+
+    // Simulate a lot of noise from fetches.
+    {
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let mut event_log_db = EventLogDb::new(&conn)?;
+        let initial_oid: MaybeZeroOid = "f777ecc9b0db5ed372b2615695191a8a17f79f24".parse()?;
+        for _ in 0..500 {
+            let now = SystemTime::now();
+            let event_tx_id = event_log_db.make_transaction_id(now, "fetch")?;
+            event_log_db.add_events(vec![Event::RefUpdateEvent {
+                timestamp: now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64(),
+                event_tx_id,
+                ref_name: "refs/remotes/origin/master".into(),
+                old_oid: initial_oid,
+                new_oid: initial_oid,
+                message: Some("fetch: fast-forward".repeat(10).into()),
+            }])?;
+        }
+    }
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.run(&["commit", "--amend", "-m", "amended test1"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["hide", "HEAD"])?;
+    git.run(&["checkout", "HEAD^"])?;
+    git.run(&["config", "branchless.gc.retentionDays", "0"])?;
+    git.run(&["branchless", "gc"])?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test4", 4)?;
+
+    let smartlog_before = {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | o ae94dc2a (foo) amended test1
+        | |
+        | @ 541deb8c create test4.txt
+        |
+        x 62fc20d2 (rewritten as ae94dc2a) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        "###);
+        stdout
+    };
+    let db_size_before = get_db_size(&git)?;
+
+    git.run(&["config", "branchless.eventlog.pruneKeepTransactions", "3"])?;
+    {
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "gc",
+            "--prune-events",
+            "--before",
+            "0s",
+            "--dry-run",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: would release 0 hidden commits
+        branchless: would delete 0 anchor refs
+        branchless: would prune 512 events from 512 transactions
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+    }
+    {
+        let (stdout, _stderr) =
+            git.run(&["branchless", "gc", "--prune-events", "--before", "0s"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: released 0 hidden commits
+        branchless: deleted 0 anchor refs
+        branchless: pruned 512 events from 512 transactions
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+    }
+
+    assert!(get_db_size(&git)? < db_size_before);
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        assert_eq!(stdout, smartlog_before);
+    }
+
+    {
+        let conn = git.get_repo()?.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        let events = event_log_db
+            .get_events()?
+            .into_iter()
+            .map(redact_event_timestamp)
+            .collect_vec();
+        insta::assert_debug_snapshot!(events, @r###"
+        [
+            CommitEvent {
+                timestamp: 0.0,
+                event_tx_id: EventTransactionId(
+                    505,
+                ),
+                commit_oid: NonZeroOid(96d1c37a3d4363611c49f7e52186e189a04c531f),
+            },
+            RewriteEvent {
+                timestamp: 0.0,
+                event_tx_id: EventTransactionId(
+                    510,
+                ),
+                old_commit_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+                new_commit_oid: ae94dc2a748bc0965c88fcf3edac2e30074ff7e2,
+            },
+            RefUpdateEvent {
+                timestamp: 0.0,
+                event_tx_id: EventTransactionId(
+                    515,
+                ),
+                ref_name: "HEAD",
+                old_oid: 26bc61d8d639abf6b829810dce2b1000661c2824,
+                new_oid: ae94dc2a748bc0965c88fcf3edac2e30074ff7e2,
+                message: None,
+            },
+            RefUpdateEvent {
+                timestamp: 0.0,
+                event_tx_id: EventTransactionId(
+                    516,
+                ),
+                ref_name: "refs/heads/foo",
+                old_oid: 0000000000000000000000000000000000000000,
+                new_oid: ae94dc2a748bc0965c88fcf3edac2e30074ff7e2,
+                message: None,
+            },
+            RefUpdateEvent {
+                timestamp: 0.0,
+                event_tx_id: EventTransactionId(
+                    517,
+                ),
+                ref_name: "HEAD",
+                old_oid: ae94dc2a748bc0965c88fcf3edac2e30074ff7e2,
+                new_oid: 541deb8c9d3951e32f5fed27d574c156e0b28676,
+                message: None,
+            },
+            CommitEvent {
+                timestamp: 0.0,
+                event_tx_id: EventTransactionId(
+                    518,
+                ),
+                commit_oid: NonZeroOid(541deb8c9d3951e32f5fed27d574c156e0b28676),
+            },
+        ]
+        "###);
+    }
+
+    git.run(&["undo", "-y", "-n", "2"])?;
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ ae94dc2a (foo) amended test1
+        |
+        x 62fc20d2 (rewritten as ae94dc2a) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_gc_auto_prune_events() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "hook-pre-auto-gc"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: released 0 hidden commits
+        branchless: deleted 0 anchor refs
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+    }
+
+    git.run(&["config", "branchless.eventlog.autoPruneDays", "0"])?;
+    git.run(&["config", "branchless.eventlog.pruneKeepTransactions", "1"])?;
+    {
+        let (stdout, _stderr) = git.run(&["branchless", "hook-pre-auto-gc"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: collecting garbage
+        branchless: released 0 hidden commits
+        branchless: deleted 0 anchor refs
+        branchless: pruned 2 events from 1 transaction
+        branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
+        "###);
+    }
+
+    Ok(())
+}
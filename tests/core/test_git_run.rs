@@ -0,0 +1,80 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use branchless::core::effects::Effects;
+use branchless::core::formatting::Glyphs;
+use branchless::git::{GitRunInfo, GitRunStreamingOpts};
+use branchless::testing::make_git;
+
+#[test]
+fn test_run_streaming() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    // Don't write the second line until the first line has been observed by
+    // the caller, to check that output isn't buffered until the process exits.
+    git.run(&[
+        "config",
+        "alias.slow",
+        "!f() { \
+            echo one; \
+            i=0; \
+            while [ ! -f marker ] && [ $i -lt 100 ]; do sleep 0.1; i=$((i+1)); done; \
+            if [ -f marker ]; then echo two >&2; else echo timed out >&2; fi; \
+            printf three; \
+            exit 3; \
+        }; f",
+    ])?;
+
+    let repo = git.get_repo()?;
+    let git_run_info = GitRunInfo {
+        path_to_git: git.path_to_git.clone(),
+        working_directory: repo.get_working_copy_path().unwrap().to_path_buf(),
+        env: git.get_base_env(0).into_iter().collect(),
+    };
+    let stdout: Arc<Mutex<Vec<u8>>> = Default::default();
+    let stderr: Arc<Mutex<Vec<u8>>> = Default::default();
+    let effects = Effects::new_from_buffer_for_test(Glyphs::text(), &stdout, &stderr);
+
+    let watcher_thread = thread::spawn({
+        let stdout = Arc::clone(&stdout);
+        let marker_path = repo.get_working_copy_path().unwrap().join("marker");
+        move || {
+            for _ in 0..100 {
+                if String::from_utf8_lossy(&stdout.lock().unwrap()).contains("[git] one\n") {
+                    std::fs::write(marker_path, "").unwrap();
+                    return;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    });
+
+    let result = git_run_info.run_streaming(
+        &effects,
+        &repo,
+        None,
+        &["slow"],
+        GitRunStreamingOpts {
+            treat_git_failure_as_error: false,
+            ..Default::default()
+        },
+    )?;
+    watcher_thread.join().unwrap();
+
+    assert_eq!(result.exit_code, 3);
+    assert_eq!(String::from_utf8(result.stdout)?, "one\nthree");
+    assert_eq!(String::from_utf8(result.stderr)?, "two\n");
+
+    let stdout = git.preprocess_output(String::from_utf8(stdout.lock().unwrap().clone())?)?;
+    insta::assert_snapshot!(stdout, @r###"
+    branchless: running command: <git-executable> slow
+    [git] one
+    [git] three
+    "###);
+    let stderr = git.preprocess_output(String::from_utf8(stderr.lock().unwrap().clone())?)?;
+    insta::assert_snapshot!(stderr, @"[git] two");
+
+    Ok(())
+}
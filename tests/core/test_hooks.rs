@@ -2,11 +2,14 @@ use branchless::core::effects::Effects;
 use branchless::core::eventlog::testing::{get_event_replayer_events, redact_event_timestamp};
 use branchless::core::eventlog::{Event, EventLogDb, EventReplayer};
 use branchless::core::formatting::Glyphs;
-use branchless::testing::make_git;
+use branchless::git::{GitVersion, Repo};
+use branchless::testing::{make_git, Git, GitInitOptions, GitRunOptions};
 use branchless::util::get_sh;
 use eyre::{eyre, Context};
 use std::process::Command;
 
+use crate::util::trim_lines;
+
 #[test]
 fn test_abandoned_commit_message() -> eyre::Result<()> {
     let git = make_git()?;
@@ -150,6 +153,191 @@ fn test_rebase_individual_commit() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_rebase_events_share_transaction() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "-b", "foo", "HEAD^"])?;
+    for i in 2..=6 {
+        git.commit_file(&format!("test{}", i), i)?;
+    }
+
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let num_events_before = event_log_db.get_events()?.len();
+
+    git.run(&["rebase", "master"])?;
+
+    let mut event_tx_ids: Vec<_> = event_log_db.get_events()?[num_events_before..]
+        .iter()
+        .map(|event| event.get_event_tx_id())
+        .collect();
+    event_tx_ids.dedup();
+    assert_eq!(event_tx_ids.len(), 1);
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git rebase master (5 commits) (transaction 15)
+        Will apply these actions:
+        1. Check out from 84a891e7 create test6.txt
+                       to 913c4e52 create test6.txt
+        2. Rewrite commit 84a891e7 create test6.txt
+                      as 913c4e52 create test6.txt
+        3. Rewrite commit e9a03037 create test5.txt
+                      as 447139aa create test5.txt
+        4. Rewrite commit 5ed59bad create test4.txt
+                      as 8e62740b create test4.txt
+        5. Rewrite commit 2cbf1841 create test3.txt
+                      as 02067177 create test3.txt
+        6. Rewrite commit f8d9985b create test2.txt
+                      as fe65c1fe create test2.txt
+        7. Move branch foo from 84a891e7 create test6.txt
+                             to 913c4e52 create test6.txt
+        8. Hide commit 84a891e7 create test6.txt
+
+        9. Hide commit e9a03037 create test5.txt
+
+        10. Hide commit 5ed59bad create test4.txt
+
+        11. Hide commit 2cbf1841 create test3.txt
+
+        12. Hide commit f8d9985b create test2.txt
+
+        branchless: running command: <git-executable> checkout 913c4e527641dc3cd9438037b9022f2fbe9bfe28 --detach
+        O f777ecc9 create initial.txt
+        |\
+        | x fe65c1fe (rewritten as f8d9985b) create test2.txt
+        | |
+        | x 02067177 (rewritten as 2cbf1841) create test3.txt
+        | |
+        | x 8e62740b (rewritten as 5ed59bad) create test4.txt
+        | |
+        | x 447139aa (rewritten as e9a03037) create test5.txt
+        | |
+        | % 913c4e52 (rewritten as 84a891e7) create test6.txt
+        |
+        O 62fc20d2 (master) create test1.txt
+        |
+        o f8d9985b create test2.txt
+        |
+        o 2cbf1841 create test3.txt
+        |
+        o 5ed59bad create test4.txt
+        |
+        o e9a03037 create test5.txt
+        |
+        o 84a891e7 (foo) create test6.txt
+        Applied 12 inverse events.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 create initial.txt
+        |\
+        | o fe65c1fe create test2.txt
+        | |
+        | o 02067177 create test3.txt
+        | |
+        | o 8e62740b create test4.txt
+        | |
+        | o 447139aa create test5.txt
+        | |
+        | @ 913c4e52 (foo) create test6.txt
+        |
+        O 62fc20d2 (master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_am_events_share_transaction() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "-b", "foo", "HEAD^"])?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    let (patches, _stderr) = git.run(&["format-patch", "--stdout", "HEAD~2"])?;
+    git.write_file("patches", &patches)?;
+    git.run(&["checkout", "master"])?;
+
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let num_events_before = event_log_db.get_events()?.len();
+
+    {
+        let (_stdout, stderr) = git.run(&["am", "patches.txt"])?;
+        let stderr = trim_lines(stderr);
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: processing 2 updates: branch master, ref HEAD
+        branchless: processed commit: f8d9985b create test2.txt
+        branchless: processing 2 updates: branch master, ref HEAD
+        branchless: processed commit: 2cbf1841 create test3.txt
+        "###);
+    }
+
+    let events = &event_log_db.get_events()?[num_events_before..];
+    let mut event_tx_ids: Vec<_> = events.iter().map(|event| event.get_event_tx_id()).collect();
+    event_tx_ids.dedup();
+    assert_eq!(event_tx_ids.len(), 1);
+    let num_commit_events = events
+        .iter()
+        .filter(|event| matches!(event, Event::CommitEvent { .. }))
+        .count();
+    assert_eq!(num_commit_events, 2);
+
+    {
+        let (stdout, _stderr) = git.run(&["undo", "-y"])?;
+        let stdout = trim_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Will undo these commands:
+        1. git am patches.txt (2 commits) (transaction 10)
+        Will apply these actions:
+        1. Check out from 2cbf1841 create test3.txt
+                       to 62fc20d2 create test1.txt
+        2. Hide commit 2cbf1841 create test3.txt
+
+        3. Hide commit f8d9985b create test2.txt
+
+        4. Move branch master from 2cbf1841 create test3.txt
+                                to 62fc20d2 create test1.txt
+        branchless: running command: <git-executable> checkout 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --detach
+        O f777ecc9 create initial.txt
+        |\
+        | o fe65c1fe create test2.txt
+        | |
+        | o 02067177 (foo) create test3.txt
+        |
+        @ 62fc20d2 create test1.txt
+        :
+        O 2cbf1841 (master) create test3.txt
+        Applied 4 inverse events.
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_interactive_rebase_noop() -> eyre::Result<()> {
     let git = make_git()?;
@@ -203,9 +391,12 @@ fn test_pre_auto_gc() -> eyre::Result<()> {
         stdout,
         stderr
     );
+    let stdout = git.preprocess_output(stdout)?;
     insta::assert_snapshot!(stdout, @r###"
     branchless: collecting garbage
-    branchless: 0 dangling references deleted
+    branchless: released 0 hidden commits
+    branchless: deleted 0 anchor refs
+    branchless: up to <size> KiB of loose objects can be reclaimed by `git gc`
     "###);
 
     Ok(())
@@ -304,7 +495,7 @@ fn test_merge_commit_recorded() -> eyre::Result<()> {
         RefUpdateEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                8,
+                7,
             ),
             ref_name: "HEAD",
             old_oid: fe65c1fe15584744e649b2c79d4cf9b0d878f92e,
@@ -314,7 +505,7 @@ fn test_merge_commit_recorded() -> eyre::Result<()> {
         CommitEvent {
             timestamp: 0.0,
             event_tx_id: EventTransactionId(
-                9,
+                8,
             ),
             commit_oid: NonZeroOid(91a5ccb4feefba38b0ffa4911c5c3f6c225f662e),
         },
@@ -323,3 +514,677 @@ fn test_merge_commit_recorded() -> eyre::Result<()> {
 
     Ok(())
 }
+
+fn get_ref_update_names(git: &branchless::testing::Git) -> eyre::Result<Vec<String>> {
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let ref_names = event_log_db
+        .get_events()?
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::RefUpdateEvent { ref_name, .. } => Some(ref_name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    Ok(ref_names)
+}
+
+#[test]
+fn test_reference_transaction_filters_ignored_refs() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let num_events_before = get_ref_update_names(&git)?.len();
+
+    let zero_oid = "0000000000000000000000000000000000000000";
+    let mut input = String::new();
+    for i in 0..5000 {
+        let ref_name = match i % 3 {
+            0 => format!("refs/remotes/origin/branch-{}", i),
+            1 => format!("refs/notes/note-{}", i),
+            _ => format!("refs/branchless/{}", i),
+        };
+        input.push_str(&format!("{} {} {}\n", zero_oid, test1_oid, ref_name));
+    }
+    input.push_str(&format!("{} {} refs/heads/foo\n", zero_oid, test1_oid));
+    input.push_str(&format!("{} {} refs/heads/bar\n", zero_oid, test1_oid));
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "hook-reference-transaction", "committed"],
+            &GitRunOptions {
+                input: Some(input),
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: processing 2 updates: branch bar, branch foo
+        "###);
+    }
+
+    let ref_names = get_ref_update_names(&git)?;
+    insta::assert_debug_snapshot!(&ref_names[num_events_before..], @r###"
+    [
+        "refs/heads/foo",
+        "refs/heads/bar",
+    ]
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_reference_transaction_only_ignored_refs() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let num_events_before = get_ref_update_names(&git)?.len();
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "hook-reference-transaction", "committed"],
+            &GitRunOptions {
+                input: Some(format!(
+                    "0000000000000000000000000000000000000000 {} refs/remotes/origin/foo\n",
+                    test1_oid
+                )),
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+    assert_eq!(get_ref_update_names(&git)?.len(), num_events_before);
+
+    Ok(())
+}
+
+#[test]
+fn test_reference_transaction_records_branch_updates() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.run(&[
+        "config",
+        "branchless.eventlog.ignoreRefs",
+        "refs/heads/ignored-*",
+    ])?;
+    let num_events_before = get_ref_update_names(&git)?.len();
+
+    git.run(&["branch", "foo"])?;
+    git.run(&["branch", "ignored-bar"])?;
+    git.run(&["update-ref", "refs/remotes/origin/baz", "HEAD"])?;
+
+    let ref_names = get_ref_update_names(&git)?;
+    insta::assert_debug_snapshot!(&ref_names[num_events_before..], @r###"
+    [
+        "refs/heads/foo",
+        "refs/remotes/origin/baz",
+    ]
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_worktrees_share_event_log() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+
+    let worktree_dir = tempfile::tempdir()?;
+    let worktree_path = worktree_dir.path().join("wt");
+    git.run(&[
+        "worktree",
+        "add",
+        "-b",
+        "wt-branch",
+        worktree_path.to_str().unwrap(),
+    ])?;
+    let worktree_git = Git {
+        repo_path: worktree_path,
+        path_to_git: git.path_to_git.clone(),
+    };
+
+    worktree_git.commit_file("test2", 2)?;
+    worktree_git.run(&["checkout", "HEAD^"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "HEAD^"])?;
+
+    let get_events = |repo: &Repo| -> eyre::Result<Vec<Event>> {
+        let effects = Effects::new_suppress_for_test(Glyphs::text());
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        let event_replayer = EventReplayer::from_event_log_db(&effects, repo, &event_log_db)?;
+        let events = get_event_replayer_events(&event_replayer)
+            .iter()
+            .map(|event| redact_event_timestamp(event.clone()))
+            .collect();
+        Ok(events)
+    };
+    let events = get_events(&git.get_repo()?)?;
+    assert_eq!(events, get_events(&worktree_git.get_repo()?)?);
+
+    let events: Vec<Event> = events
+        .into_iter()
+        .filter(|event| match event {
+            Event::CommitEvent { .. } => true,
+            Event::RefUpdateEvent { ref_name, .. } => ref_name == "HEAD",
+            _ => false,
+        })
+        .collect();
+    insta::assert_debug_snapshot!(events, @r###"
+    [
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                1,
+            ),
+            ref_name: "HEAD",
+            old_oid: f777ecc9b0db5ed372b2615695191a8a17f79f24,
+            new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            message: None,
+        },
+        CommitEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                2,
+            ),
+            commit_oid: NonZeroOid(62fc20d2a290daea0d52bdc2ed2ad4be6491010e),
+        },
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                4,
+            ),
+            ref_name: "HEAD",
+            old_oid: 0000000000000000000000000000000000000000,
+            new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            message: Some(
+                "wt",
+            ),
+        },
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                5,
+            ),
+            ref_name: "HEAD",
+            old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            new_oid: 96d1c37a3d4363611c49f7e52186e189a04c531f,
+            message: Some(
+                "wt",
+            ),
+        },
+        CommitEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                6,
+            ),
+            commit_oid: NonZeroOid(96d1c37a3d4363611c49f7e52186e189a04c531f),
+        },
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                7,
+            ),
+            ref_name: "HEAD",
+            old_oid: 0000000000000000000000000000000000000000,
+            new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            message: Some(
+                "wt",
+            ),
+        },
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                8,
+            ),
+            ref_name: "HEAD",
+            old_oid: 96d1c37a3d4363611c49f7e52186e189a04c531f,
+            new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            message: Some(
+                "wt",
+            ),
+        },
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                9,
+            ),
+            ref_name: "HEAD",
+            old_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            new_oid: 4838e49b08954becdd17c0900c1179c2c654c627,
+            message: None,
+        },
+        CommitEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                10,
+            ),
+            commit_oid: NonZeroOid(4838e49b08954becdd17c0900c1179c2c654c627),
+        },
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                11,
+            ),
+            ref_name: "HEAD",
+            old_oid: 0000000000000000000000000000000000000000,
+            new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            message: None,
+        },
+        RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id: EventTransactionId(
+                12,
+            ),
+            ref_name: "HEAD",
+            old_oid: 4838e49b08954becdd17c0900c1179c2c654c627,
+            new_oid: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+            message: None,
+        },
+    ]
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_ignored_refs_not_recorded() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.run(&[
+        "config",
+        "--add",
+        "branchless.eventlog.ignoreRefs",
+        "refs/notes/*",
+    ])?;
+    git.run(&[
+        "config",
+        "--add",
+        "branchless.eventlog.ignoreRefs",
+        "refs/pipelines/*",
+    ])?;
+    git.run(&[
+        "config",
+        "--add",
+        "branchless.eventlog.ignoreRefs",
+        "refs/heads/ci-*",
+    ])?;
+    let num_events_before = get_ref_update_names(&git)?.len();
+
+    git.run(&["notes", "add", "-m", "a note"])?;
+    let (pipeline_oid, _stderr) =
+        git.run(&["commit-tree", "-p", "HEAD", "-m", "ci", "HEAD^{tree}"])?;
+    let pipeline_oid = pipeline_oid.trim();
+    git.run(&["update-ref", "refs/pipelines/1", pipeline_oid])?;
+    git.run(&["branch", "ci-build", pipeline_oid])?;
+    git.run(&["branch", "foo"])?;
+
+    let ref_names = get_ref_update_names(&git)?;
+    insta::assert_debug_snapshot!(&ref_names[num_events_before..], @r###"
+    [
+        "refs/heads/foo",
+    ]
+    "###);
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (foo, master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_hooks_quiet() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+
+    {
+        let (_stdout, stderr) = git.run(&["commit", "--allow-empty", "-m", "not quiet"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: processing 2 updates: branch master, ref HEAD
+        branchless: processed commit: 519bf4d6 not quiet
+        "###);
+    }
+
+    git.run(&["config", "branchless.hooks.quiet", "true"])?;
+    {
+        let (_stdout, stderr) = git.run(&["commit", "--allow-empty", "-m", "quiet"])?;
+        insta::assert_snapshot!(stderr, @"");
+    }
+
+    git.run(&["checkout", "HEAD^"])?;
+    {
+        let (_stdout, stderr) =
+            git.run(&["commit", "--amend", "--allow-empty", "-m", "amended"])?;
+        insta::assert_snapshot!(stderr, @"");
+    }
+
+    git.run(&["config", "--unset", "branchless.hooks.quiet"])?;
+    {
+        let (_stdout, stderr) = git.run_with_options(
+            &["commit", "--allow-empty", "-m", "quiet from env"],
+            &GitRunOptions {
+                env: {
+                    let mut env = std::collections::HashMap::new();
+                    env.insert("BRANCHLESS_QUIET".to_string(), "1".to_string());
+                    env
+                },
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_post_checkout_skips_file_checkout() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.write_file("test2", "modified contents")?;
+
+    let get_num_events = || -> eyre::Result<usize> {
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        Ok(event_log_db.get_events()?.len())
+    };
+    let num_events_before = get_num_events()?;
+
+    git.run(&["checkout", "--", "test2.txt"])?;
+    git.run(&["checkout", "HEAD^", "--", "test1.txt"])?;
+    {
+        let test2_oid = test2_oid.to_string();
+        let (stdout, _stderr) = git.run(&[
+            "branchless",
+            "hook-post-checkout",
+            &test2_oid,
+            &test2_oid,
+            "1",
+        ])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+    assert_eq!(get_num_events()?, num_events_before);
+
+    Ok(())
+}
+
+#[test]
+fn test_external_amend_marks_old_commit_obsolete() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "HEAD^"])?;
+
+    {
+        let (_stdout, stderr) = git.run(&["commit", "--amend", "-m", "amended test1"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: processing 1 update: ref HEAD
+        branchless: processed commit: ae94dc2a amended test1
+        branchless: processing 1 rewritten commit
+        branchless: This operation abandoned 1 commit!
+        branchless: Consider running one of the following:
+        branchless:   - git restack: re-apply the abandoned commits/branches
+        branchless:     (this is most likely what you want to do)
+        branchless:   - git smartlog: assess the situation
+        branchless:   - git hide [<commit>...]: hide the commits from the smartlog
+        branchless:   - git undo: undo the operation
+        branchless:   - git config branchless.restack.warnAbandoned false: suppress this message
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ ae94dc2a amended test1
+        |
+        x 62fc20d2 (rewritten as ae94dc2a) create test1.txt
+        |
+        o 96d1c37a create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_post_rewrite_unknown_commits() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["branchless", "hook-post-rewrite", "rebase"],
+            &GitRunOptions {
+                input: Some(format!(
+                    "1111111111111111111111111111111111111111 {}\n\n",
+                    test1_oid
+                )),
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @"branchless: processing 1 rewritten commit");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d2 (master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_external_rebase_anchors_commits() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    // The `--apply` option was added in Git v2.26.
+    if git.get_version()? < GitVersion(2, 26, 0) {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "-b", "foo", "HEAD^"])?;
+    git.commit_file("test2", 2)?;
+    // Use the `apply` backend, which doesn't invoke the `post-commit` hook,
+    // so the rebased commit is only seen by the `post-rewrite` hook.
+    git.run(&["rebase", "--apply", "master"])?;
+
+    let (stdout, _stderr) = git.run(&["rev-parse", "HEAD"])?;
+    let rebased_oid = stdout.trim();
+    let (stdout, _stderr) =
+        git.run(&["for-each-ref", "--format=%(objectname)", "refs/branchless/"])?;
+    assert!(stdout.lines().any(|line| line == rebased_oid));
+
+    Ok(())
+}
+
+#[test]
+fn test_hooks_noop_when_not_initialized() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+    let head_oid = git.commit_file("test1", 1)?;
+    let head_oid = head_oid.to_string();
+
+    let hook_invocations: Vec<(Vec<&str>, Option<String>)> = vec![
+        (vec!["branchless", "hook-post-commit"], None),
+        (
+            vec![
+                "branchless",
+                "hook-post-checkout",
+                &head_oid,
+                &head_oid,
+                "1",
+            ],
+            None,
+        ),
+        (
+            vec!["branchless", "hook-post-rewrite", "amend"],
+            Some(format!("{} {}\n", head_oid, head_oid)),
+        ),
+        (
+            vec!["branchless", "hook-reference-transaction", "committed"],
+            Some(format!("{} {} refs/heads/foo\n", head_oid, head_oid)),
+        ),
+        (vec!["branchless", "hook-pre-auto-gc"], None),
+    ];
+    for (args, input) in hook_invocations {
+        let (stdout, stderr) = git.run_with_options(
+            &args,
+            &GitRunOptions {
+                input,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(stdout, "", "stdout for {:?}", args);
+        assert_eq!(stderr, "", "stderr for {:?}", args);
+    }
+    assert!(!git.repo_path.join(".git").join("branchless").exists());
+
+    git.run_with_options(
+        &["branchless", "hook-post-commit"],
+        &GitRunOptions {
+            env: {
+                let mut env = std::collections::HashMap::new();
+                env.insert(
+                    "BRANCHLESS_INITIALIZE_ON_DEMAND".to_string(),
+                    "1".to_string(),
+                );
+                env
+            },
+            ..Default::default()
+        },
+    )?;
+    assert!(git.repo_path.join(".git").join("branchless").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_rebase_update_refs() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_rebase_update_refs()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["branch", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["branch", "bar"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "-b", "baz"])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "baz"])?;
+    let num_events_before = get_ref_update_names(&git)?.len();
+
+    {
+        let (_stdout, stderr) = git.run(&["rebase", "--update-refs", "master"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: processing 1 update: ref HEAD
+        branchless: processing checkout
+        branchless: processing 1 update: ref HEAD
+        branchless: processed commit: ebd33ec8 create test1.txt
+        branchless: processing 1 update: ref HEAD
+        branchless: processed commit: 3359247b create test2.txt
+        branchless: processing 1 update: ref HEAD
+        branchless: processed commit: da62daae create test3.txt
+        branchless: processing 1 update: branch baz
+        branchless: processing 3 rewritten commits
+        Successfully rebased and updated refs/heads/baz.
+        branchless: processing 1 update: branch bar
+        branchless: processing 1 update: branch foo
+        Updated the following refs with --update-refs:
+        	refs/heads/bar
+        	refs/heads/foo
+        "###);
+    }
+
+    {
+        let ref_names = get_ref_update_names(&git)?;
+        insta::assert_debug_snapshot!(&ref_names[num_events_before..], @r###"
+        [
+            "HEAD",
+            "HEAD",
+            "HEAD",
+            "HEAD",
+            "HEAD",
+            "refs/heads/baz",
+            "refs/heads/bar",
+            "refs/heads/foo",
+        ]
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 8f7aef57 (master) create test4.txt
+        |
+        o ebd33ec8 (foo) create test1.txt
+        |
+        o 3359247b (bar) create test2.txt
+        |
+        @ da62daae (baz) create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
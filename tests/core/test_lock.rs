@@ -0,0 +1,153 @@
+use branchless::testing::{make_git, GitRunOptions};
+
+#[test]
+fn test_lock_held_by_running_process() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+
+    // The test process itself is still running, so the lock isn't stale.
+    let lock_path = git.repo_path.join(".git").join("branchless").join("lock");
+    std::fs::write(&lock_path, format!("{}\ngit move\n", std::process::id()))?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["hide", &test1_oid.to_string()],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        let stdout = stdout.replace(&std::process::id().to_string(), "<pid>");
+        insta::assert_snapshot!(stdout, @r###"
+        Another git-branchless operation (git move, pid <pid>) is in progress.
+        If it is no longer running, retry with: git branchless --force-unlock <command>
+        "###);
+    }
+    assert!(lock_path.exists());
+
+    {
+        // Read-only commands don't take the lock.
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 62fc20d2 create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) =
+            git.run(&["branchless", "--force-unlock", "hide", &test1_oid.to_string()])?;
+        let stderr = stderr.replace(&std::process::id().to_string(), "<pid>");
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: removing the lock held by another git-branchless operation (git move, pid <pid>)
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Hid commit: 62fc20d2 create test1.txt
+        To unhide this commit, run: git unhide 62fc20d2
+        "###);
+    }
+    assert!(!lock_path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_lock_stale() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+
+    let mut child = std::process::Command::new("true").spawn()?;
+    let dead_pid = child.id();
+    child.wait()?;
+    let lock_path = git.repo_path.join(".git").join("branchless").join("lock");
+    std::fs::write(&lock_path, format!("{}\ngit restack\n", dead_pid))?;
+
+    {
+        let (stdout, stderr) = git.run(&["hide", &test1_oid.to_string()])?;
+        let stderr = stderr.replace(&dead_pid.to_string(), "<pid>");
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: removing a stale lock left by a git-branchless operation which is no longer running (git restack, pid <pid>)
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Hid commit: 62fc20d2 create test1.txt
+        To unhide this commit, run: git unhide 62fc20d2
+        "###);
+    }
+    assert!(!lock_path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_lock_held_blocks_other_commands() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+
+    let lock_path = git.repo_path.join(".git").join("branchless").join("lock");
+    std::fs::write(&lock_path, format!("{}\ngit move\n", std::process::id()))?;
+
+    for args in [
+        &["reword", "-m", "new message"][..],
+        &["branchless", "gc"],
+        &["branchless", "repair"],
+        &["prev"],
+    ] {
+        let (stdout, _stderr) = git.run_with_options(
+            args,
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        let stdout = stdout.replace(&std::process::id().to_string(), "<pid>");
+        insta::assert_snapshot!(stdout, @r###"
+        Another git-branchless operation (git move, pid <pid>) is in progress.
+        If it is no longer running, retry with: git branchless --force-unlock <command>
+        "###);
+    }
+    assert!(lock_path.exists());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_lock_held_by_process_of_other_user() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+
+    // PID 1 is always running, but may belong to another user, in which case
+    // it can't be signaled. The lock mustn't be treated as stale either way.
+    let lock_path = git.repo_path.join(".git").join("branchless").join("lock");
+    std::fs::write(&lock_path, "1\ngit move\n")?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["hide", &test1_oid.to_string()],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Another git-branchless operation (git move, pid 1) is in progress.
+        If it is no longer running, retry with: git branchless --force-unlock <command>
+        "###);
+    }
+    assert!(lock_path.exists());
+
+    Ok(())
+}
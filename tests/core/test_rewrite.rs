@@ -0,0 +1,154 @@
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use branchless::core::dag::Dag;
+use branchless::core::effects::{Effects, OperationType, ProgressEvent};
+use branchless::core::eventlog::{EventLogDb, EventReplayer};
+use branchless::core::formatting::Glyphs;
+use branchless::core::rewrite::{
+    execute_rebase_plan, BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
+    RebasePlanBuilder,
+};
+use branchless::git::GitRunInfo;
+use branchless::testing::make_git;
+
+#[test]
+fn test_rewrite_progress_events() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+
+    let repo = git.get_repo()?;
+    let head_oid = repo.get_head_info()?.oid.unwrap();
+    let base_oid = repo.find_commit_or_fail(head_oid)?.get_parent_oids()[0];
+
+    let events: Arc<Mutex<Vec<ProgressEvent>>> = Default::default();
+    let effects = Effects::new_record_progress_for_test(Glyphs::text(), &events);
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    let dag = Dag::open_and_sync(
+        &effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let mut builder = RebasePlanBuilder::new(&repo, &dag);
+    builder.move_subtree(base_oid, test1_oid)?;
+    let rebase_plan = builder
+        .build(
+            &effects,
+            &BuildRebasePlanOptions {
+                dump_rebase_constraints: false,
+                dump_rebase_plan: false,
+                detect_duplicate_commits_via_patch_id: true,
+                drop_merges: false,
+                keep_empty: false,
+                autosquash: false,
+            },
+        )?
+        .unwrap()
+        .unwrap();
+
+    let git_run_info = GitRunInfo {
+        path_to_git: git.path_to_git.clone(),
+        working_directory: repo.get_working_copy_path().unwrap().to_path_buf(),
+        env: git.get_base_env(0).into_iter().collect(),
+    };
+    let now = SystemTime::now();
+    let event_tx_id = event_log_db.make_transaction_id(now, "test")?;
+    let result = execute_rebase_plan(
+        &effects,
+        &git_run_info,
+        &repo,
+        &rebase_plan,
+        &ExecuteRebasePlanOptions {
+            now,
+            event_tx_id,
+            preserve_timestamps: false,
+            force_in_memory: true,
+            force_on_disk: false,
+            resolve_merge_conflicts: false,
+            update_worktrees: false,
+        },
+    )?;
+    assert!(matches!(result, ExecuteRebasePlanResult::Succeeded));
+
+    let events: Vec<ProgressEvent> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| {
+            let operation_type = match event {
+                ProgressEvent::Start(operation_type)
+                | ProgressEvent::Progress { operation_type, .. }
+                | ProgressEvent::Status { operation_type, .. }
+                | ProgressEvent::End(operation_type) => operation_type,
+            };
+            matches!(
+                operation_type,
+                OperationType::BuildRebasePlan
+                    | OperationType::RebaseCommits
+                    | OperationType::UpdateBranches
+            )
+        })
+        .cloned()
+        .collect();
+    insta::assert_debug_snapshot!(events, @r###"
+    [
+        Start(
+            BuildRebasePlan,
+        ),
+        End(
+            BuildRebasePlan,
+        ),
+        Start(
+            RebaseCommits,
+        ),
+        Progress {
+            operation_type: RebaseCommits,
+            current: 0,
+            total: 2,
+        },
+        Progress {
+            operation_type: RebaseCommits,
+            current: 1,
+            total: 2,
+        },
+        Status {
+            operation_type: RebaseCommits,
+            status: "create test2.txt",
+        },
+        Progress {
+            operation_type: RebaseCommits,
+            current: 2,
+            total: 2,
+        },
+        Status {
+            operation_type: RebaseCommits,
+            status: "create test3.txt",
+        },
+        End(
+            RebaseCommits,
+        ),
+        Start(
+            UpdateBranches,
+        ),
+        End(
+            UpdateBranches,
+        ),
+    ]
+    "###);
+
+    Ok(())
+}
@@ -1,19 +1,37 @@
 mod util;
 
 mod core {
+    mod test_dag;
     mod test_eventlog;
     mod test_gc;
+    mod test_git_run;
     mod test_hooks;
+    mod test_lock;
+    mod test_rewrite;
 }
 
 mod command {
+    mod test_absorb;
     mod test_amend;
+    mod test_bug_report;
+    mod test_diff;
+    mod test_export;
     mod test_hide;
     mod test_init;
     mod test_move;
     mod test_navigation;
+    mod test_obslog;
+    mod test_query;
+    mod test_record;
+    mod test_repair;
     mod test_restack;
+    mod test_reword;
     mod test_smartlog;
+    mod test_split;
+    mod test_squash;
+    mod test_submit;
+    mod test_sync;
+    mod test_test;
     mod test_undo;
     mod test_wrap;
 }
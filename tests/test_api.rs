@@ -0,0 +1,206 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use branchless::core::effects::Effects;
+use branchless::core::formatting::Glyphs;
+use branchless::git::{GitRunInfo, Repo};
+use branchless::testing::{make_git, GitInitOptions};
+
+/// An output sink which can still be inspected after being handed to
+/// `Effects`.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take_string(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_api_amend_and_restack() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo_with_options(&GitInitOptions {
+        run_branchless_init: false,
+        ..Default::default()
+    })?;
+
+    let git_run_info = GitRunInfo {
+        path_to_git: git.path_to_git.clone(),
+        working_directory: git.repo_path.clone(),
+        env: git.get_base_env(0).into_iter().collect(),
+    };
+    let stdout = SharedBuffer::default();
+    let effects = Effects::new_from_writers(Glyphs::text(), stdout.clone(), std::io::sink());
+
+    branchless::init(&effects, &git_run_info, "master")?;
+    insta::assert_snapshot!(git.preprocess_output(stdout.take_string())?, @r###"
+    Created config file at <repo-path>/.git/branchless/config
+    Installing hook: post-applypatch
+    Installing hook: post-commit
+    Installing hook: post-merge
+    Installing hook: post-rewrite
+    Installing hook: post-checkout
+    Installing hook: pre-auto-gc
+    Installing hook: reference-transaction
+    Successfully installed git-branchless.
+    To uninstall, run: git branchless init --uninstall
+    "###);
+
+    let commit_file = |name: &str, contents: &str| -> eyre::Result<()> {
+        std::fs::write(git.repo_path.join(format!("{}.txt", name)), contents)?;
+        assert_eq!(git_run_info.run(&effects, None, &["add", "."])?, 0);
+        assert_eq!(
+            git_run_info.run(
+                &effects,
+                None,
+                &["commit", "-m", &format!("create {}.txt", name)],
+            )?,
+            0
+        );
+        Ok(())
+    };
+    assert_eq!(
+        git_run_info.run(&effects, None, &["checkout", "--detach"])?,
+        0
+    );
+    commit_file("test1", "test1 contents\n")?;
+    commit_file("test2", "test2 contents\n")?;
+    assert_eq!(git_run_info.run(&effects, None, &["checkout", "HEAD^"])?, 0);
+    stdout.take_string();
+
+    std::fs::write(git.repo_path.join("test1.txt"), "updated contents\n")?;
+    branchless::amend(&effects, &git_run_info)?;
+    insta::assert_snapshot!(git.preprocess_output(stdout.take_string())?, @r###"
+    branchless: running command: <git-executable> reset
+    Amended with 1 uncommitted change.
+    Left 1 commit based on the old version of the amended commit, because restacking is disabled:
+    - 1e3b4c3a create test2.txt
+    Run `git restack` to move abandoned commits onto the amended commit.
+    "###);
+
+    let repo = Repo::from_dir(&git.repo_path)?;
+    {
+        let graph = branchless::smartlog::render_graph(&effects, &repo)?;
+        insta::assert_snapshot!(graph.lines.join("\n"), @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ 3a930a05 create test1.txt
+        |
+        x 7eea5b77 (rewritten as 3a930a05) create test1.txt
+        |
+        o 1e3b4c3a create test2.txt
+        "###);
+        let summaries: Vec<_> = graph
+            .nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.summary.clone(),
+                    node.is_obsolete,
+                    node.branches.clone(),
+                )
+            })
+            .collect();
+        insta::assert_debug_snapshot!(summaries, @r###"
+        [
+            (
+                Some(
+                    "create test2.txt",
+                ),
+                false,
+                [],
+            ),
+            (
+                Some(
+                    "create test1.txt",
+                ),
+                false,
+                [],
+            ),
+            (
+                Some(
+                    "create test1.txt",
+                ),
+                true,
+                [],
+            ),
+            (
+                Some(
+                    "create initial.txt",
+                ),
+                false,
+                [
+                    "refs/heads/master",
+                ],
+            ),
+        ]
+        "###);
+        assert_eq!(graph.head_oid, repo.get_head_info()?.oid);
+    }
+
+    branchless::restack(&effects, &git_run_info)?;
+    stdout.take_string();
+    {
+        let graph = branchless::smartlog::render_graph(&effects, &repo)?;
+        insta::assert_snapshot!(graph.lines.join("\n"), @r###"
+        O f777ecc9 (master) create initial.txt
+        |
+        @ 3a930a05 create test1.txt
+        |
+        o b670d8a2 create test2.txt
+        "###);
+        assert!(graph.nodes.iter().all(|node| !node.is_obsolete));
+    }
+
+    let plan = branchless::undo::plan(&effects, &git_run_info, 1)?;
+    insta::assert_snapshot!(plan.actions.join("\n"), @r###"
+    1. Rewrite commit b670d8a2 create test2.txt
+                  as 1e3b4c3a create test2.txt
+    "###);
+    branchless::undo::apply(&effects, &git_run_info, &plan)?;
+    stdout.take_string();
+    {
+        let graph = branchless::smartlog::render_graph(&effects, &repo)?;
+        insta::assert_snapshot!(graph.lines.join("\n"), @r###"
+        O f777ecc9 (master) create initial.txt
+        |\
+        | @ 3a930a05 create test1.txt
+        |
+        x 7eea5b77 (rewritten as 3a930a05) create test1.txt
+        |
+        o 1e3b4c3a create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_api_error() -> eyre::Result<()> {
+    let git = make_git()?;
+    let git_run_info = GitRunInfo {
+        path_to_git: git.path_to_git.clone(),
+        working_directory: git.repo_path.join("nonexistent"),
+        env: git.get_base_env(0).into_iter().collect(),
+    };
+    let effects = Effects::new_from_writers(Glyphs::text(), std::io::sink(), std::io::sink());
+
+    let err = branchless::restack(&effects, &git_run_info).unwrap_err();
+    assert!(matches!(err, branchless::Error::Internal(_)));
+    let err: Box<dyn std::error::Error + Send + Sync> = Box::new(err);
+    assert!(!err.to_string().is_empty());
+
+    Ok(())
+}
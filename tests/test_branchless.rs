@@ -82,3 +82,75 @@ fn test_profiling() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_working_directory_option() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+
+    let other_git = make_git()?;
+    other_git.init_repo()?;
+
+    let repo_path = git.repo_path.to_str().unwrap();
+    let smartlog = {
+        let (stdout, _stderr) = other_git.run(&["branchless", "-C", repo_path, "smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        @ 96d1c37a create test2.txt
+        "###);
+        stdout
+    };
+
+    {
+        // Relative paths are interpreted relative to the preceding `-C`.
+        let parent_path = git.repo_path.parent().unwrap().to_str().unwrap();
+        let repo_name = git.repo_path.file_name().unwrap().to_str().unwrap();
+        let (stdout, _stderr) = other_git.run(&[
+            "branchless",
+            "-C",
+            parent_path,
+            "--working-directory",
+            repo_name,
+            "smartlog",
+        ])?;
+        assert_eq!(stdout, smartlog);
+    }
+
+    git.write_file("test2", "updated contents\n")?;
+    {
+        let (stdout, _stderr) = other_git.run(&["branchless", "-C", repo_path, "amend"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> reset
+        No abandoned commits to restack.
+        No abandoned branches to restack.
+        :
+        O 62fc20d2 (master) create test1.txt
+        |
+        @ 11300932 create test2.txt
+        Amended with 1 uncommitted change.
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["show", "--pretty=format:", "HEAD"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        diff --git a/test2.txt b/test2.txt
+        new file mode 100644
+        index 0000000..27e2fc9
+        --- /dev/null
+        +++ b/test2.txt
+        @@ -0,0 +1 @@
+        +updated contents
+        "###);
+    }
+    {
+        let (stdout, _stderr) = other_git.run(&["status", "--short"])?;
+        assert_eq!(stdout, "");
+    }
+
+    Ok(())
+}
@@ -1,6 +1,73 @@
+use std::io::{Read, Write};
+use std::thread;
+
+use branchless::testing::Git;
+use eyre::eyre;
+use portable_pty::{native_pty_system, CommandBuilder, ExitStatus, PtySize};
+
 pub fn trim_lines(output: String) -> String {
     output
         .lines()
         .flat_map(|line| vec![line.trim_end(), "\n"].into_iter())
         .collect()
 }
+
+pub enum PtyAction<'a> {
+    Write(&'a str),
+    WaitUntilContains(&'a str),
+}
+
+/// Run `git` with the given arguments in a pseudo-terminal, performing the given
+/// actions against its output in order. Returns the exit status of the process.
+pub fn run_in_pty(git: &Git, args: &[&str], inputs: &[PtyAction]) -> eyre::Result<ExitStatus> {
+    // Use the native pty implementation for the system
+    let pty_system = native_pty_system();
+    let pty_size = PtySize::default();
+    let mut pty = pty_system
+        .openpty(pty_size)
+        .map_err(|e| eyre!("Could not open pty: {}", e))?;
+
+    // Spawn a git instance in the pty.
+    let mut cmd = CommandBuilder::new(&git.path_to_git);
+    cmd.env_clear();
+    for (k, v) in git.get_base_env(0) {
+        cmd.env(k, v);
+    }
+    cmd.env("TERM", "xterm");
+    cmd.args(args);
+    cmd.cwd(&git.repo_path);
+
+    let mut child = pty
+        .slave
+        .spawn_command(cmd)
+        .map_err(|e| eyre!("Could not spawn child: {}", e))?;
+    let mut reader = pty
+        .master
+        .try_clone_reader()
+        .map_err(|e| eyre!("Could not clone reader: {}", e))?;
+
+    let mut parser = vt100::Parser::new(pty_size.rows, pty_size.cols, 0);
+    for action in inputs {
+        match &action {
+            PtyAction::WaitUntilContains(value) => {
+                let mut buffer = [0; 1024];
+                while !parser.screen().contents().contains(value) {
+                    let n = reader.read(&mut buffer)?;
+                    parser.process(&buffer[..n]);
+                }
+            }
+            PtyAction::Write(value) => {
+                write!(pty.master, "{}", value)?;
+                pty.master.flush()?;
+            }
+        }
+    }
+
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer).expect("finish reading pty");
+    });
+
+    let exit_status = child.wait()?;
+    Ok(exit_status)
+}